@@ -0,0 +1,95 @@
+//! Times the metric updates one chat request makes and checks they stop allocating once every
+//! label set has been seen. Run with `cargo bench --bench metrics`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    hint::black_box,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use rust_llm_inference_gateway::{
+    metrics::{AppMetrics, MetricsConfig},
+    models::Usage,
+};
+
+const ITERATIONS: u32 = 200_000;
+
+/// Counts allocations made by the current thread, so the harness's own threads do not count.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// The updates the chat handler, schedulers, and router make for one streamed request.
+fn record_request(metrics: &AppMetrics, usage: &Usage) {
+    let _inflight = metrics.inflight_guard();
+    metrics.observe_tenant_request("acme");
+    metrics.adjust_queue_depth("admission", 1);
+    metrics.observe_queue_wait("admission", Duration::from_millis(3));
+    metrics.adjust_queue_depth("admission", -1);
+    let _slot = metrics.pool_usage("gpt-4o*");
+    metrics.observe_stream_ttft("openai", "gpt-4o", Duration::from_millis(180));
+    metrics.observe_stream_inter_chunk("openai", "gpt-4o", Duration::from_millis(20));
+    metrics.observe_stream_tokens_per_second("openai", "gpt-4o", 55.0);
+    metrics.observe_usage("gpt-4o", Some("pro"), usage);
+    metrics.observe_tenant_usage("acme", usage);
+    metrics.observe_cost("gpt-4o", "openai", 0.0021);
+    metrics.observe_request(
+        "/v1/chat/completions",
+        "POST",
+        true,
+        200,
+        Duration::from_millis(950),
+    );
+}
+
+fn main() -> ExitCode {
+    let metrics = AppMetrics::with_config(MetricsConfig {
+        max_tier_labels: 8,
+        max_tenant_labels: 8,
+        ..MetricsConfig::default()
+    });
+    let usage = Usage::new(420, 180);
+    // The first request creates every labelled child; only steady state is measured.
+    record_request(&metrics, &usage);
+
+    let before = allocations();
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        record_request(black_box(&metrics), black_box(&usage));
+    }
+    let elapsed = started.elapsed();
+    let allocated = allocations() - before;
+
+    println!(
+        "metrics hot path: {:.0} ns/request, {:.2} allocations/request",
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS),
+        allocated as f64 / f64::from(ITERATIONS),
+    );
+    if allocated > 0 {
+        eprintln!("expected no allocations once labels are warm, saw {allocated}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,568 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::warn;
+
+use crate::{
+    error_reporting::ErrorReport,
+    models::{ClientTags, Usage},
+    usage_sink::UsageRecord,
+};
+
+/// Where access-log lines go; configured by `GATEWAY_ACCESS_LOG` as `off`, `stdout`, or a
+/// file path (appended to).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AccessLogSink {
+    #[default]
+    Off,
+    Stdout,
+    File(PathBuf),
+}
+
+impl AccessLogSink {
+    pub fn from_env() -> Self {
+        match env::var("GATEWAY_ACCESS_LOG") {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::Off,
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("off") {
+            Self::Off
+        } else if value.eq_ignore_ascii_case("stdout") {
+            Self::Stdout
+        } else {
+            Self::File(PathBuf::from(value))
+        }
+    }
+}
+
+/// One JSON line per chat request, written off the request path by a background task and
+/// kept separate from `tracing` output so it can be shipped and parsed as-is.
+pub struct AccessLog {
+    tx: Option<mpsc::UnboundedSender<String>>,
+    /// Requests slower than this are also logged at WARN with their timing breakdown, whether
+    /// or not an access-log sink is configured.
+    slow_threshold: Option<Duration>,
+    /// Streamed lines also carry the completion text and chunk timings.
+    stream_transcripts: bool,
+}
+
+impl AccessLog {
+    pub fn from_env() -> Self {
+        Self::new(AccessLogSink::from_env())
+            .with_slow_threshold(read_slow_threshold())
+            .with_stream_transcripts(
+                env::var("GATEWAY_ACCESS_LOG_STREAM_TRANSCRIPTS")
+                    .map(|value| value != "0" && !value.eq_ignore_ascii_case("false"))
+                    .unwrap_or(false),
+            )
+    }
+
+    pub fn new(sink: AccessLogSink) -> Self {
+        let tx = match sink {
+            AccessLogSink::Off => None,
+            AccessLogSink::Stdout => Some(spawn_writer(async { Ok(tokio::io::stdout()) })),
+            AccessLogSink::File(path) => Some(spawn_writer(async move {
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await
+            })),
+        };
+        Self {
+            tx,
+            slow_threshold: None,
+            stream_transcripts: false,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            tx: None,
+            slow_threshold: None,
+            stream_transcripts: false,
+        }
+    }
+
+    pub fn with_slow_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_threshold = threshold;
+        self
+    }
+
+    /// Adds each finished stream's full text and chunk timings to its line. Off by default:
+    /// completions can be large and may hold sensitive content.
+    pub fn with_stream_transcripts(mut self, enabled: bool) -> Self {
+        self.stream_transcripts = enabled;
+        self
+    }
+
+    /// Starts the record for a request; it is written when the last handle is finished.
+    pub fn record(self: &Arc<Self>) -> AccessRecord {
+        AccessRecord {
+            log: self.clone(),
+            started: Instant::now(),
+            fields: Arc::new(Mutex::new(AccessFields::default())),
+        }
+    }
+
+    fn is_slow(&self, duration_ms: u64) -> bool {
+        self.slow_threshold
+            .is_some_and(|threshold| duration_ms >= millis(threshold))
+    }
+
+    fn write(&self, entry: &AccessLogEntry) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match serde_json::to_string(entry) {
+            Ok(mut line) => {
+                line.push('\n');
+                let _ = tx.send(line);
+            }
+            Err(error) => warn!(error = %error, "failed to encode access log entry"),
+        }
+    }
+}
+
+fn read_slow_threshold() -> Option<Duration> {
+    let raw = env::var("GATEWAY_SLOW_REQUEST_MS").ok()?;
+    match raw.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(millis) => Some(Duration::from_millis(millis)),
+        Err(_) => {
+            warn!(value = %raw, "invalid GATEWAY_SLOW_REQUEST_MS, slow-request logging disabled");
+            None
+        }
+    }
+}
+
+fn spawn_writer<W, F>(open: F) -> mpsc::UnboundedSender<String>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+    F: std::future::Future<Output = std::io::Result<W>> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        let mut writer = match open.await {
+            Ok(writer) => writer,
+            Err(error) => {
+                warn!(error = %error, "failed to open access log sink; access logging disabled");
+                return;
+            }
+        };
+        while let Some(line) = rx.recv().await {
+            if let Err(error) = writer.write_all(line.as_bytes()).await {
+                warn!(error = %error, "failed to write access log line");
+                continue;
+            }
+            let _ = writer.flush().await;
+        }
+    });
+    tx
+}
+
+#[derive(Debug, Default)]
+struct AccessFields {
+    request_id: Option<String>,
+    key_id: Option<String>,
+    tenant_id: Option<String>,
+    model: Option<String>,
+    backend: Option<String>,
+    stream: bool,
+    status: u16,
+    cache: Option<&'static str>,
+    coalesced: Option<&'static str>,
+    experiment: Option<String>,
+    variant: Option<String>,
+    client: ClientTags,
+    usage: Option<Usage>,
+    cost_usd: Option<f64>,
+    first_token_at: Option<Instant>,
+    queue_wait: Option<Duration>,
+    backend_latency: Option<Duration>,
+    transcript: Option<StreamCapture>,
+}
+
+/// A streamed completion as the client received it.
+#[derive(Debug, Clone, Default, Serialize)]
+struct StreamCapture {
+    text: String,
+    finish_reason: Option<String>,
+    chunks: Vec<ChunkTiming>,
+}
+
+/// When a chunk was sent, in milliseconds since the request started, and its length in
+/// characters; offsets into `text` follow from the running sum of `chars`.
+#[derive(Debug, Clone, Serialize)]
+struct ChunkTiming {
+    offset_ms: u64,
+    chars: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct AccessLogEntry {
+    timestamp_ms: u64,
+    request_id: Option<String>,
+    key_id: Option<String>,
+    tenant_id: Option<String>,
+    model: Option<String>,
+    backend: Option<String>,
+    stream: bool,
+    status: u16,
+    cache: Option<&'static str>,
+    coalesced: Option<&'static str>,
+    experiment: Option<String>,
+    variant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<BTreeMap<String, String>>,
+    cost_usd: Option<f64>,
+    prompt_tokens: Option<u32>,
+    completion_tokens: Option<u32>,
+    total_tokens: Option<u32>,
+    queue_wait_ms: Option<u64>,
+    backend_ms: Option<u64>,
+    ttft_ms: Option<u64>,
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcript: Option<StreamCapture>,
+}
+
+/// Fields gathered for one request's access-log line. Clones share the same record, so the
+/// handler and the streamed body can both fill it in; `finish` writes it.
+#[derive(Clone)]
+pub struct AccessRecord {
+    log: Arc<AccessLog>,
+    started: Instant,
+    fields: Arc<Mutex<AccessFields>>,
+}
+
+impl AccessRecord {
+    pub fn set_request(&self, request_id: &str, key_id: &str, model: &str, stream: bool) {
+        let mut fields = self.lock();
+        fields.request_id = Some(request_id.to_owned());
+        fields.key_id = Some(key_id.to_owned());
+        fields.model = Some(model.to_owned());
+        fields.stream = stream;
+    }
+
+    pub fn set_tenant(&self, tenant_id: &str) {
+        self.lock().tenant_id = Some(tenant_id.to_owned());
+    }
+
+    pub fn set_status(&self, status: u16) {
+        self.lock().status = status;
+    }
+
+    pub fn set_cache(&self, outcome: &'static str) {
+        self.lock().cache = Some(outcome);
+    }
+
+    pub fn set_coalesced(&self, outcome: &'static str) {
+        self.lock().coalesced = Some(outcome);
+    }
+
+    pub fn set_experiment(&self, experiment: &str, variant: &str) {
+        let mut fields = self.lock();
+        fields.experiment = Some(experiment.to_owned());
+        fields.variant = Some(variant.to_owned());
+    }
+
+    /// The request's own `user` and `metadata` tags.
+    pub fn set_client(&self, client: &ClientTags) {
+        self.lock().client = client.clone();
+    }
+
+    pub fn set_usage(&self, usage: &Usage) {
+        self.lock().usage = Some(usage.clone());
+    }
+
+    pub fn set_backend(&self, backend: &str) {
+        self.lock().backend = Some(backend.to_owned());
+    }
+
+    pub fn set_cost(&self, cost_usd: f64) {
+        self.lock().cost_usd = Some(cost_usd);
+    }
+
+    /// Time spent waiting for a gateway admission slot.
+    pub fn set_queue_wait(&self, wait: Duration) {
+        self.lock().queue_wait = Some(wait);
+    }
+
+    /// Time the backend took: the whole call for one-shot requests, or until the stream opened.
+    pub fn set_backend_latency(&self, latency: Duration) {
+        self.lock().backend_latency = Some(latency);
+    }
+
+    /// Marks the first streamed token; later calls keep the earliest time.
+    pub fn mark_first_token(&self) {
+        self.lock().first_token_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Appends streamed text sent to the client, when stream transcripts are logged.
+    pub fn push_stream_text(&self, text: &str) {
+        if !self.log.stream_transcripts || self.log.tx.is_none() {
+            return;
+        }
+        let offset_ms = millis(self.started.elapsed());
+        let mut fields = self.lock();
+        let transcript = fields.transcript.get_or_insert_with(StreamCapture::default);
+        transcript.text.push_str(text);
+        transcript.chunks.push(ChunkTiming {
+            offset_ms,
+            chars: text.chars().count(),
+        });
+    }
+
+    /// Records how a logged stream transcript ended.
+    pub fn finish_stream_transcript(&self, finish_reason: &str) {
+        if !self.log.stream_transcripts || self.log.tx.is_none() {
+            return;
+        }
+        self.lock()
+            .transcript
+            .get_or_insert_with(StreamCapture::default)
+            .finish_reason = Some(finish_reason.to_owned());
+    }
+
+    /// Writes the line with the duration measured up to now, and a WARN event when the request
+    /// crossed the slow-request threshold.
+    pub fn finish(&self) {
+        let fields = self.lock();
+        let entry = AccessLogEntry {
+            timestamp_ms: unix_millis(),
+            request_id: fields.request_id.clone(),
+            key_id: fields.key_id.clone(),
+            tenant_id: fields.tenant_id.clone(),
+            model: fields.model.clone(),
+            backend: fields.backend.clone(),
+            stream: fields.stream,
+            status: fields.status,
+            cache: fields.cache,
+            coalesced: fields.coalesced,
+            experiment: fields.experiment.clone(),
+            variant: fields.variant.clone(),
+            client_user: fields.client.user.clone(),
+            metadata: fields.client.metadata_tags(),
+            cost_usd: fields.cost_usd,
+            prompt_tokens: fields.usage.as_ref().map(|usage| usage.prompt_tokens),
+            completion_tokens: fields.usage.as_ref().map(|usage| usage.completion_tokens),
+            total_tokens: fields.usage.as_ref().map(|usage| usage.total_tokens),
+            queue_wait_ms: fields.queue_wait.map(millis),
+            backend_ms: fields.backend_latency.map(millis),
+            ttft_ms: fields
+                .first_token_at
+                .map(|at| millis(at.saturating_duration_since(self.started))),
+            duration_ms: millis(self.started.elapsed()),
+            transcript: fields.transcript.clone(),
+        };
+        drop(fields);
+        if self.log.is_slow(entry.duration_ms) {
+            warn!(
+                request_id = entry.request_id.as_deref().unwrap_or_default(),
+                key_id = entry.key_id.as_deref().unwrap_or_default(),
+                tenant_id = entry.tenant_id.as_deref().unwrap_or_default(),
+                model = entry.model.as_deref().unwrap_or_default(),
+                backend = entry.backend.as_deref().unwrap_or_default(),
+                stream = entry.stream,
+                status = entry.status,
+                cache = entry.cache.unwrap_or_default(),
+                coalesced = entry.coalesced.unwrap_or_default(),
+                prompt_tokens = entry.prompt_tokens,
+                completion_tokens = entry.completion_tokens,
+                queue_wait_ms = entry.queue_wait_ms,
+                backend_ms = entry.backend_ms,
+                ttft_ms = entry.ttft_ms,
+                duration_ms = entry.duration_ms,
+                "slow request"
+            );
+        }
+        self.log.write(&entry);
+    }
+
+    /// The usage-sink view of this request; `None` until it has reported token usage.
+    pub fn usage_record(&self) -> Option<UsageRecord> {
+        let fields = self.lock();
+        let usage = fields.usage.as_ref()?;
+        Some(UsageRecord {
+            timestamp_ms: unix_millis(),
+            request_id: fields.request_id.clone()?,
+            key_id: fields.key_id.clone()?,
+            // Requests rejected before authentication never report usage, so this is set.
+            tenant_id: fields.tenant_id.clone().unwrap_or_default(),
+            model: fields.model.clone()?,
+            backend: fields.backend.clone(),
+            stream: fields.stream,
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+            cost_usd: fields.cost_usd,
+            latency_ms: millis(self.started.elapsed()),
+            cache: fields.cache,
+            coalesced: fields.coalesced,
+            experiment: fields.experiment.clone(),
+            variant: fields.variant.clone(),
+            client_user: fields.client.user.clone(),
+            metadata: fields.client.metadata_tags(),
+        })
+    }
+
+    /// An error report carrying this request's context.
+    pub fn error_report(&self, kind: &'static str, message: String) -> ErrorReport {
+        let fields = self.lock();
+        ErrorReport {
+            kind,
+            message,
+            request_id: fields.request_id.clone(),
+            key_id: fields.key_id.clone(),
+            model: fields.model.clone(),
+            backend: fields.backend.clone(),
+            stream: fields.stream,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AccessFields> {
+        self.fields
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+fn unix_millis() -> u64 {
+    millis(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{AccessLog, AccessLogSink};
+    use crate::models::Usage;
+
+    #[test]
+    fn parses_sinks() {
+        assert_eq!(AccessLogSink::parse("off"), AccessLogSink::Off);
+        assert_eq!(AccessLogSink::parse("STDOUT"), AccessLogSink::Stdout);
+        assert_eq!(
+            AccessLogSink::parse("/var/log/gateway.jsonl"),
+            AccessLogSink::File("/var/log/gateway.jsonl".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_one_json_line_per_request_to_file_sink() {
+        let path =
+            std::env::temp_dir().join(format!("gateway-access-{}.jsonl", uuid::Uuid::new_v4()));
+        let log = Arc::new(AccessLog::new(AccessLogSink::File(path.clone())));
+
+        let record = log.record();
+        record.set_request("req_1", "key_dev", "mock-1", true);
+        record.mark_first_token();
+        record.set_cache("miss");
+        record.set_coalesced("leader");
+        record.set_usage(&Usage::new(3, 4));
+        record.set_queue_wait(Duration::from_millis(5));
+        record.set_status(200);
+        record.finish();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let contents = std::fs::read_to_string(&path).expect("access log written");
+        let _ = std::fs::remove_file(&path);
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).expect("json line");
+        assert_eq!(entry["request_id"], "req_1");
+        assert_eq!(entry["key_id"], "key_dev");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["cache"], "miss");
+        assert_eq!(entry["coalesced"], "leader");
+        assert_eq!(entry["total_tokens"], 7);
+        assert!(entry["ttft_ms"].is_u64());
+        assert_eq!(entry["queue_wait_ms"], 5);
+        assert!(entry["backend_ms"].is_null());
+    }
+
+    #[tokio::test]
+    async fn stream_transcripts_are_logged_only_when_enabled() {
+        let path =
+            std::env::temp_dir().join(format!("gateway-access-{}.jsonl", uuid::Uuid::new_v4()));
+        for (request_id, enabled) in [("req_off", false), ("req_on", true)] {
+            let log = Arc::new(
+                AccessLog::new(AccessLogSink::File(path.clone())).with_stream_transcripts(enabled),
+            );
+            let record = log.record();
+            record.set_request(request_id, "key_dev", "mock-1", true);
+            record.push_stream_text("Hel");
+            record.push_stream_text("lo é");
+            record.finish_stream_transcript("stop");
+            record.finish();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let contents = std::fs::read_to_string(&path).expect("access log written");
+        let _ = std::fs::remove_file(&path);
+        // Each log writes from its own task, so the two lines may land in either order.
+        let line = |request_id: &str| {
+            contents
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("json line"))
+                .find(|entry| entry["request_id"] == request_id)
+                .expect("line written")
+        };
+        assert!(line("req_off").get("transcript").is_none());
+        let transcript = &line("req_on")["transcript"];
+        assert_eq!(transcript["text"], "Hello é");
+        assert_eq!(transcript["finish_reason"], "stop");
+        assert_eq!(transcript["chunks"][1]["chars"], 4);
+        assert!(transcript["chunks"][0]["offset_ms"].is_u64());
+    }
+
+    #[test]
+    fn slow_threshold_is_inclusive_and_optional() {
+        let log = AccessLog::disabled().with_slow_threshold(Some(Duration::from_millis(250)));
+        assert!(!log.is_slow(249));
+        assert!(log.is_slow(250));
+        assert!(!AccessLog::disabled().is_slow(u64::MAX));
+    }
+
+    #[test]
+    fn usage_record_requires_reported_usage() {
+        let log = Arc::new(AccessLog::disabled());
+        let record = log.record();
+        record.set_request("req_1", "key_dev", "mock-1", false);
+        assert!(record.usage_record().is_none());
+
+        record.set_usage(&Usage::new(3, 4));
+        record.set_backend("mock-a");
+        record.set_cost(0.5);
+        let usage = record.usage_record().expect("usage record");
+        assert_eq!(usage.request_id, "req_1");
+        assert_eq!(usage.backend.as_deref(), Some("mock-a"));
+        assert_eq!(usage.total_tokens, 7);
+        assert_eq!(usage.cost_usd, Some(0.5));
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::{auth::constant_time_eq, errors::AppError};
+
+/// An IPv4 or IPv6 network in `addr/prefix` form; a bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (addr, prefix) = match raw.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (raw, None),
+        };
+        let network = addr
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("`{raw}` is not an IP address or CIDR"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("`{raw}` has an invalid prefix length"))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(addr)) => addr
+                .to_ipv4_mapped()
+                .is_some_and(|addr| self.contains(IpAddr::V4(addr))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let full = usize::from(prefix / 8);
+    let rest = prefix % 8;
+    if network[..full] != addr[..full] {
+        return false;
+    }
+    if rest == 0 {
+        return true;
+    }
+    let mask = u8::MAX << (8 - rest);
+    network[full] & mask == addr[full] & mask
+}
+
+/// Protection for operational endpoints (`/metrics`, `/admin/*`), which should not be open on
+/// the public data-plane port.
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    /// Bearer token required on admin requests.
+    pub token: Option<String>,
+    /// Client networks allowed to reach admin endpoints; empty allows any client to reach
+    /// `/metrics` and loopback clients to reach `/admin/*`.
+    pub allowed_networks: Vec<Cidr>,
+    /// Separate listener for admin endpoints; when set they are removed from the main port.
+    pub listen_addr: Option<SocketAddr>,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        Self {
+            token: env::var("GATEWAY_ADMIN_TOKEN")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            allowed_networks: read_networks(),
+            listen_addr: read_listen_addr(),
+        }
+    }
+
+    /// Checks a request's bearer token and client address against the configuration.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        client: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        if let Some(token) = &self.token {
+            let presented = authorization
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::trim);
+            if !presented
+                .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+            {
+                return Err(AppError::Unauthorized(
+                    "admin endpoints require a valid bearer token".to_owned(),
+                ));
+            }
+        }
+        if !self.allowed_networks.is_empty() {
+            // Without a known peer address the request cannot be matched, so it is refused.
+            let allowed = client.is_some_and(|client| {
+                self.allowed_networks
+                    .iter()
+                    .any(|network| network.contains(client))
+            });
+            if !allowed {
+                return Err(AppError::Forbidden(
+                    "client address is not allowed to reach admin endpoints".to_owned(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// [`authorize`](Self::authorize) for the `/admin/*` endpoints, which change balances,
+    /// quotas, and upstream keys and so fail closed: with neither a token nor an allowlist
+    /// configured, only loopback clients get through. A `mutating` request, one that is not a
+    /// `GET` or `HEAD`, needs the token or a loopback client even from an allowlisted network,
+    /// since a network is a much weaker credential than a secret.
+    pub fn authorize_admin(
+        &self,
+        authorization: Option<&str>,
+        client: Option<IpAddr>,
+        mutating: bool,
+    ) -> Result<(), AppError> {
+        if self.token.is_none() && !client.is_some_and(is_loopback) {
+            if mutating {
+                return Err(AppError::Forbidden(
+                    "admin changes need GATEWAY_ADMIN_TOKEN or a loopback client".to_owned(),
+                ));
+            }
+            if self.allowed_networks.is_empty() {
+                return Err(AppError::Forbidden(
+                    "admin endpoints only accept loopback clients unless GATEWAY_ADMIN_TOKEN \
+                     or GATEWAY_ADMIN_ALLOWED_CIDRS is set"
+                        .to_owned(),
+                ));
+            }
+        }
+        self.authorize(authorization, client)
+    }
+}
+
+fn is_loopback(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_loopback(),
+        IpAddr::V6(addr) => {
+            addr.is_loopback() || addr.to_ipv4_mapped().is_some_and(|addr| addr.is_loopback())
+        }
+    }
+}
+
+/// Middleware guarding `/metrics` with [`AdminConfig::authorize`].
+pub async fn protect(
+    State(config): State<Arc<AdminConfig>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let result = config.authorize(authorization(&request), client(connect_info));
+    admit(result, request, next).await
+}
+
+/// Middleware guarding `/admin/*` with [`AdminConfig::authorize_admin`].
+pub async fn protect_admin(
+    State(config): State<Arc<AdminConfig>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mutating = !request.method().is_safe();
+    let result = config.authorize_admin(authorization(&request), client(connect_info), mutating);
+    admit(result, request, next).await
+}
+
+fn authorization(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn client(connect_info: Option<ConnectInfo<SocketAddr>>) -> Option<IpAddr> {
+    connect_info.map(|ConnectInfo(addr)| addr.ip())
+}
+
+async fn admit(result: Result<(), AppError>, request: Request, next: Next) -> Response {
+    match result {
+        Ok(()) => next.run(request).await,
+        Err(error) => error.into_response(),
+    }
+}
+
+fn read_networks() -> Vec<Cidr> {
+    let Ok(raw) = env::var("GATEWAY_ADMIN_ALLOWED_CIDRS") else {
+        return Vec::new();
+    };
+    Cidr::parse_list(&raw).unwrap_or_else(|error| {
+        // Failing open would expose the endpoints the operator meant to restrict.
+        warn!(error = %error, "invalid GATEWAY_ADMIN_ALLOWED_CIDRS, allowing loopback only");
+        vec![
+            Cidr::parse("127.0.0.1/8").expect("valid loopback"),
+            Cidr::parse("::1").expect("valid loopback"),
+        ]
+    })
+}
+
+fn read_listen_addr() -> Option<SocketAddr> {
+    let raw = env::var("GATEWAY_ADMIN_LISTEN_ADDR").ok()?;
+    match raw.trim().parse::<SocketAddr>() {
+        Ok(addr) => Some(addr),
+        Err(_) => {
+            warn!(value = %raw, "invalid GATEWAY_ADMIN_LISTEN_ADDR, serving admin endpoints on the main port");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{AdminConfig, Cidr};
+    use crate::errors::AppError;
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().expect("valid ip")
+    }
+
+    #[test]
+    fn cidrs_match_by_prefix() {
+        let private = Cidr::parse("10.0.0.0/8").expect("valid cidr");
+        assert!(private.contains(ip("10.20.30.40")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(private.contains(ip("::ffff:10.1.2.3")));
+
+        let narrow = Cidr::parse("192.168.1.128/25").expect("valid cidr");
+        assert!(narrow.contains(ip("192.168.1.200")));
+        assert!(!narrow.contains(ip("192.168.1.100")));
+
+        assert!(Cidr::parse("fd00::/8")
+            .expect("valid cidr")
+            .contains(ip("fd12::1")));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn authorizes_by_token_and_network() {
+        let config = AdminConfig {
+            token: Some("secret".to_owned()),
+            allowed_networks: Cidr::parse_list("10.0.0.0/8").expect("valid cidrs"),
+            listen_addr: None,
+        };
+
+        assert!(config
+            .authorize(Some("Bearer secret"), Some(ip("10.0.0.5")))
+            .is_ok());
+        assert!(matches!(
+            config.authorize(Some("Bearer wrong"), Some(ip("10.0.0.5"))),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            config.authorize(Some("Bearer secret"), Some(ip("8.8.8.8"))),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            config.authorize(Some("Bearer secret"), None),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(AdminConfig::default().authorize(None, None).is_ok());
+    }
+
+    #[test]
+    fn admin_endpoints_fail_closed_without_a_token() {
+        let open = AdminConfig::default();
+        for client in [None, Some(ip("10.0.0.5")), Some(ip("8.8.8.8"))] {
+            assert!(matches!(
+                open.authorize_admin(None, client, false),
+                Err(AppError::Forbidden(_))
+            ));
+        }
+        for client in ["127.0.0.1", "::1", "::ffff:127.0.0.1"] {
+            assert!(
+                open.authorize_admin(None, Some(ip(client)), true).is_ok(),
+                "{client}"
+            );
+        }
+
+        let allowlisted = AdminConfig {
+            allowed_networks: Cidr::parse_list("10.0.0.0/8").expect("valid cidrs"),
+            ..AdminConfig::default()
+        };
+        assert!(allowlisted
+            .authorize_admin(None, Some(ip("10.0.0.5")), false)
+            .is_ok());
+        // Reading from an allowlisted network is fine; changing quotas or keys is not.
+        assert!(matches!(
+            allowlisted.authorize_admin(None, Some(ip("10.0.0.5")), true),
+            Err(AppError::Forbidden(_))
+        ));
+        let token = AdminConfig {
+            token: Some("secret".to_owned()),
+            ..AdminConfig::default()
+        };
+        assert!(token
+            .authorize_admin(Some("Bearer secret"), Some(ip("8.8.8.8")), true)
+            .is_ok());
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    backend::with_deadline,
+    errors::AppError,
+    metrics::{AppMetrics, PoolUsage},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdmissionConfig {
+    /// Chat requests the gateway works on at once; `0` disables global admission control.
+    pub max_concurrency: usize,
+    /// Requests allowed to wait for a slot before new ones are shed.
+    pub queue_capacity: usize,
+    /// How long a queued request waits for a slot before it is shed.
+    pub queue_timeout: Duration,
+    pub retry_after: Duration,
+    /// How often a queued stream request is sent an SSE comment with its place in line; the
+    /// stream's headers go out as soon as it queues. `None` holds queued streams silently
+    /// until they are admitted.
+    pub progress_interval: Option<Duration>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 1_024,
+            queue_capacity: 512,
+            queue_timeout: Duration::from_millis(1_000),
+            retry_after: Duration::from_secs(1),
+            progress_interval: Some(Duration::from_millis(500)),
+        }
+    }
+}
+
+impl AdmissionConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_concurrency: read_usize(
+                "GATEWAY_ADMISSION_MAX_CONCURRENCY",
+                defaults.max_concurrency,
+            ),
+            queue_capacity: read_usize("GATEWAY_ADMISSION_QUEUE_CAPACITY", defaults.queue_capacity),
+            queue_timeout: Duration::from_millis(read_u64(
+                "GATEWAY_ADMISSION_QUEUE_TIMEOUT_MS",
+                defaults.queue_timeout.as_millis() as u64,
+            )),
+            retry_after: Duration::from_secs(read_u64(
+                "GATEWAY_OVERLOAD_RETRY_AFTER_SECS",
+                defaults.retry_after.as_secs(),
+            )),
+            progress_interval: match read_u64(
+                "GATEWAY_ADMISSION_PROGRESS_MS",
+                defaults
+                    .progress_interval
+                    .map_or(0, |interval| interval.as_millis() as u64),
+            ) {
+                0 => None,
+                millis => Some(Duration::from_millis(millis)),
+            },
+        }
+    }
+}
+
+/// Gateway-wide concurrency budget in front of all chat work. Requests past the budget wait
+/// in a bounded queue; once the queue is full or the wait times out they are shed with `503`.
+pub struct AdmissionController {
+    config: AdmissionConfig,
+    slots: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    /// Requests that have joined and left the wait queue, so a waiting request can tell how
+    /// many of those that arrived before it are still ahead.
+    arrivals: AtomicU64,
+    departures: AtomicU64,
+    /// Recent queue wait per place in line, in microseconds; `0` until a queued request has
+    /// been admitted.
+    wait_per_position_us: AtomicU64,
+    metrics: Arc<AppMetrics>,
+}
+
+/// Held for the lifetime of an admitted request, including its streamed body.
+pub struct AdmissionTicket {
+    _permit: Option<(OwnedSemaphorePermit, PoolUsage)>,
+    /// 1-based position in the wait queue on arrival, if the request had to queue.
+    pub queue_position: Option<usize>,
+    /// Estimated wait for a slot on arrival, if the request had to queue.
+    pub queue_eta: Option<Duration>,
+}
+
+/// A request's place in the wait queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueProgress {
+    /// 1-based.
+    pub position: usize,
+    /// Estimated wait for a slot from recent queue waits; `None` until one has been seen.
+    pub eta: Option<Duration>,
+}
+
+pub enum Admission {
+    Admitted(AdmissionTicket),
+    Queued(QueuedRequest),
+}
+
+/// A request holding a place in the wait queue; dropping it gives the place up.
+pub struct QueuedRequest {
+    tracker: QueueTracker,
+    arrived: QueueProgress,
+    enqueued_at: Instant,
+    left: bool,
+}
+
+/// Reads a queued request's current place in line while it waits.
+#[derive(Clone)]
+pub struct QueueTracker {
+    controller: Arc<AdmissionController>,
+    arrival: u64,
+}
+
+impl AdmissionController {
+    pub fn new(config: AdmissionConfig, metrics: Arc<AppMetrics>) -> Self {
+        metrics.set_pool_capacity("admission", config.max_concurrency);
+        Self {
+            config,
+            slots: Arc::new(Semaphore::new(config.max_concurrency)),
+            waiting: AtomicUsize::new(0),
+            arrivals: AtomicU64::new(0),
+            departures: AtomicU64::new(0),
+            wait_per_position_us: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    /// Admits the request, waiting in the queue for a slot if need be.
+    pub async fn admit(
+        self: &Arc<Self>,
+        deadline: Option<Instant>,
+    ) -> Result<AdmissionTicket, AppError> {
+        match self.enqueue()? {
+            Admission::Admitted(ticket) => Ok(ticket),
+            Admission::Queued(queued) => queued.wait(deadline).await,
+        }
+    }
+
+    /// Takes a free slot, or a place in the wait queue when there is none; sheds the request
+    /// when the queue is full too.
+    pub fn enqueue(self: &Arc<Self>) -> Result<Admission, AppError> {
+        if self.config.max_concurrency == 0 {
+            return Ok(Admission::Admitted(AdmissionTicket {
+                _permit: None,
+                queue_position: None,
+                queue_eta: None,
+            }));
+        }
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            self.metrics.observe_queue_wait("admission", Duration::ZERO);
+            return Ok(Admission::Admitted(AdmissionTicket {
+                _permit: Some((permit, self.metrics.pool_usage("admission"))),
+                queue_position: None,
+                queue_eta: None,
+            }));
+        }
+
+        let capacity = self.config.queue_capacity;
+        self.waiting
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |waiting| {
+                (waiting < capacity).then_some(waiting + 1)
+            })
+            .map_err(|_| self.shed("gateway queue is full"))?;
+        self.metrics.adjust_queue_depth("admission", 1);
+        let tracker = QueueTracker {
+            controller: self.clone(),
+            arrival: self.arrivals.fetch_add(1, Ordering::AcqRel),
+        };
+        Ok(Admission::Queued(QueuedRequest {
+            arrived: tracker.progress(),
+            tracker,
+            enqueued_at: Instant::now(),
+            left: false,
+        }))
+    }
+
+    fn eta(&self, position: usize) -> Option<Duration> {
+        match self.wait_per_position_us.load(Ordering::Acquire) {
+            0 => None,
+            micros => Some(Duration::from_micros(
+                micros.saturating_mul(position as u64),
+            )),
+        }
+    }
+
+    /// Folds a queued request's wait into the per-position estimate.
+    fn observe_wait(&self, position: usize, waited: Duration) {
+        let sample = (waited.as_micros() as u64 / position.max(1) as u64).max(1);
+        let _ =
+            self.wait_per_position_us
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |recent| {
+                    Some(match recent {
+                        0 => sample,
+                        recent => (recent * 3 + sample) / 4,
+                    })
+                });
+    }
+
+    fn shed(&self, message: &str) -> AppError {
+        self.metrics.observe_load_shed("admission");
+        AppError::Overloaded {
+            message: message.to_owned(),
+            retry_after_secs: self.config.retry_after.as_secs().max(1),
+        }
+    }
+}
+
+impl QueuedRequest {
+    /// Place in line and estimated wait when the request joined the queue.
+    pub fn arrived(&self) -> QueueProgress {
+        self.arrived
+    }
+
+    pub fn tracker(&self) -> QueueTracker {
+        self.tracker.clone()
+    }
+
+    /// Waits for a slot until the queue timeout or `deadline`, whichever comes first.
+    pub async fn wait(mut self, deadline: Option<Instant>) -> Result<AdmissionTicket, AppError> {
+        let controller = self.tracker.controller.clone();
+        let timeout_at = self.enqueued_at + controller.config.queue_timeout;
+        let acquired =
+            with_deadline(deadline, "admission queue", async {
+                Ok(tokio::time::timeout_at(
+                    timeout_at.into(),
+                    controller.slots.clone().acquire_owned(),
+                )
+                .await)
+            })
+            .await;
+        self.leave();
+
+        match acquired.map_err(AppError::from)? {
+            Ok(Ok(permit)) => {
+                let waited = self.enqueued_at.elapsed();
+                controller.metrics.observe_queue_wait("admission", waited);
+                controller.observe_wait(self.arrived.position, waited);
+                Ok(AdmissionTicket {
+                    _permit: Some((permit, controller.metrics.pool_usage("admission"))),
+                    queue_position: Some(self.arrived.position),
+                    queue_eta: self.arrived.eta,
+                })
+            }
+            Ok(Err(_)) => Err(AppError::Internal("admission control closed".to_owned())),
+            Err(_) => Err(controller.shed("timed out waiting for gateway capacity")),
+        }
+    }
+
+    fn leave(&mut self) {
+        if std::mem::replace(&mut self.left, true) {
+            return;
+        }
+        let controller = &self.tracker.controller;
+        controller.waiting.fetch_sub(1, Ordering::AcqRel);
+        controller.departures.fetch_add(1, Ordering::AcqRel);
+        controller.metrics.adjust_queue_depth("admission", -1);
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+impl QueueTracker {
+    /// Slots are handed out first come, first served, so the requests ahead are those that
+    /// arrived earlier and have not left yet.
+    pub fn progress(&self) -> QueueProgress {
+        let controller = &self.controller;
+        let ahead = self
+            .arrival
+            .saturating_sub(controller.departures.load(Ordering::Acquire));
+        let waiting = controller.waiting.load(Ordering::Acquire).max(1);
+        let position = (ahead as usize + 1).min(waiting);
+        QueueProgress {
+            position,
+            eta: controller.eta(position),
+        }
+    }
+}
+
+fn read_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(default)
+}
+
+fn read_u64(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{Admission, AdmissionConfig, AdmissionController, QueueProgress};
+    use crate::{errors::AppError, metrics::AppMetrics};
+
+    #[tokio::test]
+    async fn queues_then_sheds_past_the_budget() {
+        let metrics = Arc::new(AppMetrics::new());
+        let controller = Arc::new(AdmissionController::new(
+            AdmissionConfig {
+                max_concurrency: 1,
+                queue_capacity: 1,
+                queue_timeout: Duration::from_millis(200),
+                retry_after: Duration::from_secs(3),
+                progress_interval: None,
+            },
+            metrics.clone(),
+        ));
+
+        let held = controller
+            .admit(None)
+            .await
+            .expect("first request admitted");
+        assert_eq!(held.queue_position, None);
+
+        let queued = {
+            let controller = controller.clone();
+            tokio::spawn(async move { controller.admit(None).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let shed = controller.admit(None).await;
+        assert!(matches!(
+            shed,
+            Err(AppError::Overloaded {
+                retry_after_secs: 3,
+                ..
+            })
+        ));
+
+        drop(held);
+        let ticket = queued
+            .await
+            .expect("join")
+            .expect("queued request admitted");
+        assert_eq!(ticket.queue_position, Some(1));
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_load_shed_total{stage=\"admission\"} 1"));
+        assert!(rendered.contains("gateway_pool_in_use{pool=\"admission\"} 1"));
+        assert!(rendered.contains("gateway_queue_depth{queue=\"admission\"} 0"));
+        assert!(rendered.contains("gateway_queue_wait_seconds_count{queue=\"admission\"} 2"));
+    }
+
+    #[tokio::test]
+    async fn reports_place_in_line_and_estimates_the_wait() {
+        let controller = Arc::new(AdmissionController::new(
+            AdmissionConfig {
+                max_concurrency: 1,
+                queue_capacity: 2,
+                queue_timeout: Duration::from_secs(1),
+                ..AdmissionConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let queued = |controller: &Arc<AdmissionController>| match controller.enqueue() {
+            Ok(Admission::Queued(queued)) => queued,
+            _ => panic!("expected the request to queue"),
+        };
+
+        let held = controller.admit(None).await.expect("slot taken");
+        let first = queued(&controller);
+        let second = queued(&controller);
+        let unknown = |position| QueueProgress {
+            position,
+            eta: None,
+        };
+        assert_eq!(first.arrived(), unknown(1));
+        assert_eq!(second.arrived(), unknown(2));
+
+        let tracker = second.tracker();
+        let first = tokio::spawn(first.wait(None));
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        drop(held);
+        let ticket = first.await.expect("join").expect("first admitted");
+        assert_eq!(ticket.queue_position, Some(1));
+
+        // The second request moved up, and the first one's wait is the estimate for it.
+        let progress = tracker.progress();
+        assert_eq!(progress.position, 1);
+        let eta = progress.eta.expect("estimated");
+        assert!(eta >= Duration::from_millis(40) && eta < Duration::from_secs(1));
+        drop(second);
+        assert_eq!(
+            controller
+                .waiting
+                .load(std::sync::atomic::Ordering::Acquire),
+            0
+        );
+    }
+}
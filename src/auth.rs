@@ -1,8 +1,21 @@
-use std::{collections::HashSet, env};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+};
 
+use async_trait::async_trait;
 use axum::http::HeaderMap;
+use serde::Deserialize;
+use tracing::warn;
 
-use crate::errors::AppError;
+use crate::{
+    errors::AppError,
+    glob,
+    models::{InvalidParameter, Priority},
+    prompts::SystemPromptPolicy,
+    tenants::{read_tenants, Tenant, TenantPolicy},
+    truncation::TruncationStrategy,
+};
 
 #[derive(Debug, Clone)]
 pub struct RatePolicy {
@@ -11,20 +24,214 @@ pub struct RatePolicy {
     pub tokens_per_day: u64,
 }
 
+impl Default for RatePolicy {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 120,
+            tokens_per_minute: 120_000,
+            tokens_per_day: 2_000_000,
+        }
+    }
+}
+
+/// Per-key settings loaded from `GATEWAY_KEY_POLICIES`, a JSON object keyed by API key.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KeyPolicy {
+    /// Tenant id the key belongs to; see `GATEWAY_TENANTS`.
+    pub tenant: Option<String>,
+    /// Default scheduling priority and the ceiling for `x-gateway-priority`; unset falls back
+    /// to the tier's entry in `GATEWAY_FAIR_TIER_PRIORITIES`.
+    pub priority: Option<Priority>,
+    /// `Some(false)` routes the key's one-shot requests around the micro-batcher.
+    pub batching: Option<bool>,
+    /// `Some(false)` never coalesces the key's requests; `Some(true)` also coalesces sampled
+    /// (`temperature > 0`) requests.
+    pub coalesce: Option<bool>,
+    /// `Some(false)` rejects the key's `stream: true` requests, e.g. for batch-tier keys whose
+    /// traffic should go through the micro-batcher rather than hold connections open.
+    pub streaming: Option<bool>,
+    /// Tier name looked up in `GATEWAY_FAIR_TIER_WEIGHTS` for the key's fair-queuing weight.
+    pub tier: Option<String>,
+    /// System prompt added to the key's requests, in place of its tenant's.
+    pub system_prompt: Option<SystemPromptPolicy>,
+    /// Names of processors from `GATEWAY_POST_PROCESSORS` applied, in order, to the key's
+    /// responses.
+    pub post_process: Vec<String>,
+    /// Overrides `GATEWAY_TRUNCATION` for the key's over-long conversations.
+    pub truncation: Option<TruncationStrategy>,
+    /// Globs of the backends the key may pin a request to with `x-gateway-backend`; empty
+    /// leaves every request to the router.
+    pub backends: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct AuthContext {
     pub api_key: String,
     pub user_id: String,
+    pub tenant: Tenant,
     pub policy: RatePolicy,
+    pub key_policy: KeyPolicy,
 }
 
+/// What a valid API key is allowed to do.
+#[derive(Debug, Clone)]
+pub struct KeyGrant {
+    pub rate: RatePolicy,
+    pub policy: KeyPolicy,
+    /// `None` makes the key its own tenant, with no shared policy.
+    pub tenant: Option<Tenant>,
+}
+
+/// Source of valid API keys. The gateway ships [`ApiKeyRegistry`]; embedders can back keys
+/// with their own database or identity service.
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// The grant for `api_key`, or `None` when the key is not valid.
+    async fn lookup(&self, api_key: &str) -> Option<KeyGrant>;
+}
+
+impl dyn KeyStore {
+    /// Resolves the `x-api-key` header into the caller's identity and policies.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<AuthContext, AppError> {
+        let api_key = headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| AppError::Unauthorized("missing x-api-key header".to_owned()))?;
+
+        let grant = self
+            .lookup(api_key)
+            .await
+            .ok_or_else(|| AppError::Unauthorized("invalid api key".to_owned()))?;
+
+        let user_id = format!("key_{}", redact_key(api_key));
+        let mut key_policy = grant.policy;
+        let tenant = grant.tenant.unwrap_or_else(|| {
+            let id = key_policy.tenant.clone().unwrap_or_else(|| user_id.clone());
+            Tenant::new(id, TenantPolicy::default())
+        });
+        if key_policy.tier.is_none() {
+            key_policy.tier = tenant.policy.tier.clone();
+        }
+
+        Ok(AuthContext {
+            api_key: api_key.to_owned(),
+            user_id,
+            tenant,
+            policy: grant.rate,
+            key_policy,
+        })
+    }
+}
+
+/// Static key list sharing one rate policy, with optional per-key and per-tenant policies.
 #[derive(Debug, Clone)]
 pub struct ApiKeyRegistry {
     valid_keys: HashSet<String>,
     policy: RatePolicy,
+    key_policies: HashMap<String, KeyPolicy>,
+    tenants: HashMap<String, TenantPolicy>,
+}
+
+impl AuthContext {
+    /// Resolves the request priority: `x-gateway-priority` may lower a request below the
+    /// key's priority but never raise it above. Keys whose policy sets no priority get
+    /// `tier_priority`, their tier's, else `normal`.
+    pub fn request_priority(
+        &self,
+        headers: &HeaderMap,
+        tier_priority: Option<Priority>,
+    ) -> Result<Priority, AppError> {
+        let ceiling = self
+            .key_policy
+            .priority
+            .or(tier_priority)
+            .unwrap_or_default();
+        let Some(value) = headers.get("x-gateway-priority") else {
+            return Ok(ceiling);
+        };
+        let requested = value
+            .to_str()
+            .ok()
+            .and_then(Priority::parse)
+            .ok_or_else(|| {
+                AppError::from(InvalidParameter::new(
+                    "x-gateway-priority",
+                    "invalid_value",
+                    "x-gateway-priority must be low, normal, or high",
+                ))
+            })?;
+        Ok(requested.min(ceiling))
+    }
+
+    /// The backend named by `x-gateway-backend`, if any. Only keys whose policy lists the
+    /// backend in `backends` may pin it; whether such a backend exists is up to the caller.
+    pub fn pinned_backend(&self, headers: &HeaderMap) -> Result<Option<String>, AppError> {
+        let Some(value) = headers.get("x-gateway-backend") else {
+            return Ok(None);
+        };
+        let backend = value
+            .to_str()
+            .ok()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| {
+                AppError::from(InvalidParameter::new(
+                    "x-gateway-backend",
+                    "invalid_value",
+                    "x-gateway-backend must name a backend",
+                ))
+            })?;
+        if !self
+            .key_policy
+            .backends
+            .iter()
+            .any(|pattern| glob::matches(pattern, backend))
+        {
+            return Err(AppError::Forbidden(format!(
+                "this API key may not pin requests to backend `{backend}`"
+            )));
+        }
+        Ok(Some(backend.to_owned()))
+    }
+
+    /// The key's system prompt, falling back to its tenant's.
+    pub fn system_prompt(&self) -> Option<&SystemPromptPolicy> {
+        self.key_policy
+            .system_prompt
+            .as_ref()
+            .or(self.tenant.policy.system_prompt.as_ref())
+    }
 }
 
 impl ApiKeyRegistry {
+    pub fn new<I, K>(keys: I, policy: RatePolicy) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        Self {
+            valid_keys: keys.into_iter().map(Into::into).collect(),
+            policy,
+            key_policies: HashMap::new(),
+            tenants: HashMap::new(),
+        }
+    }
+
+    pub fn with_key_policy(mut self, api_key: impl Into<String>, policy: KeyPolicy) -> Self {
+        self.key_policies.insert(api_key.into(), policy);
+        self
+    }
+
+    /// Policy for the tenant keys name in [`KeyPolicy::tenant`]; a tenant without one only
+    /// groups its keys.
+    pub fn with_tenant(mut self, tenant_id: impl Into<String>, policy: TenantPolicy) -> Self {
+        self.tenants.insert(tenant_id.into(), policy);
+        self
+    }
+
     pub fn from_env() -> Self {
         let keys = env::var("GATEWAY_API_KEYS").unwrap_or_else(|_| "dev-key".to_owned());
         let mut valid_keys = keys
@@ -37,35 +244,66 @@ impl ApiKeyRegistry {
             valid_keys.insert("dev-key".to_owned());
         }
 
+        let defaults = RatePolicy::default();
         let policy = RatePolicy {
-            requests_per_minute: read_u32("GATEWAY_LIMIT_REQUESTS_PER_MINUTE", 120),
-            tokens_per_minute: read_u64("GATEWAY_LIMIT_TOKENS_PER_MINUTE", 120_000),
-            tokens_per_day: read_u64("GATEWAY_LIMIT_TOKENS_PER_DAY", 2_000_000),
+            requests_per_minute: read_u32(
+                "GATEWAY_LIMIT_REQUESTS_PER_MINUTE",
+                defaults.requests_per_minute,
+            ),
+            tokens_per_minute: read_u64(
+                "GATEWAY_LIMIT_TOKENS_PER_MINUTE",
+                defaults.tokens_per_minute,
+            ),
+            tokens_per_day: read_u64("GATEWAY_LIMIT_TOKENS_PER_DAY", defaults.tokens_per_day),
         };
 
-        Self { valid_keys, policy }
+        Self {
+            valid_keys,
+            policy,
+            key_policies: read_key_policies(),
+            tenants: read_tenants(),
+        }
     }
+}
 
-    pub fn authenticate(&self, headers: &HeaderMap) -> Result<AuthContext, AppError> {
-        let api_key = headers
-            .get("x-api-key")
-            .and_then(|value| value.to_str().ok())
-            .map(str::trim)
-            .filter(|value| !value.is_empty())
-            .ok_or_else(|| AppError::Unauthorized("missing x-api-key header".to_owned()))?;
-
+#[async_trait]
+impl KeyStore for ApiKeyRegistry {
+    async fn lookup(&self, api_key: &str) -> Option<KeyGrant> {
         if !self.valid_keys.contains(api_key) {
-            return Err(AppError::Unauthorized("invalid api key".to_owned()));
+            return None;
         }
-
-        Ok(AuthContext {
-            api_key: api_key.to_owned(),
-            user_id: format!("key_{}", redact_key(api_key)),
-            policy: self.policy.clone(),
+        let policy = self.key_policies.get(api_key).cloned().unwrap_or_default();
+        let tenant = policy.tenant.as_ref().map(|tenant_id| {
+            Tenant::new(
+                tenant_id.clone(),
+                self.tenants.get(tenant_id).cloned().unwrap_or_default(),
+            )
+        });
+        Some(KeyGrant {
+            rate: self.policy.clone(),
+            policy,
+            tenant,
         })
     }
 }
 
+fn read_key_policies() -> HashMap<String, KeyPolicy> {
+    let Ok(raw) = env::var("GATEWAY_KEY_POLICIES") else {
+        return HashMap::new();
+    };
+    if raw.trim().is_empty() {
+        return HashMap::new();
+    }
+
+    match serde_json::from_str::<HashMap<String, KeyPolicy>>(&raw) {
+        Ok(policies) => policies,
+        Err(error) => {
+            warn!(error = %error, "invalid GATEWAY_KEY_POLICIES, ignoring per-key policies");
+            HashMap::new()
+        }
+    }
+}
+
 fn read_u32(name: &str, default: u32) -> u32 {
     env::var(name)
         .ok()
@@ -83,3 +321,9 @@ fn read_u64(name: &str, default: u64) -> u64 {
 fn redact_key(key: &str) -> String {
     key.chars().take(8).collect()
 }
+
+/// Compares secrets in time that depends only on their lengths, so a caller cannot recover one
+/// byte by byte from how quickly wrong guesses are refused.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
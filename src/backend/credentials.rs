@@ -0,0 +1,193 @@
+use std::sync::Mutex;
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{errors::AppError, state::AppState};
+
+/// The upstream API keys a backend may authenticate with, in order of preference. Requests use
+/// the active key; once the provider rejects it the next one takes over, wrapping around to
+/// the first. Shared between an adapter's clones so a rotation or a hot swap applies to every
+/// copy at once.
+#[derive(Debug)]
+pub struct Credentials {
+    state: Mutex<KeySet>,
+}
+
+#[derive(Debug)]
+struct KeySet {
+    keys: Vec<String>,
+    active: usize,
+    /// Bumped by [`Credentials::replace`], so rejections of a replaced key are ignored.
+    generation: u64,
+}
+
+/// The key a request was sent with, to report back to [`Credentials::reject`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub key: String,
+    index: usize,
+    generation: u64,
+}
+
+impl Credentials {
+    /// `keys` must not be empty.
+    pub fn new(keys: Vec<String>) -> Self {
+        assert!(
+            !keys.is_empty(),
+            "at least one credential must be configured"
+        );
+        Self {
+            state: Mutex::new(KeySet {
+                keys,
+                active: 0,
+                generation: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, KeySet> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn active(&self) -> Credential {
+        let state = self.lock();
+        Credential {
+            key: state.keys[state.active].clone(),
+            index: state.active,
+            generation: state.generation,
+        }
+    }
+
+    /// Moves past `credential` after the provider refused it. Concurrent requests rejected
+    /// with the same key rotate only once, and a key already rotated away from or replaced is
+    /// left alone. Returns whether another key is now active.
+    pub fn reject(&self, credential: &Credential) -> bool {
+        let mut state = self.lock();
+        if state.generation != credential.generation || state.active != credential.index {
+            return true;
+        }
+        if state.keys.len() == 1 {
+            return false;
+        }
+        state.active = (state.active + 1) % state.keys.len();
+        true
+    }
+
+    /// Swaps in a new set of keys, starting from the first, without restarting.
+    pub fn replace(&self, keys: Vec<String>) -> Result<(), String> {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.trim().to_owned())
+            .collect::<Vec<_>>();
+        if keys.is_empty() || keys.iter().any(String::is_empty) {
+            return Err("api_keys must list at least one non-empty key".to_owned());
+        }
+        let mut state = self.lock();
+        state.keys = keys;
+        state.active = 0;
+        state.generation += 1;
+        Ok(())
+    }
+
+    /// How many keys are configured.
+    pub fn count(&self) -> usize {
+        self.lock().keys.len()
+    }
+
+    /// Position of the active key among the configured ones.
+    pub fn active_index(&self) -> usize {
+        self.lock().active
+    }
+}
+
+/// Body of `PUT /admin/backends/{name}/credentials`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceCredentials {
+    pub api_keys: Vec<String>,
+}
+
+/// Response of `PUT /admin/backends/{name}/credentials`; the keys themselves are never echoed.
+#[derive(Debug, Serialize)]
+pub struct CredentialSummary {
+    pub backend: String,
+    pub credentials: usize,
+    pub active: usize,
+}
+
+/// `PUT /admin/backends/{name}/credentials`: replaces the upstream API keys of a backend in
+/// place, or `404` when no backend of that name authenticates with keys.
+pub async fn replace_credentials(
+    State(state): State<AppState>,
+    Path(backend): Path<String>,
+    payload: Result<Json<ReplaceCredentials>, JsonRejection>,
+) -> Response {
+    let Json(update) = match payload {
+        Ok(update) => update,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let Some(credentials) = state.backend.credentials(&backend) else {
+        return AppError::NotFound(format!("backend `{backend}` has no credentials to replace"))
+            .into_response();
+    };
+    if let Err(message) = credentials.replace(update.api_keys) {
+        return AppError::BadRequest(message).into_response();
+    }
+    info!(
+        backend = %backend,
+        credentials = credentials.count(),
+        "backend credentials replaced"
+    );
+    Json(CredentialSummary {
+        backend,
+        credentials: credentials.count(),
+        active: credentials.active_index(),
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Credentials;
+
+    #[test]
+    fn rotates_once_per_rejected_key_and_ignores_stale_rejections() {
+        let credentials = Credentials::new(vec!["sk-a".to_owned(), "sk-b".to_owned()]);
+        let first = credentials.active();
+        assert_eq!(first.key, "sk-a");
+
+        // Two requests rejected with the same key move on only once.
+        assert!(credentials.reject(&first));
+        assert!(credentials.reject(&first));
+        assert_eq!(credentials.active().key, "sk-b");
+
+        // The last key wraps around to the first.
+        assert!(credentials.reject(&credentials.active()));
+        assert_eq!(credentials.active().key, "sk-a");
+
+        // A rejection of a key that was swapped out in the meantime changes nothing.
+        let before_swap = credentials.active();
+        credentials
+            .replace(vec!["sk-c".to_owned(), " sk-d ".to_owned()])
+            .expect("valid keys");
+        assert!(credentials.reject(&before_swap));
+        assert_eq!(credentials.active().key, "sk-c");
+        assert!(credentials.reject(&credentials.active()));
+        assert_eq!(credentials.active().key, "sk-d");
+
+        assert!(credentials.replace(Vec::new()).is_err());
+        assert!(credentials.replace(vec![" ".to_owned()]).is_err());
+        assert_eq!(credentials.count(), 2);
+
+        let single = Credentials::new(vec!["sk-only".to_owned()]);
+        assert!(!single.reject(&single.active()));
+    }
+}
@@ -1,18 +1,172 @@
-use std::time::Duration;
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use futures_util::StreamExt;
+use serde::Deserialize;
 use tokio::{sync::mpsc, time::sleep};
 use tokio_stream::wrappers::ReceiverStream;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::backend::{BackendError, BackendStream, InferenceBackend};
-use crate::models::{BackendChatResponse, BackendChunk, MessageRole, NormalizedChatRequest, Usage};
+use crate::glob;
+use crate::models::{
+    normalize_finish_reason, BackendChatResponse, BackendChunk, MessageRole, NormalizedChatRequest,
+    Usage,
+};
 
 #[derive(Debug, Clone)]
 pub struct MockBackend {
     name: String,
     token_delay: Duration,
+    faults: Arc<Vec<MockFaultRule>>,
+    script: Arc<Vec<MockScriptEntry>>,
+    /// Draws for error rates and latency jitter; a fixed seed gives the same sequence of
+    /// outcomes on every run with the same call order.
+    rng: Arc<AtomicU64>,
+}
+
+/// Failure behavior injected into [`MockBackend`] calls for models matching `model`. Rules are
+/// read from `GATEWAY_MOCK_FAULTS` as a JSON list, first match wins, e.g.
+/// `[{"model":"gpt-4o*","error_rate":0.2,"latency_ms":200,"latency_jitter_ms":300}]`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockFaultRule {
+    /// Model glob; `*` applies to every model.
+    pub model: String,
+    /// Fraction of calls, from `0.0` to `1.0`, that fail with `error` instead of answering.
+    pub error_rate: f64,
+    pub error: MockError,
+    /// Added before every call answers or its stream starts, plus up to `latency_jitter_ms`
+    /// more drawn uniformly.
+    pub latency_ms: u64,
+    pub latency_jitter_ms: u64,
+    /// Delay between stream chunks in place of the backend's own, for slow drips.
+    pub token_delay_ms: Option<u64>,
+    /// Ends streams after this many content chunks without a final chunk or usage.
+    pub truncate_after_chunks: Option<usize>,
+    /// Fails streams after this many content chunks as an unparseable upstream chunk would.
+    pub malformed_after_chunks: Option<usize>,
+}
+
+impl Default for MockFaultRule {
+    fn default() -> Self {
+        Self {
+            model: "*".to_owned(),
+            error_rate: 0.0,
+            error: MockError::default(),
+            latency_ms: 0,
+            latency_jitter_ms: 0,
+            token_delay_ms: None,
+            truncate_after_chunks: None,
+            malformed_after_chunks: None,
+        }
+    }
+}
+
+/// Error class of injected failures, chosen to exercise retry and failover classification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MockError {
+    #[default]
+    Unavailable,
+    Timeout,
+    RateLimited,
+    InvalidResponse,
+    Unauthorized,
+    BadRequest,
+}
+
+impl MockError {
+    fn into_backend_error(self, backend: &str) -> BackendError {
+        let message = format!("injected fault in {backend}");
+        match self {
+            Self::Unavailable => BackendError::Unavailable(message),
+            Self::Timeout => BackendError::Timeout(message),
+            Self::RateLimited => BackendError::RateLimited {
+                message,
+                headers: vec![("retry-after".to_owned(), "1".to_owned())],
+                retry_after: Some(Duration::from_secs(1)),
+            },
+            Self::InvalidResponse => BackendError::InvalidResponse(message),
+            Self::Unauthorized => BackendError::Unauthorized(message),
+            Self::BadRequest => BackendError::BadRequest(message),
+        }
+    }
+}
+
+pub fn read_fault_rules() -> Vec<MockFaultRule> {
+    let Ok(raw) = env::var("GATEWAY_MOCK_FAULTS") else {
+        return Vec::new();
+    };
+    if raw.trim().is_empty() {
+        return Vec::new();
+    }
+
+    match serde_json::from_str::<Vec<MockFaultRule>>(&raw) {
+        Ok(rules) => rules,
+        Err(error) => {
+            warn!(error = %error, "invalid GATEWAY_MOCK_FAULTS, mock backends run without faults");
+            Vec::new()
+        }
+    }
+}
+
+/// A canned answer for requests whose model and last user message match the globs. Scripts are
+/// read from the JSON file named by `GATEWAY_MOCK_SCRIPT`, a list where the first match wins,
+/// e.g. `[{"prompt":"*weather*","content":"Sunny, 24°C.","completion_tokens":5}]`;
+/// unmatched requests get the echo response.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockScriptEntry {
+    pub model: String,
+    pub prompt: String,
+    pub content: String,
+    pub finish_reason: String,
+    /// Reported usage; unset counts are estimated from the text like unscripted responses.
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+}
+
+impl Default for MockScriptEntry {
+    fn default() -> Self {
+        Self {
+            model: "*".to_owned(),
+            prompt: "*".to_owned(),
+            content: String::new(),
+            finish_reason: "stop".to_owned(),
+            prompt_tokens: None,
+            completion_tokens: None,
+        }
+    }
+}
+
+pub fn read_script() -> Vec<MockScriptEntry> {
+    let Some(path) = env::var_os("GATEWAY_MOCK_SCRIPT").filter(|path| !path.is_empty()) else {
+        return Vec::new();
+    };
+    let parsed = std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|raw| {
+            serde_json::from_str::<Vec<MockScriptEntry>>(&raw).map_err(|error| error.to_string())
+        });
+    match parsed {
+        Ok(script) => script,
+        Err(error) => {
+            warn!(
+                error = %error,
+                path = %std::path::Path::new(&path).display(),
+                "invalid GATEWAY_MOCK_SCRIPT, mock backends echo prompts"
+            );
+            Vec::new()
+        }
+    }
 }
 
 impl Default for MockBackend {
@@ -20,6 +174,9 @@ impl Default for MockBackend {
         Self {
             name: "mock-backend".to_owned(),
             token_delay: Duration::from_millis(35),
+            faults: Arc::new(Vec::new()),
+            script: Arc::new(Vec::new()),
+            rng: Arc::new(AtomicU64::new(0)),
         }
     }
 }
@@ -31,6 +188,97 @@ impl MockBackend {
             ..Self::default()
         }
     }
+
+    /// Injects failures per [`MockFaultRule`], first matching rule per request.
+    pub fn with_faults(mut self, rules: Vec<MockFaultRule>) -> Self {
+        self.faults = Arc::new(rules);
+        self
+    }
+
+    /// Answers matching requests with [`MockScriptEntry`] responses instead of the echo.
+    pub fn with_script(mut self, script: Vec<MockScriptEntry>) -> Self {
+        self.script = Arc::new(script);
+        self
+    }
+
+    /// Pause between streamed chunks; fault rules with `token_delay_ms` still override it.
+    pub fn with_token_delay(mut self, delay: Duration) -> Self {
+        self.token_delay = delay;
+        self
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.rng.store(seed, Ordering::Relaxed);
+        self
+    }
+
+    fn respond(&self, request: &NormalizedChatRequest) -> BackendChatResponse {
+        let prompt = last_user_message(request);
+        let scripted = self.script.iter().find(|entry| {
+            glob::matches(&entry.model, &request.model) && glob::matches(&entry.prompt, prompt)
+        });
+        let Some(entry) = scripted else {
+            let content = format!("Mock response for model {}: {}", request.model, prompt);
+            return BackendChatResponse {
+                usage: estimate_usage(request, &content),
+                content,
+                finish_reason: "stop".to_owned(),
+                provider_finish_reason: None,
+                backend: None,
+                upstream_headers: None,
+            };
+        };
+        let estimated = estimate_usage(request, &entry.content);
+        let (finish_reason, provider_finish_reason) = normalize_finish_reason(&entry.finish_reason);
+        BackendChatResponse {
+            content: entry.content.clone(),
+            finish_reason,
+            provider_finish_reason,
+            usage: Usage::new(
+                entry.prompt_tokens.unwrap_or(estimated.prompt_tokens),
+                entry
+                    .completion_tokens
+                    .unwrap_or(estimated.completion_tokens),
+            ),
+            backend: None,
+            upstream_headers: None,
+        }
+    }
+
+    fn fault_rule(&self, model: &str) -> Option<&MockFaultRule> {
+        self.faults
+            .iter()
+            .find(|rule| glob::matches(&rule.model, model))
+    }
+
+    /// Uniform draw in `[0, 1)` from a splitmix64 sequence.
+    fn draw(&self) -> f64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Applies the rule's latency and error rate to a call that is about to answer.
+    async fn inject(&self, rule: Option<&MockFaultRule>) -> Result<(), BackendError> {
+        let Some(rule) = rule else {
+            return Ok(());
+        };
+        let jitter = (rule.latency_jitter_ms as f64 * self.draw()) as u64;
+        let latency = rule.latency_ms.saturating_add(jitter);
+        if latency > 0 {
+            sleep(Duration::from_millis(latency)).await;
+        }
+        if rule.error_rate > 0.0 && self.draw() < rule.error_rate {
+            debug!(backend = %self.name, model = %rule.model, "injecting mock backend fault");
+            return Err(rule.error.into_backend_error(&self.name));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -41,36 +289,57 @@ impl InferenceBackend for MockBackend {
 
     async fn execute_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendChatResponse, BackendError> {
-        let content = render_response(&request);
-        let usage = estimate_usage(&request, &content);
-
-        Ok(BackendChatResponse {
-            content,
-            finish_reason: "stop".to_owned(),
-            usage,
-        })
+        self.inject(self.fault_rule(&request.model)).await?;
+        Ok(self.respond(&request))
     }
 
     async fn stream_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendStream, BackendError> {
-        let content = render_response(&request);
-        let usage = estimate_usage(&request, &content);
-        let delay = self.token_delay;
+        let rule = self.fault_rule(&request.model).cloned();
+        self.inject(rule.as_ref()).await?;
+        let BackendChatResponse {
+            content,
+            finish_reason,
+            provider_finish_reason,
+            usage,
+            ..
+        } = self.respond(&request);
+        let delay = rule
+            .as_ref()
+            .and_then(|rule| rule.token_delay_ms)
+            .map_or(self.token_delay, Duration::from_millis);
+        let truncate_after = rule.as_ref().and_then(|rule| rule.truncate_after_chunks);
+        let malformed_after = rule.as_ref().and_then(|rule| rule.malformed_after_chunks);
         let (tx, rx) = mpsc::channel(32);
 
         tokio::spawn(async move {
             let tokens = split_for_stream(&content);
-            for token in tokens {
+            for (sent, token) in tokens.into_iter().enumerate() {
+                if truncate_after == Some(sent) {
+                    return;
+                }
+                if malformed_after == Some(sent) {
+                    let _ = tx
+                        .send(Err(BackendError::InvalidResponse(
+                            "malformed stream chunk: expected value at line 1 column 1".to_owned(),
+                        )))
+                        .await;
+                    return;
+                }
                 if tx
                     .send(Ok(BackendChunk {
                         delta: Some(token),
                         finish_reason: None,
+                        provider_finish_reason: None,
                         usage: None,
                         done: false,
+                        backend: None,
+                        raw: None,
+                        upstream_headers: None,
                     }))
                     .await
                     .is_err()
@@ -84,9 +353,13 @@ impl InferenceBackend for MockBackend {
             let _ = tx
                 .send(Ok(BackendChunk {
                     delta: None,
-                    finish_reason: Some("stop".to_owned()),
+                    finish_reason: Some(finish_reason),
+                    provider_finish_reason,
                     usage: Some(usage),
                     done: true,
+                    backend: None,
+                    raw: None,
+                    upstream_headers: None,
                 }))
                 .await;
         });
@@ -96,16 +369,14 @@ impl InferenceBackend for MockBackend {
     }
 }
 
-fn render_response(request: &NormalizedChatRequest) -> String {
-    let prompt = request
+fn last_user_message(request: &NormalizedChatRequest) -> &str {
+    request
         .messages
         .iter()
         .rev()
         .find(|message| message.role == MessageRole::User)
         .map(|message| message.content.as_str())
-        .unwrap_or("hello");
-
-    format!("Mock response for model {}: {}", request.model, prompt)
+        .unwrap_or("hello")
 }
 
 fn estimate_usage(request: &NormalizedChatRequest, completion: &str) -> Usage {
@@ -125,19 +396,173 @@ fn rough_token_estimate(text: &str) -> u32 {
     text.split_whitespace().count() as u32
 }
 
+/// Splits `text` into words that each carry the whitespace after them, so the chunks join
+/// back into the exact text, line breaks included.
 fn split_for_stream(text: &str) -> Vec<String> {
-    let tokens: Vec<String> = text.split_whitespace().map(ToString::to_string).collect();
-    let len = tokens.len();
-
-    tokens
-        .into_iter()
-        .enumerate()
-        .map(|(index, token)| {
-            if index + 1 == len {
-                token
-            } else {
-                format!("{token} ")
-            }
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for ch in text.chars() {
+        if !ch.is_whitespace() && current.ends_with(char::is_whitespace) {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push(ch);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+
+    use super::{MockBackend, MockError, MockFaultRule, MockScriptEntry};
+    use crate::{
+        backend::{BackendError, InferenceBackend},
+        models::{
+            ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+            Priority,
+        },
+    };
+
+    fn request(model: &str) -> Arc<NormalizedChatRequest> {
+        Arc::new(NormalizedChatRequest {
+            request_id: "req-1".to_owned(),
+            user_id: "user-1".to_owned(),
+            model: model.to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: "one two three four".to_owned(),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+            },
+            stream: true,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
         })
-        .collect()
+    }
+
+    #[tokio::test]
+    async fn error_rates_are_per_model_and_repeat_for_a_seed() {
+        let faulty = || {
+            MockBackend::named("faulty")
+                .with_faults(vec![
+                    MockFaultRule {
+                        model: "down-*".to_owned(),
+                        error_rate: 1.0,
+                        error: MockError::Timeout,
+                        ..MockFaultRule::default()
+                    },
+                    MockFaultRule {
+                        error_rate: 0.5,
+                        ..MockFaultRule::default()
+                    },
+                ])
+                .with_seed(7)
+        };
+        assert!(matches!(
+            faulty().execute_chat(request("down-1")).await,
+            Err(BackendError::Timeout(_))
+        ));
+
+        let outcomes = |backend: MockBackend| async move {
+            let mut outcomes = Vec::new();
+            for _ in 0..40 {
+                outcomes.push(backend.execute_chat(request("mock-1")).await.is_ok());
+            }
+            outcomes
+        };
+        let first = outcomes(faulty()).await;
+        assert_eq!(first, outcomes(faulty()).await);
+        let failures = first.iter().filter(|ok| !**ok).count();
+        assert!((8..=32).contains(&failures), "{failures} of 40 failed");
+    }
+
+    #[tokio::test]
+    async fn scripted_entries_answer_matching_prompts_in_both_modes() {
+        let script: Vec<MockScriptEntry> =
+            serde_json::from_str(include_str!("../../deploy/mock-script.example.json"))
+                .expect("valid example script");
+        let backend = MockBackend::named("scripted").with_script(script);
+        let mut weather = request("gpt-4o");
+        Arc::make_mut(&mut weather).messages[0].content = "what's the weather like?".to_owned();
+
+        let answer = backend
+            .execute_chat(weather.clone())
+            .await
+            .expect("scripted answer");
+        assert!(answer.content.starts_with("It's sunny"));
+        assert_eq!(answer.usage.prompt_tokens, 18);
+        assert_eq!(answer.usage.total_tokens, 37);
+
+        let mut essay = request("gpt-4o-mini");
+        Arc::make_mut(&mut essay).messages[0].content = "write me an essay".to_owned();
+        let mut chunks = backend.stream_chat(essay).await.expect("stream");
+        let mut streamed = String::new();
+        let mut finish_reason = None;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.expect("chunk");
+            streamed.push_str(&chunk.delta.unwrap_or_default());
+            finish_reason = chunk.finish_reason.or(finish_reason);
+        }
+        assert!(streamed.starts_with("Here is the start of your essay.\n\nThe"));
+        assert_eq!(finish_reason.as_deref(), Some("length"));
+
+        let fallback = backend
+            .execute_chat(request("o1"))
+            .await
+            .expect("catch-all answer");
+        assert!(fallback.content.starts_with("I'm a scripted mock response"));
+    }
+
+    #[tokio::test]
+    async fn streams_can_be_cut_short_or_broken() {
+        let backend = MockBackend::named("streams").with_faults(vec![
+            MockFaultRule {
+                model: "truncated".to_owned(),
+                token_delay_ms: Some(0),
+                truncate_after_chunks: Some(2),
+                ..MockFaultRule::default()
+            },
+            MockFaultRule {
+                model: "malformed".to_owned(),
+                token_delay_ms: Some(0),
+                malformed_after_chunks: Some(1),
+                ..MockFaultRule::default()
+            },
+        ]);
+
+        let truncated = backend
+            .stream_chat(request("truncated"))
+            .await
+            .expect("stream")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(truncated.len(), 2);
+        assert!(truncated
+            .iter()
+            .all(|item| item.as_ref().is_ok_and(|chunk| !chunk.done)));
+
+        let malformed = backend
+            .stream_chat(request("malformed"))
+            .await
+            .expect("stream")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(malformed.len(), 2);
+        assert!(matches!(
+            malformed.last(),
+            Some(Err(BackendError::InvalidResponse(_)))
+        ));
+    }
 }
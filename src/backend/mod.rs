@@ -1,11 +1,28 @@
+pub mod client;
+pub mod credentials;
 pub mod mock;
 pub mod openai;
+pub mod peer;
+pub mod ratelimit;
+pub mod sse;
+pub mod validation;
+pub mod vcr;
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
-use futures_util::stream::BoxStream;
+use futures_util::{stream::BoxStream, StreamExt};
 use thiserror::Error;
 
-use crate::models::{BackendChatResponse, BackendChunk, NormalizedChatRequest};
+use crate::{
+    backend::{credentials::Credentials, ratelimit::ProviderLimits},
+    models::{BackendChatResponse, BackendChunk, NormalizedChatRequest},
+    router::EndpointStatus,
+};
 
 pub type BackendStream = BoxStream<'static, Result<BackendChunk, BackendError>>;
 
@@ -14,15 +31,55 @@ pub trait InferenceBackend: Send + Sync {
     fn name(&self) -> &str;
     async fn execute_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendChatResponse, BackendError>;
     async fn stream_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendStream, BackendError>;
+
+    /// Executes a micro-batch of same-class requests, returning one result per request in
+    /// order. Adapters with native batching (vLLM, Triton, TGI) should override this; the
+    /// default runs the requests sequentially.
+    async fn execute_chat_batch(
+        &self,
+        requests: Vec<Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            results.push(self.execute_chat(request).await);
+        }
+        results
+    }
+
+    /// Names of the endpoints a request would be sent to, in the order they would be tried,
+    /// without calling or reserving any. Routers override this; an adapter is its own only
+    /// endpoint.
+    async fn route_plan(&self) -> Vec<String> {
+        vec![self.name().to_owned()]
+    }
+
+    /// Circuit and health-check history of each endpoint, for `GET /admin/backends`. Routers
+    /// override this; an adapter is its own only endpoint, with nothing tracked.
+    async fn endpoint_status(&self) -> Vec<EndpointStatus> {
+        vec![EndpointStatus::untracked(self.name())]
+    }
+
+    /// Budgets the provider reported on its latest response. Adapters that read rate-limit
+    /// headers override this, and wrappers pass it through.
+    fn provider_limits(&self) -> Option<ProviderLimits> {
+        None
+    }
+
+    /// Upstream API keys of the endpoint named `backend`, for hot swaps through
+    /// `PUT /admin/backends/{name}/credentials`. Adapters that authenticate with keys
+    /// override this, and wrappers and routers pass it through.
+    fn credentials(&self, _backend: &str) -> Option<Arc<Credentials>> {
+        None
+    }
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum BackendError {
     #[error("backend unavailable: {0}")]
     Unavailable(String),
@@ -30,4 +87,135 @@ pub enum BackendError {
     Timeout(String),
     #[error("backend invalid response: {0}")]
     InvalidResponse(String),
+    /// The provider refused the gateway's credentials (`401`/`403`), with every configured key.
+    #[error("backend rejected the gateway's credentials: {0}")]
+    Unauthorized(String),
+    /// The provider refused the request itself (`400`, `404`, `413`, `422`), as any other
+    /// endpoint would, so it is neither retried nor held against the endpoint.
+    #[error("backend rejected the request: {0}")]
+    BadRequest(String),
+    /// The provider answered `429`; `headers` are its `Retry-After`/reset headers to pass on,
+    /// and `retry_after` how long it asked to be left alone.
+    #[error("backend rate limited: {message}")]
+    RateLimited {
+        message: String,
+        headers: Vec<(String, String)>,
+        retry_after: Option<Duration>,
+    },
+    /// Rejected by gateway admission control before reaching a backend.
+    #[error("gateway overloaded: {message}")]
+    Overloaded {
+        message: String,
+        retry_after_secs: u64,
+    },
+    /// The request's client deadline passed before a result was produced.
+    #[error("deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+}
+
+/// Runs `work` until `deadline`, abandoning it with `DeadlineExceeded` once the deadline passes.
+pub async fn with_deadline<T, F>(
+    deadline: Option<Instant>,
+    stage: &str,
+    work: F,
+) -> Result<T, BackendError>
+where
+    F: Future<Output = Result<T, BackendError>>,
+{
+    let Some(deadline) = deadline else {
+        return work.await;
+    };
+    if deadline <= Instant::now() {
+        return Err(deadline_exceeded(stage));
+    }
+    tokio::time::timeout_at(deadline.into(), work)
+        .await
+        .unwrap_or_else(|_| Err(deadline_exceeded(stage)))
+}
+
+/// Ends `stream` with a `DeadlineExceeded` item at `deadline` instead of letting it run on.
+pub fn stream_with_deadline(stream: BackendStream, deadline: Option<Instant>) -> BackendStream {
+    let Some(deadline) = deadline else {
+        return stream;
+    };
+    async_stream::stream! {
+        let mut stream = stream;
+        loop {
+            match tokio::time::timeout_at(deadline.into(), stream.next()).await {
+                Ok(Some(item)) => yield item,
+                Ok(None) => break,
+                Err(_) => {
+                    yield Err(deadline_exceeded("stream"));
+                    break;
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Ends `stream` with a `Timeout` error when no item arrives for `idle`, so a stalled upstream
+/// cannot hold the stream open indefinitely.
+pub fn stream_with_idle_timeout(stream: BackendStream, idle: Option<Duration>) -> BackendStream {
+    let Some(idle) = idle else {
+        return stream;
+    };
+    async_stream::stream! {
+        let mut stream = stream;
+        loop {
+            match tokio::time::timeout(idle, stream.next()).await {
+                Ok(Some(item)) => yield item,
+                Ok(None) => break,
+                Err(_) => {
+                    yield Err(BackendError::Timeout(format!(
+                        "no stream chunk received for {}s",
+                        idle.as_secs_f64()
+                    )));
+                    break;
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+fn deadline_exceeded(stage: &str) -> BackendError {
+    BackendError::DeadlineExceeded(format!("request deadline passed during {stage}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use super::{stream_with_idle_timeout, BackendError, BackendStream};
+    use crate::models::BackendChunk;
+
+    fn chunk(delta: &str) -> BackendChunk {
+        BackendChunk {
+            delta: Some(delta.to_owned()),
+            finish_reason: None,
+            provider_finish_reason: None,
+            usage: None,
+            done: false,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_streams_end_with_a_timeout() {
+        let stalled: BackendStream = futures_util::stream::iter([Ok(chunk("a"))])
+            .chain(futures_util::stream::pending())
+            .boxed();
+        let items = stream_with_idle_timeout(stalled, Some(Duration::from_millis(20)))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        assert!(matches!(items[1], Err(BackendError::Timeout(_))));
+    }
 }
@@ -1,51 +1,267 @@
-use std::{env, time::Duration};
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use futures_util::StreamExt;
-use reqwest::StatusCode;
+use reqwest::{header::HeaderMap, StatusCode};
 use serde::Deserialize;
 use serde_json::json;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
-    backend::{BackendError, BackendStream, InferenceBackend},
-    models::{BackendChatResponse, BackendChunk, MessageRole, NormalizedChatRequest, Usage},
+    backend::{
+        client::HttpClientConfig, credentials::Credentials, ratelimit::ProviderLimits,
+        sse::SseParser, BackendError, BackendStream, InferenceBackend,
+    },
+    models::{
+        normalize_finish_reason, BackendChatResponse, BackendChunk, MessageRole,
+        NormalizedChatRequest, Usage,
+    },
 };
 
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Connection settings for an OpenAI-compatible endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenAiConfig {
+    pub api_key: String,
+    /// Keys tried in order once the provider rejects `api_key` with `401` or `403`.
+    pub fallback_api_keys: Vec<String>,
+    /// Base URL the `/chat/completions` path is appended to.
+    pub base_url: String,
+    /// Whole-request timeout of the client [`OpenAiAdapter::new`] builds.
+    pub timeout: Duration,
+    /// Proxy and extra root certificates of the client [`OpenAiAdapter::new`] builds.
+    pub http: HttpClientConfig,
+    /// Passes messages' `cache_control` hints on, for OpenAI-compatible upstreams in front of
+    /// providers that cache prompts on request (e.g. LiteLLM or OpenRouter serving Anthropic
+    /// models). OpenAI itself caches prompts without hints.
+    pub cache_control: bool,
+}
+
+impl OpenAiConfig {
+    /// The public OpenAI API with a 60 second timeout.
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            fallback_api_keys: Vec::new(),
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            timeout: Duration::from_secs(60),
+            http: HttpClientConfig::default(),
+            cache_control: false,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_http(mut self, http: HttpClientConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_cache_control(mut self, enabled: bool) -> Self {
+        self.cache_control = enabled;
+        self
+    }
+
+    pub fn with_fallback_api_keys(mut self, keys: Vec<String>) -> Self {
+        self.fallback_api_keys = keys;
+        self
+    }
+
+    /// `None` when `OPENAI_API_KEY` is unset or empty.
+    pub fn from_env() -> Option<Self> {
+        let api_key = env::var("OPENAI_API_KEY")
+            .ok()
+            .filter(|value| !value.is_empty())?;
+        let mut config = Self::new(api_key);
+        if let Ok(keys) = env::var("OPENAI_FALLBACK_API_KEYS") {
+            config.fallback_api_keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        if let Ok(base_url) = env::var("OPENAI_BASE_URL") {
+            config.base_url = base_url;
+        }
+        if let Some(timeout_secs) = env::var("OPENAI_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+        {
+            config.timeout = Duration::from_secs(timeout_secs);
+        }
+        config.http = HttpClientConfig::from_env("OPENAI");
+        config.cache_control = env::var("OPENAI_CACHE_CONTROL")
+            .ok()
+            .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        Some(config)
+    }
+}
+
+/// Extra headers computed for each upstream request, e.g. the signed identity a peer gateway
+/// checks.
+pub(super) type RequestHeaders = Arc<dyn Fn(&NormalizedChatRequest) -> HeaderMap + Send + Sync>;
+
 #[derive(Clone)]
 pub struct OpenAiAdapter {
     client: reqwest::Client,
-    api_key: String,
+    /// Shared between clones, so a rotation or hot swap applies to every copy.
+    credentials: Arc<Credentials>,
     base_url: String,
+    request_headers: Option<RequestHeaders>,
+    /// Sends the client's `metadata` tags upstream, for upstreams that accept them.
+    forward_metadata: bool,
+    forward_cache_control: bool,
+    /// Budgets from the rate-limit headers of the latest response, shared between clones.
+    limits: Arc<Mutex<Option<ProviderLimits>>>,
 }
 
 impl OpenAiAdapter {
     pub fn from_env() -> Result<Option<Self>, String> {
-        let Some(api_key) = env::var("OPENAI_API_KEY")
-            .ok()
-            .filter(|value| !value.is_empty())
-        else {
-            return Ok(None);
-        };
-        let base_url = env::var("OPENAI_BASE_URL")
-            .unwrap_or_else(|_| "https://api.openai.com/v1".to_owned())
-            .trim_end_matches('/')
-            .to_owned();
-        let timeout_secs = env::var("OPENAI_TIMEOUT_SECS")
-            .ok()
-            .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(60);
+        OpenAiConfig::from_env().map(Self::new).transpose()
+    }
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(timeout_secs))
-            .build()
-            .map_err(|error| format!("failed to build OpenAI HTTP client: {error}"))?;
+    /// Builds an HTTP client with the config's timeout, proxy, and CA bundle.
+    pub fn new(config: OpenAiConfig) -> Result<Self, String> {
+        let client = config
+            .http
+            .client(config.timeout)
+            .map_err(|error| format!("OpenAI backend: {error}"))?;
+        Ok(Self::with_client(config, client))
+    }
 
-        Ok(Some(Self {
+    /// Sends requests through `client`, e.g. one with proxy settings, extra root certificates,
+    /// or pointed at a test server. The client's own timeout applies instead of
+    /// [`OpenAiConfig::timeout`].
+    pub fn with_client(config: OpenAiConfig, client: reqwest::Client) -> Self {
+        Self {
             client,
-            api_key,
-            base_url,
-        }))
+            credentials: Arc::new(Credentials::new(
+                std::iter::once(config.api_key)
+                    .chain(config.fallback_api_keys)
+                    .collect(),
+            )),
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            request_headers: None,
+            forward_metadata: false,
+            forward_cache_control: config.cache_control,
+            limits: Arc::default(),
+        }
+    }
+
+    pub(super) fn with_request_headers(mut self, headers: RequestHeaders) -> Self {
+        self.request_headers = Some(headers);
+        self
+    }
+
+    pub(super) fn with_metadata_forwarding(mut self) -> Self {
+        self.forward_metadata = true;
+        self
+    }
+
+    /// The request's messages in OpenAI's shape, with their cache hints where those are
+    /// forwarded.
+    fn messages(&self, request: &NormalizedChatRequest) -> Vec<serde_json::Value> {
+        request
+            .messages
+            .iter()
+            .map(|message| {
+                let mut payload =
+                    json!({"role": role_name(&message.role), "content": message.content});
+                if let Some(cache_control) =
+                    message.cache_control.filter(|_| self.forward_cache_control)
+                {
+                    payload["cache_control"] = json!(cache_control);
+                }
+                payload
+            })
+            .collect()
+    }
+
+    /// Adds the client's `user` to the payload, and its `metadata` where it is forwarded.
+    fn tag(&self, request: &NormalizedChatRequest, payload: &mut serde_json::Value) {
+        if let Some(user) = &request.client.user {
+            payload["user"] = json!(user);
+        }
+        if self.forward_metadata && !request.client.metadata.is_empty() {
+            payload["metadata"] = json!(request.client.metadata);
+        }
+    }
+
+    fn post(
+        &self,
+        api_key: &str,
+        request: &NormalizedChatRequest,
+        payload: &serde_json::Value,
+    ) -> reqwest::RequestBuilder {
+        let mut builder = self
+            .client
+            .post(self.url("/chat/completions"))
+            .bearer_auth(api_key)
+            .header("X-Request-Id", &request.request_id);
+        if let Some(headers) = &self.request_headers {
+            builder = builder.headers(headers(request));
+        }
+        builder.json(payload)
+    }
+
+    /// Sends the request with the active key, moving on to the next configured key whenever
+    /// the provider answers `401` or `403`, until one is accepted or every key was refused.
+    async fn send(
+        &self,
+        request: &NormalizedChatRequest,
+        payload: &serde_json::Value,
+    ) -> Result<reqwest::Response, BackendError> {
+        let mut attempts_left = self.credentials.count();
+        loop {
+            let credential = self.credentials.active();
+            let response = self
+                .post(&credential.key, request, payload)
+                .send()
+                .await
+                .map_err(|error| BackendError::Unavailable(error.to_string()))?;
+            self.record_limits(response.headers());
+            let status = response.status();
+            if !matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+                return Ok(response);
+            }
+            attempts_left = attempts_left.saturating_sub(1);
+            let rotated = self.credentials.reject(&credential);
+            // Keys are logged by position only.
+            warn!(
+                backend = self.name(),
+                status = status.as_u16(),
+                active = self.credentials.active_index(),
+                credentials = self.credentials.count(),
+                "upstream rejected the backend credential"
+            );
+            if !rotated || attempts_left == 0 {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Keeps the budgets `headers` report, if any, for [`InferenceBackend::provider_limits`].
+    fn record_limits(&self, headers: &HeaderMap) {
+        if let Some(limits) = ProviderLimits::from_headers(headers) {
+            *self
+                .limits
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(limits);
+        }
     }
 
     fn url(&self, path: &str) -> String {
@@ -59,36 +275,40 @@ impl InferenceBackend for OpenAiAdapter {
         "openai-adapter"
     }
 
+    fn provider_limits(&self) -> Option<ProviderLimits> {
+        *self
+            .limits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn credentials(&self, backend: &str) -> Option<Arc<Credentials>> {
+        (backend == self.name()).then(|| self.credentials.clone())
+    }
+
     #[tracing::instrument(skip(self, request), fields(model = %request.model))]
     async fn execute_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendChatResponse, BackendError> {
-        let payload = json!({
+        let mut payload = json!({
             "model": request.model,
-            "messages": request
-                .messages
-                .iter()
-                .map(|message| json!({"role": role_name(&message.role), "content": message.content}))
-                .collect::<Vec<_>>(),
+            "messages": self.messages(&request),
             "max_tokens": request.generation.max_tokens,
             "temperature": request.generation.temperature,
             "top_p": request.generation.top_p,
             "stream": false
         });
+        self.tag(&request, &mut payload);
 
-        let response = self
-            .client
-            .post(self.url("/chat/completions"))
-            .bearer_auth(&self.api_key)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|error| BackendError::Unavailable(error.to_string()))?;
+        let response = self.send(&request, &payload).await?;
 
         if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
             return Err(map_http_error(
-                response.status(),
+                status,
+                &headers,
                 response
                     .text()
                     .await
@@ -96,6 +316,7 @@ impl InferenceBackend for OpenAiAdapter {
             ));
         }
 
+        let upstream_headers = response.headers().clone();
         let parsed: OpenAiChatResponse = response
             .json()
             .await
@@ -115,28 +336,28 @@ impl InferenceBackend for OpenAiAdapter {
             Usage::new(prompt_tokens, completion_tokens)
         });
 
+        let (finish_reason, provider_finish_reason) = finish_reason(
+            choice.finish_reason.as_deref().unwrap_or("stop"),
+            choice.provider_finish_reason.as_ref(),
+        );
         Ok(BackendChatResponse {
             content,
-            finish_reason: choice
-                .finish_reason
-                .clone()
-                .unwrap_or_else(|| "stop".to_owned()),
+            finish_reason,
+            provider_finish_reason,
             usage,
+            backend: None,
+            upstream_headers: Some(upstream_headers),
         })
     }
 
     #[tracing::instrument(skip(self, request), fields(model = %request.model))]
     async fn stream_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendStream, BackendError> {
-        let payload = json!({
+        let mut payload = json!({
             "model": request.model,
-            "messages": request
-                .messages
-                .iter()
-                .map(|message| json!({"role": role_name(&message.role), "content": message.content}))
-                .collect::<Vec<_>>(),
+            "messages": self.messages(&request),
             "max_tokens": request.generation.max_tokens,
             "temperature": request.generation.temperature,
             "top_p": request.generation.top_p,
@@ -145,19 +366,16 @@ impl InferenceBackend for OpenAiAdapter {
                 "include_usage": true
             }
         });
+        self.tag(&request, &mut payload);
 
-        let response = self
-            .client
-            .post(self.url("/chat/completions"))
-            .bearer_auth(&self.api_key)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|error| BackendError::Unavailable(error.to_string()))?;
+        let response = self.send(&request, &payload).await?;
 
         if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
             return Err(map_http_error(
-                response.status(),
+                status,
+                &headers,
                 response
                     .text()
                     .await
@@ -165,101 +383,56 @@ impl InferenceBackend for OpenAiAdapter {
             ));
         }
 
+        let mut decoder = StreamDecoder {
+            passthrough: request.passthrough,
+            upstream_headers: Some(response.headers().clone()),
+            ..StreamDecoder::default()
+        };
         let mut upstream = response.bytes_stream();
-        let mut buffer = String::new();
+        let mut parser = SseParser::new();
 
         let stream = async_stream::stream! {
-            let mut final_usage: Option<Usage> = None;
-            let mut done_emitted = false;
-
+            let mut failed = false;
             while let Some(next) = upstream.next().await {
                 let bytes = match next {
                     Ok(bytes) => bytes,
                     Err(error) => {
                         yield Err(BackendError::Unavailable(error.to_string()));
+                        failed = true;
                         break;
                     }
                 };
-
-                let text = match std::str::from_utf8(&bytes) {
-                    Ok(text) => text,
+                match parser.push(&bytes) {
+                    Ok(events) => {
+                        for event in events {
+                            for item in decoder.decode(&event.data) {
+                                yield item;
+                            }
+                        }
+                    }
                     Err(error) => {
                         yield Err(BackendError::InvalidResponse(error.to_string()));
+                        failed = true;
                         break;
                     }
-                };
-
-                buffer.push_str(text);
-
-                while let Some(index) = buffer.find('\n') {
-                    let line = buffer[..index].trim().to_owned();
-                    buffer.drain(..=index);
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    let Some(payload) = line.strip_prefix("data:") else {
-                        continue;
-                    };
-                    let payload = payload.trim();
-
-                    if payload == "[DONE]" {
-                        if !done_emitted {
-                            yield Ok(BackendChunk {
-                                delta: None,
-                                finish_reason: Some("stop".to_owned()),
-                                usage: final_usage.clone(),
-                                done: true,
-                            });
-                            done_emitted = true;
-                        }
-                        continue;
-                    }
+                }
+            }
 
-                    let parsed: OpenAiStreamResponse = match serde_json::from_str(payload) {
-                        Ok(parsed) => parsed,
-                        Err(error) => {
-                            yield Err(BackendError::InvalidResponse(error.to_string()));
-                            continue;
+            if !failed {
+                match parser.finish() {
+                    Ok(Some(event)) => {
+                        for item in decoder.decode(&event.data) {
+                            yield item;
                         }
-                    };
-
-                    if let Some(usage) = parsed.usage.map(Usage::from) {
-                        final_usage = Some(usage);
                     }
-
-                    if let Some(choice) = parsed.choices.first() {
-                        if let Some(content) = choice.delta.content.clone().filter(|value| !value.is_empty()) {
-                            yield Ok(BackendChunk {
-                                delta: Some(content),
-                                finish_reason: None,
-                                usage: None,
-                                done: false,
-                            });
-                        }
-
-                        if let Some(reason) = choice.finish_reason.clone() {
-                            if !done_emitted {
-                                yield Ok(BackendChunk {
-                                    delta: None,
-                                    finish_reason: Some(reason),
-                                    usage: final_usage.clone(),
-                                    done: true,
-                                });
-                                done_emitted = true;
-                            }
-                        }
+                    Ok(None) => {}
+                    Err(error) => {
+                        yield Err(BackendError::InvalidResponse(error.to_string()));
                     }
                 }
             }
-
-            if !done_emitted {
-                yield Ok(BackendChunk {
-                    delta: None,
-                    finish_reason: Some("stop".to_owned()),
-                    usage: final_usage,
-                    done: true,
-                });
+            if let Some(chunk) = decoder.finish() {
+                yield Ok(chunk);
             }
         };
 
@@ -268,19 +441,238 @@ impl InferenceBackend for OpenAiAdapter {
     }
 }
 
-fn map_http_error(status: StatusCode, body: String) -> BackendError {
+/// Provider headers that tell a throttled client when to retry.
+const RATE_LIMIT_HEADERS: [&str; 4] = [
+    "retry-after",
+    "retry-after-ms",
+    "x-ratelimit-reset-requests",
+    "x-ratelimit-reset-tokens",
+];
+
+fn map_http_error(status: StatusCode, headers: &HeaderMap, body: String) -> BackendError {
     let trimmed = body.chars().take(400).collect::<String>();
     match status {
-        StatusCode::TOO_MANY_REQUESTS => {
-            BackendError::Unavailable(format!("rate limited: {trimmed}"))
+        StatusCode::TOO_MANY_REQUESTS => BackendError::RateLimited {
+            message: format!("upstream provider rate limited the request: {trimmed}"),
+            headers: RATE_LIMIT_HEADERS
+                .iter()
+                .filter_map(|name| {
+                    let value = headers.get(*name)?.to_str().ok()?;
+                    Some(((*name).to_owned(), value.to_owned()))
+                })
+                .collect(),
+            retry_after: retry_after(headers),
+        },
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            BackendError::Unauthorized(format!("status {}: {trimmed}", status.as_u16()))
+        }
+        StatusCode::BAD_REQUEST
+        | StatusCode::NOT_FOUND
+        | StatusCode::PAYLOAD_TOO_LARGE
+        | StatusCode::UNPROCESSABLE_ENTITY => {
+            BackendError::BadRequest(format!("status {}: {trimmed}", status.as_u16()))
         }
         StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => {
             BackendError::Timeout(format!("upstream timeout: {trimmed}"))
         }
+        // A server-side failure, overload, or missing backend of its own; another endpoint may
+        // still answer.
+        status if status.is_server_error() => {
+            BackendError::Unavailable(format!("status {}: {trimmed}", status.as_u16()))
+        }
         _ => BackendError::InvalidResponse(format!("status {}: {trimmed}", status.as_u16())),
     }
 }
 
+/// The wait a `429` asks for: OpenAI's `retry-after-ms`, else `retry-after` in seconds. HTTP
+/// dates are not worth parsing for waits this short.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    header("retry-after-ms")
+        .map(|ms| ms / 1_000.0)
+        .or_else(|| header("retry-after"))
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// Turns the `data` of each upstream SSE event into backend chunks.
+#[derive(Default)]
+struct StreamDecoder {
+    /// Emit one chunk per event carrying the event verbatim, for passthrough streams.
+    passthrough: bool,
+    final_usage: Option<Usage>,
+    /// The terminal chunk, held from the event with the finish reason until the usage event
+    /// OpenAI sends after it, `[DONE]`, or the end of the stream.
+    pending_done: Option<BackendChunk>,
+    done_emitted: bool,
+    /// Headers of the upstream response, handed to the first chunk decoded.
+    upstream_headers: Option<HeaderMap>,
+}
+
+impl StreamDecoder {
+    fn decode(&mut self, data: &str) -> Vec<Result<BackendChunk, BackendError>> {
+        let mut chunks = self.decode_event(data);
+        if let Some(Ok(first)) = chunks.first_mut() {
+            if first.upstream_headers.is_none() {
+                first.upstream_headers = self.upstream_headers.take();
+            }
+        }
+        chunks
+    }
+
+    fn decode_event(&mut self, data: &str) -> Vec<Result<BackendChunk, BackendError>> {
+        let data = data.trim();
+        if data == "[DONE]" {
+            return self.finish().map(Ok).into_iter().collect();
+        }
+
+        let parsed: OpenAiStreamResponse = match serde_json::from_str(data) {
+            Ok(parsed) => parsed,
+            Err(error) => return vec![Err(BackendError::InvalidResponse(error.to_string()))],
+        };
+        if let Some(usage) = parsed.usage.map(Usage::from) {
+            self.final_usage = Some(usage);
+        }
+        if self.done_emitted {
+            return Vec::new();
+        }
+        if let Some(mut done) = self.pending_done.take() {
+            if self.final_usage.is_none() {
+                self.pending_done = Some(done);
+                return self.forward_only(data);
+            }
+            // OpenAI's usage event, `choices: []` after the finish reason, completes the stream.
+            done.usage = self.final_usage.clone();
+            done.raw = self.passthrough.then(|| data.to_owned());
+            self.done_emitted = true;
+            return vec![Ok(done)];
+        }
+
+        let choice = parsed.choices.first();
+        let content = choice
+            .and_then(|choice| choice.delta.content.clone())
+            .filter(|value| !value.is_empty());
+        let (finish_reason, provider_finish_reason) = choice
+            .and_then(|choice| {
+                let reason = choice.finish_reason.as_deref()?;
+                Some(finish_reason(
+                    reason,
+                    choice.provider_finish_reason.as_ref(),
+                ))
+            })
+            .map_or((None, None), |(reason, provider)| (Some(reason), provider));
+        let Some(reason) = finish_reason else {
+            return match (self.passthrough, content) {
+                (true, content) => vec![Ok(raw_chunk(data, content))],
+                (false, Some(content)) => vec![Ok(delta_chunk(content))],
+                (false, None) => Vec::new(),
+            };
+        };
+        let done = BackendChunk {
+            delta: None,
+            finish_reason: Some(reason),
+            provider_finish_reason,
+            usage: self.final_usage.clone(),
+            done: true,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        };
+        // Usage normally follows in an event of its own; a server that sends it with the finish
+        // reason has nothing left to wait for.
+        let complete = self.final_usage.is_some();
+        if !complete {
+            self.pending_done = Some(done.clone());
+        }
+        self.done_emitted = complete;
+
+        if self.passthrough {
+            // One chunk per event, so each parsed event is forwarded exactly once.
+            return vec![Ok(if complete {
+                BackendChunk {
+                    delta: content,
+                    raw: Some(data.to_owned()),
+                    ..done
+                }
+            } else {
+                raw_chunk(data, content)
+            })];
+        }
+        let mut chunks = content
+            .map(delta_chunk)
+            .map(Ok)
+            .into_iter()
+            .collect::<Vec<_>>();
+        if complete {
+            chunks.push(Ok(done));
+        }
+        chunks
+    }
+
+    /// An event with nothing to decode, forwarded as it is on passthrough streams.
+    fn forward_only(&self, data: &str) -> Vec<Result<BackendChunk, BackendError>> {
+        match self.passthrough {
+            true => vec![Ok(raw_chunk(data, None))],
+            false => Vec::new(),
+        }
+    }
+
+    /// The terminal chunk, with the finish reason the upstream sent if it sent one, unless the
+    /// stream already ended.
+    fn finish(&mut self) -> Option<BackendChunk> {
+        if std::mem::replace(&mut self.done_emitted, true) {
+            return None;
+        }
+        let mut done = self.pending_done.take().unwrap_or_else(|| BackendChunk {
+            delta: None,
+            finish_reason: Some("stop".to_owned()),
+            provider_finish_reason: None,
+            usage: None,
+            done: true,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        });
+        done.usage = self.final_usage.clone();
+        done.upstream_headers = self.upstream_headers.take();
+        Some(done)
+    }
+}
+
+fn delta_chunk(content: String) -> BackendChunk {
+    BackendChunk {
+        delta: Some(content),
+        finish_reason: None,
+        provider_finish_reason: None,
+        usage: None,
+        done: false,
+        backend: None,
+        raw: None,
+        upstream_headers: None,
+    }
+}
+
+/// A passthrough chunk that does not end the stream.
+fn raw_chunk(data: &str, content: Option<String>) -> BackendChunk {
+    BackendChunk {
+        delta: content,
+        finish_reason: None,
+        provider_finish_reason: None,
+        usage: None,
+        done: false,
+        backend: None,
+        raw: Some(data.to_owned()),
+        upstream_headers: None,
+    }
+}
+
+/// OpenAI's finish reason for an upstream's, as compatible servers in front of other providers
+/// may relay Anthropic's or Gemini's untranslated. A peer gateway sends a normalized reason with
+/// the provider's own alongside, which is kept as is.
+fn finish_reason(reason: &str, provider: Option<&String>) -> (String, Option<String>) {
+    let (reason, raw) = normalize_finish_reason(reason);
+    (reason, provider.cloned().or(raw))
+}
+
 fn role_name(role: &MessageRole) -> &'static str {
     match role {
         MessageRole::System => "system",
@@ -309,6 +701,8 @@ struct OpenAiChoice {
     message: OpenAiMessage,
     #[serde(default)]
     finish_reason: Option<String>,
+    #[serde(default)]
+    provider_finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -331,6 +725,8 @@ struct OpenAiStreamChoice {
     delta: OpenAiDelta,
     #[serde(default)]
     finish_reason: Option<String>,
+    #[serde(default)]
+    provider_finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -355,3 +751,478 @@ impl From<OpenAiUsage> for Usage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{HeaderMap, HeaderValue},
+        StatusCode,
+    };
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::response::IntoResponse;
+
+    use super::{map_http_error, retry_after, OpenAiAdapter, OpenAiConfig, StreamDecoder};
+    use crate::{
+        backend::{client::HttpClientConfig, BackendError, InferenceBackend},
+        models::{
+            CacheControl, CacheTtl, ChatCompletionsRequest, MessageRole, NormalizedChatRequest,
+            OpenAiMessage, Usage,
+        },
+    };
+
+    fn chat_request() -> Arc<NormalizedChatRequest> {
+        let request = ChatCompletionsRequest {
+            model: "gpt-4o-mini".to_owned(),
+            messages: vec![OpenAiMessage {
+                role: MessageRole::User,
+                content: "hello".to_owned(),
+                cache_control: None,
+            }],
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: false,
+            user: None,
+            timeout: None,
+            stream_options: None,
+            metadata: None,
+        };
+        Arc::new(
+            request
+                .into_normalized("user".to_owned())
+                .expect("valid request"),
+        )
+    }
+
+    #[test]
+    fn provider_429_keeps_retry_and_reset_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("20"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("6m0s"));
+        headers.insert("x-request-id", HeaderValue::from_static("req_upstream"));
+
+        let error = map_http_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            "Rate limit reached".to_owned(),
+        );
+        let BackendError::RateLimited {
+            message,
+            headers,
+            retry_after,
+        } = error
+        else {
+            panic!("expected RateLimited, got {error}");
+        };
+        assert!(message.contains("Rate limit reached"));
+        assert_eq!(retry_after, Some(Duration::from_secs(20)));
+        assert_eq!(
+            headers,
+            [
+                ("retry-after".to_owned(), "20".to_owned()),
+                ("x-ratelimit-reset-tokens".to_owned(), "6m0s".to_owned()),
+            ]
+        );
+
+        assert!(matches!(
+            map_http_error(
+                StatusCode::GATEWAY_TIMEOUT,
+                &HeaderMap::new(),
+                String::new()
+            ),
+            BackendError::Timeout(_)
+        ));
+    }
+
+    #[test]
+    fn client_and_credential_errors_get_their_own_variants() {
+        let error = |status| map_http_error(status, &HeaderMap::new(), "nope".to_owned());
+        assert!(matches!(
+            error(StatusCode::UNAUTHORIZED),
+            BackendError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            error(StatusCode::FORBIDDEN),
+            BackendError::Unauthorized(_)
+        ));
+        for status in [StatusCode::BAD_REQUEST, StatusCode::NOT_FOUND] {
+            assert!(matches!(error(status), BackendError::BadRequest(_)));
+        }
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(matches!(error(status), BackendError::Unavailable(_)));
+        }
+        assert!(matches!(
+            error(StatusCode::GATEWAY_TIMEOUT),
+            BackendError::Timeout(_)
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("20"));
+        headers.insert("retry-after-ms", HeaderValue::from_static("1500"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1_500)));
+    }
+
+    #[test]
+    fn passthrough_decoding_yields_one_chunk_per_event() {
+        let events = [
+            r#"{"choices":[{"delta":{"role":"assistant","content":""}}]}"#,
+            r#"{"choices":[{"delta":{"content":"Hi"},"finish_reason":"stop"}]}"#,
+            r#"{"choices":[],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#,
+            "[DONE]",
+        ];
+        let mut decoder = StreamDecoder {
+            passthrough: true,
+            ..StreamDecoder::default()
+        };
+        let chunks = events
+            .iter()
+            .flat_map(|event| decoder.decode(event))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("valid events");
+
+        // The usage event after the finish reason is forwarded too, and ends the stream.
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].raw.as_deref(), Some(events[0]));
+        assert_eq!(chunks[0].delta, None);
+        assert_eq!(chunks[1].raw.as_deref(), Some(events[1]));
+        assert_eq!(chunks[1].delta.as_deref(), Some("Hi"));
+        assert!(!chunks[1].done);
+        assert_eq!(chunks[2].raw.as_deref(), Some(events[2]));
+        assert!(chunks[2].done);
+        assert_eq!(chunks[2].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(
+            chunks[2].usage.as_ref().map(|usage| usage.total_tokens),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn usage_sent_after_the_finish_reason_reaches_the_terminal_chunk() {
+        // OpenAI's order with `include_usage`: content, then the finish reason with an empty
+        // delta, then usage with no choices, then `[DONE]`.
+        let events = [
+            concat!(
+                r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":""},"#,
+                r#""finish_reason":null}],"usage":null}"#
+            ),
+            concat!(
+                r#"{"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}],"#,
+                r#""usage":null}"#
+            ),
+            r#"{"choices":[{"index":0,"delta":{},"finish_reason":"length"}],"usage":null}"#,
+            r#"{"choices":[],"usage":{"prompt_tokens":9,"completion_tokens":1,"total_tokens":10}}"#,
+            "[DONE]",
+        ];
+        let mut decoder = StreamDecoder::default();
+        let chunks = events
+            .iter()
+            .flat_map(|event| decoder.decode(event))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("valid events");
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].delta.as_deref(), Some("Hello"));
+        let done = &chunks[1];
+        assert!(done.done);
+        assert_eq!(done.finish_reason.as_deref(), Some("length"));
+        assert_eq!(done.usage, Some(Usage::new(9, 1)));
+
+        // Without a usage event the held chunk still ends the stream at `[DONE]`.
+        let mut decoder = StreamDecoder::default();
+        assert!(decoder
+            .decode(r#"{"choices":[{"delta":{},"finish_reason":"stop"}]}"#)
+            .is_empty());
+        let done = decoder.decode("[DONE]");
+        let done = done[0].as_ref().expect("terminal chunk");
+        assert_eq!(done.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(done.usage, None);
+        assert!(decoder.finish().is_none());
+    }
+
+    #[test]
+    fn relayed_finish_reasons_are_normalized_and_kept() {
+        let mut decoder = StreamDecoder::default();
+        let chunks = [
+            r#"{"choices":[{"delta":{"content":"Hi"},"finish_reason":"end_turn"}]}"#,
+            "[DONE]",
+        ]
+        .iter()
+        .flat_map(|event| decoder.decode(event))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("valid event");
+        let done = chunks.last().expect("terminal chunk");
+        assert_eq!(done.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(done.provider_finish_reason.as_deref(), Some("end_turn"));
+
+        // A peer gateway already normalized the reason and names the provider's alongside.
+        let mut decoder = StreamDecoder::default();
+        let chunks = [
+            concat!(
+                r#"{"choices":[{"delta":{},"finish_reason":"length","#,
+                r#""provider_finish_reason":"MAX_TOKENS"}]}"#
+            ),
+            "[DONE]",
+        ]
+        .iter()
+        .flat_map(|event| decoder.decode(event))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("valid event");
+        assert_eq!(chunks[0].finish_reason.as_deref(), Some("length"));
+        assert_eq!(
+            chunks[0].provider_finish_reason.as_deref(),
+            Some("MAX_TOKENS")
+        );
+
+        let chunks = StreamDecoder::default().decode("[DONE]");
+        let done = chunks[0].as_ref().expect("terminal chunk");
+        assert_eq!(
+            (
+                done.finish_reason.as_deref(),
+                done.provider_finish_reason.as_deref()
+            ),
+            (Some("stop"), None)
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_config_and_injected_client_reach_the_given_endpoint() {
+        let upstream = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|headers: axum::http::HeaderMap| async move {
+                let header = |name: &str| {
+                    headers
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_owned()
+                };
+                axum::Json(serde_json::json!({
+                    "choices": [{
+                        "message": {"content": format!("{} via {}", header("authorization"), header("x-proxy-tag"))},
+                        "finish_reason": "stop"
+                    }],
+                    "usage": {"prompt_tokens": 2, "completion_tokens": 3, "total_tokens": 5}
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind upstream");
+        let addr = listener.local_addr().expect("upstream address");
+        let server = tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("x-proxy-tag", HeaderValue::from_static("corp-proxy"));
+        let client = reqwest::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .expect("client");
+        let config = OpenAiConfig::new("sk-test").with_base_url(format!("http://{addr}/v1/"));
+        let adapter = OpenAiAdapter::with_client(config, client);
+
+        let response = adapter
+            .execute_chat(chat_request())
+            .await
+            .expect("upstream answers");
+        assert_eq!(response.content, "Bearer sk-test via corp-proxy");
+        assert_eq!(response.usage.total_tokens, 5);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn client_user_is_sent_and_metadata_only_where_forwarded() {
+        let upstream = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                axum::Json(serde_json::json!({
+                    "choices": [{
+                        "message": {"content": format!("{} {}", body["user"], body["metadata"])},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind upstream");
+        let addr = listener.local_addr().expect("upstream address");
+        let server = tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let mut request = (*chat_request()).clone();
+        request.client.user = Some("end-user-7".to_owned());
+        request
+            .client
+            .metadata
+            .insert("feature".to_owned(), "search".to_owned());
+        let request = Arc::new(request);
+        let config = OpenAiConfig::new("sk-test").with_base_url(format!("http://{addr}/v1"));
+        let adapter = OpenAiAdapter::new(config).expect("adapter");
+        let sent = |adapter: OpenAiAdapter| {
+            let request = request.clone();
+            async move {
+                adapter
+                    .execute_chat(request)
+                    .await
+                    .expect("upstream answers")
+                    .content
+            }
+        };
+
+        assert_eq!(sent(adapter.clone()).await, r#""end-user-7" null"#);
+        assert_eq!(
+            sent(adapter.with_metadata_forwarding()).await,
+            r#""end-user-7" {"feature":"search"}"#
+        );
+        server.abort();
+    }
+
+    #[test]
+    fn cache_hints_are_forwarded_only_when_enabled() {
+        let mut request = (*chat_request()).clone();
+        request.messages[0].cache_control = Some(CacheControl::Ephemeral {
+            ttl: Some(CacheTtl::OneHour),
+        });
+        let messages = |cache_control: bool| {
+            let config = OpenAiConfig::new("sk-test").with_cache_control(cache_control);
+            OpenAiAdapter::new(config)
+                .expect("adapter")
+                .messages(&request)
+        };
+
+        assert_eq!(
+            messages(true)[0]["cache_control"],
+            serde_json::json!({"type": "ephemeral", "ttl": "1h"})
+        );
+        assert!(messages(false)[0].get("cache_control").is_none());
+    }
+
+    #[tokio::test]
+    async fn configured_proxy_carries_requests_to_the_provider() {
+        // Stands in for a forward proxy: plain-HTTP requests reach it in absolute form.
+        let proxy = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|uri: axum::http::Uri| async move {
+                axum::Json(serde_json::json!({
+                    "choices": [{
+                        "message": {"content": format!("proxied to {uri}")},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind proxy");
+        let addr = listener.local_addr().expect("proxy address");
+        let server = tokio::spawn(async move { axum::serve(listener, proxy).await });
+
+        let config = OpenAiConfig::new("sk-test")
+            .with_base_url("http://provider.internal/v1")
+            .with_http(HttpClientConfig {
+                proxy: Some(format!("http://{addr}")),
+                ..HttpClientConfig::default()
+            });
+        let response = OpenAiAdapter::new(config)
+            .expect("adapter")
+            .execute_chat(chat_request())
+            .await
+            .expect("answered through the proxy");
+        assert_eq!(
+            response.content,
+            "proxied to http://provider.internal/v1/chat/completions"
+        );
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn rejected_keys_fail_over_to_the_next_and_can_be_swapped_live() {
+        let rejected = Arc::new(AtomicUsize::new(0));
+        let counter = rejected.clone();
+        let upstream = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(move |headers: axum::http::HeaderMap| {
+                let counter = counter.clone();
+                async move {
+                    let authorization = headers
+                        .get("authorization")
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_owned();
+                    if authorization == "Bearer sk-revoked" {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        return (axum::http::StatusCode::UNAUTHORIZED, "invalid api key")
+                            .into_response();
+                    }
+                    axum::Json(serde_json::json!({
+                        "choices": [{
+                            "message": {"content": authorization},
+                            "finish_reason": "stop"
+                        }]
+                    }))
+                    .into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind upstream");
+        let addr = listener.local_addr().expect("upstream address");
+        let server = tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = OpenAiConfig::new("sk-revoked")
+            .with_fallback_api_keys(vec!["sk-spare".to_owned()])
+            .with_base_url(format!("http://{addr}/v1"));
+        let adapter = OpenAiAdapter::new(config).expect("adapter");
+        let answer = |adapter: OpenAiAdapter| async move {
+            adapter
+                .execute_chat(chat_request())
+                .await
+                .map(|response| response.content)
+        };
+
+        // The rejected key is retried with the spare within the same call...
+        assert_eq!(
+            answer(adapter.clone()).await.expect("spare key"),
+            "Bearer sk-spare"
+        );
+        assert_eq!(rejected.load(Ordering::SeqCst), 1);
+        // ...and later calls, from any clone, start from the spare.
+        assert_eq!(
+            answer(adapter.clone()).await.expect("spare key"),
+            "Bearer sk-spare"
+        );
+        assert_eq!(rejected.load(Ordering::SeqCst), 1);
+
+        let credentials = adapter
+            .credentials("openai-adapter")
+            .expect("keys of the adapter");
+        assert!(adapter.credentials("peer-eu").is_none());
+        credentials
+            .replace(vec!["sk-rotated".to_owned()])
+            .expect("valid keys");
+        assert_eq!(
+            answer(adapter.clone()).await.expect("new key"),
+            "Bearer sk-rotated"
+        );
+
+        // With every key refused, the provider's rejection is returned.
+        credentials
+            .replace(vec!["sk-revoked".to_owned()])
+            .expect("valid keys");
+        assert!(matches!(
+            answer(adapter).await,
+            Err(BackendError::Unauthorized(message)) if message.contains("status 401")
+        ));
+        assert_eq!(rejected.load(Ordering::SeqCst), 2);
+        server.abort();
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderValue};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::{
+    backend::{
+        client::HttpClientConfig,
+        openai::{OpenAiAdapter, OpenAiConfig},
+        ratelimit::ProviderLimits,
+        BackendError, BackendStream, InferenceBackend,
+    },
+    errors::AppError,
+    models::{BackendChatResponse, NormalizedChatRequest},
+    scheduler::to_hex,
+};
+
+pub const PEER_USER_HEADER: &str = "x-gateway-peer-user";
+pub const PEER_TIMESTAMP_HEADER: &str = "x-gateway-peer-timestamp";
+pub const PEER_SIGNATURE_HEADER: &str = "x-gateway-peer-signature";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How far a signed timestamp may drift from the receiving gateway's clock.
+const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+/// Another gateway instance, e.g. in a second region, reached through its own
+/// `/v1/chat/completions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerConfig {
+    pub name: String,
+    /// The peer's root URL, without `/v1`.
+    pub base_url: String,
+    /// A key the peer accepts in `x-api-key`; its rate limits and quotas apply on the peer.
+    pub api_key: String,
+    /// Shared with the peer to sign the forwarded caller; `None` sends the request as the
+    /// peer key's own.
+    pub secret: Option<String>,
+    pub timeout: Duration,
+    pub http: HttpClientConfig,
+}
+
+impl PeerConfig {
+    /// Reads `GATEWAY_PEERS`, `name=url` entries separated by commas, which share
+    /// `GATEWAY_PEER_API_KEY`, `GATEWAY_PEER_SECRET`, `GATEWAY_PEER_TIMEOUT_SECS`, and the
+    /// `GATEWAY_PEER_PROXY`, `GATEWAY_PEER_NO_PROXY`, and `GATEWAY_PEER_CA_BUNDLE` client
+    /// settings.
+    pub fn from_env() -> Vec<Self> {
+        let Ok(raw) = env::var("GATEWAY_PEERS") else {
+            return Vec::new();
+        };
+        let endpoints = match Self::parse_list(&raw) {
+            Ok(endpoints) => endpoints,
+            Err(error) => {
+                warn!(error = %error, "invalid GATEWAY_PEERS, no peer gateways configured");
+                return Vec::new();
+            }
+        };
+        if endpoints.is_empty() {
+            return Vec::new();
+        }
+        let Some(api_key) = env::var("GATEWAY_PEER_API_KEY")
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+        else {
+            warn!("GATEWAY_PEERS is set without GATEWAY_PEER_API_KEY, no peer gateways configured");
+            return Vec::new();
+        };
+        let secret = env::var("GATEWAY_PEER_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let timeout = env::var("GATEWAY_PEER_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT);
+        let http = HttpClientConfig::from_env("GATEWAY_PEER");
+        endpoints
+            .into_iter()
+            .map(|(name, base_url)| Self {
+                name,
+                base_url,
+                api_key: api_key.clone(),
+                secret: secret.clone(),
+                timeout,
+                http: http.clone(),
+            })
+            .collect()
+    }
+
+    /// Parses `name=url` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<(String, String)>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, url) = entry
+                    .split_once('=')
+                    .map(|(name, url)| (name.trim(), url.trim()))
+                    .filter(|(name, url)| !name.is_empty() && !url.is_empty())
+                    .ok_or_else(|| format!("peer `{entry}` must be `name=url`"))?;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!("peer `{name}` needs an http or https url"));
+                }
+                Ok((name.to_owned(), url.trim_end_matches('/').to_owned()))
+            })
+            .collect()
+    }
+}
+
+/// Forwards requests to a peer gateway, for region-level failover or a hierarchy of gateways.
+/// The request id, remaining deadline, and priority travel with the request, and with a
+/// shared secret the caller's identity does too, so usage on the peer is attributed to the
+/// original user rather than the peer key.
+#[derive(Clone)]
+pub struct PeerAdapter {
+    name: String,
+    inner: OpenAiAdapter,
+}
+
+impl PeerAdapter {
+    pub fn new(config: PeerConfig) -> Result<Self, String> {
+        let name = format!("peer:{}", config.name);
+        let openai = OpenAiConfig::new(config.api_key.clone())
+            .with_base_url(format!("{}/v1", config.base_url.trim_end_matches('/')))
+            .with_timeout(config.timeout)
+            .with_http(config.http)
+            .with_cache_control(true);
+        let (api_key, secret) = (config.api_key, config.secret);
+        let inner = OpenAiAdapter::new(openai)
+            .map_err(|error| format!("peer `{}`: {error}", config.name))?
+            .with_metadata_forwarding()
+            .with_request_headers(Arc::new(move |request| {
+                peer_headers(&api_key, secret.as_deref(), request, unix_now())
+            }));
+        Ok(Self { name, inner })
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for PeerAdapter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        self.inner.stream_chat(request).await
+    }
+
+    fn provider_limits(&self) -> Option<ProviderLimits> {
+        self.inner.provider_limits()
+    }
+}
+
+fn peer_headers(
+    api_key: &str,
+    secret: Option<&str>,
+    request: &NormalizedChatRequest,
+    now: u64,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut insert = |name: &'static str, value: &str| {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    };
+    insert("x-api-key", api_key);
+    insert("x-gateway-priority", request.priority.as_str());
+    if let Some(deadline) = request.deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        insert(
+            "x-gateway-timeout-ms",
+            &remaining.as_millis().max(1).to_string(),
+        );
+    }
+    if let Some(secret) = secret {
+        let timestamp = now.to_string();
+        insert(PEER_USER_HEADER, &request.user_id);
+        insert(PEER_TIMESTAMP_HEADER, &timestamp);
+        insert(
+            PEER_SIGNATURE_HEADER,
+            &sign(secret, &timestamp, &request.request_id, &request.user_id),
+        );
+    }
+    headers
+}
+
+/// Accepts the caller identity a peer gateway forwards, when it is signed with the shared
+/// `GATEWAY_PEER_SECRET`.
+///
+/// The signature binds the user to one request id and timestamp but not to the request body,
+/// so a captured set of peer headers can be replayed with any payload until the timestamp is
+/// more than [`MAX_CLOCK_SKEW_SECS`] old. Peers should talk over TLS.
+#[derive(Debug, Clone, Default)]
+pub struct PeerTrust {
+    secret: Option<String>,
+}
+
+impl PeerTrust {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            secret: Some(secret.into()),
+        }
+    }
+
+    /// Trusts no peer; forwarded identities are ignored.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn from_env() -> Self {
+        match env::var("GATEWAY_PEER_SECRET") {
+            Ok(secret) if !secret.is_empty() => Self::new(secret),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// The user a peer forwarded `request_id` on behalf of. Unsigned requests, and any
+    /// request while no secret is configured, are `None` and keep the caller's key identity;
+    /// a bad or stale signature is rejected.
+    pub fn forwarded_user(
+        &self,
+        headers: &HeaderMap,
+        request_id: &str,
+    ) -> Result<Option<String>, AppError> {
+        self.verify(headers, request_id, unix_now())
+    }
+
+    fn verify(
+        &self,
+        headers: &HeaderMap,
+        request_id: &str,
+        now: u64,
+    ) -> Result<Option<String>, AppError> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let Some(signature) = header(PEER_SIGNATURE_HEADER) else {
+            return Ok(None);
+        };
+        let Some(secret) = &self.secret else {
+            debug!("ignoring a peer signature, GATEWAY_PEER_SECRET is not set");
+            return Ok(None);
+        };
+        let rejected = |reason: &str| AppError::Unauthorized(format!("peer signature {reason}"));
+        let (Some(user), Some(timestamp)) =
+            (header(PEER_USER_HEADER), header(PEER_TIMESTAMP_HEADER))
+        else {
+            return Err(rejected("is missing its user or timestamp"));
+        };
+        let signed_at = timestamp
+            .parse::<u64>()
+            .map_err(|_| rejected("has an invalid timestamp"))?;
+        if now.abs_diff(signed_at) > MAX_CLOCK_SKEW_SECS {
+            return Err(rejected("has expired"));
+        }
+        // `verify_slice` compares in constant time.
+        let matches = from_hex(signature.trim()).is_some_and(|signature| {
+            signer(secret, timestamp, request_id, user)
+                .verify_slice(&signature)
+                .is_ok()
+        });
+        if !matches {
+            return Err(rejected("does not match"));
+        }
+        Ok(Some(user.to_owned()))
+    }
+}
+
+/// Hex HMAC-SHA256 over the timestamp, request id, and user, one per line. The request body
+/// is not signed.
+fn sign(secret: &str, timestamp: &str, request_id: &str, user: &str) -> String {
+    to_hex(
+        &signer(secret, timestamp, request_id, user)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn signer(secret: &str, timestamp: &str, request_id: &str, user: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}\n{request_id}\n{user}").as_bytes());
+    mac
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{peer_headers, PeerConfig, PeerTrust, PEER_SIGNATURE_HEADER, PEER_USER_HEADER};
+    use crate::models::{ClientTags, GenerationParams, NormalizedChatRequest, Priority};
+
+    fn request() -> NormalizedChatRequest {
+        NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "key_abcd".to_owned(),
+            model: "gpt-test".to_owned(),
+            messages: Vec::new(),
+            generation: GenerationParams {
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Low,
+            deadline: Some(Instant::now() + Duration::from_secs(5)),
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    #[test]
+    fn signed_identity_is_accepted_only_with_the_shared_secret() {
+        let now = 1_700_000_000;
+        let headers = peer_headers("peer-key", Some("s3cret"), &request(), now);
+        assert_eq!(headers["x-api-key"], "peer-key");
+        assert_eq!(headers["x-gateway-priority"], "low");
+        assert!(headers.contains_key("x-gateway-timeout-ms"));
+
+        let trust = PeerTrust::new("s3cret");
+        assert_eq!(
+            trust.verify(&headers, "req_1", now + 10).expect("valid"),
+            Some("key_abcd".to_owned())
+        );
+        // Bound to the request id, the clock, and the secret.
+        assert!(trust.verify(&headers, "req_2", now).is_err());
+        assert!(trust.verify(&headers, "req_1", now + 301).is_err());
+        assert!(PeerTrust::new("other")
+            .verify(&headers, "req_1", now)
+            .is_err());
+        let mut forged = headers.clone();
+        forged.insert(PEER_USER_HEADER, "key_admin".parse().expect("header"));
+        assert!(trust.verify(&forged, "req_1", now).is_err());
+        for signature in ["not-hex", "abc", ""] {
+            let mut malformed = headers.clone();
+            malformed.insert(PEER_SIGNATURE_HEADER, signature.parse().expect("header"));
+            assert!(trust.verify(&malformed, "req_1", now).is_err());
+        }
+
+        // Without a secret on either side the peer key's own identity is used.
+        assert_eq!(
+            PeerTrust::disabled()
+                .verify(&headers, "req_1", now)
+                .expect("ignored"),
+            None
+        );
+        let unsigned = peer_headers("peer-key", None, &request(), now);
+        assert!(!unsigned.contains_key(PEER_USER_HEADER));
+        assert_eq!(
+            trust.verify(&unsigned, "req_1", now).expect("unsigned"),
+            None
+        );
+
+        assert!(PeerConfig::parse_list("eu=https://eu.example.com/, us=http://us:8080").is_ok());
+        assert!(PeerConfig::parse_list("eu=eu.example.com").is_err());
+    }
+}
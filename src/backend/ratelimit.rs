@@ -0,0 +1,301 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+
+/// Below this share of a budget left, routers try an endpoint after its roomier peers.
+const LOW_HEADROOM: f64 = 0.1;
+/// Assumed refill time of budgets whose reset the provider did not report; both OpenAI's and
+/// Anthropic's budgets are per minute.
+const DEFAULT_RESET: Duration = Duration::from_secs(60);
+
+/// One of a provider's rolling budgets, as its last response reported it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    /// When the budget is back to full.
+    pub resets_at: Instant,
+}
+
+impl Budget {
+    /// Budgets past their reset say nothing about the next call.
+    fn live(&self, now: Instant) -> bool {
+        self.resets_at > now
+    }
+}
+
+/// Request and token budgets read from a provider's rate-limit response headers: OpenAI's
+/// `x-ratelimit-{remaining,limit,reset}-{requests,tokens}` and Anthropic's
+/// `anthropic-ratelimit-{requests,tokens}-{remaining,limit,reset}`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProviderLimits {
+    pub requests: Option<Budget>,
+    pub tokens: Option<Budget>,
+}
+
+impl ProviderLimits {
+    /// `None` when the response carried no remaining counts.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::parse(headers, Instant::now(), unix_now)
+    }
+
+    fn parse(headers: &HeaderMap, now: Instant, unix_now: Duration) -> Option<Self> {
+        let limits = Self {
+            requests: budget(headers, "requests", now, unix_now),
+            tokens: budget(headers, "tokens", now, unix_now),
+        };
+        (limits.requests.is_some() || limits.tokens.is_some()).then_some(limits)
+    }
+
+    fn live_budgets(&self, now: Instant) -> impl Iterator<Item = Budget> {
+        [self.requests, self.tokens]
+            .into_iter()
+            .flatten()
+            .filter(move |budget| budget.live(now))
+    }
+
+    /// Share of the tightest budget still left, from 0 to 1; `None` when no live budget
+    /// reported its limit.
+    pub fn headroom(&self, now: Instant) -> Option<f64> {
+        self.live_budgets(now)
+            .filter_map(|budget| {
+                let limit = budget.limit.filter(|limit| *limit > 0)?;
+                Some((budget.remaining as f64 / limit as f64).min(1.0))
+            })
+            .reduce(f64::min)
+    }
+
+    /// Whether a budget is exhausted or nearly so.
+    pub fn is_low(&self, now: Instant) -> bool {
+        self.live_budgets(now).any(|budget| budget.remaining == 0)
+            || self
+                .headroom(now)
+                .is_some_and(|headroom| headroom < LOW_HEADROOM)
+    }
+
+    /// Earliest a call of `requests` requests and `tokens` tokens fits: the reset of any budget
+    /// with too little left for it.
+    pub fn not_before(&self, requests: u32, tokens: u64, now: Instant) -> Option<Instant> {
+        [(self.requests, u64::from(requests)), (self.tokens, tokens)]
+            .into_iter()
+            .filter_map(|(budget, cost)| {
+                let budget = budget.filter(|budget| budget.live(now))?;
+                (budget.remaining < cost).then_some(budget.resets_at)
+            })
+            .max()
+    }
+
+    /// How far apart calls of this size must leave to spread what is left of each budget
+    /// evenly over the time until it resets.
+    pub fn spacing(&self, requests: u32, tokens: u64, now: Instant) -> Duration {
+        [(self.requests, u64::from(requests)), (self.tokens, tokens)]
+            .into_iter()
+            .filter_map(|(budget, cost)| {
+                let budget = budget.filter(|budget| budget.live(now) && budget.remaining > 0)?;
+                let window = budget.resets_at.saturating_duration_since(now);
+                Some(window.mul_f64((cost as f64 / budget.remaining as f64).min(1.0)))
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+fn budget(headers: &HeaderMap, kind: &str, now: Instant, unix_now: Duration) -> Option<Budget> {
+    let header = |name: String| headers.get(name)?.to_str().ok().map(str::trim);
+    if let Some(remaining) = header(format!("x-ratelimit-remaining-{kind}")) {
+        return Some(Budget {
+            limit: header(format!("x-ratelimit-limit-{kind}")).and_then(|value| value.parse().ok()),
+            remaining: remaining.parse().ok()?,
+            resets_at: header(format!("x-ratelimit-reset-{kind}"))
+                .and_then(parse_duration)
+                .map_or(now + DEFAULT_RESET, |wait| now + wait),
+        });
+    }
+    let remaining = header(format!("anthropic-ratelimit-{kind}-remaining"))?;
+    Some(Budget {
+        limit: header(format!("anthropic-ratelimit-{kind}-limit"))
+            .and_then(|value| value.parse().ok()),
+        remaining: remaining.parse().ok()?,
+        resets_at: header(format!("anthropic-ratelimit-{kind}-reset"))
+            .and_then(parse_rfc3339)
+            .map_or(now + DEFAULT_RESET, |reset| {
+                now + reset.saturating_sub(unix_now)
+            }),
+    })
+}
+
+/// Parses OpenAI's reset durations, such as `6m0s`, `1.5s`, or `20ms`.
+fn parse_duration(raw: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|end| *end > 0)?;
+        let value = rest[..number_end].parse::<f64>().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        total += value
+            * match &rest[..unit_end] {
+                "h" => 3_600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+        rest = &rest[unit_end..];
+    }
+    Duration::try_from_secs_f64(total).ok()
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-06-01T12:00:30Z` into time since the Unix epoch.
+fn parse_rfc3339(raw: &str) -> Option<Duration> {
+    let (date, time) = raw.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset_secs) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (hours, minutes) = time[split + 1..].split_once(':')?;
+        let offset = hours.parse::<i64>().ok()? * 3_600 + minutes.parse::<i64>().ok()? * 60;
+        let sign = if time.as_bytes()[split] == b'-' {
+            -1
+        } else {
+            1
+        };
+        (&time[..split], sign * offset)
+    };
+    let mut clock = time.splitn(3, ':');
+    let hours = clock.next()?.parse::<i64>().ok()?;
+    let minutes = clock.next()?.parse::<i64>().ok()?;
+    let seconds = clock.next()?.parse::<f64>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days-from-civil over 400-year eras, the inverse of `traces::iso8601`.
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let whole = days * 86_400 + hours * 3_600 + minutes * 60 - offset_secs;
+    Duration::try_from_secs_f64(whole as f64 + seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use super::{parse_duration, parse_rfc3339, ProviderLimits};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).expect("header value"));
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_openai_and_anthropic_headers() {
+        let now = Instant::now();
+        let openai = ProviderLimits::parse(
+            &headers(&[
+                ("x-ratelimit-limit-requests", "500"),
+                ("x-ratelimit-remaining-requests", "40"),
+                ("x-ratelimit-reset-requests", "6m0s"),
+                ("x-ratelimit-remaining-tokens", "9000"),
+                ("x-ratelimit-reset-tokens", "250ms"),
+            ]),
+            now,
+            Duration::ZERO,
+        )
+        .expect("limits");
+        let requests = openai.requests.expect("request budget");
+        assert_eq!((requests.limit, requests.remaining), (Some(500), 40));
+        assert_eq!(requests.resets_at, now + Duration::from_secs(360));
+        assert_eq!(
+            openai.tokens.expect("token budget").resets_at,
+            now + Duration::from_millis(250)
+        );
+        assert_eq!(openai.headroom(now), Some(0.08));
+        assert!(openai.is_low(now));
+        // Budgets past their reset no longer hold anything back.
+        assert!(!openai.is_low(now + Duration::from_secs(361)));
+
+        // 2024-06-01T12:00:00Z, five seconds before the token budget resets.
+        let unix_now = Duration::from_secs(1_717_243_200);
+        let anthropic = ProviderLimits::parse(
+            &headers(&[
+                ("anthropic-ratelimit-tokens-limit", "80000"),
+                ("anthropic-ratelimit-tokens-remaining", "60000"),
+                ("anthropic-ratelimit-tokens-reset", "2024-06-01T12:00:05Z"),
+            ]),
+            now,
+            unix_now,
+        )
+        .expect("limits");
+        assert_eq!(anthropic.requests, None);
+        assert_eq!(
+            anthropic.tokens.expect("token budget").resets_at,
+            now + Duration::from_secs(5)
+        );
+        assert_eq!(anthropic.headroom(now), Some(0.75));
+        assert_eq!(
+            ProviderLimits::parse(&HeaderMap::new(), now, unix_now),
+            None
+        );
+    }
+
+    #[test]
+    fn spaces_calls_over_what_is_left_until_the_reset() {
+        let now = Instant::now();
+        let limits = ProviderLimits::parse(
+            &headers(&[
+                ("x-ratelimit-remaining-requests", "10"),
+                ("x-ratelimit-reset-requests", "1s"),
+                ("x-ratelimit-remaining-tokens", "100"),
+                ("x-ratelimit-reset-tokens", "2s"),
+            ]),
+            now,
+            Duration::ZERO,
+        )
+        .expect("limits");
+        // A tenth of the requests left over a second, a tenth of the tokens over two.
+        assert_eq!(limits.spacing(1, 10, now), Duration::from_millis(200));
+        assert_eq!(limits.not_before(1, 10, now), None);
+        // Too many tokens for what is left: wait for the token budget to reset.
+        assert_eq!(
+            limits.not_before(1, 500, now),
+            Some(now + Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn parses_reset_formats() {
+        assert_eq!(
+            parse_duration("1h2m3.5s"),
+            Some(Duration::from_secs_f64(3_723.5))
+        );
+        assert_eq!(parse_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(
+            parse_rfc3339("1970-01-02T00:00:01.5Z"),
+            Some(Duration::from_secs_f64(86_401.5))
+        );
+        assert_eq!(
+            parse_rfc3339("2024-06-01T14:00:00+02:00"),
+            Some(Duration::from_secs(1_717_243_200))
+        );
+        assert_eq!(parse_rfc3339("2024-13-01T00:00:00Z"), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Incremental parser for `text/event-stream` bodies, following the WHATWG server-sent events
+//! rules: `\r\n`, `\r`, and `\n` line endings, multi-line `data:` fields, and `:` comments.
+//! Bytes are buffered until a line is complete, so a chunk boundary may fall anywhere,
+//! including inside a multi-byte UTF-8 character.
+
+use std::str::Utf8Error;
+
+/// One dispatched event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event:` field; `None` for the default `message` type.
+    pub event: Option<String>,
+    /// The event's `data:` lines joined with `\n`.
+    pub data: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    /// The last chunk ended in `\r`, so a `\n` opening the next one ends no further line.
+    pending_cr: bool,
+    /// Whether a leading byte-order mark has been checked for.
+    started: bool,
+    event: PendingEvent,
+}
+
+/// Fields of the event being assembled, until a blank line dispatches it.
+#[derive(Debug, Default)]
+struct PendingEvent {
+    event: Option<String>,
+    data: String,
+}
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next body chunk and returns the events it completes, in order. A complete line
+    /// that is not valid UTF-8 fails the parse.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<SseEvent>, Utf8Error> {
+        let mut bytes = bytes;
+        if self.pending_cr && !bytes.is_empty() {
+            self.pending_cr = false;
+            bytes = bytes.strip_prefix(b"\n").unwrap_or(bytes);
+        }
+        self.buffer.extend_from_slice(bytes);
+        if !self.started {
+            if self.buffer.len() < BOM.len() && BOM.starts_with(&self.buffer) {
+                return Ok(Vec::new());
+            }
+            self.started = true;
+            if self.buffer.starts_with(BOM) {
+                self.buffer.drain(..BOM.len());
+            }
+        }
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(offset) = self.buffer[start..]
+            .iter()
+            .position(|byte| matches!(byte, b'\r' | b'\n'))
+        {
+            let end = start + offset;
+            let mut next = end + 1;
+            if self.buffer[end] == b'\r' {
+                match self.buffer.get(next) {
+                    Some(b'\n') => next += 1,
+                    None => self.pending_cr = true,
+                    Some(_) => {}
+                }
+            }
+            let line = std::str::from_utf8(&self.buffer[start..end])?;
+            if let Some(event) = self.event.process(line) {
+                events.push(event);
+            }
+            start = next;
+        }
+        self.buffer.drain(..start);
+        Ok(events)
+    }
+
+    /// Ends the stream, returning the event left without its closing blank line. The spec
+    /// discards such an event, but some OpenAI-compatible servers omit the final blank line.
+    pub fn finish(&mut self) -> Result<Option<SseEvent>, Utf8Error> {
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            let line = std::str::from_utf8(&rest)?;
+            if let Some(event) = self.event.process(line) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(self.event.dispatch())
+    }
+}
+
+impl PendingEvent {
+    /// Applies one line, returning the event a blank line dispatches.
+    fn process(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            // `id` and `retry` only matter to reconnecting clients, which adapters are not.
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let mut pending = std::mem::take(self);
+        if pending.data.is_empty() {
+            return None;
+        }
+        pending.data.pop();
+        Some(SseEvent {
+            event: pending.event.filter(|event| !event.is_empty()),
+            data: pending.data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SseEvent, SseParser};
+
+    fn data(data: &str) -> SseEvent {
+        SseEvent {
+            event: None,
+            data: data.to_owned(),
+        }
+    }
+
+    /// Feeds `body` split at every possible pair of boundaries and checks each split parses
+    /// the same as the whole.
+    fn assert_fragmentation_safe(body: &[u8], expected: &[SseEvent]) {
+        for first in 0..=body.len() {
+            for second in first..=body.len() {
+                let mut parser = SseParser::new();
+                let mut events = Vec::new();
+                for piece in [&body[..first], &body[first..second], &body[second..]] {
+                    events.extend(parser.push(piece).expect("valid UTF-8"));
+                }
+                events.extend(parser.finish().expect("valid UTF-8"));
+                assert_eq!(events, expected, "split at {first} and {second}");
+            }
+        }
+    }
+
+    #[test]
+    fn parses_events_with_any_line_ending() {
+        let expected = [data("{\"a\":1}"), data("[DONE]")];
+        for body in [
+            "data: {\"a\":1}\n\ndata: [DONE]\n\n",
+            "data: {\"a\":1}\r\n\r\ndata: [DONE]\r\n\r\n",
+            "data: {\"a\":1}\r\rdata: [DONE]\r\r",
+        ] {
+            assert_fragmentation_safe(body.as_bytes(), &expected);
+        }
+    }
+
+    #[test]
+    fn joins_multi_line_data_and_skips_comments() {
+        let body = b": keep-alive\n\nevent: completion\ndata: {\"a\":\ndata:1}\n: heartbeat\n\n";
+        assert_fragmentation_safe(
+            body,
+            &[SseEvent {
+                event: Some("completion".to_owned()),
+                data: "{\"a\":\n1}".to_owned(),
+            }],
+        );
+    }
+
+    #[test]
+    fn keeps_multi_byte_characters_split_across_chunks() {
+        let body = "\u{feff}data: {\"content\":\"héllo 👋\"}\n\n".as_bytes();
+        assert_fragmentation_safe(body, &[data("{\"content\":\"héllo 👋\"}")]);
+    }
+
+    #[test]
+    fn flushes_an_unterminated_final_event() {
+        let mut parser = SseParser::new();
+        assert_eq!(
+            parser
+                .push(b"data: one\n\ndata: [DONE]")
+                .expect("valid UTF-8"),
+            [data("one")]
+        );
+        assert_eq!(parser.finish().expect("valid UTF-8"), Some(data("[DONE]")));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_in_a_complete_line() {
+        let mut parser = SseParser::new();
+        assert!(parser.push(b"data: \xFF\xFE\n\n").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use thiserror::Error;
+
+use crate::models::{normalize_finish_reason, BackendChatResponse};
+
+/// More tokens than any context window holds; usage past this is a provider bug, and billing
+/// it would charge the key for tokens that never existed.
+const MAX_PLAUSIBLE_TOKENS: u32 = 10_000_000;
+
+/// Something wrong with a backend's one-shot response, found by [`validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Problem {
+    #[error("response has no finish reason")]
+    MissingFinishReason,
+    #[error("response finish reason is not an OpenAI one")]
+    UnknownFinishReason,
+    #[error("response usage total is not the sum of prompt and completion tokens")]
+    UsageTotalMismatch,
+    #[error("response usage counts no completion tokens for non-empty content")]
+    MissingCompletionUsage,
+    #[error("response content is empty without a tool call, filter, or length cut-off")]
+    EmptyContent,
+    #[error("response usage of {0} tokens is implausible")]
+    ImplausibleUsage(u32),
+}
+
+impl Problem {
+    /// Label of the problem on `gateway_backend_response_problems_total`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MissingFinishReason => "missing_finish_reason",
+            Self::UnknownFinishReason => "unknown_finish_reason",
+            Self::UsageTotalMismatch => "usage_total_mismatch",
+            Self::MissingCompletionUsage => "missing_completion_usage",
+            Self::EmptyContent => "empty_content",
+            Self::ImplausibleUsage(_) => "implausible_usage",
+        }
+    }
+}
+
+/// Checks a backend's one-shot response before it is cached, billed, or returned. What can be
+/// fixed without guessing at the content is repaired in place and returned; anything else is
+/// the returned error, for the caller to turn into [`BackendError::InvalidResponse`].
+///
+/// [`BackendError::InvalidResponse`]: crate::backend::BackendError::InvalidResponse
+pub fn validate(response: &mut BackendChatResponse) -> Result<Vec<Problem>, Problem> {
+    let usage = &response.usage;
+    if let Some(tokens) = [usage.prompt_tokens, usage.completion_tokens]
+        .into_iter()
+        .find(|tokens| *tokens > MAX_PLAUSIBLE_TOKENS)
+    {
+        return Err(Problem::ImplausibleUsage(tokens));
+    }
+
+    let mut repairs = Vec::new();
+    if response.finish_reason.trim().is_empty() {
+        response.finish_reason = "stop".to_owned();
+        repairs.push(Problem::MissingFinishReason);
+    } else {
+        let (finish_reason, raw) = normalize_finish_reason(&response.finish_reason);
+        if let Some(raw) = raw {
+            response.finish_reason = finish_reason;
+            response.provider_finish_reason.get_or_insert(raw);
+            repairs.push(Problem::UnknownFinishReason);
+        }
+    }
+
+    // Tool calls carry no text, filtered output is withheld, and reasoning models can spend a
+    // whole `max_tokens` budget before writing anything.
+    let may_be_empty = matches!(
+        response.finish_reason.as_str(),
+        "tool_calls" | "function_call" | "content_filter" | "length"
+    );
+    if response.content.is_empty() && !may_be_empty {
+        return Err(Problem::EmptyContent);
+    }
+
+    let usage = &mut response.usage;
+    if usage.completion_tokens == 0 && !response.content.trim().is_empty() {
+        usage.completion_tokens = response.content.split_whitespace().count() as u32;
+        repairs.push(Problem::MissingCompletionUsage);
+    }
+    let total = usage.prompt_tokens.saturating_add(usage.completion_tokens);
+    if usage.total_tokens != total {
+        usage.total_tokens = total;
+        repairs.push(Problem::UsageTotalMismatch);
+    }
+    Ok(repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, Problem};
+    use crate::models::{BackendChatResponse, Usage};
+
+    fn response(content: &str, finish_reason: &str, usage: Usage) -> BackendChatResponse {
+        BackendChatResponse {
+            content: content.to_owned(),
+            finish_reason: finish_reason.to_owned(),
+            provider_finish_reason: None,
+            usage,
+            backend: None,
+            upstream_headers: None,
+        }
+    }
+
+    #[test]
+    fn well_formed_responses_pass_untouched() {
+        let mut ok = response("Hello there", "stop", Usage::new(3, 2));
+        let before = ok.clone();
+        assert_eq!(validate(&mut ok), Ok(Vec::new()));
+        assert_eq!(ok, before);
+
+        // Tool calls and length cut-offs may come without text.
+        for finish_reason in ["tool_calls", "length"] {
+            assert_eq!(
+                validate(&mut response("", finish_reason, Usage::new(3, 0))),
+                Ok(Vec::new())
+            );
+        }
+    }
+
+    #[test]
+    fn repairs_finish_reasons_and_usage() {
+        let mut missing = response("Hello there", " ", Usage::new(3, 0));
+        missing.usage.total_tokens = 99;
+        assert_eq!(
+            validate(&mut missing),
+            Ok(vec![
+                Problem::MissingFinishReason,
+                Problem::MissingCompletionUsage,
+                Problem::UsageTotalMismatch,
+            ])
+        );
+        assert_eq!(missing.finish_reason, "stop");
+        assert_eq!(missing.usage, Usage::new(3, 2));
+
+        let mut relayed = response("Hi", "end_turn", Usage::new(1, 1));
+        assert_eq!(
+            validate(&mut relayed),
+            Ok(vec![Problem::UnknownFinishReason])
+        );
+        assert_eq!(relayed.finish_reason, "stop");
+        assert_eq!(relayed.provider_finish_reason.as_deref(), Some("end_turn"));
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_repaired() {
+        assert_eq!(
+            validate(&mut response("", "stop", Usage::new(3, 0))),
+            Err(Problem::EmptyContent)
+        );
+        assert_eq!(
+            validate(&mut response("Hi", "stop", Usage::new(3, u32::MAX - 3))),
+            Err(Problem::ImplausibleUsage(u32::MAX - 3))
+        );
+    }
+}
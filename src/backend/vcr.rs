@@ -0,0 +1,402 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    backend::{credentials::Credentials, BackendError, BackendStream, InferenceBackend},
+    models::{
+        BackendChatResponse, BackendChunk, NormalizedChatRequest, NormalizedMessage,
+        StreamTranscript,
+    },
+    router::HEALTH_PROBE_REQUEST_ID,
+    scheduler::fingerprint_for,
+};
+
+/// Whether the gateway records upstream traffic to fixtures, replays them instead of calling
+/// any backend, or neither.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VcrMode {
+    #[default]
+    Off,
+    Record,
+    Replay,
+}
+
+impl VcrMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "" => Some(Self::Off),
+            "record" => Some(Self::Record),
+            "replay" => Some(Self::Replay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VcrConfig {
+    pub mode: VcrMode,
+    /// Directory holding one fixture file per recorded request.
+    pub dir: PathBuf,
+    /// Replays streams with the recorded chunk timing instead of as fast as possible.
+    pub paced_replay: bool,
+}
+
+impl Default for VcrConfig {
+    fn default() -> Self {
+        Self {
+            mode: VcrMode::Off,
+            dir: PathBuf::from("fixtures/vcr"),
+            paced_replay: true,
+        }
+    }
+}
+
+impl VcrConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let mode = match env::var("GATEWAY_VCR_MODE") {
+            Ok(value) => VcrMode::parse(&value).unwrap_or_else(|| {
+                warn!(value = %value, "invalid GATEWAY_VCR_MODE, recording and replay stay off");
+                VcrMode::Off
+            }),
+            Err(_) => defaults.mode,
+        };
+        Self {
+            mode,
+            dir: env::var_os("GATEWAY_VCR_DIR")
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or(defaults.dir),
+            paced_replay: env::var("GATEWAY_VCR_PACED_REPLAY")
+                .ok()
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(defaults.paced_replay),
+        }
+    }
+}
+
+/// One recorded exchange. The request fields are kept for people reading the fixture; replay
+/// looks fixtures up by the request fingerprint in the file name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fixture {
+    model: String,
+    messages: Vec<NormalizedMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<BackendChatResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transcript: Option<StreamTranscript>,
+}
+
+impl Fixture {
+    fn for_request(request: &NormalizedChatRequest) -> Self {
+        Self {
+            model: request.model.clone(),
+            messages: request.messages.clone(),
+            response: None,
+            transcript: None,
+        }
+    }
+}
+
+/// `{dir}/{fingerprint}.json` for one-shot calls and `{fingerprint}.stream.json` for streams,
+/// so the same prompt can be recorded both ways.
+fn fixture_path(dir: &Path, request: &NormalizedChatRequest, stream: bool) -> PathBuf {
+    let fingerprint = fingerprint_for(request);
+    let suffix = if stream { ".stream.json" } else { ".json" };
+    dir.join(format!("{}{suffix}", fingerprint.as_str()))
+}
+
+async fn write_fixture(path: &Path, fixture: &Fixture) {
+    let result = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_vec_pretty(fixture).map_err(std::io::Error::other)?;
+        // Written aside and renamed so a concurrent replay never reads half a fixture.
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, json).await?;
+        tokio::fs::rename(&partial, path).await
+    }
+    .await;
+    match result {
+        Ok(()) => debug!(path = %path.display(), "vcr fixture recorded"),
+        Err(error) => warn!(error = %error, path = %path.display(), "failed to write vcr fixture"),
+    }
+}
+
+/// Passes calls through to `inner` and writes each successful exchange to a fixture file,
+/// including every stream chunk's offset from the start of the stream.
+pub struct RecordingBackend {
+    inner: Arc<dyn InferenceBackend>,
+    dir: PathBuf,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn InferenceBackend>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            dir: dir.into(),
+        }
+    }
+
+    async fn record_response(
+        &self,
+        request: &NormalizedChatRequest,
+        response: &BackendChatResponse,
+    ) {
+        // Health checks hit the endpoint on a timer and are not traffic worth replaying.
+        if request.request_id == HEALTH_PROBE_REQUEST_ID {
+            return;
+        }
+        let fixture = Fixture {
+            response: Some(response.clone()),
+            ..Fixture::for_request(request)
+        };
+        write_fixture(&fixture_path(&self.dir, request, false), &fixture).await;
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for RecordingBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn credentials(&self, backend: &str) -> Option<Arc<Credentials>> {
+        self.inner.credentials(backend)
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        let response = self.inner.execute_chat(request.clone()).await?;
+        self.record_response(&request, &response).await;
+        Ok(response)
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let mut upstream = self.inner.stream_chat(request.clone()).await?;
+        let path = fixture_path(&self.dir, &request, true);
+        let mut fixture = Fixture::for_request(&request);
+        Ok(async_stream::stream! {
+            let started = Instant::now();
+            let mut transcript = StreamTranscript::default();
+            while let Some(item) = upstream.next().await {
+                if let Ok(chunk) = &item {
+                    if let Some(delta) = &chunk.delta {
+                        transcript.push_delta(delta.clone(), started.elapsed().as_millis() as u64);
+                    }
+                    if chunk.done {
+                        transcript.finish(chunk);
+                        fixture.transcript = Some(std::mem::take(&mut transcript));
+                        write_fixture(&path, &fixture).await;
+                    }
+                }
+                yield item;
+            }
+        }
+        .boxed())
+    }
+
+    async fn execute_chat_batch(
+        &self,
+        requests: Vec<Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        let results = self.inner.execute_chat_batch(requests.clone()).await;
+        for (request, result) in requests.iter().zip(&results) {
+            if let Ok(response) = result {
+                self.record_response(request, response).await;
+            }
+        }
+        results
+    }
+}
+
+/// Serves recorded fixtures and never calls upstream; a request without a fixture fails as an
+/// unavailable backend naming the fixture it looked for.
+pub struct ReplayBackend {
+    name: String,
+    dir: PathBuf,
+    paced: bool,
+}
+
+impl ReplayBackend {
+    pub fn new(dir: impl Into<PathBuf>, paced: bool) -> Self {
+        Self {
+            name: "replay".to_owned(),
+            dir: dir.into(),
+            paced,
+        }
+    }
+
+    async fn load(
+        &self,
+        request: &NormalizedChatRequest,
+        stream: bool,
+    ) -> Result<Fixture, BackendError> {
+        let path = fixture_path(&self.dir, request, stream);
+        let raw = tokio::fs::read(&path).await.map_err(|error| {
+            BackendError::Unavailable(format!(
+                "no recorded fixture at {} ({error})",
+                path.display()
+            ))
+        })?;
+        serde_json::from_slice(&raw).map_err(|error| {
+            BackendError::InvalidResponse(format!("unreadable fixture {}: {error}", path.display()))
+        })
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for ReplayBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        self.load(&request, false).await?.response.ok_or_else(|| {
+            BackendError::InvalidResponse("fixture holds no one-shot response".to_owned())
+        })
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let transcript = self.load(&request, true).await?.transcript.ok_or_else(|| {
+            BackendError::InvalidResponse("fixture holds no stream transcript".to_owned())
+        })?;
+        let paced = self.paced;
+        Ok(async_stream::stream! {
+            let started = tokio::time::Instant::now();
+            for chunk in transcript.chunks {
+                if paced {
+                    tokio::time::sleep_until(started + Duration::from_millis(chunk.offset_ms)).await;
+                }
+                yield Ok(BackendChunk {
+                    delta: Some(chunk.delta),
+                    finish_reason: None,
+                    provider_finish_reason: None,
+                    usage: None,
+                    done: false,
+                    backend: None,
+                    raw: None,
+                    upstream_headers: None,
+                });
+            }
+            yield Ok(BackendChunk {
+                delta: None,
+                finish_reason: Some(transcript.finish_reason),
+                provider_finish_reason: transcript.provider_finish_reason,
+                usage: transcript.usage,
+                done: true,
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            });
+        }
+        .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+
+    use super::{RecordingBackend, ReplayBackend, VcrMode};
+    use crate::{
+        backend::{mock::MockBackend, BackendError, InferenceBackend},
+        models::{
+            ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+            Priority,
+        },
+    };
+
+    fn request(content: &str) -> Arc<NormalizedChatRequest> {
+        Arc::new(NormalizedChatRequest {
+            request_id: "req-1".to_owned(),
+            user_id: "user-1".to_owned(),
+            model: "mock-1".to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: content.to_owned(),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_responses_and_streams_without_upstream() {
+        let dir = std::env::temp_dir().join(format!("vcr-test-{}", uuid::Uuid::new_v4()));
+        let recorder = RecordingBackend::new(Arc::new(MockBackend::default()), &dir);
+        let recorded = recorder
+            .execute_chat(request("hello there"))
+            .await
+            .expect("recorded response");
+        let mut live = recorder
+            .stream_chat(request("stream me"))
+            .await
+            .expect("recorded stream");
+        let mut live_text = String::new();
+        while let Some(chunk) = live.next().await {
+            live_text.push_str(&chunk.expect("chunk").delta.unwrap_or_default());
+        }
+
+        let replay = ReplayBackend::new(&dir, false);
+        let replayed = replay
+            .execute_chat(request("hello there"))
+            .await
+            .expect("replayed response");
+        assert_eq!(replayed, recorded);
+        let mut stream = replay
+            .stream_chat(request("stream me"))
+            .await
+            .expect("replayed stream");
+        let mut replayed_text = String::new();
+        let mut usage = None;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.expect("chunk");
+            replayed_text.push_str(&chunk.delta.unwrap_or_default());
+            usage = chunk.usage.or(usage);
+        }
+        assert_eq!(replayed_text, live_text);
+        assert!(usage.is_some());
+
+        assert!(matches!(
+            replay.execute_chat(request("never recorded")).await,
+            Err(BackendError::Unavailable(_))
+        ));
+        assert_eq!(VcrMode::parse("Replay"), Some(VcrMode::Replay));
+        assert_eq!(VcrMode::parse("tape"), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
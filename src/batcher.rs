@@ -1,23 +1,34 @@
 use std::{
-    collections::VecDeque,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
     env,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
-use tokio::sync::{mpsc, oneshot};
+use futures_util::{FutureExt, StreamExt};
+use tokio::sync::{mpsc, oneshot, Notify, OwnedSemaphorePermit, Semaphore};
 use tracing::debug;
 
 use crate::{
-    backend::{BackendError, BackendStream, InferenceBackend},
-    models::NormalizedChatRequest,
+    backend::{with_deadline, BackendError, BackendStream, InferenceBackend},
+    metrics::{AppMetrics, PoolUsage},
+    models::{NormalizedChatRequest, Priority},
 };
 
 #[derive(Clone)]
 pub struct Batcher {
     backend: Arc<dyn InferenceBackend>,
     tx: mpsc::Sender<BatchItem>,
+    queued: Arc<AtomicUsize>,
+    stream_slots: Arc<Semaphore>,
+    stream_waiting: Arc<AtomicUsize>,
+    config: BatchConfig,
+    metrics: Arc<AppMetrics>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -25,39 +36,106 @@ pub struct BatchConfig {
     pub enabled: bool,
     pub max_batch_size: usize,
     pub max_wait: Duration,
+    /// Number of flushed batches that may execute against the backend concurrently.
+    pub workers: usize,
+    /// Requests admitted but not yet executing; submissions beyond this are shed as overloaded.
+    pub queue_capacity: usize,
+    pub retry_after: Duration,
+    /// Streams allowed to run against the backend at once; streams are admitted here but
+    /// never token-batched.
+    pub stream_concurrency: usize,
+    /// Streams allowed to wait for a slot before new ones are shed.
+    pub stream_queue_capacity: usize,
+    /// How long a queued stream waits for a slot before it is shed.
+    pub stream_queue_timeout: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_batch_size: 8,
+            max_wait: Duration::from_millis(10),
+            workers: 4,
+            queue_capacity: 1_024,
+            retry_after: Duration::from_secs(1),
+            stream_concurrency: 256,
+            stream_queue_capacity: 256,
+            stream_queue_timeout: Duration::from_millis(2_000),
+        }
+    }
 }
 
 impl BatchConfig {
     pub fn from_env() -> Self {
+        let defaults = Self::default();
         let enabled = env::var("GATEWAY_BATCH_ENABLED")
             .ok()
             .map(|value| value != "0" && !value.eq_ignore_ascii_case("false"))
-            .unwrap_or(true);
+            .unwrap_or(defaults.enabled);
         let max_batch_size = env::var("GATEWAY_BATCH_MAX_SIZE")
             .ok()
             .and_then(|value| value.parse::<usize>().ok())
             .filter(|value| *value > 0)
-            .unwrap_or(8);
-        let max_wait_ms = env::var("GATEWAY_BATCH_MAX_WAIT_MS")
+            .unwrap_or(defaults.max_batch_size);
+        let max_wait = env::var("GATEWAY_BATCH_MAX_WAIT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.max_wait);
+        let workers = env::var("GATEWAY_BATCH_WORKERS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(defaults.workers);
+        let queue_capacity = env::var("GATEWAY_BATCH_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(defaults.queue_capacity);
+        let retry_after = env::var("GATEWAY_OVERLOAD_RETRY_AFTER_SECS")
             .ok()
             .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(10);
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.retry_after);
+        let stream_concurrency = env::var("GATEWAY_STREAM_MAX_CONCURRENCY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(defaults.stream_concurrency);
+        let stream_queue_capacity = env::var("GATEWAY_STREAM_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(defaults.stream_queue_capacity);
+        let stream_queue_timeout = env::var("GATEWAY_STREAM_QUEUE_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.stream_queue_timeout);
 
         Self {
             enabled,
             max_batch_size,
-            max_wait: Duration::from_millis(max_wait_ms),
+            max_wait,
+            workers,
+            queue_capacity,
+            retry_after,
+            stream_concurrency,
+            stream_queue_capacity,
+            stream_queue_timeout,
         }
     }
 }
 
 struct BatchItem {
     class: BatchClass,
-    request: NormalizedChatRequest,
+    priority: Priority,
+    enqueued_at: Instant,
+    request: Arc<NormalizedChatRequest>,
     response_tx: oneshot::Sender<Result<crate::models::BackendChatResponse, BackendError>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct BatchClass {
     model: String,
     max_tokens: Option<u32>,
@@ -77,31 +155,125 @@ impl BatchClass {
 }
 
 impl Batcher {
-    pub fn new(backend: Arc<dyn InferenceBackend>, config: BatchConfig) -> Self {
-        let (tx, rx) = mpsc::channel(1_024);
-        let worker_backend = backend.clone();
-        tokio::spawn(run_batch_worker(worker_backend, rx, config));
-        Self { backend, tx }
+    pub fn new(
+        backend: Arc<dyn InferenceBackend>,
+        config: BatchConfig,
+        metrics: Arc<AppMetrics>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        metrics.set_pool_capacity("stream", config.stream_concurrency.max(1));
+        let queued = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(run_batch_worker(
+            backend.clone(),
+            rx,
+            config,
+            metrics.clone(),
+            queued.clone(),
+        ));
+        Self {
+            backend,
+            tx,
+            queued,
+            stream_slots: Arc::new(Semaphore::new(config.stream_concurrency.max(1))),
+            stream_waiting: Arc::new(AtomicUsize::new(0)),
+            config,
+            metrics,
+        }
+    }
+
+    fn overloaded(&self, stage: &str, message: &str) -> BackendError {
+        self.metrics.observe_load_shed(stage);
+        BackendError::Overloaded {
+            message: message.to_owned(),
+            retry_after_secs: self.config.retry_after.as_secs().max(1),
+        }
+    }
+
+    /// Waits for a stream slot, shedding the stream when the wait queue is full or the slot
+    /// does not free up within `stream_queue_timeout`. A request deadline that comes first
+    /// fails the wait as `DeadlineExceeded` instead.
+    async fn admit_stream(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<(OwnedSemaphorePermit, PoolUsage), BackendError> {
+        if let Ok(permit) = self.stream_slots.clone().try_acquire_owned() {
+            self.metrics.observe_queue_wait("stream", Duration::ZERO);
+            return Ok((permit, self.metrics.pool_usage("stream")));
+        }
+
+        let capacity = self.config.stream_queue_capacity;
+        self.stream_waiting
+            .fetch_update(AtomicOrdering::AcqRel, AtomicOrdering::Acquire, |waiting| {
+                (waiting < capacity).then_some(waiting + 1)
+            })
+            .map_err(|_| self.overloaded("stream", "stream queue is full"))?;
+        self.metrics.adjust_queue_depth("stream", 1);
+        let started = Instant::now();
+        let acquired = with_deadline(
+            deadline,
+            "stream admission",
+            tokio::time::timeout(
+                self.config.stream_queue_timeout,
+                self.stream_slots.clone().acquire_owned(),
+            )
+            .map(Ok),
+        )
+        .await;
+        self.stream_waiting.fetch_sub(1, AtomicOrdering::AcqRel);
+        self.metrics.adjust_queue_depth("stream", -1);
+
+        match acquired? {
+            Ok(Ok(permit)) => {
+                self.metrics.observe_queue_wait("stream", started.elapsed());
+                Ok((permit, self.metrics.pool_usage("stream")))
+            }
+            Ok(Err(_)) => Err(BackendError::Unavailable(
+                "stream admission closed".to_owned(),
+            )),
+            Err(_) => Err(self.overloaded("stream", "timed out waiting for a stream slot")),
+        }
+    }
+
+    /// Reserves a queue slot, or fails with `Overloaded` once `queue_capacity` requests are
+    /// already waiting.
+    fn admit(&self) -> Result<(), BackendError> {
+        let capacity = self.config.queue_capacity.max(1);
+        self.queued
+            .fetch_update(AtomicOrdering::AcqRel, AtomicOrdering::Acquire, |queued| {
+                (queued < capacity).then_some(queued + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| self.overloaded("batcher", "batcher queue is full"))
     }
 
     async fn submit(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<crate::models::BackendChatResponse, BackendError> {
+        self.admit()?;
         let (response_tx, response_rx) = oneshot::channel();
         let class = BatchClass::from_request(&request);
+        let deadline = request.deadline;
         self.tx
             .send(BatchItem {
                 class,
+                priority: request.priority,
+                enqueued_at: Instant::now(),
                 request,
                 response_tx,
             })
             .await
-            .map_err(|_| BackendError::Unavailable("batcher queue closed".to_owned()))?;
+            .map_err(|_| {
+                self.queued.fetch_sub(1, AtomicOrdering::AcqRel);
+                BackendError::Unavailable("batcher queue closed".to_owned())
+            })?;
 
-        response_rx
-            .await
-            .map_err(|_| BackendError::Unavailable("batch response channel closed".to_owned()))?
+        with_deadline(deadline, "batch queue", async {
+            response_rx.await.map_err(|_| {
+                BackendError::Unavailable("batch response channel closed".to_owned())
+            })?
+        })
+        .await
     }
 }
 
@@ -113,85 +285,252 @@ impl InferenceBackend for Batcher {
 
     async fn execute_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<crate::models::BackendChatResponse, BackendError> {
         self.submit(request).await
     }
 
     async fn stream_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendStream, BackendError> {
-        self.backend.stream_chat(request).await
+        let permit = self.admit_stream(request.deadline).await?;
+        let stream = self.backend.stream_chat(request).await?;
+        // The slot is held by the stream itself and released when it finishes or is dropped.
+        Ok(stream
+            .map(move |item| {
+                let _slot = &permit;
+                item
+            })
+            .boxed())
     }
 }
 
+/// A batch still accepting items for its class; flushed when full, when `deadline` passes, or
+/// as soon as a high-priority item joins it.
+struct FormingBatch {
+    items: Vec<BatchItem>,
+    priority: Priority,
+    deadline: Instant,
+}
+
+/// A flushed batch waiting for a worker; higher priorities run first, then flush order.
+struct ReadyBatch {
+    priority: Priority,
+    sequence: u64,
+    items: Vec<BatchItem>,
+}
+
+impl PartialEq for ReadyBatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ReadyBatch {}
+
+impl PartialOrd for ReadyBatch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ReadyBatch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[derive(Default)]
+struct ReadyQueue {
+    batches: std::sync::Mutex<BinaryHeap<ReadyBatch>>,
+    next_sequence: std::sync::atomic::AtomicU64,
+    notify: Notify,
+}
+
+impl ReadyQueue {
+    fn push(&self, items: Vec<BatchItem>) {
+        let priority = items
+            .iter()
+            .map(|item| item.priority)
+            .max()
+            .unwrap_or_default();
+        let sequence = self
+            .next_sequence
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if let Ok(mut batches) = self.batches.lock() {
+            batches.push(ReadyBatch {
+                priority,
+                sequence,
+                items,
+            });
+        }
+        self.notify.notify_one();
+    }
+
+    async fn pop(&self) -> Vec<BatchItem> {
+        loop {
+            let next = self
+                .batches
+                .lock()
+                .ok()
+                .and_then(|mut batches| batches.pop());
+            if let Some(batch) = next {
+                return batch.items;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Dispatches items into per-class queues with independent flush timers, so a slow class
+/// never holds back another. Flushed batches run on a pool of `config.workers` tasks that
+/// always take the highest-priority ready batch.
 async fn run_batch_worker(
     backend: Arc<dyn InferenceBackend>,
     mut rx: mpsc::Receiver<BatchItem>,
     config: BatchConfig,
+    metrics: Arc<AppMetrics>,
+    queued: Arc<AtomicUsize>,
 ) {
-    let mut pending = VecDeque::new();
-    loop {
-        let first = if let Some(item) = pending.pop_front() {
-            item
-        } else {
-            match rx.recv().await {
-                Some(item) => item,
-                None => break,
+    let ready = Arc::new(ReadyQueue::default());
+    for _ in 0..config.workers.max(1) {
+        let backend = backend.clone();
+        let ready = ready.clone();
+        let metrics = metrics.clone();
+        let queued = queued.clone();
+        tokio::spawn(async move {
+            loop {
+                let batch = ready.pop().await;
+                let now = Instant::now();
+                queued.fetch_sub(batch.len(), AtomicOrdering::AcqRel);
+                metrics.adjust_batch_queue_depth(-(batch.len() as i64));
+                for item in &batch {
+                    metrics.observe_batch_queue_wait(
+                        item.priority.as_str(),
+                        now.saturating_duration_since(item.enqueued_at),
+                    );
+                }
+                let batch = drop_expired(batch);
+                if !batch.is_empty() {
+                    execute_batch(backend.clone(), batch).await;
+                }
             }
-        };
+        });
+    }
+    let flush = |reason: &str, items: Vec<BatchItem>| {
+        metrics.observe_batch_flush(reason, items.len());
+        ready.push(items);
+    };
 
-        if !config.enabled {
-            let result = backend.execute_chat(first.request).await;
-            let _ = first.response_tx.send(result);
-            continue;
-        }
+    let mut forming: HashMap<BatchClass, FormingBatch> = HashMap::new();
 
-        let class = first.class.clone();
-        let deadline = Instant::now() + config.max_wait;
-        let mut batch = vec![first];
+    loop {
+        let next_deadline = forming.values().map(|batch| batch.deadline).min();
+        let received = match next_deadline {
+            Some(deadline) => tokio::select! {
+                item = rx.recv() => Some(item),
+                _ = tokio::time::sleep_until(deadline.into()) => None,
+            },
+            None => Some(rx.recv().await),
+        };
 
-        while batch.len() < config.max_batch_size {
-            if let Some(position) = pending.iter().position(|item| item.class == class) {
-                if let Some(item) = pending.remove(position) {
-                    batch.push(item);
+        match received {
+            Some(Some(item)) => {
+                metrics.adjust_batch_queue_depth(1);
+                if !config.enabled {
+                    flush("disabled", vec![item]);
                     continue;
                 }
-            }
 
-            let now = Instant::now();
-            if now >= deadline {
+                let class = item.class.clone();
+                let batch = forming
+                    .entry(class.clone())
+                    .or_insert_with(|| FormingBatch {
+                        items: Vec::with_capacity(config.max_batch_size),
+                        priority: item.priority,
+                        deadline: Instant::now() + config.max_wait,
+                    });
+                batch.priority = batch.priority.max(item.priority);
+                // Never hold an item in the wait window past its own deadline.
+                if let Some(deadline) = item.request.deadline {
+                    batch.deadline = batch.deadline.min(deadline);
+                }
+                batch.items.push(item);
+                let reason = if batch.items.len() >= config.max_batch_size {
+                    Some("size")
+                } else if batch.priority == Priority::High {
+                    Some("priority")
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    if let Some(batch) = forming.remove(&class) {
+                        flush(reason, batch.items);
+                    }
+                }
+            }
+            Some(None) => {
+                for (_, batch) in forming.drain() {
+                    flush("shutdown", batch.items);
+                }
                 break;
             }
-            let remaining = deadline - now;
-            let next = tokio::time::timeout(remaining, rx.recv()).await;
-            match next {
-                Ok(Some(item)) => {
-                    if item.class == class {
-                        batch.push(item);
-                    } else {
-                        pending.push_back(item);
+            None => {
+                let now = Instant::now();
+                let due = forming
+                    .iter()
+                    .filter(|(_, batch)| batch.deadline <= now)
+                    .map(|(class, _)| class.clone())
+                    .collect::<Vec<_>>();
+                for class in due {
+                    if let Some(batch) = forming.remove(&class) {
+                        flush("deadline", batch.items);
                     }
                 }
-                Ok(None) => break,
-                Err(_) => break,
             }
         }
+    }
+}
 
+/// Fails items whose deadline passed while they were queued, so the backend never does work
+/// no client is waiting for.
+fn drop_expired(batch: Vec<BatchItem>) -> Vec<BatchItem> {
+    let (expired, live): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .partition(|item| item.request.deadline_passed());
+    for item in expired {
+        let _ = item.response_tx.send(Err(BackendError::DeadlineExceeded(
+            "request deadline passed while queued".to_owned(),
+        )));
+    }
+    live
+}
+
+async fn execute_batch(backend: Arc<dyn InferenceBackend>, batch: Vec<BatchItem>) {
+    if let Some(first) = batch.first() {
         debug!(
             batch_size = batch.len(),
-            model = %class.model,
-            max_tokens = ?class.max_tokens,
+            model = %first.class.model,
+            max_tokens = ?first.class.max_tokens,
             "flushing micro-batch"
         );
+    }
 
-        // Adapter boundary supports per-request execution today; real providers can replace this
-        // with a true batched call while preserving scheduler behavior.
-        for item in batch {
-            let result = backend.execute_chat(item.request).await;
-            let _ = item.response_tx.send(result);
-        }
+    let (requests, senders): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|item| (item.request, item.response_tx))
+        .unzip();
+    let mut results = backend.execute_chat_batch(requests).await.into_iter();
+    for response_tx in senders {
+        let result = results.next().unwrap_or_else(|| {
+            Err(BackendError::InvalidResponse(
+                "batched call returned fewer results than requests".to_owned(),
+            ))
+        });
+        let _ = response_tx.send(result);
     }
 }
 
@@ -200,3 +539,360 @@ fn format_float(value: Option<f32>) -> String {
         .map(|number| format!("{number:.4}"))
         .unwrap_or_else(|| "none".to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+
+    use crate::{
+        backend::{mock::MockBackend, BackendError, BackendStream, InferenceBackend},
+        metrics::AppMetrics,
+        models::{
+            BackendChatResponse, ClientTags, GenerationParams, MessageRole, NormalizedChatRequest,
+            NormalizedMessage, Priority,
+        },
+    };
+
+    use super::{BatchClass, BatchConfig, BatchItem, Batcher, ReadyQueue};
+
+    #[derive(Default)]
+    struct RecordingBackend {
+        inner: MockBackend,
+        batch_sizes: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl InferenceBackend for RecordingBackend {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn execute_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            self.inner.execute_chat(request).await
+        }
+
+        async fn stream_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            self.inner.stream_chat(request).await
+        }
+
+        async fn execute_chat_batch(
+            &self,
+            requests: Vec<Arc<NormalizedChatRequest>>,
+        ) -> Vec<Result<BackendChatResponse, BackendError>> {
+            self.batch_sizes.lock().unwrap().push(requests.len());
+            if requests.iter().any(|request| request.model == "slow-model") {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            let mut results = Vec::new();
+            for request in requests {
+                results.push(self.inner.execute_chat(request).await);
+            }
+            results
+        }
+    }
+
+    fn test_config() -> BatchConfig {
+        BatchConfig {
+            enabled: true,
+            max_batch_size: 8,
+            max_wait: Duration::from_millis(10),
+            workers: 1,
+            queue_capacity: 64,
+            retry_after: Duration::from_secs(1),
+            stream_concurrency: 4,
+            stream_queue_capacity: 4,
+            stream_queue_timeout: Duration::from_millis(100),
+        }
+    }
+
+    fn request(prompt: &str) -> Arc<NormalizedChatRequest> {
+        request_for("gpt-test", prompt)
+    }
+
+    fn request_for(model: &str, prompt: &str) -> Arc<NormalizedChatRequest> {
+        Arc::new(NormalizedChatRequest {
+            request_id: format!("req_{prompt}"),
+            user_id: "user".to_owned(),
+            model: model.to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: prompt.to_owned(),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens: Some(16),
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn worker_issues_a_single_batched_call_per_flush() {
+        let backend = Arc::new(RecordingBackend::default());
+        let metrics = Arc::new(AppMetrics::new());
+        let batcher = Batcher::new(
+            backend.clone(),
+            BatchConfig {
+                max_batch_size: 3,
+                max_wait: Duration::from_millis(200),
+                ..test_config()
+            },
+            metrics.clone(),
+        );
+
+        let started = std::time::Instant::now();
+        let (a, b, c) = tokio::join!(
+            batcher.execute_chat(request("a")),
+            batcher.execute_chat(request("b")),
+            batcher.execute_chat(request("c")),
+        );
+
+        assert!(a.expect("a").content.ends_with(": a"));
+        assert!(b.expect("b").content.ends_with(": b"));
+        assert!(c.expect("c").content.ends_with(": c"));
+        assert_eq!(*backend.batch_sizes.lock().unwrap(), vec![3]);
+        // A full batch goes out as soon as it fills, not when the wait window closes.
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_batch_flushes_total{reason=\"size\"} 1"));
+        assert!(rendered.contains("gateway_batch_size_sum{reason=\"size\"} 3"));
+        assert!(rendered.contains("gateway_batch_queue_depth 0"));
+    }
+
+    #[tokio::test]
+    async fn partial_batches_flush_per_class_once_max_wait_elapses() {
+        let backend = Arc::new(RecordingBackend::default());
+        let metrics = Arc::new(AppMetrics::new());
+        let batcher = Batcher::new(
+            backend.clone(),
+            BatchConfig {
+                max_wait: Duration::from_millis(100),
+                ..test_config()
+            },
+            metrics.clone(),
+        );
+
+        let started = std::time::Instant::now();
+        let (a, b, other) = tokio::join!(
+            batcher.execute_chat(request("a")),
+            batcher.execute_chat(request("b")),
+            batcher.execute_chat(request_for("other-model", "o")),
+        );
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        assert!(a.expect("a").content.ends_with(": a"));
+        assert!(b.expect("b").content.ends_with(": b"));
+        assert!(other.expect("other").content.ends_with(": o"));
+        let mut sizes = backend.batch_sizes.lock().unwrap().clone();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![1, 2]);
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_batch_flushes_total{reason=\"deadline\"} 2"));
+        assert!(rendered.contains("gateway_batch_size_sum{reason=\"deadline\"} 3"));
+    }
+
+    #[tokio::test]
+    async fn slow_class_does_not_block_other_classes() {
+        let backend = Arc::new(RecordingBackend::default());
+        let batcher = Batcher::new(
+            backend.clone(),
+            BatchConfig {
+                workers: 2,
+                ..test_config()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+
+        let slow = {
+            let batcher = batcher.clone();
+            tokio::spawn(async move { batcher.execute_chat(request_for("slow-model", "s")).await })
+        };
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let started = std::time::Instant::now();
+        batcher
+            .execute_chat(request_for("fast-model", "f"))
+            .await
+            .expect("fast request");
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        slow.await.expect("join").expect("slow request");
+        assert_eq!(*backend.batch_sizes.lock().unwrap(), vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn expired_items_fail_without_reaching_the_backend() {
+        let backend = Arc::new(RecordingBackend::default());
+        let batcher = Batcher::new(backend.clone(), test_config(), Arc::new(AppMetrics::new()));
+
+        let slow = {
+            let batcher = batcher.clone();
+            tokio::spawn(async move { batcher.execute_chat(request_for("slow-model", "s")).await })
+        };
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let mut impatient = request_for("fast-model", "f");
+        Arc::make_mut(&mut impatient).deadline =
+            Some(std::time::Instant::now() + Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        let error = batcher
+            .execute_chat(impatient)
+            .await
+            .expect_err("deadline should pass behind the slow batch");
+        assert!(matches!(error, BackendError::DeadlineExceeded(_)));
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        slow.await.expect("join").expect("slow request");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(*backend.batch_sizes.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn high_priority_item_flushes_without_waiting() {
+        let backend = Arc::new(RecordingBackend::default());
+        let batcher = Batcher::new(
+            backend.clone(),
+            BatchConfig {
+                max_wait: Duration::from_millis(500),
+                ..test_config()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+
+        let mut urgent = request("urgent");
+        Arc::make_mut(&mut urgent).priority = Priority::High;
+        let started = std::time::Instant::now();
+        batcher.execute_chat(urgent).await.expect("urgent request");
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert_eq!(*backend.batch_sizes.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn ready_queue_pops_highest_priority_first() {
+        let queue = ReadyQueue::default();
+        for (prompt, priority) in [
+            ("low", Priority::Low),
+            ("normal-1", Priority::Normal),
+            ("high", Priority::High),
+            ("normal-2", Priority::Normal),
+        ] {
+            let mut request = request(prompt);
+            Arc::make_mut(&mut request).priority = priority;
+            let (response_tx, _) = tokio::sync::oneshot::channel();
+            queue.push(vec![BatchItem {
+                class: BatchClass::from_request(&request),
+                priority,
+                enqueued_at: std::time::Instant::now(),
+                request,
+                response_tx,
+            }]);
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            let batch = queue.pop().await;
+            order.push(batch[0].request.request_id.clone());
+        }
+        assert_eq!(
+            order,
+            vec!["req_high", "req_normal-1", "req_normal-2", "req_low"]
+        );
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_beyond_queue_capacity() {
+        let backend = Arc::new(RecordingBackend::default());
+        let metrics = Arc::new(AppMetrics::new());
+        let batcher = Batcher::new(
+            backend,
+            BatchConfig {
+                max_wait: Duration::from_millis(100),
+                queue_capacity: 1,
+                retry_after: Duration::from_secs(3),
+                ..test_config()
+            },
+            metrics.clone(),
+        );
+
+        let (first, second) = tokio::join!(
+            batcher.execute_chat(request("a")),
+            batcher.execute_chat(request("b")),
+        );
+
+        assert!(first.is_ok());
+        match second {
+            Err(BackendError::Overloaded {
+                retry_after_secs, ..
+            }) => assert_eq!(retry_after_secs, 3),
+            other => panic!("expected overload, got {other:?}"),
+        }
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_load_shed_total{stage=\"batcher\"} 1"));
+
+        batcher
+            .execute_chat(request("c"))
+            .await
+            .expect("slot is released after execution");
+    }
+
+    #[tokio::test]
+    async fn stream_admission_queues_then_sheds() {
+        let metrics = Arc::new(AppMetrics::new());
+        let batcher = Batcher::new(
+            Arc::new(RecordingBackend::default()),
+            BatchConfig {
+                stream_concurrency: 1,
+                stream_queue_capacity: 1,
+                stream_queue_timeout: Duration::from_millis(50),
+                ..test_config()
+            },
+            metrics.clone(),
+        );
+
+        let mut streaming = request("stream");
+        Arc::make_mut(&mut streaming).stream = true;
+        let held = batcher
+            .stream_chat(streaming.clone())
+            .await
+            .expect("first stream admitted");
+
+        let (queued, shed) = tokio::join!(
+            batcher.stream_chat(streaming.clone()),
+            batcher.stream_chat(streaming.clone()),
+        );
+        assert!(matches!(queued, Err(BackendError::Overloaded { .. })));
+        assert!(matches!(shed, Err(BackendError::Overloaded { .. })));
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_load_shed_total{stage=\"stream\"} 2"));
+        assert!(rendered.contains("gateway_pool_in_use{pool=\"stream\"} 1"));
+        assert!(rendered.contains("gateway_pool_capacity{pool=\"stream\"} 1"));
+
+        drop(held);
+        let mut resumed = batcher
+            .stream_chat(streaming)
+            .await
+            .expect("slot is released when the stream is dropped");
+        assert!(resumed.next().await.is_some());
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    collections::BTreeMap,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+/// Drives concurrent chat traffic at a running gateway and reports latency percentiles, time
+/// to first token, throughput, and error rates.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Gateway base URL.
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    url: String,
+    #[arg(long, env = "GATEWAY_BENCH_API_KEY", default_value = "dev-key")]
+    api_key: String,
+    #[arg(long, default_value = "mock-1")]
+    model: String,
+    /// Requests in flight at once.
+    #[arg(long, default_value_t = 16)]
+    concurrency: usize,
+    /// Requests to send in total.
+    #[arg(long, default_value_t = 200)]
+    requests: usize,
+    /// Stop sending after this many seconds even if `--requests` are not all sent.
+    #[arg(long, value_name = "SECS")]
+    duration_secs: Option<u64>,
+    /// Fraction of requests sent with `stream: true`, from 0 to 1.
+    #[arg(long, default_value_t = 0.0)]
+    stream_fraction: f64,
+    #[arg(long, default_value = "Write a haiku about load testing.")]
+    prompt: String,
+    /// Appends the request number to every prompt so no two requests share a fingerprint.
+    #[arg(long)]
+    unique_prompts: bool,
+    /// Lets the gateway serve and store cached responses instead of sending `no-store`.
+    #[arg(long)]
+    allow_cache: bool,
+    #[arg(long)]
+    max_tokens: Option<u32>,
+    /// Per-request timeout.
+    #[arg(long, default_value_t = 60)]
+    timeout_secs: u64,
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+/// What one request produced.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    stream: bool,
+    /// HTTP status, or `None` when the request failed before a response.
+    status: Option<u16>,
+    /// A streamed response that ended in an `error` event or without `[DONE]`.
+    stream_failed: bool,
+    latency: Duration,
+    ttft: Option<Duration>,
+    completion_tokens: u64,
+}
+
+impl Sample {
+    fn ok(&self) -> bool {
+        self.status
+            .is_some_and(|status| (200..300).contains(&status))
+            && !self.stream_failed
+    }
+
+    fn outcome(&self) -> String {
+        match self.status {
+            Some(status) if self.stream_failed => format!("{status} stream_error"),
+            Some(status) => status.to_string(),
+            None => "transport_error".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Percentiles {
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+impl Percentiles {
+    fn of(mut durations: Vec<Duration>) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+        let rank = |quantile: f64| {
+            let index = ((quantile * durations.len() as f64).ceil() as usize).max(1) - 1;
+            durations[index.min(durations.len() - 1)].as_secs_f64() * 1000.0
+        };
+        Some(Self {
+            p50_ms: rank(0.50),
+            p90_ms: rank(0.90),
+            p99_ms: rank(0.99),
+            max_ms: rank(1.0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Report {
+    requests: usize,
+    streamed: usize,
+    succeeded: usize,
+    error_rate: f64,
+    /// Requests by status code (or `transport_error` / `<status> stream_error`).
+    outcomes: BTreeMap<String, usize>,
+    elapsed_secs: f64,
+    requests_per_sec: f64,
+    /// Completion tokens from one-shot usage plus one per streamed content chunk.
+    completion_tokens_per_sec: f64,
+    latency: Option<Percentiles>,
+    /// Successful streams only.
+    ttft: Option<Percentiles>,
+}
+
+impl Report {
+    fn build(samples: &[Sample], elapsed: Duration) -> Self {
+        let mut outcomes = BTreeMap::new();
+        for sample in samples {
+            *outcomes.entry(sample.outcome()).or_insert(0) += 1;
+        }
+        let succeeded = samples.iter().filter(|sample| sample.ok()).count();
+        let elapsed_secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let tokens: u64 = samples.iter().map(|sample| sample.completion_tokens).sum();
+        Self {
+            requests: samples.len(),
+            streamed: samples.iter().filter(|sample| sample.stream).count(),
+            succeeded,
+            error_rate: if samples.is_empty() {
+                0.0
+            } else {
+                (samples.len() - succeeded) as f64 / samples.len() as f64
+            },
+            outcomes,
+            elapsed_secs,
+            requests_per_sec: samples.len() as f64 / elapsed_secs,
+            completion_tokens_per_sec: tokens as f64 / elapsed_secs,
+            latency: Percentiles::of(samples.iter().map(|sample| sample.latency).collect()),
+            ttft: Percentiles::of(
+                samples
+                    .iter()
+                    .filter(|sample| sample.ok())
+                    .filter_map(|sample| sample.ttft)
+                    .collect(),
+            ),
+        }
+    }
+
+    fn print_text(&self) {
+        println!(
+            "requests: {} ({} streamed, {} ok, error rate {:.2}%)",
+            self.requests,
+            self.streamed,
+            self.succeeded,
+            self.error_rate * 100.0
+        );
+        for (outcome, count) in &self.outcomes {
+            println!("  {outcome}: {count}");
+        }
+        println!(
+            "throughput: {:.1} req/s, {:.1} completion tokens/s over {:.2}s",
+            self.requests_per_sec, self.completion_tokens_per_sec, self.elapsed_secs
+        );
+        for (label, percentiles) in [("latency", &self.latency), ("ttft", &self.ttft)] {
+            if let Some(p) = percentiles {
+                println!(
+                    "{label} ms: p50 {:.1}, p90 {:.1}, p99 {:.1}, max {:.1}",
+                    p.p50_ms, p.p90_ms, p.p99_ms, p.max_ms
+                );
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.concurrency == 0 || !(0.0..=1.0).contains(&cli.stream_fraction) {
+        eprintln!("error: --concurrency must be positive and --stream-fraction within 0..=1");
+        return ExitCode::FAILURE;
+    }
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(cli.timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("error: failed to build HTTP client: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let cli = Arc::new(cli);
+    let next = Arc::new(AtomicUsize::new(0));
+    let samples = Arc::new(Mutex::new(Vec::with_capacity(cli.requests)));
+    let started = Instant::now();
+    let stop_at = cli
+        .duration_secs
+        .map(|secs| started + Duration::from_secs(secs));
+    let workers = (0..cli.concurrency.min(cli.requests.max(1)))
+        .map(|_| {
+            let (cli, client, next, samples) =
+                (cli.clone(), client.clone(), next.clone(), samples.clone());
+            tokio::spawn(async move {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= cli.requests || stop_at.is_some_and(|stop| Instant::now() >= stop) {
+                        return;
+                    }
+                    let sample = send(&client, &cli, index).await;
+                    samples.lock().await.push(sample);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        let _ = worker.await;
+    }
+
+    let report = Report::build(&samples.lock().await, started.elapsed());
+    if cli.json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{json}"),
+            Err(error) => {
+                eprintln!("error: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        report.print_text();
+    }
+    ExitCode::SUCCESS
+}
+
+/// Whether request `index` streams; spreads streamed requests evenly through the run.
+fn streams(index: usize, fraction: f64) -> bool {
+    ((index + 1) as f64 * fraction).floor() > (index as f64 * fraction).floor()
+}
+
+async fn send(client: &reqwest::Client, cli: &Cli, index: usize) -> Sample {
+    let stream = streams(index, cli.stream_fraction);
+    let prompt = if cli.unique_prompts {
+        format!("{} (#{index})", cli.prompt)
+    } else {
+        cli.prompt.clone()
+    };
+    let mut body = serde_json::json!({
+        "model": cli.model,
+        "messages": [{"role": "user", "content": prompt}],
+        "stream": stream,
+    });
+    if let Some(max_tokens) = cli.max_tokens {
+        body["max_tokens"] = max_tokens.into();
+    }
+    let mut request = client
+        .post(format!(
+            "{}/v1/chat/completions",
+            cli.url.trim_end_matches('/')
+        ))
+        .header("x-api-key", &cli.api_key)
+        .json(&body);
+    if !cli.allow_cache {
+        request = request.header("cache-control", "no-store");
+    }
+
+    let started = Instant::now();
+    let mut sample = Sample {
+        stream,
+        status: None,
+        stream_failed: false,
+        latency: Duration::ZERO,
+        ttft: None,
+        completion_tokens: 0,
+    };
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(_) => {
+            sample.latency = started.elapsed();
+            return sample;
+        }
+    };
+    sample.status = Some(response.status().as_u16());
+    if !response.status().is_success() {
+        let _ = response.bytes().await;
+    } else if stream {
+        read_stream(response, started, &mut sample).await;
+    } else if let Ok(payload) = response.json::<serde_json::Value>().await {
+        sample.completion_tokens = payload["usage"]["completion_tokens"]
+            .as_u64()
+            .unwrap_or_default();
+    }
+    sample.latency = started.elapsed();
+    sample
+}
+
+/// Reads SSE events until `[DONE]`, noting when the first content delta arrived.
+async fn read_stream(response: reqwest::Response, started: Instant, sample: &mut Sample) {
+    let mut body = response.bytes_stream();
+    let mut buffer = String::new();
+    let mut saw_done = false;
+    while let Some(next) = body.next().await {
+        let Ok(bytes) = next else {
+            break;
+        };
+        buffer.push_str(&String::from_utf8_lossy(&bytes));
+        while let Some(index) = buffer.find('\n') {
+            let line = buffer[..index].trim().to_owned();
+            buffer.drain(..=index);
+            match parse_event(&line) {
+                Some(StreamEvent::Delta) => {
+                    sample.ttft.get_or_insert_with(|| started.elapsed());
+                    sample.completion_tokens += 1;
+                }
+                Some(StreamEvent::Error) => sample.stream_failed = true,
+                Some(StreamEvent::Done) => saw_done = true,
+                None => {}
+            }
+        }
+    }
+    sample.stream_failed |= !saw_done;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum StreamEvent {
+    Delta,
+    Error,
+    Done,
+}
+
+fn parse_event(line: &str) -> Option<StreamEvent> {
+    let payload = line.strip_prefix("data:")?.trim();
+    if payload == "[DONE]" {
+        return Some(StreamEvent::Done);
+    }
+    let event: serde_json::Value = serde_json::from_str(payload).ok()?;
+    if event.get("error").is_some() {
+        return Some(StreamEvent::Error);
+    }
+    event["choices"][0]["delta"]["content"]
+        .as_str()
+        .filter(|content| !content.is_empty())
+        .map(|_| StreamEvent::Delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_event, streams, Percentiles, Report, Sample, StreamEvent};
+
+    fn sample(status: Option<u16>, latency_ms: u64) -> Sample {
+        Sample {
+            stream: false,
+            status,
+            stream_failed: false,
+            latency: Duration::from_millis(latency_ms),
+            ttft: None,
+            completion_tokens: 10,
+        }
+    }
+
+    #[test]
+    fn reports_nearest_rank_percentiles_and_error_rates() {
+        let p = Percentiles::of((1..=100).map(Duration::from_millis).collect()).expect("samples");
+        assert_eq!(
+            (p.p50_ms, p.p90_ms, p.p99_ms, p.max_ms),
+            (50.0, 90.0, 99.0, 100.0)
+        );
+        assert!(Percentiles::of(Vec::new()).is_none());
+
+        let samples = [
+            sample(Some(200), 10),
+            sample(Some(200), 20),
+            sample(Some(429), 1),
+            sample(None, 5),
+        ];
+        let report = Report::build(&samples, Duration::from_secs(2));
+        assert_eq!(report.succeeded, 2);
+        assert_eq!(report.error_rate, 0.5);
+        assert_eq!(report.outcomes["429"], 1);
+        assert_eq!(report.outcomes["transport_error"], 1);
+        assert_eq!(report.requests_per_sec, 2.0);
+        assert_eq!(report.completion_tokens_per_sec, 20.0);
+    }
+
+    #[test]
+    fn classifies_sse_lines_and_spreads_streamed_requests() {
+        let delta = r#"data: {"choices":[{"index":0,"delta":{"content":"hi"}}]}"#;
+        assert_eq!(parse_event(delta), Some(StreamEvent::Delta));
+        let role = r#"data: {"choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(parse_event(role), None);
+        let error = r#"data: {"error":{"message":"boom","type":"backend_error"}}"#;
+        assert_eq!(parse_event(error), Some(StreamEvent::Error));
+        assert_eq!(parse_event("data: [DONE]"), Some(StreamEvent::Done));
+        assert_eq!(parse_event(": keep-alive"), None);
+
+        let streamed = (0..100).filter(|index| streams(*index, 0.25)).count();
+        assert_eq!(streamed, 25);
+        assert!((0..10).all(|index| !streams(index, 0.0)));
+        assert!((0..10).all(|index| streams(index, 1.0)));
+    }
+}
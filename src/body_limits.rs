@@ -0,0 +1,142 @@
+use std::env;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::header::CONTENT_LENGTH,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{errors::AppError, request_id::RequestId, state::AppState};
+
+/// Size and nesting limits applied to chat request bodies before they are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    pub max_body_bytes: usize,
+    /// Deepest allowed nesting of JSON objects and arrays.
+    pub max_json_depth: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 4 * 1024 * 1024,
+            max_json_depth: 32,
+        }
+    }
+}
+
+impl BodyLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_body_bytes: read_usize("GATEWAY_MAX_BODY_BYTES", defaults.max_body_bytes).max(1),
+            max_json_depth: read_usize("GATEWAY_MAX_JSON_DEPTH", defaults.max_json_depth).max(1),
+        }
+    }
+}
+
+/// Middleware that buffers the body within `max_body_bytes` and refuses bodies nested deeper
+/// than `max_json_depth`, answering with OpenAI-style `413`/`400` errors.
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let request_id = request.extensions().get::<RequestId>().cloned();
+    match check(&state, request).await {
+        Ok(request) => next.run(request).await,
+        Err((reason, error)) => {
+            state.metrics.observe_request_rejection(reason);
+            match request_id {
+                Some(request_id) => error.into_response_for(request_id.as_str()),
+                None => axum::response::IntoResponse::into_response(error),
+            }
+        }
+    }
+}
+
+async fn check(state: &AppState, request: Request) -> Result<Request, (&'static str, AppError)> {
+    let limits = *state.body_limits;
+    let too_large = || {
+        (
+            "body_too_large",
+            AppError::PayloadTooLarge(format!(
+                "request body exceeds the {} byte limit",
+                limits.max_body_bytes
+            )),
+        )
+    };
+    // A declared length over the limit is refused without reading the body.
+    let declared = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limits.max_body_bytes) {
+        return Err(too_large());
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, limits.max_body_bytes)
+        .await
+        .map_err(|_| too_large())?;
+    if exceeds_depth(&bytes, limits.max_json_depth) {
+        return Err((
+            "json_too_deep",
+            AppError::BadRequest(format!(
+                "request JSON is nested deeper than {} levels",
+                limits.max_json_depth
+            )),
+        ));
+    }
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+/// Whether objects and arrays in `json` nest deeper than `max_depth`. Only brackets outside
+/// string literals count; malformed input is left for the JSON parser to reject.
+fn exceeds_depth(json: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+fn read_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exceeds_depth;
+
+    #[test]
+    fn counts_nesting_outside_string_literals() {
+        assert!(!exceeds_depth(br#"{"messages":[{"content":"hi"}]}"#, 3));
+        assert!(exceeds_depth(br#"{"messages":[{"content":"hi"}]}"#, 2));
+        assert!(!exceeds_depth(br#"{"content":"[[[[{{{{ \" [[[["}"#, 1));
+        assert!(exceeds_depth("[".repeat(100).as_bytes(), 32));
+    }
+}
@@ -0,0 +1,488 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::Router;
+use tracing::info;
+
+use crate::{
+    access_log::AccessLog,
+    admin::AdminConfig,
+    admission::{AdmissionConfig, AdmissionController},
+    auth::{ApiKeyRegistry, KeyStore, RatePolicy},
+    backend::{peer::PeerTrust, InferenceBackend},
+    batcher::{BatchConfig, Batcher},
+    body_limits::BodyLimits,
+    build_app,
+    cache::{CacheConfig, CacheStore, ResponseCache},
+    catalog::ModelCatalog,
+    clock::{Clock, SystemClock},
+    coalescing::{CoalescerConfig, InflightCoalescer},
+    context_window::ContextLimits,
+    credits::CreditLedger,
+    egress::EgressConfig,
+    error_reporting::ErrorReporter,
+    experiments::Experiments,
+    fair_queue::{FairQueue, FairQueueConfig},
+    forwarded_headers::ForwardedHeaders,
+    limits::{LimitStore, QuotaDay, RateLimiter},
+    metrics::{AppMetrics, MetricsConfig},
+    model_pools::{ModelPoolConfig, ModelPools},
+    postprocess::{PostProcessors, ResponseProcessor},
+    pricing::PricingTable,
+    quotas::QuotaOverrides,
+    redis_pool::RedisPool,
+    router::{BackendRouter, EndpointHealthConfig, EndpointPoolConfig, RetryPolicy},
+    state::AppState,
+    streaming::StreamingConfig,
+    timeouts::TimeoutConfig,
+    traces::TraceExporter,
+    truncation::TruncationConfig,
+    usage_sink::UsageSink,
+};
+
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Assembles the gateway for services that embed it instead of running the binary.
+///
+/// [`GatewayBuilder::new`] starts from code defaults and reads no environment: the cache and
+/// rate limiter stay in memory, and the access log, usage sink, error reporter, trace export,
+/// and prepaid credits are off.
+/// [`GatewayBuilder::from_env`] starts from the same settings the binary uses.
+pub struct GatewayBuilder {
+    from_env: bool,
+    backends: Vec<Arc<dyn InferenceBackend>>,
+    key_store: Arc<dyn KeyStore>,
+    limit_store: Option<Arc<dyn LimitStore>>,
+    quota_day: QuotaDay,
+    cache_store: Option<Arc<dyn CacheStore>>,
+    metrics: Option<Arc<AppMetrics>>,
+    batch: BatchConfig,
+    cache: CacheConfig,
+    coalescer: CoalescerConfig,
+    fair_queue: FairQueueConfig,
+    admission: AdmissionConfig,
+    model_pools: ModelPoolConfig,
+    admin: AdminConfig,
+    body_limits: BodyLimits,
+    context_limits: ContextLimits,
+    truncation: TruncationConfig,
+    experiments: Experiments,
+    peer_trust: PeerTrust,
+    pricing: PricingTable,
+    catalog: ModelCatalog,
+    post_processors: PostProcessors,
+    timeouts: TimeoutConfig,
+    streaming: StreamingConfig,
+    forwarded_headers: ForwardedHeaders,
+    retry: RetryPolicy,
+    endpoint_pools: EndpointPoolConfig,
+    egress: EgressConfig,
+    endpoint_health: EndpointHealthConfig,
+    health_check_interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for GatewayBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GatewayBuilder {
+    /// A builder with no backends and a key store that accepts no keys.
+    pub fn new() -> Self {
+        Self {
+            from_env: false,
+            backends: Vec::new(),
+            key_store: Arc::new(ApiKeyRegistry::new(
+                Vec::<String>::new(),
+                RatePolicy::default(),
+            )),
+            limit_store: None,
+            quota_day: QuotaDay::default(),
+            cache_store: None,
+            metrics: None,
+            batch: BatchConfig::default(),
+            cache: CacheConfig::default(),
+            coalescer: CoalescerConfig::default(),
+            fair_queue: FairQueueConfig::default(),
+            admission: AdmissionConfig::default(),
+            model_pools: ModelPoolConfig::default(),
+            admin: AdminConfig::default(),
+            body_limits: BodyLimits::default(),
+            context_limits: ContextLimits::default(),
+            truncation: TruncationConfig::default(),
+            experiments: Experiments::default(),
+            peer_trust: PeerTrust::disabled(),
+            pricing: PricingTable::default(),
+            catalog: ModelCatalog::default(),
+            post_processors: PostProcessors::default(),
+            timeouts: TimeoutConfig::default(),
+            streaming: StreamingConfig::default(),
+            forwarded_headers: ForwardedHeaders::default(),
+            retry: RetryPolicy::default(),
+            endpoint_pools: EndpointPoolConfig::default(),
+            egress: EgressConfig::default(),
+            endpoint_health: EndpointHealthConfig::default(),
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// A builder configured from the `GATEWAY_*` environment, as the binary runs. Backends are
+    /// still registered with [`GatewayBuilder::backend`].
+    pub fn from_env() -> Self {
+        Self {
+            from_env: true,
+            backends: Vec::new(),
+            key_store: Arc::new(ApiKeyRegistry::from_env()),
+            limit_store: None,
+            quota_day: QuotaDay::from_env(),
+            cache_store: None,
+            metrics: None,
+            batch: BatchConfig::from_env(),
+            cache: CacheConfig::from_env(),
+            coalescer: CoalescerConfig::from_env(),
+            fair_queue: FairQueueConfig::from_env(),
+            admission: AdmissionConfig::from_env(),
+            model_pools: ModelPoolConfig::from_env(),
+            admin: AdminConfig::from_env(),
+            body_limits: BodyLimits::from_env(),
+            context_limits: ContextLimits::from_env(),
+            truncation: TruncationConfig::from_env(),
+            experiments: Experiments::from_env(),
+            peer_trust: PeerTrust::from_env(),
+            pricing: PricingTable::from_env(),
+            catalog: ModelCatalog::from_env(),
+            post_processors: PostProcessors::from_env(),
+            timeouts: TimeoutConfig::from_env(),
+            streaming: StreamingConfig::from_env(),
+            forwarded_headers: ForwardedHeaders::from_env(),
+            retry: RetryPolicy::from_env(),
+            endpoint_pools: EndpointPoolConfig::from_env(),
+            egress: EgressConfig::from_env(),
+            endpoint_health: EndpointHealthConfig::from_env(),
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// Adds an endpoint to the routing pool; requests round-robin across healthy endpoints.
+    pub fn backend(mut self, backend: Arc<dyn InferenceBackend>) -> Self {
+        self.backends.push(backend);
+        self
+    }
+
+    pub fn key_store(mut self, key_store: Arc<dyn KeyStore>) -> Self {
+        self.key_store = key_store;
+        self
+    }
+
+    /// Counts rate-limit windows in `store` instead of memory or `REDIS_URL`.
+    pub fn limit_store(mut self, store: Arc<dyn LimitStore>) -> Self {
+        self.limit_store = Some(store);
+        self
+    }
+
+    /// Rolls daily token quotas over at `quota_day`'s boundary instead of midnight UTC.
+    pub fn quota_day(mut self, quota_day: QuotaDay) -> Self {
+        self.quota_day = quota_day;
+        self
+    }
+
+    /// Keeps cached responses in `store` instead of memory, disk, or `REDIS_URL`.
+    pub fn cache_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.cache_store = Some(store);
+        self
+    }
+
+    /// Shares a registry the embedding service already exposes.
+    pub fn metrics(mut self, metrics: Arc<AppMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn batch_config(mut self, config: BatchConfig) -> Self {
+        self.batch = config;
+        self
+    }
+
+    pub fn cache_config(mut self, config: CacheConfig) -> Self {
+        self.cache = config;
+        self
+    }
+
+    pub fn coalescer_config(mut self, config: CoalescerConfig) -> Self {
+        self.coalescer = config;
+        self
+    }
+
+    pub fn fair_queue_config(mut self, config: FairQueueConfig) -> Self {
+        self.fair_queue = config;
+        self
+    }
+
+    pub fn admission_config(mut self, config: AdmissionConfig) -> Self {
+        self.admission = config;
+        self
+    }
+
+    pub fn model_pool_config(mut self, config: ModelPoolConfig) -> Self {
+        self.model_pools = config;
+        self
+    }
+
+    pub fn admin_config(mut self, config: AdminConfig) -> Self {
+        self.admin = config;
+        self
+    }
+
+    pub fn body_limits(mut self, limits: BodyLimits) -> Self {
+        self.body_limits = limits;
+        self
+    }
+
+    pub fn context_limits(mut self, limits: ContextLimits) -> Self {
+        self.context_limits = limits;
+        self
+    }
+
+    /// How conversations too long for their model's context window are shortened.
+    pub fn truncation(mut self, truncation: TruncationConfig) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    pub fn experiments(mut self, experiments: Experiments) -> Self {
+        self.experiments = experiments;
+        self
+    }
+
+    /// Which peer gateways may forward requests on behalf of their own callers.
+    pub fn peer_trust(mut self, trust: PeerTrust) -> Self {
+        self.peer_trust = trust;
+        self
+    }
+
+    pub fn pricing(mut self, pricing: PricingTable) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// The models clients may call. Its context windows and prices take precedence over the
+    /// glob rules of [`context_limits`](Self::context_limits) and [`pricing`](Self::pricing).
+    pub fn catalog(mut self, catalog: ModelCatalog) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Registers a response post-processor that key policies can name in `post_process`.
+    pub fn post_processor(
+        mut self,
+        name: impl Into<String>,
+        processor: Arc<dyn ResponseProcessor>,
+    ) -> Self {
+        self.post_processors = self.post_processors.with_processor(name, processor);
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn streaming(mut self, streaming: StreamingConfig) -> Self {
+        self.streaming = streaming;
+        self
+    }
+
+    /// Upstream response headers passed on to clients; none are by default.
+    pub fn forwarded_headers(mut self, forwarded_headers: ForwardedHeaders) -> Self {
+        self.forwarded_headers = forwarded_headers;
+        self
+    }
+
+    /// Retries for transient failures of one-shot calls across the registered backends.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Per-backend concurrency caps and the spillover backends that take overflow from them.
+    pub fn endpoint_pool_config(mut self, config: EndpointPoolConfig) -> Self {
+        self.endpoint_pools = config;
+        self
+    }
+
+    /// Request and token rates each backend's calls are paced to, whatever the client demand.
+    pub fn egress_config(mut self, config: EgressConfig) -> Self {
+        self.egress = config;
+        self
+    }
+
+    /// Health-check history kept per backend, and the successes in a row that bring a backend
+    /// whose circuit opened fully back into rotation.
+    pub fn endpoint_health_config(mut self, config: EndpointHealthConfig) -> Self {
+        self.endpoint_health = config;
+        self
+    }
+
+    /// Never serves or stores cached responses.
+    pub fn disable_cache(mut self) -> Self {
+        self.cache = CacheConfig::disabled();
+        self
+    }
+
+    /// Sends every one-shot request to the backend on its own instead of micro-batching.
+    pub fn disable_batching(mut self) -> Self {
+        self.batch.enabled = false;
+        self
+    }
+
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// Time source for rate-limit windows, cache expiry, and circuit-breaker cooldowns; tests
+    /// pass a [`crate::clock::ManualClock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Builds the shared state and starts its background tasks (cache sweeping, coalescer
+    /// cleanup, backend and Redis health checks), so it must run inside a Tokio runtime.
+    pub fn build_state(mut self) -> Result<AppState, String> {
+        if self.backends.is_empty() {
+            return Err("at least one backend must be registered".to_owned());
+        }
+        let metrics = self
+            .metrics
+            .get_or_insert_with(|| Arc::new(default_metrics(self.from_env)))
+            .clone();
+        let backends = std::mem::take(&mut self.backends)
+            .into_iter()
+            .map(|backend| self.egress.gate(backend, metrics.clone()))
+            .collect::<Vec<_>>();
+        let backend_names = backends
+            .iter()
+            .map(|backend| backend.name().to_owned())
+            .collect::<Vec<_>>()
+            .join(",");
+        let router = Arc::new(
+            BackendRouter::new(backends)
+                .with_metrics(metrics.clone())
+                .with_retry_policy(self.retry.clone())
+                .with_endpoint_pools(&self.endpoint_pools)
+                .with_health_config(self.endpoint_health.clone())
+                .with_clock(self.clock.clone()),
+        );
+        router
+            .clone()
+            .spawn_health_checks(self.health_check_interval);
+        info!(backend = router.name(), endpoints = %backend_names, "backend router configured");
+        Ok(self.assemble(router))
+    }
+
+    /// Builds the data-plane router, with admin endpoints merged in unless the admin config
+    /// gives them their own listener.
+    pub fn build(self) -> Result<Router, String> {
+        self.build_state().map(build_app)
+    }
+
+    /// Wires every subsystem around `backend`, which is used as-is rather than pooled.
+    pub(crate) fn assemble(self, backend: Arc<dyn InferenceBackend>) -> AppState {
+        let metrics = self
+            .metrics
+            .unwrap_or_else(|| Arc::new(default_metrics(self.from_env)));
+        let batcher = Arc::new(Batcher::new(backend.clone(), self.batch, metrics.clone()));
+        let redis = if self.from_env {
+            RedisPool::from_env(metrics.clone())
+        } else {
+            None
+        };
+        if let Some(pool) = &redis {
+            pool.clone().spawn_health_checks(self.health_check_interval);
+        }
+        let response_cache = Arc::new(
+            match self.cache_store {
+                Some(store) => ResponseCache::with_store(self.cache, metrics.clone(), store),
+                None if self.from_env => {
+                    ResponseCache::from_env(self.cache, metrics.clone(), redis.clone())
+                }
+                None => ResponseCache::memory(self.cache, metrics.clone()),
+            }
+            .with_clock(self.clock.clone()),
+        );
+        response_cache.clone().spawn_expiry_sweeper();
+        let coalescer = Arc::new(InflightCoalescer::new(self.coalescer, metrics.clone()));
+        coalescer.clone().spawn_janitor();
+        let (rate_limiter, access_log, usage_sink, error_reporter, traces, credits) =
+            if self.from_env {
+                (
+                    RateLimiter::new(redis.clone()),
+                    AccessLog::from_env(),
+                    UsageSink::from_env(metrics.clone()),
+                    ErrorReporter::from_env(),
+                    TraceExporter::from_env(),
+                    CreditLedger::from_env(redis.clone()),
+                )
+            } else {
+                (
+                    RateLimiter::in_memory(),
+                    AccessLog::disabled(),
+                    UsageSink::disabled(),
+                    ErrorReporter::disabled(),
+                    TraceExporter::disabled(),
+                    CreditLedger::disabled(),
+                )
+            };
+        let rate_limiter = match self.limit_store {
+            Some(store) => RateLimiter::with_store(store),
+            None => rate_limiter,
+        };
+        AppState {
+            backend,
+            batcher,
+            auth: self.key_store,
+            rate_limiter: Arc::new(
+                rate_limiter
+                    .with_clock(self.clock)
+                    .with_quota_day(self.quota_day),
+            ),
+            quotas: Arc::new(QuotaOverrides::default()),
+            response_cache,
+            coalescer,
+            fair_queue: Arc::new(FairQueue::new(self.fair_queue, metrics.clone())),
+            admission: Arc::new(AdmissionController::new(self.admission, metrics.clone())),
+            model_pools: Arc::new(ModelPools::new(self.model_pools, metrics.clone())),
+            access_log: Arc::new(access_log),
+            admin: Arc::new(self.admin),
+            body_limits: Arc::new(self.body_limits),
+            context_limits: Arc::new(self.context_limits.prepend(self.catalog.context_windows())),
+            truncation: Arc::new(self.truncation),
+            experiments: Arc::new(self.experiments),
+            peer_trust: Arc::new(self.peer_trust),
+            pricing: Arc::new(self.pricing.prepend(self.catalog.prices())),
+            catalog: Arc::new(self.catalog),
+            post_processors: Arc::new(self.post_processors),
+            credits: Arc::new(credits),
+            usage_sink: Arc::new(usage_sink),
+            error_reporter: Arc::new(error_reporter),
+            traces: Arc::new(traces),
+            timeouts: Arc::new(self.timeouts),
+            streaming: Arc::new(self.streaming),
+            forwarded_headers: Arc::new(self.forwarded_headers),
+            redis,
+            metrics,
+        }
+    }
+}
+
+fn default_metrics(from_env: bool) -> AppMetrics {
+    if from_env {
+        AppMetrics::with_config(MetricsConfig::from_env())
+    } else {
+        AppMetrics::new()
+    }
+}
@@ -1,105 +1,833 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
+use async_trait::async_trait;
+use axum::http::{header::CACHE_CONTROL, HeaderMap};
+use redb::{ReadableDatabase, ReadableTable, TableDefinition};
 use redis::AsyncCommands;
+use serde::Deserialize;
 use tokio::sync::Mutex;
-use tracing::warn;
+use tracing::{debug, warn};
 
-use crate::models::BackendChatResponse;
+use crate::{
+    auth::AuthContext,
+    clock::{Clock, SystemClock},
+    metrics::AppMetrics,
+    models::{BackendChatResponse, StreamTranscript},
+    redis_pool::RedisPool,
+    scheduler::FingerprintHash,
+};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct CacheConfig {
     pub ttl: Duration,
+    pub stale_window: Duration,
+    pub cache_streams: bool,
+    pub paced_stream_replay: bool,
+    pub scope: CacheScope,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub sweep_interval: Duration,
+    pub model_rules: Vec<ModelCacheRule>,
+    /// Digest for the request fingerprints that key cache and coalescing entries.
+    pub fingerprint_hash: FingerprintHash,
+}
+
+/// Per-model override evaluated in order; the first rule whose glob matches the model wins.
+/// A `ttl` of `None` marks the model as uncacheable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelCacheRule {
+    pub pattern: String,
+    pub ttl: Option<Duration>,
+}
+
+impl ModelCacheRule {
+    /// Parses `pattern=off|<ttl_secs>` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pattern, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("cache rule `{entry}` must be `pattern=off|secs`"))?;
+                let value = value.trim();
+                let ttl = if value.eq_ignore_ascii_case("off") {
+                    None
+                } else {
+                    let secs = value
+                        .parse::<u64>()
+                        .map_err(|_| format!("cache rule `{entry}` has an invalid TTL"))?;
+                    Some(Duration::from_secs(secs))
+                };
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    ttl,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Controls which callers may share a cached (or coalesced) response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheScope {
+    #[default]
+    Global,
+    #[serde(rename = "key", alias = "api_key", alias = "api-key")]
+    ApiKey,
+    Tenant,
+}
+
+impl CacheScope {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "global" => Some(Self::Global),
+            "key" | "api_key" | "api-key" => Some(Self::ApiKey),
+            "tenant" => Some(Self::Tenant),
+            _ => None,
+        }
+    }
+
+    /// Partition value mixed into the request fingerprint; `None` shares across all callers.
+    pub fn partition(&self, auth: &AuthContext) -> Option<String> {
+        match self {
+            Self::Global => None,
+            Self::ApiKey => Some(format!("key:{}", auth.api_key)),
+            Self::Tenant => Some(format!("tenant:{}", auth.tenant.id)),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(90),
+            stale_window: Duration::ZERO,
+            cache_streams: true,
+            paced_stream_replay: false,
+            scope: CacheScope::default(),
+            max_entries: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            sweep_interval: Duration::from_secs(30),
+            model_rules: Vec::new(),
+            fingerprint_hash: FingerprintHash::default(),
+        }
+    }
 }
 
 impl CacheConfig {
     pub fn from_env() -> Self {
-        let ttl_secs = env::var("GATEWAY_CACHE_TTL_SECS")
+        let defaults = Self::default();
+        let ttl = env::var("GATEWAY_CACHE_TTL_SECS")
             .ok()
             .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(90);
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.ttl);
+        let secs = |name, default: Duration| {
+            Duration::from_secs(read_usize(name, default.as_secs() as usize) as u64)
+        };
+        Self {
+            ttl,
+            stale_window: secs("GATEWAY_CACHE_STALE_SECS", defaults.stale_window),
+            cache_streams: read_bool("GATEWAY_CACHE_STREAMS", defaults.cache_streams),
+            paced_stream_replay: read_bool(
+                "GATEWAY_CACHE_STREAM_PACED_REPLAY",
+                defaults.paced_stream_replay,
+            ),
+            scope: read_scope(),
+            max_entries: read_usize("GATEWAY_CACHE_MAX_ENTRIES", defaults.max_entries),
+            max_bytes: read_usize("GATEWAY_CACHE_MAX_BYTES", defaults.max_bytes),
+            sweep_interval: secs("GATEWAY_CACHE_SWEEP_INTERVAL_SECS", defaults.sweep_interval),
+            model_rules: read_model_rules(),
+            fingerprint_hash: read_fingerprint_hash(),
+        }
+    }
+
+    /// Marks every model uncacheable, turning off response caching while keeping the rest of
+    /// the request path unchanged.
+    pub fn disabled() -> Self {
         Self {
-            ttl: Duration::from_secs(ttl_secs),
+            model_rules: vec![ModelCacheRule {
+                pattern: "*".to_owned(),
+                ttl: None,
+            }],
+            ..Self::default()
         }
     }
 }
 
+/// Per-request cache behaviour derived from client headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheDirective {
+    pub read: bool,
+    pub write: bool,
+    /// TTL override from a model rule; `None` uses the configured default.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for CacheDirective {
+    fn default() -> Self {
+        Self {
+            read: true,
+            write: true,
+            ttl: None,
+        }
+    }
+}
+
+impl CacheDirective {
+    /// `no-cache` skips the lookup but still refreshes the entry, `no-store` skips both, and
+    /// `x-gateway-cache: bypass` opts the request out of the cache entirely.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut directive = Self::default();
+
+        for value in headers.get_all(CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for token in value.split(',').map(str::trim) {
+                if token.eq_ignore_ascii_case("no-store") {
+                    directive.read = false;
+                    directive.write = false;
+                } else if token.eq_ignore_ascii_case("no-cache") {
+                    directive.read = false;
+                }
+            }
+        }
+
+        let bypass = headers
+            .get("x-gateway-cache")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("bypass"));
+        if bypass {
+            directive.read = false;
+            directive.write = false;
+        }
+
+        directive
+    }
+
+    pub fn miss_header(&self) -> &'static str {
+        if self.read {
+            "miss"
+        } else {
+            "bypass"
+        }
+    }
+}
+
+/// A cache hit; `stale` entries are past their TTL but inside the stale-while-revalidate window.
+#[derive(Debug, Clone)]
+pub struct CacheHit<T> {
+    pub value: T,
+    pub stale: bool,
+}
+
+/// Shared or persistent storage for cache entries. The gateway ships [`RedisCacheStore`] and
+/// [`DiskCacheStore`]; embedders can keep entries elsewhere (Memcached, DynamoDB, ...) through
+/// [`ResponseCache::with_store`].
+///
+/// Entries are opaque strings that carry their own freshness deadline, so a store only has to
+/// keep each one until it expires. `now` is the cache clock in unix seconds.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// Label for the `gateway_cache_*` metrics.
+    fn name(&self) -> &str;
+
+    /// The entry stored under `key`, or `None` when there is none or it has expired.
+    async fn get(&self, key: &str, now: u64) -> Result<Option<String>, String>;
+
+    /// Stores `payload` under `key`, replacing any entry, until `expires_in` from `now`.
+    async fn set(
+        &self,
+        key: &str,
+        payload: String,
+        now: u64,
+        expires_in: Duration,
+    ) -> Result<(), String>;
+
+    /// Deletes expired entries and returns how many, for stores that do not expire them on
+    /// their own. Called every sweep interval.
+    async fn sweep_expired(&self, _now: u64) -> Result<u64, String> {
+        Ok(0)
+    }
+}
+
 pub struct ResponseCache {
     backend: CacheBackend,
     config: CacheConfig,
+    metrics: Arc<AppMetrics>,
+    refreshing: std::sync::Mutex<HashSet<String>>,
+    clock: Arc<dyn Clock>,
 }
 
+/// The in-process store keeps decoded responses rather than going through [`CacheStore`], so
+/// memory hits skip deserialization.
 enum CacheBackend {
-    Memory(Mutex<HashMap<String, MemoryCacheItem>>),
-    Redis {
-        client: redis::Client,
-        prefix: String,
-    },
+    Memory(Mutex<MemoryStore>),
+    Store(Arc<dyn CacheStore>),
+}
+
+/// Entries under `<prefix>:cache:<namespace>:<key>`, expired by Redis itself.
+pub struct RedisCacheStore {
+    pool: Arc<RedisPool>,
+}
+
+impl RedisCacheStore {
+    pub fn new(pool: Arc<RedisPool>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisCacheStore {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    async fn get(&self, key: &str, _now: u64) -> Result<Option<String>, String> {
+        let mut connection = self
+            .pool
+            .connection()
+            .await
+            .map_err(|error| format!("no redis connection: {error}"))?;
+        let redis_key = format!("{}:cache:{key}", self.pool.prefix());
+        let payload = connection.get::<_, Option<String>>(&redis_key).await;
+        self.pool
+            .track(payload)
+            .await
+            .map_err(|error| error.to_string())
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        payload: String,
+        _now: u64,
+        expires_in: Duration,
+    ) -> Result<(), String> {
+        let mut connection = self
+            .pool
+            .connection()
+            .await
+            .map_err(|error| format!("no redis connection: {error}"))?;
+        let redis_key = format!("{}:cache:{key}", self.pool.prefix());
+        let result = connection
+            .set_ex::<_, _, ()>(&redis_key, payload, expires_in.as_secs())
+            .await;
+        self.pool
+            .track(result)
+            .await
+            .map_err(|error| error.to_string())
+    }
+}
+
+/// Entries in an embedded redb database, so they survive restarts of a single instance.
+pub struct DiskCacheStore {
+    db: Arc<redb::Database>,
+}
+
+impl DiskCacheStore {
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: &str) -> Result<Self, redb::Error> {
+        Ok(Self {
+            db: Arc::new(open_disk_store(path)?),
+        })
+    }
+}
+
+#[async_trait]
+impl CacheStore for DiskCacheStore {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    async fn get(&self, key: &str, now: u64) -> Result<Option<String>, String> {
+        let (db, key) = (self.db.clone(), key.to_owned());
+        match tokio::task::spawn_blocking(move || disk_get(&db, &key, now)).await {
+            Ok(result) => result.map_err(|error| error.to_string()),
+            Err(error) => Err(format!("disk cache task failed: {error}")),
+        }
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        payload: String,
+        now: u64,
+        expires_in: Duration,
+    ) -> Result<(), String> {
+        let (db, key) = (self.db.clone(), key.to_owned());
+        let expires_at = now.saturating_add(expires_in.as_secs());
+        match tokio::task::spawn_blocking(move || disk_put(&db, &key, expires_at, &payload)).await {
+            Ok(result) => result.map_err(|error| error.to_string()),
+            Err(error) => Err(format!("disk cache task failed: {error}")),
+        }
+    }
+
+    async fn sweep_expired(&self, now: u64) -> Result<u64, String> {
+        let db = self.db.clone();
+        match tokio::task::spawn_blocking(move || disk_sweep(&db, now)).await {
+            Ok(result) => result.map_err(|error| error.to_string()),
+            Err(error) => Err(format!("disk cache task failed: {error}")),
+        }
+    }
 }
 
+/// Disk rows are keyed like memory entries and hold `(expires_at_unix, envelope)` so the
+/// sweeper can drop expired rows without decoding payloads.
+const DISK_TABLE: TableDefinition<&str, (u64, &str)> = TableDefinition::new("response_cache");
+
 struct MemoryCacheItem {
-    value: BackendChatResponse,
+    value: CachedValue,
+    fresh_until: Instant,
     expires_at: Instant,
+    size_bytes: usize,
+    recency: u64,
+}
+
+/// LRU store bounded by entry count and approximate payload bytes.
+struct MemoryStore {
+    items: HashMap<String, MemoryCacheItem>,
+    recency: BTreeMap<u64, String>,
+    next_tick: u64,
+    total_bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum MemoryLookup {
+    Missing,
+    Expired,
+    Found(CachedValue),
+    Stale(CachedValue),
+}
+
+impl MemoryStore {
+    fn new(config: &CacheConfig) -> Self {
+        Self {
+            items: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+            total_bytes: 0,
+            max_entries: config.max_entries.max(1),
+            max_bytes: config.max_bytes.max(1),
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick = self.next_tick.wrapping_add(1);
+        self.next_tick
+    }
+
+    fn get(&mut self, key: &str, now: Instant) -> MemoryLookup {
+        let Some(item) = self.items.get(key) else {
+            return MemoryLookup::Missing;
+        };
+        if item.expires_at <= now {
+            self.remove(key);
+            return MemoryLookup::Expired;
+        }
+
+        let tick = self.tick();
+        let Some(item) = self.items.get_mut(key) else {
+            return MemoryLookup::Missing;
+        };
+        self.recency.remove(&item.recency);
+        item.recency = tick;
+        self.recency.insert(tick, key.to_owned());
+        if item.fresh_until <= now {
+            MemoryLookup::Stale(item.value.clone())
+        } else {
+            MemoryLookup::Found(item.value.clone())
+        }
+    }
+
+    /// Inserts an entry and returns how many entries were evicted to make room for it.
+    fn insert(
+        &mut self,
+        key: String,
+        value: CachedValue,
+        fresh_until: Instant,
+        expires_at: Instant,
+        size: usize,
+    ) -> u64 {
+        self.remove(&key);
+        if size > self.max_bytes {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.items.len() >= self.max_entries || self.total_bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(item) = self.items.remove(&oldest) {
+                self.total_bytes = self.total_bytes.saturating_sub(item.size_bytes);
+                evicted += 1;
+            }
+        }
+
+        let tick = self.tick();
+        self.recency.insert(tick, key.clone());
+        self.total_bytes += size;
+        self.items.insert(
+            key,
+            MemoryCacheItem {
+                value,
+                fresh_until,
+                expires_at,
+                size_bytes: size,
+                recency: tick,
+            },
+        );
+        evicted
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(item) = self.items.remove(key) {
+            self.recency.remove(&item.recency);
+            self.total_bytes = self.total_bytes.saturating_sub(item.size_bytes);
+        }
+    }
+
+    fn sweep_expired(&mut self, now: Instant) -> u64 {
+        let expired = self
+            .items
+            .iter()
+            .filter(|(_, item)| item.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CachedValue {
+    Chat(BackendChatResponse),
+    Stream(StreamTranscript),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CacheNamespace {
+    Chat,
+    Stream,
+}
+
+impl CacheNamespace {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Chat => "chat",
+            Self::Stream => "stream",
+        }
+    }
+}
+
+impl CachedValue {
+    fn encode(&self) -> Result<String, serde_json::Error> {
+        match self {
+            Self::Chat(value) => serde_json::to_string(value),
+            Self::Stream(value) => serde_json::to_string(value),
+        }
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        match self {
+            Self::Chat(value) => serde_json::to_value(value),
+            Self::Stream(value) => serde_json::to_value(value),
+        }
+    }
+
+    fn from_json(
+        namespace: CacheNamespace,
+        payload: serde_json::Value,
+    ) -> Result<Self, serde_json::Error> {
+        match namespace {
+            CacheNamespace::Chat => serde_json::from_value(payload).map(Self::Chat),
+            CacheNamespace::Stream => serde_json::from_value(payload).map(Self::Stream),
+        }
+    }
+
+    /// Redis payloads carry their freshness deadline so stale reads can be told apart; bare
+    /// payloads written before the envelope existed are treated as fresh.
+    fn encode_envelope(&self, fresh_until_unix: u64) -> Result<String, serde_json::Error> {
+        let envelope = serde_json::json!({
+            "fresh_until": fresh_until_unix,
+            "value": self.to_json()?,
+        });
+        serde_json::to_string(&envelope)
+    }
+
+    fn decode_envelope(
+        namespace: CacheNamespace,
+        payload: &str,
+        now_unix: u64,
+    ) -> Result<(Self, bool), serde_json::Error> {
+        let mut parsed: serde_json::Value = serde_json::from_str(payload)?;
+        let fresh_until = parsed
+            .get("fresh_until")
+            .and_then(serde_json::Value::as_u64);
+        match (fresh_until, parsed.get_mut("value")) {
+            (Some(fresh_until), Some(value)) => {
+                let value = Self::from_json(namespace, value.take())?;
+                Ok((value, fresh_until <= now_unix))
+            }
+            _ => Ok((Self::from_json(namespace, parsed)?, false)),
+        }
+    }
 }
 
 impl ResponseCache {
-    pub fn memory(config: CacheConfig) -> Self {
+    pub fn memory(config: CacheConfig, metrics: Arc<AppMetrics>) -> Self {
         Self {
-            backend: CacheBackend::Memory(Mutex::new(HashMap::new())),
+            backend: CacheBackend::Memory(Mutex::new(MemoryStore::new(&config))),
             config,
+            metrics,
+            refreshing: std::sync::Mutex::new(HashSet::new()),
+            clock: SystemClock::shared(),
         }
     }
 
-    pub fn from_env(config: CacheConfig) -> Self {
-        let backend = match env::var("REDIS_URL") {
-            Ok(url) if !url.trim().is_empty() => match redis::Client::open(url.clone()) {
-                Ok(client) => {
-                    let prefix =
-                        env::var("GATEWAY_REDIS_PREFIX").unwrap_or_else(|_| "gateway".to_owned());
-                    CacheBackend::Redis { client, prefix }
-                }
-                Err(error) => {
-                    warn!(error = %error, "invalid REDIS_URL, falling back to in-memory cache");
-                    CacheBackend::Memory(Mutex::new(HashMap::new()))
-                }
+    /// Opens (or creates) an embedded on-disk cache at `path`.
+    pub fn disk(
+        config: CacheConfig,
+        metrics: Arc<AppMetrics>,
+        path: &str,
+    ) -> Result<Self, redb::Error> {
+        Ok(Self::with_store(
+            config,
+            metrics,
+            Arc::new(DiskCacheStore::open(path)?),
+        ))
+    }
+
+    /// Keeps entries in `store` instead of in memory.
+    pub fn with_store(
+        config: CacheConfig,
+        metrics: Arc<AppMetrics>,
+        store: Arc<dyn CacheStore>,
+    ) -> Self {
+        Self {
+            backend: CacheBackend::Store(store),
+            config,
+            metrics,
+            refreshing: std::sync::Mutex::new(HashSet::new()),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// Stores entries in Redis when `redis` is set, on disk when `GATEWAY_CACHE_DISK_PATH` is
+    /// set, and in memory otherwise.
+    pub fn from_env(
+        config: CacheConfig,
+        metrics: Arc<AppMetrics>,
+        redis: Option<Arc<RedisPool>>,
+    ) -> Self {
+        let disk_path = env::var("GATEWAY_CACHE_DISK_PATH")
+            .ok()
+            .filter(|path| !path.trim().is_empty());
+        let backend = match redis {
+            Some(pool) => CacheBackend::Store(Arc::new(RedisCacheStore::new(pool))),
+            None => match disk_path {
+                Some(path) => match DiskCacheStore::open(&path) {
+                    Ok(store) => CacheBackend::Store(Arc::new(store)),
+                    Err(error) => {
+                        warn!(error = %error, path = %path, "failed to open disk cache, falling back to in-memory cache");
+                        CacheBackend::Memory(Mutex::new(MemoryStore::new(&config)))
+                    }
+                },
+                None => CacheBackend::Memory(Mutex::new(MemoryStore::new(&config))),
             },
-            _ => CacheBackend::Memory(Mutex::new(HashMap::new())),
         };
 
-        Self { backend, config }
+        Self {
+            backend,
+            config,
+            metrics,
+            refreshing: std::sync::Mutex::new(HashSet::new()),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// Times entries with `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Periodically drops expired entries so space is reclaimed without waiting for a lookup of
+    /// the same key. Stores that expire entries on their own, like Redis, have nothing to drop.
+    pub fn spawn_expiry_sweeper(self: Arc<Self>) {
+        let interval = self.config.sweep_interval.max(Duration::from_secs(1));
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let expired = match &self.backend {
+                    CacheBackend::Memory(store) => {
+                        store.lock().await.sweep_expired(self.clock.now())
+                    }
+                    CacheBackend::Store(store) => {
+                        match store.sweep_expired(self.clock.unix_secs()).await {
+                            Ok(expired) => expired,
+                            Err(error) => {
+                                warn!(store = store.name(), error = %error, "cache sweep failed");
+                                0
+                            }
+                        }
+                    }
+                };
+                if expired > 0 {
+                    debug!(expired, "swept expired cache entries");
+                    self.metrics
+                        .observe_cache_event(self.backend_label(), "expired", expired);
+                }
+            }
+        });
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Narrows a header-derived directive with the first matching per-model rule.
+    pub fn directive_for_model(
+        &self,
+        model: &str,
+        mut directive: CacheDirective,
+    ) -> CacheDirective {
+        let Some(rule) = self
+            .config
+            .model_rules
+            .iter()
+            .find(|rule| crate::glob::matches(&rule.pattern, model))
+        else {
+            return directive;
+        };
+
+        match rule.ttl {
+            Some(ttl) => directive.ttl = Some(ttl),
+            None => {
+                directive.read = false;
+                directive.write = false;
+            }
+        }
+        directive
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CacheHit<BackendChatResponse>> {
+        match self.lookup(CacheNamespace::Chat, key).await? {
+            (CachedValue::Chat(value), stale) => Some(CacheHit { value, stale }),
+            (CachedValue::Stream(_), _) => None,
+        }
     }
 
-    pub async fn get(&self, key: &str) -> Option<BackendChatResponse> {
+    pub async fn set(&self, key: &str, value: &BackendChatResponse, ttl: Option<Duration>) {
+        // A hit answers without an upstream call, so it must not repeat that call's headers.
+        let value = BackendChatResponse {
+            upstream_headers: None,
+            ..value.clone()
+        };
+        self.store(CacheNamespace::Chat, key, CachedValue::Chat(value), ttl)
+            .await;
+    }
+
+    pub async fn get_stream(&self, key: &str) -> Option<CacheHit<StreamTranscript>> {
+        if !self.config.cache_streams {
+            return None;
+        }
+        match self.lookup(CacheNamespace::Stream, key).await? {
+            (CachedValue::Stream(value), stale) => Some(CacheHit { value, stale }),
+            (CachedValue::Chat(_), _) => None,
+        }
+    }
+
+    /// Claims the background refresh for a stale key; returns `false` when one is already
+    /// running so concurrent stale hits trigger a single backend call.
+    pub fn begin_refresh(&self, key: &str) -> bool {
+        self.refreshing
+            .lock()
+            .map(|mut refreshing| refreshing.insert(key.to_owned()))
+            .unwrap_or(false)
+    }
+
+    pub fn end_refresh(&self, key: &str) {
+        if let Ok(mut refreshing) = self.refreshing.lock() {
+            refreshing.remove(key);
+        }
+    }
+
+    pub async fn set_stream(&self, key: &str, value: &StreamTranscript, ttl: Option<Duration>) {
+        if !self.config.cache_streams {
+            return;
+        }
+        self.store(
+            CacheNamespace::Stream,
+            key,
+            CachedValue::Stream(value.clone()),
+            ttl,
+        )
+        .await;
+    }
+
+    fn backend_label(&self) -> &str {
+        match &self.backend {
+            CacheBackend::Memory(_) => "memory",
+            CacheBackend::Store(store) => store.name(),
+        }
+    }
+
+    async fn lookup(&self, namespace: CacheNamespace, key: &str) -> Option<(CachedValue, bool)> {
+        let result = self.lookup_entry(namespace, key).await;
+        let event = match &result {
+            Some((_, false)) => "hit",
+            Some((_, true)) => "stale",
+            None => "miss",
+        };
+        self.metrics
+            .observe_cache_event(self.backend_label(), event, 1);
+        result
+    }
+
+    async fn lookup_entry(
+        &self,
+        namespace: CacheNamespace,
+        key: &str,
+    ) -> Option<(CachedValue, bool)> {
         match &self.backend {
             CacheBackend::Memory(store) => {
-                let mut guard = store.lock().await;
-                let item = guard.get(key)?;
-                if item.expires_at <= Instant::now() {
-                    guard.remove(key);
-                    return None;
+                let memory_key = format!("{}:{key}", namespace.as_str());
+                let lookup = store.lock().await.get(&memory_key, self.clock.now());
+                match lookup {
+                    MemoryLookup::Found(value) => Some((value, false)),
+                    MemoryLookup::Stale(value) => Some((value, true)),
+                    MemoryLookup::Expired => {
+                        self.metrics.observe_cache_event("memory", "expired", 1);
+                        None
+                    }
+                    MemoryLookup::Missing => None,
                 }
-                Some(item.value.clone())
             }
-            CacheBackend::Redis { client, prefix } => {
-                let mut connection = match client.get_multiplexed_async_connection().await {
-                    Ok(connection) => connection,
-                    Err(error) => {
-                        warn!(error = %error, "failed to get redis connection for cache get");
-                        return None;
-                    }
-                };
-                let redis_key = format!("{prefix}:cache:chat:{key}");
-                let payload = match connection.get::<_, Option<String>>(&redis_key).await {
+            CacheBackend::Store(store) => {
+                let now = self.clock.unix_secs();
+                let entry_key = format!("{}:{key}", namespace.as_str());
+                let payload = match store.get(&entry_key, now).await {
                     Ok(payload) => payload?,
                     Err(error) => {
-                        warn!(error = %error, "redis get failed for cache");
+                        warn!(store = store.name(), error = %error, "cache get failed");
                         return None;
                     }
                 };
-                match serde_json::from_str::<BackendChatResponse>(&payload) {
+                match CachedValue::decode_envelope(namespace, &payload, now) {
                     Ok(value) => Some(value),
                     Err(error) => {
                         warn!(error = %error, "failed to decode cached backend response");
@@ -110,43 +838,465 @@ impl ResponseCache {
         }
     }
 
-    pub async fn set(&self, key: &str, value: &BackendChatResponse) {
+    async fn store(
+        &self,
+        namespace: CacheNamespace,
+        key: &str,
+        value: CachedValue,
+        ttl: Option<Duration>,
+    ) {
+        let ttl = ttl.unwrap_or(self.config.ttl);
         match &self.backend {
             CacheBackend::Memory(store) => {
-                let mut guard = store.lock().await;
-                guard.insert(
-                    key.to_owned(),
-                    MemoryCacheItem {
-                        value: value.clone(),
-                        expires_at: Instant::now() + self.config.ttl,
-                    },
+                let memory_key = format!("{}:{key}", namespace.as_str());
+                let size = memory_key.len() + value.encode().map_or(0, |payload| payload.len());
+                let fresh_until = self.clock.now() + ttl;
+                let evicted = store.lock().await.insert(
+                    memory_key,
+                    value,
+                    fresh_until,
+                    fresh_until + self.config.stale_window,
+                    size,
                 );
+                self.metrics.observe_cache_write("memory", size);
+                if evicted > 0 {
+                    self.metrics
+                        .observe_cache_event("memory", "evicted", evicted);
+                }
             }
-            CacheBackend::Redis { client, prefix } => {
-                let mut connection = match client.get_multiplexed_async_connection().await {
-                    Ok(connection) => connection,
-                    Err(error) => {
-                        warn!(error = %error, "failed to get redis connection for cache set");
-                        return;
-                    }
-                };
-
-                let payload = match serde_json::to_string(value) {
+            CacheBackend::Store(store) => {
+                let now = self.clock.unix_secs();
+                let payload = match value.encode_envelope(now.saturating_add(ttl.as_secs())) {
                     Ok(payload) => payload,
                     Err(error) => {
                         warn!(error = %error, "failed to serialize cached backend response");
                         return;
                     }
                 };
-
-                let redis_key = format!("{prefix}:cache:chat:{key}");
-                if let Err(error) = connection
-                    .set_ex::<_, _, ()>(&redis_key, payload, self.config.ttl.as_secs())
-                    .await
-                {
-                    warn!(error = %error, "redis set failed for cache");
+                let entry_key = format!("{}:{key}", namespace.as_str());
+                let size = entry_key.len() + payload.len();
+                let expires_in = ttl + self.config.stale_window;
+                match store.set(&entry_key, payload, now, expires_in).await {
+                    Ok(()) => self.metrics.observe_cache_write(store.name(), size),
+                    Err(error) => warn!(store = store.name(), error = %error, "cache set failed"),
                 }
             }
         }
     }
 }
+
+fn open_disk_store(path: &str) -> Result<redb::Database, redb::Error> {
+    let db = redb::Database::create(path)?;
+    let txn = db.begin_write()?;
+    txn.open_table(DISK_TABLE)?;
+    txn.commit()?;
+    Ok(db)
+}
+
+/// Returns the stored envelope, deleting the row instead when it has already expired.
+fn disk_get(db: &redb::Database, key: &str, now: u64) -> Result<Option<String>, redb::Error> {
+    let expires_at = {
+        let txn = db.begin_read()?;
+        let table = txn.open_table(DISK_TABLE)?;
+        let Some(row) = table.get(key)? else {
+            return Ok(None);
+        };
+        let (expires_at, payload) = row.value();
+        if expires_at > now {
+            return Ok(Some(payload.to_owned()));
+        }
+        expires_at
+    };
+
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(DISK_TABLE)?;
+        let still_expired = table
+            .get(key)?
+            .is_some_and(|row| row.value().0 == expires_at);
+        if still_expired {
+            table.remove(key)?;
+        }
+    }
+    txn.commit()?;
+    Ok(None)
+}
+
+fn disk_put(
+    db: &redb::Database,
+    key: &str,
+    expires_at: u64,
+    payload: &str,
+) -> Result<(), redb::Error> {
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(DISK_TABLE)?;
+        table.insert(key, (expires_at, payload))?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+fn disk_sweep(db: &redb::Database, now: u64) -> Result<u64, redb::Error> {
+    let txn = db.begin_write()?;
+    let mut expired = 0;
+    {
+        let mut table = txn.open_table(DISK_TABLE)?;
+        table.retain(|_, (expires_at, _)| {
+            let keep = expires_at > now;
+            if !keep {
+                expired += 1;
+            }
+            keep
+        })?;
+    }
+    txn.commit()?;
+    Ok(expired)
+}
+
+fn read_model_rules() -> Vec<ModelCacheRule> {
+    let Ok(raw) = env::var("GATEWAY_CACHE_MODEL_RULES") else {
+        return Vec::new();
+    };
+    ModelCacheRule::parse_list(&raw).unwrap_or_else(|error| {
+        warn!(error = %error, "invalid GATEWAY_CACHE_MODEL_RULES, ignoring model cache rules");
+        Vec::new()
+    })
+}
+
+fn read_scope() -> CacheScope {
+    let Ok(value) = env::var("GATEWAY_CACHE_SCOPE") else {
+        return CacheScope::default();
+    };
+    CacheScope::parse(&value).unwrap_or_else(|| {
+        warn!(value = %value, "invalid GATEWAY_CACHE_SCOPE, using global scope");
+        CacheScope::default()
+    })
+}
+
+fn read_fingerprint_hash() -> FingerprintHash {
+    let Ok(value) = env::var("GATEWAY_CACHE_FINGERPRINT_HASH") else {
+        return FingerprintHash::default();
+    };
+    FingerprintHash::parse(&value).unwrap_or_else(|| {
+        warn!(value = %value, "invalid GATEWAY_CACHE_FINGERPRINT_HASH, using sha256");
+        FingerprintHash::default()
+    })
+}
+
+fn read_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(default)
+}
+
+fn read_bool(name: &str, default: bool) -> bool {
+    env::var(name)
+        .ok()
+        .map(|value| value != "0" && !value.eq_ignore_ascii_case("false"))
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use crate::{
+        clock::ManualClock,
+        metrics::AppMetrics,
+        models::{BackendChatResponse, Usage},
+    };
+
+    use super::{
+        CacheConfig, CacheDirective, CacheNamespace, CacheStore, CachedValue, MemoryLookup,
+        MemoryStore, ModelCacheRule, ResponseCache,
+    };
+
+    fn cached(content: &str) -> CachedValue {
+        CachedValue::Chat(BackendChatResponse {
+            content: content.to_owned(),
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: None,
+        })
+    }
+
+    fn store_with_limits(max_entries: usize, max_bytes: usize) -> MemoryStore {
+        let mut config = CacheConfig::from_env();
+        config.max_entries = max_entries;
+        config.max_bytes = max_bytes;
+        MemoryStore::new(&config)
+    }
+
+    #[test]
+    fn memory_store_evicts_least_recently_used_entry() {
+        let mut store = store_with_limits(2, 1_000);
+        let now = Instant::now();
+        let expires = now + Duration::from_secs(60);
+
+        assert_eq!(
+            store.insert("a".to_owned(), cached("a"), expires, expires, 10),
+            0
+        );
+        assert_eq!(
+            store.insert("b".to_owned(), cached("b"), expires, expires, 10),
+            0
+        );
+        assert_eq!(store.get("a", now), MemoryLookup::Found(cached("a")));
+        assert_eq!(
+            store.insert("c".to_owned(), cached("c"), expires, expires, 10),
+            1
+        );
+
+        assert_eq!(store.get("b", now), MemoryLookup::Missing);
+        assert_eq!(store.get("a", now), MemoryLookup::Found(cached("a")));
+        assert_eq!(store.get("c", now), MemoryLookup::Found(cached("c")));
+    }
+
+    #[test]
+    fn memory_store_enforces_byte_budget_and_sweeps_expired() {
+        let mut store = store_with_limits(100, 25);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+
+        store.insert("a".to_owned(), cached("a"), now, now, 10);
+        store.insert("b".to_owned(), cached("b"), later, later, 10);
+        assert_eq!(
+            store.insert("c".to_owned(), cached("c"), later, later, 10),
+            1
+        );
+        assert_eq!(store.total_bytes, 20);
+
+        store.insert("d".to_owned(), cached("d"), now, now, 1);
+        assert_eq!(store.sweep_expired(now), 1);
+        assert_eq!(store.items.len(), 2);
+        assert_eq!(store.total_bytes, 20);
+    }
+
+    #[test]
+    fn memory_store_serves_stale_entries_inside_window() {
+        let mut store = store_with_limits(10, 1_000);
+        let now = Instant::now();
+        store.insert(
+            "a".to_owned(),
+            cached("a"),
+            now,
+            now + Duration::from_secs(30),
+            10,
+        );
+
+        assert_eq!(store.get("a", now), MemoryLookup::Stale(cached("a")));
+        assert_eq!(
+            store.get("a", now + Duration::from_secs(31)),
+            MemoryLookup::Expired
+        );
+    }
+
+    #[test]
+    fn redis_envelope_round_trips_freshness() {
+        let value = cached("a");
+        let payload = value.encode_envelope(100).expect("encode envelope");
+
+        let (decoded, stale) =
+            CachedValue::decode_envelope(CacheNamespace::Chat, &payload, 99).expect("decode");
+        assert_eq!(decoded, value);
+        assert!(!stale);
+
+        let (_, stale) =
+            CachedValue::decode_envelope(CacheNamespace::Chat, &payload, 100).expect("decode");
+        assert!(stale);
+
+        let legacy = value.encode().expect("encode legacy");
+        let (decoded, stale) =
+            CachedValue::decode_envelope(CacheNamespace::Chat, &legacy, 1_000).expect("decode");
+        assert_eq!(decoded, value);
+        assert!(!stale);
+    }
+
+    #[tokio::test]
+    async fn disk_cache_persists_across_reopen() {
+        let path =
+            std::env::temp_dir().join(format!("gateway-cache-{}.redb", uuid::Uuid::new_v4()));
+        let path = path.to_str().expect("utf-8 temp path").to_owned();
+        let response = BackendChatResponse {
+            content: "persisted".to_owned(),
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: None,
+        };
+
+        {
+            let cache =
+                ResponseCache::disk(CacheConfig::from_env(), Arc::new(AppMetrics::new()), &path)
+                    .expect("open disk cache");
+            cache.set("k", &response, None).await;
+        }
+
+        let cache =
+            ResponseCache::disk(CacheConfig::from_env(), Arc::new(AppMetrics::new()), &path)
+                .expect("reopen disk cache");
+        let hit = cache.get("k").await.expect("entry survives reopen");
+        assert_eq!(hit.value, response);
+        assert!(!hit.stale);
+        assert!(cache.get("other").await.is_none());
+
+        cache.set("gone", &response, Some(Duration::ZERO)).await;
+        assert!(cache.get("gone").await.is_none());
+
+        drop(cache);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// A store that never expires anything, keeping the `expires_in` it was given.
+    #[derive(Default)]
+    struct MapStore(std::sync::Mutex<HashMap<String, (String, Duration)>>);
+
+    #[async_trait]
+    impl CacheStore for MapStore {
+        fn name(&self) -> &str {
+            "map"
+        }
+
+        async fn get(&self, key: &str, _now: u64) -> Result<Option<String>, String> {
+            Ok(self
+                .0
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|(payload, _)| payload.clone()))
+        }
+
+        async fn set(
+            &self,
+            key: &str,
+            payload: String,
+            _now: u64,
+            expires_in: Duration,
+        ) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_owned(), (payload, expires_in));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_stores_hold_namespaced_envelopes_that_go_stale() {
+        let clock = Arc::new(ManualClock::new());
+        let store = Arc::new(MapStore::default());
+        let mut config = CacheConfig::from_env();
+        config.ttl = Duration::from_secs(60);
+        config.stale_window = Duration::from_secs(30);
+        let metrics = Arc::new(AppMetrics::new());
+        let cache = ResponseCache::with_store(config, metrics.clone(), store.clone())
+            .with_clock(clock.clone());
+        let response = BackendChatResponse {
+            content: "stored".to_owned(),
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: None,
+        };
+
+        cache.set("k", &response, None).await;
+        assert_eq!(store.0.lock().unwrap()["chat:k"].1, Duration::from_secs(90));
+        let hit = cache.get("k").await.expect("hit");
+        assert_eq!(hit.value, response);
+        assert!(!hit.stale);
+        assert!(cache.get_stream("k").await.is_none());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(cache.get("k").await.expect("stale hit").stale);
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("backend=\"map\""));
+    }
+
+    #[tokio::test]
+    async fn memory_entries_go_stale_then_expire_on_the_cache_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let mut config = CacheConfig::from_env();
+        config.ttl = Duration::from_secs(60);
+        config.stale_window = Duration::from_secs(30);
+        config.model_rules.clear();
+        let cache =
+            ResponseCache::memory(config, Arc::new(AppMetrics::new())).with_clock(clock.clone());
+        let response = BackendChatResponse {
+            content: "fresh".to_owned(),
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: None,
+        };
+        cache.set("k", &response, None).await;
+
+        clock.advance(Duration::from_secs(59));
+        assert!(!cache.get("k").await.expect("fresh hit").stale);
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get("k").await.expect("stale hit").stale);
+        clock.advance(Duration::from_secs(30));
+        assert!(cache.get("k").await.is_none());
+    }
+
+    #[test]
+    fn model_rules_disable_or_override_ttl() {
+        let mut config = CacheConfig::from_env();
+        config.model_rules =
+            ModelCacheRule::parse_list("*-realtime=off, *mini*=600").expect("valid rules");
+        let cache = ResponseCache::memory(config, Arc::new(AppMetrics::new()));
+
+        let realtime = cache.directive_for_model("gpt-4o-realtime", CacheDirective::default());
+        assert!(!realtime.read && !realtime.write);
+
+        let mini = cache.directive_for_model("gpt-4o-mini", CacheDirective::default());
+        assert!(mini.read && mini.write);
+        assert_eq!(mini.ttl, Some(Duration::from_secs(600)));
+
+        let other = cache.directive_for_model("gpt-4o", CacheDirective::default());
+        assert_eq!(other, CacheDirective::default());
+
+        assert!(ModelCacheRule::parse_list("gpt-4o").is_err());
+        assert!(ModelCacheRule::parse_list("gpt-4o=soon").is_err());
+    }
+
+    #[test]
+    fn cache_control_directives_map_to_read_and_write() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            CacheDirective::from_headers(&headers),
+            CacheDirective::default()
+        );
+
+        headers.insert(
+            "cache-control",
+            HeaderValue::from_static("max-age=0, no-cache"),
+        );
+        let directive = CacheDirective::from_headers(&headers);
+        assert!(!directive.read);
+        assert!(directive.write);
+
+        headers.insert("cache-control", HeaderValue::from_static("No-Store"));
+        let directive = CacheDirective::from_headers(&headers);
+        assert!(!directive.read);
+        assert!(!directive.write);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-gateway-cache", HeaderValue::from_static("bypass"));
+        assert_eq!(directive, CacheDirective::from_headers(&headers));
+        assert_eq!(directive.miss_header(), "bypass");
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    context_window::ContextWindow,
+    models::{InvalidParameter, NormalizedChatRequest},
+    pricing::ModelPrice,
+};
+
+/// Capability a model that lists any must include to be called with `stream: true`.
+pub const STREAMING: &str = "streaming";
+
+/// USD per million tokens of a catalog model.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogPrice {
+    pub prompt_per_million: f64,
+    pub completion_per_million: f64,
+}
+
+/// A model clients may call, loaded from `GATEWAY_MODEL_CATALOG`, a JSON object keyed by
+/// model id.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogEntry {
+    /// Name shown to people, e.g. in a model picker.
+    pub display_name: Option<String>,
+    /// Other names clients may send; requests are rewritten to the model id before anything
+    /// else sees them.
+    pub aliases: Vec<String>,
+    /// Context window in tokens, in place of any `GATEWAY_CONTEXT_WINDOWS` rule.
+    pub context_window: Option<u64>,
+    /// Token prices, in place of any `GATEWAY_MODEL_PRICING` rule.
+    pub pricing: Option<CatalogPrice>,
+    /// Tags such as `streaming`, `tools`, or `vision`. A model that lists none is not
+    /// checked; one that lists some is only streamed if it lists `streaming`.
+    pub capabilities: Vec<String>,
+    /// Backend the model is served by; unset leaves it to the router.
+    pub backend: Option<String>,
+    /// `owned_by` in `GET /v1/models`, `gateway` when unset.
+    pub owned_by: Option<String>,
+}
+
+/// The models the gateway offers. While the catalog is empty every model name is passed on
+/// as sent; once it lists any, it is the only source of model names, context windows, and
+/// prices for the models it lists, and requests for other models are rejected.
+#[derive(Debug, Clone, Default)]
+pub struct ModelCatalog {
+    models: BTreeMap<String, CatalogEntry>,
+    /// Alias to the id of the model it names.
+    aliases: HashMap<String, String>,
+}
+
+impl ModelCatalog {
+    /// Fails on an invalid entry or an alias that could name more than one model.
+    pub fn new(models: BTreeMap<String, CatalogEntry>) -> Result<Self, String> {
+        let mut aliases = HashMap::new();
+        for (id, entry) in &models {
+            check_name(id)?;
+            if entry.context_window == Some(0) {
+                return Err(format!("model `{id}` has a context window of 0 tokens"));
+            }
+            if let Some(price) = &entry.pricing {
+                let valid = |amount: f64| amount.is_finite() && amount >= 0.0;
+                if !valid(price.prompt_per_million) || !valid(price.completion_per_million) {
+                    return Err(format!("model `{id}` has an invalid price"));
+                }
+            }
+            if entry
+                .backend
+                .as_ref()
+                .is_some_and(|backend| backend.trim().is_empty())
+            {
+                return Err(format!("model `{id}` has an empty backend"));
+            }
+            for alias in &entry.aliases {
+                check_name(alias)?;
+                if models.contains_key(alias) {
+                    return Err(format!("alias `{alias}` of `{id}` is also a model id"));
+                }
+                if let Some(other) = aliases.insert(alias.clone(), id.clone()) {
+                    return Err(format!("alias `{alias}` names both `{other}` and `{id}`"));
+                }
+            }
+        }
+        Ok(Self { models, aliases })
+    }
+
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let models = serde_json::from_str::<BTreeMap<String, CatalogEntry>>(raw)
+            .map_err(|error| error.to_string())?;
+        Self::new(models)
+    }
+
+    /// Reads `GATEWAY_MODEL_CATALOG`; an invalid catalog is ignored, leaving every model
+    /// name to pass through.
+    pub fn from_env() -> Self {
+        let Ok(raw) = env::var("GATEWAY_MODEL_CATALOG") else {
+            return Self::default();
+        };
+        if raw.trim().is_empty() {
+            return Self::default();
+        }
+        Self::parse(&raw).unwrap_or_else(|error| {
+            warn!(%error, "invalid GATEWAY_MODEL_CATALOG, ignoring the model catalog");
+            Self::default()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    /// The id and entry of the model `name` refers to, directly or through an alias.
+    pub fn get(&self, name: &str) -> Option<(&str, &CatalogEntry)> {
+        let id = self.aliases.get(name).map_or(name, String::as_str);
+        self.models
+            .get_key_value(id)
+            .map(|(id, entry)| (id.as_str(), entry))
+    }
+
+    /// Rewrites the request's model to its catalog id, checks the model can serve it, and
+    /// sends it to the model's backend unless it is already pinned to one.
+    pub fn apply(&self, request: &mut NormalizedChatRequest) -> Result<(), InvalidParameter> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let Some((id, entry)) = self.get(&request.model) else {
+            return Err(InvalidParameter::new(
+                "model",
+                "model_not_found",
+                format!("the model `{}` does not exist", request.model),
+            ));
+        };
+        if request.stream
+            && !entry.capabilities.is_empty()
+            && !entry.capabilities.iter().any(|tag| tag == STREAMING)
+        {
+            return Err(InvalidParameter::new(
+                "stream",
+                "stream_not_supported",
+                format!("the model `{id}` does not support streaming; send `stream: false`"),
+            ));
+        }
+        request.model = id.to_owned();
+        if request.pinned_backend.is_none() {
+            request.pinned_backend = entry.backend.clone();
+        }
+        Ok(())
+    }
+
+    /// Exact-name window rules for the models that set one.
+    pub fn context_windows(&self) -> Vec<ContextWindow> {
+        self.models
+            .iter()
+            .filter_map(|(id, entry)| {
+                Some(ContextWindow {
+                    pattern: id.clone(),
+                    tokens: entry.context_window?,
+                })
+            })
+            .collect()
+    }
+
+    /// Exact-name price rules for the models that set one.
+    pub fn prices(&self) -> Vec<ModelPrice> {
+        self.models
+            .iter()
+            .filter_map(|(id, entry)| {
+                let price = entry.pricing?;
+                Some(ModelPrice {
+                    pattern: id.clone(),
+                    prompt_per_million: price.prompt_per_million,
+                    completion_per_million: price.completion_per_million,
+                })
+            })
+            .collect()
+    }
+
+    /// The `GET /v1/models` body: the models `allowed` accepts, sorted by id, in OpenAI's list
+    /// shape with the catalog's metadata alongside.
+    pub fn list(&self, allowed: impl Fn(&str) -> bool) -> ModelList<'_> {
+        ModelList {
+            object: "list",
+            data: self
+                .models
+                .iter()
+                .filter(|(id, _)| allowed(id))
+                .map(|(id, entry)| ModelObject {
+                    id,
+                    object: "model",
+                    owned_by: entry.owned_by.as_deref().unwrap_or("gateway"),
+                    display_name: entry.display_name.as_deref(),
+                    aliases: &entry.aliases,
+                    context_window: entry.context_window,
+                    pricing: entry.pricing,
+                    capabilities: &entry.capabilities,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Model names are matched exactly, so one that reads as a glob would mislead.
+fn check_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("model ids and aliases must not be empty".to_owned());
+    }
+    if name.contains('*') {
+        return Err(format!("model name `{name}` must not contain `*`"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelList<'a> {
+    object: &'static str,
+    data: Vec<ModelObject<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelObject<'a> {
+    id: &'a str,
+    object: &'static str,
+    owned_by: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<&'a str>,
+    aliases: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_window: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pricing: Option<CatalogPrice>,
+    capabilities: &'a [String],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModelCatalog;
+    use crate::models::{
+        ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+        Priority,
+    };
+
+    const CATALOG: &str = r#"{
+        "gpt-4o": {
+            "display_name": "GPT-4o",
+            "aliases": ["gpt-4o-latest"],
+            "context_window": 128000,
+            "pricing": {"prompt_per_million": 2.5, "completion_per_million": 10},
+            "capabilities": ["streaming", "tools"],
+            "backend": "openai-primary",
+            "owned_by": "openai"
+        },
+        "embedder-chat": {"capabilities": ["tools"]}
+    }"#;
+
+    fn request(model: &str, stream: bool) -> NormalizedChatRequest {
+        NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "user_a".to_owned(),
+            model: model.to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: "Hello".to_owned(),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+            },
+            stream,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    #[test]
+    fn resolves_aliases_and_routes_to_the_models_backend() {
+        let catalog = ModelCatalog::parse(CATALOG).expect("valid catalog");
+
+        let mut aliased = request("gpt-4o-latest", true);
+        catalog.apply(&mut aliased).expect("known alias");
+        assert_eq!(aliased.model, "gpt-4o");
+        assert_eq!(aliased.pinned_backend.as_deref(), Some("openai-primary"));
+
+        // A pin from `x-gateway-backend` wins over the catalog's backend.
+        let mut pinned = request("gpt-4o", false);
+        pinned.pinned_backend = Some("openai-canary".to_owned());
+        catalog.apply(&mut pinned).expect("known model");
+        assert_eq!(pinned.pinned_backend.as_deref(), Some("openai-canary"));
+
+        let unknown = catalog
+            .apply(&mut request("gpt-3.5-turbo", false))
+            .expect_err("not in the catalog");
+        assert_eq!(unknown.code, "model_not_found");
+        let streamed = catalog
+            .apply(&mut request("embedder-chat", true))
+            .expect_err("cannot stream");
+        assert_eq!(streamed.code, "stream_not_supported");
+
+        // An empty catalog passes every model through.
+        let mut anything = request("gpt-3.5-turbo", true);
+        ModelCatalog::default()
+            .apply(&mut anything)
+            .expect("no catalog");
+        assert_eq!(anything.model, "gpt-3.5-turbo");
+    }
+
+    #[test]
+    fn exposes_windows_prices_and_the_model_list() {
+        let catalog = ModelCatalog::parse(CATALOG).expect("valid catalog");
+        let windows = catalog.context_windows();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(
+            (windows[0].pattern.as_str(), windows[0].tokens),
+            ("gpt-4o", 128_000)
+        );
+        let prices = catalog.prices();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0].completion_per_million, 10.0);
+
+        let list =
+            serde_json::to_value(catalog.list(|id| id != "embedder-chat")).expect("serializable");
+        assert_eq!(list["object"], "list");
+        assert_eq!(list["data"].as_array().map(Vec::len), Some(1));
+        assert_eq!(list["data"][0]["id"], "gpt-4o");
+        assert_eq!(list["data"][0]["owned_by"], "openai");
+        assert_eq!(list["data"][0]["display_name"], "GPT-4o");
+        assert_eq!(list["data"][0]["context_window"], 128_000);
+        assert!(list["data"][0].get("backend").is_none());
+    }
+
+    #[test]
+    fn rejects_ambiguous_or_invalid_catalogs() {
+        for raw in [
+            r#"{"a": {"aliases": ["b"]}, "b": {}}"#,
+            r#"{"a": {"aliases": ["c"]}, "b": {"aliases": ["c"]}}"#,
+            r#"{"gpt-4o*": {}}"#,
+            r#"{"a": {"context_window": 0}}"#,
+            r#"{"a": {"pricing": {"prompt_per_million": -1, "completion_per_million": 1}}}"#,
+            r#"{"a": {"max_tokens": 10}}"#,
+        ] {
+            assert!(ModelCatalog::parse(raw).is_err(), "{raw}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::{any::Any, panic::AssertUnwindSafe};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::FutureExt;
+use tracing::error;
+
+use crate::{
+    error_reporting::ErrorReport, errors::AppError, request_id::RequestId, state::AppState,
+};
+
+/// Middleware that turns a panic while producing the response into an OpenAI-style `500`
+/// instead of a dropped connection. Panics inside an already-started stream body are not
+/// covered; those end the stream the same way a backend failure does.
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let request_id = request.extensions().get::<RequestId>().cloned();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    match AssertUnwindSafe(next.run(request)).catch_unwind().await {
+        Ok(response) => response,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            error!(
+                request_id = request_id.as_ref().map(RequestId::as_str),
+                %method,
+                path,
+                panic = message,
+                "handler panicked"
+            );
+            state.metrics.observe_handler_panic();
+            state.error_reporter.capture(ErrorReport {
+                kind: "panic",
+                message: message.to_owned(),
+                request_id: request_id.as_ref().map(|id| id.as_str().to_owned()),
+                ..ErrorReport::default()
+            });
+            // The panic message can carry request data, so the client only sees a generic one.
+            let error = AppError::Internal("the gateway hit an internal error".to_owned());
+            match request_id {
+                Some(request_id) => error.into_response_for(request_id.as_str()),
+                None => error.into_response(),
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&'static str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use crate::{backend::mock::MockBackend, request_id, state::AppState};
+
+    async fn boom() -> &'static str {
+        panic!("handler bug")
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_answer_with_an_internal_error() {
+        let state = AppState::new_for_tests(Arc::new(MockBackend::default()));
+        let app = Router::new()
+            .route("/boom", get(boom))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                super::guard,
+            ))
+            .layer(axum::middleware::from_fn(request_id::propagate));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/boom")
+                    .header("x-request-id", "trace-panic")
+                    .body(Body::empty())
+                    .expect("request build"),
+            )
+            .await
+            .expect("request execution");
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()["x-request-id"], "trace-panic");
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("json error body");
+        assert_eq!(json["error"]["type"], "server_error");
+        assert_eq!(json["error"]["code"], "internal_error");
+        assert_eq!(json["error"]["request_id"], "trace-panic");
+        let rendered = state.metrics.render().expect("metrics render");
+        assert!(rendered.contains("gateway_handler_panics_total 1"));
+    }
+}
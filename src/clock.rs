@@ -0,0 +1,109 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of time for rate-limit windows, cache expiry, and circuit-breaker cooldowns, so tests
+/// can move it forward with a [`ManualClock`] instead of waiting out real minutes and days.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for TTLs and cooldowns.
+    fn now(&self) -> Instant;
+    /// Wall-clock seconds since the Unix epoch, for calendar windows and expiries stored
+    /// outside the process.
+    fn unix_secs(&self) -> u64;
+}
+
+/// The real clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> Arc<dyn Clock> {
+        Arc::new(Self)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// A clock that stands still until [`ManualClock::advance`] moves it.
+#[derive(Debug)]
+pub struct ManualClock {
+    started: Instant,
+    started_unix_secs: u64,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    /// Starts at the current time.
+    pub fn new() -> Self {
+        Self::at_unix_secs(SystemClock.unix_secs())
+    }
+
+    /// Starts at a fixed wall-clock time, e.g. just before a minute or day boundary.
+    pub fn at_unix_secs(unix_secs: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            started_unix_secs: unix_secs,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut elapsed = self
+            .elapsed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *elapsed += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self
+            .elapsed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.started + self.elapsed()
+    }
+
+    fn unix_secs(&self) -> u64 {
+        self.started_unix_secs + self.elapsed().as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::at_unix_secs(1_700_000_000);
+        let before = clock.now();
+        assert_eq!(clock.now(), before);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - before, Duration::from_secs(90));
+        assert_eq!(clock.unix_secs(), 1_700_000_090);
+    }
+}
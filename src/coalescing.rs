@@ -1,10 +1,22 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
 
-use tokio::sync::{mpsc, oneshot, Mutex};
-use tracing::debug;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use tracing::{debug, warn};
 
 use crate::{
     backend::{BackendError, InferenceBackend},
+    metrics::AppMetrics,
     models::{BackendChatResponse, BackendChunk, NormalizedChatRequest},
 };
 
@@ -14,135 +26,733 @@ pub enum CoalesceOutcome {
     Joined,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy)]
+pub struct CoalescerConfig {
+    /// One-shot entries whose leader has gone without removing them are reaped by the janitor
+    /// once this old. An entry lives as long as its leader does, however long that is.
+    pub one_shot_ttl: Duration,
+    /// Stream entries with no published chunk for this long are failed and removed.
+    pub stream_idle_timeout: Duration,
+    pub janitor_interval: Duration,
+    /// Chunks retained per stream for replaying to late joiners.
+    pub stream_history_limit: usize,
+    /// Chunks a follower may fall behind before it is disconnected, so one slow client cannot
+    /// hold an unbounded backlog in memory. The leader's own client, and a subscriber left on
+    /// its own, are never disconnected for lag; the stream waits for them instead.
+    pub subscriber_buffer: usize,
+    pub late_join: LateJoinPolicy,
+    /// Whether sampled (`temperature > 0`) requests may share a backend call.
+    pub coalesce_sampled: bool,
+    /// Times a failed one-shot leader may hand the call to a follower before the error is
+    /// fanned out; `0` disables re-election.
+    pub leader_retries: u32,
+}
+
+/// How followers that join a stream after it started are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LateJoinPolicy {
+    /// Replay the stream from the first chunk. Once the history cap is exceeded the entry
+    /// stops accepting joiners and new requests start their own stream.
+    #[default]
+    Replay,
+    /// Only forward chunks published after the join; no history is retained.
+    LiveOnly,
+}
+
+impl LateJoinPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "replay" => Some(Self::Replay),
+            "live" | "live_only" | "live-only" => Some(Self::LiveOnly),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CoalescerConfig {
+    fn default() -> Self {
+        Self {
+            one_shot_ttl: Duration::from_secs(120),
+            stream_idle_timeout: Duration::from_secs(60),
+            janitor_interval: Duration::from_secs(10),
+            stream_history_limit: 2_048,
+            subscriber_buffer: 256,
+            late_join: LateJoinPolicy::Replay,
+            coalesce_sampled: false,
+            leader_retries: 0,
+        }
+    }
+}
+
+impl CoalescerConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            one_shot_ttl: read_secs("GATEWAY_COALESCE_TTL_SECS", defaults.one_shot_ttl),
+            stream_idle_timeout: read_secs(
+                "GATEWAY_COALESCE_STREAM_IDLE_SECS",
+                defaults.stream_idle_timeout,
+            ),
+            janitor_interval: read_secs(
+                "GATEWAY_COALESCE_JANITOR_INTERVAL_SECS",
+                defaults.janitor_interval,
+            ),
+            stream_history_limit: env::var("GATEWAY_COALESCE_STREAM_HISTORY_MAX_CHUNKS")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(defaults.stream_history_limit),
+            subscriber_buffer: env::var("GATEWAY_COALESCE_SUBSCRIBER_BUFFER")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|buffer| *buffer > 0)
+                .unwrap_or(defaults.subscriber_buffer),
+            late_join: read_late_join_policy(),
+            coalesce_sampled: env::var("GATEWAY_COALESCE_SAMPLED")
+                .ok()
+                .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true")),
+            leader_retries: env::var("GATEWAY_COALESCE_LEADER_RETRIES")
+                .ok()
+                .and_then(|value| value.parse::<u32>().ok())
+                .unwrap_or(defaults.leader_retries),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct InflightCoalescer {
-    inflight: Mutex<HashMap<String, Vec<InflightWaiter>>>,
-    stream_inflight: Mutex<HashMap<String, Arc<Mutex<StreamEntry>>>>,
+    config: CoalescerConfig,
+    metrics: Arc<AppMetrics>,
+    next_generation: AtomicU64,
+    inflight: Mutex<HashMap<String, OneShotEntry>>,
+    streams: Mutex<StreamRegistry>,
+}
+
+/// Stream entries are owned by generation; `joinable` maps a fingerprint to the entry new
+/// requests may still join. Entries that stop accepting joiners keep serving their existing
+/// subscribers after the key is released.
+#[derive(Default)]
+struct StreamRegistry {
+    entries: HashMap<u64, StreamEntry>,
+    joinable: HashMap<String, u64>,
+}
+
+impl StreamRegistry {
+    fn remove(&mut self, generation: u64) -> Option<StreamEntry> {
+        let entry = self.entries.remove(&generation)?;
+        if self.joinable.get(&entry.key) == Some(&generation) {
+            self.joinable.remove(&entry.key);
+        }
+        Some(entry)
+    }
+}
+
+type InflightWaiter = oneshot::Sender<WaiterMessage>;
+
+#[derive(Debug)]
+enum WaiterMessage {
+    Finished(Result<BackendChatResponse, BackendError>),
+    /// The leader failed; the receiving follower retries the call as the entry's new leader,
+    /// holding the token that keeps the entry alive.
+    Promoted(Arc<()>),
+}
+
+/// Entries carry a generation so a leader that outlives its entry (reaped by the janitor)
+/// never removes or publishes into a newer entry for the same key.
+#[derive(Debug)]
+struct OneShotEntry {
+    generation: u64,
+    created_at: Instant,
+    /// Upgradable while the leader runs: the leader holds the token this points at.
+    leader: Weak<()>,
+    waiters: Vec<InflightWaiter>,
+    retries_used: u32,
+}
+
+impl OneShotEntry {
+    fn leader_alive(&self) -> bool {
+        self.leader.strong_count() > 0
+    }
+}
+
+enum LeaderOutcome {
+    FanOut(Vec<InflightWaiter>),
+    /// A follower took over; the previous leader waits for its result like any joiner.
+    HandedOff(oneshot::Receiver<WaiterMessage>),
+}
+
+/// Removes the leader's entry if `execute_or_join` is cancelled mid-flight; dropping the
+/// waiters wakes followers with an error instead of leaving them parked forever.
+struct OneShotLeaderGuard<'a> {
+    coalescer: &'a InflightCoalescer,
+    key: &'a str,
+    generation: u64,
+    finished: bool,
+    /// The entry's liveness token, released with the guard.
+    _leader: Arc<()>,
+}
+
+impl OneShotLeaderGuard<'_> {
+    fn finish(mut self, failed: bool) -> LeaderOutcome {
+        self.finished = true;
+        if failed {
+            if let Some(receiver) = self.coalescer.hand_off(self.key, self.generation) {
+                return LeaderOutcome::HandedOff(receiver);
+            }
+        }
+        LeaderOutcome::FanOut(self.coalescer.take_one_shot(self.key, self.generation))
+    }
 }
 
-type InflightWaiter = oneshot::Sender<Result<BackendChatResponse, String>>;
+impl Drop for OneShotLeaderGuard<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let waiters = self.coalescer.take_one_shot(self.key, self.generation);
+        if !waiters.is_empty() {
+            warn!(fingerprint = %self.key, followers = waiters.len(), "coalescing leader cancelled");
+        }
+        fail_waiters(waiters, "coalescing leader was cancelled before completion");
+    }
+}
+
+enum OneShotRole {
+    Lead(u64, Arc<()>),
+    Follow(oneshot::Receiver<WaiterMessage>),
+}
 
 impl InflightCoalescer {
+    pub fn new(config: CoalescerConfig, metrics: Arc<AppMetrics>) -> Self {
+        Self {
+            config,
+            metrics,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &CoalescerConfig {
+        &self.config
+    }
+
+    fn generation(&self) -> u64 {
+        self.next_generation.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub async fn execute_or_join(
         &self,
         key: String,
         backend: Arc<dyn InferenceBackend>,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<(BackendChatResponse, CoalesceOutcome), BackendError> {
-        let receiver = {
-            let mut inflight = self.inflight.lock().await;
-            if let Some(waiters) = inflight.get_mut(&key) {
-                let (tx, rx) = oneshot::channel();
-                waiters.push(tx);
-                Some(rx)
-            } else {
-                inflight.insert(key.clone(), Vec::new());
-                None
+        let mut role = {
+            let mut inflight = lock(&self.inflight);
+            match inflight.get_mut(&key) {
+                Some(entry) if entry.leader_alive() => {
+                    let (tx, rx) = oneshot::channel();
+                    entry.waiters.push(tx);
+                    OneShotRole::Follow(rx)
+                }
+                _ => {
+                    let generation = self.generation();
+                    let leader = Arc::new(());
+                    let stale = inflight.insert(
+                        key.clone(),
+                        OneShotEntry {
+                            generation,
+                            created_at: Instant::now(),
+                            leader: Arc::downgrade(&leader),
+                            waiters: Vec::new(),
+                            retries_used: 0,
+                        },
+                    );
+                    if let Some(stale) = stale {
+                        fail_waiters(stale.waiters, LEADER_GONE);
+                    }
+                    OneShotRole::Lead(generation, leader)
+                }
             }
         };
+        let outcome = match role {
+            OneShotRole::Lead(..) => CoalesceOutcome::Leader,
+            OneShotRole::Follow(_) => CoalesceOutcome::Joined,
+        };
+        self.metrics.observe_coalesce_request(
+            "one_shot",
+            match outcome {
+                CoalesceOutcome::Leader => "leader",
+                CoalesceOutcome::Joined => "follower",
+            },
+        );
 
-        if let Some(receiver) = receiver {
-            debug!(fingerprint = %key, "joined inflight request");
-            return match receiver.await {
-                Ok(Ok(response)) => Ok((response, CoalesceOutcome::Joined)),
-                Ok(Err(message)) => Err(BackendError::Unavailable(message)),
-                Err(_) => Err(BackendError::Unavailable(
-                    "leader request dropped before completion".to_owned(),
-                )),
+        loop {
+            role = match role {
+                OneShotRole::Follow(receiver) => {
+                    debug!(fingerprint = %key, "joined inflight request");
+                    match receiver.await {
+                        Ok(WaiterMessage::Finished(result)) => {
+                            return result.map(|response| (response, outcome));
+                        }
+                        Ok(WaiterMessage::Promoted(leader)) => {
+                            debug!(fingerprint = %key, "promoted to coalescing leader");
+                            let generation =
+                                lock(&self.inflight).get(&key).map(|entry| entry.generation);
+                            match generation {
+                                Some(generation) => OneShotRole::Lead(generation, leader),
+                                None => {
+                                    return Err(BackendError::Unavailable(
+                                        "coalescing entry vanished during leader handoff"
+                                            .to_owned(),
+                                    ))
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            return Err(BackendError::Unavailable(
+                                "leader request dropped before completion".to_owned(),
+                            ))
+                        }
+                    }
+                }
+                OneShotRole::Lead(generation, leader) => {
+                    debug!(fingerprint = %key, "leader executing request");
+                    let guard = OneShotLeaderGuard {
+                        coalescer: self,
+                        key: &key,
+                        generation,
+                        finished: false,
+                        _leader: leader,
+                    };
+                    let leader_result = backend.execute_chat(request.clone()).await;
+                    match guard.finish(leader_result.is_err()) {
+                        LeaderOutcome::FanOut(waiters) => {
+                            let mut delivered = 0;
+                            for waiter in waiters {
+                                if waiter
+                                    .send(WaiterMessage::Finished(leader_result.clone()))
+                                    .is_ok()
+                                {
+                                    delivered += 1;
+                                }
+                            }
+                            if let Ok(response) = &leader_result {
+                                self.metrics.observe_coalesce_bytes_saved(
+                                    "one_shot",
+                                    response.content.len() * delivered,
+                                );
+                            }
+                            return leader_result.map(|response| (response, outcome));
+                        }
+                        LeaderOutcome::HandedOff(receiver) => {
+                            if let Err(error) = &leader_result {
+                                warn!(
+                                    fingerprint = %key,
+                                    error = %error,
+                                    "coalescing leader failed; retrying via promoted follower"
+                                );
+                            }
+                            OneShotRole::Follow(receiver)
+                        }
+                    }
+                }
             };
         }
+    }
 
-        debug!(fingerprint = %key, "leader executing request");
-        let leader_result = backend.execute_chat(request).await;
-
-        let follower_result = match &leader_result {
-            Ok(response) => Ok(response.clone()),
-            Err(error) => Err(error.to_string()),
-        };
-
-        let waiters = {
-            let mut inflight = self.inflight.lock().await;
-            inflight.remove(&key).unwrap_or_default()
-        };
+    /// Promotes the first live follower of a failed leader while the retry budget allows,
+    /// re-registering the failed leader as a waiter on the retried result.
+    fn hand_off(&self, key: &str, generation: u64) -> Option<oneshot::Receiver<WaiterMessage>> {
+        let mut inflight = lock(&self.inflight);
+        let entry = inflight.get_mut(key)?;
+        if entry.generation != generation || entry.retries_used >= self.config.leader_retries {
+            return None;
+        }
 
-        for waiter in waiters {
-            let _ = waiter.send(follower_result.clone());
+        while !entry.waiters.is_empty() {
+            let follower = entry.waiters.remove(0);
+            let leader = Arc::new(());
+            let token = Arc::downgrade(&leader);
+            if follower.send(WaiterMessage::Promoted(leader)).is_ok() {
+                entry.leader = token;
+                entry.retries_used += 1;
+                entry.created_at = Instant::now();
+                let (tx, rx) = oneshot::channel();
+                entry.waiters.push(tx);
+                return Some(rx);
+            }
         }
+        None
+    }
 
-        leader_result.map(|response| (response, CoalesceOutcome::Leader))
+    fn take_one_shot(&self, key: &str, generation: u64) -> Vec<InflightWaiter> {
+        let mut inflight = lock(&self.inflight);
+        match inflight.get(key) {
+            Some(entry) if entry.generation == generation => inflight
+                .remove(key)
+                .map(|entry| entry.waiters)
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
     }
 
     pub async fn join_or_create_stream(&self, key: String) -> StreamJoin {
-        let (entry, is_leader) = {
-            let mut streams = self.stream_inflight.lock().await;
-            if let Some(entry) = streams.get(&key) {
-                (entry.clone(), false)
-            } else {
-                let entry = Arc::new(Mutex::new(StreamEntry::default()));
-                streams.insert(key.clone(), entry.clone());
-                (entry, true)
+        let mut streams = lock(&self.streams);
+        let existing = streams.joinable.get(&key).copied();
+        let (generation, is_leader) = match existing {
+            Some(generation) => (generation, false),
+            None => {
+                let generation = self.generation();
+                streams.joinable.insert(key.clone(), generation);
+                streams.entries.insert(
+                    generation,
+                    StreamEntry {
+                        key,
+                        history: Vec::new(),
+                        history_bytes: 0,
+                        subscribers: Vec::new(),
+                        last_activity: Instant::now(),
+                    },
+                );
+                (generation, true)
             }
         };
 
-        let mut entry_guard = entry.lock().await;
-        let (tx, rx) = mpsc::unbounded_channel();
-        for item in &entry_guard.history {
-            if tx.send(item.clone()).is_err() {
-                break;
+        let history_len = streams
+            .entries
+            .get(&generation)
+            .map_or(0, |entry| entry.history.len());
+        // Room for the replayed history on top of the live buffer.
+        let (tx, rx) = mpsc::channel(history_len + self.config.subscriber_buffer.max(1));
+        let lagged = Arc::new(AtomicBool::new(false));
+        if let Some(entry) = streams.entries.get_mut(&generation) {
+            let mut replayed_bytes = 0;
+            for item in &entry.history {
+                if tx.try_send(item.clone()).is_err() {
+                    break;
+                }
+                replayed_bytes += delta_len(item);
             }
+            if !is_leader {
+                self.metrics.observe_coalesce_replay(entry.history.len());
+                self.metrics
+                    .observe_coalesce_bytes_saved("stream", replayed_bytes);
+            }
+            entry.subscribers.push(Subscriber {
+                tx,
+                lagged: lagged.clone(),
+                leader: is_leader,
+            });
         }
-        if !entry_guard.done {
-            entry_guard.subscribers.push(tx);
-        }
-        drop(entry_guard);
+        self.metrics
+            .observe_coalesce_request("stream", if is_leader { "leader" } else { "follower" });
 
         StreamJoin {
-            receiver: rx,
+            receiver: StreamReceiver { rx, lagged },
             is_leader,
         }
     }
 
+    /// Ties the joinable stream entry for `key` to the leader task holding the lease. The
+    /// leader publishes through the lease; if it is dropped before a terminal item
+    /// (panic, cancellation), followers receive an error and the entry is removed.
+    pub fn stream_lease(self: &Arc<Self>, key: &str) -> StreamLease {
+        let generation = lock(&self.streams).joinable.get(key).copied();
+        StreamLease {
+            coalescer: self.clone(),
+            key: key.to_owned(),
+            generation,
+        }
+    }
+
+    /// Publishes to the entry currently joinable under `key`.
     pub async fn publish_stream_item(&self, key: &str, item: StreamItem) {
-        let Some(entry) = self.stream_inflight.lock().await.get(key).cloned() else {
-            return;
-        };
+        let generation = lock(&self.streams).joinable.get(key).copied();
+        if let Some(generation) = generation {
+            let _ = self.publish_to(generation, item).await;
+        }
+    }
 
-        let mut entry_guard = entry.lock().await;
-        if entry_guard.done {
-            return;
+    /// Fans `item` out and returns how many subscribers are still listening.
+    async fn publish_to(&self, generation: u64, item: StreamItem) -> usize {
+        let (mut listeners, waited) = self.fan_out(generation, &item);
+        // Sent outside the lock: a slow leader holds up its own stream, not the coalescer.
+        for tx in waited {
+            if tx.send(item.clone()).await.is_err() {
+                listeners -= 1;
+            }
         }
+        listeners
+    }
 
-        entry_guard.history.push(item.clone());
-        entry_guard
-            .subscribers
-            .retain(|subscriber| subscriber.send(item.clone()).is_ok());
+    /// Queues `item` for followers and records it, returning the listener count and the
+    /// senders of subscribers to be waited for rather than dropped when they fall behind.
+    fn fan_out(
+        &self,
+        generation: u64,
+        item: &StreamItem,
+    ) -> (usize, Vec<mpsc::Sender<StreamItem>>) {
+        let mut streams = lock(&self.streams);
+        let Some(entry) = streams.entries.get_mut(&generation) else {
+            return (0, Vec::new());
+        };
+
+        entry.last_activity = Instant::now();
+        let alone = entry.subscribers.len() == 1;
+        let mut waited = Vec::new();
+        entry.subscribers.retain(|subscriber| {
+            if subscriber.tx.is_closed() {
+                return false;
+            }
+            if !subscriber.leader && !alone {
+                return subscriber.offer(item.clone(), &self.metrics);
+            }
+            subscriber.observe_lag(&self.metrics);
+            waited.push(subscriber.tx.clone());
+            true
+        });
+        let listeners = entry.subscribers.len();
+        // Every subscriber past the leader's own received this chunk for free.
+        self.metrics.observe_coalesce_bytes_saved(
+            "stream",
+            delta_len(&item) * entry.subscribers.len().saturating_sub(1),
+        );
 
         if is_terminal_item(&item) {
-            entry_guard.done = true;
-            entry_guard.subscribers.clear();
+            if let Some(entry) = streams.remove(generation) {
+                self.release_history(&entry);
+            }
+            return (listeners, waited);
         }
-        let should_remove = entry_guard.done;
-        drop(entry_guard);
 
-        if should_remove {
-            self.stream_inflight.lock().await.remove(key);
+        if self.config.late_join == LateJoinPolicy::LiveOnly {
+            return (listeners, waited);
         }
+        if entry.history.len() < self.config.stream_history_limit {
+            let size = item_size(&item);
+            entry.history.push(item);
+            entry.history_bytes += size;
+            self.metrics.adjust_coalesce_history_bytes(size as i64);
+            return (listeners, waited);
+        }
+
+        // Late joiners could no longer be replayed from the start: stop accepting them and
+        // drop the history, while existing subscribers keep receiving live chunks.
+        let key = entry.key.clone();
+        let released = entry.history_bytes;
+        entry.history = Vec::new();
+        entry.history_bytes = 0;
+        if streams.joinable.get(&key) == Some(&generation) {
+            streams.joinable.remove(&key);
+        }
+        self.metrics
+            .adjust_coalesce_history_bytes(-(released as i64));
+        self.metrics.observe_coalesce_history_truncated();
+        debug!(fingerprint = %key, "stream history cap reached; closed to late joiners");
+        (listeners, waited)
+    }
+
+    fn release_history(&self, entry: &StreamEntry) {
+        if entry.history_bytes > 0 {
+            self.metrics
+                .adjust_coalesce_history_bytes(-(entry.history_bytes as i64));
+        }
+    }
+
+    fn fail_stream(&self, generation: u64, message: &str) -> bool {
+        let Some(entry) = lock(&self.streams).remove(generation) else {
+            return false;
+        };
+        self.release_history(&entry);
+        for subscriber in entry.subscribers {
+            subscriber.offer(
+                Err(BackendError::Unavailable(message.to_owned())),
+                &self.metrics,
+            );
+        }
+        true
+    }
+
+    /// Reaps one-shot entries whose leader is gone once past their TTL, and stream entries
+    /// whose leader went quiet.
+    pub fn sweep(&self, now: Instant) -> usize {
+        let expired_one_shots = {
+            let mut inflight = lock(&self.inflight);
+            let expired = inflight
+                .iter()
+                .filter(|(_, entry)| {
+                    !entry.leader_alive()
+                        && now.saturating_duration_since(entry.created_at)
+                            >= self.config.one_shot_ttl
+                })
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            expired
+                .into_iter()
+                .filter_map(|key| inflight.remove(&key))
+                .collect::<Vec<_>>()
+        };
+        let mut reaped = expired_one_shots.len();
+        for entry in expired_one_shots {
+            fail_waiters(entry.waiters, LEADER_GONE);
+        }
+
+        let idle_streams = lock(&self.streams)
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                now.saturating_duration_since(entry.last_activity)
+                    >= self.config.stream_idle_timeout
+            })
+            .map(|(generation, _)| *generation)
+            .collect::<Vec<_>>();
+        for generation in idle_streams {
+            if self.fail_stream(generation, "stream leader stopped responding") {
+                reaped += 1;
+            }
+        }
+
+        reaped
+    }
+
+    pub fn spawn_janitor(self: Arc<Self>) {
+        let interval = self.config.janitor_interval.max(Duration::from_secs(1));
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let reaped = self.sweep(Instant::now());
+                if reaped > 0 {
+                    warn!(reaped, "reaped abandoned coalescing entries");
+                }
+            }
+        });
     }
 }
 
 #[derive(Debug)]
 pub struct StreamJoin {
-    pub receiver: mpsc::UnboundedReceiver<StreamItem>,
+    pub receiver: StreamReceiver,
     pub is_leader: bool,
 }
 
-pub type StreamItem = Result<BackendChunk, String>;
+/// One subscriber's view of a coalesced stream. A subscriber disconnected for falling behind
+/// still reads the chunks already buffered for it, then a final error.
+#[derive(Debug)]
+pub struct StreamReceiver {
+    rx: mpsc::Receiver<StreamItem>,
+    lagged: Arc<AtomicBool>,
+}
+
+impl StreamReceiver {
+    pub async fn recv(&mut self) -> Option<StreamItem> {
+        match self.rx.recv().await {
+            Some(item) => Some(item),
+            None if self.lagged.swap(false, Ordering::Relaxed) => {
+                Some(Err(BackendError::Unavailable(
+                    "stream client fell too far behind and was disconnected".to_owned(),
+                )))
+            }
+            None => None,
+        }
+    }
+}
+
+impl From<mpsc::Receiver<StreamItem>> for StreamReceiver {
+    /// A receiver fed by something other than the coalescer, such as a cache replay.
+    fn from(rx: mpsc::Receiver<StreamItem>) -> Self {
+        Self {
+            rx,
+            lagged: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+struct Subscriber {
+    tx: mpsc::Sender<StreamItem>,
+    lagged: Arc<AtomicBool>,
+    /// Whether this is the leader's own client.
+    leader: bool,
+}
+
+impl Subscriber {
+    fn observe_lag(&self, metrics: &AppMetrics) {
+        metrics.observe_coalesce_subscriber_lag(self.tx.max_capacity() - self.tx.capacity());
+    }
+
+    /// Queues `item` without waiting; `false` drops the subscriber, either because its client
+    /// is gone or because its buffer is full.
+    fn offer(&self, item: StreamItem, metrics: &AppMetrics) -> bool {
+        self.observe_lag(metrics);
+        match self.tx.try_send(item) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.lagged.store(true, Ordering::Relaxed);
+                metrics.observe_coalesce_slow_subscriber();
+                warn!("disconnecting stream subscriber that fell behind");
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+pub struct StreamLease {
+    coalescer: Arc<InflightCoalescer>,
+    key: String,
+    generation: Option<u64>,
+}
+
+impl StreamLease {
+    /// Publishes to every subscriber and returns how many are still connected; `0` means every
+    /// client has gone and the rest of the generation is unobserved.
+    pub async fn publish(&self, item: StreamItem) -> usize {
+        match self.generation {
+            Some(generation) => self.coalescer.publish_to(generation, item).await,
+            None => 0,
+        }
+    }
+}
+
+impl Drop for StreamLease {
+    fn drop(&mut self) {
+        let Some(generation) = self.generation else {
+            return;
+        };
+        if self
+            .coalescer
+            .fail_stream(generation, "stream leader stopped before completing")
+        {
+            warn!(fingerprint = %self.key, "stream leader dropped before completion");
+        }
+    }
+}
+
+pub type StreamItem = Result<BackendChunk, BackendError>;
 
-#[derive(Debug, Default)]
 struct StreamEntry {
+    key: String,
     history: Vec<StreamItem>,
-    subscribers: Vec<mpsc::UnboundedSender<StreamItem>>,
-    done: bool,
+    history_bytes: usize,
+    subscribers: Vec<Subscriber>,
+    last_activity: Instant,
+}
+
+/// Approximate retained size of a history item: its text plus a fixed per-chunk overhead.
+fn item_size(item: &StreamItem) -> usize {
+    const CHUNK_OVERHEAD: usize = 64;
+    let text = match item {
+        Ok(chunk) => {
+            chunk.delta.as_ref().map_or(0, String::len)
+                + chunk.finish_reason.as_ref().map_or(0, String::len)
+        }
+        Err(error) => error.to_string().len(),
+    };
+    text + CHUNK_OVERHEAD
+}
+
+fn delta_len(item: &StreamItem) -> usize {
+    match item {
+        Ok(chunk) => chunk.delta.as_ref().map_or(0, String::len),
+        Err(_) => 0,
+    }
 }
 
 fn is_terminal_item(item: &StreamItem) -> bool {
@@ -152,9 +762,47 @@ fn is_terminal_item(item: &StreamItem) -> bool {
     }
 }
 
+/// Why followers fail when their one-shot leader went away without answering them.
+const LEADER_GONE: &str = "coalescing leader stopped before completion";
+
+fn fail_waiters(waiters: Vec<InflightWaiter>, message: &str) {
+    for waiter in waiters {
+        let _ = waiter.send(WaiterMessage::Finished(Err(BackendError::Unavailable(
+            message.to_owned(),
+        ))));
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn read_late_join_policy() -> LateJoinPolicy {
+    let Ok(value) = env::var("GATEWAY_COALESCE_LATE_JOIN") else {
+        return LateJoinPolicy::default();
+    };
+    LateJoinPolicy::parse(&value).unwrap_or_else(|| {
+        warn!(value = %value, "invalid GATEWAY_COALESCE_LATE_JOIN, replaying from start");
+        LateJoinPolicy::default()
+    })
+}
+
+fn read_secs(name: &str, default: Duration) -> Duration {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(default)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
 
     use async_trait::async_trait;
     use futures_util::stream::BoxStream;
@@ -162,13 +810,17 @@ mod tests {
 
     use crate::{
         backend::{BackendError, BackendStream, InferenceBackend},
+        metrics::AppMetrics,
         models::{
-            BackendChatResponse, BackendChunk, GenerationParams, MessageRole,
-            NormalizedChatRequest, NormalizedMessage, Usage,
+            BackendChatResponse, BackendChunk, ClientTags, GenerationParams, MessageRole,
+            NormalizedChatRequest, NormalizedMessage, Priority, Usage,
         },
     };
 
-    use super::{CoalesceOutcome, InflightCoalescer};
+    use super::{
+        lock, CoalesceOutcome, CoalescerConfig, InflightCoalescer, LateJoinPolicy, OneShotEntry,
+        WaiterMessage,
+    };
 
     struct SlowTestBackend;
 
@@ -180,19 +832,22 @@ mod tests {
 
         async fn execute_chat(
             &self,
-            _request: NormalizedChatRequest,
+            _request: Arc<NormalizedChatRequest>,
         ) -> Result<BackendChatResponse, BackendError> {
             sleep(Duration::from_millis(30)).await;
             Ok(BackendChatResponse {
                 content: "ok".to_owned(),
                 finish_reason: "stop".to_owned(),
+                provider_finish_reason: None,
                 usage: Usage::new(1, 1),
+                backend: None,
+                upstream_headers: None,
             })
         }
 
         async fn stream_chat(
             &self,
-            _request: NormalizedChatRequest,
+            _request: Arc<NormalizedChatRequest>,
         ) -> Result<BackendStream, BackendError> {
             let stream: BoxStream<'static, Result<BackendChunk, BackendError>> =
                 Box::pin(futures_util::stream::empty());
@@ -200,18 +855,55 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn coalesces_identical_one_shot_requests() {
-        let coalescer = Arc::new(InflightCoalescer::default());
-        let backend = Arc::new(SlowTestBackend);
+    /// Fails its first call and succeeds afterwards.
+    #[derive(Default)]
+    struct FlakyTestBackend {
+        calls: AtomicUsize,
+    }
 
-        let request = NormalizedChatRequest {
+    #[async_trait]
+    impl InferenceBackend for FlakyTestBackend {
+        fn name(&self) -> &str {
+            "flaky-test-backend"
+        }
+
+        async fn execute_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            sleep(Duration::from_millis(30)).await;
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(BackendError::Unavailable("first call fails".to_owned()));
+            }
+            Ok(BackendChatResponse {
+                content: "retried".to_owned(),
+                finish_reason: "stop".to_owned(),
+                provider_finish_reason: None,
+                usage: Usage::new(1, 1),
+                backend: None,
+                upstream_headers: None,
+            })
+        }
+
+        async fn stream_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            Err(BackendError::Unavailable(
+                "streaming unsupported".to_owned(),
+            ))
+        }
+    }
+
+    fn test_request() -> Arc<NormalizedChatRequest> {
+        Arc::new(NormalizedChatRequest {
             request_id: "req_1".to_owned(),
             user_id: "user_1".to_owned(),
             model: "mock".to_owned(),
             messages: vec![NormalizedMessage {
                 role: MessageRole::User,
                 content: "hello".to_owned(),
+                cache_control: None,
             }],
             generation: GenerationParams {
                 max_tokens: Some(20),
@@ -219,7 +911,19 @@ mod tests {
                 top_p: None,
             },
             stream: false,
-        };
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn coalesces_identical_one_shot_requests() {
+        let coalescer = Arc::new(InflightCoalescer::default());
+        let backend = Arc::new(SlowTestBackend);
+        let request = test_request();
 
         let key = "same".to_owned();
         let key_for_first = key.clone();
@@ -257,6 +961,59 @@ mod tests {
         assert_eq!(first.0.content, second.0.content);
     }
 
+    #[tokio::test]
+    async fn failed_leader_hands_off_to_follower_within_retry_budget() {
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig {
+                leader_retries: 1,
+                ..CoalescerConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let backend = Arc::new(FlakyTestBackend::default());
+
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let coalescer = Arc::clone(&coalescer);
+                let backend = backend.clone();
+                tokio::spawn(async move {
+                    coalescer
+                        .execute_or_join("flaky".to_owned(), backend, test_request())
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (response, _) = task.await.expect("task should run").expect("retried");
+            assert_eq!(response.content, "retried");
+        }
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_leader_fans_out_error_without_retry_budget() {
+        let coalescer = Arc::new(InflightCoalescer::default());
+        let backend = Arc::new(FlakyTestBackend::default());
+
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let coalescer = Arc::clone(&coalescer);
+                let backend = backend.clone();
+                tokio::spawn(async move {
+                    coalescer
+                        .execute_or_join("flaky".to_owned(), backend, test_request())
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert!(task.await.expect("task should run").is_err());
+        }
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 1);
+    }
+
     #[tokio::test]
     async fn stream_joiner_receives_history_and_live_updates() {
         let coalescer = InflightCoalescer::default();
@@ -271,8 +1028,12 @@ mod tests {
                 Ok(BackendChunk {
                     delta: Some("hello ".to_owned()),
                     finish_reason: None,
+                    provider_finish_reason: None,
                     usage: None,
                     done: false,
+                    backend: None,
+                    raw: None,
+                    upstream_headers: None,
                 }),
             )
             .await;
@@ -286,8 +1047,12 @@ mod tests {
                 Ok(BackendChunk {
                     delta: Some("world".to_owned()),
                     finish_reason: Some("stop".to_owned()),
+                    provider_finish_reason: None,
                     usage: None,
                     done: true,
+                    backend: None,
+                    raw: None,
+                    upstream_headers: None,
                 }),
             )
             .await;
@@ -308,4 +1073,323 @@ mod tests {
         assert_eq!(second.delta.as_deref(), Some("world"));
         assert!(second.done);
     }
+
+    #[tokio::test]
+    async fn cancelled_one_shot_leader_releases_followers_and_key() {
+        let coalescer = Arc::new(InflightCoalescer::default());
+        let backend: Arc<dyn InferenceBackend> = Arc::new(SlowTestBackend);
+
+        let leader = {
+            let coalescer = coalescer.clone();
+            let backend = backend.clone();
+            tokio::spawn(async move {
+                coalescer
+                    .execute_or_join("key".to_owned(), backend, test_request())
+                    .await
+            })
+        };
+        sleep(Duration::from_millis(5)).await;
+        let follower = {
+            let coalescer = coalescer.clone();
+            let backend = backend.clone();
+            tokio::spawn(async move {
+                coalescer
+                    .execute_or_join("key".to_owned(), backend, test_request())
+                    .await
+            })
+        };
+        sleep(Duration::from_millis(5)).await;
+        leader.abort();
+
+        let follower = follower.await.expect("follower task should run");
+        assert!(matches!(follower, Err(BackendError::Unavailable(_))));
+
+        let (_, outcome) = coalescer
+            .execute_or_join("key".to_owned(), backend, test_request())
+            .await
+            .expect("fresh leader");
+        assert_eq!(outcome, CoalesceOutcome::Leader);
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_lease_fails_followers() {
+        let coalescer = Arc::new(InflightCoalescer::default());
+        let key = "stream-key".to_owned();
+
+        let leader = coalescer.join_or_create_stream(key.clone()).await;
+        assert!(leader.is_leader);
+        let lease = coalescer.stream_lease(&key);
+        let mut follower = coalescer.join_or_create_stream(key.clone()).await;
+
+        drop(lease);
+        let item = follower.receiver.recv().await.expect("terminal item");
+        assert!(item.is_err());
+        assert!(coalescer.join_or_create_stream(key).await.is_leader);
+    }
+
+    #[tokio::test]
+    async fn janitor_sweep_reaps_idle_streams_and_expired_one_shots() {
+        let coalescer = InflightCoalescer::new(
+            CoalescerConfig {
+                one_shot_ttl: Duration::from_secs(30),
+                stream_idle_timeout: Duration::from_secs(30),
+                janitor_interval: Duration::from_secs(1),
+                ..CoalescerConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+        let mut follower = coalescer.join_or_create_stream("idle".to_owned()).await;
+        assert_eq!(coalescer.sweep(std::time::Instant::now()), 0);
+
+        let later = std::time::Instant::now() + Duration::from_secs(31);
+        assert_eq!(coalescer.sweep(later), 1);
+        assert!(follower.receiver.recv().await.expect("item").is_err());
+    }
+
+    #[tokio::test]
+    async fn one_shot_entries_last_as_long_as_their_leader() {
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig {
+                one_shot_ttl: Duration::from_millis(1),
+                ..CoalescerConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let leader = tokio::spawn({
+            let coalescer = coalescer.clone();
+            async move {
+                coalescer
+                    .execute_or_join("live".to_owned(), Arc::new(SlowTestBackend), test_request())
+                    .await
+            }
+        });
+        sleep(Duration::from_millis(10)).await;
+
+        // Past the TTL with its leader still running: neither reaped nor replaced.
+        let later = std::time::Instant::now() + Duration::from_secs(3_600);
+        assert_eq!(coalescer.sweep(later), 0);
+        let (_, outcome) = coalescer
+            .execute_or_join("live".to_owned(), Arc::new(SlowTestBackend), test_request())
+            .await
+            .expect("the leader's result");
+        assert_eq!(outcome, CoalesceOutcome::Joined);
+        let (_, outcome) = leader.await.expect("leader task").expect("leader result");
+        assert_eq!(outcome, CoalesceOutcome::Leader);
+
+        // An entry whose leader vanished without removing it is reaped once past the TTL.
+        let now = std::time::Instant::now();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        lock(&coalescer.inflight).insert(
+            "leaked".to_owned(),
+            OneShotEntry {
+                generation: 0,
+                created_at: std::time::Instant::now(),
+                leader: std::sync::Weak::new(),
+                waiters: vec![tx],
+                retries_used: 0,
+            },
+        );
+        assert_eq!(coalescer.sweep(now), 0);
+        assert_eq!(coalescer.sweep(later), 1);
+        assert!(matches!(
+            rx.await,
+            Ok(WaiterMessage::Finished(Err(BackendError::Unavailable(_))))
+        ));
+    }
+
+    fn delta(text: &str, done: bool) -> BackendChunk {
+        BackendChunk {
+            delta: Some(text.to_owned()),
+            finish_reason: done.then(|| "stop".to_owned()),
+            provider_finish_reason: None,
+            usage: None,
+            done,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn history_cap_closes_stream_to_late_joiners() {
+        let metrics = Arc::new(AppMetrics::new());
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig {
+                stream_history_limit: 1,
+                ..CoalescerConfig::default()
+            },
+            metrics.clone(),
+        ));
+        let key = "capped".to_owned();
+
+        let mut leader = coalescer.join_or_create_stream(key.clone()).await;
+        let lease = coalescer.stream_lease(&key);
+        lease.publish(Ok(delta("a", false))).await;
+        assert!(metrics
+            .render()
+            .expect("render")
+            .contains("gateway_coalesce_stream_history_bytes 65"));
+
+        lease.publish(Ok(delta("b", false))).await;
+        let late = coalescer.join_or_create_stream(key.clone()).await;
+        assert!(late.is_leader, "capped entry must not accept joiners");
+
+        lease.publish(Ok(delta("c", true))).await;
+        let mut received = Vec::new();
+        while let Some(Ok(chunk)) = leader.receiver.recv().await {
+            received.push(chunk.delta.unwrap_or_default());
+            if chunk.done {
+                break;
+            }
+        }
+        assert_eq!(received, vec!["a", "b", "c"]);
+
+        let rendered = metrics.render().expect("render");
+        assert!(rendered.contains("gateway_coalesce_stream_history_bytes 0"));
+        assert!(rendered.contains("gateway_coalesce_stream_history_truncated_total 1"));
+    }
+
+    #[tokio::test]
+    async fn stream_followers_are_counted_with_replayed_and_saved_bytes() {
+        let metrics = Arc::new(AppMetrics::new());
+        let coalescer = InflightCoalescer::new(CoalescerConfig::default(), metrics.clone());
+        let key = "counted".to_owned();
+
+        let _leader = coalescer.join_or_create_stream(key.clone()).await;
+        coalescer
+            .publish_stream_item(&key, Ok(delta("hello ", false)))
+            .await;
+        let _follower = coalescer.join_or_create_stream(key.clone()).await;
+        coalescer
+            .publish_stream_item(&key, Ok(delta("world", true)))
+            .await;
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered
+            .contains("gateway_coalesce_requests_total{mode=\"stream\",role=\"follower\"} 1"));
+        assert!(rendered.contains("gateway_coalesce_stream_replay_chunks_sum 1"));
+        assert!(rendered.contains("gateway_coalesce_bytes_saved_total{mode=\"stream\"} 11"));
+    }
+
+    #[tokio::test]
+    async fn live_only_joiners_skip_history() {
+        let coalescer = InflightCoalescer::new(
+            CoalescerConfig {
+                late_join: LateJoinPolicy::LiveOnly,
+                ..CoalescerConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+        let key = "live".to_owned();
+
+        let _leader = coalescer.join_or_create_stream(key.clone()).await;
+        coalescer
+            .publish_stream_item(&key, Ok(delta("early", false)))
+            .await;
+        let mut follower = coalescer.join_or_create_stream(key.clone()).await;
+        assert!(!follower.is_leader);
+        coalescer
+            .publish_stream_item(&key, Ok(delta("late", true)))
+            .await;
+
+        let first = follower
+            .receiver
+            .recv()
+            .await
+            .expect("live chunk")
+            .expect("ok chunk");
+        assert_eq!(first.delta.as_deref(), Some("late"));
+    }
+
+    #[tokio::test]
+    async fn lease_reports_remaining_listeners() {
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig::default(),
+            Arc::new(AppMetrics::new()),
+        ));
+        let key = "listeners".to_owned();
+
+        let leader = coalescer.join_or_create_stream(key.clone()).await;
+        let lease = coalescer.stream_lease(&key);
+        let follower = coalescer.join_or_create_stream(key.clone()).await;
+        assert_eq!(lease.publish(Ok(delta("a", false))).await, 2);
+
+        drop(follower);
+        assert_eq!(lease.publish(Ok(delta("b", false))).await, 1);
+        drop(leader);
+        assert_eq!(lease.publish(Ok(delta("c", true))).await, 0);
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_are_disconnected_without_stalling_the_stream() {
+        let metrics = Arc::new(AppMetrics::new());
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig {
+                subscriber_buffer: 2,
+                ..CoalescerConfig::default()
+            },
+            metrics.clone(),
+        ));
+        let key = "slow".to_owned();
+
+        let mut leader = coalescer.join_or_create_stream(key.clone()).await;
+        let lease = coalescer.stream_lease(&key);
+        let mut slow = coalescer.join_or_create_stream(key.clone()).await;
+        let mut received = Vec::new();
+        for (index, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let listeners = lease.publish(Ok(delta(text, index == 3))).await;
+            assert_eq!(listeners, if index < 2 { 2 } else { 1 });
+            let chunk = leader.receiver.recv().await.expect("chunk").expect("ok");
+            received.push(chunk.delta.unwrap_or_default());
+        }
+        assert_eq!(received, vec!["a", "b", "c", "d"]);
+
+        for expected in ["a", "b"] {
+            let chunk = slow.receiver.recv().await.expect("chunk").expect("ok");
+            assert_eq!(chunk.delta.as_deref(), Some(expected));
+        }
+        assert!(matches!(
+            slow.receiver.recv().await,
+            Some(Err(BackendError::Unavailable(_)))
+        ));
+        assert!(slow.receiver.recv().await.is_none());
+
+        let rendered = metrics.render().expect("render");
+        assert!(rendered.contains("gateway_coalesce_stream_slow_subscribers_disconnected_total 1"));
+        assert!(rendered.contains("gateway_coalesce_stream_subscriber_lag_chunks_count 7"));
+    }
+
+    #[tokio::test]
+    async fn a_slow_leader_is_waited_for_instead_of_disconnected() {
+        let metrics = Arc::new(AppMetrics::new());
+        let coalescer = Arc::new(InflightCoalescer::new(
+            CoalescerConfig {
+                subscriber_buffer: 1,
+                ..CoalescerConfig::default()
+            },
+            metrics.clone(),
+        ));
+        let key = "slow-leader".to_owned();
+
+        let mut leader = coalescer.join_or_create_stream(key.clone()).await;
+        let lease = coalescer.stream_lease(&key);
+        let publisher = tokio::spawn(async move {
+            for (index, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+                assert_eq!(lease.publish(Ok(delta(text, index == 3))).await, 1);
+            }
+        });
+        // The publisher blocks on the full buffer until the leader catches up.
+        sleep(Duration::from_millis(20)).await;
+        assert!(!publisher.is_finished());
+
+        let mut received = Vec::new();
+        while let Some(item) = leader.receiver.recv().await {
+            received.push(item.expect("ok").delta.unwrap_or_default());
+        }
+        assert_eq!(received, vec!["a", "b", "c", "d"]);
+        publisher.await.expect("publisher");
+
+        let rendered = metrics.render().expect("render");
+        assert!(rendered.contains("gateway_coalesce_stream_slow_subscribers_disconnected_total 0"));
+    }
 }
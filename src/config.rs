@@ -0,0 +1,1224 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    admin::Cidr,
+    auth::KeyPolicy,
+    backend::{client::parse_proxy, mock::MockFaultRule, peer::PeerConfig, vcr::VcrMode},
+    cache::{CacheScope, ModelCacheRule},
+    catalog::ModelCatalog,
+    coalescing::LateJoinPolicy,
+    context_window::{ContextOverflow, ContextWindow},
+    egress::EgressLimit,
+    error_reporting::SentryDsn,
+    experiments::Experiments,
+    forwarded_headers::ForwardedHeaders,
+    limits::QuotaDay,
+    metrics::parse_buckets,
+    model_pools::ModelPoolRule,
+    models::Priority,
+    postprocess::ProcessorSpec,
+    pricing::ModelPrice,
+    router::{EndpointLimit, RetryClass},
+    scheduler::FingerprintHash,
+    tenants::TenantPolicy,
+    traces::{TraceFormat, TraceTarget},
+    truncation::TruncationStrategy,
+};
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("invalid config file {path}: `{field}`: {message}")]
+    Invalid {
+        path: PathBuf,
+        field: &'static str,
+        message: String,
+    },
+}
+
+/// Typed gateway configuration read from the TOML file named by `GATEWAY_CONFIG`.
+///
+/// Every setting maps onto one of the documented environment variables. The file supplies
+/// defaults for them and a variable that is already set still wins, so deployments can keep
+/// overriding single settings (or secrets) from the environment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    pub listen: ListenSection,
+    pub backends: BackendsSection,
+    pub redis: RedisSection,
+    pub auth: AuthSection,
+    pub limits: LimitsSection,
+    pub cache: CacheSection,
+    pub batching: BatchingSection,
+    pub streams: StreamsSection,
+    pub coalescing: CoalescingSection,
+    pub admission: AdmissionSection,
+    pub model_pools: ModelPoolsSection,
+    pub fair_queue: FairQueueSection,
+    pub retry: RetrySection,
+    pub backend_pools: BackendPoolsSection,
+    pub metrics: MetricsSection,
+    pub admin: AdminSection,
+    pub logging: LoggingSection,
+    pub context: ContextSection,
+    pub pricing: PricingSection,
+    /// `[models.<id>]` tables in the `GATEWAY_MODEL_CATALOG` shape.
+    pub models: Option<serde_json::Map<String, serde_json::Value>>,
+    pub post_processing: PostProcessingSection,
+    pub usage: UsageSection,
+    pub error_reporting: ErrorReportingSection,
+    pub traces: TracesSection,
+    /// `[[experiments]]` tables in the `GATEWAY_EXPERIMENTS` shape.
+    pub experiments: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenSection {
+    pub addr: Option<SocketAddr>,
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    pub tls_reload_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendsSection {
+    /// Upstream response headers passed on to clients; all others are dropped.
+    pub forward_response_headers: Option<Vec<String>>,
+    pub openai: OpenAiSection,
+    pub peers: PeersSection,
+    pub vcr: VcrSection,
+    pub mock: MockSection,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiSection {
+    pub api_key: Option<String>,
+    /// Tried in order once the provider rejects `api_key`.
+    pub fallback_api_keys: Option<Vec<String>>,
+    pub base_url: Option<String>,
+    pub timeout_secs: Option<u64>,
+    /// `http(s)://` or `socks5(h)://` proxy the adapter's requests go through.
+    pub proxy: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    /// PEM root certificates trusted on top of the built-in ones.
+    pub ca_bundle: Option<PathBuf>,
+    /// Forward messages' `cache_control` hints upstream.
+    pub cache_control: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersSection {
+    /// `name=url` peer gateways, registered as `peer:<name>` backends.
+    pub endpoints: Option<Vec<String>>,
+    pub api_key: Option<String>,
+    /// Signs the forwarded caller, and verifies callers forwarded to this gateway.
+    pub secret: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    pub ca_bundle: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VcrSection {
+    /// `off`, `record`, or `replay`.
+    pub mode: Option<String>,
+    pub dir: Option<PathBuf>,
+    pub paced_replay: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockSection {
+    /// Fault-injection rules for the mock backends, first matching model wins.
+    pub faults: Option<Vec<serde_json::Value>>,
+    /// JSON file of scripted mock responses.
+    pub script: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisSection {
+    pub url: Option<String>,
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    pub api_keys: Option<Vec<String>>,
+    /// Per-key policies keyed by API key, in the `GATEWAY_KEY_POLICIES` shape.
+    pub key_policies: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Tenant policies keyed by tenant id, in the `GATEWAY_TENANTS` shape.
+    pub tenants: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    /// Local `HH:MM` at which daily token quotas roll over, in `quota_timezone`.
+    pub quota_day_start: Option<String>,
+    /// `UTC` or a fixed `±HH:MM` offset.
+    pub quota_timezone: Option<String>,
+    pub max_body_bytes: Option<usize>,
+    pub max_json_depth: Option<usize>,
+    /// Server-side cap on each request's duration, streams included; `0` disables it.
+    pub request_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSection {
+    pub ttl_secs: Option<u64>,
+    pub stale_secs: Option<u64>,
+    pub scope: Option<String>,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub sweep_interval_secs: Option<u64>,
+    pub disk_path: Option<PathBuf>,
+    pub streams: Option<bool>,
+    pub stream_paced_replay: Option<bool>,
+    /// `model_glob=off|ttl_secs` rules, first match wins.
+    pub model_rules: Option<Vec<String>>,
+    pub fingerprint_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchingSection {
+    pub enabled: Option<bool>,
+    pub max_size: Option<usize>,
+    pub max_wait_ms: Option<u64>,
+    pub workers: Option<usize>,
+    pub queue_capacity: Option<usize>,
+    pub overload_retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamsSection {
+    pub max_concurrency: Option<usize>,
+    pub queue_capacity: Option<usize>,
+    pub queue_timeout_ms: Option<u64>,
+    /// Aborts streams whose backend sends nothing for this long; `0` disables it.
+    pub idle_timeout_secs: Option<u64>,
+    /// Forwards upstream SSE events verbatim on uncoalesced streams.
+    pub passthrough: Option<bool>,
+    /// Seconds of silence before a `: <keepalive_text>` comment is sent; `0` disables them.
+    pub keepalive_secs: Option<u64>,
+    pub keepalive_text: Option<String>,
+    /// Ends streams running longer than this with a timeout error event; `0` disables it.
+    pub max_duration_secs: Option<u64>,
+    /// Merges upstream deltas until this many characters are held; `0` disables it.
+    pub merge_min_chars: Option<usize>,
+    pub merge_max_delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoalescingSection {
+    pub ttl_secs: Option<u64>,
+    pub stream_idle_secs: Option<u64>,
+    pub janitor_interval_secs: Option<u64>,
+    pub stream_history_max_chunks: Option<usize>,
+    pub subscriber_buffer: Option<usize>,
+    pub late_join: Option<String>,
+    pub sampled: Option<bool>,
+    pub leader_retries: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionSection {
+    pub max_concurrency: Option<usize>,
+    pub queue_capacity: Option<usize>,
+    pub queue_timeout_ms: Option<u64>,
+    /// `0` holds queued streams without progress comments.
+    pub progress_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelPoolsSection {
+    /// `model_glob=max_concurrency` pools, first match wins.
+    pub pools: Option<Vec<String>>,
+    pub queue_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FairQueueSection {
+    pub max_concurrency: Option<usize>,
+    pub tier_weights: Option<BTreeMap<String, u32>>,
+    /// Default and maximum priority for keys of each tier whose policy sets none.
+    pub tier_priorities: Option<BTreeMap<String, Priority>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrySection {
+    pub max_attempts: Option<u32>,
+    pub backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+    /// `unavailable` and/or `timeout`.
+    pub retry_on: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendPoolsSection {
+    /// `backend-glob=max_concurrency` entries.
+    pub limits: Option<Vec<String>>,
+    /// Globs naming the backends that only take overflow.
+    pub spillover: Option<Vec<String>>,
+    /// `backend-glob=requests_per_sec[:tokens_per_min]` entries.
+    pub egress: Option<Vec<String>>,
+    pub egress_max_wait_ms: Option<u64>,
+    /// Pace every backend by the budgets its provider reports in rate-limit headers.
+    pub egress_follow_provider: Option<bool>,
+    /// Health-check outcomes kept per backend for `GET /admin/backends`.
+    pub health_history: Option<u32>,
+    /// Successes in a row that fully close a backend's circuit after its cooldown.
+    pub recovery_successes: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    pub latency_buckets: Option<Vec<f64>>,
+    pub max_tier_labels: Option<usize>,
+    pub max_tenant_labels: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSection {
+    pub token: Option<String>,
+    pub allowed_cidrs: Option<Vec<String>>,
+    pub listen_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    /// `off`, `stdout`, or a file path.
+    pub access_log: Option<String>,
+    pub slow_request_ms: Option<u64>,
+    /// Adds streamed completion text and chunk timings to access-log lines.
+    pub stream_transcripts: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContextSection {
+    /// `model_glob=tokens` context-window sizes, first match wins.
+    pub windows: Option<Vec<String>>,
+    /// `reject` or `truncate`.
+    pub overflow: Option<String>,
+    /// `off`, `oldest`, or `summarize`.
+    pub truncation: Option<String>,
+    pub summary_model: Option<String>,
+    pub summary_max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricingSection {
+    /// `model_glob=prompt_usd:completion_usd` prices per million tokens, first match wins.
+    pub models: Option<Vec<String>>,
+    /// Deducts each request's cost from its tenant's prepaid balance.
+    pub prepaid_credits: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostProcessingSection {
+    /// Named response processors, in the `GATEWAY_POST_PROCESSORS` shape.
+    pub processors: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsageSection {
+    pub sink: Option<String>,
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_table: Option<String>,
+    pub clickhouse_user: Option<String>,
+    pub clickhouse_password: Option<String>,
+    pub batch_size: Option<usize>,
+    pub flush_interval_ms: Option<u64>,
+    pub queue_capacity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorReportingSection {
+    pub sentry_dsn: Option<String>,
+    pub sentry_environment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracesSection {
+    /// `off`, `langfuse`, or `otlp`.
+    pub export: Option<String>,
+    pub endpoint: Option<String>,
+    pub langfuse_public_key: Option<String>,
+    pub langfuse_secret_key: Option<String>,
+    /// `name=value` headers sent to OTLP collectors.
+    pub headers: Option<Vec<String>>,
+    pub capture_content: Option<bool>,
+}
+
+impl GatewayConfig {
+    /// Loads the file named by `GATEWAY_CONFIG`, if set.
+    pub fn load_from_env() -> Result<Option<Self>, ConfigError> {
+        match env::var_os("GATEWAY_CONFIG") {
+            Some(path) if !path.is_empty() => Self::from_file(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::parse(&raw).map_err(|error| error.at(path))
+    }
+
+    /// Parses and validates TOML; errors carry a placeholder path until [`ConfigError::at`].
+    pub fn parse(raw: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(raw).map_err(|error| ConfigError::Parse {
+            path: PathBuf::new(),
+            message: error.to_string(),
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks values whose syntax the type alone does not capture, using the same parsers the
+    /// environment readers use.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field, message: String| ConfigError::Invalid {
+            path: PathBuf::new(),
+            field,
+            message,
+        };
+        if self.listen.tls_cert_path.is_some() != self.listen.tls_key_path.is_some() {
+            return Err(invalid(
+                "listen.tls_key_path",
+                "`tls_cert_path` and `tls_key_path` must be set together".to_owned(),
+            ));
+        }
+        if let Some(mode) = &self.backends.vcr.mode {
+            VcrMode::parse(mode).ok_or_else(|| {
+                invalid(
+                    "backends.vcr.mode",
+                    format!("unknown mode `{mode}`, expected `off`, `record`, or `replay`"),
+                )
+            })?;
+        }
+        if self
+            .backends
+            .openai
+            .fallback_api_keys
+            .as_ref()
+            .is_some_and(|keys| !keys.is_empty())
+            && self.backends.openai.api_key.is_none()
+        {
+            return Err(invalid(
+                "backends.openai.api_key",
+                "required when `backends.openai.fallback_api_keys` is set".to_owned(),
+            ));
+        }
+        for (field, proxy) in [
+            ("backends.openai.proxy", &self.backends.openai.proxy),
+            ("backends.peers.proxy", &self.backends.peers.proxy),
+        ] {
+            if let Some(proxy) = proxy {
+                parse_proxy(proxy).map_err(|error| invalid(field, error))?;
+            }
+        }
+        if let Some(endpoints) = &self.backends.peers.endpoints {
+            PeerConfig::parse_list(&endpoints.join(","))
+                .map_err(|error| invalid("backends.peers.endpoints", error))?;
+            if !endpoints.is_empty() && self.backends.peers.api_key.is_none() {
+                return Err(invalid(
+                    "backends.peers.api_key",
+                    "required when `backends.peers.endpoints` is set".to_owned(),
+                ));
+            }
+        }
+        let quota_day_start = self.limits.quota_day_start.as_deref().unwrap_or("00:00");
+        QuotaDay::parse(quota_day_start, "UTC")
+            .map_err(|error| invalid("limits.quota_day_start", error))?;
+        QuotaDay::parse(
+            quota_day_start,
+            self.limits.quota_timezone.as_deref().unwrap_or("UTC"),
+        )
+        .map_err(|error| invalid("limits.quota_timezone", error))?;
+        if self
+            .streams
+            .keepalive_text
+            .as_ref()
+            .is_some_and(|text| text.contains(['\n', '\r']))
+        {
+            return Err(invalid(
+                "streams.keepalive_text",
+                "must not contain line breaks".to_owned(),
+            ));
+        }
+        if let Some(scope) = &self.cache.scope {
+            CacheScope::parse(scope).ok_or_else(|| {
+                invalid(
+                    "cache.scope",
+                    format!("unknown scope `{scope}`, expected `global`, `key`, or `tenant`"),
+                )
+            })?;
+        }
+        if let Some(hash) = &self.cache.fingerprint_hash {
+            FingerprintHash::parse(hash).ok_or_else(|| {
+                invalid(
+                    "cache.fingerprint_hash",
+                    format!("unknown hash `{hash}`, expected `sha256` or `blake3`"),
+                )
+            })?;
+        }
+        if let Some(rules) = &self.cache.model_rules {
+            ModelCacheRule::parse_list(&rules.join(","))
+                .map_err(|error| invalid("cache.model_rules", error))?;
+        }
+        if let Some(late_join) = &self.coalescing.late_join {
+            LateJoinPolicy::parse(late_join).ok_or_else(|| {
+                invalid(
+                    "coalescing.late_join",
+                    format!("unknown policy `{late_join}`, expected `replay` or `live`"),
+                )
+            })?;
+        }
+        if let Some(pools) = &self.model_pools.pools {
+            ModelPoolRule::parse_list(&pools.join(","))
+                .map_err(|error| invalid("model_pools.pools", error))?;
+        }
+        if let Some(classes) = &self.retry.retry_on {
+            RetryClass::parse_list(&classes.join(","))
+                .map_err(|error| invalid("retry.retry_on", error))?;
+        }
+        if let Some(limits) = &self.backend_pools.limits {
+            EndpointLimit::parse_list(&limits.join(","))
+                .map_err(|error| invalid("backend_pools.limits", error))?;
+        }
+        if let Some(egress) = &self.backend_pools.egress {
+            EgressLimit::parse_list(&egress.join(","))
+                .map_err(|error| invalid("backend_pools.egress", error))?;
+        }
+        if self.backend_pools.recovery_successes == Some(0) {
+            return Err(invalid(
+                "backend_pools.recovery_successes",
+                "must be at least 1".to_owned(),
+            ));
+        }
+        if let Some(buckets) = &self.metrics.latency_buckets {
+            parse_buckets(&join(buckets))
+                .map_err(|error| invalid("metrics.latency_buckets", error))?;
+        }
+        if let Some(cidrs) = &self.admin.allowed_cidrs {
+            Cidr::parse_list(&cidrs.join(","))
+                .map_err(|error| invalid("admin.allowed_cidrs", error))?;
+        }
+        if let Some(windows) = &self.context.windows {
+            ContextWindow::parse_list(&windows.join(","))
+                .map_err(|error| invalid("context.windows", error))?;
+        }
+        if let Some(overflow) = &self.context.overflow {
+            ContextOverflow::parse(overflow).map_err(|error| invalid("context.overflow", error))?;
+        }
+        if let Some(truncation) = &self.context.truncation {
+            TruncationStrategy::parse(truncation)
+                .map_err(|error| invalid("context.truncation", error))?;
+        }
+        if let Some(prices) = &self.pricing.models {
+            ModelPrice::parse_list(&prices.join(","))
+                .map_err(|error| invalid("pricing.models", error))?;
+        }
+        if let Some(models) = &self.models {
+            ModelCatalog::parse(&serde_json::Value::Object(models.clone()).to_string())
+                .map_err(|error| invalid("models", error))?;
+        }
+        if let Some(policies) = &self.auth.key_policies {
+            serde_json::from_value::<HashMap<String, KeyPolicy>>(serde_json::Value::Object(
+                policies.clone(),
+            ))
+            .map_err(|error| invalid("auth.key_policies", error.to_string()))?;
+        }
+        if let Some(headers) = &self.backends.forward_response_headers {
+            ForwardedHeaders::new(headers)
+                .map_err(|error| invalid("backends.forward_response_headers", error))?;
+        }
+        if let Some(faults) = &self.backends.mock.faults {
+            serde_json::from_value::<Vec<MockFaultRule>>(serde_json::Value::Array(faults.clone()))
+                .map_err(|error| invalid("backends.mock.faults", error.to_string()))?;
+        }
+        if let Some(tenants) = &self.auth.tenants {
+            serde_json::from_value::<HashMap<String, TenantPolicy>>(serde_json::Value::Object(
+                tenants.clone(),
+            ))
+            .map_err(|error| invalid("auth.tenants", error.to_string()))?;
+        }
+        if let Some(processors) = &self.post_processing.processors {
+            serde_json::from_value::<HashMap<String, ProcessorSpec>>(serde_json::Value::Object(
+                processors.clone(),
+            ))
+            .map_err(|error| invalid("post_processing.processors", error.to_string()))?;
+        }
+        if let Some(experiments) = &self.experiments {
+            Experiments::parse(&serde_json::Value::Array(experiments.clone()).to_string())
+                .map_err(|error| invalid("experiments", error))?;
+        }
+        if let Some(sink) = &self.usage.sink {
+            if !matches!(
+                sink.trim().to_ascii_lowercase().as_str(),
+                "off" | "clickhouse"
+            ) {
+                return Err(invalid(
+                    "usage.sink",
+                    format!("unknown sink `{sink}`, expected `off` or `clickhouse`"),
+                ));
+            }
+            if sink.trim().eq_ignore_ascii_case("clickhouse") && self.usage.clickhouse_url.is_none()
+            {
+                return Err(invalid(
+                    "usage.clickhouse_url",
+                    "required when `usage.sink` is `clickhouse`".to_owned(),
+                ));
+            }
+        }
+        if let Some(dsn) = &self.error_reporting.sentry_dsn {
+            SentryDsn::parse(dsn).map_err(|error| invalid("error_reporting.sentry_dsn", error))?;
+        }
+        if let Some(export) = &self.traces.export {
+            let format =
+                TraceFormat::parse(export).map_err(|error| invalid("traces.export", error))?;
+            if format == TraceFormat::Langfuse
+                && (self.traces.langfuse_public_key.is_none()
+                    || self.traces.langfuse_secret_key.is_none())
+            {
+                return Err(invalid(
+                    "traces.langfuse_public_key",
+                    "`langfuse_public_key` and `langfuse_secret_key` are required when \
+                     `traces.export` is `langfuse`"
+                        .to_owned(),
+                ));
+            }
+        }
+        if let Some(headers) = &self.traces.headers {
+            TraceTarget::parse_headers(&headers.join(","))
+                .map_err(|error| invalid("traces.headers", error))?;
+        }
+        Ok(())
+    }
+
+    /// The environment variable each configured setting stands for, in the format that
+    /// variable's reader expects.
+    pub fn env_defaults(&self) -> Vec<(&'static str, String)> {
+        let mut vars = EnvVars::default();
+        let listen = &self.listen;
+        vars.set("GATEWAY_LISTEN_ADDR", &listen.addr);
+        for (name, path) in [
+            ("GATEWAY_TLS_CERT_PATH", &listen.tls_cert_path),
+            ("GATEWAY_TLS_KEY_PATH", &listen.tls_key_path),
+        ] {
+            if let Some(path) = path {
+                vars.push(name, path.display().to_string());
+            }
+        }
+        vars.set("GATEWAY_TLS_RELOAD_SECS", &listen.tls_reload_secs);
+        vars.set_list(
+            "GATEWAY_FORWARD_RESPONSE_HEADERS",
+            &self.backends.forward_response_headers,
+        );
+        let openai = &self.backends.openai;
+        vars.set("OPENAI_API_KEY", &openai.api_key);
+        vars.set_list("OPENAI_FALLBACK_API_KEYS", &openai.fallback_api_keys);
+        vars.set("OPENAI_BASE_URL", &openai.base_url);
+        vars.set("OPENAI_TIMEOUT_SECS", &openai.timeout_secs);
+        vars.set("OPENAI_PROXY", &openai.proxy);
+        vars.set_list("OPENAI_NO_PROXY", &openai.no_proxy);
+        vars.set("OPENAI_CACHE_CONTROL", &openai.cache_control);
+        let peers = &self.backends.peers;
+        vars.set_list("GATEWAY_PEERS", &peers.endpoints);
+        vars.set("GATEWAY_PEER_API_KEY", &peers.api_key);
+        vars.set("GATEWAY_PEER_SECRET", &peers.secret);
+        vars.set("GATEWAY_PEER_TIMEOUT_SECS", &peers.timeout_secs);
+        vars.set("GATEWAY_PEER_PROXY", &peers.proxy);
+        vars.set_list("GATEWAY_PEER_NO_PROXY", &peers.no_proxy);
+        for (name, path) in [
+            ("OPENAI_CA_BUNDLE", &openai.ca_bundle),
+            ("GATEWAY_PEER_CA_BUNDLE", &peers.ca_bundle),
+        ] {
+            if let Some(path) = path {
+                vars.push(name, path.display().to_string());
+            }
+        }
+        let vcr = &self.backends.vcr;
+        vars.set("GATEWAY_VCR_MODE", &vcr.mode);
+        if let Some(dir) = &vcr.dir {
+            vars.push("GATEWAY_VCR_DIR", dir.display().to_string());
+        }
+        vars.set("GATEWAY_VCR_PACED_REPLAY", &vcr.paced_replay);
+        if let Some(faults) = &self.backends.mock.faults {
+            vars.push(
+                "GATEWAY_MOCK_FAULTS",
+                serde_json::Value::Array(faults.clone()).to_string(),
+            );
+        }
+        if let Some(script) = &self.backends.mock.script {
+            vars.push("GATEWAY_MOCK_SCRIPT", script.display().to_string());
+        }
+        vars.set("REDIS_URL", &self.redis.url);
+        vars.set("GATEWAY_REDIS_PREFIX", &self.redis.prefix);
+
+        vars.set_list("GATEWAY_API_KEYS", &self.auth.api_keys);
+        if let Some(policies) = &self.auth.key_policies {
+            vars.push(
+                "GATEWAY_KEY_POLICIES",
+                serde_json::Value::Object(policies.clone()).to_string(),
+            );
+        }
+        if let Some(tenants) = &self.auth.tenants {
+            vars.push(
+                "GATEWAY_TENANTS",
+                serde_json::Value::Object(tenants.clone()).to_string(),
+            );
+        }
+        let limits = &self.limits;
+        vars.set(
+            "GATEWAY_LIMIT_REQUESTS_PER_MINUTE",
+            &limits.requests_per_minute,
+        );
+        vars.set("GATEWAY_LIMIT_TOKENS_PER_MINUTE", &limits.tokens_per_minute);
+        vars.set("GATEWAY_LIMIT_TOKENS_PER_DAY", &limits.tokens_per_day);
+        vars.set("GATEWAY_QUOTA_DAY_START", &limits.quota_day_start);
+        vars.set("GATEWAY_QUOTA_TIMEZONE", &limits.quota_timezone);
+        vars.set("GATEWAY_MAX_BODY_BYTES", &limits.max_body_bytes);
+        vars.set("GATEWAY_MAX_JSON_DEPTH", &limits.max_json_depth);
+        vars.set("GATEWAY_REQUEST_TIMEOUT_MS", &limits.request_timeout_ms);
+
+        let cache = &self.cache;
+        vars.set("GATEWAY_CACHE_TTL_SECS", &cache.ttl_secs);
+        vars.set("GATEWAY_CACHE_STALE_SECS", &cache.stale_secs);
+        vars.set("GATEWAY_CACHE_SCOPE", &cache.scope);
+        vars.set("GATEWAY_CACHE_MAX_ENTRIES", &cache.max_entries);
+        vars.set("GATEWAY_CACHE_MAX_BYTES", &cache.max_bytes);
+        vars.set(
+            "GATEWAY_CACHE_SWEEP_INTERVAL_SECS",
+            &cache.sweep_interval_secs,
+        );
+        if let Some(path) = &cache.disk_path {
+            vars.push("GATEWAY_CACHE_DISK_PATH", path.display().to_string());
+        }
+        vars.set("GATEWAY_CACHE_STREAMS", &cache.streams);
+        vars.set(
+            "GATEWAY_CACHE_STREAM_PACED_REPLAY",
+            &cache.stream_paced_replay,
+        );
+        vars.set_list("GATEWAY_CACHE_MODEL_RULES", &cache.model_rules);
+        vars.set("GATEWAY_CACHE_FINGERPRINT_HASH", &cache.fingerprint_hash);
+
+        let batching = &self.batching;
+        vars.set("GATEWAY_BATCH_ENABLED", &batching.enabled);
+        vars.set("GATEWAY_BATCH_MAX_SIZE", &batching.max_size);
+        vars.set("GATEWAY_BATCH_MAX_WAIT_MS", &batching.max_wait_ms);
+        vars.set("GATEWAY_BATCH_WORKERS", &batching.workers);
+        vars.set("GATEWAY_BATCH_QUEUE_CAPACITY", &batching.queue_capacity);
+        vars.set(
+            "GATEWAY_OVERLOAD_RETRY_AFTER_SECS",
+            &batching.overload_retry_after_secs,
+        );
+        let streams = &self.streams;
+        vars.set("GATEWAY_STREAM_MAX_CONCURRENCY", &streams.max_concurrency);
+        vars.set("GATEWAY_STREAM_QUEUE_CAPACITY", &streams.queue_capacity);
+        vars.set("GATEWAY_STREAM_QUEUE_TIMEOUT_MS", &streams.queue_timeout_ms);
+        vars.set(
+            "GATEWAY_STREAM_IDLE_TIMEOUT_SECS",
+            &streams.idle_timeout_secs,
+        );
+        vars.set("GATEWAY_STREAM_PASSTHROUGH", &streams.passthrough);
+        vars.set("GATEWAY_STREAM_KEEPALIVE_SECS", &streams.keepalive_secs);
+        vars.set("GATEWAY_STREAM_KEEPALIVE_TEXT", &streams.keepalive_text);
+        vars.set(
+            "GATEWAY_STREAM_MAX_DURATION_SECS",
+            &streams.max_duration_secs,
+        );
+        vars.set("GATEWAY_STREAM_MERGE_MIN_CHARS", &streams.merge_min_chars);
+        vars.set(
+            "GATEWAY_STREAM_MERGE_MAX_DELAY_MS",
+            &streams.merge_max_delay_ms,
+        );
+
+        let coalescing = &self.coalescing;
+        vars.set("GATEWAY_COALESCE_TTL_SECS", &coalescing.ttl_secs);
+        vars.set(
+            "GATEWAY_COALESCE_STREAM_IDLE_SECS",
+            &coalescing.stream_idle_secs,
+        );
+        vars.set(
+            "GATEWAY_COALESCE_JANITOR_INTERVAL_SECS",
+            &coalescing.janitor_interval_secs,
+        );
+        vars.set(
+            "GATEWAY_COALESCE_STREAM_HISTORY_MAX_CHUNKS",
+            &coalescing.stream_history_max_chunks,
+        );
+        vars.set(
+            "GATEWAY_COALESCE_SUBSCRIBER_BUFFER",
+            &coalescing.subscriber_buffer,
+        );
+        vars.set("GATEWAY_COALESCE_LATE_JOIN", &coalescing.late_join);
+        vars.set("GATEWAY_COALESCE_SAMPLED", &coalescing.sampled);
+        vars.set(
+            "GATEWAY_COALESCE_LEADER_RETRIES",
+            &coalescing.leader_retries,
+        );
+
+        let admission = &self.admission;
+        vars.set(
+            "GATEWAY_ADMISSION_MAX_CONCURRENCY",
+            &admission.max_concurrency,
+        );
+        vars.set(
+            "GATEWAY_ADMISSION_QUEUE_CAPACITY",
+            &admission.queue_capacity,
+        );
+        vars.set(
+            "GATEWAY_ADMISSION_QUEUE_TIMEOUT_MS",
+            &admission.queue_timeout_ms,
+        );
+        vars.set("GATEWAY_ADMISSION_PROGRESS_MS", &admission.progress_ms);
+        vars.set_list("GATEWAY_MODEL_POOLS", &self.model_pools.pools);
+        vars.set(
+            "GATEWAY_MODEL_POOL_QUEUE_TIMEOUT_MS",
+            &self.model_pools.queue_timeout_ms,
+        );
+        vars.set(
+            "GATEWAY_FAIR_MAX_CONCURRENCY",
+            &self.fair_queue.max_concurrency,
+        );
+        if let Some(weights) = &self.fair_queue.tier_weights {
+            vars.push(
+                "GATEWAY_FAIR_TIER_WEIGHTS",
+                serde_json::to_string(weights).unwrap_or_default(),
+            );
+        }
+        if let Some(priorities) = &self.fair_queue.tier_priorities {
+            vars.push(
+                "GATEWAY_FAIR_TIER_PRIORITIES",
+                serde_json::to_string(priorities).unwrap_or_default(),
+            );
+        }
+
+        let retry = &self.retry;
+        vars.set("GATEWAY_RETRY_MAX_ATTEMPTS", &retry.max_attempts);
+        vars.set("GATEWAY_RETRY_BACKOFF_MS", &retry.backoff_ms);
+        vars.set("GATEWAY_RETRY_MAX_BACKOFF_MS", &retry.max_backoff_ms);
+        vars.set_list("GATEWAY_RETRY_ON", &retry.retry_on);
+        vars.set_list("GATEWAY_BACKEND_CONCURRENCY", &self.backend_pools.limits);
+        vars.set_list("GATEWAY_SPILLOVER_BACKENDS", &self.backend_pools.spillover);
+        vars.set_list("GATEWAY_BACKEND_EGRESS", &self.backend_pools.egress);
+        vars.set(
+            "GATEWAY_BACKEND_EGRESS_MAX_WAIT_MS",
+            &self.backend_pools.egress_max_wait_ms,
+        );
+        vars.set(
+            "GATEWAY_BACKEND_EGRESS_FOLLOW_PROVIDER",
+            &self.backend_pools.egress_follow_provider,
+        );
+        vars.set(
+            "GATEWAY_BACKEND_HEALTH_HISTORY",
+            &self.backend_pools.health_history,
+        );
+        vars.set(
+            "GATEWAY_BACKEND_RECOVERY_SUCCESSES",
+            &self.backend_pools.recovery_successes,
+        );
+
+        if let Some(buckets) = &self.metrics.latency_buckets {
+            vars.push("GATEWAY_METRICS_LATENCY_BUCKETS", join(buckets));
+        }
+        vars.set(
+            "GATEWAY_METRICS_MAX_TIER_LABELS",
+            &self.metrics.max_tier_labels,
+        );
+        vars.set(
+            "GATEWAY_METRICS_MAX_TENANT_LABELS",
+            &self.metrics.max_tenant_labels,
+        );
+        vars.set("GATEWAY_ADMIN_TOKEN", &self.admin.token);
+        vars.set_list("GATEWAY_ADMIN_ALLOWED_CIDRS", &self.admin.allowed_cidrs);
+        vars.set("GATEWAY_ADMIN_LISTEN_ADDR", &self.admin.listen_addr);
+        vars.set("GATEWAY_ACCESS_LOG", &self.logging.access_log);
+        vars.set("GATEWAY_SLOW_REQUEST_MS", &self.logging.slow_request_ms);
+        vars.set(
+            "GATEWAY_ACCESS_LOG_STREAM_TRANSCRIPTS",
+            &self.logging.stream_transcripts,
+        );
+        vars.set_list("GATEWAY_CONTEXT_WINDOWS", &self.context.windows);
+        vars.set("GATEWAY_CONTEXT_OVERFLOW", &self.context.overflow);
+        vars.set("GATEWAY_TRUNCATION", &self.context.truncation);
+        vars.set(
+            "GATEWAY_TRUNCATION_SUMMARY_MODEL",
+            &self.context.summary_model,
+        );
+        vars.set(
+            "GATEWAY_TRUNCATION_SUMMARY_MAX_TOKENS",
+            &self.context.summary_max_tokens,
+        );
+        vars.set_list("GATEWAY_MODEL_PRICING", &self.pricing.models);
+        vars.set("GATEWAY_PREPAID_CREDITS", &self.pricing.prepaid_credits);
+        if let Some(models) = &self.models {
+            vars.push(
+                "GATEWAY_MODEL_CATALOG",
+                serde_json::Value::Object(models.clone()).to_string(),
+            );
+        }
+        if let Some(processors) = &self.post_processing.processors {
+            vars.push(
+                "GATEWAY_POST_PROCESSORS",
+                serde_json::Value::Object(processors.clone()).to_string(),
+            );
+        }
+
+        if let Some(experiments) = &self.experiments {
+            vars.push(
+                "GATEWAY_EXPERIMENTS",
+                serde_json::Value::Array(experiments.clone()).to_string(),
+            );
+        }
+
+        let usage = &self.usage;
+        vars.set("GATEWAY_USAGE_SINK", &usage.sink);
+        vars.set("GATEWAY_USAGE_CLICKHOUSE_URL", &usage.clickhouse_url);
+        vars.set("GATEWAY_USAGE_CLICKHOUSE_TABLE", &usage.clickhouse_table);
+        vars.set("GATEWAY_USAGE_CLICKHOUSE_USER", &usage.clickhouse_user);
+        vars.set(
+            "GATEWAY_USAGE_CLICKHOUSE_PASSWORD",
+            &usage.clickhouse_password,
+        );
+        vars.set("GATEWAY_USAGE_BATCH_SIZE", &usage.batch_size);
+        vars.set("GATEWAY_USAGE_FLUSH_INTERVAL_MS", &usage.flush_interval_ms);
+        vars.set("GATEWAY_USAGE_QUEUE_CAPACITY", &usage.queue_capacity);
+        vars.set("GATEWAY_SENTRY_DSN", &self.error_reporting.sentry_dsn);
+        vars.set(
+            "GATEWAY_SENTRY_ENVIRONMENT",
+            &self.error_reporting.sentry_environment,
+        );
+        let traces = &self.traces;
+        vars.set("GATEWAY_TRACE_EXPORT", &traces.export);
+        vars.set("GATEWAY_TRACE_ENDPOINT", &traces.endpoint);
+        vars.set("GATEWAY_LANGFUSE_PUBLIC_KEY", &traces.langfuse_public_key);
+        vars.set("GATEWAY_LANGFUSE_SECRET_KEY", &traces.langfuse_secret_key);
+        vars.set_list("GATEWAY_TRACE_HEADERS", &traces.headers);
+        vars.set("GATEWAY_TRACE_CAPTURE_CONTENT", &traces.capture_content);
+        vars.0
+    }
+
+    /// Exports [`GatewayConfig::env_defaults`] for every variable not already set. Call this
+    /// at startup, before any component reads its configuration.
+    pub fn apply_env_defaults(&self) {
+        for (name, value) in self.env_defaults() {
+            if env::var_os(name).is_none() {
+                env::set_var(name, value);
+            }
+        }
+    }
+}
+
+/// The gateway settings in `vars` (normally `std::env::vars()` after file defaults are
+/// applied), sorted by name, with credentials replaced by `<redacted>`. Settings not listed
+/// run with their built-in defaults.
+pub fn effective_settings<I>(vars: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut settings = vars
+        .into_iter()
+        .filter(|(name, _)| {
+            name.starts_with("GATEWAY_") || name.starts_with("OPENAI_") || name == "REDIS_URL"
+        })
+        .map(|(name, value)| {
+            let value = if is_secret(&name) {
+                "<redacted>".to_owned()
+            } else {
+                value
+            };
+            (name, value)
+        })
+        .collect::<Vec<_>>();
+    settings.sort();
+    settings
+}
+
+/// API keys, tokens, passwords, shared secrets and URLs that may embed them. Key policies are
+/// keyed by the API keys themselves, trace collector headers usually carry auth, and proxy
+/// URLs may carry the proxy's credentials.
+fn is_secret(name: &str) -> bool {
+    [
+        "_KEY",
+        "_KEYS",
+        "_TOKEN",
+        "_PASSWORD",
+        "_SECRET",
+        "_DSN",
+        "_KEY_POLICIES",
+    ]
+    .iter()
+    .any(|suffix| name.ends_with(suffix))
+        || name == "REDIS_URL"
+        || name == "GATEWAY_TRACE_HEADERS"
+        || (name.ends_with("_PROXY") && !name.ends_with("_NO_PROXY"))
+}
+
+impl ConfigError {
+    /// Attaches the file path to an error from [`GatewayConfig::parse`].
+    fn at(self, file: &Path) -> Self {
+        match self {
+            Self::Parse { message, .. } => Self::Parse {
+                path: file.to_owned(),
+                message,
+            },
+            Self::Invalid { field, message, .. } => Self::Invalid {
+                path: file.to_owned(),
+                field,
+                message,
+            },
+            other => other,
+        }
+    }
+}
+
+#[derive(Default)]
+struct EnvVars(Vec<(&'static str, String)>);
+
+impl EnvVars {
+    fn push(&mut self, name: &'static str, value: String) {
+        self.0.push((name, value));
+    }
+
+    fn set<T: ToString>(&mut self, name: &'static str, value: &Option<T>) {
+        if let Some(value) = value {
+            self.push(name, value.to_string());
+        }
+    }
+
+    fn set_list(&mut self, name: &'static str, values: &Option<Vec<String>>) {
+        if let Some(values) = values {
+            self.push(name, values.join(","));
+        }
+    }
+}
+
+fn join(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(f64::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{effective_settings, ConfigError, GatewayConfig};
+
+    const SAMPLE: &str = r#"
+[listen]
+addr = "0.0.0.0:8443"
+tls_cert_path = "/etc/gateway/tls/cert.pem"
+tls_key_path = "/etc/gateway/tls/key.pem"
+
+[backends.openai]
+api_key = "sk-test"
+timeout_secs = 30
+
+[auth]
+api_keys = ["key-a", "key-b"]
+key_policies = { key-a = { tenant = "acme", tier = "pro" } }
+tenants = { acme = { models = ["gpt-4o*"], requests_per_minute = 600 } }
+
+[cache]
+ttl_secs = 300
+scope = "tenant"
+model_rules = ["*-realtime=off", "*mini*=600"]
+
+[batching]
+enabled = false
+
+[fair_queue]
+tier_weights = { free = 1, pro = 4 }
+
+[metrics]
+latency_buckets = [0.5, 1, 30]
+
+[admin]
+listen_addr = "127.0.0.1:9090"
+"#;
+
+    #[test]
+    fn maps_file_settings_onto_environment_variables() {
+        let config = GatewayConfig::parse(SAMPLE).expect("valid config");
+        let vars = config.env_defaults();
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(var("GATEWAY_LISTEN_ADDR"), Some("0.0.0.0:8443"));
+        assert_eq!(
+            var("GATEWAY_TLS_KEY_PATH"),
+            Some("/etc/gateway/tls/key.pem")
+        );
+        assert_eq!(var("OPENAI_API_KEY"), Some("sk-test"));
+        assert_eq!(var("OPENAI_TIMEOUT_SECS"), Some("30"));
+        assert_eq!(var("GATEWAY_API_KEYS"), Some("key-a,key-b"));
+        assert_eq!(
+            var("GATEWAY_KEY_POLICIES"),
+            Some(r#"{"key-a":{"tenant":"acme","tier":"pro"}}"#)
+        );
+        assert_eq!(
+            var("GATEWAY_TENANTS"),
+            Some(r#"{"acme":{"models":["gpt-4o*"],"requests_per_minute":600}}"#)
+        );
+        assert_eq!(var("GATEWAY_CACHE_SCOPE"), Some("tenant"));
+        assert_eq!(
+            var("GATEWAY_CACHE_MODEL_RULES"),
+            Some("*-realtime=off,*mini*=600")
+        );
+        assert_eq!(var("GATEWAY_BATCH_ENABLED"), Some("false"));
+        assert_eq!(
+            var("GATEWAY_FAIR_TIER_WEIGHTS"),
+            Some(r#"{"free":1,"pro":4}"#)
+        );
+        assert_eq!(var("GATEWAY_METRICS_LATENCY_BUCKETS"), Some("0.5,1,30"));
+        assert_eq!(var("GATEWAY_ADMIN_LISTEN_ADDR"), Some("127.0.0.1:9090"));
+        assert_eq!(var("REDIS_URL"), None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_invalid_values_with_their_location() {
+        let ConfigError::Parse { message, .. } =
+            GatewayConfig::parse("[cache]\nttl_sec = 300\n").expect_err("unknown key")
+        else {
+            panic!("expected a parse error");
+        };
+        assert!(message.contains("ttl_sec"), "{message}");
+        assert!(message.contains("line 2"), "{message}");
+
+        let error = GatewayConfig::parse("[cache]\nscope = \"team\"\n").expect_err("bad scope");
+        assert!(
+            matches!(
+                error,
+                ConfigError::Invalid {
+                    field: "cache.scope",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+
+        let error =
+            GatewayConfig::parse("[model_pools]\npools = [\"llama*=0\"]\n").expect_err("bad pool");
+        assert!(error.to_string().contains("model_pools.pools"), "{error}");
+
+        let error = GatewayConfig::parse("[usage]\nsink = \"clickhouse\"\n")
+            .expect_err("missing clickhouse url");
+        assert!(
+            error.to_string().contains("usage.clickhouse_url"),
+            "{error}"
+        );
+
+        let error = GatewayConfig::parse("[backends.peers]\nproxy = \"proxy.corp:3128\"\n")
+            .expect_err("proxy without a scheme");
+        assert!(
+            error.to_string().contains("backends.peers.proxy"),
+            "{error}"
+        );
+
+        let error = GatewayConfig::parse("[models.a]\naliases = [\"b\"]\n[models.b]\n")
+            .expect_err("alias of one model is another's id");
+        assert!(
+            matches!(
+                error,
+                ConfigError::Invalid {
+                    field: "models",
+                    ..
+                }
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn example_config_file_is_valid() {
+        GatewayConfig::parse(include_str!("../deploy/gateway.example.toml"))
+            .expect("example config parses");
+    }
+
+    #[test]
+    fn effective_settings_are_sorted_filtered_and_redacted() {
+        let vars = [
+            ("PATH", "/usr/bin"),
+            ("GATEWAY_LISTEN_ADDR", "127.0.0.1:9000"),
+            ("OPENAI_API_KEY", "sk-live"),
+            ("GATEWAY_API_KEYS", "a,b"),
+            ("GATEWAY_KEY_POLICIES", r#"{"a":{}}"#),
+            ("GATEWAY_TLS_KEY_PATH", "/etc/tls/key.pem"),
+            ("OPENAI_PROXY", "http://user:pw@proxy.corp:3128"),
+            ("OPENAI_NO_PROXY", "localhost"),
+            ("REDIS_URL", "redis://:pw@redis:6379"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let settings = effective_settings(vars);
+        let rendered = settings
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rendered,
+            [
+                "GATEWAY_API_KEYS=<redacted>",
+                "GATEWAY_KEY_POLICIES=<redacted>",
+                "GATEWAY_LISTEN_ADDR=127.0.0.1:9000",
+                "GATEWAY_TLS_KEY_PATH=/etc/tls/key.pem",
+                "OPENAI_API_KEY=<redacted>",
+                "OPENAI_NO_PROXY=localhost",
+                "OPENAI_PROXY=<redacted>",
+                "REDIS_URL=<redacted>",
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::env;
+
+use tracing::warn;
+
+use crate::{
+    glob,
+    limits::estimate_prompt_tokens,
+    models::{InvalidParameter, NormalizedChatRequest},
+};
+
+/// Context-window size in tokens for models matching `pattern`; rules are evaluated in order
+/// and the first match wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextWindow {
+    pub pattern: String,
+    pub tokens: u64,
+}
+
+impl ContextWindow {
+    /// Parses `pattern=tokens` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pattern, tokens) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("context window `{entry}` must be `pattern=tokens`"))?;
+                let tokens = tokens
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|tokens| *tokens > 0)
+                    .ok_or_else(|| format!("context window `{entry}` has an invalid size"))?;
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    tokens,
+                })
+            })
+            .collect()
+    }
+}
+
+/// What happens to a request whose prompt plus `max_tokens` does not fit the window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextOverflow {
+    /// Fails with `400 context_length_exceeded`, as the provider would.
+    #[default]
+    Reject,
+    /// Lowers `max_tokens` to the room the prompt leaves. A prompt that fills the window on
+    /// its own is still rejected.
+    Truncate,
+}
+
+impl ContextOverflow {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "truncate" => Ok(Self::Truncate),
+            other => Err(format!(
+                "unknown context overflow `{other}`, expected `reject` or `truncate`"
+            )),
+        }
+    }
+}
+
+/// Per-model context windows, checked before a request is queued so one the backend is bound
+/// to reject never spends rate-limit budget or a dispatch slot. Models without a window are
+/// not checked.
+#[derive(Debug, Clone, Default)]
+pub struct ContextLimits {
+    windows: Vec<ContextWindow>,
+    overflow: ContextOverflow,
+}
+
+impl ContextLimits {
+    pub fn new(windows: Vec<ContextWindow>, overflow: ContextOverflow) -> Self {
+        Self { windows, overflow }
+    }
+
+    pub fn from_env() -> Self {
+        let windows = env::var("GATEWAY_CONTEXT_WINDOWS")
+            .ok()
+            .map(|raw| {
+                ContextWindow::parse_list(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_CONTEXT_WINDOWS, context windows will not be enforced");
+                    Vec::new()
+                })
+            })
+            .unwrap_or_default();
+        let overflow = env::var("GATEWAY_CONTEXT_OVERFLOW")
+            .ok()
+            .map(|raw| {
+                ContextOverflow::parse(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_CONTEXT_OVERFLOW, rejecting oversized requests");
+                    ContextOverflow::Reject
+                })
+            })
+            .unwrap_or_default();
+        Self::new(windows, overflow)
+    }
+
+    /// Puts `windows` ahead of the configured rules, so they win for the models they match.
+    pub fn prepend(mut self, windows: Vec<ContextWindow>) -> Self {
+        self.windows.splice(0..0, windows);
+        self
+    }
+
+    pub fn window_for(&self, model: &str) -> Option<u64> {
+        self.windows
+            .iter()
+            .find(|window| glob::matches(&window.pattern, model))
+            .map(|window| window.tokens)
+    }
+
+    /// Fits `request` into its model's window, lowering `max_tokens` when the overflow policy
+    /// allows it.
+    pub fn enforce(&self, request: &mut NormalizedChatRequest) -> Result<(), InvalidParameter> {
+        let Some(window) = self.window_for(&request.model) else {
+            return Ok(());
+        };
+        let prompt = estimate_prompt_tokens(request);
+        let completion = request.generation.max_tokens.map(u64::from).unwrap_or(0);
+        let requested = prompt.saturating_add(completion);
+        if requested <= window {
+            return Ok(());
+        }
+        if self.overflow == ContextOverflow::Truncate && prompt < window {
+            let room = u32::try_from(window - prompt).unwrap_or(u32::MAX);
+            request.generation.max_tokens = Some(room);
+            return Ok(());
+        }
+        Err(InvalidParameter::new(
+            "messages",
+            "context_length_exceeded",
+            format!(
+                "This model's maximum context length is {window} tokens. However, you requested \
+                 {requested} tokens ({prompt} in the messages, {completion} in the completion). \
+                 Please reduce the length of the messages or completion."
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContextLimits, ContextOverflow, ContextWindow};
+    use crate::models::{
+        ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+        Priority,
+    };
+
+    fn request(model: &str, words: usize, max_tokens: Option<u32>) -> NormalizedChatRequest {
+        NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "user_a".to_owned(),
+            model: model.to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: vec!["word"; words].join(" "),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens,
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    #[test]
+    fn rejects_or_shrinks_requests_that_overflow_the_window() {
+        let windows = ContextWindow::parse_list("small-*=100, *=1000").expect("valid windows");
+        let reject = ContextLimits::new(windows.clone(), ContextOverflow::Reject);
+
+        let mut fits = request("small-1", 60, Some(40));
+        assert!(reject.enforce(&mut fits).is_ok());
+        let error = reject
+            .enforce(&mut request("small-1", 60, Some(41)))
+            .expect_err("over the window");
+        assert_eq!(error.code, "context_length_exceeded");
+        assert!(error
+            .message
+            .contains("maximum context length is 100 tokens"));
+        assert!(reject.enforce(&mut request("large", 60, Some(41))).is_ok());
+
+        let truncate = ContextLimits::new(windows, ContextOverflow::Truncate);
+        let mut shrunk = request("small-1", 60, Some(500));
+        assert!(truncate.enforce(&mut shrunk).is_ok());
+        assert_eq!(shrunk.generation.max_tokens, Some(40));
+        assert!(truncate
+            .enforce(&mut request("small-1", 101, None))
+            .is_err());
+
+        assert!(ContextWindow::parse_list("gpt-4o=0").is_err());
+        assert!(ContextOverflow::parse("summarize").is_err());
+    }
+}
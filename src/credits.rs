@@ -0,0 +1,275 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{errors::AppError, redis_pool::RedisPool, state::AppState};
+
+/// Balances are kept in millionths of a dollar so every deduction is one atomic integer update.
+const MICROS_PER_USD: f64 = 1_000_000.0;
+
+/// Prepaid balances per tenant (keys without a tenant are their own tenant). Each request's
+/// priced cost is deducted once it is known, and requests are refused while the balance is
+/// not positive; requests already in flight can take it slightly below zero.
+pub struct CreditLedger {
+    backend: Option<LedgerBackend>,
+}
+
+enum LedgerBackend {
+    Memory(Mutex<HashMap<String, i64>>),
+    Redis(Arc<RedisPool>),
+}
+
+impl CreditLedger {
+    /// Enabled by `GATEWAY_PREPAID_CREDITS`; balances live in Redis when `redis` is set so
+    /// every instance draws from the same account.
+    pub fn from_env(redis: Option<Arc<RedisPool>>) -> Self {
+        let enabled = env::var("GATEWAY_PREPAID_CREDITS")
+            .ok()
+            .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        if !enabled {
+            return Self::disabled();
+        }
+        match redis {
+            Some(pool) => Self {
+                backend: Some(LedgerBackend::Redis(pool)),
+            },
+            None => Self::in_memory(),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            backend: Some(LedgerBackend::Memory(Mutex::new(HashMap::new()))),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { backend: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// The account's balance in USD, or `None` when credits are off or the store is
+    /// unreachable, in which case requests are let through.
+    pub async fn balance(&self, account: &str) -> Option<f64> {
+        match self.backend.as_ref()? {
+            LedgerBackend::Memory(balances) => Some(to_usd(
+                lock(balances).get(account).copied().unwrap_or_default(),
+            )),
+            LedgerBackend::Redis(pool) => {
+                let result: redis::RedisResult<Option<i64>> = async {
+                    let mut connection = pool.connection().await?;
+                    connection.get(balance_key(pool.prefix(), account)).await
+                }
+                .await;
+                match pool.track(result).await {
+                    Ok(micros) => Some(to_usd(micros.unwrap_or_default())),
+                    Err(error) => {
+                        warn!(error = %error, "redis unavailable for credit balance check");
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Deducts `cost_usd` and returns the remaining balance.
+    pub async fn charge(&self, account: &str, cost_usd: f64) -> Option<f64> {
+        match self.adjust(account, -cost_usd).await {
+            Ok(balance) => balance,
+            Err(error) => {
+                warn!(error = %error, account, cost_usd, "failed to deduct credits");
+                None
+            }
+        }
+    }
+
+    /// Adds `amount_usd` (negative to debit) and returns the new balance; `None` when credits
+    /// are off.
+    pub async fn adjust(&self, account: &str, amount_usd: f64) -> redis::RedisResult<Option<f64>> {
+        let delta = to_micros(amount_usd);
+        match &self.backend {
+            None => Ok(None),
+            Some(LedgerBackend::Memory(balances)) => {
+                let mut balances = lock(balances);
+                let balance = balances.entry(account.to_owned()).or_default();
+                *balance = balance.saturating_add(delta);
+                Ok(Some(to_usd(*balance)))
+            }
+            Some(LedgerBackend::Redis(pool)) => {
+                let mut connection = pool.connection().await?;
+                let result = connection
+                    .incr(balance_key(pool.prefix(), account), delta)
+                    .await;
+                let micros: i64 = pool.track(result).await?;
+                Ok(Some(to_usd(micros)))
+            }
+        }
+    }
+
+    /// Replaces the balance; `None` when credits are off.
+    pub async fn set(&self, account: &str, balance_usd: f64) -> redis::RedisResult<Option<f64>> {
+        let micros = to_micros(balance_usd);
+        match &self.backend {
+            None => Ok(None),
+            Some(LedgerBackend::Memory(balances)) => {
+                lock(balances).insert(account.to_owned(), micros);
+                Ok(Some(to_usd(micros)))
+            }
+            Some(LedgerBackend::Redis(pool)) => {
+                let mut connection = pool.connection().await?;
+                let result = connection
+                    .set(balance_key(pool.prefix(), account), micros)
+                    .await;
+                let () = pool.track(result).await?;
+                Ok(Some(to_usd(micros)))
+            }
+        }
+    }
+}
+
+fn balance_key(prefix: &str, account: &str) -> String {
+    format!("{prefix}:credits:{account}")
+}
+
+fn lock(balances: &Mutex<HashMap<String, i64>>) -> std::sync::MutexGuard<'_, HashMap<String, i64>> {
+    balances
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn to_micros(usd: f64) -> i64 {
+    (usd * MICROS_PER_USD).round() as i64
+}
+
+fn to_usd(micros: i64) -> f64 {
+    micros as f64 / MICROS_PER_USD
+}
+
+/// Value of the `x-gateway-credits-remaining` header.
+pub fn format_balance(balance_usd: f64) -> String {
+    format!("{balance_usd:.6}")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CreditBalance {
+    pub tenant_id: String,
+    pub balance_usd: f64,
+}
+
+/// Body of `PUT /admin/credits/{tenant_id}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetCredits {
+    pub balance_usd: f64,
+}
+
+/// Body of `POST /admin/credits/{tenant_id}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopUpCredits {
+    pub amount_usd: f64,
+}
+
+/// `GET /admin/credits/{tenant_id}`: the remaining balance.
+pub async fn get_credits(State(state): State<AppState>, Path(tenant_id): Path<String>) -> Response {
+    if !state.credits.is_enabled() {
+        return credits_disabled().into_response();
+    }
+    match state.credits.balance(&tenant_id).await {
+        Some(balance_usd) => Json(CreditBalance {
+            tenant_id,
+            balance_usd,
+        })
+        .into_response(),
+        None => store_unavailable().into_response(),
+    }
+}
+
+/// `PUT /admin/credits/{tenant_id}`: replaces the balance.
+pub async fn set_credits(
+    State(state): State<AppState>,
+    Path(tenant_id): Path<String>,
+    payload: Result<Json<SetCredits>, JsonRejection>,
+) -> Response {
+    let Json(update) = match payload {
+        Ok(update) => update,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let result = state.credits.set(&tenant_id, update.balance_usd).await;
+    respond(tenant_id, "credit balance set", result)
+}
+
+/// `POST /admin/credits/{tenant_id}`: adds to the balance; a negative amount corrects it down.
+pub async fn top_up_credits(
+    State(state): State<AppState>,
+    Path(tenant_id): Path<String>,
+    payload: Result<Json<TopUpCredits>, JsonRejection>,
+) -> Response {
+    let Json(top_up) = match payload {
+        Ok(top_up) => top_up,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let result = state.credits.adjust(&tenant_id, top_up.amount_usd).await;
+    respond(tenant_id, "credits topped up", result)
+}
+
+fn respond(tenant_id: String, event: &str, result: redis::RedisResult<Option<f64>>) -> Response {
+    match result {
+        Ok(Some(balance_usd)) => {
+            info!(tenant_id = %tenant_id, balance_usd, "{event}");
+            Json(CreditBalance {
+                tenant_id,
+                balance_usd,
+            })
+            .into_response()
+        }
+        Ok(None) => credits_disabled().into_response(),
+        Err(error) => {
+            warn!(error = %error, "failed to update credit balance");
+            store_unavailable().into_response()
+        }
+    }
+}
+
+fn credits_disabled() -> AppError {
+    AppError::BadRequest("prepaid credits are not enabled (GATEWAY_PREPAID_CREDITS)".to_owned())
+}
+
+fn store_unavailable() -> AppError {
+    AppError::Backend("credit store unavailable".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CreditLedger;
+
+    #[tokio::test]
+    async fn charges_and_top_ups_move_the_balance_in_whole_micros() {
+        let ledger = CreditLedger::in_memory();
+        assert_eq!(ledger.balance("acme").await, Some(0.0));
+        assert_eq!(ledger.adjust("acme", 1.5).await.expect("top up"), Some(1.5));
+        assert_eq!(ledger.charge("acme", 0.000_000_4).await, Some(1.5));
+        assert_eq!(ledger.charge("acme", 0.25).await, Some(1.25));
+        assert_eq!(ledger.charge("acme", 2.0).await, Some(-0.75));
+        assert_eq!(ledger.set("acme", 10.0).await.expect("set"), Some(10.0));
+        assert_eq!(ledger.balance("other").await, Some(0.0));
+
+        let disabled = CreditLedger::disabled();
+        assert_eq!(disabled.balance("acme").await, None);
+        assert_eq!(disabled.charge("acme", 1.0).await, None);
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::{
+    backend::{
+        credentials::Credentials, ratelimit::ProviderLimits, BackendError, BackendStream,
+        InferenceBackend,
+    },
+    glob,
+    limits::estimate_request_tokens,
+    metrics::AppMetrics,
+    models::{BackendChatResponse, NormalizedChatRequest},
+};
+
+/// Rate toward the provider behind backends whose name matches `pattern`; rules are evaluated
+/// in order and the first match wins. Each matching backend is paced on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressLimit {
+    pub pattern: String,
+    pub requests_per_sec: Option<f64>,
+    /// Counted from each request's estimate: its prompt plus `max_tokens`.
+    pub tokens_per_min: Option<u64>,
+}
+
+impl EgressLimit {
+    /// Parses `pattern=<requests_per_sec>[:<tokens_per_min>]` entries separated by commas;
+    /// either rate may be left empty, as in `openai=:90000`.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let invalid = || format!("backend egress `{entry}` must be `pattern=rps[:tpm]`");
+                let (pattern, value) = entry.split_once('=').ok_or_else(invalid)?;
+                let (requests, tokens) = value.split_once(':').unwrap_or((value, ""));
+                let requests_per_sec = match requests.trim() {
+                    "" => None,
+                    requests => Some(
+                        requests
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|rate| rate.is_finite() && *rate > 0.0)
+                            .ok_or_else(invalid)?,
+                    ),
+                };
+                let tokens_per_min = match tokens.trim() {
+                    "" => None,
+                    tokens => Some(
+                        tokens
+                            .parse::<u64>()
+                            .ok()
+                            .filter(|rate| *rate > 0)
+                            .ok_or_else(invalid)?,
+                    ),
+                };
+                if requests_per_sec.is_none() && tokens_per_min.is_none() {
+                    return Err(invalid());
+                }
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    requests_per_sec,
+                    tokens_per_min,
+                })
+            })
+            .collect()
+    }
+
+    /// How long a call of `requests` requests and `tokens` tokens occupies the bucket.
+    fn drain_time(&self, requests: u32, tokens: u64) -> Duration {
+        let for_requests = self
+            .requests_per_sec
+            .map_or(0.0, |rate| f64::from(requests) / rate);
+        let for_tokens = self
+            .tokens_per_min
+            .map_or(0.0, |rate| tokens as f64 * 60.0 / rate as f64);
+        Duration::from_secs_f64(for_requests.max(for_tokens))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressConfig {
+    pub limits: Vec<EgressLimit>,
+    /// Longest a call waits for its turn; calls that would wait longer are shed.
+    pub max_wait: Duration,
+    /// Also paces backends without a limit by the budgets their provider reports in its
+    /// rate-limit headers. Backends with a limit always follow those budgets too.
+    pub follow_provider_limits: bool,
+}
+
+impl Default for EgressConfig {
+    fn default() -> Self {
+        Self {
+            limits: Vec::new(),
+            max_wait: Duration::from_secs(5),
+            follow_provider_limits: false,
+        }
+    }
+}
+
+impl EgressConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let limits = env::var("GATEWAY_BACKEND_EGRESS")
+            .ok()
+            .map(|raw| {
+                EgressLimit::parse_list(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_BACKEND_EGRESS, backend egress is unpaced");
+                    Vec::new()
+                })
+            })
+            .unwrap_or_default();
+        Self {
+            limits,
+            max_wait: env::var("GATEWAY_BACKEND_EGRESS_MAX_WAIT_MS")
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_wait),
+            follow_provider_limits: env::var("GATEWAY_BACKEND_EGRESS_FOLLOW_PROVIDER")
+                .ok()
+                .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true")),
+        }
+    }
+
+    /// Wraps `backend` so its calls leave no faster than the first matching limit and the
+    /// provider's reported budgets allow. Backends without a limit are returned as they are
+    /// unless `follow_provider_limits` is set.
+    pub fn gate(
+        &self,
+        backend: Arc<dyn InferenceBackend>,
+        metrics: Arc<AppMetrics>,
+    ) -> Arc<dyn InferenceBackend> {
+        let limit = self
+            .limits
+            .iter()
+            .find(|limit| glob::matches(&limit.pattern, backend.name()));
+        if limit.is_none() && !self.follow_provider_limits {
+            return backend;
+        }
+        Arc::new(EgressBackend {
+            bucket: LeakyBucket {
+                limit: limit.cloned(),
+                max_wait: self.max_wait,
+                next_free: Mutex::new(Instant::now()),
+            },
+            inner: backend,
+            metrics,
+        })
+    }
+}
+
+/// Smooths calls into a steady flow instead of letting a burst of client demand through: each
+/// call is scheduled once the calls before it have drained at the configured rates, and waits
+/// for its turn. What the provider last reported left of its budgets is spread over the time
+/// until they reset, so calls slow down before the provider starts answering `429`.
+struct LeakyBucket {
+    limit: Option<EgressLimit>,
+    max_wait: Duration,
+    next_free: Mutex<Instant>,
+}
+
+impl LeakyBucket {
+    /// Books a turn for `requests` calls carrying `tokens`, returning how long to wait for it,
+    /// or `None` when that is longer than the bucket allows or than `deadline` leaves. Nothing
+    /// is booked in that case.
+    fn book(
+        &self,
+        requests: u32,
+        tokens: u64,
+        deadline: Option<Instant>,
+        provider: Option<ProviderLimits>,
+    ) -> Option<Duration> {
+        let mut next_free = self
+            .next_free
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let mut start = (*next_free).max(now);
+        let mut drain = self
+            .limit
+            .as_ref()
+            .map_or(Duration::ZERO, |limit| limit.drain_time(requests, tokens));
+        if let Some(provider) = provider {
+            if let Some(reset) = provider.not_before(requests, tokens, now) {
+                start = start.max(reset);
+            }
+            drain = drain.max(provider.spacing(requests, tokens, now));
+        }
+        let wait = start - now;
+        if wait > self.max_wait || deadline.is_some_and(|deadline| start >= deadline) {
+            return None;
+        }
+        *next_free = start + drain;
+        Some(wait)
+    }
+}
+
+struct EgressBackend {
+    bucket: LeakyBucket,
+    inner: Arc<dyn InferenceBackend>,
+    metrics: Arc<AppMetrics>,
+}
+
+impl EgressBackend {
+    async fn wait_turn(
+        &self,
+        requests: u32,
+        tokens: u64,
+        deadline: Option<Instant>,
+    ) -> Result<(), BackendError> {
+        let provider = self.inner.provider_limits();
+        let Some(wait) = self.bucket.book(requests, tokens, deadline, provider) else {
+            self.metrics.observe_load_shed("egress");
+            return Err(BackendError::Overloaded {
+                message: format!(
+                    "backend `{}` is at its egress rate limit",
+                    self.inner.name()
+                ),
+                retry_after_secs: self.bucket.max_wait.as_secs().max(1),
+            });
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        self.metrics.observe_queue_wait("egress", wait);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for EgressBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_limits(&self) -> Option<ProviderLimits> {
+        self.inner.provider_limits()
+    }
+
+    fn credentials(&self, backend: &str) -> Option<Arc<Credentials>> {
+        self.inner.credentials(backend)
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        self.wait_turn(1, estimate_request_tokens(&request), request.deadline)
+            .await?;
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        self.wait_turn(1, estimate_request_tokens(&request), request.deadline)
+            .await?;
+        self.inner.stream_chat(request).await
+    }
+
+    async fn execute_chat_batch(
+        &self,
+        requests: Vec<Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        let tokens = requests
+            .iter()
+            .map(|request| estimate_request_tokens(request))
+            .sum();
+        let deadline = requests
+            .iter()
+            .map(|request| request.deadline)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|deadlines| deadlines.into_iter().max());
+        let count = u32::try_from(requests.len()).unwrap_or(u32::MAX);
+        if let Err(error) = self.wait_turn(count, tokens, deadline).await {
+            return requests.iter().map(|_| Err(error.clone())).collect();
+        }
+        self.inner.execute_chat_batch(requests).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+
+    use super::{EgressConfig, EgressLimit};
+    use crate::{
+        backend::{
+            mock::MockBackend,
+            ratelimit::{Budget, ProviderLimits},
+            BackendError, BackendStream, InferenceBackend,
+        },
+        metrics::AppMetrics,
+        models::{
+            BackendChatResponse, ClientTags, GenerationParams, MessageRole, NormalizedChatRequest,
+            NormalizedMessage,
+        },
+    };
+
+    /// A backend whose provider always reports `limits`.
+    struct ReportingBackend {
+        inner: MockBackend,
+        limits: ProviderLimits,
+    }
+
+    #[async_trait]
+    impl InferenceBackend for ReportingBackend {
+        fn name(&self) -> &str {
+            self.inner.name()
+        }
+
+        async fn execute_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            self.inner.execute_chat(request).await
+        }
+
+        async fn stream_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            self.inner.stream_chat(request).await
+        }
+
+        fn provider_limits(&self) -> Option<ProviderLimits> {
+            Some(self.limits)
+        }
+    }
+
+    fn request() -> Arc<NormalizedChatRequest> {
+        Arc::new(NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "user_a".to_owned(),
+            model: "mock-1".to_owned(),
+            messages: vec![NormalizedMessage {
+                role: MessageRole::User,
+                content: "hello".to_owned(),
+                cache_control: None,
+            }],
+            generation: GenerationParams {
+                max_tokens: Some(9),
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Default::default(),
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        })
+    }
+
+    #[test]
+    fn parses_request_and_token_rates() {
+        let limits = EgressLimit::parse_list("openai*=50:90000, local=2.5, peer:*=:6000")
+            .expect("valid limits");
+        assert_eq!(limits[0].requests_per_sec, Some(50.0));
+        assert_eq!(limits[0].tokens_per_min, Some(90_000));
+        assert_eq!(limits[1].tokens_per_min, None);
+        assert_eq!(limits[2].pattern, "peer:*");
+        assert_eq!(limits[2].requests_per_sec, None);
+        assert!(EgressLimit::parse_list("openai=:").is_err());
+        assert!(EgressLimit::parse_list("openai=0").is_err());
+        assert!(EgressLimit::parse_list("openai").is_err());
+    }
+
+    #[tokio::test]
+    async fn paces_calls_and_sheds_those_that_would_wait_too_long() {
+        let config = EgressConfig {
+            // One request per 40ms and 10 tokens, each test request's estimate, per 60ms: the
+            // token rate is the tighter one, so calls leave 60ms apart.
+            limits: EgressLimit::parse_list("mock-*=25:10000").expect("valid limits"),
+            max_wait: Duration::from_millis(130),
+            ..EgressConfig::default()
+        };
+        let metrics = Arc::new(AppMetrics::new());
+        let backend = config.gate(Arc::new(MockBackend::named("mock-a")), metrics.clone());
+        let unpaced = config.gate(Arc::new(MockBackend::named("other")), metrics);
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            backend.execute_chat(request()).await.expect("paced call");
+        }
+        assert!(started.elapsed() >= Duration::from_millis(120));
+
+        // Concurrent calls queue up 60ms apart; the third in line would wait 180ms.
+        let queued = tokio::join!(
+            backend.execute_chat(request()),
+            backend.execute_chat(request()),
+            backend.execute_chat(request())
+        );
+        assert!(queued.0.is_ok() && queued.1.is_ok());
+        assert!(matches!(queued.2, Err(BackendError::Overloaded { .. })));
+
+        let started = Instant::now();
+        for _ in 0..5 {
+            unpaced.execute_chat(request()).await.expect("unpaced call");
+        }
+        assert!(started.elapsed() < Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn slows_down_as_the_provider_budget_runs_out() {
+        let config = EgressConfig {
+            max_wait: Duration::from_secs(1),
+            follow_provider_limits: true,
+            ..EgressConfig::default()
+        };
+        let metrics = Arc::new(AppMetrics::new());
+        let now = Instant::now();
+        // Room for one more test request's 10 tokens over the next 100ms.
+        let tight = config.gate(
+            Arc::new(ReportingBackend {
+                inner: MockBackend::named("mock-a"),
+                limits: ProviderLimits {
+                    requests: None,
+                    tokens: Some(Budget {
+                        limit: Some(1_000),
+                        remaining: 10,
+                        resets_at: now + Duration::from_millis(100),
+                    }),
+                },
+            }),
+            metrics.clone(),
+        );
+        let started = Instant::now();
+        tight.execute_chat(request()).await.expect("first call");
+        tight.execute_chat(request()).await.expect("spaced call");
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // Nothing left until long after the longest allowed wait.
+        let exhausted = config.gate(
+            Arc::new(ReportingBackend {
+                inner: MockBackend::named("mock-b"),
+                limits: ProviderLimits {
+                    requests: Some(Budget {
+                        limit: Some(500),
+                        remaining: 0,
+                        resets_at: now + Duration::from_secs(10),
+                    }),
+                    tokens: None,
+                },
+            }),
+            metrics,
+        );
+        assert!(matches!(
+            exhausted.execute_chat(request()).await,
+            Err(BackendError::Overloaded { .. })
+        ));
+    }
+}
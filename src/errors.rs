@@ -6,19 +6,42 @@ use axum::{
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::{backend::BackendError, models::InvalidParameter};
+
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("{0}")]
     BadRequest(String),
     #[error("{0}")]
+    InvalidParameter(#[from] InvalidParameter),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{0}")]
     Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
     #[error("{message}")]
     RateLimited {
         message: String,
         headers: Vec<(String, String)>,
     },
+    /// The tenant's prepaid balance is used up.
+    #[error("{message}")]
+    InsufficientCredits { message: String, balance_usd: f64 },
     #[error("{0}")]
     Backend(String),
+    /// The provider refused the gateway's own credentials; an operator has to fix the keys.
+    #[error("{0}")]
+    UpstreamUnauthorized(String),
+    #[error("{message}")]
+    Overloaded {
+        message: String,
+        retry_after_secs: u64,
+    },
+    #[error("{0}")]
+    GatewayTimeout(String),
     #[error("{0}")]
     Internal(String),
 }
@@ -33,44 +56,153 @@ struct OpenAiError {
     message: String,
     #[serde(rename = "type")]
     error_type: String,
+    /// Always present, `null` when no single parameter is at fault, as in OpenAI's payloads.
+    param: Option<String>,
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        self.render(None)
+    }
+}
+
+impl AppError {
+    /// Renders the error envelope with the request id so clients can quote it in reports.
+    pub fn into_response_for(self, request_id: &str) -> Response {
+        self.render(Some(request_id))
+    }
+
+    /// Error class forwarded to the error reporter; client and capacity errors are not reported.
+    pub fn report_kind(&self) -> Option<&'static str> {
         match self {
-            AppError::BadRequest(message) => {
-                make_error_response(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+            AppError::Backend(_) => Some("backend"),
+            AppError::UpstreamUnauthorized(_) => Some("backend_auth"),
+            AppError::Internal(_) => Some("internal"),
+            _ => None,
+        }
+    }
+
+    /// OpenAI-style `code`, which SDK error handling branches on.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            AppError::BadRequest(_) => None,
+            AppError::InvalidParameter(invalid) => Some(invalid.code),
+            AppError::NotFound(_) => Some("not_found"),
+            AppError::PayloadTooLarge(_) => Some("request_too_large"),
+            AppError::Unauthorized(_) => Some("invalid_api_key"),
+            AppError::Forbidden(_) => Some("permission_denied"),
+            AppError::RateLimited { .. } => Some("rate_limit_exceeded"),
+            AppError::InsufficientCredits { .. } => Some("insufficient_credits"),
+            AppError::Backend(_) => Some("upstream_error"),
+            AppError::UpstreamUnauthorized(_) => Some("upstream_unauthorized"),
+            AppError::Overloaded { .. } => Some("overloaded"),
+            AppError::GatewayTimeout(_) => Some("timeout"),
+            AppError::Internal(_) => Some("internal_error"),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status_and_type().0
+    }
+
+    fn status_and_type(&self) -> (StatusCode, &'static str) {
+        match self {
+            AppError::BadRequest(_) | AppError::InvalidParameter(_) => {
+                (StatusCode::BAD_REQUEST, "invalid_request_error")
+            }
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, "invalid_request_error"),
+            AppError::PayloadTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "invalid_request_error")
+            }
+            AppError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "authentication_error"),
+            AppError::Forbidden(_) => (StatusCode::FORBIDDEN, "permission_error"),
+            AppError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error"),
+            AppError::InsufficientCredits { .. } => {
+                (StatusCode::PAYMENT_REQUIRED, "insufficient_credits")
             }
-            AppError::Unauthorized(message) => {
-                make_error_response(StatusCode::UNAUTHORIZED, "authentication_error", message)
+            AppError::Backend(_) | AppError::UpstreamUnauthorized(_) => {
+                (StatusCode::BAD_GATEWAY, "backend_error")
             }
-            AppError::RateLimited { message, headers } => {
-                let mut response =
-                    make_error_response(StatusCode::TOO_MANY_REQUESTS, "rate_limit_error", message);
+            AppError::Overloaded { .. } => (StatusCode::SERVICE_UNAVAILABLE, "overloaded"),
+            AppError::GatewayTimeout(_) => (StatusCode::GATEWAY_TIMEOUT, "timeout_error"),
+            AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+        }
+    }
+
+    fn envelope(&self, request_id: Option<&str>) -> OpenAiErrorEnvelope {
+        let (_, error_type) = self.status_and_type();
+        OpenAiErrorEnvelope {
+            error: OpenAiError {
+                message: self.to_string(),
+                error_type: error_type.to_owned(),
+                param: match self {
+                    AppError::InvalidParameter(invalid) => Some(invalid.param.to_owned()),
+                    _ => None,
+                },
+                code: self.code().map(ToOwned::to_owned),
+                request_id: request_id.map(ToOwned::to_owned),
+            },
+        }
+    }
+
+    /// The same envelope as the HTTP error body, serialized for the `data:` line of the SSE
+    /// event that ends a stream which fails after its headers were sent.
+    pub fn stream_event_data(&self, request_id: Option<&str>) -> String {
+        serde_json::to_string(&self.envelope(request_id)).unwrap_or_else(|_| {
+            r#"{"error":{"message":"internal error","type":"server_error","param":null,"code":"internal_error"}}"#
+                .to_owned()
+        })
+    }
+
+    fn render(self, request_id: Option<&str>) -> Response {
+        let (status, _) = self.status_and_type();
+        let mut response = (status, Json(self.envelope(request_id))).into_response();
+        match self {
+            AppError::RateLimited { headers, .. } => {
                 for (name, value) in headers {
                     apply_header(response.headers_mut(), &name, &value);
                 }
-                response
-            }
-            AppError::Backend(message) => {
-                make_error_response(StatusCode::BAD_GATEWAY, "backend_error", message)
-            }
-            AppError::Internal(message) => {
-                make_error_response(StatusCode::INTERNAL_SERVER_ERROR, "server_error", message)
             }
+            AppError::InsufficientCredits { balance_usd, .. } => apply_header(
+                response.headers_mut(),
+                "x-gateway-credits-remaining",
+                &crate::credits::format_balance(balance_usd),
+            ),
+            AppError::Overloaded {
+                retry_after_secs, ..
+            } => apply_header(
+                response.headers_mut(),
+                "retry-after",
+                &retry_after_secs.to_string(),
+            ),
+            _ => {}
         }
+        response
     }
 }
 
-fn make_error_response(status: StatusCode, error_type: &str, message: String) -> Response {
-    let payload = OpenAiErrorEnvelope {
-        error: OpenAiError {
-            message,
-            error_type: error_type.to_owned(),
-        },
-    };
-
-    (status, Json(payload)).into_response()
+impl From<BackendError> for AppError {
+    fn from(error: BackendError) -> Self {
+        match error {
+            BackendError::Overloaded {
+                message,
+                retry_after_secs,
+            } => AppError::Overloaded {
+                message,
+                retry_after_secs,
+            },
+            BackendError::DeadlineExceeded(_) => AppError::GatewayTimeout(error.to_string()),
+            BackendError::RateLimited {
+                message, headers, ..
+            } => AppError::RateLimited { message, headers },
+            BackendError::BadRequest(_) => AppError::BadRequest(error.to_string()),
+            BackendError::Unauthorized(_) => AppError::UpstreamUnauthorized(error.to_string()),
+            other => AppError::Backend(other.to_string()),
+        }
+    }
 }
 
 pub fn apply_header(headers: &mut axum::http::HeaderMap, name: &str, value: &str) {
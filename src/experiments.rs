@@ -0,0 +1,196 @@
+use std::{collections::HashSet, env};
+
+use serde::Deserialize;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::glob;
+
+/// An A/B test splitting one model's traffic between a control and a treatment variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    pub name: String,
+    /// Glob over the requested model; the first matching experiment assigns the request.
+    pub model: String,
+    #[serde(default)]
+    pub split: SplitBy,
+    /// Share of matching traffic, from 0 to 100, sent to `treatment`.
+    pub treatment_percent: u8,
+    pub control: Variant,
+    pub treatment: Variant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Variant {
+    pub name: String,
+    /// Model the variant is served by; `None` keeps the requested one.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    /// Hashes the API key, so a key sees the same variant on every request.
+    #[default]
+    Key,
+    /// Draws each request independently.
+    Request,
+}
+
+/// The variant a request was assigned to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Experiments {
+    experiments: Vec<Experiment>,
+}
+
+impl Experiments {
+    pub fn new(experiments: Vec<Experiment>) -> Self {
+        Self { experiments }
+    }
+
+    /// Reads `GATEWAY_EXPERIMENTS`, a JSON array of experiments.
+    pub fn from_env() -> Self {
+        let Ok(raw) = env::var("GATEWAY_EXPERIMENTS") else {
+            return Self::default();
+        };
+        if raw.trim().is_empty() {
+            return Self::default();
+        }
+        match Self::parse(&raw) {
+            Ok(experiments) => Self::new(experiments),
+            Err(error) => {
+                warn!(error = %error, "invalid GATEWAY_EXPERIMENTS, running no experiments");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Vec<Experiment>, String> {
+        let experiments: Vec<Experiment> =
+            serde_json::from_str(raw).map_err(|error| error.to_string())?;
+        let mut names = HashSet::new();
+        for experiment in &experiments {
+            if !names.insert(experiment.name.as_str()) {
+                return Err(format!("experiment `{}` is defined twice", experiment.name));
+            }
+            if experiment.treatment_percent > 100 {
+                return Err(format!(
+                    "experiment `{}` sends more than 100 percent to its treatment",
+                    experiment.name
+                ));
+            }
+            if experiment.control.name == experiment.treatment.name {
+                return Err(format!(
+                    "experiment `{}` gives both variants the same name",
+                    experiment.name
+                ));
+            }
+        }
+        Ok(experiments)
+    }
+
+    /// Assigns a request for `model` from `api_key` to a variant of the first experiment
+    /// covering the model.
+    pub fn assign(&self, model: &str, api_key: &str) -> Option<Assignment> {
+        let experiment = self
+            .experiments
+            .iter()
+            .find(|experiment| glob::matches(&experiment.model, model))?;
+        let bucket = match experiment.split {
+            SplitBy::Key => key_bucket(&experiment.name, api_key),
+            SplitBy::Request => (Uuid::new_v4().as_u128() % 100) as u8,
+        };
+        let variant = if bucket < experiment.treatment_percent {
+            &experiment.treatment
+        } else {
+            &experiment.control
+        };
+        Some(Assignment {
+            experiment: experiment.name.clone(),
+            variant: variant.name.clone(),
+            model: variant.model.clone(),
+        })
+    }
+}
+
+/// A stable 0–99 bucket for `api_key`, salted with the experiment name so one key does not
+/// land in every experiment's treatment.
+fn key_bucket(experiment: &str, api_key: &str) -> u8 {
+    // FNV-1a: stable across processes and releases, unlike `DefaultHasher`.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in experiment
+        .bytes()
+        .chain(std::iter::once(0))
+        .chain(api_key.bytes())
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Experiments;
+
+    const EXPERIMENTS: &str = r#"[
+        {"name":"mini","model":"gpt-4o","treatment_percent":30,
+         "control":{"name":"control"},"treatment":{"name":"mini","model":"gpt-4o-mini"}},
+        {"name":"always","model":"claude-*","split":"request","treatment_percent":100,
+         "control":{"name":"a"},"treatment":{"name":"b","model":"claude-haiku"}}
+    ]"#;
+
+    #[test]
+    fn splits_by_key_hash_or_per_request() {
+        let experiments = Experiments::new(Experiments::parse(EXPERIMENTS).expect("valid"));
+
+        let keys = (0..1000)
+            .map(|key| format!("key-{key}"))
+            .collect::<Vec<_>>();
+        let treated = keys
+            .iter()
+            .filter(|key| {
+                let assignment = experiments.assign("gpt-4o", key).expect("assigned");
+                // Key splits are sticky.
+                assert_eq!(experiments.assign("gpt-4o", key), Some(assignment.clone()));
+                assignment.variant == "mini"
+            })
+            .count();
+        assert!((200..400).contains(&treated), "{treated} of 1000 treated");
+
+        let assignment = experiments
+            .assign("claude-sonnet", "key-1")
+            .expect("assigned");
+        assert_eq!(assignment.variant, "b");
+        assert_eq!(assignment.model.as_deref(), Some("claude-haiku"));
+        assert_eq!(experiments.assign("llama", "key-1"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_experiments() {
+        let variants = r#""control":{"name":"a"},"treatment":{"name":"b"}"#;
+        assert!(Experiments::parse(&format!(
+            r#"[{{"name":"x","model":"*","treatment_percent":101,{variants}}}]"#
+        ))
+        .is_err());
+        assert!(Experiments::parse(&format!(
+            r#"[{{"name":"x","model":"*","treatment_percent":5,{variants}}},
+                {{"name":"x","model":"*","treatment_percent":5,{variants}}}]"#
+        ))
+        .is_err());
+        assert!(Experiments::parse(
+            r#"[{"name":"x","model":"*","treatment_percent":5,"control":{"name":"a"},"treatment":{"name":"a"}}]"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::{
+    backend::{with_deadline, BackendError, BackendStream, InferenceBackend},
+    metrics::{AppMetrics, PoolUsage},
+    models::{BackendChatResponse, NormalizedChatRequest, Priority},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct FairQueueConfig {
+    /// Requests dispatched to the backend at once across all flows; `0` disables fair queuing.
+    pub max_concurrency: usize,
+    /// Dequeue weight per key-policy tier; flows without a known tier weigh `1`.
+    pub tier_weights: HashMap<String, u32>,
+    /// Default and maximum request priority per tier, for keys whose policy sets none.
+    pub tier_priorities: HashMap<String, Priority>,
+}
+
+impl FairQueueConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_concurrency: env::var("GATEWAY_FAIR_MAX_CONCURRENCY")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(0),
+            tier_weights: read_tier_weights(),
+            tier_priorities: read_tier_priorities(),
+        }
+    }
+
+    pub fn weight_for(&self, tier: Option<&str>) -> u32 {
+        tier.and_then(|tier| self.tier_weights.get(tier))
+            .copied()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn priority_for(&self, tier: Option<&str>) -> Option<Priority> {
+        tier.and_then(|tier| self.tier_priorities.get(tier))
+            .copied()
+    }
+}
+
+/// Dispatch slots shared by every flow (tenant). When all slots are busy, waiters queue per
+/// flow and freed slots are handed out by weighted deficit round-robin, so one busy flow
+/// cannot starve the others however many requests it has waiting. Within a flow, waiters are
+/// served highest priority first, so a tenant's interactive traffic overtakes its own batch
+/// jobs without taking slots from other tenants.
+#[derive(Default)]
+pub struct FairQueue {
+    config: FairQueueConfig,
+    state: Mutex<FairState>,
+    metrics: Arc<AppMetrics>,
+}
+
+#[derive(Default)]
+struct FairState {
+    available: usize,
+    flows: HashMap<String, Flow>,
+    /// Flows with waiters, in round-robin order.
+    active: VecDeque<String>,
+}
+
+struct Flow {
+    weight: u32,
+    /// Grants left to this flow in the current round.
+    deficit: u32,
+    /// Highest priority first, in arrival order within a priority.
+    waiters: VecDeque<(Priority, oneshot::Sender<FairPermit>)>,
+}
+
+/// A dispatch slot; dropping it hands the slot to the next flow in line.
+pub struct FairPermit {
+    queue: Option<Arc<FairQueue>>,
+    _usage: Option<PoolUsage>,
+}
+
+impl Drop for FairPermit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+impl FairQueue {
+    pub fn new(config: FairQueueConfig, metrics: Arc<AppMetrics>) -> Self {
+        let available = config.max_concurrency;
+        if available > 0 {
+            metrics.set_pool_capacity("fair", available);
+        }
+        Self {
+            config,
+            state: Mutex::new(FairState {
+                available,
+                ..FairState::default()
+            }),
+            metrics,
+        }
+    }
+
+    pub fn config(&self) -> &FairQueueConfig {
+        &self.config
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.max_concurrency > 0
+    }
+
+    /// Wraps `inner` so each call first takes a dispatch slot on behalf of `flow`.
+    pub fn gate(
+        self: &Arc<Self>,
+        inner: Arc<dyn InferenceBackend>,
+        flow: &str,
+        weight: u32,
+    ) -> Arc<dyn InferenceBackend> {
+        if !self.enabled() {
+            return inner;
+        }
+        Arc::new(FairBackend {
+            queue: self.clone(),
+            inner,
+            flow: flow.to_owned(),
+            weight,
+        })
+    }
+
+    pub async fn acquire(
+        self: &Arc<Self>,
+        flow: &str,
+        weight: u32,
+        priority: Priority,
+    ) -> FairPermit {
+        let receiver = {
+            let mut state = self.lock();
+            if state.available > 0 && state.active.is_empty() {
+                state.available -= 1;
+                return self.permit();
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let flow_state = state.flows.entry(flow.to_owned()).or_insert_with(|| Flow {
+                weight,
+                deficit: 0,
+                waiters: VecDeque::new(),
+            });
+            flow_state.weight = weight.max(1);
+            let position = flow_state
+                .waiters
+                .iter()
+                .position(|(queued, _)| *queued < priority)
+                .unwrap_or(flow_state.waiters.len());
+            flow_state.waiters.insert(position, (priority, tx));
+            if flow_state.waiters.len() == 1 {
+                state.active.push_back(flow.to_owned());
+            }
+            self.metrics.adjust_queue_depth("fair", 1);
+            rx
+        };
+
+        // The sender only disappears if the queue itself is dropped, which never happens
+        // while a caller still holds an `Arc` to it.
+        receiver.await.unwrap_or_else(|_| FairPermit {
+            queue: None,
+            _usage: None,
+        })
+    }
+
+    fn permit(self: &Arc<Self>) -> FairPermit {
+        FairPermit {
+            queue: Some(self.clone()),
+            _usage: Some(self.metrics.pool_usage("fair")),
+        }
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.lock();
+        state.available += 1;
+        self.dispatch(&mut state);
+    }
+
+    /// Hands free slots to waiting flows: the flow at the front of the ring receives up to
+    /// `weight` grants per round before it rotates to the back.
+    fn dispatch(self: &Arc<Self>, state: &mut FairState) {
+        while state.available > 0 {
+            let Some(flow_key) = state.active.front().cloned() else {
+                return;
+            };
+            let Some(flow) = state.flows.get_mut(&flow_key) else {
+                state.active.pop_front();
+                continue;
+            };
+            if flow.deficit == 0 {
+                flow.deficit = flow.weight;
+            }
+
+            let mut granted = false;
+            while let Some((_, waiter)) = flow.waiters.pop_front() {
+                self.metrics.adjust_queue_depth("fair", -1);
+                match waiter.send(self.permit()) {
+                    Ok(()) => {
+                        granted = true;
+                        break;
+                    }
+                    // The caller gave up while queued; the returned permit must not release
+                    // the slot again while the lock is held.
+                    Err(mut permit) => permit.queue = None,
+                }
+            }
+            if granted {
+                state.available -= 1;
+                flow.deficit -= 1;
+            }
+
+            if flow.waiters.is_empty() {
+                state.flows.remove(&flow_key);
+                state.active.pop_front();
+            } else if flow.deficit == 0 {
+                state.active.rotate_left(1);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FairState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+struct FairBackend {
+    queue: Arc<FairQueue>,
+    inner: Arc<dyn InferenceBackend>,
+    flow: String,
+    weight: u32,
+}
+
+impl FairBackend {
+    async fn acquire(&self, request: &NormalizedChatRequest) -> Result<FairPermit, BackendError> {
+        let started = Instant::now();
+        let permit = with_deadline(request.deadline, "fair queue", async {
+            Ok(self
+                .queue
+                .acquire(&self.flow, self.weight, request.priority)
+                .await)
+        })
+        .await?;
+        self.queue
+            .metrics
+            .observe_queue_wait("fair", started.elapsed());
+        Ok(permit)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for FairBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        let _permit = self.acquire(&request).await?;
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let permit = self.acquire(&request).await?;
+        let stream = self.inner.stream_chat(request).await?;
+        Ok(stream
+            .map(move |item| {
+                let _slot = &permit;
+                item
+            })
+            .boxed())
+    }
+}
+
+fn read_tier_weights() -> HashMap<String, u32> {
+    let Ok(raw) = env::var("GATEWAY_FAIR_TIER_WEIGHTS") else {
+        return HashMap::new();
+    };
+    if raw.trim().is_empty() {
+        return HashMap::new();
+    }
+
+    match serde_json::from_str::<HashMap<String, u32>>(&raw) {
+        Ok(weights) => weights,
+        Err(error) => {
+            warn!(error = %error, "invalid GATEWAY_FAIR_TIER_WEIGHTS, using equal weights");
+            HashMap::new()
+        }
+    }
+}
+
+fn read_tier_priorities() -> HashMap<String, Priority> {
+    let Ok(raw) = env::var("GATEWAY_FAIR_TIER_PRIORITIES") else {
+        return HashMap::new();
+    };
+    if raw.trim().is_empty() {
+        return HashMap::new();
+    }
+
+    match serde_json::from_str::<HashMap<String, Priority>>(&raw) {
+        Ok(priorities) => priorities,
+        Err(error) => {
+            warn!(error = %error, "invalid GATEWAY_FAIR_TIER_PRIORITIES, tiers set no priority");
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::mpsc;
+
+    use super::{FairQueue, FairQueueConfig};
+    use crate::{metrics::AppMetrics, models::Priority};
+
+    #[tokio::test]
+    async fn weighted_flows_share_slots_by_weight() {
+        let queue = Arc::new(FairQueue::new(
+            FairQueueConfig {
+                max_concurrency: 1,
+                ..FairQueueConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let held = queue.acquire("warmup", 1, Priority::Normal).await;
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        for (flow, weight, count) in [("heavy", 1, 6), ("pro", 2, 4)] {
+            for _ in 0..count {
+                let queue = queue.clone();
+                let order_tx = order_tx.clone();
+                tokio::spawn(async move {
+                    let _permit = queue.acquire(flow, weight, Priority::Normal).await;
+                    order_tx.send(flow).expect("record grant");
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                });
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        drop(order_tx);
+        drop(held);
+
+        let mut order = Vec::new();
+        while let Some(flow) = order_rx.recv().await {
+            order.push(flow);
+        }
+        assert_eq!(
+            order,
+            vec!["heavy", "pro", "pro", "heavy", "pro", "pro", "heavy", "heavy", "heavy", "heavy"]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_do_not_leak_slots() {
+        let queue = Arc::new(FairQueue::new(
+            FairQueueConfig {
+                max_concurrency: 1,
+                ..FairQueueConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let held = queue.acquire("a", 1, Priority::Normal).await;
+
+        let abandoned = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire("b", 1, Priority::Normal).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        drop(held);
+
+        tokio::time::timeout(
+            Duration::from_millis(100),
+            queue.acquire("c", 1, Priority::Normal),
+        )
+        .await
+        .expect("slot should be free again");
+    }
+
+    #[tokio::test]
+    async fn higher_priority_waiters_go_first_within_a_flow() {
+        let queue = Arc::new(FairQueue::new(
+            FairQueueConfig {
+                max_concurrency: 1,
+                ..FairQueueConfig::default()
+            },
+            Arc::new(AppMetrics::new()),
+        ));
+        let held = queue.acquire("tenant", 1, Priority::Normal).await;
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        for (name, priority) in [
+            ("batch-1", Priority::Low),
+            ("chat-1", Priority::Normal),
+            ("batch-2", Priority::Low),
+            ("urgent", Priority::High),
+            ("chat-2", Priority::Normal),
+        ] {
+            let queue = queue.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire("tenant", 1, priority).await;
+                order_tx.send(name).expect("record grant");
+            });
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        drop(order_tx);
+        drop(held);
+
+        let mut order = Vec::new();
+        while let Some(name) = order_rx.recv().await {
+            order.push(name);
+        }
+        assert_eq!(order, ["urgent", "chat-1", "chat-2", "batch-1", "batch-2"]);
+    }
+}
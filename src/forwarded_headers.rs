@@ -0,0 +1,142 @@
+use std::env;
+
+use axum::http::{HeaderMap, HeaderName};
+use tracing::warn;
+
+/// Headers that describe how the gateway frames its own body, which no upstream value may
+/// stand in for.
+const FRAMING: [&str; 5] = [
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-type",
+    "transfer-encoding",
+];
+
+/// Headers the gateway always sets on chat responses, some only after the handler has run.
+const GATEWAY_OWNED: [&str; 1] = ["x-request-id"];
+
+/// Upstream response headers passed on to clients, e.g. `openai-processing-ms` or a provider's
+/// own request id for finding a call in the provider's logs. Every other upstream header is
+/// dropped, as are all of them while the list is empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedHeaders {
+    /// Each upstream name with the `x-upstream-` name it goes out under when the gateway
+    /// already uses the original.
+    names: Vec<(HeaderName, HeaderName)>,
+}
+
+impl ForwardedHeaders {
+    /// Fails on a name that is not a valid header name or frames the response body.
+    pub fn new<I, S>(names: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut forwarded = Vec::<(HeaderName, HeaderName)>::new();
+        for name in names {
+            let name = name.as_ref().trim();
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("`{name}` is not a valid header name"))?;
+            if FRAMING.contains(&header.as_str()) {
+                return Err(format!(
+                    "`{header}` frames the response and cannot be forwarded"
+                ));
+            }
+            if forwarded.iter().any(|(seen, _)| *seen == header) {
+                continue;
+            }
+            let bare = header
+                .as_str()
+                .strip_prefix("x-")
+                .unwrap_or(header.as_str());
+            let renamed = HeaderName::from_bytes(format!("x-upstream-{bare}").as_bytes())
+                .map_err(|_| format!("`{name}` is not a valid header name"))?;
+            forwarded.push((header, renamed));
+        }
+        Ok(Self { names: forwarded })
+    }
+
+    /// Reads the comma-separated `GATEWAY_FORWARD_RESPONSE_HEADERS`; an invalid list forwards
+    /// nothing.
+    pub fn from_env() -> Self {
+        let Ok(raw) = env::var("GATEWAY_FORWARD_RESPONSE_HEADERS") else {
+            return Self::default();
+        };
+        let names = raw.split(',').filter(|name| !name.trim().is_empty());
+        Self::new(names).unwrap_or_else(|error| {
+            warn!(%error, "invalid GATEWAY_FORWARD_RESPONSE_HEADERS, forwarding no headers");
+            Self::default()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Copies the listed headers from `upstream` onto `response`. One the gateway sets itself,
+    /// such as `x-request-id` or its own `x-ratelimit-*`, is kept and the upstream value goes
+    /// out as `x-upstream-<name>` instead, e.g. `x-upstream-request-id`.
+    pub fn apply(&self, upstream: Option<&HeaderMap>, response: &mut HeaderMap) {
+        let Some(upstream) = upstream else {
+            return;
+        };
+        for (name, renamed) in &self.names {
+            let taken = GATEWAY_OWNED.contains(&name.as_str()) || response.contains_key(name);
+            let target = if taken { renamed } else { name };
+            for value in upstream.get_all(name) {
+                response.append(target.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::ForwardedHeaders;
+
+    #[test]
+    fn forwards_listed_headers_renaming_the_gateways_own() {
+        let forwarded = ForwardedHeaders::new([
+            "OpenAI-Processing-Ms",
+            " x-ratelimit-remaining-tokens",
+            "x-request-id",
+        ])
+        .expect("valid names");
+        let mut upstream = HeaderMap::new();
+        upstream.insert("openai-processing-ms", HeaderValue::from_static("412"));
+        upstream.insert("openai-organization", HeaderValue::from_static("org-acme"));
+        upstream.insert(
+            "x-ratelimit-remaining-tokens",
+            HeaderValue::from_static("9000"),
+        );
+        upstream.insert("x-request-id", HeaderValue::from_static("req_upstream"));
+        let mut response = HeaderMap::new();
+        response.insert(
+            "x-ratelimit-remaining-tokens",
+            HeaderValue::from_static("120"),
+        );
+
+        forwarded.apply(Some(&upstream), &mut response);
+        assert_eq!(response["openai-processing-ms"], "412");
+        assert!(!response.contains_key("openai-organization"));
+        // Names the gateway uses itself keep its value; the upstream's is renamed.
+        assert_eq!(response["x-ratelimit-remaining-tokens"], "120");
+        assert_eq!(response["x-upstream-ratelimit-remaining-tokens"], "9000");
+        assert!(!response.contains_key("x-request-id"));
+        assert_eq!(response["x-upstream-request-id"], "req_upstream");
+
+        let mut untouched = HeaderMap::new();
+        ForwardedHeaders::default().apply(Some(&upstream), &mut untouched);
+        assert!(untouched.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_and_framing_names() {
+        assert!(ForwardedHeaders::new(["not a header"]).is_err());
+        assert!(ForwardedHeaders::new(["Content-Length"]).is_err());
+        assert!(ForwardedHeaders::new(["transfer-encoding"]).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+/// Matches `value` against a pattern where `*` matches any run of characters (including none).
+/// Model-name rules are the only consumer, so there is no escaping or character classes.
+pub fn matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let value = value.as_bytes();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == value[v] {
+            p += 1;
+            v += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcard_patterns_match_model_names() {
+        assert!(matches("*", "gpt-4o"));
+        assert!(matches("gpt-4o", "gpt-4o"));
+        assert!(matches("*-realtime", "gpt-4o-realtime"));
+        assert!(matches("*mini*", "gpt-4o-mini-2024"));
+        assert!(matches("llama-*-70b", "llama-3.1-70b"));
+        assert!(!matches("*-realtime", "gpt-4o-realtime-preview"));
+        assert!(!matches("gpt-4o", "gpt-4o-mini"));
+        assert!(!matches("llama-*-70b", "llama-3.1-8b"));
+    }
+}
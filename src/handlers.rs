@@ -1,197 +1,1186 @@
 use std::{
     convert::Infallible,
+    future::Future,
+    pin::Pin,
     sync::Arc,
-    time::{Instant, SystemTime, UNIX_EPOCH},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
-    extract::State,
-    http::{header::CONTENT_TYPE, HeaderMap},
+    body::{Body, Bytes, HttpBody},
+    extract::{rejection::JsonRejection, State},
+    http::{
+        header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+        HeaderMap, HeaderValue,
+    },
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Response,
     },
-    Json,
+    Extension, Json,
 };
-use futures_util::StreamExt;
-use tracing::{info, warn};
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{info, warn, Instrument};
 use uuid::Uuid;
 
 use crate::{
-    backend::InferenceBackend,
-    coalescing::CoalesceOutcome,
+    access_log::AccessRecord,
+    admission::{Admission, AdmissionTicket, QueueProgress, QueuedRequest},
+    auth::{AuthContext, RatePolicy},
+    backend::{stream_with_idle_timeout, with_deadline, BackendError, InferenceBackend},
+    cache::CacheDirective,
+    coalescing::{CoalesceOutcome, StreamItem, StreamReceiver},
     errors::AppError,
-    limits::{estimate_request_tokens, RateLimitSnapshot},
+    experiments::Assignment,
+    limits::{estimate_prompt_tokens, estimate_request_tokens, RateLimitSnapshot},
+    metrics::AppMetrics,
     models::{
-        ChatCompletionsChunk, ChatCompletionsRequest, ChatCompletionsResponse,
-        NormalizedChatRequest,
+        BackendChatResponse, BackendChunk, ChatCompletionsChunk, ChatCompletionsRequest,
+        ChatCompletionsResponse, InvalidParameter, NormalizedChatRequest, StreamTranscript, Usage,
     },
+    postprocess::ProcessorChain,
+    request_id::RequestId,
     scheduler,
     state::AppState,
+    streaming::StreamingConfig,
+    traces::TraceCapture,
+    truncation::TruncationStrategy,
+    usage_sink::UsageSink,
 };
 
+/// Answers with the request's plan instead of running it.
+const DRY_RUN_HEADER: &str = "x-gateway-dry-run";
+
 pub async fn healthz() -> &'static str {
     "ok"
 }
 
-pub async fn metrics(State(state): State<AppState>) -> Response {
-    match state.metrics.render() {
-        Ok(body) => (
-            [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
-            body,
+/// `GET /v1/models`: the catalog's models the caller's tenant may call, in OpenAI's list
+/// shape. Without a catalog the list is empty, since any model name is passed through.
+pub async fn list_models(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    match state.auth.authenticate(&headers).await {
+        Ok(auth) => {
+            Json(state.catalog.list(|model| auth.tenant.allows_model(model))).into_response()
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+pub async fn metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    // Exemplars only exist in OpenMetrics, served while traces are exported for them to name
+    // and the scraper asks for it.
+    let openmetrics = state.traces.enabled()
+        && headers
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    let (content_type, rendered) = if openmetrics {
+        (
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            state.metrics.render_openmetrics(),
         )
-            .into_response(),
+    } else {
+        (
+            "text/plain; version=0.0.4; charset=utf-8",
+            state.metrics.render(),
+        )
+    };
+    match rendered {
+        Ok(body) => ([(CONTENT_TYPE, content_type)], body).into_response(),
         Err(error) => AppError::Internal(format!("metrics render failed: {error}")).into_response(),
     }
 }
 
 pub async fn chat_completions(
     State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
     headers: HeaderMap,
-    Json(request): Json<ChatCompletionsRequest>,
+    payload: Result<Json<ChatCompletionsRequest>, JsonRejection>,
 ) -> Response {
+    let request = match payload {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            state.metrics.observe_request_rejection("invalid_json");
+            return AppError::BadRequest(rejection.body_text())
+                .into_response_for(request_id.as_str());
+        }
+    };
     let started = Instant::now();
     let stream = request.stream;
     let _inflight = state.metrics.inflight_guard();
+    let access = state.access_log.record();
+    let trace = state.traces.start();
 
-    let response = match process_chat_completions(state.clone(), headers, request).await {
+    let response = match process_chat_completions(
+        state.clone(),
+        headers,
+        request,
+        request_id.clone(),
+        access.clone(),
+        trace.clone(),
+    )
+    .await
+    {
         Ok(response) => response,
-        Err(error) => error.into_response(),
+        Err(error) => {
+            if let Some(kind) = error.report_kind() {
+                state
+                    .error_reporter
+                    .capture(access.error_report(kind, error.to_string()));
+            }
+            error.into_response_for(request_id.as_str())
+        }
     };
 
+    let elapsed = started.elapsed();
     state.metrics.observe_request(
         "/v1/chat/completions",
         "POST",
         stream,
         response.status().as_u16(),
-        started.elapsed(),
+        elapsed,
     );
+    // Only successful requests have a trace exported for the exemplar to point at.
+    if let Some(trace_id) = state
+        .traces
+        .trace_id(request_id.as_str())
+        .filter(|_| response.status().is_success())
+    {
+        state.metrics.observe_latency_exemplar(
+            "/v1/chat/completions",
+            "POST",
+            stream,
+            elapsed,
+            &trace_id,
+        );
+    }
+    access.set_status(response.status().as_u16());
 
-    response
+    hold_until_body_ends(
+        response,
+        RequestLogOnDrop {
+            access,
+            usage_sink: state.usage_sink.clone(),
+            metrics: state.metrics.clone(),
+            trace,
+        },
+    )
 }
 
-#[tracing::instrument(skip(state, headers, request), fields(stream = request.stream))]
+/// `POST /v1/chat/completions/validate`: a chat completion request answered with its dry-run
+/// plan, whatever its headers say.
+pub async fn validate_chat_completions(
+    state: State<AppState>,
+    request_id: Extension<RequestId>,
+    mut headers: HeaderMap,
+    payload: Result<Json<ChatCompletionsRequest>, JsonRejection>,
+) -> Response {
+    headers.insert(DRY_RUN_HEADER, HeaderValue::from_static("1"));
+    chat_completions(state, request_id, headers, payload).await
+}
+
+#[tracing::instrument(
+    skip(state, headers, request, request_id, access, trace),
+    fields(
+        request_id = %request_id.as_str(),
+        stream = request.stream,
+        tenant_id = tracing::field::Empty
+    )
+)]
 async fn process_chat_completions(
     state: AppState,
     headers: HeaderMap,
     request: ChatCompletionsRequest,
+    request_id: RequestId,
+    access: AccessRecord,
+    trace: TraceCapture,
 ) -> Result<Response, AppError> {
-    let client_user = request.user.clone();
-    let auth_context = state.auth.authenticate(&headers)?;
+    let received = Instant::now();
+    let dry_run = header_is_on(&headers, DRY_RUN_HEADER);
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    let mut auth_context = state.auth.authenticate(&headers).await?;
+    if let Some(user) = state
+        .peer_trust
+        .forwarded_user(&headers, request_id.as_str())?
+    {
+        auth_context.user_id = user;
+    }
+    tracing::Span::current().record("tenant_id", auth_context.tenant.id.as_str());
+    access.set_tenant(&auth_context.tenant.id);
+    let header_directive = CacheDirective::from_headers(&headers);
+    let priority = auth_context.request_priority(
+        &headers,
+        state
+            .fair_queue
+            .config()
+            .priority_for(auth_context.key_policy.tier.as_deref()),
+    )?;
     let user_id = auth_context.user_id.clone();
-    let normalized = request
-        .into_normalized(user_id)
-        .map_err(AppError::BadRequest)?;
-    let estimated_tokens = estimate_request_tokens(&normalized);
-    let rate_snapshot = state
-        .rate_limiter
-        .check_and_consume(
-            &auth_context.api_key,
-            &auth_context.policy,
-            estimated_tokens,
+    let mut normalized = request.into_normalized(user_id)?;
+    if normalized.stream && auth_context.key_policy.streaming == Some(false) {
+        return Err(InvalidParameter::new(
+            "stream",
+            "stream_not_allowed",
+            "streaming is not enabled for this API key; send `stream: false`",
         )
-        .await
-        .map_err(|error| AppError::RateLimited {
-            message: error.message().to_owned(),
-            headers: error.snapshot().to_header_pairs(),
-        })?;
-
-    let fingerprint = scheduler::fingerprint_for(&normalized);
-    info!(
-        request_id = %normalized.request_id,
-        user_id = %normalized.user_id,
-        model = %normalized.model,
-        stream = normalized.stream,
-        estimated_tokens,
-        client_user = %client_user.unwrap_or_default(),
-        fingerprint = %fingerprint.as_str(),
-        "chat request accepted"
+        .into());
+    }
+    let pinned = pinned_backend(&state, &headers, &auth_context).await?;
+    normalized.pinned_backend = pinned.clone();
+    let requested_model = normalized.model.clone();
+    state.catalog.apply(&mut normalized)?;
+    if let Some(system_prompt) = auth_context.system_prompt() {
+        system_prompt.apply(&mut normalized);
+    }
+    if !auth_context.tenant.allows_model(&normalized.model) {
+        return Err(AppError::Forbidden(format!(
+            "model `{}` is not enabled for this tenant",
+            normalized.model
+        )));
+    }
+    // A variant may not take a tenant's traffic to a model outside its allowlist; such a
+    // request is served as asked, outside the experiment.
+    let assignment = state
+        .experiments
+        .assign(&normalized.model, &auth_context.api_key)
+        .filter(|assignment| {
+            assignment
+                .model
+                .as_ref()
+                .map_or(true, |model| auth_context.tenant.allows_model(model))
+        });
+    if let Some(assignment) = &assignment {
+        if let Some(model) = &assignment.model {
+            normalized.model = model.clone();
+        }
+        access.set_experiment(&assignment.experiment, &assignment.variant);
+        state
+            .metrics
+            .observe_experiment_request(&assignment.experiment, &assignment.variant);
+    }
+    normalized.request_id = request_id.as_str().to_owned();
+    normalized.priority = priority;
+    if let Some(deadline) = header_deadline(&headers)? {
+        normalized.limit_deadline(deadline);
+    }
+    if let Some(timeout) = state.timeouts.request_timeout {
+        normalized.limit_deadline(received + timeout);
+    }
+    let truncation = match state.context_limits.window_for(&normalized.model) {
+        Some(window) => {
+            let strategy = auth_context
+                .key_policy
+                .truncation
+                .unwrap_or(state.truncation.strategy);
+            if dry_run && strategy == TruncationStrategy::Summarize {
+                // Plans the summary without the backend call that would write it.
+                state
+                    .truncation
+                    .fit(
+                        TruncationStrategy::Oldest,
+                        &state.backend,
+                        &mut normalized,
+                        window,
+                    )
+                    .await
+                    .map(|_| match state.truncation.summary_model {
+                        Some(_) => strategy,
+                        None => TruncationStrategy::Oldest,
+                    })
+            } else {
+                state
+                    .truncation
+                    .fit(strategy, &state.backend, &mut normalized, window)
+                    .await
+            }
+        }
+        None => None,
+    };
+    state.context_limits.enforce(&mut normalized)?;
+    access.set_request(
+        &normalized.request_id,
+        &auth_context.user_id,
+        &normalized.model,
+        normalized.stream,
     );
+    access.set_client(&normalized.client);
+    trace.set_input(&normalized);
+    let estimated_tokens = estimate_request_tokens(&normalized);
+    let mut policy = RequestPolicy {
+        cache: state
+            .response_cache
+            .directive_for_model(&normalized.model, header_directive),
+        batching: batching_enabled(&headers, &auth_context),
+        coalesce: coalescing_enabled(
+            &headers,
+            &auth_context,
+            &normalized,
+            state.coalescer.config().coalesce_sampled,
+        ),
+        post_process: state
+            .post_processors
+            .chain(&auth_context.key_policy.post_process),
+        flow: auth_context.tenant.id.clone(),
+        weight: state
+            .fair_queue
+            .config()
+            .weight_for(auth_context.key_policy.tier.as_deref()),
+        include_usage,
+    };
+    if pinned.is_some() {
+        // A pinned request is meant to reach its backend, not share another request's call
+        // or answer, nor leave one behind for unpinned requests. The catalog's backend is not
+        // a pin: every request for the model goes there, so sharing stays safe.
+        policy.cache.read = false;
+        policy.cache.write = false;
+        policy.batching = false;
+        policy.coalesce = false;
+    }
+    let key_quota = state
+        .quotas
+        .key_policy(&auth_context.user_id, &auth_context.policy);
+    let tenant_quota = state
+        .quotas
+        .tenant_quota(&auth_context.tenant.id, auth_context.tenant.quota());
+    let cache_partition = auth_context
+        .tenant
+        .policy
+        .cache_scope
+        .unwrap_or(state.response_cache.config().scope)
+        .partition(&auth_context);
+    let fingerprint = scheduler::hashed_fingerprint_for(
+        state.response_cache.config().fingerprint_hash,
+        &normalized,
+        cache_partition.as_deref(),
+    );
+    if dry_run {
+        let plan = DryRunPlan {
+            requested_model: &requested_model,
+            request: &normalized,
+            auth_context: &auth_context,
+            policy: &policy,
+            key_quota: &key_quota,
+            fingerprint: fingerprint.as_str(),
+            estimated_tokens,
+            truncation,
+            assignment: assignment.as_ref(),
+        };
+        return Ok(plan.into_response(&state).await);
+    }
+    let queued = Instant::now();
+    let deadline = normalized.deadline;
+    let stream = normalized.stream;
+    let progress_state = state.clone();
+    let progress_access = access.clone();
+    let progress_request_id = request_id.as_str().to_owned();
+    let admitted = move |admission: AdmissionTicket| async move {
+        access.set_queue_wait(queued.elapsed());
+        let rate_snapshot = state
+            .rate_limiter
+            .check_and_consume(&auth_context.api_key, &key_quota, estimated_tokens)
+            .await
+            .map_err(|error| AppError::RateLimited {
+                message: error.message().to_owned(),
+                headers: error.snapshot().to_header_pairs(),
+            })?;
+        if let Some(quota) = &tenant_quota {
+            state
+                .rate_limiter
+                .check_and_consume(&auth_context.tenant.quota_key(), quota, estimated_tokens)
+                .await
+                .map_err(|error| AppError::RateLimited {
+                    message: format!("tenant {}", error.message()),
+                    headers: error.snapshot().to_header_pairs(),
+                })?;
+        }
+        let credits_balance = state.credits.balance(&auth_context.tenant.id).await;
+        if let Some(balance_usd) = credits_balance.filter(|balance| *balance <= 0.0) {
+            state
+                .metrics
+                .observe_insufficient_credits(&auth_context.tenant.id);
+            return Err(AppError::InsufficientCredits {
+                message: format!(
+                    "tenant `{}` has no prepaid credits left",
+                    auth_context.tenant.id
+                ),
+                balance_usd,
+            });
+        }
+        // Every request draws on its own tenant's balance, so a model with no price would be
+        // free to a tenant that has run out.
+        if state.credits.is_enabled() && !state.pricing.is_priced(&normalized.model) {
+            warn!(model = %normalized.model, "refusing unpriced model under prepaid credits");
+            return Err(AppError::Forbidden(format!(
+                "model `{}` has no price, so it cannot be billed against prepaid credits",
+                normalized.model
+            )));
+        }
+        state
+            .metrics
+            .observe_tenant_request(&auth_context.tenant.id);
 
-    if normalized.stream {
-        stream_completion(
-            state,
-            normalized,
-            auth_context.api_key,
-            fingerprint.as_str().to_owned(),
+        info!(
+            request_id = %normalized.request_id,
+            user_id = %normalized.user_id,
+            tenant_id = %auth_context.tenant.id,
+            model = %normalized.model,
+            stream = normalized.stream,
+            priority = normalized.priority.as_str(),
             estimated_tokens,
-            rate_snapshot,
-        )
-        .await
-    } else {
-        one_shot_completion(
-            state,
-            normalized,
-            auth_context.api_key,
-            fingerprint.as_str().to_owned(),
+            client_user = normalized.client.user.as_deref().unwrap_or_default(),
+            fingerprint = %fingerprint.as_str(),
+            "chat request accepted"
+        );
+
+        let accounting = RequestAccounting {
+            request_id,
+            api_key: auth_context.api_key,
+            tier: auth_context.key_policy.tier,
+            tenant_quota_key: tenant_quota.map(|_| auth_context.tenant.quota_key()),
+            tenant_id: auth_context.tenant.id,
             estimated_tokens,
             rate_snapshot,
-        )
-        .await
+            credits_balance,
+            access,
+            trace,
+        };
+        // Shared from here on so the coalescer, batcher, and router pass it along without copying
+        // the prompt.
+        let normalized = Arc::new(normalized);
+        let mut response = if normalized.stream {
+            stream_completion(
+                state,
+                normalized,
+                fingerprint.as_str().to_owned(),
+                policy,
+                accounting,
+            )
+            .await?
+        } else {
+            one_shot_completion(
+                state,
+                normalized,
+                fingerprint.as_str().to_owned(),
+                policy,
+                accounting,
+            )
+            .await?
+        };
+        if let Some(assignment) = assignment {
+            crate::errors::apply_header(
+                response.headers_mut(),
+                "x-gateway-experiment",
+                &assignment.experiment,
+            );
+            crate::errors::apply_header(
+                response.headers_mut(),
+                "x-gateway-variant",
+                &assignment.variant,
+            );
+        }
+        if let Some(strategy) = truncation {
+            crate::errors::apply_header(
+                response.headers_mut(),
+                "x-gateway-truncation",
+                strategy.as_str(),
+            );
+        }
+        if let Some(position) = admission.queue_position {
+            apply_queue_headers(
+                response.headers_mut(),
+                QueueProgress {
+                    position,
+                    eta: admission.queue_eta,
+                },
+            );
+        }
+        // The admission slot stays taken until the body, including an SSE stream, is finished.
+        Ok::<_, AppError>(hold_until_body_ends(response, admission))
+    };
+    let admission = progress_state.admission.clone();
+    match admission.enqueue()? {
+        Admission::Admitted(ticket) => admitted(ticket).await,
+        Admission::Queued(waiting) => match admission.config().progress_interval {
+            Some(interval) if stream => Ok(queued_stream_response(
+                progress_state,
+                progress_access,
+                progress_request_id,
+                waiting,
+                deadline,
+                interval,
+                admitted,
+            )),
+            _ => admitted(waiting.wait(deadline).await?).await,
+        },
     }
 }
 
-#[tracing::instrument(skip(state, request), fields(model = %request.model))]
-async fn one_shot_completion(
+/// Answers a stream request that has to queue for admission straight away, so the client sees
+/// progress instead of a silent connection: an SSE comment with the request's place in line
+/// and estimated wait goes out every `interval` until it is admitted, then `admitted` runs and
+/// its body follows. Errors past this point can no longer change the status, so they arrive as
+/// an SSE error event.
+fn queued_stream_response<F, Fut>(
+    state: AppState,
+    access: AccessRecord,
+    request_id: String,
+    waiting: QueuedRequest,
+    deadline: Option<Instant>,
+    interval: Duration,
+    admitted: F,
+) -> Response
+where
+    F: FnOnce(AdmissionTicket) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response, AppError>> + Send + 'static,
+{
+    let arrived = waiting.arrived();
+    let span = tracing::Span::current();
+    let body = async_stream::stream! {
+        let tracker = waiting.tracker();
+        let admitting = waiting.wait(deadline);
+        tokio::pin!(admitting);
+        let mut ticks = tokio::time::interval(interval);
+        let admission = loop {
+            let ready = tokio::select! {
+                admission = &mut admitting => Some(admission),
+                _ = ticks.tick() => None,
+            };
+            match ready {
+                Some(admission) => break admission,
+                None => {
+                    let comment = queue_comment(tracker.progress());
+                    yield Ok::<Bytes, axum::Error>(Bytes::from(comment));
+                }
+            }
+        };
+        let result = match admission {
+            Ok(ticket) => admitted(ticket).instrument(span).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(response) => {
+                let mut body = response.into_body().into_data_stream();
+                while let Some(chunk) = body.next().await {
+                    yield chunk;
+                }
+            }
+            Err(error) => {
+                access.set_status(error.status().as_u16());
+                if let Some(kind) = error.report_kind() {
+                    state
+                        .error_reporter
+                        .capture(access.error_report(kind, error.to_string()));
+                }
+                let data = error.stream_event_data(Some(&request_id));
+                yield Ok(Bytes::from(format!("data: {data}\n\ndata: [DONE]\n\n")));
+            }
+        }
+    };
+    let mut response = Response::new(Body::from_stream(body));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    apply_queue_headers(headers, arrived);
+    response
+}
+
+/// `x-gateway-queue-position`, plus `x-gateway-eta-ms` once a wait can be estimated.
+fn apply_queue_headers(headers: &mut HeaderMap, progress: QueueProgress) {
+    crate::errors::apply_header(
+        headers,
+        "x-gateway-queue-position",
+        &progress.position.to_string(),
+    );
+    if let Some(eta) = progress.eta {
+        crate::errors::apply_header(headers, "x-gateway-eta-ms", &eta.as_millis().to_string());
+    }
+}
+
+/// The SSE comment a queued stream is sent while it waits, e.g. `: queued position=3 eta_ms=1200`.
+fn queue_comment(progress: QueueProgress) -> String {
+    match progress.eta {
+        Some(eta) => format!(
+            ": queued position={} eta_ms={}\n\n",
+            progress.position,
+            eta.as_millis()
+        ),
+        None => format!(": queued position={}\n\n", progress.position),
+    }
+}
+
+/// What a request would do, answered instead of running it when it carries
+/// `x-gateway-dry-run`: auth, validation, and quotas have been checked, but nothing has been
+/// called, queued, or charged.
+struct DryRunPlan<'a> {
+    requested_model: &'a str,
+    request: &'a NormalizedChatRequest,
+    auth_context: &'a AuthContext,
+    policy: &'a RequestPolicy,
+    key_quota: &'a RatePolicy,
+    fingerprint: &'a str,
+    estimated_tokens: u64,
+    truncation: Option<TruncationStrategy>,
+    assignment: Option<&'a Assignment>,
+}
+
+impl DryRunPlan<'_> {
+    async fn into_response(self, state: &AppState) -> Response {
+        let prompt_tokens = estimate_prompt_tokens(self.request);
+        let completion_tokens = self.estimated_tokens.saturating_sub(prompt_tokens);
+        let estimated_cost_usd = state.pricing.cost_usd(
+            &self.request.model,
+            &Usage::new(
+                u32::try_from(prompt_tokens).unwrap_or(u32::MAX),
+                u32::try_from(completion_tokens).unwrap_or(u32::MAX),
+            ),
+        );
+        let body = serde_json::json!({
+            "object": "chat.completion.plan",
+            "request_id": self.request.request_id,
+            "model": self.request.model,
+            "requested_model": self.requested_model,
+            "tenant": self.auth_context.tenant.id,
+            "priority": self.request.priority.as_str(),
+            "stream": self.request.stream,
+            "estimated_tokens": {
+                "prompt": prompt_tokens,
+                "completion": completion_tokens,
+                "total": self.estimated_tokens,
+            },
+            "estimated_cost_usd": estimated_cost_usd,
+            "truncation": self.truncation.map(TruncationStrategy::as_str),
+            "experiment": self.assignment.map(|assignment| serde_json::json!({
+                "name": assignment.experiment,
+                "variant": assignment.variant,
+            })),
+            "cache": {
+                "read": self.policy.cache.read,
+                "write": self.policy.cache.write,
+                "fingerprint": self.fingerprint,
+            },
+            "batching": self.policy.batching,
+            "coalesce": self.policy.coalesce,
+            "include_usage": self.policy.include_usage,
+            "backends": state
+                .backend
+                .route_plan()
+                .await
+                .into_iter()
+                .filter(|backend| match &self.request.pinned_backend {
+                    Some(pinned) => pinned == backend,
+                    None => true,
+                })
+                .collect::<Vec<_>>(),
+            "rate_limit": {
+                "requests_per_minute": self.key_quota.requests_per_minute,
+                "tokens_per_minute": self.key_quota.tokens_per_minute,
+                "tokens_per_day": self.key_quota.tokens_per_day,
+            },
+            "credits_balance_usd": state.credits.balance(&self.auth_context.tenant.id).await,
+        });
+        let mut response = Json(body).into_response();
+        crate::errors::apply_header(response.headers_mut(), DRY_RUN_HEADER, "true");
+        response
+    }
+}
+
+/// Keeps `guard` alive until the response body has been fully sent or dropped.
+fn hold_until_body_ends<G>(response: Response, guard: G) -> Response
+where
+    G: Send + Unpin + 'static,
+{
+    response.map(|body| {
+        Body::new(GuardedBody {
+            body,
+            _guard: guard,
+        })
+    })
+}
+
+/// A body that owns a guard and otherwise passes through to `body`, including its length, so
+/// a one-shot response keeps its `Content-Length`.
+struct GuardedBody<G> {
+    body: Body,
+    _guard: G,
+}
+
+impl<G: Unpin> HttpBody for GuardedBody<G> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Writes the request's access-log line and usage record once the response body is done.
+struct RequestLogOnDrop {
+    access: AccessRecord,
+    usage_sink: Arc<UsageSink>,
+    metrics: Arc<AppMetrics>,
+    trace: TraceCapture,
+}
+
+impl Drop for RequestLogOnDrop {
+    fn drop(&mut self) {
+        self.access.finish();
+        if let Some(record) = self.access.usage_record() {
+            if let (Some(experiment), Some(variant)) = (&record.experiment, &record.variant) {
+                self.metrics.observe_experiment_completion(
+                    experiment,
+                    variant,
+                    Duration::from_millis(record.latency_ms),
+                    &Usage::new(record.prompt_tokens, record.completion_tokens),
+                );
+            }
+            self.trace.finish(&record);
+            self.usage_sink.record(record);
+        }
+    }
+}
+
+/// Counts an SSE client that went away before its stream reached the final chunk.
+struct StreamDisconnectGuard {
+    metrics: Arc<AppMetrics>,
+    backend: Option<String>,
+    finished: bool,
+}
+
+impl Drop for StreamDisconnectGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.metrics
+                .observe_stream_disconnect(self.backend.as_deref().unwrap_or("unknown"));
+        }
+    }
+}
+
+/// Settles one stream subscriber's tokens against its own key, however its stream ends. The
+/// final chunk's usage is used when it has one; a stream that never reported usage, or a
+/// subscriber that disconnected, fell behind, or hit an error first, is settled from the
+/// prompt estimate and the words it was actually sent. The body drops this before its
+/// `RequestLogOnDrop`, so the usage set here reaches the access log and usage record.
+struct StreamSettlement {
     state: AppState,
-    request: NormalizedChatRequest,
+    model: String,
+    accounting: RequestAccounting,
+    prompt_tokens: u32,
+    completion: WordCount,
+    settled: bool,
+}
+
+impl StreamSettlement {
+    /// Settles with the usage the stream reported, or else the estimate of what was sent.
+    async fn settle(&mut self, reported: Option<Usage>) -> Usage {
+        self.settled = true;
+        let usage = reported.unwrap_or_else(|| self.estimate());
+        self.accounting
+            .settle_usage(&self.state, &self.model, &usage)
+            .await;
+        self.accounting.access.set_usage(&usage);
+        usage
+    }
+
+    fn estimate(&self) -> Usage {
+        Usage::new(self.prompt_tokens, self.completion.words)
+    }
+}
+
+impl Drop for StreamSettlement {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        let usage = self.estimate();
+        self.accounting.access.set_usage(&usage);
+        let state = self.state.clone();
+        let model = std::mem::take(&mut self.model);
+        let accounting = self.accounting.clone();
+        tokio::spawn(async move {
+            accounting.settle_usage(&state, &model, &usage).await;
+        });
+    }
+}
+
+/// Words across a stream's deltas, counted as `split_whitespace` counts the joined text, which
+/// is how the gateway estimates tokens.
+#[derive(Default)]
+struct WordCount {
+    words: u32,
+    in_word: bool,
+}
+
+impl WordCount {
+    fn push(&mut self, text: &str) {
+        for ch in text.chars() {
+            let space = ch.is_whitespace();
+            if !space && !self.in_word {
+                self.words = self.words.saturating_add(1);
+            }
+            self.in_word = !space;
+        }
+    }
+}
+
+/// Completion tokens attributable to the text generated after `abandoned_at` characters, pro
+/// rata by character count since backends only report usage for the whole completion.
+fn abandoned_tokens(completion_tokens: u32, generated_chars: usize, abandoned_at: usize) -> u64 {
+    if generated_chars == 0 {
+        return 0;
+    }
+    let unobserved = generated_chars.saturating_sub(abandoned_at) as u64;
+    u64::from(completion_tokens) * unobserved / generated_chars as u64
+}
+
+/// Attributes upstream spend to the request that actually triggered the backend call. Credits
+/// are charged separately, per request, in [`RequestAccounting::settle_usage`].
+fn record_spend(
+    state: &AppState,
+    model: &str,
+    backend: Option<&str>,
+    usage: &Usage,
+) -> Option<f64> {
+    let cost = state.pricing.cost_usd(model, usage)?;
+    state
+        .metrics
+        .observe_cost(model, backend.unwrap_or("unknown"), cost);
+    Some(cost)
+}
+
+/// Applies the key's post-processors to a one-shot response; a processor that ends the text
+/// early finishes it with `stop`.
+fn post_process(chain: &ProcessorChain, mut response: BackendChatResponse) -> BackendChatResponse {
+    if chain.is_empty() {
+        return response;
+    }
+    let (content, stopped) = chain.apply(&response.content);
+    response.content = content;
+    if stopped {
+        response.finish_reason = "stop".to_owned();
+        response.provider_finish_reason = None;
+    }
+    response
+}
+
+/// Per-request token accounting and access-log fields carried through to the response body.
+#[derive(Clone)]
+struct RequestAccounting {
+    request_id: RequestId,
     api_key: String,
-    fingerprint: String,
+    tier: Option<String>,
+    tenant_id: String,
+    /// Limiter bucket of the tenant's shared quota, when it has one.
+    tenant_quota_key: Option<String>,
     estimated_tokens: u64,
     rate_snapshot: RateLimitSnapshot,
+    /// Prepaid balance when the request was admitted, if credits are on.
+    credits_balance: Option<f64>,
+    access: AccessRecord,
+    trace: TraceCapture,
+}
+
+impl RequestAccounting {
+    /// Replaces the token estimate with actual usage in the key's (and tenant's) quota, records
+    /// the usage metrics, and charges the cost of the usage to the tenant's prepaid credits.
+    /// Returns the balance after the charge, when credits are on and the model is priced.
+    async fn settle_usage(&self, state: &AppState, model: &str, usage: &Usage) -> Option<f64> {
+        let actual = usage.total_tokens as u64;
+        for quota_key in std::iter::once(&self.api_key).chain(&self.tenant_quota_key) {
+            state
+                .rate_limiter
+                .reconcile_tokens(quota_key, self.estimated_tokens, actual)
+                .await;
+        }
+        state
+            .metrics
+            .observe_usage(model, self.tier.as_deref(), usage);
+        state.metrics.observe_tenant_usage(&self.tenant_id, usage);
+        let cost = state.pricing.cost_usd(model, usage)?;
+        state.credits.charge(&self.tenant_id, cost).await
+    }
+}
+
+/// Per-request execution choices resolved from headers, key policy, and model rules.
+#[derive(Debug, Clone)]
+struct RequestPolicy {
+    cache: CacheDirective,
+    /// `false` sends one-shot requests straight to the backend, skipping the batch wait window.
+    batching: bool,
+    /// `false` gives the request a private coalescing slot so it never shares a backend call.
+    coalesce: bool,
+    /// The key's response post-processors, applied after caching and coalescing.
+    post_process: ProcessorChain,
+    /// Fair-queuing flow (the tenant) and its dequeue weight.
+    flow: String,
+    weight: u32,
+    /// Ends streams with a usage-only chunk, as `stream_options.include_usage` asks.
+    include_usage: bool,
+}
+
+impl RequestPolicy {
+    /// Puts `backend` behind the request model's concurrency pool, then the fair queue for
+    /// this request's flow; the pool is taken first so a request waiting on a busy model
+    /// never holds a shared dispatch slot.
+    fn dispatch(
+        &self,
+        state: &AppState,
+        backend: Arc<dyn InferenceBackend>,
+    ) -> Arc<dyn InferenceBackend> {
+        state
+            .model_pools
+            .gate(state.fair_queue.gate(backend, &self.flow, self.weight))
+    }
+
+    /// Key under which the request joins inflight work; uncoalesced requests get a key no other
+    /// request can produce, so the coalescer machinery still applies without sharing.
+    fn coalescing_key(&self, fingerprint: &str, request: &NormalizedChatRequest) -> String {
+        if self.coalesce {
+            fingerprint.to_owned()
+        } else {
+            format!("{fingerprint}|{}", request.request_id)
+        }
+    }
+}
+
+/// The backend a request is pinned to with `x-gateway-backend`, checked against the key's
+/// policy and then against the backends the gateway has; an unknown name is a `400`.
+async fn pinned_backend(
+    state: &AppState,
+    headers: &HeaderMap,
+    auth: &AuthContext,
+) -> Result<Option<String>, AppError> {
+    let Some(backend) = auth.pinned_backend(headers)? else {
+        return Ok(None);
+    };
+    let endpoints = state.backend.endpoint_status().await;
+    if !endpoints.iter().any(|endpoint| endpoint.name == backend) {
+        return Err(InvalidParameter::new(
+            "x-gateway-backend",
+            "unknown_backend",
+            format!("no backend is named `{backend}`"),
+        )
+        .into());
+    }
+    Ok(Some(backend))
+}
+
+/// `x-gateway-batch: off` or a key policy of `"batching": false` opts out of micro-batching.
+fn batching_enabled(headers: &HeaderMap, auth: &AuthContext) -> bool {
+    auth.key_policy.batching != Some(false) && !header_is_off(headers, "x-gateway-batch")
+}
+
+/// `x-gateway-coalesce: off` or a key policy of `"coalesce": false` opts out of coalescing.
+/// Sampled requests (`temperature > 0`) are not coalesced unless `GATEWAY_COALESCE_SAMPLED`
+/// allows it, since identical creative prompts usually expect different completions.
+fn coalescing_enabled(
+    headers: &HeaderMap,
+    auth: &AuthContext,
+    request: &NormalizedChatRequest,
+    coalesce_sampled: bool,
+) -> bool {
+    if auth.key_policy.coalesce == Some(false) || header_is_off(headers, "x-gateway-coalesce") {
+        return false;
+    }
+    let sampled = request
+        .generation
+        .temperature
+        .is_some_and(|temperature| temperature > 0.0);
+    !sampled || auth.key_policy.coalesce == Some(true) || coalesce_sampled
+}
+
+/// `x-gateway-timeout-ms` bounds how long the gateway works on the request; it combines with
+/// the body `timeout` field, and the earlier deadline wins.
+fn header_deadline(headers: &HeaderMap) -> Result<Option<Instant>, AppError> {
+    let Some(value) = headers.get("x-gateway-timeout-ms") else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|millis| *millis > 0)
+        .map(|millis| Some(Instant::now() + Duration::from_millis(millis)))
+        .ok_or_else(|| {
+            AppError::from(InvalidParameter::new(
+                "x-gateway-timeout-ms",
+                "invalid_value",
+                "x-gateway-timeout-ms must be a positive integer",
+            ))
+        })
+}
+
+fn header_is_on(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .is_some_and(|value| {
+            value.eq_ignore_ascii_case("on") || value.eq_ignore_ascii_case("true") || value == "1"
+        })
+}
+
+fn header_is_off(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .is_some_and(|value| {
+            value.eq_ignore_ascii_case("off") || value.eq_ignore_ascii_case("false") || value == "0"
+        })
+}
+
+#[tracing::instrument(skip(state, request, accounting), fields(model = %request.model))]
+async fn one_shot_completion(
+    state: AppState,
+    request: Arc<NormalizedChatRequest>,
+    fingerprint: String,
+    policy: RequestPolicy,
+    accounting: RequestAccounting,
 ) -> Result<Response, AppError> {
+    let cache_directive = policy.cache;
     let created = unix_timestamp();
     let response_id = format!("chatcmpl-{}", Uuid::new_v4());
     let cache_key = fingerprint.clone();
 
-    if let Some(cached) = state.response_cache.get(&cache_key).await {
-        state
-            .rate_limiter
-            .reconcile_tokens(&api_key, estimated_tokens, cached.usage.total_tokens as u64)
-            .await;
-        state.metrics.observe_usage(&cached.usage);
+    let cached = if cache_directive.read {
+        state.response_cache.get(&cache_key).await
+    } else {
+        None
+    };
+    if let Some(hit) = cached {
+        if hit.stale && cache_directive.write {
+            spawn_one_shot_refresh(
+                &state,
+                cache_key.clone(),
+                request.clone(),
+                cache_directive.ttl,
+            );
+        }
+        let cache_status = if hit.stale { "stale" } else { "hit" };
+        let cached = hit.value;
+        let credits_balance = accounting
+            .settle_usage(&state, &request.model, &cached.usage)
+            .await
+            .or(accounting.credits_balance);
+        accounting.access.set_cache(cache_status);
+        accounting.access.set_usage(&cached.usage);
+        if let Some(backend) = &cached.backend {
+            accounting.access.set_backend(backend);
+        }
 
-        let payload =
-            ChatCompletionsResponse::from_backend(response_id, created, request.model, cached);
+        let cached = post_process(&policy.post_process, cached);
+        accounting.trace.push_output(&cached.content);
+        accounting.trace.set_finish_reason(&cached.finish_reason);
+        let payload = ChatCompletionsResponse::from_backend(
+            response_id,
+            created,
+            request.model.clone(),
+            cached,
+        );
         let mut response = Json(payload).into_response();
-        apply_rate_limit_headers(response.headers_mut(), &rate_snapshot);
-        crate::errors::apply_header(response.headers_mut(), "x-cache", "hit");
+        apply_rate_limit_headers(response.headers_mut(), &accounting.rate_snapshot);
+        apply_credits_header(response.headers_mut(), credits_balance);
+        crate::errors::apply_header(response.headers_mut(), "x-cache", cache_status);
         return Ok(response);
     }
 
-    let execution_backend: Arc<dyn InferenceBackend> = state.batcher.clone();
+    let execution_backend = policy.dispatch(
+        &state,
+        if policy.batching {
+            state.batcher.clone()
+        } else {
+            state.backend.clone()
+        },
+    );
 
-    let (backend_response, coalesced) = state
-        .coalescer
-        .execute_or_join(fingerprint, execution_backend, request.clone())
+    // Followers wait on a shared call, so each request also enforces its own deadline here.
+    let backend_started = Instant::now();
+    let (backend_response, coalesced) = with_deadline(
+        request.deadline,
+        "coalesced execution",
+        state.coalescer.execute_or_join(
+            policy.coalescing_key(&fingerprint, &request),
+            execution_backend,
+            request.clone(),
+        ),
+    )
+    .await
+    .map_err(|error| {
+        state.metrics.observe_backend_error("one_shot");
+        AppError::from(error)
+    })?;
+    let credits_balance = accounting
+        .settle_usage(&state, &request.model, &backend_response.usage)
         .await
-        .map_err(|error| {
-            state.metrics.observe_backend_error("one_shot");
-            AppError::Backend(error.to_string())
-        })?;
-    state
-        .rate_limiter
-        .reconcile_tokens(
-            &api_key,
-            estimated_tokens,
-            backend_response.usage.total_tokens as u64,
-        )
-        .await;
-    state.metrics.observe_usage(&backend_response.usage);
-    state
-        .response_cache
-        .set(&cache_key, &backend_response)
-        .await;
+        .or(accounting.credits_balance);
+    accounting
+        .access
+        .set_backend_latency(backend_started.elapsed());
+    accounting.access.set_cache(cache_directive.miss_header());
+    accounting.access.set_coalesced(match coalesced {
+        CoalesceOutcome::Leader => "leader",
+        CoalesceOutcome::Joined => "joined",
+    });
+    accounting.access.set_usage(&backend_response.usage);
+    if let Some(backend) = &backend_response.backend {
+        accounting.access.set_backend(backend);
+    }
+    if coalesced == CoalesceOutcome::Leader {
+        if let Some(cost) = record_spend(
+            &state,
+            &request.model,
+            backend_response.backend.as_deref(),
+            &backend_response.usage,
+        ) {
+            accounting.access.set_cost(cost);
+        }
+    }
+    if cache_directive.write {
+        state
+            .response_cache
+            .set(&cache_key, &backend_response, cache_directive.ttl)
+            .await;
+    }
 
+    let mut backend_response = post_process(&policy.post_process, backend_response);
+    let upstream_headers = backend_response.upstream_headers.take();
+    accounting.trace.push_output(&backend_response.content);
+    accounting
+        .trace
+        .set_finish_reason(&backend_response.finish_reason);
     let payload = ChatCompletionsResponse::from_backend(
         response_id,
         created,
-        request.model,
+        request.model.clone(),
         backend_response,
     );
     let mut response = Json(payload).into_response();
-    apply_rate_limit_headers(response.headers_mut(), &rate_snapshot);
-    crate::errors::apply_header(response.headers_mut(), "x-cache", "miss");
+    apply_rate_limit_headers(response.headers_mut(), &accounting.rate_snapshot);
+    apply_credits_header(response.headers_mut(), credits_balance);
+    crate::errors::apply_header(
+        response.headers_mut(),
+        "x-cache",
+        cache_directive.miss_header(),
+    );
+    state
+        .forwarded_headers
+        .apply(upstream_headers.as_ref(), response.headers_mut());
 
     if coalesced == CoalesceOutcome::Joined {
         info!("one-shot response served from inflight coalescing");
@@ -200,55 +1189,176 @@ async fn one_shot_completion(
     Ok(response)
 }
 
-#[tracing::instrument(skip(state, request), fields(model = %request.model))]
+#[tracing::instrument(skip(state, request, accounting), fields(model = %request.model))]
 async fn stream_completion(
     state: AppState,
-    request: NormalizedChatRequest,
-    api_key: String,
+    mut request: Arc<NormalizedChatRequest>,
     fingerprint: String,
-    estimated_tokens: u64,
-    rate_snapshot: RateLimitSnapshot,
+    policy: RequestPolicy,
+    accounting: RequestAccounting,
 ) -> Result<Response, AppError> {
+    let cache_directive = policy.cache;
     let created = unix_timestamp();
     let response_id = format!("chatcmpl-{}", Uuid::new_v4());
     let model = request.model.clone();
+    let prompt_tokens = u32::try_from(estimate_prompt_tokens(&request)).unwrap_or(u32::MAX);
+
+    let cached = if cache_directive.read {
+        state.response_cache.get_stream(&fingerprint).await
+    } else {
+        None
+    };
+    if let Some(hit) = cached {
+        if hit.stale && cache_directive.write {
+            spawn_stream_refresh(
+                &state,
+                fingerprint.clone(),
+                request.clone(),
+                cache_directive.ttl,
+            );
+        }
+        let cache_status = if hit.stale { "stale" } else { "hit" };
+        accounting.access.set_cache(cache_status);
+        let receiver =
+            replay_transcript(hit.value, state.response_cache.config().paced_stream_replay);
+        let rate_snapshot = accounting.rate_snapshot.clone();
+        let credits_balance = accounting.credits_balance;
+        let streaming = state.streaming.clone();
+        let outbound = sse_events(
+            state,
+            receiver,
+            response_id,
+            created,
+            model,
+            accounting,
+            prompt_tokens,
+            policy.post_process,
+            false,
+            policy.include_usage,
+        );
+        let mut response = sse_response(outbound, &streaming);
+        apply_rate_limit_headers(response.headers_mut(), &rate_snapshot);
+        apply_credits_header(response.headers_mut(), credits_balance);
+        crate::errors::apply_header(response.headers_mut(), "x-cache", cache_status);
+        return Ok(response);
+    }
+
+    // An uncoalesced stream has exactly one subscriber, so its upstream events can be
+    // forwarded without rewriting them under the gateway's response id, unless its text is
+    // post-processed.
+    let passthrough =
+        state.streaming.passthrough && !policy.coalesce && policy.post_process.is_empty();
+    if passthrough {
+        Arc::make_mut(&mut request).passthrough = true;
+    }
+    let coalescing_key = policy.coalescing_key(&fingerprint, &request);
     let stream_join = state
         .coalescer
-        .join_or_create_stream(fingerprint.clone())
+        .join_or_create_stream(coalescing_key.clone())
         .await;
+    accounting.access.set_cache(cache_directive.miss_header());
+    accounting.access.set_coalesced(if stream_join.is_leader {
+        "leader"
+    } else {
+        "follower"
+    });
+    let mut upstream_headers = None;
     if stream_join.is_leader {
-        let backend = state.backend.clone();
-        let coalescer = state.coalescer.clone();
-        let request_for_leader = request;
+        // Taken before anything is awaited, so followers get an error rather than a wait for
+        // the janitor if the leader's client goes away while its stream is still starting.
+        let lease = state.coalescer.stream_lease(&coalescing_key);
+        // Streams start through the batcher's admission slots; starting inline lets an
+        // overloaded or failed start surface as a proper HTTP error instead of an SSE event.
+        let started = Instant::now();
+        let dispatch = policy.dispatch(&state, state.batcher.clone());
+        let backend_stream = match dispatch.stream_chat(request).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                state.metrics.observe_backend_error("stream_leader_start");
+                lease.publish(Err(error.clone())).await;
+                return Err(AppError::from(error));
+            }
+        };
+        accounting.access.set_backend_latency(started.elapsed());
+        let mut backend_stream =
+            stream_with_idle_timeout(backend_stream, state.timeouts.stream_idle_timeout);
+        // Upstream headers ride on the first chunk, so forwarding them holds the response
+        // headers until it arrives.
+        if !state.forwarded_headers.is_empty() {
+            if let Some(mut first) = backend_stream.next().await {
+                if let Ok(chunk) = &mut first {
+                    upstream_headers = chunk.upstream_headers.take();
+                }
+                backend_stream = futures_util::stream::iter([first])
+                    .chain(backend_stream)
+                    .boxed();
+            }
+        }
+        let backend_stream = state.streaming.merge_deltas(backend_stream);
+        let response_cache = state.response_cache.clone();
         let key = fingerprint.clone();
         let metrics = state.metrics.clone();
+        let leader_state = state.clone();
+        let leader_access = accounting.access.clone();
+        let leader_model = model.clone();
         tokio::spawn(async move {
-            let backend_stream = match backend.stream_chat(request_for_leader).await {
-                Ok(stream) => stream,
-                Err(error) => {
-                    metrics.observe_backend_error("stream_leader_start");
-                    coalescer
-                        .publish_stream_item(&key, Err(error.to_string()))
-                        .await;
-                    return;
-                }
-            };
-
+            let mut transcript = StreamTranscript::default();
+            // Characters generated so far, and how many had been generated when the last
+            // subscriber disconnected; the leader keeps reading so the cache still gets filled.
+            let mut generated_chars = 0usize;
+            let mut abandoned_at = None;
             tokio::pin!(backend_stream);
             while let Some(next) = backend_stream.next().await {
                 match next {
                     Ok(chunk) => {
                         let done = chunk.done;
-                        coalescer.publish_stream_item(&key, Ok(chunk)).await;
+                        let chars_before = generated_chars;
+                        if let Some(delta) = &chunk.delta {
+                            generated_chars += delta.chars().count();
+                            transcript
+                                .push_delta(delta.clone(), started.elapsed().as_millis() as u64);
+                        }
                         if done {
+                            transcript.finish(&chunk);
+                            if let Some(usage) = &chunk.usage {
+                                if let Some(cost) = record_spend(
+                                    &leader_state,
+                                    &leader_model,
+                                    chunk.backend.as_deref(),
+                                    usage,
+                                ) {
+                                    leader_access.set_cost(cost);
+                                }
+                            }
+                            if cache_directive.write {
+                                response_cache
+                                    .set_stream(&key, &transcript, cache_directive.ttl)
+                                    .await;
+                            }
+                        }
+                        let abandoned = done.then(|| (chunk.backend.clone(), chunk.usage.clone()));
+                        if lease.publish(Ok(chunk)).await == 0 && abandoned_at.is_none() {
+                            abandoned_at = Some(chars_before);
+                        }
+                        if let Some((backend, usage)) = abandoned {
+                            if let (Some(at), Some(usage), Some(backend)) =
+                                (abandoned_at, usage, backend)
+                            {
+                                metrics.observe_stream_abandoned_tokens(
+                                    &backend,
+                                    abandoned_tokens(usage.completion_tokens, generated_chars, at),
+                                );
+                            }
                             break;
                         }
                     }
                     Err(error) => {
                         metrics.observe_backend_error("stream_leader_read");
-                        coalescer
-                            .publish_stream_item(&key, Err(error.to_string()))
-                            .await;
+                        // Reported once by the leader rather than by every subscriber it fans to.
+                        leader_state
+                            .error_reporter
+                            .capture(leader_access.error_report("stream", error.to_string()));
+                        lease.publish(Err(error)).await;
                         break;
                     }
                 }
@@ -256,69 +1366,367 @@ async fn stream_completion(
         });
     }
 
-    let outbound = async_stream::stream! {
-        let mut stream_rx = stream_join.receiver;
+    let rate_snapshot = accounting.rate_snapshot.clone();
+    let credits_balance = accounting.credits_balance;
+    let streaming = state.streaming.clone();
+    let forwarded_headers = state.forwarded_headers.clone();
+    let outbound = sse_events(
+        state,
+        stream_join.receiver,
+        response_id,
+        created,
+        model,
+        accounting,
+        prompt_tokens,
+        policy.post_process,
+        passthrough,
+        policy.include_usage,
+    );
+    let mut response = sse_response(outbound, &streaming);
+    apply_rate_limit_headers(response.headers_mut(), &rate_snapshot);
+    // A stream's cost is only known at its end, so it reports the balance it started with.
+    apply_credits_header(response.headers_mut(), credits_balance);
+    crate::errors::apply_header(
+        response.headers_mut(),
+        "x-cache",
+        cache_directive.miss_header(),
+    );
+    forwarded_headers.apply(upstream_headers.as_ref(), response.headers_mut());
+    Ok(response)
+}
+
+/// Maps backend chunks to OpenAI SSE events. Deltas go through `post_process`; once it stops
+/// the response, later deltas are dropped while the stream is read to its end for usage. With
+/// `passthrough`, chunks carrying the upstream event are forwarded as-is rather than
+/// re-serialized under the gateway's response id; the upstream's usage-only event is forwarded
+/// only when the client asked for usage. With `include_usage`, every chunk carries
+/// `"usage": null` and the usage-only chunk OpenAI sends follows the finish. A stream outlasting
+/// the configured maximum duration ends with a timeout error event. Every subscriber, whether
+/// it led the backend call, followed it, or replays the cache, settles its tokens against its
+/// own key.
+#[allow(clippy::too_many_arguments)]
+fn sse_events(
+    state: AppState,
+    mut stream_rx: StreamReceiver,
+    response_id: String,
+    created: i64,
+    model: String,
+    accounting: RequestAccounting,
+    prompt_tokens: u32,
+    post_process: ProcessorChain,
+    passthrough: bool,
+    include_usage: bool,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let chunk_event = move |mut chunk: ChatCompletionsChunk| {
+        if include_usage && chunk.usage.is_none() {
+            chunk.usage = Some(None);
+        }
+        json_event(chunk)
+    };
+    async_stream::stream! {
         let mut emitted_role = false;
-        while let Some(next) = stream_rx.recv().await {
+        // Set once the upstream's own events are being forwarded; the gateway then adds none.
+        let mut forwarded = false;
+        let mut processing = post_process.begin();
+        let mut stopped = false;
+        let mut disconnect = StreamDisconnectGuard {
+            metrics: state.metrics.clone(),
+            backend: None,
+            finished: false,
+        };
+        let mut settlement = StreamSettlement {
+            state: state.clone(),
+            model: model.clone(),
+            accounting: accounting.clone(),
+            prompt_tokens,
+            completion: WordCount::default(),
+            settled: false,
+        };
+        let ends_at = state
+            .streaming
+            .max_duration
+            .map(|max| tokio::time::Instant::now() + max);
+        while let Some(next) = recv_until(&mut stream_rx, ends_at).await {
             match next {
-                Ok(chunk) => {
-                    if !emitted_role {
+                Ok(mut chunk) => {
+                    if disconnect.backend.is_none() {
+                        disconnect.backend = chunk.backend.clone();
+                    }
+                    let raw = chunk.raw.take().filter(|_| passthrough);
+                    forwarded |= raw.is_some();
+                    // The gateway always asks upstream for usage; clients that did not see none.
+                    let raw = raw.filter(|raw| include_usage || !is_usage_only_event(raw));
+                    if !emitted_role && !forwarded {
                         emitted_role = true;
                         let role_chunk = ChatCompletionsChunk::role(&response_id, created, &model);
-                        yield Ok::<Event, Infallible>(json_event(role_chunk));
+                        yield Ok::<Event, Infallible>(chunk_event(role_chunk));
                     }
 
                     if let Some(delta) = chunk.delta {
-                        let delta_chunk = ChatCompletionsChunk::delta(&response_id, created, &model, delta);
-                        yield Ok::<Event, Infallible>(json_event(delta_chunk));
+                        accounting.access.mark_first_token();
+                        settlement.completion.push(&delta);
+                        if forwarded {
+                            accounting.access.push_stream_text(&delta);
+                            accounting.trace.push_output(&delta);
+                        } else {
+                            let processed = processing.push(&delta);
+                            stopped |= processed.stop;
+                            if !processed.text.is_empty() {
+                                accounting.access.push_stream_text(&processed.text);
+                                accounting.trace.push_output(&processed.text);
+                                let delta_chunk = ChatCompletionsChunk::delta(&response_id, created, &model, processed.text);
+                                yield Ok::<Event, Infallible>(chunk_event(delta_chunk));
+                            }
+                        }
+                    }
+                    if let Some(raw) = raw {
+                        yield Ok::<Event, Infallible>(Event::default().data(raw));
                     }
 
                     if chunk.done {
-                        if let Some(usage) = chunk.usage {
-                            state
-                                .rate_limiter
-                                .reconcile_tokens(
-                                    &api_key,
-                                    estimated_tokens,
-                                    usage.total_tokens as u64,
-                                )
-                                .await;
-                            state.metrics.observe_usage(&usage);
-                            info!(
-                                prompt_tokens = usage.prompt_tokens,
-                                completion_tokens = usage.completion_tokens,
-                                total_tokens = usage.total_tokens,
-                                "stream usage summary"
+                        disconnect.finished = true;
+                        if let Some(backend) = &chunk.backend {
+                            accounting.access.set_backend(backend);
+                        }
+                        let final_usage = chunk.usage.clone();
+                        let usage = settlement.settle(chunk.usage).await;
+                        info!(
+                            prompt_tokens = usage.prompt_tokens,
+                            completion_tokens = usage.completion_tokens,
+                            total_tokens = usage.total_tokens,
+                            reported = final_usage.is_some(),
+                            "stream usage summary"
+                        );
+                        if forwarded {
+                            if let Some(finish_reason) = &chunk.finish_reason {
+                                accounting.access.finish_stream_transcript(finish_reason);
+                                accounting.trace.set_finish_reason(finish_reason);
+                            }
+                        } else {
+                            let held = processing.finish();
+                            if !held.is_empty() {
+                                accounting.access.push_stream_text(&held);
+                                accounting.trace.push_output(&held);
+                                let delta_chunk = ChatCompletionsChunk::delta(&response_id, created, &model, held);
+                                yield Ok::<Event, Infallible>(chunk_event(delta_chunk));
+                            }
+                            let finish_reason = chunk
+                                .finish_reason
+                                .filter(|_| !stopped)
+                                .unwrap_or_else(|| "stop".to_owned());
+                            let provider_finish_reason =
+                                chunk.provider_finish_reason.filter(|_| !stopped);
+                            accounting.access.finish_stream_transcript(&finish_reason);
+                            accounting.trace.set_finish_reason(&finish_reason);
+                            let done_chunk = ChatCompletionsChunk::finish(
+                                &response_id,
+                                created,
+                                &model,
+                                finish_reason,
+                                provider_finish_reason,
                             );
+                            yield Ok::<Event, Infallible>(chunk_event(done_chunk));
+                        }
+                        if let Some(usage) = final_usage.filter(|_| include_usage && !forwarded) {
+                            let usage_chunk = ChatCompletionsChunk::usage(&response_id, created, &model, usage);
+                            yield Ok::<Event, Infallible>(json_event(usage_chunk));
                         }
-                        let finish_reason = chunk.finish_reason.unwrap_or_else(|| "stop".to_owned());
-                        let done_chunk = ChatCompletionsChunk::finish(&response_id, created, &model, finish_reason);
-                        yield Ok::<Event, Infallible>(json_event(done_chunk));
                     }
                 }
                 Err(error) => {
+                    disconnect.finished = true;
                     state.metrics.observe_backend_error("stream_fanout");
                     warn!(error = %error, "backend stream error");
-                    let error_json = serde_json::json!({
-                        "error": {
-                            "message": error,
-                            "type": "backend_error"
-                        }
-                    });
-                    yield Ok::<Event, Infallible>(Event::default().data(error_json.to_string()));
+                    // OpenAI SDKs raise on an `error` payload and then expect `[DONE]`.
+                    let data = AppError::from(error)
+                        .stream_event_data(Some(accounting.request_id.as_str()));
+                    yield Ok::<Event, Infallible>(Event::default().data(data));
                     break;
                 }
             }
         }
 
         yield Ok::<Event, Infallible>(Event::default().data("[DONE]"));
+    }
+}
+
+/// The next item from `stream_rx`, or a timeout error once `ends_at` passes.
+async fn recv_until(
+    stream_rx: &mut StreamReceiver,
+    ends_at: Option<tokio::time::Instant>,
+) -> Option<StreamItem> {
+    let Some(ends_at) = ends_at else {
+        return stream_rx.recv().await;
     };
+    tokio::time::timeout_at(ends_at, stream_rx.recv())
+        .await
+        .unwrap_or_else(|_| {
+            Some(Err(BackendError::Timeout(
+                "stream ran past its maximum duration".to_owned(),
+            )))
+        })
+}
 
-    let mut response = Sse::new(outbound)
-        .keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(10)))
-        .into_response();
-    apply_rate_limit_headers(response.headers_mut(), &rate_snapshot);
-    Ok(response)
+fn sse_response<S>(outbound: S, streaming: &StreamingConfig) -> Response
+where
+    S: Stream<Item = Result<Event, Infallible>> + Send + 'static,
+{
+    let sse = Sse::new(outbound);
+    match streaming.keep_alive_interval {
+        Some(interval) => sse
+            .keep_alive(
+                KeepAlive::new()
+                    .interval(interval)
+                    .text(&streaming.keep_alive_text),
+            )
+            .into_response(),
+        None => sse.into_response(),
+    }
+}
+
+/// Feeds a cached stream transcript through the same channel shape the coalescer uses, so cache
+/// hits share the live SSE mapping. Paced replay reproduces the original chunk timing.
+fn replay_transcript(transcript: StreamTranscript, paced: bool) -> StreamReceiver {
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let started = tokio::time::Instant::now();
+        for chunk in transcript.chunks {
+            if paced {
+                tokio::time::sleep_until(started + Duration::from_millis(chunk.offset_ms)).await;
+            }
+            let item = Ok(BackendChunk {
+                delta: Some(chunk.delta),
+                finish_reason: None,
+                provider_finish_reason: None,
+                usage: None,
+                done: false,
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            });
+            if tx.send(item).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = tx
+            .send(Ok(BackendChunk {
+                delta: None,
+                finish_reason: Some(transcript.finish_reason),
+                provider_finish_reason: transcript.provider_finish_reason,
+                usage: transcript.usage,
+                done: true,
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            }))
+            .await;
+    });
+    rx.into()
+}
+
+/// Refreshes a stale one-shot entry in the background. The refresh joins any inflight request
+/// for the same fingerprint instead of issuing a second backend call.
+fn spawn_one_shot_refresh(
+    state: &AppState,
+    key: String,
+    mut request: Arc<NormalizedChatRequest>,
+    ttl: Option<Duration>,
+) {
+    if !state.response_cache.begin_refresh(&key) {
+        return;
+    }
+    // Refreshes outlive the request that triggered them.
+    Arc::make_mut(&mut request).deadline = None;
+
+    let request_model = request.model.clone();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let backend: Arc<dyn InferenceBackend> = state.batcher.clone();
+        match state
+            .coalescer
+            .execute_or_join(key.clone(), backend, request)
+            .await
+        {
+            Ok((response, _)) => {
+                state
+                    .metrics
+                    .observe_usage(&request_model, None, &response.usage);
+                record_spend(
+                    &state,
+                    &request_model,
+                    response.backend.as_deref(),
+                    &response.usage,
+                );
+                state.response_cache.set(&key, &response, ttl).await;
+            }
+            Err(error) => {
+                state.metrics.observe_backend_error("cache_refresh");
+                warn!(error = %error, "stale cache refresh failed");
+            }
+        }
+        state.response_cache.end_refresh(&key);
+    });
+}
+
+fn spawn_stream_refresh(
+    state: &AppState,
+    key: String,
+    mut request: Arc<NormalizedChatRequest>,
+    ttl: Option<Duration>,
+) {
+    if !state.response_cache.begin_refresh(&key) {
+        return;
+    }
+    // Refreshes outlive the request that triggered them.
+    Arc::make_mut(&mut request).deadline = None;
+
+    let request_model = request.model.clone();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let idle_timeout = state.timeouts.stream_idle_timeout;
+        match capture_transcript(state.batcher.clone(), request, idle_timeout).await {
+            Ok((transcript, backend)) => {
+                if let Some(usage) = &transcript.usage {
+                    state.metrics.observe_usage(&request_model, None, usage);
+                    record_spend(&state, &request_model, backend.as_deref(), usage);
+                }
+                state
+                    .response_cache
+                    .set_stream(&key, &transcript, ttl)
+                    .await;
+            }
+            Err(error) => {
+                state.metrics.observe_backend_error("cache_refresh");
+                warn!(error = %error, "stale stream cache refresh failed");
+            }
+        }
+        state.response_cache.end_refresh(&key);
+    });
+}
+
+async fn capture_transcript(
+    backend: Arc<dyn InferenceBackend>,
+    request: Arc<NormalizedChatRequest>,
+    idle_timeout: Option<Duration>,
+) -> Result<(StreamTranscript, Option<String>), BackendError> {
+    let started = Instant::now();
+    let mut stream = stream_with_idle_timeout(backend.stream_chat(request).await?, idle_timeout);
+    let mut transcript = StreamTranscript::default();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if let Some(delta) = chunk.delta {
+            transcript.push_delta(delta, started.elapsed().as_millis() as u64);
+        }
+        if chunk.done {
+            transcript.finish(&chunk);
+            return Ok((transcript, chunk.backend));
+        }
+    }
+
+    Err(BackendError::InvalidResponse(
+        "stream ended without a terminal chunk".to_owned(),
+    ))
 }
 
 fn apply_rate_limit_headers(headers: &mut axum::http::HeaderMap, snapshot: &RateLimitSnapshot) {
@@ -327,6 +1735,28 @@ fn apply_rate_limit_headers(headers: &mut axum::http::HeaderMap, snapshot: &Rate
     }
 }
 
+/// `x-gateway-credits-remaining`: the tenant's prepaid balance in USD after this request.
+fn apply_credits_header(headers: &mut axum::http::HeaderMap, balance_usd: Option<f64>) {
+    if let Some(balance_usd) = balance_usd {
+        crate::errors::apply_header(
+            headers,
+            "x-gateway-credits-remaining",
+            &crate::credits::format_balance(balance_usd),
+        );
+    }
+}
+
+/// Whether an upstream SSE payload is the `choices: []` event carrying only usage.
+fn is_usage_only_event(raw: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(raw).is_ok_and(|event| {
+        event.get("usage").is_some_and(|usage| !usage.is_null())
+            && event
+                .get("choices")
+                .and_then(serde_json::Value::as_array)
+                .is_some_and(Vec::is_empty)
+    })
+}
+
 fn json_event<T: serde::Serialize>(payload: T) -> Event {
     match serde_json::to_string(&payload) {
         Ok(serialized) => Event::default().data(serialized),
@@ -334,7 +1764,9 @@ fn json_event<T: serde::Serialize>(payload: T) -> Event {
             let fallback = serde_json::json!({
                 "error": {
                     "message": format!("serialization error: {error}"),
-                    "type": "server_error"
+                    "type": "server_error",
+                    "param": null,
+                    "code": "internal_error"
                 }
             });
             Event::default().data(fallback.to_string())
@@ -348,3 +1780,120 @@ fn unix_timestamp() -> i64 {
         .unwrap_or_default();
     duration.as_secs() as i64
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use crate::{
+        auth::{AuthContext, KeyPolicy, RatePolicy},
+        models::{ChatCompletionsRequest, MessageRole, OpenAiMessage},
+        tenants::{Tenant, TenantPolicy},
+    };
+
+    use super::{abandoned_tokens, coalescing_enabled};
+
+    fn auth(key_policy: KeyPolicy) -> AuthContext {
+        AuthContext {
+            api_key: "key".to_owned(),
+            user_id: "key_key".to_owned(),
+            tenant: Tenant::new("key_key", TenantPolicy::default()),
+            policy: RatePolicy {
+                requests_per_minute: 1,
+                tokens_per_minute: 1,
+                tokens_per_day: 1,
+            },
+            key_policy,
+        }
+    }
+
+    fn request(temperature: Option<f32>) -> crate::models::NormalizedChatRequest {
+        ChatCompletionsRequest {
+            model: "mock".to_owned(),
+            messages: vec![OpenAiMessage {
+                role: MessageRole::User,
+                content: "hi".to_owned(),
+                cache_control: None,
+            }],
+            max_tokens: None,
+            temperature,
+            top_p: None,
+            stream: false,
+            user: None,
+            timeout: None,
+            stream_options: None,
+            metadata: None,
+        }
+        .into_normalized("user".to_owned())
+        .expect("valid request")
+    }
+
+    #[test]
+    fn coalescing_skips_sampled_and_opted_out_requests() {
+        let headers = HeaderMap::new();
+        let default_key = auth(KeyPolicy::default());
+
+        assert!(coalescing_enabled(
+            &headers,
+            &default_key,
+            &request(None),
+            false
+        ));
+        assert!(coalescing_enabled(
+            &headers,
+            &default_key,
+            &request(Some(0.0)),
+            false
+        ));
+        assert!(!coalescing_enabled(
+            &headers,
+            &default_key,
+            &request(Some(0.7)),
+            false
+        ));
+        assert!(coalescing_enabled(
+            &headers,
+            &default_key,
+            &request(Some(0.7)),
+            true
+        ));
+
+        let opted_in = auth(KeyPolicy {
+            coalesce: Some(true),
+            ..KeyPolicy::default()
+        });
+        assert!(coalescing_enabled(
+            &headers,
+            &opted_in,
+            &request(Some(0.7)),
+            false
+        ));
+
+        let opted_out = auth(KeyPolicy {
+            coalesce: Some(false),
+            ..KeyPolicy::default()
+        });
+        assert!(!coalescing_enabled(
+            &headers,
+            &opted_out,
+            &request(None),
+            true
+        ));
+
+        let mut off = HeaderMap::new();
+        off.insert("x-gateway-coalesce", HeaderValue::from_static("off"));
+        assert!(!coalescing_enabled(
+            &off,
+            &default_key,
+            &request(None),
+            true
+        ));
+    }
+
+    #[test]
+    fn abandoned_tokens_are_prorated_by_unobserved_text() {
+        assert_eq!(abandoned_tokens(100, 400, 100), 75);
+        assert_eq!(abandoned_tokens(100, 400, 400), 0);
+        assert_eq!(abandoned_tokens(100, 0, 0), 0);
+    }
+}
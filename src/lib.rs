@@ -1,55 +1,183 @@
+pub mod access_log;
+pub mod admin;
+pub mod admission;
 pub mod auth;
 pub mod backend;
 pub mod batcher;
+pub mod body_limits;
+pub mod builder;
 pub mod cache;
+pub mod catalog;
+pub mod catch_panic;
+pub mod clock;
 pub mod coalescing;
+pub mod config;
+pub mod context_window;
+pub mod credits;
+pub mod egress;
+pub mod error_reporting;
 pub mod errors;
+pub mod experiments;
+pub mod fair_queue;
+pub mod forwarded_headers;
+pub mod glob;
 pub mod handlers;
 pub mod limits;
+pub mod listener;
 pub mod metrics;
+pub mod model_pools;
 pub mod models;
+pub mod postprocess;
+pub mod pricing;
+pub mod prompts;
+pub mod quotas;
+pub mod redis_pool;
+pub mod reports;
+pub mod request_id;
 pub mod router;
 pub mod scheduler;
 pub mod state;
+pub mod streaming;
+pub mod tenants;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timeouts;
+pub mod traces;
+pub mod truncation;
+pub mod usage_sink;
 
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    routing::{get, post, put},
     Router,
 };
-use backend::{mock::MockBackend, openai::OpenAiAdapter, InferenceBackend};
-use router::BackendRouter;
-use tracing::info;
+use backend::{
+    mock::{read_fault_rules, read_script, MockBackend},
+    openai::OpenAiAdapter,
+    peer::{PeerAdapter, PeerConfig},
+    vcr::{RecordingBackend, ReplayBackend, VcrConfig, VcrMode},
+    InferenceBackend,
+};
+
+pub use builder::GatewayBuilder;
 
 pub fn build_state() -> Result<state::AppState, std::io::Error> {
+    let vcr = VcrConfig::from_env();
+    if vcr.mode == VcrMode::Replay {
+        // Fixtures have no health to probe, so the replay backend is used without the router.
+        tracing::info!(dir = %vcr.dir.display(), "replaying backend fixtures, upstream is not called");
+        let replay: Arc<dyn InferenceBackend> =
+            Arc::new(ReplayBackend::new(&vcr.dir, vcr.paced_replay));
+        return Ok(GatewayBuilder::from_env().assemble(replay));
+    }
+
     let mut backends: Vec<Arc<dyn InferenceBackend>> = Vec::new();
     if let Some(openai) = OpenAiAdapter::from_env().map_err(std::io::Error::other)? {
         backends.push(Arc::new(openai));
     }
+    for peer in PeerConfig::from_env() {
+        backends.push(Arc::new(
+            PeerAdapter::new(peer).map_err(std::io::Error::other)?,
+        ));
+    }
 
     if backends.is_empty() {
-        let backend_a: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("mock-a"));
-        let backend_b: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("mock-b"));
+        let (faults, script) = (read_fault_rules(), read_script());
+        let mock = |name: &str| {
+            Arc::new(
+                MockBackend::named(name)
+                    .with_faults(faults.clone())
+                    .with_script(script.clone()),
+            ) as Arc<dyn InferenceBackend>
+        };
+        let backend_a = mock("mock-a");
+        let backend_b = mock("mock-b");
         backends.push(backend_a);
         backends.push(backend_b);
     }
 
-    let backend_names = backends
-        .iter()
-        .map(|backend| backend.name().to_owned())
-        .collect::<Vec<_>>()
-        .join(",");
-    let router = Arc::new(BackendRouter::new(backends));
-    router.clone().spawn_health_checks(Duration::from_secs(15));
-    info!(backend = router.name(), endpoints = %backend_names, "backend router configured");
-    Ok(state::AppState::new(router))
+    if vcr.mode == VcrMode::Record {
+        tracing::info!(dir = %vcr.dir.display(), "recording backend traffic to fixtures");
+        backends = backends
+            .into_iter()
+            .map(|backend| {
+                Arc::new(RecordingBackend::new(backend, &vcr.dir)) as Arc<dyn InferenceBackend>
+            })
+            .collect();
+    }
+
+    backends
+        .into_iter()
+        .fold(GatewayBuilder::from_env(), GatewayBuilder::backend)
+        .build_state()
+        .map_err(std::io::Error::other)
 }
 
 pub fn build_app(state: state::AppState) -> Router {
-    Router::new()
+    // The guard enforces the configured body limit itself, in place of axum's default.
+    let chat = Router::new()
+        .route("/v1/chat/completions", post(handlers::chat_completions))
+        .route(
+            "/v1/chat/completions/validate",
+            post(handlers::validate_chat_completions),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            body_limits::guard,
+        ))
+        .layer(DefaultBodyLimit::disable());
+    let mut app = Router::new()
         .route("/healthz", get(handlers::healthz))
+        .route("/v1/models", get(handlers::list_models))
+        .merge(chat)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            catch_panic::guard,
+        ))
+        .layer(axum::middleware::from_fn(request_id::propagate))
+        .with_state(state.clone());
+    if state.admin.listen_addr.is_none() {
+        app = app.merge(build_admin_app(state));
+    }
+    app
+}
+
+/// Operational endpoints, guarded by the admin token and network allowlist; `/admin/*` also
+/// refuses non-loopback clients while neither is configured. Served on the main port unless
+/// `GATEWAY_ADMIN_LISTEN_ADDR` gives them their own listener.
+pub fn build_admin_app(state: state::AppState) -> Router {
+    let admin = Router::new()
+        .route("/admin/reports/costs", get(reports::cost_report))
+        .route("/admin/backends", get(router::backend_status))
+        .route(
+            "/admin/backends/:name/credentials",
+            put(backend::credentials::replace_credentials),
+        )
+        .route("/admin/quotas", get(quotas::list_quotas))
+        .route(
+            "/admin/quotas/:scope/:id",
+            get(quotas::get_quota)
+                .put(quotas::put_quota)
+                .delete(quotas::delete_quota),
+        )
+        .route(
+            "/admin/credits/:tenant_id",
+            get(credits::get_credits)
+                .put(credits::set_credits)
+                .post(credits::top_up_credits),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.admin.clone(),
+            admin::protect_admin,
+        ));
+    Router::new()
         .route("/metrics", get(handlers::metrics))
-        .route("/v1/chat/completions", post(handlers::chat_completions))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.admin.clone(),
+            admin::protect,
+        ))
+        .merge(admin)
         .with_state(state)
 }
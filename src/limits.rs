@@ -1,14 +1,20 @@
 use std::{
     collections::HashMap,
     env,
-    time::{SystemTime, UNIX_EPOCH},
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex, MutexGuard},
 };
 
+use async_trait::async_trait;
 use redis::AsyncCommands;
-use tokio::sync::Mutex;
 use tracing::warn;
 
-use crate::{auth::RatePolicy, models::NormalizedChatRequest};
+use crate::{
+    auth::RatePolicy,
+    clock::{Clock, SystemClock},
+    models::NormalizedChatRequest,
+    redis_pool::RedisPool,
+};
 
 #[derive(Debug, Clone)]
 pub struct RateLimitSnapshot {
@@ -23,6 +29,29 @@ pub struct RateLimitSnapshot {
 }
 
 impl RateLimitSnapshot {
+    /// The snapshot of a key that has used `requests` requests and `tokens_minute` tokens in
+    /// the minute window containing `now` (unix seconds), and `tokens_day` in its day window.
+    pub fn from_counts(
+        policy: &RatePolicy,
+        requests: u64,
+        tokens_minute: u64,
+        tokens_day: u64,
+        now: u64,
+    ) -> Self {
+        Self {
+            limit_requests_per_minute: policy.requests_per_minute,
+            remaining_requests_per_minute: policy
+                .requests_per_minute
+                .saturating_sub(requests as u32),
+            limit_tokens_per_minute: policy.tokens_per_minute,
+            remaining_tokens_per_minute: policy.tokens_per_minute.saturating_sub(tokens_minute),
+            limit_tokens_per_day: policy.tokens_per_day,
+            remaining_tokens_per_day: policy.tokens_per_day.saturating_sub(tokens_day),
+            reset_requests_per_minute: current_minute_start(now).saturating_add(60),
+            reset_tokens_per_day: current_day_start(now).saturating_add(86_400),
+        }
+    }
+
     pub fn to_header_pairs(&self) -> Vec<(String, String)> {
         vec![
             (
@@ -84,18 +113,188 @@ impl RateLimitError {
             Self::TokensPerDay(snapshot) => snapshot,
         }
     }
+
+    fn snapshot_mut(&mut self) -> &mut RateLimitSnapshot {
+        match self {
+            Self::RequestsPerMinute(snapshot) => snapshot,
+            Self::TokensPerMinute(snapshot) => snapshot,
+            Self::TokensPerDay(snapshot) => snapshot,
+        }
+    }
+}
+
+/// When daily token quotas roll over: a local start time in a fixed UTC offset, e.g. a
+/// billing day starting at 06:00 at `-05:00`. The default is midnight UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaDay {
+    /// Seconds after midnight UTC at which each quota day starts; a whole number of minutes
+    /// below a day.
+    offset_secs: u64,
+}
+
+impl QuotaDay {
+    /// The quota day starting at `start` (`HH:MM`) in `timezone` (`UTC`, `Z`, or `±HH:MM`).
+    /// Named zones with daylight saving time are not supported; give their current offset.
+    pub fn parse(start: &str, timezone: &str) -> Result<Self, String> {
+        let minutes = |raw: &str| {
+            let (hours, minutes) = raw.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+            ((0..24).contains(&hours) && (0..60).contains(&minutes)).then_some(hours * 60 + minutes)
+        };
+        let start_minutes =
+            minutes(start).ok_or_else(|| format!("quota day start `{start}` must be `HH:MM`"))?;
+        let timezone = timezone.trim();
+        let offset_minutes = match timezone {
+            "UTC" | "utc" | "Z" | "z" => 0,
+            _ => {
+                let invalid = || format!("quota timezone `{timezone}` must be `UTC` or `±HH:MM`");
+                let (sign, offset) = match timezone.strip_prefix('-') {
+                    Some(offset) => (-1, offset),
+                    None => (1, timezone.strip_prefix('+').ok_or_else(invalid)?),
+                };
+                sign * minutes(offset).ok_or_else(invalid)?
+            }
+        };
+        Ok(Self {
+            offset_secs: ((start_minutes - offset_minutes).rem_euclid(24 * 60) * 60) as u64,
+        })
+    }
+
+    /// Reads `GATEWAY_QUOTA_DAY_START` and `GATEWAY_QUOTA_TIMEZONE`.
+    pub fn from_env() -> Self {
+        let start = env::var("GATEWAY_QUOTA_DAY_START").ok();
+        let timezone = env::var("GATEWAY_QUOTA_TIMEZONE").ok();
+        if start.is_none() && timezone.is_none() {
+            return Self::default();
+        }
+        Self::parse(
+            start.as_deref().unwrap_or("00:00"),
+            timezone.as_deref().unwrap_or("UTC"),
+        )
+        .unwrap_or_else(|error| {
+            warn!(error = %error, "invalid quota day, daily quotas reset at midnight UTC");
+            Self::default()
+        })
+    }
+
+    /// Moves `now` onto a clock whose midnights are the quota day's boundaries.
+    fn shift(self, now: u64) -> u64 {
+        now.saturating_sub(self.offset_secs)
+    }
+
+    /// Moves a snapshot counted on the shifted clock back to real reset times.
+    fn unshift(self, snapshot: &mut RateLimitSnapshot) {
+        snapshot.reset_requests_per_minute += self.offset_secs;
+        snapshot.reset_tokens_per_day += self.offset_secs;
+    }
+}
+
+/// Where per-key minute and day windows are counted. The gateway ships [`MemoryLimitStore`]
+/// and [`RedisLimitStore`]; embedders can count elsewhere (Memcached, DynamoDB, ...) through
+/// [`RateLimiter::with_store`]. `now` is the limiter clock in unix seconds, set back by the
+/// [`QuotaDay`] offset, and windows start on whole minutes and days of it; the limiter moves
+/// snapshot reset times forward again.
+#[async_trait]
+pub trait LimitStore: Send + Sync {
+    /// Counts one request of `estimated_tokens` against `key`, unless that would exceed
+    /// `policy`, in which case nothing is counted. Must be atomic per key across instances
+    /// sharing the store.
+    async fn check_and_consume(
+        &self,
+        key: &str,
+        policy: &RatePolicy,
+        estimated_tokens: u64,
+        now: u64,
+    ) -> Result<RateLimitSnapshot, RateLimitError>;
+
+    /// Corrects `key`'s token counts once the request's actual usage is known.
+    async fn reconcile_tokens(&self, key: &str, estimated: u64, actual: u64, now: u64);
 }
 
 pub struct RateLimiter {
-    backend: RateLimiterBackend,
+    store: Arc<dyn LimitStore>,
+    clock: Arc<dyn Clock>,
+    quota_day: QuotaDay,
 }
 
-enum RateLimiterBackend {
-    Memory(Mutex<HashMap<String, KeyUsage>>),
-    Redis {
-        client: redis::Client,
-        prefix: String,
-    },
+/// Number of independently locked maps the in-memory limiter spreads keys over.
+const MEMORY_SHARDS: usize = 64;
+
+/// Per-key windows split into shards by key hash, so concurrent requests for different keys
+/// rarely wait on the same lock. Limits are per instance.
+pub struct MemoryLimitStore {
+    hasher: RandomState,
+    shards: Box<[Mutex<HashMap<String, KeyUsage>>]>,
+}
+
+impl Default for MemoryLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryLimitStore {
+    pub fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..MEMORY_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, api_key: &str) -> MutexGuard<'_, HashMap<String, KeyUsage>> {
+        let index = self.hasher.hash_one(api_key) as usize % self.shards.len();
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl LimitStore for MemoryLimitStore {
+    async fn check_and_consume(
+        &self,
+        key: &str,
+        policy: &RatePolicy,
+        estimated_tokens: u64,
+        now: u64,
+    ) -> Result<RateLimitSnapshot, RateLimitError> {
+        check_and_consume_memory(self, key, policy, estimated_tokens, now)
+    }
+
+    async fn reconcile_tokens(&self, key: &str, estimated: u64, actual: u64, now: u64) {
+        reconcile_tokens_memory(self, key, estimated, actual, now);
+    }
+}
+
+/// Windows counted in Redis by one Lua script per request, so every instance enforces the
+/// same limits. A Redis outage lets requests through rather than rejecting them.
+pub struct RedisLimitStore {
+    pool: Arc<RedisPool>,
+}
+
+impl RedisLimitStore {
+    pub fn new(pool: Arc<RedisPool>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl LimitStore for RedisLimitStore {
+    async fn check_and_consume(
+        &self,
+        key: &str,
+        policy: &RatePolicy,
+        estimated_tokens: u64,
+        now: u64,
+    ) -> Result<RateLimitSnapshot, RateLimitError> {
+        check_and_consume_redis(&self.pool, key, policy, estimated_tokens, now).await
+    }
+
+    async fn reconcile_tokens(&self, key: &str, estimated: u64, actual: u64, now: u64) {
+        reconcile_tokens_redis(&self.pool, key, estimated, actual, now).await;
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -121,54 +320,63 @@ impl KeyUsage {
 
 impl Default for RateLimiter {
     fn default() -> Self {
-        Self::from_env()
+        Self::in_memory()
     }
 }
 
 impl RateLimiter {
-    pub fn from_env() -> Self {
-        match env::var("REDIS_URL") {
-            Ok(url) if !url.trim().is_empty() => match redis::Client::open(url.clone()) {
-                Ok(client) => {
-                    let prefix =
-                        env::var("GATEWAY_REDIS_PREFIX").unwrap_or_else(|_| "gateway".to_owned());
-                    Self {
-                        backend: RateLimiterBackend::Redis { client, prefix },
-                    }
-                }
-                Err(error) => {
-                    warn!(error = %error, "invalid REDIS_URL, falling back to in-memory limiter");
-                    Self {
-                        backend: RateLimiterBackend::Memory(Mutex::new(HashMap::new())),
-                    }
-                }
-            },
-            _ => Self {
-                backend: RateLimiterBackend::Memory(Mutex::new(HashMap::new())),
-            },
+    /// Counts requests in Redis when `redis` is set, so every instance enforces the same
+    /// limits, and in memory otherwise.
+    pub fn new(redis: Option<Arc<RedisPool>>) -> Self {
+        match redis {
+            Some(pool) => Self::with_store(Arc::new(RedisLimitStore::new(pool))),
+            None => Self::in_memory(),
         }
     }
 
     pub fn in_memory() -> Self {
+        Self::with_store(Arc::new(MemoryLimitStore::new()))
+    }
+
+    /// Counts requests in `store`.
+    pub fn with_store(store: Arc<dyn LimitStore>) -> Self {
         Self {
-            backend: RateLimiterBackend::Memory(Mutex::new(HashMap::new())),
+            store,
+            clock: SystemClock::shared(),
+            quota_day: QuotaDay::default(),
         }
     }
 
+    /// Places requests in minute and day windows by `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Rolls daily quotas over at `quota_day`'s boundary instead of midnight UTC.
+    pub fn with_quota_day(mut self, quota_day: QuotaDay) -> Self {
+        self.quota_day = quota_day;
+        self
+    }
+
     pub async fn check_and_consume(
         &self,
         api_key: &str,
         policy: &RatePolicy,
         estimated_tokens: u64,
     ) -> Result<RateLimitSnapshot, RateLimitError> {
-        match &self.backend {
-            RateLimiterBackend::Memory(usage_map) => {
-                check_and_consume_memory(usage_map, api_key, policy, estimated_tokens).await
-            }
-            RateLimiterBackend::Redis { client, prefix } => {
-                check_and_consume_redis(client, prefix, api_key, policy, estimated_tokens).await
-            }
-        }
+        let now = self.quota_day.shift(self.clock.unix_secs());
+        self.store
+            .check_and_consume(api_key, policy, estimated_tokens, now)
+            .await
+            .map(|mut snapshot| {
+                self.quota_day.unshift(&mut snapshot);
+                snapshot
+            })
+            .map_err(|mut error| {
+                self.quota_day.unshift(error.snapshot_mut());
+                error
+            })
     }
 
     pub async fn reconcile_tokens(&self, api_key: &str, estimated: u64, actual: u64) {
@@ -176,36 +384,35 @@ impl RateLimiter {
             return;
         }
 
-        match &self.backend {
-            RateLimiterBackend::Memory(usage_map) => {
-                reconcile_tokens_memory(usage_map, api_key, estimated, actual).await;
-            }
-            RateLimiterBackend::Redis { client, prefix } => {
-                reconcile_tokens_redis(client, prefix, api_key, estimated, actual).await;
-            }
-        }
+        let now = self.quota_day.shift(self.clock.unix_secs());
+        self.store
+            .reconcile_tokens(api_key, estimated, actual, now)
+            .await;
     }
 }
 
 pub fn estimate_request_tokens(request: &NormalizedChatRequest) -> u64 {
-    let prompt_tokens = request
+    let prompt_tokens = estimate_prompt_tokens(request);
+    let completion_estimate = request.generation.max_tokens.unwrap_or(256) as u64;
+    prompt_tokens.saturating_add(completion_estimate)
+}
+
+pub fn estimate_prompt_tokens(request: &NormalizedChatRequest) -> u64 {
+    request
         .messages
         .iter()
         .map(|message| rough_token_estimate(&message.content))
-        .sum::<u64>();
-
-    let completion_estimate = request.generation.max_tokens.unwrap_or(256) as u64;
-    prompt_tokens.saturating_add(completion_estimate)
+        .sum()
 }
 
-async fn check_and_consume_memory(
-    usage_map: &Mutex<HashMap<String, KeyUsage>>,
+fn check_and_consume_memory(
+    usage_map: &MemoryLimitStore,
     api_key: &str,
     policy: &RatePolicy,
     estimated_tokens: u64,
+    now: u64,
 ) -> Result<RateLimitSnapshot, RateLimitError> {
-    let now = unix_timestamp();
-    let mut usage_map = usage_map.lock().await;
+    let mut usage_map = usage_map.shard(api_key);
     let usage = usage_map
         .entry(api_key.to_owned())
         .or_insert_with(|| KeyUsage::new(now));
@@ -235,14 +442,14 @@ async fn check_and_consume_memory(
     Ok(snapshot(policy, usage, now))
 }
 
-async fn reconcile_tokens_memory(
-    usage_map: &Mutex<HashMap<String, KeyUsage>>,
+fn reconcile_tokens_memory(
+    usage_map: &MemoryLimitStore,
     api_key: &str,
     estimated: u64,
     actual: u64,
+    now: u64,
 ) {
-    let now = unix_timestamp();
-    let mut usage_map = usage_map.lock().await;
+    let mut usage_map = usage_map.shard(api_key);
     let Some(usage) = usage_map.get_mut(api_key) else {
         return;
     };
@@ -261,26 +468,27 @@ async fn reconcile_tokens_memory(
 }
 
 async fn check_and_consume_redis(
-    client: &redis::Client,
-    prefix: &str,
+    pool: &RedisPool,
     api_key: &str,
     policy: &RatePolicy,
     estimated_tokens: u64,
+    now: u64,
 ) -> Result<RateLimitSnapshot, RateLimitError> {
-    let now = unix_timestamp();
     let minute_start = current_minute_start(now);
     let day_start = current_day_start(now);
     let minute_reset = minute_start.saturating_add(60);
     let day_reset = day_start.saturating_add(86_400);
 
-    let req_key = format!("{prefix}:rl:{api_key}:m:{minute_start}:req");
-    let tok_min_key = format!("{prefix}:rl:{api_key}:m:{minute_start}:tok");
-    let tok_day_key = format!("{prefix}:rl:{api_key}:d:{day_start}:tok");
+    let prefix = pool.prefix();
+    // The braces hash-tag a key's counters into one Redis Cluster slot, which the script needs.
+    let req_key = format!("{prefix}:rl:{{{api_key}}}:m:{minute_start}:req");
+    let tok_min_key = format!("{prefix}:rl:{{{api_key}}}:m:{minute_start}:tok");
+    let tok_day_key = format!("{prefix}:rl:{{{api_key}}}:d:{day_start}:tok");
 
     let req_ttl = minute_reset.saturating_sub(now).max(1);
     let day_ttl = day_reset.saturating_sub(now).max(1);
 
-    let mut connection = match client.get_multiplexed_async_connection().await {
+    let mut connection = match pool.connection().await {
         Ok(connection) => connection,
         Err(error) => {
             warn!(error = %error, "redis unavailable for rate limit check");
@@ -334,6 +542,7 @@ return {1, req, tok_min, tok_day}
         .arg(day_ttl as i64)
         .invoke_async::<Vec<i64>>(&mut connection)
         .await;
+    let values = pool.track(values).await;
 
     let values = match values {
         Ok(values) if values.len() == 4 => values,
@@ -354,7 +563,8 @@ return {1, req, tok_min, tok_day}
     let req_count = values[1].max(0) as u64;
     let tok_min_count = values[2].max(0) as u64;
     let tok_day_count = values[3].max(0) as u64;
-    let snapshot = snapshot_from_counts(policy, req_count, tok_min_count, tok_day_count, now);
+    let snapshot =
+        RateLimitSnapshot::from_counts(policy, req_count, tok_min_count, tok_day_count, now);
 
     if allowed {
         Ok(snapshot)
@@ -368,13 +578,12 @@ return {1, req, tok_min, tok_day}
 }
 
 async fn reconcile_tokens_redis(
-    client: &redis::Client,
-    prefix: &str,
+    pool: &RedisPool,
     api_key: &str,
     estimated: u64,
     actual: u64,
+    now: u64,
 ) {
-    let now = unix_timestamp();
     let minute_start = current_minute_start(now);
     let day_start = current_day_start(now);
     let minute_reset = minute_start.saturating_add(60);
@@ -382,14 +591,15 @@ async fn reconcile_tokens_redis(
     let req_ttl = minute_reset.saturating_sub(now).max(1);
     let day_ttl = day_reset.saturating_sub(now).max(1);
 
-    let tok_min_key = format!("{prefix}:rl:{api_key}:m:{minute_start}:tok");
-    let tok_day_key = format!("{prefix}:rl:{api_key}:d:{day_start}:tok");
+    let prefix = pool.prefix();
+    let tok_min_key = format!("{prefix}:rl:{{{api_key}}}:m:{minute_start}:tok");
+    let tok_day_key = format!("{prefix}:rl:{{{api_key}}}:d:{day_start}:tok");
     let diff = actual as i64 - estimated as i64;
     if diff == 0 {
         return;
     }
 
-    let mut connection = match client.get_multiplexed_async_connection().await {
+    let mut connection = match pool.connection().await {
         Ok(connection) => connection,
         Err(error) => {
             warn!(error = %error, "redis unavailable for token reconciliation");
@@ -397,16 +607,27 @@ async fn reconcile_tokens_redis(
         }
     };
 
-    if diff > 0 {
-        let _: redis::RedisResult<()> = connection.incr(&tok_min_key, diff).await;
-        let _: redis::RedisResult<()> = connection.incr(&tok_day_key, diff).await;
-    } else {
-        let adjust = diff.abs();
-        let _: redis::RedisResult<()> = connection.decr(&tok_min_key, adjust).await;
-        let _: redis::RedisResult<()> = connection.decr(&tok_day_key, adjust).await;
+    let result: redis::RedisResult<()> = async {
+        if diff > 0 {
+            connection.incr::<_, _, ()>(&tok_min_key, diff).await?;
+            connection.incr::<_, _, ()>(&tok_day_key, diff).await?;
+        } else {
+            let adjust = diff.abs();
+            connection.decr::<_, _, ()>(&tok_min_key, adjust).await?;
+            connection.decr::<_, _, ()>(&tok_day_key, adjust).await?;
+        }
+        connection
+            .expire::<_, bool>(&tok_min_key, req_ttl as i64)
+            .await?;
+        connection
+            .expire::<_, bool>(&tok_day_key, day_ttl as i64)
+            .await?;
+        Ok(())
+    }
+    .await;
+    if let Err(error) = pool.track(result).await {
+        warn!(error = %error, "redis token reconciliation failed");
     }
-    let _: redis::RedisResult<bool> = connection.expire(&tok_min_key, req_ttl as i64).await;
-    let _: redis::RedisResult<bool> = connection.expire(&tok_day_key, day_ttl as i64).await;
 }
 
 fn rough_token_estimate(text: &str) -> u64 {
@@ -432,7 +653,7 @@ fn refresh_windows(now: u64, usage: &mut KeyUsage) {
 }
 
 fn snapshot(policy: &RatePolicy, usage: &KeyUsage, now: u64) -> RateLimitSnapshot {
-    snapshot_from_counts(
+    RateLimitSnapshot::from_counts(
         policy,
         usage.requests_in_minute as u64,
         usage.tokens_in_minute,
@@ -441,29 +662,8 @@ fn snapshot(policy: &RatePolicy, usage: &KeyUsage, now: u64) -> RateLimitSnapsho
     )
 }
 
-fn snapshot_from_counts(
-    policy: &RatePolicy,
-    request_count: u64,
-    tokens_minute_count: u64,
-    tokens_day_count: u64,
-    now: u64,
-) -> RateLimitSnapshot {
-    RateLimitSnapshot {
-        limit_requests_per_minute: policy.requests_per_minute,
-        remaining_requests_per_minute: policy
-            .requests_per_minute
-            .saturating_sub(request_count as u32),
-        limit_tokens_per_minute: policy.tokens_per_minute,
-        remaining_tokens_per_minute: policy.tokens_per_minute.saturating_sub(tokens_minute_count),
-        limit_tokens_per_day: policy.tokens_per_day,
-        remaining_tokens_per_day: policy.tokens_per_day.saturating_sub(tokens_day_count),
-        reset_requests_per_minute: current_minute_start(now).saturating_add(60),
-        reset_tokens_per_day: current_day_start(now).saturating_add(86_400),
-    }
-}
-
 fn empty_snapshot(policy: &RatePolicy, now: u64) -> RateLimitSnapshot {
-    snapshot_from_counts(policy, 0, 0, 0, now)
+    RateLimitSnapshot::from_counts(policy, 0, 0, 0, now)
 }
 
 fn current_minute_start(now: u64) -> u64 {
@@ -474,17 +674,16 @@ fn current_day_start(now: u64) -> u64 {
     (now / 86_400) * 86_400
 }
 
-fn unix_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
-}
-
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
-    use crate::models::{GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage};
+    use crate::clock::ManualClock;
+    use crate::models::{
+        ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+        Priority,
+    };
 
     #[tokio::test]
     async fn limits_consume_and_reconcile() {
@@ -509,6 +708,109 @@ mod tests {
         assert!(snapshot.remaining_tokens_per_minute <= 860);
     }
 
+    #[tokio::test]
+    async fn concurrent_requests_across_shards_are_all_counted() {
+        let limiter = Arc::new(RateLimiter::in_memory());
+        let policy = RatePolicy {
+            requests_per_minute: 50,
+            tokens_per_minute: u64::MAX,
+            tokens_per_day: u64::MAX,
+        };
+        let tasks = (0..200)
+            .map(|task| {
+                let (limiter, policy) = (limiter.clone(), policy.clone());
+                tokio::spawn(async move {
+                    let key = format!("key-{}", task % 4);
+                    limiter.check_and_consume(&key, &policy, 1).await.is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut admitted = 0;
+        for task in tasks {
+            admitted += usize::from(task.await.expect("task"));
+        }
+        assert_eq!(admitted, 200);
+        for key in 0..4 {
+            assert!(limiter
+                .check_and_consume(&format!("key-{key}"), &policy, 1)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn windows_reset_when_the_limiter_clock_crosses_them() {
+        // One second before midnight UTC, so the next minute is also the next day.
+        let clock = Arc::new(ManualClock::at_unix_secs(20_000 * 86_400 - 1));
+        let limiter = RateLimiter::in_memory().with_clock(clock.clone());
+        let policy = RatePolicy {
+            requests_per_minute: 1,
+            tokens_per_minute: 1_000,
+            tokens_per_day: 100,
+        };
+
+        limiter
+            .check_and_consume("key-1", &policy, 100)
+            .await
+            .expect("first request fits");
+        assert!(matches!(
+            limiter.check_and_consume("key-1", &policy, 1).await,
+            Err(RateLimitError::RequestsPerMinute(_))
+        ));
+
+        clock.advance(Duration::from_secs(1));
+        let snapshot = limiter
+            .check_and_consume("key-1", &policy, 100)
+            .await
+            .expect("new minute and day");
+        assert_eq!(snapshot.remaining_tokens_per_day, 0);
+        assert_eq!(snapshot.reset_tokens_per_day, 20_001 * 86_400);
+    }
+
+    #[tokio::test]
+    async fn daily_quotas_roll_over_at_the_quota_day_boundary() {
+        // 06:00 at -05:00 is 11:00 UTC; start a second before it.
+        let quota_day = QuotaDay::parse("06:00", "-05:00").expect("valid quota day");
+        let boundary = 20_000 * 86_400 + 11 * 3_600;
+        let clock = Arc::new(ManualClock::at_unix_secs(boundary - 1));
+        let limiter = RateLimiter::in_memory()
+            .with_clock(clock.clone())
+            .with_quota_day(quota_day);
+        let policy = RatePolicy {
+            requests_per_minute: 10,
+            tokens_per_minute: 1_000,
+            tokens_per_day: 100,
+        };
+
+        let snapshot = limiter
+            .check_and_consume("key-1", &policy, 100)
+            .await
+            .expect("first request fits");
+        assert_eq!(snapshot.reset_tokens_per_day, boundary);
+        assert_eq!(snapshot.reset_requests_per_minute, boundary);
+        let Err(RateLimitError::TokensPerDay(snapshot)) =
+            limiter.check_and_consume("key-1", &policy, 1).await
+        else {
+            panic!("expected the day's quota to be spent");
+        };
+        assert_eq!(snapshot.reset_tokens_per_day, boundary);
+
+        // Midnight UTC passed hours ago; the quota day only ends at the boundary.
+        clock.advance(Duration::from_secs(1));
+        let snapshot = limiter
+            .check_and_consume("key-1", &policy, 100)
+            .await
+            .expect("new quota day");
+        assert_eq!(snapshot.reset_tokens_per_day, boundary + 86_400);
+
+        assert_eq!(
+            QuotaDay::parse("00:00", "+09:00"),
+            QuotaDay::parse("15:00", "UTC")
+        );
+        assert!(QuotaDay::parse("24:00", "UTC").is_err());
+        assert!(QuotaDay::parse("00:00", "Europe/Paris").is_err());
+    }
+
     #[test]
     fn estimate_tokens_uses_prompt_and_max_tokens() {
         let request = NormalizedChatRequest {
@@ -518,6 +820,7 @@ mod tests {
             messages: vec![NormalizedMessage {
                 role: MessageRole::User,
                 content: "hello world".to_owned(),
+                cache_control: None,
             }],
             generation: GenerationParams {
                 max_tokens: Some(20),
@@ -525,6 +828,11 @@ mod tests {
                 top_p: None,
             },
             stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
         };
 
         assert_eq!(estimate_request_tokens(&request), 22);
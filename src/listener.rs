@@ -0,0 +1,348 @@
+use std::{
+    env, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        self,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+    },
+    TlsAcceptor,
+};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_LISTEN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8080);
+
+/// Handshakes that take longer than this are dropped so idle sockets cannot hold accept slots.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate and key for TLS termination on the main listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1, or SEC1).
+    pub key_path: PathBuf,
+    /// How often to check the files for a renewed certificate; `None` loads them once.
+    pub reload_interval: Option<Duration>,
+}
+
+/// Where the data-plane listener binds and whether it terminates TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub addr: SocketAddr,
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    /// Reads `GATEWAY_LISTEN_ADDR` and the `GATEWAY_TLS_*` settings. Unlike most settings a bad
+    /// value fails startup: falling back could bind the wrong interface or serve plaintext.
+    pub fn from_env() -> Result<Self, String> {
+        let addr = match env::var("GATEWAY_LISTEN_ADDR") {
+            Ok(raw) if !raw.trim().is_empty() => raw
+                .trim()
+                .parse::<SocketAddr>()
+                .map_err(|_| format!("GATEWAY_LISTEN_ADDR `{raw}` is not an address:port"))?,
+            _ => DEFAULT_LISTEN_ADDR,
+        };
+        let path = |name| {
+            env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        let tls = match (path("GATEWAY_TLS_CERT_PATH"), path("GATEWAY_TLS_KEY_PATH")) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path,
+                key_path,
+                reload_interval: read_reload_interval()?,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(
+                    "GATEWAY_TLS_CERT_PATH and GATEWAY_TLS_KEY_PATH must be set together"
+                        .to_owned(),
+                )
+            }
+        };
+        Ok(Self { addr, tls })
+    }
+}
+
+fn read_reload_interval() -> Result<Option<Duration>, String> {
+    let Ok(raw) = env::var("GATEWAY_TLS_RELOAD_SECS") else {
+        return Ok(None);
+    };
+    match raw.trim().parse::<u64>() {
+        Ok(0) => Ok(None),
+        Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+        Err(_) => Err(format!(
+            "GATEWAY_TLS_RELOAD_SECS `{raw}` is not a number of seconds"
+        )),
+    }
+}
+
+/// Serves the certificate currently loaded; swapped in place when the files change.
+#[derive(Debug)]
+struct ReloadableCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    fn get(&self) -> Arc<CertifiedKey> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, key: CertifiedKey) {
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(key);
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.get())
+    }
+}
+
+fn load_certified_key(tls: &TlsConfig) -> Result<CertifiedKey, String> {
+    let cert_error = |error| format!("failed to read {}: {error}", tls.cert_path.display());
+    let chain = CertificateDer::pem_file_iter(&tls.cert_path)
+        .map_err(cert_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(cert_error)?;
+    if chain.is_empty() {
+        return Err(format!(
+            "{} contains no PEM certificates",
+            tls.cert_path.display()
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(&tls.key_path)
+        .map_err(|error| format!("failed to read {}: {error}", tls.key_path.display()))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|error| format!("unusable private key {}: {error}", tls.key_path.display()))?;
+    Ok(CertifiedKey::new(chain, signing_key))
+}
+
+fn modified(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&tls.cert_path).ok()?.modified().ok()?;
+    let key = std::fs::metadata(&tls.key_path).ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Polls the certificate files and swaps in a renewed pair; a pair that fails to load is
+/// logged and the previous certificate stays in service.
+struct CertReloader {
+    tls: TlsConfig,
+    cert: Arc<ReloadableCert>,
+    seen: Option<(SystemTime, SystemTime)>,
+}
+
+impl CertReloader {
+    fn reload_if_changed(&mut self) -> bool {
+        let current = modified(&self.tls);
+        if current.is_none() || current == self.seen {
+            return false;
+        }
+        match load_certified_key(&self.tls) {
+            Ok(key) => {
+                self.cert.set(key);
+                self.seen = current;
+                info!(cert = %self.tls.cert_path.display(), "reloaded TLS certificate");
+                true
+            }
+            Err(error) => {
+                warn!(error = %error, "failed to reload TLS certificate; keeping the current one");
+                false
+            }
+        }
+    }
+}
+
+/// Loads the certificate pair and builds the acceptor; with a reload interval it also starts
+/// the background task that picks up renewed certificates.
+pub fn tls_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let seen = modified(tls);
+    let cert = Arc::new(ReloadableCert {
+        current: RwLock::new(Arc::new(load_certified_key(tls)?)),
+    });
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|error| error.to_string())?
+    .with_no_client_auth()
+    .with_cert_resolver(cert.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    if let Some(interval) = tls.reload_interval {
+        let mut reloader = CertReloader {
+            tls: tls.clone(),
+            cert,
+            seen,
+        };
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                reloader.reload_if_changed();
+            }
+        });
+    }
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Serves `app` over TLS, with the same `ConnectInfo<SocketAddr>` the plaintext listener
+/// provides so admin CIDR checks keep working.
+pub async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+) -> io::Result<()> {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                // Usually descriptor exhaustion; back off instead of spinning.
+                warn!(error = %error, "failed to accept connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(error)) => {
+                        debug!(client = %remote, error = %error, "TLS handshake failed");
+                        return;
+                    }
+                    Err(_) => {
+                        debug!(client = %remote, "TLS handshake timed out");
+                        return;
+                    }
+                };
+            let service = TowerToHyperService::new(app.layer(Extension(ConnectInfo(remote))));
+            if let Err(error) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(client = %remote, error = %error, "connection closed with error");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, RwLock},
+        time::{Duration, SystemTime},
+    };
+
+    use axum::{routing::get, Router};
+
+    use super::{load_certified_key, modified, CertReloader, ReloadableCert, TlsConfig};
+
+    fn fixture_tls() -> TlsConfig {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            reload_interval: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_https_with_configured_certificate() {
+        let acceptor = super::tls_acceptor(&fixture_tls()).expect("tls acceptor");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let port = listener.local_addr().expect("local addr").port();
+        let app = Router::new().route("/healthz", get(|| async { "ok" }));
+        tokio::spawn(super::serve_tls(listener, acceptor, app));
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .expect("client");
+        let body = client
+            .get(format!("https://localhost:{port}/healthz"))
+            .send()
+            .await
+            .expect("https request")
+            .text()
+            .await
+            .expect("body");
+        assert_eq!(body, "ok");
+    }
+
+    #[test]
+    fn reloads_certificate_when_files_change() {
+        let dir = std::env::temp_dir().join(format!("gateway-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let fixture = fixture_tls();
+        let tls = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            reload_interval: Some(Duration::from_secs(1)),
+        };
+        let copy = |from: &PathBuf, to: &PathBuf| std::fs::copy(from, to).expect("copy fixture");
+        copy(&fixture.cert_path, &tls.cert_path);
+        copy(&fixture.key_path, &tls.key_path);
+
+        let cert = Arc::new(ReloadableCert {
+            current: RwLock::new(Arc::new(load_certified_key(&tls).expect("load"))),
+        });
+        let original = cert.get();
+        let mut reloader = CertReloader {
+            tls: tls.clone(),
+            cert: cert.clone(),
+            seen: modified(&tls),
+        };
+        assert!(!reloader.reload_if_changed());
+
+        std::fs::write(&tls.cert_path, "not a certificate").expect("write");
+        touch(&tls.cert_path, 60);
+        assert!(!reloader.reload_if_changed());
+        assert!(Arc::ptr_eq(&cert.get(), &original));
+
+        copy(&fixture.cert_path, &tls.cert_path);
+        touch(&tls.cert_path, 120);
+        assert!(reloader.reload_if_changed());
+        assert!(!Arc::ptr_eq(&cert.get(), &original));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn touch(path: &Path, secs_ahead: u64) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .expect("open")
+            .set_modified(SystemTime::now() + Duration::from_secs(secs_ahead))
+            .expect("set mtime");
+    }
+}
@@ -1,25 +1,130 @@
-use std::net::SocketAddr;
+use std::{env, net::SocketAddr, path::PathBuf, process::ExitCode};
 
+use clap::{Parser, ValueEnum};
+use rust_llm_inference_gateway::{
+    config::{effective_settings, GatewayConfig},
+    listener::{self, ListenerConfig},
+};
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// OpenAI-compatible LLM inference gateway. Every option can also be set through the
+/// environment; command-line values take precedence over both it and the config file.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML config file whose settings become defaults for the environment.
+    #[arg(long, env = "GATEWAY_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Data-plane bind address, overriding `GATEWAY_LISTEN_ADDR`.
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+    #[arg(long, value_enum, env = "GATEWAY_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Load and check the configuration, then exit.
+    #[arg(long)]
+    validate_config: bool,
+    /// Print the gateway settings in effect, with credentials redacted, then exit.
+    #[arg(long)]
+    print_effective_config: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.log_format == LogFormat::Json;
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "info,rust_llm_inference_gateway=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(json.then(|| tracing_subscriber::fmt::layer().json()))
+        .with((!json).then(tracing_subscriber::fmt::layer))
         .init();
 
+    // The environment is only written before the runtime starts its worker threads, since
+    // changing it while other threads may read it is unsound.
+    let result = apply_env(&cli).and_then(|()| {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(run(cli))
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Writes the config file's settings, as defaults, and the command-line overrides into the
+/// environment, so they land before any component reads its configuration.
+fn apply_env(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = &cli.config {
+        GatewayConfig::from_file(path)?.apply_env_defaults();
+    }
+    if let Some(addr) = cli.listen {
+        env::set_var("GATEWAY_LISTEN_ADDR", addr.to_string());
+    }
+    Ok(())
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let listener_config = ListenerConfig::from_env().map_err(std::io::Error::other)?;
+
+    if cli.print_effective_config {
+        for (name, value) in effective_settings(env::vars()) {
+            println!("{name}={value}");
+        }
+    }
+    if cli.validate_config {
+        println!("configuration is valid");
+    }
+    if cli.print_effective_config || cli.validate_config {
+        return Ok(());
+    }
+    if let Some(path) = &cli.config {
+        info!(path = %path.display(), "gateway config file loaded");
+    }
+
     let state = rust_llm_inference_gateway::build_state()?;
+    if let Some(admin_addr) = state.admin.listen_addr {
+        let admin = rust_llm_inference_gateway::build_admin_app(state.clone());
+        let listener = tokio::net::TcpListener::bind(admin_addr).await?;
+        info!(addr = %admin_addr, "admin endpoints listening");
+        tokio::spawn(async move {
+            let service = admin.into_make_service_with_connect_info::<SocketAddr>();
+            if let Err(error) = axum::serve(listener, service).await {
+                tracing::error!(error = %error, "admin listener stopped");
+            }
+        });
+    }
     let app = rust_llm_inference_gateway::build_app(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let addr = listener_config.addr;
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    info!(%addr, "gateway listening");
-
-    axum::serve(listener, app).await?;
+    match &listener_config.tls {
+        Some(tls) => {
+            let acceptor = listener::tls_acceptor(tls).map_err(std::io::Error::other)?;
+            info!(%addr, cert = %tls.cert_path.display(), "gateway listening with TLS");
+            listener::serve_tls(listener, acceptor, app).await?;
+        }
+        None => {
+            info!(%addr, "gateway listening");
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?;
+        }
+    }
     Ok(())
 }
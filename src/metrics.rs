@@ -1,27 +1,205 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use axum::http::StatusCode;
 use prometheus::{
-    opts, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Registry, TextEncoder,
+    exponential_buckets, opts, CounterVec, Encoder, Histogram, HistogramOpts, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
 };
 
+use tracing::warn;
+
 use crate::models::Usage;
 
+/// Upper bounds (seconds) of the request latency histogram. Prometheus' defaults stop at 10s,
+/// which lumps every long generation into `+Inf`.
+pub const DEFAULT_LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    /// Distinct key tiers given their own `tier` label on token counters; later tiers share
+    /// `other`. `0` leaves tiers out of the label set (`tier="all"`).
+    pub max_tier_labels: usize,
+    /// Distinct tenants given their own `tenant` label on the per-tenant counters; later
+    /// tenants share `other`. `0` collapses them into `tenant="all"`.
+    pub max_tenant_labels: usize,
+    /// Buckets for `gateway_http_request_duration_seconds`.
+    pub latency_buckets: Vec<f64>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            max_tier_labels: 0,
+            max_tenant_labels: 0,
+            latency_buckets: DEFAULT_LATENCY_BUCKETS.to_vec(),
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_tier_labels: env::var("GATEWAY_METRICS_MAX_TIER_LABELS")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(0),
+            max_tenant_labels: env::var("GATEWAY_METRICS_MAX_TENANT_LABELS")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(0),
+            latency_buckets: read_latency_buckets(),
+        }
+    }
+}
+
+/// Parses comma-separated bucket bounds in seconds; they must be positive and increasing.
+pub fn parse_buckets(raw: &str) -> Result<Vec<f64>, String> {
+    let buckets = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<f64>()
+                .ok()
+                .filter(|bound| bound.is_finite() && *bound > 0.0)
+                .ok_or_else(|| format!("bucket `{entry}` is not a positive number"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if buckets.is_empty() {
+        return Err("at least one bucket is required".to_owned());
+    }
+    if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("buckets must be strictly increasing".to_owned());
+    }
+    Ok(buckets)
+}
+
+fn read_latency_buckets() -> Vec<f64> {
+    let Ok(raw) = env::var("GATEWAY_METRICS_LATENCY_BUCKETS") else {
+        return DEFAULT_LATENCY_BUCKETS.to_vec();
+    };
+    parse_buckets(&raw).unwrap_or_else(|error| {
+        warn!(error = %error, "invalid GATEWAY_METRICS_LATENCY_BUCKETS, using defaults");
+        DEFAULT_LATENCY_BUCKETS.to_vec()
+    })
+}
+
 #[derive(Clone)]
 pub struct AppMetrics {
     registry: Registry,
+    tier_labels: Arc<LabelCap>,
+    tenant_labels: Arc<LabelCap>,
+    latency_buckets: Arc<[f64]>,
+    latency_exemplars: Arc<Mutex<HashMap<ExemplarSlot, Exemplar>>>,
     request_total: IntCounterVec,
     request_duration_seconds: HistogramVec,
     inflight_requests: IntGauge,
     backend_errors_total: IntCounterVec,
     tokens_total: IntCounterVec,
+    cache_events_total: IntCounterVec,
+    cache_payload_bytes: HistogramVec,
+    batch_size: HistogramVec,
+    batch_flushes_total: IntCounterVec,
+    batch_queue_wait_seconds: HistogramVec,
+    batch_queue_depth: IntGauge,
+    load_shed_total: IntCounterVec,
+    coalesce_history_bytes: IntGauge,
+    coalesce_history_truncated_total: IntCounter,
+    coalesce_requests_total: IntCounterVec,
+    coalesce_replay_chunks: Histogram,
+    coalesce_subscriber_lag_chunks: Histogram,
+    coalesce_slow_subscribers_total: IntCounter,
+    coalesce_bytes_saved_total: IntCounterVec,
+    queue_depth: IntGaugeVec,
+    queue_wait_seconds: HistogramVec,
+    pool_capacity: IntGaugeVec,
+    pool_in_use: IntGaugeVec,
+    stream_ttft_seconds: HistogramVec,
+    stream_inter_chunk_seconds: HistogramVec,
+    stream_tokens_per_second: HistogramVec,
+    usage_records_total: IntCounterVec,
+    cost_usd_total: CounterVec,
+    stream_disconnects_total: IntCounterVec,
+    stream_abandoned_tokens_total: IntCounterVec,
+    request_rejections_total: IntCounterVec,
+    upstream_rate_limited_total: IntCounterVec,
+    backend_retries_total: IntCounterVec,
+    backend_spillover_total: IntCounterVec,
+    backend_response_problems_total: IntCounterVec,
+    handler_panics_total: IntCounter,
+    tenant_requests_total: IntCounterVec,
+    tenant_tokens_total: IntCounterVec,
+    insufficient_credits_total: IntCounterVec,
+    redis_up: IntGauge,
+    experiment_requests_total: IntCounterVec,
+    experiment_duration_seconds: HistogramVec,
+    experiment_tokens_total: IntCounterVec,
 }
 
 pub struct InflightGuard<'a> {
     metrics: &'a AppMetrics,
 }
 
+/// Admits the first `max` distinct values of a label; later values collapse into `other`.
+struct LabelCap {
+    max: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl LabelCap {
+    /// Borrows the label to use for `value`; only the first sighting of a value allocates.
+    fn label<'a>(&self, value: Option<&'a str>) -> &'a str {
+        if self.max == 0 {
+            return "all";
+        }
+        let value = value.unwrap_or("none");
+        let mut seen = self
+            .seen
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if seen.contains(value) {
+            return value;
+        }
+        if seen.len() < self.max {
+            seen.insert(value.to_owned());
+            return value;
+        }
+        "other"
+    }
+}
+
+/// A request latency bucket: path, method, stream label, and the bucket's index, with the
+/// `+Inf` bucket one past the last configured bound.
+type ExemplarSlot = (String, String, &'static str, usize);
+
+/// The latest traced observation that fell into a latency bucket.
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+/// Counts one occupied slot in a concurrency pool until dropped.
+pub struct PoolUsage {
+    in_use: IntGauge,
+}
+
 impl AppMetrics {
     pub fn new() -> Self {
+        Self::with_config(MetricsConfig::default())
+    }
+
+    pub fn with_config(config: MetricsConfig) -> Self {
         let registry = Registry::new();
 
         let request_total = IntCounterVec::new(
@@ -37,7 +215,8 @@ impl AppMetrics {
             HistogramOpts::new(
                 "gateway_http_request_duration_seconds",
                 "HTTP request latency in seconds",
-            ),
+            )
+            .buckets(config.latency_buckets.clone()),
             &["path", "method", "stream"],
         )
         .expect("valid request_duration_seconds metric");
@@ -60,12 +239,341 @@ impl AppMetrics {
         let tokens_total = IntCounterVec::new(
             opts!(
                 "gateway_tokens_total",
-                "Token accounting aggregated by type"
+                "Token accounting by type, model, and key tier"
             ),
-            &["kind"],
+            &["kind", "model", "tier"],
         )
         .expect("valid tokens_total metric");
 
+        let cache_events_total = IntCounterVec::new(
+            opts!(
+                "gateway_cache_events_total",
+                "Response cache events by storage backend and event"
+            ),
+            &["backend", "event"],
+        )
+        .expect("valid cache_events_total metric");
+
+        let cache_payload_bytes = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_cache_payload_bytes",
+                "Size of payloads written to the response cache",
+            )
+            .buckets(exponential_buckets(256.0, 4.0, 8).expect("valid cache size buckets")),
+            &["backend"],
+        )
+        .expect("valid cache_payload_bytes metric");
+
+        let batch_size = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_batch_size",
+                "Number of requests per flushed micro-batch",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 8).expect("valid batch size buckets")),
+            &["reason"],
+        )
+        .expect("valid batch_size metric");
+
+        let batch_flushes_total = IntCounterVec::new(
+            opts!(
+                "gateway_batch_flushes_total",
+                "Micro-batch flushes by reason (size, deadline, priority, disabled, shutdown)"
+            ),
+            &["reason"],
+        )
+        .expect("valid batch_flushes_total metric");
+
+        let batch_queue_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_batch_queue_wait_seconds",
+                "Time a request waits in the batcher before backend execution",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 12).expect("valid batch queue wait buckets")),
+            &["priority"],
+        )
+        .expect("valid batch_queue_wait_seconds metric");
+
+        let batch_queue_depth = IntGauge::new(
+            "gateway_batch_queue_depth",
+            "Requests queued in the batcher and not yet executing",
+        )
+        .expect("valid batch_queue_depth metric");
+
+        let load_shed_total = IntCounterVec::new(
+            opts!(
+                "gateway_load_shed_total",
+                "Requests rejected by admission control as overloaded"
+            ),
+            &["stage"],
+        )
+        .expect("valid load_shed_total metric");
+
+        let coalesce_history_bytes = IntGauge::new(
+            "gateway_coalesce_stream_history_bytes",
+            "Approximate bytes of stream history retained for coalescing late joiners",
+        )
+        .expect("valid coalesce_history_bytes metric");
+
+        let coalesce_history_truncated_total = IntCounter::new(
+            "gateway_coalesce_stream_history_truncated_total",
+            "Coalesced streams that hit the history cap and closed to late joiners",
+        )
+        .expect("valid coalesce_history_truncated_total metric");
+
+        let coalesce_requests_total = IntCounterVec::new(
+            opts!(
+                "gateway_coalesce_requests_total",
+                "Coalescing participants by mode (one_shot, stream) and role (leader, follower)"
+            ),
+            &["mode", "role"],
+        )
+        .expect("valid coalesce_requests_total metric");
+
+        let coalesce_replay_chunks = Histogram::with_opts(
+            HistogramOpts::new(
+                "gateway_coalesce_stream_replay_chunks",
+                "History chunks replayed to each stream follower on join",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 12).expect("valid replay chunk buckets")),
+        )
+        .expect("valid coalesce_replay_chunks metric");
+
+        let coalesce_subscriber_lag_chunks = Histogram::with_opts(
+            HistogramOpts::new(
+                "gateway_coalesce_stream_subscriber_lag_chunks",
+                "Chunks a stream subscriber had not yet read when the next one was published",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 12).expect("valid lag chunk buckets")),
+        )
+        .expect("valid coalesce_subscriber_lag_chunks metric");
+
+        let coalesce_slow_subscribers_total = IntCounter::new(
+            "gateway_coalesce_stream_slow_subscribers_disconnected_total",
+            "Stream subscribers disconnected for filling their buffer",
+        )
+        .expect("valid coalesce_slow_subscribers_total metric");
+
+        let coalesce_bytes_saved_total = IntCounterVec::new(
+            opts!(
+                "gateway_coalesce_bytes_saved_total",
+                "Completion bytes delivered to followers without their own backend call"
+            ),
+            &["mode"],
+        )
+        .expect("valid coalesce_bytes_saved_total metric");
+
+        let queue_depth = IntGaugeVec::new(
+            opts!(
+                "gateway_queue_depth",
+                "Requests waiting in a scheduler queue (admission, fair, model_pool, stream)"
+            ),
+            &["queue"],
+        )
+        .expect("valid queue_depth metric");
+
+        let queue_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_queue_wait_seconds",
+                "Time a request spends in a scheduler queue before it is granted a slot",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 14).expect("valid queue wait buckets")),
+            &["queue"],
+        )
+        .expect("valid queue_wait_seconds metric");
+
+        let pool_capacity = IntGaugeVec::new(
+            opts!(
+                "gateway_pool_capacity",
+                "Configured slots per concurrency pool"
+            ),
+            &["pool"],
+        )
+        .expect("valid pool_capacity metric");
+
+        let pool_in_use = IntGaugeVec::new(
+            opts!("gateway_pool_in_use", "Occupied slots per concurrency pool"),
+            &["pool"],
+        )
+        .expect("valid pool_in_use metric");
+
+        let stream_ttft_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_stream_ttft_seconds",
+                "Time from dispatching a stream to its first content chunk",
+            )
+            .buckets(exponential_buckets(0.01, 2.0, 12).expect("valid ttft buckets")),
+            &["backend", "model"],
+        )
+        .expect("valid stream_ttft_seconds metric");
+
+        let stream_inter_chunk_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_stream_inter_chunk_seconds",
+                "Gap between consecutive content chunks of a stream",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 12).expect("valid inter-chunk buckets")),
+            &["backend", "model"],
+        )
+        .expect("valid stream_inter_chunk_seconds metric");
+
+        let stream_tokens_per_second = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_stream_tokens_per_second",
+                "Completion tokens per second after the first content chunk of a stream",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 10).expect("valid tokens per second buckets")),
+            &["backend", "model"],
+        )
+        .expect("valid stream_tokens_per_second metric");
+
+        let usage_records_total = IntCounterVec::new(
+            opts!(
+                "gateway_usage_records_total",
+                "Usage records handled by the usage sink (written, failed, dropped)"
+            ),
+            &["outcome"],
+        )
+        .expect("valid usage_records_total metric");
+
+        let cost_usd_total = CounterVec::new(
+            opts!(
+                "gateway_cost_usd_total",
+                "Upstream spend in USD from priced token usage"
+            ),
+            &["model", "backend"],
+        )
+        .expect("valid cost_usd_total metric");
+
+        let stream_disconnects_total = IntCounterVec::new(
+            opts!(
+                "gateway_stream_disconnects_total",
+                "SSE clients that disconnected before their stream completed"
+            ),
+            &["backend"],
+        )
+        .expect("valid stream_disconnects_total metric");
+
+        let stream_abandoned_tokens_total = IntCounterVec::new(
+            opts!(
+                "gateway_stream_abandoned_tokens_total",
+                "Completion tokens generated after every client of a stream had disconnected"
+            ),
+            &["backend"],
+        )
+        .expect("valid stream_abandoned_tokens_total metric");
+
+        let request_rejections_total = IntCounterVec::new(
+            opts!(
+                "gateway_request_rejections_total",
+                "Chat requests rejected before parsing, by reason"
+            ),
+            &["reason"],
+        )
+        .expect("valid request_rejections_total metric");
+
+        let upstream_rate_limited_total = IntCounterVec::new(
+            opts!(
+                "gateway_upstream_rate_limited_total",
+                "Backend calls the upstream provider answered with 429, by endpoint"
+            ),
+            &["backend"],
+        )
+        .expect("valid upstream_rate_limited_total metric");
+
+        let backend_retries_total = IntCounterVec::new(
+            opts!(
+                "gateway_backend_retries_total",
+                "One-shot backend calls retried after a transient failure, by failing endpoint and class"
+            ),
+            &["backend", "class"],
+        )
+        .expect("valid backend_retries_total metric");
+
+        let backend_spillover_total = IntCounterVec::new(
+            opts!(
+                "gateway_backend_spillover_total",
+                "Backend calls sent to a spillover endpoint because the preferred endpoints were at capacity, by spillover endpoint"
+            ),
+            &["backend"],
+        )
+        .expect("valid backend_spillover_total metric");
+
+        let backend_response_problems_total = IntCounterVec::new(
+            opts!(
+                "gateway_backend_response_problems_total",
+                "Malformed one-shot backend responses, by endpoint, problem, and whether the gateway repaired or rejected them"
+            ),
+            &["action", "backend", "problem"],
+        )
+        .expect("valid backend_response_problems_total metric");
+
+        let handler_panics_total = IntCounter::new(
+            "gateway_handler_panics_total",
+            "Requests whose handler panicked and were answered with a 500",
+        )
+        .expect("valid handler_panics_total metric");
+
+        let tenant_requests_total = IntCounterVec::new(
+            opts!(
+                "gateway_tenant_requests_total",
+                "Chat requests admitted past authentication and quotas, by tenant"
+            ),
+            &["tenant"],
+        )
+        .expect("valid tenant_requests_total metric");
+
+        let tenant_tokens_total = IntCounterVec::new(
+            opts!(
+                "gateway_tenant_tokens_total",
+                "Token accounting by tenant and type"
+            ),
+            &["tenant", "kind"],
+        )
+        .expect("valid tenant_tokens_total metric");
+
+        let insufficient_credits_total = IntCounterVec::new(
+            opts!(
+                "gateway_insufficient_credits_total",
+                "Chat requests refused because the tenant's prepaid credits ran out"
+            ),
+            &["tenant"],
+        )
+        .expect("valid insufficient_credits_total metric");
+
+        let redis_up = IntGauge::new(
+            "gateway_redis_up",
+            "Whether the shared Redis connection is established (1) or failing (0)",
+        )
+        .expect("valid redis_up metric");
+
+        let experiment_requests_total = IntCounterVec::new(
+            opts!(
+                "gateway_experiment_requests_total",
+                "Chat requests assigned to each A/B experiment variant"
+            ),
+            &["experiment", "variant"],
+        )
+        .expect("valid experiment_requests_total metric");
+
+        let experiment_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_experiment_duration_seconds",
+                "Latency of completed chat requests by A/B experiment variant",
+            )
+            .buckets(config.latency_buckets.clone()),
+            &["experiment", "variant"],
+        )
+        .expect("valid experiment_duration_seconds metric");
+
+        let experiment_tokens_total = IntCounterVec::new(
+            opts!(
+                "gateway_experiment_tokens_total",
+                "Token accounting by A/B experiment variant and type"
+            ),
+            &["experiment", "variant", "kind"],
+        )
+        .expect("valid experiment_tokens_total metric");
+
         registry
             .register(Box::new(request_total.clone()))
             .expect("register request_total");
@@ -81,14 +589,176 @@ impl AppMetrics {
         registry
             .register(Box::new(tokens_total.clone()))
             .expect("register tokens_total");
+        registry
+            .register(Box::new(cache_events_total.clone()))
+            .expect("register cache_events_total");
+        registry
+            .register(Box::new(cache_payload_bytes.clone()))
+            .expect("register cache_payload_bytes");
+        registry
+            .register(Box::new(batch_size.clone()))
+            .expect("register batch_size");
+        registry
+            .register(Box::new(batch_flushes_total.clone()))
+            .expect("register batch_flushes_total");
+        registry
+            .register(Box::new(batch_queue_wait_seconds.clone()))
+            .expect("register batch_queue_wait_seconds");
+        registry
+            .register(Box::new(batch_queue_depth.clone()))
+            .expect("register batch_queue_depth");
+        registry
+            .register(Box::new(load_shed_total.clone()))
+            .expect("register load_shed_total");
+        registry
+            .register(Box::new(coalesce_history_bytes.clone()))
+            .expect("register coalesce_history_bytes");
+        registry
+            .register(Box::new(coalesce_history_truncated_total.clone()))
+            .expect("register coalesce_history_truncated_total");
+        registry
+            .register(Box::new(coalesce_requests_total.clone()))
+            .expect("register coalesce_requests_total");
+        registry
+            .register(Box::new(coalesce_replay_chunks.clone()))
+            .expect("register coalesce_replay_chunks");
+        registry
+            .register(Box::new(coalesce_subscriber_lag_chunks.clone()))
+            .expect("register coalesce_subscriber_lag_chunks");
+        registry
+            .register(Box::new(coalesce_slow_subscribers_total.clone()))
+            .expect("register coalesce_slow_subscribers_total");
+        registry
+            .register(Box::new(coalesce_bytes_saved_total.clone()))
+            .expect("register coalesce_bytes_saved_total");
+        registry
+            .register(Box::new(queue_depth.clone()))
+            .expect("register queue_depth");
+        registry
+            .register(Box::new(queue_wait_seconds.clone()))
+            .expect("register queue_wait_seconds");
+        registry
+            .register(Box::new(pool_capacity.clone()))
+            .expect("register pool_capacity");
+        registry
+            .register(Box::new(pool_in_use.clone()))
+            .expect("register pool_in_use");
+        registry
+            .register(Box::new(stream_ttft_seconds.clone()))
+            .expect("register stream_ttft_seconds");
+        registry
+            .register(Box::new(stream_inter_chunk_seconds.clone()))
+            .expect("register stream_inter_chunk_seconds");
+        registry
+            .register(Box::new(stream_tokens_per_second.clone()))
+            .expect("register stream_tokens_per_second");
+        registry
+            .register(Box::new(usage_records_total.clone()))
+            .expect("register usage_records_total");
+        registry
+            .register(Box::new(cost_usd_total.clone()))
+            .expect("register cost_usd_total");
+        registry
+            .register(Box::new(stream_disconnects_total.clone()))
+            .expect("register stream_disconnects_total");
+        registry
+            .register(Box::new(stream_abandoned_tokens_total.clone()))
+            .expect("register stream_abandoned_tokens_total");
+        registry
+            .register(Box::new(request_rejections_total.clone()))
+            .expect("register request_rejections_total");
+        registry
+            .register(Box::new(upstream_rate_limited_total.clone()))
+            .expect("register upstream_rate_limited_total");
+        registry
+            .register(Box::new(backend_retries_total.clone()))
+            .expect("register backend_retries_total");
+        registry
+            .register(Box::new(backend_spillover_total.clone()))
+            .expect("register backend_spillover_total");
+        registry
+            .register(Box::new(backend_response_problems_total.clone()))
+            .expect("register backend_response_problems_total");
+        registry
+            .register(Box::new(handler_panics_total.clone()))
+            .expect("register handler_panics_total");
+        registry
+            .register(Box::new(tenant_requests_total.clone()))
+            .expect("register tenant_requests_total");
+        registry
+            .register(Box::new(tenant_tokens_total.clone()))
+            .expect("register tenant_tokens_total");
+        registry
+            .register(Box::new(insufficient_credits_total.clone()))
+            .expect("register insufficient_credits_total");
+        registry
+            .register(Box::new(redis_up.clone()))
+            .expect("register redis_up");
+        registry
+            .register(Box::new(experiment_requests_total.clone()))
+            .expect("register experiment_requests_total");
+        registry
+            .register(Box::new(experiment_duration_seconds.clone()))
+            .expect("register experiment_duration_seconds");
+        registry
+            .register(Box::new(experiment_tokens_total.clone()))
+            .expect("register experiment_tokens_total");
 
         Self {
             registry,
+            latency_buckets: config.latency_buckets.clone().into(),
+            latency_exemplars: Arc::default(),
+            tier_labels: Arc::new(LabelCap {
+                max: config.max_tier_labels,
+                seen: Mutex::new(HashSet::new()),
+            }),
+            tenant_labels: Arc::new(LabelCap {
+                max: config.max_tenant_labels,
+                seen: Mutex::new(HashSet::new()),
+            }),
             request_total,
             request_duration_seconds,
             inflight_requests,
             backend_errors_total,
             tokens_total,
+            cache_events_total,
+            cache_payload_bytes,
+            batch_size,
+            batch_flushes_total,
+            batch_queue_wait_seconds,
+            batch_queue_depth,
+            load_shed_total,
+            coalesce_history_bytes,
+            coalesce_history_truncated_total,
+            coalesce_requests_total,
+            coalesce_replay_chunks,
+            coalesce_subscriber_lag_chunks,
+            coalesce_slow_subscribers_total,
+            coalesce_bytes_saved_total,
+            queue_depth,
+            queue_wait_seconds,
+            pool_capacity,
+            pool_in_use,
+            stream_ttft_seconds,
+            stream_inter_chunk_seconds,
+            stream_tokens_per_second,
+            usage_records_total,
+            cost_usd_total,
+            stream_disconnects_total,
+            stream_abandoned_tokens_total,
+            request_rejections_total,
+            upstream_rate_limited_total,
+            backend_retries_total,
+            backend_spillover_total,
+            backend_response_problems_total,
+            handler_panics_total,
+            tenant_requests_total,
+            tenant_tokens_total,
+            insufficient_credits_total,
+            redis_up,
+            experiment_requests_total,
+            experiment_duration_seconds,
+            experiment_tokens_total,
         }
     }
 
@@ -106,29 +776,298 @@ impl AppMetrics {
         duration: Duration,
     ) {
         let stream_label = if stream { "true" } else { "false" };
-        let status_label = status.to_string();
+        // `StatusCode::as_str` borrows from a static table, so the label costs no allocation.
+        let status_code = StatusCode::from_u16(status);
+        let status_label = status_code
+            .as_ref()
+            .map_or("unknown", |status_code| status_code.as_str());
         self.request_total
-            .with_label_values(&[path, method, &status_label, stream_label])
+            .with_label_values(&[path, method, status_label, stream_label])
             .inc();
         self.request_duration_seconds
             .with_label_values(&[path, method, stream_label])
             .observe(duration.as_secs_f64());
     }
 
+    /// Remembers `trace_id` as the exemplar of the latency bucket `duration` falls into, for
+    /// the next OpenMetrics scrape. Each bucket keeps only its latest exemplar.
+    pub fn observe_latency_exemplar(
+        &self,
+        path: &str,
+        method: &str,
+        stream: bool,
+        duration: Duration,
+        trace_id: &str,
+    ) {
+        let value = duration.as_secs_f64();
+        let bucket = self
+            .latency_buckets
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.latency_buckets.len());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        let slot = (
+            path.to_owned(),
+            method.to_owned(),
+            if stream { "true" } else { "false" },
+            bucket,
+        );
+        self.latency_exemplars
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                slot,
+                Exemplar {
+                    trace_id: trace_id.to_owned(),
+                    value,
+                    timestamp,
+                },
+            );
+    }
+
     pub fn observe_backend_error(&self, stage: &str) {
         self.backend_errors_total.with_label_values(&[stage]).inc();
     }
 
-    pub fn observe_usage(&self, usage: &Usage) {
-        self.tokens_total
-            .with_label_values(&["prompt"])
-            .inc_by(usage.prompt_tokens as u64);
-        self.tokens_total
-            .with_label_values(&["completion"])
-            .inc_by(usage.completion_tokens as u64);
-        self.tokens_total
-            .with_label_values(&["total"])
-            .inc_by(usage.total_tokens as u64);
+    pub fn observe_request_rejection(&self, reason: &str) {
+        self.request_rejections_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn observe_backend_spillover(&self, backend: &str) {
+        self.backend_spillover_total
+            .with_label_values(&[backend])
+            .inc();
+    }
+
+    /// `action` is `repaired` or `rejected`.
+    pub fn observe_backend_response_problem(&self, backend: &str, problem: &str, action: &str) {
+        self.backend_response_problems_total
+            .with_label_values(&[action, backend, problem])
+            .inc();
+    }
+
+    pub fn observe_upstream_rate_limited(&self, backend: &str, count: u64) {
+        self.upstream_rate_limited_total
+            .with_label_values(&[backend])
+            .inc_by(count);
+    }
+
+    pub fn observe_backend_retry(&self, backend: &str, class: &str, count: u64) {
+        self.backend_retries_total
+            .with_label_values(&[backend, class])
+            .inc_by(count);
+    }
+
+    pub fn observe_handler_panic(&self) {
+        self.handler_panics_total.inc();
+    }
+
+    pub fn observe_cache_event(&self, backend: &str, event: &str, count: u64) {
+        self.cache_events_total
+            .with_label_values(&[backend, event])
+            .inc_by(count);
+    }
+
+    pub fn observe_cache_write(&self, backend: &str, size_bytes: usize) {
+        self.observe_cache_event(backend, "write", 1);
+        self.cache_payload_bytes
+            .with_label_values(&[backend])
+            .observe(size_bytes as f64);
+    }
+
+    pub fn observe_batch_flush(&self, reason: &str, size: usize) {
+        self.batch_flushes_total.with_label_values(&[reason]).inc();
+        self.batch_size
+            .with_label_values(&[reason])
+            .observe(size as f64);
+    }
+
+    pub fn observe_batch_queue_wait(&self, priority: &str, wait: Duration) {
+        self.batch_queue_wait_seconds
+            .with_label_values(&[priority])
+            .observe(wait.as_secs_f64());
+    }
+
+    pub fn adjust_batch_queue_depth(&self, delta: i64) {
+        self.batch_queue_depth.add(delta);
+    }
+
+    pub fn observe_load_shed(&self, stage: &str) {
+        self.load_shed_total.with_label_values(&[stage]).inc();
+    }
+
+    pub fn adjust_coalesce_history_bytes(&self, delta: i64) {
+        self.coalesce_history_bytes.add(delta);
+    }
+
+    pub fn observe_coalesce_history_truncated(&self) {
+        self.coalesce_history_truncated_total.inc();
+    }
+
+    pub fn observe_coalesce_request(&self, mode: &str, role: &str) {
+        self.coalesce_requests_total
+            .with_label_values(&[mode, role])
+            .inc();
+    }
+
+    pub fn observe_coalesce_replay(&self, chunks: usize) {
+        self.coalesce_replay_chunks.observe(chunks as f64);
+    }
+
+    pub fn observe_coalesce_subscriber_lag(&self, chunks: usize) {
+        self.coalesce_subscriber_lag_chunks.observe(chunks as f64);
+    }
+
+    pub fn observe_coalesce_slow_subscriber(&self) {
+        self.coalesce_slow_subscribers_total.inc();
+    }
+
+    pub fn observe_coalesce_bytes_saved(&self, mode: &str, bytes: usize) {
+        if bytes > 0 {
+            self.coalesce_bytes_saved_total
+                .with_label_values(&[mode])
+                .inc_by(bytes as u64);
+        }
+    }
+
+    pub fn adjust_queue_depth(&self, queue: &str, delta: i64) {
+        self.queue_depth.with_label_values(&[queue]).add(delta);
+    }
+
+    pub fn observe_queue_wait(&self, queue: &str, wait: Duration) {
+        self.queue_wait_seconds
+            .with_label_values(&[queue])
+            .observe(wait.as_secs_f64());
+    }
+
+    pub fn set_pool_capacity(&self, pool: &str, capacity: usize) {
+        self.pool_capacity
+            .with_label_values(&[pool])
+            .set(capacity as i64);
+    }
+
+    pub fn pool_usage(&self, pool: &str) -> PoolUsage {
+        let in_use = self.pool_in_use.with_label_values(&[pool]);
+        in_use.inc();
+        PoolUsage { in_use }
+    }
+
+    pub fn observe_stream_ttft(&self, backend: &str, model: &str, ttft: Duration) {
+        self.stream_ttft_seconds
+            .with_label_values(&[backend, model])
+            .observe(ttft.as_secs_f64());
+    }
+
+    pub fn observe_stream_inter_chunk(&self, backend: &str, model: &str, gap: Duration) {
+        self.stream_inter_chunk_seconds
+            .with_label_values(&[backend, model])
+            .observe(gap.as_secs_f64());
+    }
+
+    pub fn observe_stream_tokens_per_second(&self, backend: &str, model: &str, rate: f64) {
+        self.stream_tokens_per_second
+            .with_label_values(&[backend, model])
+            .observe(rate);
+    }
+
+    pub fn observe_usage_records(&self, outcome: &str, count: usize) {
+        self.usage_records_total
+            .with_label_values(&[outcome])
+            .inc_by(count as u64);
+    }
+
+    pub fn observe_usage(&self, model: &str, tier: Option<&str>, usage: &Usage) {
+        let tier = self.tier_labels.label(tier);
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+            ("total", usage.total_tokens),
+        ] {
+            self.tokens_total
+                .with_label_values(&[kind, model, tier])
+                .inc_by(tokens as u64);
+        }
+    }
+
+    pub fn observe_tenant_request(&self, tenant: &str) {
+        let tenant = self.tenant_labels.label(Some(tenant));
+        self.tenant_requests_total
+            .with_label_values(&[tenant])
+            .inc();
+    }
+
+    pub fn observe_tenant_usage(&self, tenant: &str, usage: &Usage) {
+        let tenant = self.tenant_labels.label(Some(tenant));
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+            ("total", usage.total_tokens),
+        ] {
+            self.tenant_tokens_total
+                .with_label_values(&[tenant, kind])
+                .inc_by(tokens as u64);
+        }
+    }
+
+    pub fn observe_insufficient_credits(&self, tenant: &str) {
+        let tenant = self.tenant_labels.label(Some(tenant));
+        self.insufficient_credits_total
+            .with_label_values(&[tenant])
+            .inc();
+    }
+
+    pub fn set_redis_up(&self, up: bool) {
+        self.redis_up.set(i64::from(up));
+    }
+
+    /// Experiment and variant names come from configuration, so their cardinality is bounded.
+    pub fn observe_experiment_request(&self, experiment: &str, variant: &str) {
+        self.experiment_requests_total
+            .with_label_values(&[experiment, variant])
+            .inc();
+    }
+
+    pub fn observe_experiment_completion(
+        &self,
+        experiment: &str,
+        variant: &str,
+        duration: Duration,
+        usage: &Usage,
+    ) {
+        self.experiment_duration_seconds
+            .with_label_values(&[experiment, variant])
+            .observe(duration.as_secs_f64());
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+            ("total", usage.total_tokens),
+        ] {
+            self.experiment_tokens_total
+                .with_label_values(&[experiment, variant, kind])
+                .inc_by(tokens as u64);
+        }
+    }
+
+    pub fn observe_cost(&self, model: &str, backend: &str, cost_usd: f64) {
+        self.cost_usd_total
+            .with_label_values(&[model, backend])
+            .inc_by(cost_usd);
+    }
+
+    pub fn observe_stream_disconnect(&self, backend: &str) {
+        self.stream_disconnects_total
+            .with_label_values(&[backend])
+            .inc();
+    }
+
+    pub fn observe_stream_abandoned_tokens(&self, backend: &str, tokens: u64) {
+        self.stream_abandoned_tokens_total
+            .with_label_values(&[backend])
+            .inc_by(tokens);
     }
 
     pub fn render(&self) -> Result<String, String> {
@@ -140,6 +1079,91 @@ impl AppMetrics {
             .map_err(|error| error.to_string())?;
         String::from_utf8(buffer).map_err(|error| error.to_string())
     }
+
+    /// The same metrics in the OpenMetrics text format, with trace-ID exemplars on the request
+    /// latency buckets. Counter families drop their `_total` suffix, as the format requires.
+    pub fn render_openmetrics(&self) -> Result<String, String> {
+        let text = self.render()?;
+        let counters = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE ")?.strip_suffix(" counter"))
+            .collect::<HashSet<_>>();
+        let exemplars = self
+            .latency_exemplars
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+
+        let mut rendered = String::with_capacity(text.len());
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            if let Some((kind, rest)) = line
+                .strip_prefix("# HELP ")
+                .map(|rest| ("HELP", rest))
+                .or_else(|| line.strip_prefix("# TYPE ").map(|rest| ("TYPE", rest)))
+            {
+                let (name, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                match (counters.contains(name), name.strip_suffix("_total")) {
+                    (true, Some(family)) => {
+                        let _ = writeln!(rendered, "# {kind} {family} {tail}");
+                    }
+                    (true, None) if kind == "TYPE" => {
+                        let _ = writeln!(rendered, "# TYPE {name} unknown");
+                    }
+                    _ => {
+                        let _ = writeln!(rendered, "{line}");
+                    }
+                }
+                continue;
+            }
+            rendered.push_str(line);
+            if let Some(exemplar) = self.latency_exemplar(line, &exemplars) {
+                let _ = write!(
+                    rendered,
+                    " # {{trace_id=\"{}\"}} {} {:.3}",
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                );
+            }
+            rendered.push('\n');
+        }
+        rendered.push_str("# EOF\n");
+        Ok(rendered)
+    }
+
+    /// The exemplar for `line` if it is a request latency bucket sample that has one.
+    fn latency_exemplar<'a>(
+        &self,
+        line: &str,
+        exemplars: &'a HashMap<ExemplarSlot, Exemplar>,
+    ) -> Option<&'a Exemplar> {
+        let labels = line
+            .strip_prefix("gateway_http_request_duration_seconds_bucket{")?
+            .split_once('}')?
+            .0;
+        let label = |name: &str| {
+            labels.split(',').find_map(|pair| {
+                pair.strip_prefix(name)?
+                    .strip_prefix("=\"")?
+                    .strip_suffix('"')
+            })
+        };
+        let le = label("le")?.parse::<f64>().ok()?;
+        let bucket = self
+            .latency_buckets
+            .iter()
+            .position(|bound| *bound == le)
+            .unwrap_or(self.latency_buckets.len());
+        let stream = if label("stream")? == "true" {
+            "true"
+        } else {
+            "false"
+        };
+        exemplars.get(&(
+            label("path")?.to_owned(),
+            label("method")?.to_owned(),
+            stream,
+            bucket,
+        ))
+    }
 }
 
 impl Default for AppMetrics {
@@ -148,8 +1172,113 @@ impl Default for AppMetrics {
     }
 }
 
+impl Drop for PoolUsage {
+    fn drop(&mut self) {
+        self.in_use.dec();
+    }
+}
+
 impl Drop for InflightGuard<'_> {
     fn drop(&mut self) {
         self.metrics.inflight_requests.dec();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_buckets, AppMetrics, MetricsConfig};
+    use crate::models::Usage;
+
+    #[test]
+    fn token_counters_cap_distinct_tier_labels() {
+        let metrics = AppMetrics::with_config(MetricsConfig {
+            max_tier_labels: 1,
+            ..MetricsConfig::default()
+        });
+        metrics.observe_usage("mock-1", Some("pro"), &Usage::new(2, 3));
+        metrics.observe_usage("mock-1", Some("free"), &Usage::new(1, 1));
+        metrics.observe_usage("mock-2", Some("pro"), &Usage::new(1, 1));
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered
+            .contains("gateway_tokens_total{kind=\"total\",model=\"mock-1\",tier=\"pro\"} 5"));
+        assert!(rendered
+            .contains("gateway_tokens_total{kind=\"total\",model=\"mock-1\",tier=\"other\"} 2"));
+        assert!(rendered
+            .contains("gateway_tokens_total{kind=\"prompt\",model=\"mock-2\",tier=\"pro\"} 1"));
+
+        let untiered = AppMetrics::new();
+        untiered.observe_usage("mock-1", Some("pro"), &Usage::new(1, 1));
+        assert!(untiered
+            .render()
+            .expect("render metrics")
+            .contains("gateway_tokens_total{kind=\"total\",model=\"mock-1\",tier=\"all\"} 2"));
+    }
+
+    #[test]
+    fn request_latency_uses_configured_buckets() {
+        assert_eq!(
+            parse_buckets("1, 30,600").expect("valid"),
+            vec![1.0, 30.0, 600.0]
+        );
+        assert!(parse_buckets("5,1").is_err());
+        assert!(parse_buckets("0").is_err());
+        assert!(parse_buckets("").is_err());
+
+        let metrics = AppMetrics::with_config(MetricsConfig {
+            latency_buckets: vec![1.0, 600.0],
+            ..MetricsConfig::default()
+        });
+        metrics.observe_request(
+            "/v1/chat/completions",
+            "POST",
+            true,
+            200,
+            Duration::from_secs(120),
+        );
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("le=\"600\"} 1"));
+        assert!(rendered.contains("le=\"1\"} 0"));
+    }
+
+    #[test]
+    fn openmetrics_attaches_trace_exemplars_to_latency_buckets() {
+        let metrics = AppMetrics::with_config(MetricsConfig {
+            latency_buckets: vec![1.0, 10.0],
+            ..MetricsConfig::default()
+        });
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        for (stream, elapsed) in [(false, 2_500), (true, 20_000)] {
+            let elapsed = Duration::from_millis(elapsed);
+            metrics.observe_request("/v1/chat/completions", "POST", stream, 200, elapsed);
+            metrics.observe_latency_exemplar(
+                "/v1/chat/completions",
+                "POST",
+                stream,
+                elapsed,
+                trace_id,
+            );
+        }
+
+        let rendered = metrics.render_openmetrics().expect("render metrics");
+        let bucket = |stream: &str, le: &str| {
+            format!(
+                "gateway_http_request_duration_seconds_bucket{{method=\"POST\",\
+                 path=\"/v1/chat/completions\",stream=\"{stream}\",le=\"{le}\"}}"
+            )
+        };
+        let exemplar = format!("# {{trace_id=\"{trace_id}\"}}");
+        assert!(rendered.contains(&format!("{} 0\n", bucket("false", "1"))));
+        assert!(rendered.contains(&format!("{} 1 {exemplar} 2.5 ", bucket("false", "10"))));
+        assert!(rendered.contains(&format!("{} 1 {exemplar} 20 ", bucket("true", "+Inf"))));
+        assert!(rendered.contains("# TYPE gateway_http_requests counter\n"));
+        assert!(rendered.contains("gateway_http_requests_total{"));
+        assert!(rendered.ends_with("# EOF\n"));
+        assert!(!metrics
+            .render()
+            .expect("render metrics")
+            .contains("trace_id"));
+    }
+}
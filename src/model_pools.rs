@@ -0,0 +1,287 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+use crate::{
+    backend::{with_deadline, BackendError, BackendStream, InferenceBackend},
+    glob,
+    metrics::{AppMetrics, PoolUsage},
+    models::{BackendChatResponse, NormalizedChatRequest},
+};
+
+/// Concurrency limit for models matching `pattern`; rules are evaluated in order and the
+/// first match wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelPoolRule {
+    pub pattern: String,
+    pub max_concurrency: usize,
+}
+
+impl ModelPoolRule {
+    /// Parses `pattern=<max_concurrency>` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pattern, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("model pool `{entry}` must be `pattern=limit`"))?;
+                let max_concurrency = value
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("model pool `{entry}` has an invalid limit"))?;
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    max_concurrency,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelPoolConfig {
+    pub rules: Vec<ModelPoolRule>,
+    /// How long a request waits for a slot in its model's pool before it is shed.
+    pub queue_timeout: Duration,
+    pub retry_after: Duration,
+}
+
+impl Default for ModelPoolConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            queue_timeout: Duration::from_millis(2_000),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ModelPoolConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            rules: read_rules(),
+            queue_timeout: Duration::from_millis(read_u64(
+                "GATEWAY_MODEL_POOL_QUEUE_TIMEOUT_MS",
+                defaults.queue_timeout.as_millis() as u64,
+            )),
+            retry_after: Duration::from_secs(read_u64(
+                "GATEWAY_OVERLOAD_RETRY_AFTER_SECS",
+                defaults.retry_after.as_secs(),
+            )),
+        }
+    }
+}
+
+/// Separate concurrency pools per model family, enforced before a request is routed, since a
+/// 70B model and a mini model have very different backend footprints.
+pub struct ModelPools {
+    config: ModelPoolConfig,
+    pools: Vec<Arc<Semaphore>>,
+    metrics: Arc<AppMetrics>,
+}
+
+impl ModelPools {
+    pub fn new(config: ModelPoolConfig, metrics: Arc<AppMetrics>) -> Self {
+        let pools = config
+            .rules
+            .iter()
+            .map(|rule| {
+                metrics.set_pool_capacity(&rule.pattern, rule.max_concurrency);
+                Arc::new(Semaphore::new(rule.max_concurrency))
+            })
+            .collect();
+        Self {
+            config,
+            pools,
+            metrics,
+        }
+    }
+
+    /// Wraps `inner` so each call first takes a slot in its model's pool, if one matches.
+    pub fn gate(self: &Arc<Self>, inner: Arc<dyn InferenceBackend>) -> Arc<dyn InferenceBackend> {
+        if self.pools.is_empty() {
+            return inner;
+        }
+        Arc::new(ModelPoolBackend {
+            pools: self.clone(),
+            inner,
+        })
+    }
+
+    fn pool_for(&self, model: &str) -> Option<(&str, &Arc<Semaphore>)> {
+        self.config
+            .rules
+            .iter()
+            .position(|rule| glob::matches(&rule.pattern, model))
+            .map(|index| {
+                (
+                    self.config.rules[index].pattern.as_str(),
+                    &self.pools[index],
+                )
+            })
+    }
+
+    /// Takes a slot in the pool matching the request's model; models without a pool pass freely.
+    pub async fn acquire(
+        &self,
+        model: &str,
+        deadline: Option<Instant>,
+    ) -> Result<Option<ModelPoolPermit>, BackendError> {
+        let Some((name, pool)) = self.pool_for(model) else {
+            return Ok(None);
+        };
+        let permit = |permit| ModelPoolPermit {
+            _permit: permit,
+            _usage: self.metrics.pool_usage(name),
+        };
+        if let Ok(acquired) = pool.clone().try_acquire_owned() {
+            self.metrics
+                .observe_queue_wait("model_pool", Duration::ZERO);
+            return Ok(Some(permit(acquired)));
+        }
+
+        self.metrics.adjust_queue_depth("model_pool", 1);
+        let started = Instant::now();
+        let acquired = with_deadline(deadline, "model pool", async {
+            Ok(tokio::time::timeout(self.config.queue_timeout, pool.clone().acquire_owned()).await)
+        })
+        .await;
+        self.metrics.adjust_queue_depth("model_pool", -1);
+        match acquired? {
+            Ok(Ok(acquired)) => {
+                self.metrics
+                    .observe_queue_wait("model_pool", started.elapsed());
+                Ok(Some(permit(acquired)))
+            }
+            Ok(Err(_)) => Err(BackendError::Unavailable("model pool closed".to_owned())),
+            Err(_) => {
+                self.metrics.observe_load_shed("model_pool");
+                Err(BackendError::Overloaded {
+                    message: format!("model `{model}` is at its concurrency limit"),
+                    retry_after_secs: self.config.retry_after.as_secs().max(1),
+                })
+            }
+        }
+    }
+}
+
+/// A slot in a model pool, counted in `gateway_pool_in_use` while held.
+pub struct ModelPoolPermit {
+    _permit: OwnedSemaphorePermit,
+    _usage: PoolUsage,
+}
+
+struct ModelPoolBackend {
+    pools: Arc<ModelPools>,
+    inner: Arc<dyn InferenceBackend>,
+}
+
+#[async_trait]
+impl InferenceBackend for ModelPoolBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn execute_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        let _permit = self.pools.acquire(&request.model, request.deadline).await?;
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let permit = self.pools.acquire(&request.model, request.deadline).await?;
+        let stream = self.inner.stream_chat(request).await?;
+        Ok(stream
+            .map(move |item| {
+                let _slot = &permit;
+                item
+            })
+            .boxed())
+    }
+}
+
+fn read_rules() -> Vec<ModelPoolRule> {
+    let Ok(raw) = env::var("GATEWAY_MODEL_POOLS") else {
+        return Vec::new();
+    };
+    ModelPoolRule::parse_list(&raw).unwrap_or_else(|error| {
+        warn!(error = %error, "invalid GATEWAY_MODEL_POOLS, ignoring model pools");
+        Vec::new()
+    })
+}
+
+fn read_u64(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{ModelPoolConfig, ModelPoolRule, ModelPools};
+    use crate::{backend::BackendError, metrics::AppMetrics};
+
+    #[test]
+    fn parses_pool_rules() {
+        let rules = ModelPoolRule::parse_list("llama-70b*=4, *-mini=64").expect("valid rules");
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].pattern, "*-mini");
+        assert_eq!(rules[1].max_concurrency, 64);
+        assert!(ModelPoolRule::parse_list("llama=0").is_err());
+    }
+
+    #[tokio::test]
+    async fn pools_limit_matching_models_independently() {
+        let metrics = Arc::new(AppMetrics::new());
+        let pools = ModelPools::new(
+            ModelPoolConfig {
+                rules: ModelPoolRule::parse_list("big-*=1").expect("valid rules"),
+                queue_timeout: Duration::from_millis(30),
+                retry_after: Duration::from_secs(1),
+            },
+            metrics.clone(),
+        );
+
+        let held = pools
+            .acquire("big-70b", None)
+            .await
+            .expect("first slot")
+            .expect("pooled model gets a permit");
+        assert!(matches!(
+            pools.acquire("big-70b", None).await,
+            Err(BackendError::Overloaded { .. })
+        ));
+        assert!(pools
+            .acquire("small", None)
+            .await
+            .expect("unpooled model")
+            .is_none());
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_pool_in_use{pool=\"big-*\"} 1"));
+        assert!(rendered.contains("gateway_load_shed_total{stage=\"model_pool\"} 1"));
+
+        drop(held);
+        assert!(pools.acquire("big-70b", None).await.is_ok());
+    }
+}
@@ -1,4 +1,11 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -15,14 +22,56 @@ pub struct ChatCompletionsRequest {
     pub stream: bool,
     #[serde(default)]
     pub user: Option<String>,
+    /// Seconds the client is willing to wait; the gateway abandons the request afterwards.
+    #[serde(default)]
+    pub timeout: Option<f64>,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// String tags for the client's own analysis, e.g. the feature or experiment a request
+    /// came from; copied onto usage and access records.
+    #[serde(default)]
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StreamOptions {
+    /// Ends the stream with a chunk carrying the request's usage and no choices.
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OpenAiMessage {
     pub role: MessageRole,
     pub content: String,
+    /// Marks the prompt up to and including this message as cacheable by providers that
+    /// cache prompt prefixes on request, such as Anthropic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
 }
 
+/// Anthropic's `cache_control` hint, e.g. `{"type":"ephemeral","ttl":"1h"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CacheControl {
+    Ephemeral {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<CacheTtl>,
+    },
+}
+
+/// How long a provider keeps a cached prompt prefix; its own default when unset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum CacheTtl {
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+}
+
+/// Most cache breakpoints a request may set; Anthropic refuses requests with more.
+const MAX_CACHE_BREAKPOINTS: usize = 4;
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageRole {
@@ -40,12 +89,140 @@ pub struct NormalizedChatRequest {
     pub messages: Vec<NormalizedMessage>,
     pub generation: GenerationParams,
     pub stream: bool,
+    pub priority: Priority,
+    /// Point after which the client has given up; work past it is abandoned with a 504.
+    pub deadline: Option<Instant>,
+    /// Set when the stream will be forwarded verbatim; adapters that can should then attach
+    /// each upstream event to its chunk as [`BackendChunk::raw`].
+    pub passthrough: bool,
+    /// Backend named by `x-gateway-backend` or the model's catalog entry; the router sends the
+    /// request to that endpoint alone instead of balancing it.
+    pub pinned_backend: Option<String>,
+    pub client: ClientTags,
+}
+
+impl NormalizedChatRequest {
+    /// Tightens the deadline to `deadline` if that is earlier than the current one.
+    pub fn limit_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(
+            self.deadline
+                .map_or(deadline, |current| current.min(deadline)),
+        );
+    }
+
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+}
+
+/// Most `metadata` pairs a request may carry, and the longest key, value, and `user`; the
+/// same limits OpenAI applies, so tagged requests can be forwarded as they are.
+const MAX_METADATA_PAIRS: usize = 16;
+const MAX_METADATA_KEY_CHARS: usize = 64;
+const MAX_METADATA_VALUE_CHARS: usize = 512;
+const MAX_USER_CHARS: usize = 256;
+
+/// What the client said about a request for its own bookkeeping: its end-user id (`user`) and
+/// its `metadata` tags. Neither affects routing, caching, or the gateway's own attribution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTags {
+    pub user: Option<String>,
+    pub metadata: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl ClientTags {
+    fn new(
+        user: Option<String>,
+        metadata: Option<BTreeMap<String, String>>,
+    ) -> Result<Self, InvalidParameter> {
+        if user
+            .as_ref()
+            .is_some_and(|user| user.chars().count() > MAX_USER_CHARS)
+        {
+            return Err(InvalidParameter::new(
+                "user",
+                "string_above_max_length",
+                format!("user must be at most {MAX_USER_CHARS} characters"),
+            ));
+        }
+        let metadata = metadata.unwrap_or_default();
+        if metadata.len() > MAX_METADATA_PAIRS {
+            return Err(InvalidParameter::new(
+                "metadata",
+                "object_above_max_size",
+                format!("metadata must have at most {MAX_METADATA_PAIRS} pairs"),
+            ));
+        }
+        for (key, value) in &metadata {
+            if key.is_empty() || key.chars().count() > MAX_METADATA_KEY_CHARS {
+                return Err(InvalidParameter::new(
+                    "metadata",
+                    "invalid_value",
+                    format!("metadata keys must be 1 to {MAX_METADATA_KEY_CHARS} characters"),
+                ));
+            }
+            if value.chars().count() > MAX_METADATA_VALUE_CHARS {
+                return Err(InvalidParameter::new(
+                    "metadata",
+                    "string_above_max_length",
+                    format!(
+                        "metadata value of `{key}` exceeds {MAX_METADATA_VALUE_CHARS} characters"
+                    ),
+                ));
+            }
+        }
+        Ok(Self {
+            user: user.filter(|user| !user.is_empty()),
+            metadata,
+        })
+    }
+
+    /// The metadata for records that omit it when there is none.
+    pub fn metadata_tags(&self) -> Option<BTreeMap<String, String>> {
+        (!self.metadata.is_empty()).then(|| self.metadata.clone())
+    }
+}
+
+/// Scheduling priority; higher priorities are flushed first by the micro-batcher and served
+/// first among a tenant's fair-queue waiters.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "normal" => Some(Self::Normal),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NormalizedMessage {
     pub role: MessageRole,
     pub content: String,
+    /// Passed on by adapters whose upstream honours it, and dropped by the rest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
 }
 
 #[derive(Debug, Clone)]
@@ -55,13 +232,75 @@ pub struct GenerationParams {
     pub top_p: Option<f32>,
 }
 
+/// A request parameter that failed validation, named as OpenAI's `param` field names it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message}")]
+pub struct InvalidParameter {
+    pub param: &'static str,
+    /// OpenAI error code, e.g. `missing_required_parameter`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl InvalidParameter {
+    pub fn new(param: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            param,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
 impl ChatCompletionsRequest {
-    pub fn into_normalized(self, user_id: String) -> Result<NormalizedChatRequest, String> {
+    pub fn into_normalized(
+        self,
+        user_id: String,
+    ) -> Result<NormalizedChatRequest, InvalidParameter> {
         if self.model.trim().is_empty() {
-            return Err("model is required".to_owned());
+            return Err(InvalidParameter::new(
+                "model",
+                "missing_required_parameter",
+                "model is required",
+            ));
         }
         if self.messages.is_empty() {
-            return Err("messages must not be empty".to_owned());
+            return Err(InvalidParameter::new(
+                "messages",
+                "empty_array",
+                "messages must not be empty",
+            ));
+        }
+
+        let deadline = match self.timeout {
+            Some(secs) => Some(
+                Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .filter(|timeout| !timeout.is_zero())
+                    .and_then(|timeout| Instant::now().checked_add(timeout))
+                    .ok_or_else(|| {
+                        InvalidParameter::new(
+                            "timeout",
+                            "invalid_value",
+                            "timeout must be a positive number of seconds",
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+
+        let client = ClientTags::new(self.user, self.metadata)?;
+        let breakpoints = self
+            .messages
+            .iter()
+            .filter(|message| message.cache_control.is_some())
+            .count();
+        if breakpoints > MAX_CACHE_BREAKPOINTS {
+            return Err(InvalidParameter::new(
+                "messages",
+                "too_many_cache_breakpoints",
+                format!("at most {MAX_CACHE_BREAKPOINTS} messages may set cache_control"),
+            ));
         }
 
         let messages = self
@@ -70,6 +309,7 @@ impl ChatCompletionsRequest {
             .map(|message| NormalizedMessage {
                 role: message.role,
                 content: message.content,
+                cache_control: message.cache_control,
             })
             .collect();
 
@@ -84,26 +324,98 @@ impl ChatCompletionsRequest {
                 top_p: self.top_p,
             },
             stream: self.stream,
+            priority: Priority::Normal,
+            deadline,
+            passthrough: false,
+            pinned_backend: None,
+            client,
         })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BackendChatResponse {
     pub content: String,
+    /// OpenAI's value: `stop`, `length`, `content_filter`, `tool_calls`, or `function_call`.
     pub finish_reason: String,
+    /// The provider's own finish reason, when it is not already an OpenAI value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_finish_reason: Option<String>,
     pub usage: Usage,
+    /// Endpoint that produced the response; stamped by the router.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Headers of the upstream HTTP response, for `GATEWAY_FORWARD_RESPONSE_HEADERS`; set by
+    /// HTTP adapters and never cached.
+    #[serde(skip)]
+    pub upstream_headers: Option<HeaderMap>,
 }
 
 #[derive(Debug, Clone)]
 pub struct BackendChunk {
     pub delta: Option<String>,
     pub finish_reason: Option<String>,
+    pub provider_finish_reason: Option<String>,
     pub usage: Option<Usage>,
     pub done: bool,
+    /// Endpoint that produced the stream; stamped by the router on the first and final chunks.
+    pub backend: Option<String>,
+    /// The upstream SSE `data:` payload this chunk was parsed from, for passthrough streams.
+    pub raw: Option<String>,
+    /// Headers of the upstream HTTP response, on the first chunk of a stream from an HTTP
+    /// adapter.
+    pub upstream_headers: Option<HeaderMap>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamTranscript {
+    pub chunks: Vec<TranscriptChunk>,
+    pub finish_reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptChunk {
+    pub delta: String,
+    pub offset_ms: u64,
+}
+
+impl StreamTranscript {
+    pub fn push_delta(&mut self, delta: String, offset_ms: u64) {
+        self.chunks.push(TranscriptChunk { delta, offset_ms });
+    }
+
+    /// Ends the transcript with the stream's terminal chunk.
+    pub fn finish(&mut self, chunk: &BackendChunk) {
+        self.finish_reason = chunk
+            .finish_reason
+            .clone()
+            .unwrap_or_else(|| "stop".to_owned());
+        self.provider_finish_reason = chunk.provider_finish_reason.clone();
+        self.usage = chunk.usage.clone();
+    }
+}
+
+/// Maps a provider's finish reason onto OpenAI's, e.g. Anthropic's `end_turn` to `stop` or
+/// Gemini's `MAX_TOKENS` to `length`, so clients see one vocabulary whichever backend answered.
+/// Returns the OpenAI value and, when it differs, the raw one to keep as
+/// `provider_finish_reason`. Reasons without an OpenAI counterpart become `stop`.
+pub fn normalize_finish_reason(raw: &str) -> (String, Option<String>) {
+    let normalized = match raw.trim().to_ascii_lowercase().as_str() {
+        "length" | "max_tokens" | "max_output_tokens" | "model_length" => "length",
+        "content_filter" | "safety" | "recitation" | "blocklist" | "prohibited_content"
+        | "spii" | "refusal" => "content_filter",
+        "tool_calls" | "tool_use" => "tool_calls",
+        "function_call" => "function_call",
+        _ => "stop",
+    };
+    let raw = (raw != normalized).then(|| raw.to_owned());
+    (normalized.to_owned(), raw)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
@@ -135,6 +447,9 @@ pub struct ChatChoice {
     pub index: usize,
     pub message: AssistantMessage,
     pub finish_reason: String,
+    /// The backend's own finish reason, when it was mapped onto a different OpenAI one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_finish_reason: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -162,6 +477,7 @@ impl ChatCompletionsResponse {
                     content: backend.content,
                 },
                 finish_reason: backend.finish_reason,
+                provider_finish_reason: backend.provider_finish_reason,
             }],
             usage: backend.usage,
         }
@@ -175,6 +491,10 @@ pub struct ChatCompletionsChunk {
     pub created: i64,
     pub model: String,
     pub choices: Vec<ChunkChoice>,
+    /// `Some(None)` writes `"usage": null`, which OpenAI sends on every chunk but the last
+    /// once a client asks for usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Option<Usage>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -183,6 +503,8 @@ pub struct ChunkChoice {
     pub delta: DeltaMessage,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_finish_reason: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -207,7 +529,9 @@ impl ChatCompletionsChunk {
                     content: None,
                 },
                 finish_reason: None,
+                provider_finish_reason: None,
             }],
+            usage: None,
         }
     }
 
@@ -224,11 +548,19 @@ impl ChatCompletionsChunk {
                     content: Some(content),
                 },
                 finish_reason: None,
+                provider_finish_reason: None,
             }],
+            usage: None,
         }
     }
 
-    pub fn finish(id: &str, created: i64, model: &str, finish_reason: String) -> Self {
+    pub fn finish(
+        id: &str,
+        created: i64,
+        model: &str,
+        finish_reason: String,
+        provider_finish_reason: Option<String>,
+    ) -> Self {
         Self {
             id: id.to_owned(),
             object: "chat.completion.chunk".to_owned(),
@@ -241,7 +573,21 @@ impl ChatCompletionsChunk {
                     content: None,
                 },
                 finish_reason: Some(finish_reason),
+                provider_finish_reason,
             }],
+            usage: None,
+        }
+    }
+
+    /// The closing chunk of a stream whose client set `stream_options.include_usage`.
+    pub fn usage(id: &str, created: i64, model: &str, usage: Usage) -> Self {
+        Self {
+            id: id.to_owned(),
+            object: "chat.completion.chunk".to_owned(),
+            created,
+            model: model.to_owned(),
+            choices: Vec::new(),
+            usage: Some(Some(usage)),
         }
     }
 }
@@ -260,13 +606,131 @@ mod tests {
             top_p: None,
             stream: false,
             user: None,
+            timeout: None,
+            stream_options: None,
+            metadata: None,
         };
 
         let error = request
             .into_normalized("user_123".to_owned())
             .expect_err("empty message list should fail");
 
-        assert_eq!(error, "messages must not be empty");
+        assert_eq!(error.message, "messages must not be empty");
+        assert_eq!(error.param, "messages");
+    }
+
+    #[test]
+    fn client_tags_are_kept_within_limits() {
+        let request = |user: &str, metadata: Vec<(String, String)>| ChatCompletionsRequest {
+            model: "gpt-test".to_owned(),
+            messages: vec![OpenAiMessage {
+                role: MessageRole::User,
+                content: "hi".to_owned(),
+                cache_control: None,
+            }],
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: false,
+            user: Some(user.to_owned()),
+            timeout: None,
+            stream_options: None,
+            metadata: Some(metadata.into_iter().collect()),
+        };
+        let tag = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+
+        let normalized = request("end-user-7", vec![tag("feature", "summarize")])
+            .into_normalized("key_dev".to_owned())
+            .expect("valid tags");
+        assert_eq!(normalized.client.user.as_deref(), Some("end-user-7"));
+        assert_eq!(normalized.client.metadata["feature"], "summarize");
+        assert_eq!(normalized.user_id, "key_dev");
+
+        let too_many = (0..17).map(|i| tag(&format!("k{i}"), "v")).collect();
+        let long_value = vec![tag("feature", &"x".repeat(513))];
+        let long_key = vec![tag(&"k".repeat(65), "v")];
+        for metadata in [too_many, long_value, long_key] {
+            let error = request("u", metadata)
+                .into_normalized("key_dev".to_owned())
+                .expect_err("tags over the limits");
+            assert_eq!(error.param, "metadata");
+        }
+        let error = request(&"u".repeat(257), Vec::new())
+            .into_normalized("key_dev".to_owned())
+            .expect_err("user over the limit");
+        assert_eq!(error.param, "user");
+    }
+
+    #[test]
+    fn cache_hints_are_parsed_and_capped() {
+        let request = |messages: serde_json::Value| {
+            serde_json::from_value::<ChatCompletionsRequest>(serde_json::json!({
+                "model": "claude-test",
+                "messages": messages,
+            }))
+        };
+        let hinted = |ttl: &str| {
+            serde_json::json!({
+                "role": "user",
+                "content": "long shared context",
+                "cache_control": {"type": "ephemeral", "ttl": ttl},
+            })
+        };
+
+        let normalized =
+            request(serde_json::json!([hinted("1h"), {"role": "user", "content": "q"}]))
+                .expect("valid hints")
+                .into_normalized("key_dev".to_owned())
+                .expect("within the breakpoint limit");
+        assert_eq!(
+            normalized.messages[0].cache_control,
+            Some(CacheControl::Ephemeral {
+                ttl: Some(CacheTtl::OneHour)
+            })
+        );
+        assert_eq!(normalized.messages[1].cache_control, None);
+
+        assert!(request(serde_json::json!([hinted("forever")])).is_err());
+        let error = request(serde_json::Value::Array(vec![hinted("5m"); 5]))
+            .expect("valid hints")
+            .into_normalized("key_dev".to_owned())
+            .expect_err("too many breakpoints");
+        assert_eq!(error.code, "too_many_cache_breakpoints");
+    }
+
+    #[test]
+    fn provider_finish_reasons_map_onto_openai_ones() {
+        for (raw, expected) in [
+            ("end_turn", ("stop", Some("end_turn"))),
+            ("stop_sequence", ("stop", Some("stop_sequence"))),
+            ("max_tokens", ("length", Some("max_tokens"))),
+            ("MAX_TOKENS", ("length", Some("MAX_TOKENS"))),
+            ("SAFETY", ("content_filter", Some("SAFETY"))),
+            ("tool_use", ("tool_calls", Some("tool_use"))),
+            ("length", ("length", None)),
+            ("content_filter", ("content_filter", None)),
+            ("something_new", ("stop", Some("something_new"))),
+        ] {
+            let (reason, provider) = normalize_finish_reason(raw);
+            assert_eq!((reason.as_str(), provider.as_deref()), expected, "{raw}");
+        }
+
+        let response = ChatCompletionsResponse::from_backend(
+            "chatcmpl-1".to_owned(),
+            0,
+            "claude".to_owned(),
+            BackendChatResponse {
+                content: "Hi".to_owned(),
+                finish_reason: "length".to_owned(),
+                provider_finish_reason: Some("max_tokens".to_owned()),
+                usage: Usage::new(1, 1),
+                backend: None,
+                upstream_headers: None,
+            },
+        );
+        let body = serde_json::to_value(&response).expect("serializable");
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+        assert_eq!(body["choices"][0]["provider_finish_reason"], "max_tokens");
     }
 
     #[test]
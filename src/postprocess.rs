@@ -0,0 +1,504 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use tracing::warn;
+
+/// Rewrites completion text between the backend and the response serializer. One-shot
+/// responses and streams go through the same pass, so a processor is written once.
+///
+/// Processors see the backend's text, never the cache's copy: cached and coalesced responses
+/// are shared across keys, and each key applies its own chain on the way out.
+pub trait ResponseProcessor: Send + Sync {
+    /// Starts a pass over one response.
+    fn begin(&self) -> Box<dyn TextTransform>;
+}
+
+/// The state of one processor over one response.
+pub trait TextTransform: Send {
+    /// Takes the next piece of text and returns what is ready to send. Text that may still
+    /// change, such as a word the next delta could continue, can be held back.
+    fn push(&mut self, text: &str) -> Transformed;
+
+    /// Returns whatever was held back once the response has ended.
+    fn finish(&mut self) -> String;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transformed {
+    pub text: String,
+    /// Ends the response here; later text is dropped and it finishes with `stop`.
+    pub stop: bool,
+}
+
+impl Transformed {
+    pub fn text(text: String) -> Self {
+        Self { text, stop: false }
+    }
+}
+
+/// Processors applied in order, each to the previous one's output.
+#[derive(Clone, Default)]
+pub struct ProcessorChain {
+    processors: Vec<Arc<dyn ResponseProcessor>>,
+}
+
+impl fmt::Debug for ProcessorChain {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ProcessorChain")
+            .field("processors", &self.processors.len())
+            .finish()
+    }
+}
+
+impl ProcessorChain {
+    pub fn new(processors: Vec<Arc<dyn ResponseProcessor>>) -> Self {
+        Self { processors }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    pub fn begin(&self) -> ChainPass {
+        ChainPass {
+            transforms: self
+                .processors
+                .iter()
+                .map(|processor| processor.begin())
+                .collect(),
+            done: false,
+        }
+    }
+
+    /// Runs a complete one-shot response through the chain; the flag is set when a processor
+    /// cut it short.
+    pub fn apply(&self, content: &str) -> (String, bool) {
+        let mut pass = self.begin();
+        let pushed = pass.push(content);
+        let mut text = pushed.text;
+        text.push_str(&pass.finish());
+        (text, pushed.stop)
+    }
+}
+
+/// One response's pass through a [`ProcessorChain`].
+pub struct ChainPass {
+    transforms: Vec<Box<dyn TextTransform>>,
+    done: bool,
+}
+
+impl ChainPass {
+    pub fn push(&mut self, delta: &str) -> Transformed {
+        if self.done {
+            return Transformed::default();
+        }
+        let mut text = delta.to_owned();
+        for index in 0..self.transforms.len() {
+            let out = self.transforms[index].push(&text);
+            text = out.text;
+            if out.stop {
+                self.done = true;
+                return Transformed {
+                    text: flush(&mut self.transforms[index + 1..], text),
+                    stop: true,
+                };
+            }
+        }
+        Transformed::text(text)
+    }
+
+    /// Releases held-back text at the end of the response.
+    pub fn finish(&mut self) -> String {
+        if std::mem::replace(&mut self.done, true) {
+            return String::new();
+        }
+        flush(&mut self.transforms, String::new())
+    }
+}
+
+/// Pushes `text` through `transforms` and finishes each, so everything they held comes out.
+fn flush(transforms: &mut [Box<dyn TextTransform>], mut text: String) -> String {
+    for transform in transforms {
+        let mut out = transform.push(&text).text;
+        out.push_str(&transform.finish());
+        text = out;
+    }
+    text
+}
+
+/// Built-in processors, as configured in `GATEWAY_POST_PROCESSORS`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProcessorSpec {
+    /// Replaces each letter of the listed words (whole words, any case) with `*`.
+    MaskWords { words: Vec<String> },
+    /// Drops Markdown emphasis, code ticks, and heading markers, leaving plain text.
+    StripMarkdown,
+    /// Ends the response before the first occurrence of any of the sequences.
+    StopSequences { sequences: Vec<String> },
+}
+
+impl ProcessorSpec {
+    pub fn build(&self) -> Arc<dyn ResponseProcessor> {
+        match self {
+            Self::MaskWords { words } => Arc::new(MaskWords::new(words.clone())),
+            Self::StripMarkdown => Arc::new(StripMarkdown),
+            Self::StopSequences { sequences } => Arc::new(StopSequences::new(sequences.clone())),
+        }
+    }
+}
+
+/// Named processors that key policies refer to by name in `post_process`.
+#[derive(Default)]
+pub struct PostProcessors {
+    named: HashMap<String, Arc<dyn ResponseProcessor>>,
+}
+
+impl PostProcessors {
+    pub fn from_env() -> Self {
+        let mut processors = Self::default();
+        let Ok(raw) = env::var("GATEWAY_POST_PROCESSORS") else {
+            return processors;
+        };
+        if raw.trim().is_empty() {
+            return processors;
+        }
+        match serde_json::from_str::<HashMap<String, ProcessorSpec>>(&raw) {
+            Ok(specs) => {
+                for (name, spec) in specs {
+                    processors.named.insert(name, spec.build());
+                }
+            }
+            Err(error) => {
+                warn!(error = %error, "invalid GATEWAY_POST_PROCESSORS, ignoring post-processors");
+            }
+        }
+        processors
+    }
+
+    /// Registers `processor` under `name`, replacing any processor of that name.
+    pub fn with_processor(
+        mut self,
+        name: impl Into<String>,
+        processor: Arc<dyn ResponseProcessor>,
+    ) -> Self {
+        self.named.insert(name.into(), processor);
+        self
+    }
+
+    /// The chain for a key's `post_process` list; unknown names are skipped.
+    pub fn chain(&self, names: &[String]) -> ProcessorChain {
+        ProcessorChain::new(
+            names
+                .iter()
+                .filter_map(|name| {
+                    let processor = self.named.get(name).cloned();
+                    if processor.is_none() {
+                        warn!(processor = %name, "unknown post-processor in key policy");
+                    }
+                    processor
+                })
+                .collect(),
+        )
+    }
+}
+
+pub struct MaskWords {
+    words: Arc<HashSet<String>>,
+}
+
+impl MaskWords {
+    pub fn new(words: Vec<String>) -> Self {
+        Self {
+            words: Arc::new(words.iter().map(|word| word.to_lowercase()).collect()),
+        }
+    }
+}
+
+impl ResponseProcessor for MaskWords {
+    fn begin(&self) -> Box<dyn TextTransform> {
+        Box::new(MaskWordsPass {
+            words: self.words.clone(),
+            pending: String::new(),
+        })
+    }
+}
+
+struct MaskWordsPass {
+    words: Arc<HashSet<String>>,
+    /// A trailing word the next delta may continue.
+    pending: String,
+}
+
+impl MaskWordsPass {
+    fn mask(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut word_start = None;
+        for (index, ch) in text.char_indices().chain([(text.len(), ' ')]) {
+            if ch.is_alphanumeric() {
+                word_start.get_or_insert(index);
+                continue;
+            }
+            if let Some(start) = word_start.take() {
+                let word = &text[start..index];
+                if self.words.contains(&word.to_lowercase()) {
+                    out.extend(word.chars().map(|_| '*'));
+                } else {
+                    out.push_str(word);
+                }
+            }
+            if index < text.len() {
+                out.push(ch);
+            }
+        }
+        out
+    }
+}
+
+impl TextTransform for MaskWordsPass {
+    fn push(&mut self, text: &str) -> Transformed {
+        self.pending.push_str(text);
+        let split = self
+            .pending
+            .char_indices()
+            .rev()
+            .find(|(_, ch)| !ch.is_alphanumeric())
+            .map_or(0, |(index, ch)| index + ch.len_utf8());
+        let rest = self.pending.split_off(split);
+        let ready = std::mem::replace(&mut self.pending, rest);
+        Transformed::text(self.mask(&ready))
+    }
+
+    fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.mask(&rest)
+    }
+}
+
+pub struct StripMarkdown;
+
+impl ResponseProcessor for StripMarkdown {
+    fn begin(&self) -> Box<dyn TextTransform> {
+        Box::new(StripMarkdownPass {
+            line_start: true,
+            held: String::new(),
+        })
+    }
+}
+
+struct StripMarkdownPass {
+    line_start: bool,
+    /// Heading `#`s at the start of a line, or a single `_` that may open `__`.
+    held: String,
+}
+
+impl TextTransform for StripMarkdownPass {
+    fn push(&mut self, text: &str) -> Transformed {
+        let mut out = String::with_capacity(text.len());
+        for ch in text.chars() {
+            if self.held.starts_with('#') {
+                if ch == '#' && self.held.len() < 6 {
+                    self.held.push(ch);
+                    continue;
+                }
+                if ch == ' ' {
+                    self.held.clear();
+                    self.line_start = false;
+                    continue;
+                }
+                out.push_str(&std::mem::take(&mut self.held));
+                self.line_start = false;
+            } else if !self.held.is_empty() {
+                self.held.clear();
+                if ch == '_' {
+                    continue;
+                }
+                out.push('_');
+                self.line_start = false;
+            }
+            match ch {
+                '*' | '`' => {}
+                '#' if self.line_start => self.held.push(ch),
+                '_' => self.held.push(ch),
+                _ => {
+                    out.push(ch);
+                    self.line_start = ch == '\n';
+                }
+            }
+        }
+        Transformed::text(out)
+    }
+
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+pub struct StopSequences {
+    sequences: Arc<Vec<String>>,
+}
+
+impl StopSequences {
+    pub fn new(sequences: Vec<String>) -> Self {
+        Self {
+            sequences: Arc::new(
+                sequences
+                    .into_iter()
+                    .filter(|sequence| !sequence.is_empty())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl ResponseProcessor for StopSequences {
+    fn begin(&self) -> Box<dyn TextTransform> {
+        Box::new(StopSequencesPass {
+            sequences: self.sequences.clone(),
+            pending: String::new(),
+        })
+    }
+}
+
+struct StopSequencesPass {
+    sequences: Arc<Vec<String>>,
+    /// A tail that could be the start of a stop sequence.
+    pending: String,
+}
+
+impl TextTransform for StopSequencesPass {
+    fn push(&mut self, text: &str) -> Transformed {
+        self.pending.push_str(text);
+        let found = self
+            .sequences
+            .iter()
+            .filter_map(|sequence| self.pending.find(sequence.as_str()))
+            .min();
+        if let Some(at) = found {
+            self.pending.truncate(at);
+            return Transformed {
+                text: std::mem::take(&mut self.pending),
+                stop: true,
+            };
+        }
+        let held = self
+            .sequences
+            .iter()
+            .flat_map(|sequence| {
+                (1..sequence.len())
+                    .filter(|&len| sequence.is_char_boundary(len))
+                    .filter(|&len| self.pending.ends_with(&sequence[..len]))
+            })
+            .max()
+            .unwrap_or(0);
+        let rest = self.pending.split_off(self.pending.len() - held);
+        Transformed::text(std::mem::replace(&mut self.pending, rest))
+    }
+
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ProcessorChain, ProcessorSpec, ResponseProcessor, Transformed};
+
+    fn chain(specs: &str) -> ProcessorChain {
+        let specs: Vec<ProcessorSpec> = serde_json::from_str(specs).expect("valid specs");
+        ProcessorChain::new(specs.iter().map(ProcessorSpec::build).collect())
+    }
+
+    /// Streams `text` split at every pair of boundaries and checks each split produces the
+    /// one-shot result.
+    fn assert_stream_matches_one_shot(chain: &ProcessorChain, text: &str) -> (String, bool) {
+        let expected = chain.apply(text);
+        let boundaries = text
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain([text.len()])
+            .collect::<Vec<_>>();
+        for &first in &boundaries {
+            for &second in boundaries.iter().filter(|&&second| second >= first) {
+                let mut pass = chain.begin();
+                let mut streamed = String::new();
+                let mut stopped = false;
+                for piece in [&text[..first], &text[first..second], &text[second..]] {
+                    let Transformed { text, stop } = pass.push(piece);
+                    streamed.push_str(&text);
+                    stopped |= stop;
+                }
+                streamed.push_str(&pass.finish());
+                assert_eq!(
+                    (streamed, stopped),
+                    expected,
+                    "split at {first} and {second}"
+                );
+            }
+        }
+        expected
+    }
+
+    #[test]
+    fn masks_listed_words_across_delta_boundaries() {
+        let chain = chain(r#"[{"type":"mask_words","words":["darn","Heck"]}]"#);
+        let (text, stop) = assert_stream_matches_one_shot(&chain, "Darn it, heck! darned");
+        assert_eq!(text, "**** it, ****! darned");
+        assert!(!stop);
+    }
+
+    #[test]
+    fn strips_markdown_markers() {
+        let chain = chain(r#"[{"type":"strip_markdown"}]"#);
+        let (text, _) =
+            assert_stream_matches_one_shot(&chain, "## Title\n**bold** and `code`, __u__ a_b #1");
+        assert_eq!(text, "Title\nbold and code, u a_b #1");
+    }
+
+    #[test]
+    fn trims_at_the_first_stop_sequence_and_reports_it() {
+        let chain = chain(
+            r#"[{"type":"stop_sequences","sequences":["\nUser:","END"]},{"type":"mask_words","words":["bad"]}]"#,
+        );
+        let (text, stop) = assert_stream_matches_one_shot(&chain, "a bad reply\nUser: more END");
+        assert_eq!(text, "a *** reply");
+        assert!(stop);
+
+        let (text, stop) = assert_stream_matches_one_shot(&chain, "no stop\nUse it");
+        assert_eq!(text, "no stop\nUse it");
+        assert!(!stop);
+    }
+
+    #[test]
+    fn custom_processors_chain_with_built_ins() {
+        struct Upper;
+        struct UpperPass;
+        impl ResponseProcessor for Upper {
+            fn begin(&self) -> Box<dyn super::TextTransform> {
+                Box::new(UpperPass)
+            }
+        }
+        impl super::TextTransform for UpperPass {
+            fn push(&mut self, text: &str) -> Transformed {
+                Transformed::text(text.to_uppercase())
+            }
+            fn finish(&mut self) -> String {
+                String::new()
+            }
+        }
+
+        let processors = super::PostProcessors::default()
+            .with_processor("upper", Arc::new(Upper))
+            .with_processor("plain", ProcessorSpec::StripMarkdown.build());
+        let chain =
+            processors.chain(&["plain".to_owned(), "missing".to_owned(), "upper".to_owned()]);
+        assert_eq!(chain.apply("**hi**"), ("HI".to_owned(), false));
+    }
+}
@@ -0,0 +1,116 @@
+use std::env;
+
+use tracing::warn;
+
+use crate::{glob, models::Usage};
+
+/// USD per million tokens for models matching `pattern`; rules are evaluated in order and the
+/// first match wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelPrice {
+    pub pattern: String,
+    pub prompt_per_million: f64,
+    pub completion_per_million: f64,
+}
+
+impl ModelPrice {
+    /// Parses `pattern=<prompt>:<completion>` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pattern, value) = entry.split_once('=').ok_or_else(|| {
+                    format!("price `{entry}` must be `pattern=prompt_usd:completion_usd`")
+                })?;
+                let (prompt, completion) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("price `{entry}` must list prompt and completion"))?;
+                let parse = |value: &str| {
+                    value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|price| price.is_finite() && *price >= 0.0)
+                        .ok_or_else(|| format!("price `{entry}` has an invalid amount"))
+                };
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    prompt_per_million: parse(prompt)?,
+                    completion_per_million: parse(completion)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Per-model token prices used to turn usage into spend.
+#[derive(Debug, Clone, Default)]
+pub struct PricingTable {
+    prices: Vec<ModelPrice>,
+}
+
+impl PricingTable {
+    pub fn new(prices: Vec<ModelPrice>) -> Self {
+        Self { prices }
+    }
+
+    pub fn from_env() -> Self {
+        let Ok(raw) = env::var("GATEWAY_MODEL_PRICING") else {
+            return Self::default();
+        };
+        Self::new(ModelPrice::parse_list(&raw).unwrap_or_else(|error| {
+            warn!(error = %error, "invalid GATEWAY_MODEL_PRICING, costs will not be reported");
+            Vec::new()
+        }))
+    }
+
+    /// Puts `prices` ahead of the configured rules, so they win for the models they match.
+    pub fn prepend(mut self, prices: Vec<ModelPrice>) -> Self {
+        self.prices.splice(0..0, prices);
+        self
+    }
+
+    /// Whether some rule prices `model`.
+    pub fn is_priced(&self, model: &str) -> bool {
+        self.price(model).is_some()
+    }
+
+    /// Cost of `usage` on `model`, or `None` when the model has no price.
+    pub fn cost_usd(&self, model: &str, usage: &Usage) -> Option<f64> {
+        let price = self.price(model)?;
+        Some(
+            (f64::from(usage.prompt_tokens) * price.prompt_per_million
+                + f64::from(usage.completion_tokens) * price.completion_per_million)
+                / 1_000_000.0,
+        )
+    }
+
+    fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices
+            .iter()
+            .find(|price| glob::matches(&price.pattern, model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModelPrice, PricingTable};
+    use crate::models::Usage;
+
+    #[test]
+    fn prices_usage_by_first_matching_model() {
+        let table = PricingTable::new(
+            ModelPrice::parse_list("gpt-4o-mini*=0.15:0.6, gpt-4o*=2.5:10").expect("valid prices"),
+        );
+        let usage = Usage::new(1_000_000, 500_000);
+
+        let cost = |model| table.cost_usd(model, &usage).expect("priced model");
+        assert!((cost("gpt-4o-mini-2024") - 0.45).abs() < 1e-9);
+        assert!((cost("gpt-4o") - 7.5).abs() < 1e-9);
+        assert_eq!(table.cost_usd("llama", &usage), None);
+        assert!(table.is_priced("gpt-4o-mini") && !table.is_priced("llama"));
+        assert!(ModelPrice::parse_list("gpt-4o=2.5").is_err());
+        assert!(ModelPrice::parse_list("gpt-4o=-1:2").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+
+use crate::models::{MessageRole, NormalizedChatRequest, NormalizedMessage};
+
+/// A system message the gateway adds to every request of a key or tenant, such as compliance
+/// boilerplate or a brand voice. It is applied before fingerprinting, so cached and coalesced
+/// responses are never shared between callers with different prompts.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemPromptPolicy {
+    pub content: String,
+    #[serde(default)]
+    pub mode: SystemPromptMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemPromptMode {
+    /// Goes ahead of the conversation, keeping any system messages the client sent.
+    #[default]
+    Prepend,
+    /// Drops the client's system messages and leads with the configured one instead.
+    Replace,
+}
+
+impl SystemPromptPolicy {
+    pub fn apply(&self, request: &mut NormalizedChatRequest) {
+        if self.mode == SystemPromptMode::Replace {
+            request
+                .messages
+                .retain(|message| message.role != MessageRole::System);
+        }
+        request.messages.insert(
+            0,
+            NormalizedMessage {
+                role: MessageRole::System,
+                content: self.content.clone(),
+                cache_control: None,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SystemPromptMode, SystemPromptPolicy};
+    use crate::models::{
+        ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+        Priority,
+    };
+
+    fn message(role: MessageRole, content: &str) -> NormalizedMessage {
+        NormalizedMessage {
+            role,
+            content: content.to_owned(),
+            cache_control: None,
+        }
+    }
+
+    fn request() -> NormalizedChatRequest {
+        NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "user_a".to_owned(),
+            model: "gpt-test".to_owned(),
+            messages: vec![
+                message(MessageRole::System, "be terse"),
+                message(MessageRole::User, "hello"),
+            ],
+            generation: GenerationParams {
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    fn contents(request: &NormalizedChatRequest) -> Vec<&str> {
+        request
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn prepends_or_replaces_the_client_system_prompt() {
+        let policy: SystemPromptPolicy =
+            serde_json::from_str(r#"{"content":"Follow policy."}"#).expect("valid policy");
+        assert_eq!(policy.mode, SystemPromptMode::Prepend);
+        let mut prepended = request();
+        policy.apply(&mut prepended);
+        assert_eq!(
+            contents(&prepended),
+            ["Follow policy.", "be terse", "hello"]
+        );
+
+        let policy = SystemPromptPolicy {
+            mode: SystemPromptMode::Replace,
+            ..policy
+        };
+        let mut replaced = request();
+        policy.apply(&mut replaced);
+        assert_eq!(contents(&replaced), ["Follow policy.", "hello"]);
+        assert_eq!(replaced.messages[0].role, MessageRole::System);
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{auth::RatePolicy, errors::AppError, state::AppState};
+
+/// What a runtime override applies to: one key (by the `key_id` shown in logs and usage
+/// records) or every key of a tenant through its shared quota.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum QuotaTarget {
+    Key(String),
+    Tenant(String),
+}
+
+impl QuotaTarget {
+    /// Parses the `/admin/quotas/{scope}/{id}` path segments.
+    pub fn parse(scope: &str, id: &str) -> Option<Self> {
+        match scope {
+            "keys" => Some(Self::Key(id.to_owned())),
+            "tenants" => Some(Self::Tenant(id.to_owned())),
+            _ => None,
+        }
+    }
+
+    fn scope(&self) -> &'static str {
+        match self {
+            Self::Key(_) => "key",
+            Self::Tenant(_) => "tenant",
+        }
+    }
+
+    fn id(&self) -> &str {
+        match self {
+            Self::Key(id) | Self::Tenant(id) => id,
+        }
+    }
+}
+
+/// Limits that replace the configured ones; unset dimensions keep the configured value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaUpdate {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    /// Lets the override lapse on its own, e.g. for a temporary raise.
+    pub ttl_secs: Option<u64>,
+}
+
+impl QuotaUpdate {
+    fn is_empty(&self) -> bool {
+        self.requests_per_minute.is_none()
+            && self.tokens_per_minute.is_none()
+            && self.tokens_per_day.is_none()
+    }
+}
+
+/// An active override as listed by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaEntry {
+    pub scope: &'static str,
+    pub id: String,
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    pub expires_at_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct QuotaOverride {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u64>,
+    tokens_per_day: Option<u64>,
+    expires_at_ms: Option<u64>,
+}
+
+impl QuotaOverride {
+    fn is_live(&self, now_ms: u64) -> bool {
+        self.expires_at_ms.is_none_or(|expires| now_ms < expires)
+    }
+
+    fn apply(&self, base: &RatePolicy) -> RatePolicy {
+        RatePolicy {
+            requests_per_minute: self.requests_per_minute.unwrap_or(base.requests_per_minute),
+            tokens_per_minute: self.tokens_per_minute.unwrap_or(base.tokens_per_minute),
+            tokens_per_day: self.tokens_per_day.unwrap_or(base.tokens_per_day),
+        }
+    }
+}
+
+/// Quota overrides set through the admin API, consulted by the limiter on every request so
+/// changes apply without a restart. They live in this process only and are lost on restart.
+#[derive(Debug, Default)]
+pub struct QuotaOverrides {
+    entries: RwLock<HashMap<QuotaTarget, QuotaOverride>>,
+}
+
+impl QuotaOverrides {
+    pub fn set(&self, target: QuotaTarget, update: QuotaUpdate) -> QuotaEntry {
+        let now_ms = unix_millis();
+        let quota = QuotaOverride {
+            requests_per_minute: update.requests_per_minute,
+            tokens_per_minute: update.tokens_per_minute,
+            tokens_per_day: update.tokens_per_day,
+            expires_at_ms: update
+                .ttl_secs
+                .map(|ttl| now_ms.saturating_add(Duration::from_secs(ttl).as_millis() as u64)),
+        };
+        let entry = entry(&target, &quota);
+        let mut entries = self.write();
+        entries.retain(|_, quota| quota.is_live(now_ms));
+        entries.insert(target, quota);
+        entry
+    }
+
+    /// Drops the override; `false` when there was none.
+    pub fn remove(&self, target: &QuotaTarget) -> bool {
+        self.write().remove(target).is_some()
+    }
+
+    pub fn get(&self, target: &QuotaTarget) -> Option<QuotaEntry> {
+        self.live(target).map(|quota| entry(target, &quota))
+    }
+
+    pub fn list(&self) -> Vec<QuotaEntry> {
+        let now_ms = unix_millis();
+        let entries = self.read();
+        let mut live = entries
+            .iter()
+            .filter(|(_, quota)| quota.is_live(now_ms))
+            .collect::<Vec<_>>();
+        live.sort_by_key(|(target, _)| *target);
+        live.into_iter()
+            .map(|(target, quota)| entry(target, quota))
+            .collect()
+    }
+
+    /// The key's configured policy with any override applied.
+    pub fn key_policy(&self, key_id: &str, base: &RatePolicy) -> RatePolicy {
+        match self.live(&QuotaTarget::Key(key_id.to_owned())) {
+            Some(quota) => quota.apply(base),
+            None => base.clone(),
+        }
+    }
+
+    /// The tenant's shared quota with any override applied; an override also gives a tenant
+    /// without a configured quota one, unlimited in the dimensions it leaves unset.
+    pub fn tenant_quota(&self, tenant_id: &str, base: Option<RatePolicy>) -> Option<RatePolicy> {
+        let Some(quota) = self.live(&QuotaTarget::Tenant(tenant_id.to_owned())) else {
+            return base;
+        };
+        Some(quota.apply(&base.unwrap_or(RatePolicy {
+            requests_per_minute: u32::MAX,
+            tokens_per_minute: u64::MAX,
+            tokens_per_day: u64::MAX,
+        })))
+    }
+
+    fn live(&self, target: &QuotaTarget) -> Option<QuotaOverride> {
+        self.read()
+            .get(target)
+            .filter(|quota| quota.is_live(unix_millis()))
+            .cloned()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<QuotaTarget, QuotaOverride>> {
+        self.entries
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<QuotaTarget, QuotaOverride>> {
+        self.entries
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn entry(target: &QuotaTarget, quota: &QuotaOverride) -> QuotaEntry {
+    QuotaEntry {
+        scope: target.scope(),
+        id: target.id().to_owned(),
+        requests_per_minute: quota.requests_per_minute,
+        tokens_per_minute: quota.tokens_per_minute,
+        tokens_per_day: quota.tokens_per_day,
+        expires_at_ms: quota.expires_at_ms,
+    }
+}
+
+/// `GET /admin/quotas`: every active override.
+pub async fn list_quotas(State(state): State<AppState>) -> Json<Vec<QuotaEntry>> {
+    Json(state.quotas.list())
+}
+
+/// `GET /admin/quotas/{keys|tenants}/{id}`: the override, or `404` when the target runs on
+/// its configured limits.
+pub async fn get_quota(
+    State(state): State<AppState>,
+    Path((scope, id)): Path<(String, String)>,
+) -> Response {
+    match target(&scope, &id) {
+        Ok(target) => match state.quotas.get(&target) {
+            Some(entry) => Json(entry).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(error) => error.into_response(),
+    }
+}
+
+/// `PUT /admin/quotas/{keys|tenants}/{id}`: replaces the override for the target.
+pub async fn put_quota(
+    State(state): State<AppState>,
+    Path((scope, id)): Path<(String, String)>,
+    payload: Result<Json<QuotaUpdate>, JsonRejection>,
+) -> Response {
+    let result = target(&scope, &id).and_then(|target| {
+        let Json(update) =
+            payload.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+        if update.is_empty() {
+            return Err(AppError::BadRequest(
+                "set at least one of requests_per_minute, tokens_per_minute, tokens_per_day"
+                    .to_owned(),
+            ));
+        }
+        Ok(state.quotas.set(target, update))
+    });
+    match result {
+        Ok(entry) => {
+            info!(
+                scope = entry.scope,
+                id = %entry.id,
+                requests_per_minute = entry.requests_per_minute,
+                tokens_per_minute = entry.tokens_per_minute,
+                tokens_per_day = entry.tokens_per_day,
+                expires_at_ms = entry.expires_at_ms,
+                "quota override set"
+            );
+            Json(entry).into_response()
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+/// `DELETE /admin/quotas/{keys|tenants}/{id}`: restores the configured limits.
+pub async fn delete_quota(
+    State(state): State<AppState>,
+    Path((scope, id)): Path<(String, String)>,
+) -> Response {
+    match target(&scope, &id) {
+        Ok(target) => {
+            if state.quotas.remove(&target) {
+                info!(scope = target.scope(), id = %id, "quota override removed");
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+fn target(scope: &str, id: &str) -> Result<QuotaTarget, AppError> {
+    QuotaTarget::parse(scope, id).ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "unknown quota scope `{scope}`, expected `keys` or `tenants`"
+        ))
+    })
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaOverrides, QuotaTarget, QuotaUpdate};
+    use crate::auth::RatePolicy;
+
+    #[test]
+    fn overrides_replace_only_the_dimensions_they_set() {
+        let quotas = QuotaOverrides::default();
+        let base = RatePolicy::default();
+        quotas.set(
+            QuotaTarget::Key("key_abc".to_owned()),
+            QuotaUpdate {
+                tokens_per_minute: Some(1_000_000),
+                ..QuotaUpdate::default()
+            },
+        );
+        let raised = quotas.key_policy("key_abc", &base);
+        assert_eq!(raised.tokens_per_minute, 1_000_000);
+        assert_eq!(raised.requests_per_minute, base.requests_per_minute);
+        assert_eq!(
+            quotas.key_policy("key_other", &base).tokens_per_minute,
+            base.tokens_per_minute
+        );
+
+        assert!(quotas.tenant_quota("acme", None).is_none());
+        quotas.set(
+            QuotaTarget::Tenant("acme".to_owned()),
+            QuotaUpdate {
+                requests_per_minute: Some(0),
+                ..QuotaUpdate::default()
+            },
+        );
+        let frozen = quotas.tenant_quota("acme", None).expect("tenant quota");
+        assert_eq!(frozen.requests_per_minute, 0);
+        assert_eq!(frozen.tokens_per_day, u64::MAX);
+        assert_eq!(quotas.list().len(), 2);
+
+        assert!(quotas.remove(&QuotaTarget::Key("key_abc".to_owned())));
+        assert!(!quotas.remove(&QuotaTarget::Key("key_abc".to_owned())));
+    }
+
+    #[test]
+    fn expired_overrides_stop_applying() {
+        let quotas = QuotaOverrides::default();
+        quotas.set(
+            QuotaTarget::Key("key_abc".to_owned()),
+            QuotaUpdate {
+                requests_per_minute: Some(1),
+                ttl_secs: Some(0),
+                ..QuotaUpdate::default()
+            },
+        );
+        let base = RatePolicy::default();
+        assert_eq!(
+            quotas.key_policy("key_abc", &base).requests_per_minute,
+            base.requests_per_minute
+        );
+        assert!(quotas.list().is_empty());
+    }
+}
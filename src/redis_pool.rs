@@ -0,0 +1,418 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use redis::{
+    aio::{ConnectionLike, MultiplexedConnection},
+    cluster::ClusterClient,
+    cluster_async::ClusterConnection,
+    sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType},
+    Cmd, ErrorKind, IntoConnectionInfo, Pipeline, RedisError, RedisFuture, RedisResult, Value,
+};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, timeout},
+};
+use tracing::{info, warn};
+
+use crate::metrics::AppMetrics;
+
+/// How long a failed connection attempt is remembered, so an outage costs one connect attempt
+/// per interval instead of one per request.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest a connect attempt may take, so an unreachable host fails like a refused one.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The Redis connection shared by the rate limiter, response cache, and credit ledger.
+///
+/// Redis pipelines concurrent commands over one multiplexed connection, so a single connection
+/// serves every request. It is opened on first use and replaced after an I/O failure;
+/// `gateway_redis_up` reports whether it is currently usable. Behind Sentinel, replacing it
+/// asks the sentinels for the current master, so a failover costs one failed operation.
+///
+/// One caller at a time connects, without holding the state lock; the others fail at once
+/// rather than queue behind a connect that may take until [`CONNECT_TIMEOUT`].
+pub struct RedisPool {
+    client: RedisClient,
+    prefix: String,
+    state: std::sync::Mutex<ConnectionState>,
+    metrics: Arc<AppMetrics>,
+}
+
+#[derive(Default)]
+struct ConnectionState {
+    connection: Option<RedisConnection>,
+    retry_after: Option<Instant>,
+    connecting: bool,
+}
+
+/// Clears [`ConnectionState::connecting`] however the connect attempt ends, including by its
+/// caller being dropped mid-attempt.
+struct ConnectAttempt<'a>(&'a RedisPool);
+
+impl Drop for ConnectAttempt<'_> {
+    fn drop(&mut self) {
+        self.0.lock().connecting = false;
+    }
+}
+
+/// Where Redis runs, as `REDIS_URL` describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisTopology {
+    /// `redis://` (or any other URL the client accepts): a single node.
+    Standalone(String),
+    /// `redis+cluster://[user:pass@]host:port,host:port`: seed nodes of a Redis Cluster, each
+    /// reached with the same credentials.
+    Cluster(Vec<String>),
+    /// `redis+sentinel://[user:pass@]host:port,host:port/<service>[/<db>]`: the sentinels
+    /// watching `service`. The credentials and database apply to its master.
+    Sentinel {
+        sentinels: Vec<String>,
+        service: String,
+        /// URL carrying the master's credentials and database; its host is not used.
+        master: String,
+    },
+}
+
+impl RedisTopology {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+        let Some((scheme, rest)) = url
+            .split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "redis+cluster" | "redis+sentinel"))
+        else {
+            return Ok(Self::Standalone(url.to_owned()));
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (userinfo, hosts) = match authority.rsplit_once('@') {
+            Some((userinfo, hosts)) => (format!("{userinfo}@"), hosts),
+            None => (String::new(), authority),
+        };
+        let hosts = hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .collect::<Vec<_>>();
+        if hosts.is_empty() {
+            return Err(format!("`{scheme}` URL names no hosts"));
+        }
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        if scheme == "redis+cluster" {
+            if segments.next().is_some() {
+                return Err("Redis Cluster has a single database, drop the URL path".to_owned());
+            }
+            return Ok(Self::Cluster(
+                hosts
+                    .iter()
+                    .map(|host| format!("redis://{userinfo}{host}"))
+                    .collect(),
+            ));
+        }
+        let service = segments
+            .next()
+            .ok_or("`redis+sentinel` URL needs the master's service name as its path")?;
+        let db = match segments.next() {
+            Some(db) => db
+                .parse::<i64>()
+                .map_err(|_| format!("invalid database `{db}` in `redis+sentinel` URL"))?,
+            None => 0,
+        };
+        Ok(Self::Sentinel {
+            sentinels: hosts.iter().map(|host| format!("redis://{host}")).collect(),
+            service: service.to_owned(),
+            master: format!("redis://{userinfo}{service}/{db}"),
+        })
+    }
+}
+
+enum RedisClient {
+    Standalone(redis::Client),
+    Cluster(ClusterClient),
+    /// Locked because resolving the master needs the client mutably.
+    Sentinel(Mutex<SentinelClient>),
+}
+
+impl RedisClient {
+    fn open(topology: &RedisTopology) -> RedisResult<Self> {
+        Ok(match topology {
+            RedisTopology::Standalone(url) => Self::Standalone(redis::Client::open(url.as_str())?),
+            RedisTopology::Cluster(nodes) => Self::Cluster(ClusterClient::new(nodes.clone())?),
+            RedisTopology::Sentinel {
+                sentinels,
+                service,
+                master,
+            } => Self::Sentinel(Mutex::new(SentinelClient::build(
+                sentinels.clone(),
+                service.clone(),
+                Some(SentinelNodeConnectionInfo {
+                    tls_mode: None,
+                    redis_connection_info: Some(master.as_str().into_connection_info()?.redis),
+                }),
+                SentinelServerType::Master,
+            )?)),
+        })
+    }
+
+    async fn connect(&self) -> RedisResult<RedisConnection> {
+        Ok(match self {
+            Self::Standalone(client) => {
+                RedisConnection::Node(client.get_multiplexed_async_connection().await?)
+            }
+            Self::Cluster(client) => RedisConnection::Cluster(client.get_async_connection().await?),
+            Self::Sentinel(client) => {
+                RedisConnection::Node(client.lock().await.get_async_connection().await?)
+            }
+        })
+    }
+}
+
+/// A handle to the shared connection: one node's, or the cluster's, which routes each command
+/// to the node owning its keys.
+#[derive(Clone)]
+pub enum RedisConnection {
+    Node(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Self::Node(connection) => connection.req_packed_command(cmd),
+            Self::Cluster(connection) => connection.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Self::Node(connection) => connection.req_packed_commands(cmd, offset, count),
+            Self::Cluster(connection) => connection.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Node(connection) => connection.get_db(),
+            Self::Cluster(connection) => connection.get_db(),
+        }
+    }
+}
+
+impl RedisPool {
+    /// Reads `REDIS_URL` and `GATEWAY_REDIS_PREFIX`; `None` when no URL is set or it does not
+    /// parse, in which case every subsystem keeps its state in memory.
+    pub fn from_env(metrics: Arc<AppMetrics>) -> Option<Arc<Self>> {
+        let url = env::var("REDIS_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())?;
+        let prefix = env::var("GATEWAY_REDIS_PREFIX").unwrap_or_else(|_| "gateway".to_owned());
+        let opened = RedisTopology::parse(&url).and_then(|topology| {
+            Self::open(&topology, prefix, metrics).map_err(|error| error.to_string())
+        });
+        match opened {
+            Ok(pool) => Some(Arc::new(pool)),
+            Err(error) => {
+                warn!(error = %error, "invalid REDIS_URL, keeping limits, cache, and credits in memory");
+                None
+            }
+        }
+    }
+
+    pub fn new(client: redis::Client, prefix: impl Into<String>, metrics: Arc<AppMetrics>) -> Self {
+        Self::with_client(RedisClient::Standalone(client), prefix.into(), metrics)
+    }
+
+    /// A pool for a standalone node, a cluster, or a Sentinel-managed master.
+    pub fn open(
+        topology: &RedisTopology,
+        prefix: impl Into<String>,
+        metrics: Arc<AppMetrics>,
+    ) -> RedisResult<Self> {
+        Ok(Self::with_client(
+            RedisClient::open(topology)?,
+            prefix.into(),
+            metrics,
+        ))
+    }
+
+    fn with_client(client: RedisClient, prefix: String, metrics: Arc<AppMetrics>) -> Self {
+        Self {
+            client,
+            prefix,
+            state: std::sync::Mutex::new(ConnectionState::default()),
+            metrics,
+        }
+    }
+
+    /// Namespace every key is stored under.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ConnectionState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// A handle to the shared connection, connecting first if there is none. Fails at once
+    /// while another caller is connecting or a recent failed attempt is backing off.
+    pub async fn connection(&self) -> RedisResult<RedisConnection> {
+        let attempt = {
+            let mut state = self.lock();
+            if let Some(connection) = &state.connection {
+                return Ok(connection.clone());
+            }
+            if state.connecting {
+                return Err(RedisError::from((
+                    ErrorKind::IoError,
+                    "redis unavailable, reconnecting",
+                )));
+            }
+            if state.retry_after.is_some_and(|at| Instant::now() < at) {
+                return Err(RedisError::from((
+                    ErrorKind::IoError,
+                    "redis unavailable, waiting to reconnect",
+                )));
+            }
+            state.connecting = true;
+            ConnectAttempt(self)
+        };
+        let connected = timeout(CONNECT_TIMEOUT, self.client.connect())
+            .await
+            .unwrap_or_else(|_| {
+                Err(RedisError::from((
+                    ErrorKind::IoError,
+                    "redis connect timed out",
+                )))
+            });
+        // The outcome is recorded before the attempt ends, so no caller slips in between.
+        let mut state = self.lock();
+        let result = match connected {
+            Ok(connection) => {
+                info!("redis connection established");
+                state.connection = Some(connection.clone());
+                state.retry_after = None;
+                self.metrics.set_redis_up(true);
+                Ok(connection)
+            }
+            Err(error) => {
+                state.retry_after = Some(Instant::now() + RECONNECT_BACKOFF);
+                self.metrics.set_redis_up(false);
+                Err(error)
+            }
+        };
+        drop(state);
+        drop(attempt);
+        result
+    }
+
+    /// Passes an operation's result through, dropping the shared connection when the error
+    /// means it is broken so the next operation reconnects.
+    pub async fn track<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+        if let Err(error) = &result {
+            if error.is_io_error() || error.is_connection_dropped() {
+                if self.lock().connection.take().is_some() {
+                    warn!(error = %error, "redis connection lost, reconnecting on next use");
+                }
+                self.metrics.set_redis_up(false);
+            }
+        }
+        result
+    }
+
+    /// Pings Redis every `interval` so `gateway_redis_up` stays current and a lost connection
+    /// is re-established before traffic needs it.
+    pub fn spawn_health_checks(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                let result = match self.connection().await {
+                    Ok(mut connection) => {
+                        redis::cmd("PING").query_async::<()>(&mut connection).await
+                    }
+                    Err(error) => Err(error),
+                };
+                if self.track(result).await.is_ok() {
+                    self.metrics.set_redis_up(true);
+                }
+                sleep(interval).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ConnectAttempt, RedisPool, RedisTopology};
+    use crate::metrics::AppMetrics;
+
+    #[test]
+    fn parses_cluster_and_sentinel_urls() {
+        assert_eq!(
+            RedisTopology::parse("redis://:pw@redis:6379/2"),
+            Ok(RedisTopology::Standalone(
+                "redis://:pw@redis:6379/2".to_owned()
+            ))
+        );
+        assert_eq!(
+            RedisTopology::parse("redis+cluster://:pw@node-a:7000,node-b:7001"),
+            Ok(RedisTopology::Cluster(vec![
+                "redis://:pw@node-a:7000".to_owned(),
+                "redis://:pw@node-b:7001".to_owned(),
+            ]))
+        );
+        assert_eq!(
+            RedisTopology::parse("redis+sentinel://:pw@s1:26379,s2:26379/primary/3"),
+            Ok(RedisTopology::Sentinel {
+                sentinels: vec!["redis://s1:26379".to_owned(), "redis://s2:26379".to_owned()],
+                service: "primary".to_owned(),
+                master: "redis://:pw@primary/3".to_owned(),
+            })
+        );
+        assert!(RedisTopology::parse("redis+sentinel://s1:26379").is_err());
+        assert!(RedisTopology::parse("redis+cluster://node-a:7000/1").is_err());
+        assert!(RedisTopology::parse("redis+cluster://").is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_connects_back_off_and_report_down() {
+        let metrics = Arc::new(AppMetrics::new());
+        let client = redis::Client::open("redis://127.0.0.1:1/").expect("valid url");
+        let pool = RedisPool::new(client, "gateway", metrics.clone());
+
+        assert!(pool.connection().await.is_err());
+        let error = pool.connection().await.expect_err("still backing off");
+        assert!(error.to_string().contains("waiting to reconnect"));
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_redis_up 0"));
+    }
+
+    #[tokio::test]
+    async fn callers_fail_fast_while_another_is_connecting() {
+        let client = redis::Client::open("redis://127.0.0.1:1/").expect("valid url");
+        let pool = RedisPool::new(client, "gateway", Arc::new(AppMetrics::new()));
+
+        pool.lock().connecting = true;
+        let attempt = ConnectAttempt(&pool);
+        let error = pool
+            .connection()
+            .await
+            .expect_err("another caller is connecting");
+        assert!(error.to_string().contains("reconnecting"), "{error}");
+
+        // An attempt abandoned mid-connect lets the next caller try again.
+        drop(attempt);
+        let error = pool.connection().await.expect_err("nothing listens");
+        assert!(!error.to_string().contains("reconnecting"), "{error}");
+    }
+}
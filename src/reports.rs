@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AppError, state::AppState, usage_sink::KeyUsageTotals};
+
+const MS_PER_DAY: u64 = 86_400_000;
+
+/// Query of `GET /admin/reports/costs`. `from` and `to` take a UTC date (`2026-03-01`) or
+/// unix seconds; `to` is exclusive and defaults to now.
+#[derive(Debug, Default, Deserialize)]
+pub struct CostReportParams {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// `json` or `csv`; without it an `Accept: text/csv` header selects CSV.
+    pub format: Option<String>,
+}
+
+/// Spend per tenant, and per key within each tenant, over one window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostReport {
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub currency: &'static str,
+    pub total_cost_usd: f64,
+    pub tenants: Vec<TenantCosts>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantCosts {
+    pub tenant_id: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+    pub keys: Vec<KeyUsageTotals>,
+}
+
+impl CostReport {
+    /// Rolls key totals up into tenants; tenants and keys are listed by descending spend.
+    pub fn build(from_ms: u64, to_ms: u64, rows: Vec<KeyUsageTotals>) -> Self {
+        let mut tenants = BTreeMap::<String, TenantCosts>::new();
+        for row in rows {
+            let tenant = tenants
+                .entry(row.tenant_id.clone())
+                .or_insert_with(|| TenantCosts {
+                    tenant_id: row.tenant_id.clone(),
+                    requests: 0,
+                    prompt_tokens: 0,
+                    completion_tokens: 0,
+                    total_tokens: 0,
+                    cost_usd: 0.0,
+                    keys: Vec::new(),
+                });
+            tenant.requests += row.requests;
+            tenant.prompt_tokens += row.prompt_tokens;
+            tenant.completion_tokens += row.completion_tokens;
+            tenant.total_tokens += row.total_tokens;
+            tenant.cost_usd += row.cost_usd;
+            tenant.keys.push(row);
+        }
+        let mut tenants = tenants.into_values().collect::<Vec<_>>();
+        for tenant in &mut tenants {
+            tenant.keys.sort_by(|a, b| {
+                b.cost_usd
+                    .total_cmp(&a.cost_usd)
+                    .then(a.key_id.cmp(&b.key_id))
+            });
+        }
+        // Stable sort, so equal spend keeps the map's tenant-id order.
+        tenants.sort_by(|a, b| b.cost_usd.total_cmp(&a.cost_usd));
+        Self {
+            from_ms,
+            to_ms,
+            currency: "USD",
+            total_cost_usd: tenants.iter().map(|tenant| tenant.cost_usd).sum(),
+            tenants,
+        }
+    }
+
+    /// One row per key, ready for a spreadsheet or billing import.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "tenant_id,key_id,requests,prompt_tokens,completion_tokens,total_tokens,cost_usd\n",
+        );
+        for key in self.tenants.iter().flat_map(|tenant| &tenant.keys) {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{:.6}\n",
+                csv_field(&key.tenant_id),
+                csv_field(&key.key_id),
+                key.requests,
+                key.prompt_tokens,
+                key.completion_tokens,
+                key.total_tokens,
+                key.cost_usd
+            ));
+        }
+        csv
+    }
+}
+
+/// Serves the chargeback report from the usage store; needs a sink that can be queried.
+pub async fn cost_report(
+    State(state): State<AppState>,
+    Query(params): Query<CostReportParams>,
+    headers: HeaderMap,
+) -> Response {
+    let csv = match wants_csv(&params, &headers) {
+        Ok(csv) => csv,
+        Err(error) => return error.into_response(),
+    };
+    match build_report(&state, &params).await {
+        Ok(report) if csv => {
+            ([(CONTENT_TYPE, "text/csv; charset=utf-8")], report.to_csv()).into_response()
+        }
+        Ok(report) => Json(report).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+async fn build_report(state: &AppState, params: &CostReportParams) -> Result<CostReport, AppError> {
+    let from_ms = match params.from.as_deref() {
+        Some(raw) => parse_report_time(raw)?,
+        None => return Err(AppError::BadRequest("`from` is required".to_owned())),
+    };
+    let to_ms = match params.to.as_deref() {
+        Some(raw) => parse_report_time(raw)?,
+        None => unix_millis(),
+    };
+    if from_ms >= to_ms {
+        return Err(AppError::BadRequest(
+            "`from` must be before `to`".to_owned(),
+        ));
+    }
+    let query = state.usage_sink.query().ok_or_else(|| {
+        AppError::BadRequest(
+            "cost reports need a queryable usage store (GATEWAY_USAGE_SINK=clickhouse)".to_owned(),
+        )
+    })?;
+    let rows = query
+        .key_totals(from_ms, to_ms)
+        .await
+        .map_err(|error| AppError::Backend(format!("usage store query failed: {error}")))?;
+    Ok(CostReport::build(from_ms, to_ms, rows))
+}
+
+fn wants_csv(params: &CostReportParams, headers: &HeaderMap) -> Result<bool, AppError> {
+    match params.format.as_deref().map(str::trim) {
+        Some(format) if format.eq_ignore_ascii_case("csv") => Ok(true),
+        Some(format) if format.eq_ignore_ascii_case("json") => Ok(false),
+        Some(format) => Err(AppError::BadRequest(format!(
+            "unknown format `{format}`, expected `json` or `csv`"
+        ))),
+        None => Ok(headers
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/csv"))),
+    }
+}
+
+/// Parses a UTC `YYYY-MM-DD` date (its midnight) or unix seconds into unix milliseconds.
+fn parse_report_time(raw: &str) -> Result<u64, AppError> {
+    let raw = raw.trim();
+    let invalid = || {
+        AppError::BadRequest(format!(
+            "`{raw}` is not a YYYY-MM-DD date or unix timestamp"
+        ))
+    };
+    if let Ok(secs) = raw.parse::<u64>() {
+        return secs.checked_mul(1_000).ok_or_else(invalid);
+    }
+    let mut parts = raw.splitn(3, '-').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || day == 0 {
+        return Err(invalid());
+    }
+    if day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    Ok(days_since_epoch(year, month, day) * MS_PER_DAY)
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_since_epoch(year: u32, month: u32, day: u32) -> u64 {
+    let years = (1970..year)
+        .map(|year| if is_leap_year(year) { 366 } else { 365 })
+        .sum::<u64>();
+    let months = (1..month)
+        .map(|month| u64::from(days_in_month(year, month)))
+        .sum::<u64>();
+    years + months + u64::from(day - 1)
+}
+
+/// Quotes a field that would otherwise break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_report_time, CostReport};
+    use crate::usage_sink::KeyUsageTotals;
+
+    fn totals(tenant_id: &str, key_id: &str, requests: u64, cost_usd: f64) -> KeyUsageTotals {
+        KeyUsageTotals {
+            tenant_id: tenant_id.to_owned(),
+            key_id: key_id.to_owned(),
+            requests,
+            prompt_tokens: requests * 10,
+            completion_tokens: requests * 5,
+            total_tokens: requests * 15,
+            cost_usd,
+        }
+    }
+
+    #[test]
+    fn rolls_keys_up_into_tenants_by_spend() {
+        let report = CostReport::build(
+            0,
+            1_000,
+            vec![
+                totals("search", "key_a", 1, 0.25),
+                totals("acme", "key_b", 2, 1.0),
+                totals("acme", "key_c", 4, 2.0),
+            ],
+        );
+        assert_eq!(report.total_cost_usd, 3.25);
+        assert_eq!(report.tenants[0].tenant_id, "acme");
+        assert_eq!(report.tenants[0].requests, 6);
+        assert_eq!(report.tenants[0].total_tokens, 90);
+        assert_eq!(report.tenants[0].keys[0].key_id, "key_c");
+        assert_eq!(report.tenants[1].tenant_id, "search");
+
+        let csv = report.to_csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "tenant_id,key_id,requests,prompt_tokens,completion_tokens,total_tokens,cost_usd"
+        );
+        assert_eq!(lines[1], "acme,key_c,4,40,20,60,2.000000");
+        assert_eq!(lines.len(), 4);
+
+        let quoted = CostReport::build(0, 1, vec![totals("a,b", "key_\"x", 1, 0.0)]).to_csv();
+        assert!(quoted.contains("\"a,b\",\"key_\"\"x\""));
+    }
+
+    #[test]
+    fn parses_dates_and_unix_seconds() {
+        assert_eq!(parse_report_time("1970-01-02").ok(), Some(86_400_000));
+        assert_eq!(
+            parse_report_time("2024-03-01").ok(),
+            Some(1_709_251_200_000)
+        );
+        assert_eq!(
+            parse_report_time("1700000000").ok(),
+            Some(1_700_000_000_000)
+        );
+        assert!(parse_report_time("2023-02-29").is_err());
+        assert!(parse_report_time("2024-13-01").is_err());
+        assert!(parse_report_time("yesterday").is_err());
+    }
+}
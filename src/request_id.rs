@@ -0,0 +1,92 @@
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_LEN: usize = 128;
+
+/// Correlation id for one gateway request: client-supplied through `x-request-id` when it is
+/// well formed, otherwise generated. It is echoed in the response, logged, and sent upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(format!("req_{}", Uuid::new_v4()))
+    }
+
+    /// Accepts up to 128 characters of `[A-Za-z0-9._:-]`; anything else is replaced, not
+    /// rejected, so a bad client header never fails the request.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| is_valid(value))
+            .map(|value| Self(value.to_owned()))
+            .unwrap_or_else(Self::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn is_valid(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_LEN
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b':' | b'-'))
+}
+
+/// Resolves the request id, makes it available to handlers as an extension, and returns it in
+/// the `x-request-id` response header on every response, including extractor rejections.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let request_id = RequestId::from_headers(request.headers());
+    request.extensions_mut().insert(request_id.clone());
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{RequestId, REQUEST_ID_HEADER};
+
+    #[test]
+    fn accepts_well_formed_client_ids_and_replaces_others() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::from_static("trace-42:a_b.c"),
+        );
+        assert_eq!(RequestId::from_headers(&headers).as_str(), "trace-42:a_b.c");
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("has spaces"));
+        assert!(RequestId::from_headers(&headers)
+            .as_str()
+            .starts_with("req_"));
+
+        let oversized = "x".repeat(129);
+        headers.insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::from_str(&oversized).expect("header value"),
+        );
+        assert!(RequestId::from_headers(&headers)
+            .as_str()
+            .starts_with("req_"));
+        assert!(RequestId::from_headers(&HeaderMap::new())
+            .as_str()
+            .starts_with("req_"));
+    }
+}
@@ -1,4 +1,6 @@
 use std::{
+    collections::VecDeque,
+    env,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -7,36 +9,438 @@ use std::{
 };
 
 use async_trait::async_trait;
+use axum::{extract::State, Json};
+use futures_util::StreamExt;
+use serde::Serialize;
 use tokio::{
-    sync::Mutex,
+    sync::{Mutex, OwnedSemaphorePermit, Semaphore},
     time::{sleep, Instant},
 };
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
 use crate::{
-    backend::{BackendError, BackendStream, InferenceBackend},
+    backend::{
+        credentials::Credentials,
+        stream_with_deadline,
+        validation::{self, Problem},
+        with_deadline, BackendError, BackendStream, InferenceBackend,
+    },
+    clock::{Clock, SystemClock},
+    glob,
+    metrics::AppMetrics,
     models::{BackendChatResponse, NormalizedChatRequest},
+    state::AppState,
 };
 
+/// Failure classes a one-shot backend call can be retried on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    Unavailable,
+    Timeout,
+}
+
+impl RetryClass {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "unavailable" => Some(Self::Unavailable),
+            "timeout" => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                Self::parse(entry).ok_or_else(|| {
+                    format!("unknown retry class `{entry}`, expected `unavailable` or `timeout`")
+                })
+            })
+            .collect()
+    }
+
+    fn of(error: &BackendError) -> Option<Self> {
+        match error {
+            BackendError::Unavailable(_) => Some(Self::Unavailable),
+            BackendError::Timeout(_) => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Unavailable => "unavailable",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/// Retries for transient failures of one-shot calls. Each retry goes to a different endpoint
+/// when another healthy one exists; streams are never retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per request including the first; `1` disables retries.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each later one.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub retry_on: Vec<RetryClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            retry_on: vec![RetryClass::Unavailable, RetryClass::Timeout],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let read_u64 = |name| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        let retry_on = match env::var("GATEWAY_RETRY_ON") {
+            Ok(raw) => RetryClass::parse_list(&raw).unwrap_or_else(|error| {
+                warn!(error = %error, "invalid GATEWAY_RETRY_ON, retrying unavailable and timeout errors");
+                defaults.retry_on.clone()
+            }),
+            Err(_) => defaults.retry_on.clone(),
+        };
+        Self {
+            max_attempts: read_u64("GATEWAY_RETRY_MAX_ATTEMPTS")
+                .map(|attempts| attempts.clamp(1, u64::from(u32::MAX)) as u32)
+                .unwrap_or(defaults.max_attempts),
+            backoff: read_u64("GATEWAY_RETRY_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.backoff),
+            max_backoff: read_u64("GATEWAY_RETRY_MAX_BACKOFF_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_backoff),
+            retry_on,
+        }
+    }
+
+    /// The class to retry `error` under, if `attempt` (1-based) may be followed by another.
+    fn retry_class(&self, error: &BackendError, attempt: u32) -> Option<RetryClass> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        RetryClass::of(error).filter(|class| self.retry_on.contains(class))
+    }
+
+    /// Wait before retry number `retry` (1-based).
+    fn backoff_for(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+/// Whether a request with `deadline` still has time left after waiting `backoff`.
+fn fits_before(deadline: Option<std::time::Instant>, backoff: Duration) -> bool {
+    deadline.is_none_or(|deadline| std::time::Instant::now() + backoff < deadline)
+}
+
+/// Concurrency cap for endpoints whose name matches `pattern`; rules are evaluated in order
+/// and the first match wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointLimit {
+    pub pattern: String,
+    pub max_concurrency: usize,
+}
+
+impl EndpointLimit {
+    /// Parses `pattern=<max_concurrency>` entries separated by commas.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pattern, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("backend limit `{entry}` must be `pattern=limit`"))?;
+                let max_concurrency = value
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("backend limit `{entry}` has an invalid limit"))?;
+                Ok(Self {
+                    pattern: pattern.trim().to_owned(),
+                    max_concurrency,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Per-endpoint concurrency caps, and the spillover pool that takes what the preferred
+/// endpoints have no room for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointPoolConfig {
+    pub limits: Vec<EndpointLimit>,
+    /// Globs over endpoint names. Matching endpoints only get requests when every healthy
+    /// preferred endpoint is at its cap, or none is healthy.
+    pub spillover: Vec<String>,
+}
+
+impl EndpointPoolConfig {
+    pub fn from_env() -> Self {
+        let limits = env::var("GATEWAY_BACKEND_CONCURRENCY")
+            .ok()
+            .map(|raw| {
+                EndpointLimit::parse_list(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_BACKEND_CONCURRENCY, backends are uncapped");
+                    Vec::new()
+                })
+            })
+            .unwrap_or_default();
+        let spillover = env::var("GATEWAY_SPILLOVER_BACKENDS")
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { limits, spillover }
+    }
+}
+
+/// How much health-check history each endpoint keeps, and how an endpoint whose circuit
+/// opened earns its way back into rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealthConfig {
+    /// Health-check outcomes kept per endpoint for `GET /admin/backends`; `0` keeps none.
+    pub history: usize,
+    /// Successes in a row, from health checks or traffic, that fully close a circuit once its
+    /// cooldown has passed. Until then the endpoint is tried after its closed peers, and a
+    /// single failure opens it again.
+    pub recovery_successes: u32,
+}
+
+impl Default for EndpointHealthConfig {
+    fn default() -> Self {
+        Self {
+            history: 20,
+            recovery_successes: 3,
+        }
+    }
+}
+
+impl EndpointHealthConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let read = |name: &str| {
+            let raw = env::var(name).ok()?;
+            let parsed = raw.trim().parse::<u32>().ok();
+            if parsed.is_none() {
+                warn!("invalid {name}, using the default");
+            }
+            parsed
+        };
+        let recovery_successes = match read("GATEWAY_BACKEND_RECOVERY_SUCCESSES") {
+            Some(0) => {
+                warn!("GATEWAY_BACKEND_RECOVERY_SUCCESSES must be at least 1, using the default");
+                defaults.recovery_successes
+            }
+            Some(successes) => successes,
+            None => defaults.recovery_successes,
+        };
+        Self {
+            history: read("GATEWAY_BACKEND_HEALTH_HISTORY")
+                .map_or(defaults.history, |history| history as usize),
+            recovery_successes,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BackendRouter {
     endpoints: Arc<Vec<Endpoint>>,
     next_index: Arc<AtomicUsize>,
     failure_threshold: u32,
     cooldown: Duration,
+    health: EndpointHealthConfig,
+    retry: RetryPolicy,
+    metrics: Option<Arc<AppMetrics>>,
+    clock: Arc<dyn Clock>,
 }
 
 #[derive(Clone)]
 struct Endpoint {
     backend: Arc<dyn InferenceBackend>,
     health: Arc<Mutex<EndpointHealth>>,
+    /// Concurrent calls allowed, when the endpoint is capped.
+    slots: Option<Arc<Semaphore>>,
+    /// Only takes overflow from the preferred endpoints.
+    spillover: bool,
+}
+
+/// A call's hold on its endpoint's concurrency cap; empty for uncapped endpoints.
+struct EndpointSlot(Option<OwnedSemaphorePermit>);
+
+impl Endpoint {
+    /// Whether the provider behind the endpoint last reported a nearly spent budget.
+    fn low_on_budget(&self) -> bool {
+        self.backend
+            .provider_limits()
+            .is_some_and(|limits| limits.is_low(std::time::Instant::now()))
+    }
+
+    /// A slot if one is free right now.
+    fn try_slot(&self) -> Option<EndpointSlot> {
+        match &self.slots {
+            None => Some(EndpointSlot(None)),
+            Some(slots) => slots
+                .clone()
+                .try_acquire_owned()
+                .ok()
+                .map(|permit| EndpointSlot(Some(permit))),
+        }
+    }
+
+    async fn wait_for_slot(
+        &self,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<EndpointSlot, BackendError> {
+        let Some(slots) = &self.slots else {
+            return Ok(EndpointSlot(None));
+        };
+        let permit = with_deadline(deadline, "backend slot", async {
+            slots
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|_| BackendError::Unavailable("backend slots closed".to_owned()))
+        })
+        .await?;
+        Ok(EndpointSlot(Some(permit)))
+    }
 }
 
 #[derive(Debug, Default)]
 struct EndpointHealth {
     consecutive_failures: u32,
-    circuit_open_until: Option<Instant>,
+    circuit_open_until: Option<std::time::Instant>,
+    /// Successes so far toward closing a circuit whose cooldown has passed; `None` once the
+    /// circuit is closed.
+    recovering: Option<u32>,
     last_latency_ms: Option<u64>,
+    /// Until when the provider asked, with a `429` and `Retry-After`, not to be called.
+    throttled_until: Option<std::time::Instant>,
+    /// Latest health-check outcomes, oldest first.
+    history: VecDeque<HealthCheck>,
+}
+
+impl EndpointHealth {
+    /// Whether the endpoint may take calls at `now`. An open circuit whose cooldown has passed
+    /// starts recovering.
+    fn usable(&mut self, now: std::time::Instant) -> bool {
+        match self.circuit_open_until {
+            Some(until) if until > now => false,
+            Some(_) => {
+                self.circuit_open_until = None;
+                self.consecutive_failures = 0;
+                self.recovering = Some(0);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn throttled(&self, now: std::time::Instant) -> bool {
+        self.throttled_until.is_some_and(|until| until > now)
+    }
+
+    fn circuit(&self, now: std::time::Instant) -> CircuitState {
+        match self.circuit_open_until {
+            Some(until) if until > now => CircuitState::Open,
+            Some(_) => CircuitState::Recovering,
+            None if self.recovering.is_some() => CircuitState::Recovering,
+            None => CircuitState::Closed,
+        }
+    }
+}
+
+/// Where an endpoint's circuit breaker stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CircuitState {
+    Closed,
+    /// Cooling down after repeated failures; the endpoint gets no calls.
+    Open,
+    /// Past its cooldown, but not yet through enough successes in a row to be closed.
+    Recovering,
+}
+
+/// Successes in a row a recovering circuit has, out of the number that close it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecoveryProgress {
+    pub successes: u32,
+    pub needed: u32,
+}
+
+/// One health-check probe of an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthCheck {
+    pub at_unix_secs: u64,
+    pub healthy: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An endpoint's circuit and recent health checks, as `GET /admin/backends` reports them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndpointStatus {
+    pub name: String,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    /// Set while the circuit is recovering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<RecoveryProgress>,
+    pub last_latency_ms: Option<u64>,
+    /// Share of the checks in `history` that passed; `None` before the first check.
+    pub check_success_rate: Option<f64>,
+    pub history: Vec<HealthCheck>,
+}
+
+impl EndpointStatus {
+    /// A backend that is not behind a router: always in rotation, with no checks recorded.
+    pub fn untracked(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            circuit: CircuitState::Closed,
+            consecutive_failures: 0,
+            recovery: None,
+            last_latency_ms: None,
+            check_success_rate: None,
+            history: Vec::new(),
+        }
+    }
+}
+
+/// Body of `GET /admin/backends`.
+#[derive(Debug, Serialize)]
+pub struct BackendStatusReport {
+    pub backends: Vec<EndpointStatus>,
+}
+
+/// `GET /admin/backends`: every endpoint's circuit and health-check history.
+pub async fn backend_status(State(state): State<AppState>) -> Json<BackendStatusReport> {
+    Json(BackendStatusReport {
+        backends: state.backend.endpoint_status().await,
+    })
 }
 
 impl BackendRouter {
@@ -51,6 +455,8 @@ impl BackendRouter {
             .map(|backend| Endpoint {
                 backend,
                 health: Arc::new(Mutex::new(EndpointHealth::default())),
+                slots: None,
+                spillover: false,
             })
             .collect::<Vec<_>>();
 
@@ -59,9 +465,62 @@ impl BackendRouter {
             next_index: Arc::new(AtomicUsize::new(0)),
             failure_threshold: 3,
             cooldown: Duration::from_secs(20),
+            health: EndpointHealthConfig::default(),
+            retry: RetryPolicy::default(),
+            metrics: None,
+            clock: SystemClock::shared(),
         }
     }
 
+    /// Times circuit-breaker cooldowns with `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_health_config(mut self, health: EndpointHealthConfig) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Caps endpoint concurrency and marks the spillover pool, matching rules against each
+    /// endpoint's name.
+    pub fn with_endpoint_pools(mut self, config: &EndpointPoolConfig) -> Self {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let name = endpoint.backend.name();
+                Endpoint {
+                    backend: endpoint.backend.clone(),
+                    health: endpoint.health.clone(),
+                    slots: config
+                        .limits
+                        .iter()
+                        .find(|limit| glob::matches(&limit.pattern, name))
+                        .map(|limit| Arc::new(Semaphore::new(limit.max_concurrency))),
+                    spillover: config
+                        .spillover
+                        .iter()
+                        .any(|pattern| glob::matches(pattern, name)),
+                }
+            })
+            .collect();
+        self.endpoints = Arc::new(endpoints);
+        self
+    }
+
+    /// Records per-endpoint stream timing (TTFT, inter-chunk gaps, tokens/sec) in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<AppMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
     pub fn spawn_health_checks(self: Arc<Self>, interval: Duration) {
         tokio::spawn(async move {
             loop {
@@ -78,69 +537,304 @@ impl BackendRouter {
             let result = endpoint.backend.execute_chat(probe_request.clone()).await;
             let elapsed = started.elapsed().as_millis() as u64;
             let mut health = endpoint.health.lock().await;
-            match result {
-                Ok(_) => {
-                    health.consecutive_failures = 0;
-                    health.circuit_open_until = None;
-                    health.last_latency_ms = Some(elapsed);
-                }
+            match &result {
+                Ok(_) => self.succeed(&mut health, endpoint, elapsed),
                 Err(error) => {
-                    health.consecutive_failures = health.consecutive_failures.saturating_add(1);
-                    health.last_latency_ms = Some(elapsed);
-                    if health.consecutive_failures >= self.failure_threshold {
-                        health.circuit_open_until = Some(Instant::now() + self.cooldown);
-                    }
                     warn!(
                         backend = %endpoint.backend.name(),
                         error = %error,
-                        failures = health.consecutive_failures,
+                        failures = health.consecutive_failures.saturating_add(1),
                         "health check failed"
                     );
+                    self.fail(&mut health, endpoint, elapsed);
+                }
+            }
+            if self.health.history > 0 {
+                if health.history.len() >= self.health.history {
+                    health.history.pop_front();
                 }
+                health.history.push_back(HealthCheck {
+                    at_unix_secs: self.clock.unix_secs(),
+                    healthy: result.is_ok(),
+                    latency_ms: elapsed,
+                    error: result.err().map(|error| error.to_string()),
+                });
             }
         }
     }
 
-    async fn select_endpoint(&self) -> Result<Endpoint, BackendError> {
+    /// Healthy endpoints in round-robin order, passing over the indexes in `tried` unless they
+    /// are the only healthy ones left. Recovering endpoints come after closed ones, and
+    /// endpoints whose provider reports a nearly spent budget, or asked to be left alone after
+    /// a `429`, after the rest, so traffic moves off them before they start answering `429`.
+    /// A `pinned` backend name leaves only the endpoint of that name as a candidate.
+    async fn healthy_candidates(&self, tried: &[usize], pinned: Option<&str>) -> Vec<usize> {
         let total = self.endpoints.len();
         let start = self.next_index.fetch_add(1, Ordering::Relaxed);
-        let now = Instant::now();
+        let now = self.clock.now();
+        let mut fresh = Vec::with_capacity(total);
+        let mut retried = Vec::new();
+        let mut recovering = Vec::new();
+        let mut throttled = Vec::new();
 
         for offset in 0..total {
             let index = (start + offset) % total;
-            let endpoint = self.endpoints[index].clone();
-            let mut health = endpoint.health.lock().await;
+            if pinned.is_some_and(|name| name != self.endpoints[index].backend.name()) {
+                continue;
+            }
+            let mut health = self.endpoints[index].health.lock().await;
+            if !health.usable(now) {
+                continue;
+            }
+            if health.recovering.is_some() {
+                recovering.push(index);
+            }
+            if health.throttled(now) {
+                throttled.push(index);
+            }
+            drop(health);
 
-            if let Some(until) = health.circuit_open_until {
-                if until > now {
+            if tried.contains(&index) {
+                retried.push(index);
+            } else {
+                fresh.push(index);
+            }
+        }
+
+        let mut candidates = if fresh.is_empty() { retried } else { fresh };
+        candidates.sort_by_key(|&index| {
+            (
+                recovering.contains(&index),
+                throttled.contains(&index) || self.endpoints[index].low_on_budget(),
+            )
+        });
+        candidates
+    }
+
+    /// Picks an endpoint and takes a slot on it: the first healthy preferred endpoint with room,
+    /// else the first spillover endpoint with room. When every candidate is at its cap the call
+    /// waits for a slot on the first preferred one.
+    async fn select_endpoint(
+        &self,
+        tried: &[usize],
+        pinned: Option<&str>,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<(usize, Endpoint, EndpointSlot), BackendError> {
+        let candidates = self.healthy_candidates(tried, pinned).await;
+        if candidates.is_empty() {
+            return Err(BackendError::Unavailable(match pinned {
+                Some(name) => format!("backend `{name}` is currently unhealthy"),
+                None => "all backends are currently unhealthy".to_owned(),
+            }));
+        }
+        for spillover in [false, true] {
+            for &index in &candidates {
+                let endpoint = &self.endpoints[index];
+                if endpoint.spillover != spillover {
                     continue;
                 }
-                health.circuit_open_until = None;
-                health.consecutive_failures = 0;
+                if let Some(slot) = endpoint.try_slot() {
+                    if spillover {
+                        if let Some(metrics) = &self.metrics {
+                            metrics.observe_backend_spillover(endpoint.backend.name());
+                        }
+                    }
+                    return Ok((index, endpoint.clone(), slot));
+                }
             }
-            drop(health);
+        }
+        let index = candidates
+            .iter()
+            .copied()
+            .find(|&index| !self.endpoints[index].spillover)
+            .unwrap_or(candidates[0]);
+        let endpoint = self.endpoints[index].clone();
+        let slot = endpoint.wait_for_slot(deadline).await?;
+        Ok((index, endpoint, slot))
+    }
 
-            return Ok(endpoint);
+    fn observe_retry(&self, endpoint: &Endpoint, class: RetryClass, count: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_backend_retry(endpoint.backend.name(), class.as_str(), count);
         }
+    }
 
-        Err(BackendError::Unavailable(
-            "all backends are currently unhealthy".to_owned(),
-        ))
+    /// One batch call on `endpoint`, with its outcome recorded against the endpoint's health.
+    async fn batch_attempt(
+        &self,
+        endpoint: &Endpoint,
+        requests: Vec<Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        let deadline = batch_deadline(&requests);
+        let batch_size = requests.len();
+        let started = Instant::now();
+        let results = with_deadline(deadline, "batch call", async {
+            Ok(endpoint.backend.execute_chat_batch(requests).await)
+        })
+        .await
+        .map(|mut results| {
+            // A backend that answers for fewer members than it was sent fails the rest, so every
+            // member still gets a result.
+            if results.len() != batch_size {
+                warn!(
+                    router = self.name(),
+                    backend = %endpoint.backend.name(),
+                    batch_size,
+                    results = results.len(),
+                    "execute_chat_batch returned the wrong number of results"
+                );
+                results.resize_with(batch_size, || {
+                    Err(BackendError::InvalidResponse(
+                        "batch call returned no result for this request".to_owned(),
+                    ))
+                });
+            }
+            results
+                .into_iter()
+                .map(|result| {
+                    result
+                        .and_then(|response| self.check_response(endpoint, response))
+                        .map(|response| stamp_response(response, endpoint.backend.name()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|error| vec![Err(error); batch_size]);
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let rate_limited = results
+            .iter()
+            .filter_map(|result| match result {
+                Err(BackendError::RateLimited { retry_after, .. }) => Some(*retry_after),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !rate_limited.is_empty() {
+            self.observe_rate_limited(endpoint, rate_limited.len() as u64);
+        }
+        if let Some(retry_after) = rate_limited.into_iter().flatten().max() {
+            self.back_off(endpoint, retry_after).await;
+        }
+        if results.iter().any(Result::is_ok) {
+            self.mark_success(endpoint, latency_ms).await;
+        } else if !results.iter().all(|result| {
+            matches!(
+                result,
+                Err(BackendError::DeadlineExceeded(_)
+                    | BackendError::RateLimited { .. }
+                    | BackendError::BadRequest(_))
+            )
+        }) {
+            self.mark_failure(endpoint, latency_ms).await;
+        }
+
+        debug!(
+            router = self.name(),
+            backend = %endpoint.backend.name(),
+            latency_ms,
+            batch_size = results.len(),
+            "execute_chat_batch completed"
+        );
+
+        results
+    }
+
+    /// Client deadlines, requests the provider refused as malformed, and provider throttling
+    /// say nothing about endpoint health, so they never count as failures. A `429` with a
+    /// `Retry-After` moves the endpoint to the back of the line until the wait is over.
+    async fn record_outcome(
+        &self,
+        endpoint: &Endpoint,
+        error: Option<&BackendError>,
+        latency_ms: u64,
+    ) {
+        match error {
+            None => self.mark_success(endpoint, latency_ms).await,
+            Some(BackendError::DeadlineExceeded(_) | BackendError::BadRequest(_)) => {}
+            Some(BackendError::RateLimited { retry_after, .. }) => {
+                self.observe_rate_limited(endpoint, 1);
+                if let Some(retry_after) = retry_after {
+                    self.back_off(endpoint, *retry_after).await;
+                }
+            }
+            Some(_) => self.mark_failure(endpoint, latency_ms).await,
+        }
+    }
+
+    async fn back_off(&self, endpoint: &Endpoint, retry_after: Duration) {
+        let until = self.clock.now() + retry_after;
+        let mut health = endpoint.health.lock().await;
+        health.throttled_until = health.throttled_until.max(Some(until));
+    }
+
+    /// Repairs what it can of a malformed one-shot response and turns the rest into
+    /// `InvalidResponse`, which counts against the endpoint like any other failure.
+    fn check_response(
+        &self,
+        endpoint: &Endpoint,
+        mut response: BackendChatResponse,
+    ) -> Result<BackendChatResponse, BackendError> {
+        let backend = endpoint.backend.name();
+        let observe = |problem: Problem, action: &str| {
+            if let Some(metrics) = &self.metrics {
+                metrics.observe_backend_response_problem(backend, problem.as_str(), action);
+            }
+        };
+        match validation::validate(&mut response) {
+            Ok(repairs) => {
+                for problem in repairs {
+                    debug!(backend = %backend, problem = %problem, "repaired backend response");
+                    observe(problem, "repaired");
+                }
+                Ok(response)
+            }
+            Err(problem) => {
+                warn!(backend = %backend, problem = %problem, "rejected backend response");
+                observe(problem, "rejected");
+                Err(BackendError::InvalidResponse(problem.to_string()))
+            }
+        }
+    }
+
+    fn observe_rate_limited(&self, endpoint: &Endpoint, count: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_upstream_rate_limited(endpoint.backend.name(), count);
+        }
     }
 
     async fn mark_success(&self, endpoint: &Endpoint, latency_ms: u64) {
         let mut health = endpoint.health.lock().await;
-        health.consecutive_failures = 0;
-        health.circuit_open_until = None;
-        health.last_latency_ms = Some(latency_ms);
+        self.succeed(&mut health, endpoint, latency_ms);
     }
 
     async fn mark_failure(&self, endpoint: &Endpoint, latency_ms: u64) {
         let mut health = endpoint.health.lock().await;
+        self.fail(&mut health, endpoint, latency_ms);
+    }
+
+    /// A success cuts an open circuit's cooldown short, but the circuit only closes once it has
+    /// `recovery_successes` in a row.
+    fn succeed(&self, health: &mut EndpointHealth, endpoint: &Endpoint, latency_ms: u64) {
+        health.consecutive_failures = 0;
+        health.last_latency_ms = Some(latency_ms);
+        if health.circuit_open_until.take().is_some() {
+            health.recovering = Some(0);
+        }
+        if let Some(successes) = health.recovering.as_mut() {
+            *successes += 1;
+            if *successes >= self.health.recovery_successes {
+                health.recovering = None;
+                info!(backend = %endpoint.backend.name(), "circuit closed for backend");
+            }
+        }
+    }
+
+    /// Opens the circuit after `failure_threshold` failures in a row, or on the first failure
+    /// of an endpoint that is still recovering, so a flapping upstream stays out of rotation.
+    fn fail(&self, health: &mut EndpointHealth, endpoint: &Endpoint, latency_ms: u64) {
         health.consecutive_failures = health.consecutive_failures.saturating_add(1);
         health.last_latency_ms = Some(latency_ms);
-        if health.consecutive_failures >= self.failure_threshold {
-            health.circuit_open_until = Some(Instant::now() + self.cooldown);
+        if health.recovering.is_some() || health.consecutive_failures >= self.failure_threshold {
+            health.recovering = None;
+            health.circuit_open_until = Some(self.clock.now() + self.cooldown);
             warn!(
                 backend = %endpoint.backend.name(),
                 failures = health.consecutive_failures,
@@ -160,40 +854,95 @@ impl InferenceBackend for BackendRouter {
     #[tracing::instrument(skip(self, request), fields(model = %request.model))]
     async fn execute_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendChatResponse, BackendError> {
-        let endpoint = self.select_endpoint().await?;
-        let started = Instant::now();
-        let result = endpoint.backend.execute_chat(request).await;
-        let latency_ms = started.elapsed().as_millis() as u64;
-        match &result {
-            Ok(_) => self.mark_success(&endpoint, latency_ms).await,
-            Err(_) => self.mark_failure(&endpoint, latency_ms).await,
-        }
+        let deadline = request.deadline;
+        let mut tried = Vec::new();
+        let mut last_error = None;
+        let pinned = request.pinned_backend.as_deref();
+        for attempt in 1.. {
+            let selected = self.select_endpoint(&tried, pinned, deadline).await;
+            let (index, endpoint, slot) = match selected {
+                Ok(selected) => selected,
+                // A retry that finds nothing healthy reports the failure that prompted it.
+                Err(error) => return Err(last_error.unwrap_or(error)),
+            };
+            tried.push(index);
+            let started = Instant::now();
+            let result = with_deadline(
+                deadline,
+                "backend call",
+                endpoint.backend.execute_chat(request.clone()),
+            )
+            .await
+            .and_then(|response| self.check_response(&endpoint, response))
+            .map(|response| stamp_response(response, endpoint.backend.name()));
+            let latency_ms = started.elapsed().as_millis() as u64;
+            drop(slot);
+            self.record_outcome(&endpoint, result.as_ref().err(), latency_ms)
+                .await;
 
-        debug!(
-            router = self.name(),
-            backend = %endpoint.backend.name(),
-            latency_ms,
-            "execute_chat completed"
-        );
+            debug!(
+                router = self.name(),
+                backend = %endpoint.backend.name(),
+                latency_ms,
+                attempt,
+                "execute_chat completed"
+            );
 
-        result
+            let error = match result {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+            let backoff = self.retry.backoff_for(attempt);
+            match self.retry.retry_class(&error, attempt) {
+                Some(class) if fits_before(deadline, backoff) => {
+                    self.observe_retry(&endpoint, class, 1);
+                    sleep(backoff).await;
+                    last_error = Some(error);
+                }
+                _ => return Err(error),
+            }
+        }
+        unreachable!("the retry loop only exits by returning")
     }
 
     #[tracing::instrument(skip(self, request), fields(model = %request.model))]
     async fn stream_chat(
         &self,
-        request: NormalizedChatRequest,
+        request: Arc<NormalizedChatRequest>,
     ) -> Result<BackendStream, BackendError> {
-        let endpoint = self.select_endpoint().await?;
+        let deadline = request.deadline;
+        let (_, endpoint, slot) = self
+            .select_endpoint(&[], request.pinned_backend.as_deref(), deadline)
+            .await?;
         let started = Instant::now();
-        let result = endpoint.backend.stream_chat(request).await;
+        let model = request.model.clone();
+        let result = with_deadline(
+            deadline,
+            "stream start",
+            endpoint.backend.stream_chat(request),
+        )
+        .await
+        .map(|stream| {
+            let stream = stamp_stream(
+                hold_slot(stream_with_deadline(stream, deadline), slot),
+                endpoint.backend.name(),
+            );
+            match &self.metrics {
+                Some(metrics) => observe_stream_timing(
+                    stream,
+                    metrics.clone(),
+                    endpoint.backend.name(),
+                    &model,
+                    started,
+                ),
+                None => stream,
+            }
+        });
         let latency_ms = started.elapsed().as_millis() as u64;
-        match &result {
-            Ok(_) => self.mark_success(&endpoint, latency_ms).await,
-            Err(_) => self.mark_failure(&endpoint, latency_ms).await,
-        }
+        self.record_outcome(&endpoint, result.as_ref().err(), latency_ms)
+            .await;
 
         debug!(
             router = self.name(),
@@ -204,18 +953,253 @@ impl InferenceBackend for BackendRouter {
 
         result
     }
+
+    #[tracing::instrument(skip(self, requests), fields(batch_size = requests.len()))]
+    async fn execute_chat_batch(
+        &self,
+        requests: Vec<Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        let mut results = (0..requests.len()).map(|_| None).collect::<Vec<_>>();
+        let mut pending = (0..requests.len()).collect::<Vec<_>>();
+        let mut tried = Vec::new();
+        for attempt in 1.. {
+            let deadline = batch_deadline(pending.iter().map(|&member| &requests[member]));
+            // Requests pinned by header skip the batcher, and members share a model, so the
+            // first member's backend is the catalog's choice for the whole batch.
+            let pinned = pending
+                .first()
+                .and_then(|&member| requests[member].pinned_backend.as_deref());
+            let (index, endpoint, slot) = match self.select_endpoint(&tried, pinned, deadline).await
+            {
+                Ok(selected) => selected,
+                Err(error) => {
+                    for member in pending {
+                        results[member].get_or_insert_with(|| {
+                            Err(BackendError::Unavailable(error.to_string()))
+                        });
+                    }
+                    break;
+                }
+            };
+            tried.push(index);
+            let batch = pending
+                .iter()
+                .map(|&member| requests[member].clone())
+                .collect();
+            let outcome = self.batch_attempt(&endpoint, batch).await;
+            drop(slot);
+
+            // Members that failed transiently go to another endpoint together.
+            let backoff = self.retry.backoff_for(attempt);
+            let mut retry = Vec::new();
+            let mut retry_classes = Vec::new();
+            for (member, result) in pending.into_iter().zip(outcome) {
+                if let Err(error) = &result {
+                    if let Some(class) = self.retry.retry_class(error, attempt) {
+                        if fits_before(requests[member].deadline, backoff) {
+                            retry.push(member);
+                            retry_classes.push(class);
+                        }
+                    }
+                }
+                results[member] = Some(result);
+            }
+            if retry.is_empty() {
+                break;
+            }
+            for class in [RetryClass::Unavailable, RetryClass::Timeout] {
+                let count = retry_classes.iter().filter(|&&seen| seen == class).count();
+                if count > 0 {
+                    self.observe_retry(&endpoint, class, count as u64);
+                }
+            }
+            sleep(backoff).await;
+            pending = retry;
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every batch member has a result"))
+            .collect()
+    }
+
+    /// Healthy endpoints in the order `select_endpoint` would offer them: preferred endpoints
+    /// with room, spillover endpoints with room, then full ones, each with recovering
+    /// endpoints and then those low on provider budget or throttled last. Neither the
+    /// round-robin position nor any circuit is advanced.
+    async fn route_plan(&self) -> Vec<String> {
+        let total = self.endpoints.len();
+        let start = self.next_index.load(Ordering::Relaxed);
+        let now = self.clock.now();
+        let mut healthy = Vec::with_capacity(total);
+        for offset in 0..total {
+            let endpoint = &self.endpoints[(start + offset) % total];
+            let health = endpoint.health.lock().await;
+            match health.circuit(now) {
+                CircuitState::Open => {}
+                circuit => healthy.push((
+                    endpoint,
+                    circuit == CircuitState::Recovering,
+                    health.throttled(now),
+                )),
+            }
+        }
+        healthy.sort_by_key(|(endpoint, recovering, throttled)| {
+            let full = endpoint
+                .slots
+                .as_ref()
+                .is_some_and(|slots| slots.available_permits() == 0);
+            (
+                full,
+                endpoint.spillover,
+                *recovering,
+                *throttled || endpoint.low_on_budget(),
+            )
+        });
+        healthy
+            .into_iter()
+            .map(|(endpoint, ..)| endpoint.backend.name().to_owned())
+            .collect()
+    }
+
+    async fn endpoint_status(&self) -> Vec<EndpointStatus> {
+        let now = self.clock.now();
+        let mut statuses = Vec::with_capacity(self.endpoints.len());
+        for endpoint in self.endpoints.iter() {
+            let health = endpoint.health.lock().await;
+            let circuit = health.circuit(now);
+            let passed = health.history.iter().filter(|check| check.healthy).count();
+            statuses.push(EndpointStatus {
+                name: endpoint.backend.name().to_owned(),
+                circuit,
+                consecutive_failures: health.consecutive_failures,
+                recovery: (circuit == CircuitState::Recovering).then(|| RecoveryProgress {
+                    successes: health.recovering.unwrap_or_default(),
+                    needed: self.health.recovery_successes,
+                }),
+                last_latency_ms: health.last_latency_ms,
+                check_success_rate: (!health.history.is_empty())
+                    .then(|| passed as f64 / health.history.len() as f64),
+                history: health.history.iter().cloned().collect(),
+            });
+        }
+        statuses
+    }
+
+    fn credentials(&self, backend: &str) -> Option<Arc<Credentials>> {
+        self.endpoints
+            .iter()
+            .find_map(|endpoint| endpoint.backend.credentials(backend))
+    }
 }
 
-fn health_probe_request() -> NormalizedChatRequest {
-    use crate::models::{GenerationParams, MessageRole, NormalizedMessage};
+/// The batch is abandoned only once every member's client has given up.
+fn batch_deadline<'a>(
+    requests: impl IntoIterator<Item = &'a Arc<NormalizedChatRequest>>,
+) -> Option<std::time::Instant> {
+    requests
+        .into_iter()
+        .map(|request| request.deadline)
+        .collect::<Option<Vec<_>>>()
+        .and_then(|deadlines| deadlines.into_iter().max())
+}
+
+/// Keeps the endpoint slot taken until the stream is finished or dropped.
+fn hold_slot(stream: BackendStream, slot: EndpointSlot) -> BackendStream {
+    if slot.0.is_none() {
+        return stream;
+    }
+    stream
+        .map(move |item| {
+            let _slot = &slot;
+            item
+        })
+        .boxed()
+}
+
+fn stamp_response(mut response: BackendChatResponse, backend: &str) -> BackendChatResponse {
+    response.backend = Some(backend.to_owned());
+    response
+}
+
+fn stamp_stream(stream: BackendStream, backend: &str) -> BackendStream {
+    let backend = backend.to_owned();
+    let mut first = true;
+    stream
+        .map(move |item| {
+            item.map(|mut chunk| {
+                if first || chunk.done {
+                    chunk.backend = Some(backend.clone());
+                }
+                first = false;
+                chunk
+            })
+        })
+        .boxed()
+}
 
-    NormalizedChatRequest {
-        request_id: "health-probe".to_owned(),
+/// Times content chunks as they pass through: TTFT from dispatch to the first delta, the gap
+/// between each later delta, and completion tokens/sec from the first delta to the final chunk.
+fn observe_stream_timing(
+    stream: BackendStream,
+    metrics: Arc<AppMetrics>,
+    backend: &str,
+    model: &str,
+    started: Instant,
+) -> BackendStream {
+    let backend = backend.to_owned();
+    let model = model.to_owned();
+    let mut first_delta_at: Option<Instant> = None;
+    let mut last_delta_at = started;
+    stream
+        .map(move |item| {
+            if let Ok(chunk) = &item {
+                let now = Instant::now();
+                if chunk.delta.is_some() {
+                    match first_delta_at {
+                        None => {
+                            first_delta_at = Some(now);
+                            metrics.observe_stream_ttft(&backend, &model, now - started);
+                        }
+                        Some(_) => metrics.observe_stream_inter_chunk(
+                            &backend,
+                            &model,
+                            now - last_delta_at,
+                        ),
+                    }
+                    last_delta_at = now;
+                }
+                if let (true, Some(first), Some(usage)) = (chunk.done, first_delta_at, &chunk.usage)
+                {
+                    let elapsed = (now - first).as_secs_f64();
+                    if elapsed > 0.0 && usage.completion_tokens > 0 {
+                        metrics.observe_stream_tokens_per_second(
+                            &backend,
+                            &model,
+                            f64::from(usage.completion_tokens) / elapsed,
+                        );
+                    }
+                }
+            }
+            item
+        })
+        .boxed()
+}
+
+/// Request id of health-check probes, which adapters may want to tell apart from traffic.
+pub const HEALTH_PROBE_REQUEST_ID: &str = "health-probe";
+
+fn health_probe_request() -> Arc<NormalizedChatRequest> {
+    use crate::models::{ClientTags, GenerationParams, MessageRole, NormalizedMessage, Priority};
+
+    Arc::new(NormalizedChatRequest {
+        request_id: HEALTH_PROBE_REQUEST_ID.to_owned(),
         user_id: "system".to_owned(),
         model: "health-probe".to_owned(),
         messages: vec![NormalizedMessage {
             role: MessageRole::User,
             content: "healthcheck".to_owned(),
+            cache_control: None,
         }],
         generation: GenerationParams {
             max_tokens: Some(1),
@@ -223,5 +1207,729 @@ fn health_probe_request() -> NormalizedChatRequest {
             top_p: None,
         },
         stream: false,
+        priority: Priority::Normal,
+        deadline: None,
+        passthrough: false,
+        pinned_backend: None,
+        client: ClientTags::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+
+    use super::{
+        BackendRouter, CircuitState, EndpointHealthConfig, EndpointLimit, EndpointPoolConfig,
+        RecoveryProgress, RetryClass, RetryPolicy,
+    };
+    use crate::{
+        backend::{
+            mock::{MockBackend, MockError, MockFaultRule},
+            openai::{OpenAiAdapter, OpenAiConfig},
+            ratelimit::{Budget, ProviderLimits},
+            BackendError, BackendStream, InferenceBackend,
+        },
+        clock::ManualClock,
+        metrics::AppMetrics,
+        models::{
+            BackendChatResponse, ChatCompletionsRequest, MessageRole, NormalizedChatRequest,
+            OpenAiMessage, Usage,
+        },
+    };
+
+    /// Answers every call with the same response, however malformed.
+    struct CannedBackend(BackendChatResponse);
+
+    #[async_trait]
+    impl InferenceBackend for CannedBackend {
+        fn name(&self) -> &str {
+            "canned"
+        }
+
+        async fn execute_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            Ok(self.0.clone())
+        }
+
+        async fn stream_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            Err(BackendError::Unavailable(
+                "streams are not canned".to_owned(),
+            ))
+        }
+    }
+
+    /// Answers every call the way a provider over its quota does.
+    struct ThrottledBackend;
+
+    fn throttled() -> BackendError {
+        BackendError::RateLimited {
+            message: "quota exceeded".to_owned(),
+            headers: vec![("retry-after".to_owned(), "7".to_owned())],
+            retry_after: Some(Duration::from_secs(7)),
+        }
+    }
+
+    #[async_trait]
+    impl InferenceBackend for ThrottledBackend {
+        fn name(&self) -> &str {
+            "throttled"
+        }
+
+        async fn execute_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            Err(throttled())
+        }
+
+        async fn stream_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            Err(throttled())
+        }
+    }
+
+    /// Answers a batch for its first member only, as a misbehaving batch API might.
+    struct ShortBatchBackend(MockBackend);
+
+    #[async_trait]
+    impl InferenceBackend for ShortBatchBackend {
+        fn name(&self) -> &str {
+            "short-batch"
+        }
+
+        async fn execute_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            self.0.execute_chat(request).await
+        }
+
+        async fn execute_chat_batch(
+            &self,
+            mut requests: Vec<Arc<NormalizedChatRequest>>,
+        ) -> Vec<Result<BackendChatResponse, BackendError>> {
+            requests.truncate(1);
+            self.0.execute_chat_batch(requests).await
+        }
+
+        async fn stream_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            self.0.stream_chat(request).await
+        }
+    }
+
+    /// Refuses every call as if the endpoint were unreachable.
+    struct DownBackend;
+
+    #[async_trait]
+    impl InferenceBackend for DownBackend {
+        fn name(&self) -> &str {
+            "down"
+        }
+
+        async fn execute_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            Err(BackendError::Unavailable("connection refused".to_owned()))
+        }
+
+        async fn stream_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            Err(BackendError::Unavailable("connection refused".to_owned()))
+        }
+    }
+
+    /// Refuses calls until it is switched on, as an intermittent upstream does.
+    struct FlappingBackend(AtomicBool);
+
+    #[async_trait]
+    impl InferenceBackend for FlappingBackend {
+        fn name(&self) -> &str {
+            "flapping"
+        }
+
+        async fn execute_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            if !self.0.load(Ordering::Relaxed) {
+                return Err(BackendError::Unavailable("connection refused".to_owned()));
+            }
+            MockBackend::named("flapping").execute_chat(request).await
+        }
+
+        async fn stream_chat(
+            &self,
+            _request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            Err(BackendError::Unavailable("connection refused".to_owned()))
+        }
+    }
+
+    /// Serves calls while reporting that its provider has five requests left this minute.
+    struct NearlySpentBackend(MockBackend);
+
+    #[async_trait]
+    impl InferenceBackend for NearlySpentBackend {
+        fn name(&self) -> &str {
+            self.0.name()
+        }
+
+        async fn execute_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendChatResponse, BackendError> {
+            self.0.execute_chat(request).await
+        }
+
+        async fn stream_chat(
+            &self,
+            request: Arc<NormalizedChatRequest>,
+        ) -> Result<BackendStream, BackendError> {
+            self.0.stream_chat(request).await
+        }
+
+        fn provider_limits(&self) -> Option<ProviderLimits> {
+            Some(ProviderLimits {
+                requests: Some(Budget {
+                    limit: Some(500),
+                    remaining: 5,
+                    resets_at: std::time::Instant::now() + Duration::from_secs(60),
+                }),
+                tokens: None,
+            })
+        }
+    }
+
+    fn flaky_pool(retry: RetryPolicy, metrics: Arc<AppMetrics>) -> BackendRouter {
+        let down: Arc<dyn InferenceBackend> = Arc::new(DownBackend);
+        let up: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("up"));
+        BackendRouter::new(vec![down, up])
+            .with_metrics(metrics)
+            .with_retry_policy(retry)
+    }
+
+    fn chat_request(stream: bool) -> Arc<NormalizedChatRequest> {
+        Arc::new(
+            ChatCompletionsRequest {
+                model: "mock-1".to_owned(),
+                messages: vec![OpenAiMessage {
+                    role: MessageRole::User,
+                    content: "hello".to_owned(),
+                    cache_control: None,
+                }],
+                max_tokens: None,
+                temperature: None,
+                top_p: None,
+                stream,
+                user: None,
+                timeout: None,
+                stream_options: None,
+                metadata: None,
+            }
+            .into_normalized("user".to_owned())
+            .expect("valid request"),
+        )
+    }
+
+    #[tokio::test]
+    async fn batch_members_without_a_result_fail_instead_of_panicking() {
+        let backend: Arc<dyn InferenceBackend> =
+            Arc::new(ShortBatchBackend(MockBackend::named("short-batch")));
+        let router = BackendRouter::new(vec![backend]);
+
+        let batch = router
+            .execute_chat_batch(vec![
+                chat_request(false),
+                chat_request(false),
+                chat_request(false),
+            ])
+            .await;
+        assert_eq!(batch.len(), 3);
+        assert!(batch[0].is_ok());
+        for result in &batch[1..] {
+            assert!(
+                matches!(result, Err(BackendError::InvalidResponse(_))),
+                "{result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_rate_limits_are_counted_without_opening_the_circuit() {
+        let metrics = Arc::new(AppMetrics::new());
+        let backend: Arc<dyn InferenceBackend> = Arc::new(ThrottledBackend);
+        let router = BackendRouter::new(vec![backend]).with_metrics(metrics.clone());
+
+        for _ in 0..5 {
+            let error = router
+                .execute_chat(chat_request(false))
+                .await
+                .expect_err("throttled");
+            assert!(matches!(error, BackendError::RateLimited { .. }), "{error}");
+        }
+        let batch = router
+            .execute_chat_batch(vec![chat_request(false), chat_request(false)])
+            .await;
+        assert!(batch
+            .iter()
+            .all(|result| matches!(result, Err(BackendError::RateLimited { .. }))));
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_upstream_rate_limited_total{backend=\"throttled\"} 7"));
+    }
+
+    #[tokio::test]
+    async fn malformed_responses_are_repaired_or_rejected_per_endpoint() {
+        let metrics = Arc::new(AppMetrics::new());
+        let canned = |content: &str, finish_reason: &str| {
+            let backend: Arc<dyn InferenceBackend> = Arc::new(CannedBackend(BackendChatResponse {
+                content: content.to_owned(),
+                finish_reason: finish_reason.to_owned(),
+                provider_finish_reason: None,
+                usage: Usage {
+                    prompt_tokens: 2,
+                    completion_tokens: 2,
+                    total_tokens: 0,
+                },
+                backend: None,
+                upstream_headers: None,
+            }));
+            BackendRouter::new(vec![backend]).with_metrics(metrics.clone())
+        };
+
+        let response = canned("Hi there", "end_turn")
+            .execute_chat(chat_request(false))
+            .await
+            .expect("repairable response");
+        assert_eq!(response.finish_reason, "stop");
+        assert_eq!(response.provider_finish_reason.as_deref(), Some("end_turn"));
+        assert_eq!(response.usage, Usage::new(2, 2));
+
+        let error = canned("", "stop")
+            .execute_chat(chat_request(false))
+            .await
+            .expect_err("empty content is rejected");
+        assert!(matches!(error, BackendError::InvalidResponse(_)), "{error}");
+
+        let rendered = metrics.render().expect("render metrics");
+        for series in [
+            r#"{action="repaired",backend="canned",problem="unknown_finish_reason"} 1"#,
+            r#"{action="repaired",backend="canned",problem="usage_total_mismatch"} 1"#,
+            r#"{action="rejected",backend="canned",problem="empty_content"} 1"#,
+        ] {
+            assert!(
+                rendered.contains(&format!("gateway_backend_response_problems_total{series}")),
+                "{series}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_record_timing_per_endpoint_and_model() {
+        let metrics = Arc::new(AppMetrics::new());
+        let backend: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("mock-a"));
+        let router = BackendRouter::new(vec![backend]).with_metrics(metrics.clone());
+        let request = ChatCompletionsRequest {
+            model: "mock-1".to_owned(),
+            messages: vec![OpenAiMessage {
+                role: MessageRole::User,
+                content: "time this stream".to_owned(),
+                cache_control: None,
+            }],
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: true,
+            user: None,
+            timeout: None,
+            stream_options: None,
+            metadata: None,
+        }
+        .into_normalized("user".to_owned())
+        .expect("valid request");
+
+        let stream = router
+            .stream_chat(Arc::new(request))
+            .await
+            .expect("stream starts");
+        let chunks = tokio::time::timeout(Duration::from_secs(5), stream.collect::<Vec<_>>())
+            .await
+            .expect("stream finishes");
+        assert!(chunks.iter().all(Result::is_ok));
+
+        let rendered = metrics.render().expect("render metrics");
+        let labels = "{backend=\"mock-a\",model=\"mock-1\"}";
+        assert!(rendered.contains(&format!("gateway_stream_ttft_seconds_count{labels} 1")));
+        assert!(rendered.contains(&format!("gateway_stream_inter_chunk_seconds_count{labels}")));
+        assert!(rendered.contains(&format!("gateway_stream_tokens_per_second_count{labels} 1")));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_on_another_endpoint() {
+        let metrics = Arc::new(AppMetrics::new());
+        let router = flaky_pool(
+            RetryPolicy {
+                max_attempts: 2,
+                backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+            metrics.clone(),
+        );
+
+        for _ in 0..4 {
+            let response = router
+                .execute_chat(chat_request(false))
+                .await
+                .expect("retried onto the healthy endpoint");
+            assert_eq!(response.backend.as_deref(), Some("up"));
+        }
+        for _ in 0..2 {
+            let batch = router
+                .execute_chat_batch(vec![chat_request(false), chat_request(false)])
+                .await;
+            assert!(batch.iter().all(|result| result
+                .as_ref()
+                .is_ok_and(|response| response.backend.as_deref() == Some("up"))));
+        }
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered
+            .contains("gateway_backend_retries_total{backend=\"down\",class=\"unavailable\"}"));
+    }
+
+    #[tokio::test]
+    async fn upstream_server_errors_are_retried_on_another_endpoint() {
+        let upstream = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                (
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal error",
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind upstream");
+        let addr = listener.local_addr().expect("upstream address");
+        let server = tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let failing: Arc<dyn InferenceBackend> = Arc::new(
+            OpenAiAdapter::new(
+                OpenAiConfig::new("sk-test").with_base_url(format!("http://{addr}/v1")),
+            )
+            .expect("adapter"),
+        );
+        let up: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("up"));
+        let metrics = Arc::new(AppMetrics::new());
+        let router = BackendRouter::new(vec![failing, up])
+            .with_metrics(metrics.clone())
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            });
+
+        for _ in 0..4 {
+            let response = router
+                .execute_chat(chat_request(false))
+                .await
+                .expect("retried onto the healthy endpoint");
+            assert_eq!(response.backend.as_deref(), Some("up"));
+        }
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains(
+            "gateway_backend_retries_total{backend=\"openai-adapter\",class=\"unavailable\"}"
+        ));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn failures_outside_the_retry_classes_surface() {
+        let router = flaky_pool(
+            RetryPolicy {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                retry_on: vec![RetryClass::Timeout],
+                ..RetryPolicy::default()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+
+        let mut failures = 0;
+        for _ in 0..2 {
+            if let Err(error) = router.execute_chat(chat_request(false)).await {
+                assert!(matches!(error, BackendError::Unavailable(_)), "{error}");
+                failures += 1;
+            }
+        }
+        assert_eq!(failures, 1);
+    }
+
+    #[tokio::test]
+    async fn pinned_requests_go_to_their_backend_alone() {
+        let router = flaky_pool(
+            RetryPolicy {
+                max_attempts: 2,
+                backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+            Arc::new(AppMetrics::new()),
+        );
+        let pinned = |backend: &str, stream: bool| {
+            let mut request = Arc::unwrap_or_clone(chat_request(stream));
+            request.pinned_backend = Some(backend.to_owned());
+            Arc::new(request)
+        };
+
+        for _ in 0..4 {
+            let response = router
+                .execute_chat(pinned("up", false))
+                .await
+                .expect("pinned to the healthy endpoint");
+            assert_eq!(response.backend.as_deref(), Some("up"));
+            assert!(router.stream_chat(pinned("up", true)).await.is_ok());
+        }
+
+        // A pinned request is retried on its own backend, never moved to another.
+        let error = router
+            .execute_chat(pinned("down", false))
+            .await
+            .expect_err("the pinned endpoint is down");
+        assert!(error.to_string().contains("connection refused"), "{error}");
+        router
+            .execute_chat(pinned("down", false))
+            .await
+            .expect_err("the pinned endpoint stays down, opening its circuit");
+        let error = router
+            .execute_chat(pinned("down", false))
+            .await
+            .expect_err("the circuit is open");
+        assert!(error.to_string().contains("backend `down`"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn capped_endpoints_spill_over_to_the_secondary_pool() {
+        let metrics = Arc::new(AppMetrics::new());
+        let local: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("local"));
+        let cloud: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("cloud"));
+        let router = BackendRouter::new(vec![cloud, local])
+            .with_metrics(metrics.clone())
+            .with_endpoint_pools(&EndpointPoolConfig {
+                limits: EndpointLimit::parse_list("local=1").expect("valid limits"),
+                spillover: vec!["cloud".to_owned()],
+            });
+        async fn first_backend(stream: &mut BackendStream) -> String {
+            let chunk = stream.next().await.expect("chunk").expect("ok chunk");
+            chunk.backend.expect("stamped")
+        }
+
+        // Preferred endpoints are used while they have room, whatever the round-robin order.
+        let mut held = router
+            .stream_chat(chat_request(true))
+            .await
+            .expect("stream");
+        assert_eq!(first_backend(&mut held).await, "local");
+        let mut overflow = router
+            .stream_chat(chat_request(true))
+            .await
+            .expect("stream");
+        assert_eq!(first_backend(&mut overflow).await, "cloud");
+
+        drop(held);
+        let mut freed = router
+            .stream_chat(chat_request(true))
+            .await
+            .expect("stream");
+        assert_eq!(first_backend(&mut freed).await, "local");
+
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_backend_spillover_total{backend=\"cloud\"} 1"));
+        assert!(EndpointLimit::parse_list("local=0").is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff_for(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_for(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff_for(3), Duration::from_millis(300));
+        assert!(RetryClass::parse_list("unavailable, timeout").is_ok());
+        assert!(RetryClass::parse_list("bad_request").is_err());
+    }
+
+    #[tokio::test]
+    async fn open_circuits_close_once_the_cooldown_passes_on_the_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let backend: Arc<dyn InferenceBackend> = Arc::new(DownBackend);
+        let router = BackendRouter::new(vec![backend]).with_clock(clock.clone());
+        let unhealthy = |error: &BackendError| error.to_string().contains("unhealthy");
+
+        for _ in 0..3 {
+            let error = router.stream_chat(chat_request(true)).await.err();
+            assert!(!unhealthy(&error.expect("down")));
+        }
+        let error = router.stream_chat(chat_request(true)).await.err();
+        assert!(unhealthy(&error.expect("circuit open")));
+
+        clock.advance(Duration::from_secs(19));
+        let error = router.stream_chat(chat_request(true)).await.err();
+        assert!(unhealthy(&error.expect("still cooling down")));
+        clock.advance(Duration::from_secs(1));
+        let error = router.stream_chat(chat_request(true)).await.err();
+        assert!(!unhealthy(&error.expect("down again")));
+    }
+
+    #[tokio::test]
+    async fn endpoints_low_on_provider_budget_are_tried_last() {
+        let spent: Arc<dyn InferenceBackend> =
+            Arc::new(NearlySpentBackend(MockBackend::named("spent")));
+        let roomy: Arc<dyn InferenceBackend> = Arc::new(MockBackend::named("roomy"));
+        let router = BackendRouter::new(vec![spent, roomy]);
+
+        assert_eq!(router.route_plan().await, ["roomy", "spent"]);
+        for _ in 0..4 {
+            let response = router
+                .execute_chat(chat_request(false))
+                .await
+                .expect("served");
+            assert_eq!(response.backend.as_deref(), Some("roomy"));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_spare_the_circuit_and_throttled_endpoints_wait_their_turn() {
+        let failing = |name: &str, error: MockError| -> Arc<dyn InferenceBackend> {
+            Arc::new(MockBackend::named(name).with_faults(vec![MockFaultRule {
+                error_rate: 1.0,
+                error,
+                ..MockFaultRule::default()
+            }]))
+        };
+
+        let router = BackendRouter::new(vec![failing("picky", MockError::BadRequest)]);
+        for _ in 0..5 {
+            let error = router
+                .execute_chat(chat_request(false))
+                .await
+                .expect_err("rejected");
+            assert!(matches!(error, BackendError::BadRequest(_)), "{error}");
+        }
+        assert_eq!(
+            router.endpoint_status().await[0].circuit,
+            CircuitState::Closed
+        );
+
+        // The mock's 429 asks for a one-second break.
+        let clock = Arc::new(ManualClock::new());
+        let router = BackendRouter::new(vec![
+            failing("throttled", MockError::RateLimited),
+            Arc::new(MockBackend::named("up")),
+        ])
+        .with_clock(clock.clone());
+        let error = router
+            .execute_chat(chat_request(false))
+            .await
+            .expect_err("throttled first");
+        assert!(matches!(error, BackendError::RateLimited { .. }), "{error}");
+        router
+            .execute_chat(chat_request(false))
+            .await
+            .expect("served by the other endpoint");
+        // Round-robin is back at the throttled endpoint, which still goes last.
+        assert_eq!(router.route_plan().await, ["up", "throttled"]);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(router.route_plan().await, ["throttled", "up"]);
+    }
+
+    #[tokio::test]
+    async fn flapping_endpoints_need_successes_in_a_row_to_rejoin_rotation() {
+        let clock = Arc::new(ManualClock::new());
+        let flapping = Arc::new(FlappingBackend(AtomicBool::new(false)));
+        let backends: Vec<Arc<dyn InferenceBackend>> =
+            vec![flapping.clone(), Arc::new(MockBackend::named("up"))];
+        let router = BackendRouter::new(backends)
+            .with_clock(clock.clone())
+            .with_health_config(EndpointHealthConfig {
+                history: 4,
+                recovery_successes: 2,
+            });
+
+        for _ in 0..3 {
+            router.check_once().await;
+        }
+        assert_eq!(
+            router.endpoint_status().await[0].circuit,
+            CircuitState::Open
+        );
+        assert_eq!(router.route_plan().await, ["up"]);
+
+        // Past its cooldown it is back behind its closed peer, and one failure sends it back.
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(router.route_plan().await, ["up", "flapping"]);
+        router.check_once().await;
+        assert_eq!(
+            router.endpoint_status().await[0].circuit,
+            CircuitState::Open
+        );
+
+        clock.advance(Duration::from_secs(20));
+        flapping.0.store(true, Ordering::Relaxed);
+        router.check_once().await;
+        let status = router.endpoint_status().await.remove(0);
+        assert_eq!(status.circuit, CircuitState::Recovering);
+        assert_eq!(
+            status.recovery,
+            Some(RecoveryProgress {
+                successes: 1,
+                needed: 2
+            })
+        );
+        router.check_once().await;
+        let status = router.endpoint_status().await.remove(0);
+        assert_eq!(status.circuit, CircuitState::Closed);
+        assert_eq!(router.route_plan().await.len(), 2);
+
+        // Only the latest four checks are kept.
+        let outcomes = status
+            .history
+            .iter()
+            .map(|check| check.healthy)
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, [false, false, true, true]);
+        assert_eq!(status.check_success_rate, Some(0.5));
+        assert!(status.history[0]
+            .error
+            .as_deref()
+            .is_some_and(|error| error.contains("connection refused")));
     }
 }
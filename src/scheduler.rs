@@ -2,6 +2,9 @@ use sha2::{Digest, Sha256};
 
 use crate::models::{MessageRole, NormalizedChatRequest, NormalizedMessage};
 
+/// Tags the canonical encoding, so fingerprints from an older layout never match new ones.
+const CANONICAL_VERSION: &str = "2";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RequestFingerprint(String);
 
@@ -11,28 +14,91 @@ impl RequestFingerprint {
     }
 }
 
+/// Digest applied to the canonical request payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FingerprintHash {
+    #[default]
+    Sha256,
+    /// Several times faster than SHA-256 on long multi-turn prompts.
+    Blake3,
+}
+
+impl FingerprintHash {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(Self::Sha256),
+            "blake3" => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    fn digest(self, payload: &[u8]) -> String {
+        match self {
+            Self::Sha256 => to_hex(Sha256::digest(payload).as_ref()),
+            Self::Blake3 => to_hex(blake3::hash(payload).as_bytes()),
+        }
+    }
+}
+
+/// SHA-256 fingerprint with no cache partition, as used for fixture file names.
 pub fn fingerprint_for(request: &NormalizedChatRequest) -> RequestFingerprint {
-    let canonical = canonical_payload(request);
-    let digest = Sha256::digest(canonical.as_bytes());
-    RequestFingerprint(to_hex(digest.as_ref()))
+    scoped_fingerprint_for(request, None)
 }
 
-fn canonical_payload(request: &NormalizedChatRequest) -> String {
+/// Fingerprint namespaced by a cache partition, so scoped callers never share cache or
+/// coalescing entries with each other.
+pub fn scoped_fingerprint_for(
+    request: &NormalizedChatRequest,
+    partition: Option<&str>,
+) -> RequestFingerprint {
+    hashed_fingerprint_for(FingerprintHash::default(), request, partition)
+}
+
+/// Like [`scoped_fingerprint_for`], digesting with `hash`.
+pub fn hashed_fingerprint_for(
+    hash: FingerprintHash,
+    request: &NormalizedChatRequest,
+    partition: Option<&str>,
+) -> RequestFingerprint {
+    let canonical = canonical_payload(request, partition);
+    RequestFingerprint(hash.digest(canonical.as_bytes()))
+}
+
+/// Every field is written as `name=<byte length>:<value>;`, so no value, whatever characters it
+/// contains, can be read as the boundary of another field or message.
+fn canonical_payload(request: &NormalizedChatRequest, partition: Option<&str>) -> String {
     let mut payload = String::new();
-    payload.push_str(&request.model);
-    payload.push('|');
-    payload.push_str(
+    push_field(&mut payload, "version", CANONICAL_VERSION);
+    if let Some(partition) = partition {
+        push_field(&mut payload, "scope", partition);
+    }
+    push_field(&mut payload, "model", &request.model);
+    push_field(
+        &mut payload,
+        "max_tokens",
         &request
             .generation
             .max_tokens
             .unwrap_or_default()
             .to_string(),
     );
-    payload.push('|');
-    payload.push_str(&opt_float(request.generation.temperature));
-    payload.push('|');
-    payload.push_str(&opt_float(request.generation.top_p));
+    push_field(
+        &mut payload,
+        "temperature",
+        &opt_float(request.generation.temperature),
+    );
+    push_field(&mut payload, "top_p", &opt_float(request.generation.top_p));
+    push_field(
+        &mut payload,
+        "stream",
+        if request.stream { "true" } else { "false" },
+    );
 
+    push_field(
+        &mut payload,
+        "messages",
+        &request.messages.len().to_string(),
+    );
     for message in &request.messages {
         append_message(&mut payload, message);
     }
@@ -41,15 +107,26 @@ fn canonical_payload(request: &NormalizedChatRequest) -> String {
 }
 
 fn append_message(buffer: &mut String, message: &NormalizedMessage) {
-    buffer.push('|');
-    buffer.push_str(match message.role {
-        MessageRole::System => "system",
-        MessageRole::User => "user",
-        MessageRole::Assistant => "assistant",
-        MessageRole::Tool => "tool",
-    });
+    push_field(
+        buffer,
+        "role",
+        match message.role {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::Tool => "tool",
+        },
+    );
+    push_field(buffer, "content", &message.content);
+}
+
+fn push_field(buffer: &mut String, name: &str, value: &str) {
+    buffer.push_str(name);
+    buffer.push('=');
+    buffer.push_str(&value.len().to_string());
     buffer.push(':');
-    buffer.push_str(&message.content);
+    buffer.push_str(value);
+    buffer.push(';');
 }
 
 fn opt_float(value: Option<f32>) -> String {
@@ -58,7 +135,7 @@ fn opt_float(value: Option<f32>) -> String {
         .unwrap_or_else(|| "none".to_owned())
 }
 
-fn to_hex(bytes: &[u8]) -> String {
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
     let mut encoded = String::with_capacity(bytes.len() * 2);
     for byte in bytes {
         encoded.push(nibble_to_hex(byte >> 4));
@@ -77,19 +154,25 @@ fn nibble_to_hex(value: u8) -> char {
 
 #[cfg(test)]
 mod tests {
-    use crate::models::{GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage};
+    use crate::models::{
+        ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+        Priority,
+    };
 
-    use super::fingerprint_for;
+    use super::{
+        canonical_payload, fingerprint_for, hashed_fingerprint_for, scoped_fingerprint_for,
+        FingerprintHash,
+    };
 
-    #[test]
-    fn fingerprint_is_stable_for_same_request_shape() {
-        let request = NormalizedChatRequest {
+    fn sample_request() -> NormalizedChatRequest {
+        NormalizedChatRequest {
             request_id: "req_1".to_owned(),
             user_id: "user_a".to_owned(),
             model: "gpt-test".to_owned(),
             messages: vec![NormalizedMessage {
                 role: MessageRole::User,
                 content: "hello".to_owned(),
+                cache_control: None,
             }],
             generation: GenerationParams {
                 max_tokens: Some(100),
@@ -97,10 +180,82 @@ mod tests {
                 top_p: Some(1.0),
             },
             stream: false,
-        };
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    #[test]
+    fn scoped_fingerprints_do_not_collide_across_partitions() {
+        let request = sample_request();
+        let global = fingerprint_for(&request);
+        let key_a = scoped_fingerprint_for(&request, Some("key:a"));
+        let key_b = scoped_fingerprint_for(&request, Some("key:b"));
+
+        assert_ne!(global, key_a);
+        assert_ne!(key_a, key_b);
+        assert_eq!(key_a, scoped_fingerprint_for(&request, Some("key:a")));
+    }
+
+    #[test]
+    fn fingerprint_is_stable_for_same_request_shape() {
+        let request = sample_request();
 
         let left = fingerprint_for(&request);
         let right = fingerprint_for(&request);
         assert_eq!(left, right);
     }
+
+    #[test]
+    fn field_separators_inside_content_do_not_collide() {
+        let mut split = sample_request();
+        split.messages = vec![
+            NormalizedMessage {
+                role: MessageRole::User,
+                content: "a".to_owned(),
+                cache_control: None,
+            },
+            NormalizedMessage {
+                role: MessageRole::User,
+                content: "b".to_owned(),
+                cache_control: None,
+            },
+        ];
+        let mut joined = sample_request();
+        joined.messages = vec![NormalizedMessage {
+            role: MessageRole::User,
+            content: "a|user:b".to_owned(),
+            cache_control: None,
+        }];
+
+        assert_ne!(
+            canonical_payload(&split, None),
+            canonical_payload(&joined, None)
+        );
+        assert_ne!(fingerprint_for(&split), fingerprint_for(&joined));
+    }
+
+    #[test]
+    fn stream_flag_and_hash_choice_change_the_fingerprint() {
+        let request = sample_request();
+        let mut streamed = sample_request();
+        streamed.stream = true;
+        assert_ne!(fingerprint_for(&request), fingerprint_for(&streamed));
+
+        let blake3 = hashed_fingerprint_for(FingerprintHash::Blake3, &request, None);
+        assert_eq!(blake3.as_str().len(), 64);
+        assert_ne!(blake3, fingerprint_for(&request));
+        assert_eq!(
+            blake3,
+            hashed_fingerprint_for(FingerprintHash::Blake3, &request, None)
+        );
+        assert_eq!(
+            FingerprintHash::parse(" BLAKE3 "),
+            Some(FingerprintHash::Blake3)
+        );
+        assert_eq!(FingerprintHash::parse("md5"), None);
+    }
 }
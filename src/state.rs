@@ -1,23 +1,67 @@
 use std::sync::Arc;
 
 use crate::{
-    auth::ApiKeyRegistry,
-    backend::InferenceBackend,
+    access_log::AccessLog,
+    admin::AdminConfig,
+    admission::{AdmissionConfig, AdmissionController},
+    auth::{ApiKeyRegistry, KeyStore},
+    backend::{peer::PeerTrust, InferenceBackend},
     batcher::{BatchConfig, Batcher},
+    body_limits::BodyLimits,
+    builder::GatewayBuilder,
     cache::{CacheConfig, ResponseCache},
-    coalescing::InflightCoalescer,
+    catalog::ModelCatalog,
+    coalescing::{CoalescerConfig, InflightCoalescer},
+    context_window::ContextLimits,
+    credits::CreditLedger,
+    error_reporting::ErrorReporter,
+    experiments::Experiments,
+    fair_queue::{FairQueue, FairQueueConfig},
+    forwarded_headers::ForwardedHeaders,
     limits::RateLimiter,
-    metrics::AppMetrics,
+    metrics::{AppMetrics, MetricsConfig},
+    model_pools::{ModelPoolConfig, ModelPools},
+    postprocess::PostProcessors,
+    pricing::PricingTable,
+    quotas::QuotaOverrides,
+    redis_pool::RedisPool,
+    streaming::StreamingConfig,
+    timeouts::TimeoutConfig,
+    traces::TraceExporter,
+    truncation::TruncationConfig,
+    usage_sink::UsageSink,
 };
 
 #[derive(Clone)]
 pub struct AppState {
     pub backend: Arc<dyn InferenceBackend>,
     pub batcher: Arc<Batcher>,
-    pub auth: Arc<ApiKeyRegistry>,
+    pub auth: Arc<dyn KeyStore>,
     pub rate_limiter: Arc<RateLimiter>,
+    pub quotas: Arc<QuotaOverrides>,
     pub response_cache: Arc<ResponseCache>,
     pub coalescer: Arc<InflightCoalescer>,
+    pub fair_queue: Arc<FairQueue>,
+    pub admission: Arc<AdmissionController>,
+    pub model_pools: Arc<ModelPools>,
+    pub access_log: Arc<AccessLog>,
+    pub admin: Arc<AdminConfig>,
+    pub body_limits: Arc<BodyLimits>,
+    pub context_limits: Arc<ContextLimits>,
+    pub truncation: Arc<TruncationConfig>,
+    pub experiments: Arc<Experiments>,
+    pub peer_trust: Arc<PeerTrust>,
+    pub pricing: Arc<PricingTable>,
+    pub catalog: Arc<ModelCatalog>,
+    pub post_processors: Arc<PostProcessors>,
+    pub credits: Arc<CreditLedger>,
+    pub usage_sink: Arc<UsageSink>,
+    pub error_reporter: Arc<ErrorReporter>,
+    pub traces: Arc<TraceExporter>,
+    pub timeouts: Arc<TimeoutConfig>,
+    pub streaming: Arc<StreamingConfig>,
+    pub forwarded_headers: Arc<ForwardedHeaders>,
+    pub redis: Option<Arc<RedisPool>>,
     pub metrics: Arc<AppMetrics>,
 }
 
@@ -26,17 +70,20 @@ impl AppState {
     where
         B: InferenceBackend + 'static,
     {
-        let backend: Arc<dyn InferenceBackend> = backend;
-        let batcher = Arc::new(Batcher::new(backend.clone(), BatchConfig::from_env()));
-        Self {
+        Self::with_metrics(
             backend,
-            batcher,
-            auth: Arc::new(ApiKeyRegistry::from_env()),
-            rate_limiter: Arc::new(RateLimiter::from_env()),
-            response_cache: Arc::new(ResponseCache::from_env(CacheConfig::from_env())),
-            coalescer: Arc::new(InflightCoalescer::default()),
-            metrics: Arc::new(AppMetrics::new()),
-        }
+            Arc::new(AppMetrics::with_config(MetricsConfig::from_env())),
+        )
+    }
+
+    /// Like [`AppState::new`], sharing a registry the backend already reports into.
+    pub fn with_metrics<B>(backend: Arc<B>, metrics: Arc<AppMetrics>) -> Self
+    where
+        B: InferenceBackend + 'static,
+    {
+        GatewayBuilder::from_env()
+            .metrics(metrics)
+            .assemble(backend)
     }
 
     pub fn new_for_tests<B>(backend: Arc<B>) -> Self
@@ -44,15 +91,55 @@ impl AppState {
         B: InferenceBackend + 'static,
     {
         let backend: Arc<dyn InferenceBackend> = backend;
-        let batcher = Arc::new(Batcher::new(backend.clone(), BatchConfig::from_env()));
+        let metrics = Arc::new(AppMetrics::new());
+        let batcher = Arc::new(Batcher::new(
+            backend.clone(),
+            BatchConfig::from_env(),
+            metrics.clone(),
+        ));
+        let response_cache = Arc::new(ResponseCache::memory(
+            CacheConfig::from_env(),
+            metrics.clone(),
+        ));
         Self {
             backend,
             batcher,
             auth: Arc::new(ApiKeyRegistry::from_env()),
             rate_limiter: Arc::new(RateLimiter::in_memory()),
-            response_cache: Arc::new(ResponseCache::memory(CacheConfig::from_env())),
-            coalescer: Arc::new(InflightCoalescer::default()),
-            metrics: Arc::new(AppMetrics::new()),
+            quotas: Arc::new(QuotaOverrides::default()),
+            response_cache,
+            coalescer: Arc::new(InflightCoalescer::new(
+                CoalescerConfig::from_env(),
+                metrics.clone(),
+            )),
+            fair_queue: Arc::new(FairQueue::new(FairQueueConfig::from_env(), metrics.clone())),
+            admission: Arc::new(AdmissionController::new(
+                AdmissionConfig::from_env(),
+                metrics.clone(),
+            )),
+            model_pools: Arc::new(ModelPools::new(
+                ModelPoolConfig::from_env(),
+                metrics.clone(),
+            )),
+            access_log: Arc::new(AccessLog::disabled()),
+            admin: Arc::new(AdminConfig::default()),
+            body_limits: Arc::new(BodyLimits::default()),
+            context_limits: Arc::new(ContextLimits::default()),
+            truncation: Arc::new(TruncationConfig::default()),
+            experiments: Arc::new(Experiments::default()),
+            peer_trust: Arc::new(PeerTrust::disabled()),
+            pricing: Arc::new(PricingTable::from_env()),
+            catalog: Arc::new(ModelCatalog::default()),
+            post_processors: Arc::new(PostProcessors::default()),
+            credits: Arc::new(CreditLedger::disabled()),
+            usage_sink: Arc::new(UsageSink::disabled()),
+            error_reporter: Arc::new(ErrorReporter::disabled()),
+            traces: Arc::new(TraceExporter::disabled()),
+            timeouts: Arc::new(TimeoutConfig::default()),
+            streaming: Arc::new(StreamingConfig::default()),
+            forwarded_headers: Arc::new(ForwardedHeaders::default()),
+            redis: None,
+            metrics,
         }
     }
 }
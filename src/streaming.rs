@@ -0,0 +1,279 @@
+use std::{env, time::Duration};
+
+use futures_util::StreamExt;
+use tracing::warn;
+
+use crate::{backend::BackendStream, models::BackendChunk};
+
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_KEEP_ALIVE_TEXT: &str = "ping";
+const DEFAULT_MERGE_MAX_DELAY: Duration = Duration::from_millis(20);
+
+/// How streamed responses are written to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamingConfig {
+    /// Forward upstream SSE events verbatim on streams that have a single subscriber, instead
+    /// of re-serializing every chunk. Coalesced streams and cache replays always go through the
+    /// full pipeline, since their subscribers need the gateway's own response id.
+    pub passthrough: bool,
+    /// Writes an SSE comment whenever nothing else has been sent for this long, so proxies that
+    /// cut quiet connections leave slow generations alone. `None` sends no keep-alives.
+    pub keep_alive_interval: Option<Duration>,
+    /// Keep-alive comment text, sent as `: <text>`. Must not contain line breaks.
+    pub keep_alive_text: String,
+    /// Ends streams that run longer than this with a timeout error event.
+    pub max_duration: Option<Duration>,
+    /// Holds back upstream deltas until this many characters have arrived, so backends that
+    /// stream a character at a time, like llama.cpp, cost one SSE event per word or so instead.
+    /// `0` forwards every delta as it comes.
+    pub merge_min_chars: usize,
+    /// Longest a held delta waits for more text before it is sent anyway.
+    pub merge_max_delay: Duration,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            passthrough: false,
+            keep_alive_interval: Some(DEFAULT_KEEP_ALIVE_INTERVAL),
+            keep_alive_text: DEFAULT_KEEP_ALIVE_TEXT.to_owned(),
+            max_duration: None,
+            merge_min_chars: 0,
+            merge_max_delay: DEFAULT_MERGE_MAX_DELAY,
+        }
+    }
+}
+
+impl StreamingConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let keep_alive_interval = match env::var("GATEWAY_STREAM_KEEPALIVE_SECS")
+            .ok()
+            .map(|value| value.trim().parse::<u64>())
+        {
+            None => defaults.keep_alive_interval,
+            Some(Ok(0)) => None,
+            Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+            Some(Err(_)) => {
+                warn!("invalid GATEWAY_STREAM_KEEPALIVE_SECS, using the default interval");
+                defaults.keep_alive_interval
+            }
+        };
+        let keep_alive_text = match env::var("GATEWAY_STREAM_KEEPALIVE_TEXT") {
+            Ok(text) if text.contains(['\n', '\r']) => {
+                warn!("GATEWAY_STREAM_KEEPALIVE_TEXT contains a line break, using the default");
+                defaults.keep_alive_text
+            }
+            Ok(text) => text,
+            Err(_) => defaults.keep_alive_text,
+        };
+        Self {
+            passthrough: env::var("GATEWAY_STREAM_PASSTHROUGH")
+                .ok()
+                .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true")),
+            keep_alive_interval,
+            keep_alive_text,
+            max_duration: env::var("GATEWAY_STREAM_MAX_DURATION_SECS")
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            merge_min_chars: env::var("GATEWAY_STREAM_MERGE_MIN_CHARS")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(defaults.merge_min_chars),
+            merge_max_delay: env::var("GATEWAY_STREAM_MERGE_MAX_DELAY_MS")
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|ms| *ms > 0)
+                .map_or(defaults.merge_max_delay, Duration::from_millis),
+        }
+    }
+
+    /// Merges consecutive small deltas of `stream` into one chunk of at least
+    /// `merge_min_chars` characters, or whatever arrived within `merge_max_delay` of the first
+    /// held one. Held text is sent ahead of any other chunk, and verbatim passthrough chunks
+    /// are never merged.
+    pub fn merge_deltas(&self, stream: BackendStream) -> BackendStream {
+        let min_chars = self.merge_min_chars;
+        if min_chars == 0 {
+            return stream;
+        }
+        let max_delay = self.merge_max_delay;
+        async_stream::stream! {
+            let mut stream = stream;
+            let mut held = HeldDelta::default();
+            loop {
+                let next = match held.flush_at {
+                    Some(flush_at) => match tokio::time::timeout_at(flush_at, stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            yield Ok(held.take());
+                            continue;
+                        }
+                    },
+                    None => stream.next().await,
+                };
+                match next {
+                    Some(Ok(chunk)) if is_plain_delta(&chunk) => {
+                        held.push(chunk, max_delay);
+                        if held.chars >= min_chars {
+                            yield Ok(held.take());
+                        }
+                    }
+                    Some(Ok(mut chunk)) => {
+                        if held.flush_at.is_some() {
+                            if chunk.raw.is_none() {
+                                held.absorb_into(&mut chunk);
+                            } else {
+                                yield Ok(held.take());
+                            }
+                        }
+                        yield Ok(chunk);
+                    }
+                    Some(Err(error)) => {
+                        if held.flush_at.is_some() {
+                            yield Ok(held.take());
+                        }
+                        yield Err(error);
+                        break;
+                    }
+                    None => {
+                        if held.flush_at.is_some() {
+                            yield Ok(held.take());
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// A mid-stream chunk that carries nothing but text.
+fn is_plain_delta(chunk: &BackendChunk) -> bool {
+    chunk.delta.is_some()
+        && chunk.raw.is_none()
+        && !chunk.done
+        && chunk.finish_reason.is_none()
+        && chunk.usage.is_none()
+}
+
+/// Text held back by [`StreamingConfig::merge_deltas`].
+#[derive(Default)]
+struct HeldDelta {
+    text: String,
+    chars: usize,
+    /// The router stamps the endpoint on a stream's first chunk, which may be merged.
+    backend: Option<String>,
+    /// When the held text is sent at the latest; `None` while nothing is held.
+    flush_at: Option<tokio::time::Instant>,
+}
+
+impl HeldDelta {
+    fn push(&mut self, chunk: BackendChunk, max_delay: Duration) {
+        let delta = chunk.delta.unwrap_or_default();
+        self.chars += delta.chars().count();
+        self.text.push_str(&delta);
+        self.backend = self.backend.take().or(chunk.backend);
+        self.flush_at
+            .get_or_insert_with(|| tokio::time::Instant::now() + max_delay);
+    }
+
+    fn take(&mut self) -> BackendChunk {
+        let held = std::mem::take(self);
+        BackendChunk {
+            delta: Some(held.text),
+            finish_reason: None,
+            provider_finish_reason: None,
+            usage: None,
+            done: false,
+            backend: held.backend,
+            raw: None,
+            upstream_headers: None,
+        }
+    }
+
+    /// Prepends the held text to `chunk`'s own delta.
+    fn absorb_into(&mut self, chunk: &mut BackendChunk) {
+        let held = self.take();
+        let mut text = held.delta.unwrap_or_default();
+        text.push_str(chunk.delta.as_deref().unwrap_or_default());
+        chunk.delta = Some(text);
+        chunk.backend = chunk.backend.take().or(held.backend);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use super::StreamingConfig;
+    use crate::{backend::BackendStream, models::BackendChunk};
+
+    fn chunk(delta: Option<&str>, done: bool) -> BackendChunk {
+        BackendChunk {
+            delta: delta.map(ToOwned::to_owned),
+            finish_reason: done.then(|| "stop".to_owned()),
+            provider_finish_reason: None,
+            usage: None,
+            done,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        }
+    }
+
+    fn merging(min_chars: usize, max_delay_ms: u64) -> StreamingConfig {
+        StreamingConfig {
+            merge_min_chars: min_chars,
+            merge_max_delay: Duration::from_millis(max_delay_ms),
+            ..StreamingConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn small_deltas_are_merged_up_to_the_size_threshold() {
+        let mut chunks = "Hello, world"
+            .chars()
+            .map(|c| chunk(Some(&c.to_string()), false))
+            .collect::<Vec<_>>();
+        chunks.push(chunk(None, true));
+        let upstream: BackendStream =
+            futures_util::stream::iter(chunks.into_iter().map(Ok)).boxed();
+
+        let merged = merging(5, 1_000)
+            .merge_deltas(upstream)
+            .map(|item| item.expect("no errors"))
+            .collect::<Vec<_>>()
+            .await;
+
+        let deltas = merged
+            .iter()
+            .map(|chunk| chunk.delta.as_deref().unwrap_or_default())
+            .collect::<Vec<_>>();
+        // The last two characters ride along with the terminal chunk.
+        assert_eq!(deltas, ["Hello", ", wor", "ld"]);
+        assert!(merged[2].done);
+        assert_eq!(merged[2].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn held_deltas_are_sent_once_the_delay_passes() {
+        let upstream: BackendStream = futures_util::stream::iter([Ok(chunk(Some("Hi"), false))])
+            .chain(futures_util::stream::pending())
+            .boxed();
+        let mut merged = merging(100, 20).merge_deltas(upstream);
+
+        let first = tokio::time::timeout(Duration::from_secs(1), merged.next())
+            .await
+            .expect("flushed by the delay")
+            .expect("a chunk")
+            .expect("no error");
+        assert_eq!(first.delta.as_deref(), Some("Hi"));
+        assert!(!first.done);
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, env};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{auth::RatePolicy, cache::CacheScope, glob, prompts::SystemPromptPolicy};
+
+/// Settings shared by every key of a tenant, loaded from `GATEWAY_TENANTS`, a JSON object
+/// keyed by tenant id. Keys join a tenant through the `tenant` field of their key policy.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantPolicy {
+    /// Model globs the tenant may call; empty allows every model.
+    pub models: Vec<String>,
+    /// Quota shared by all of the tenant's keys, enforced on top of each key's own quota.
+    /// Unset dimensions are unlimited.
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    /// Overrides `GATEWAY_CACHE_SCOPE` for the tenant's requests.
+    pub cache_scope: Option<CacheScope>,
+    /// Tier for keys that do not name their own.
+    pub tier: Option<String>,
+    /// System prompt added to requests from keys that do not set their own.
+    pub system_prompt: Option<SystemPromptPolicy>,
+}
+
+/// The account a key belongs to. Keys without a tenant are their own tenant, identified by
+/// the key id, with no shared policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant {
+    pub id: String,
+    pub policy: TenantPolicy,
+}
+
+impl Tenant {
+    pub fn new(id: impl Into<String>, policy: TenantPolicy) -> Self {
+        Self {
+            id: id.into(),
+            policy,
+        }
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.policy.models.is_empty()
+            || self
+                .policy
+                .models
+                .iter()
+                .any(|pattern| glob::matches(pattern, model))
+    }
+
+    /// The shared quota, or `None` when the tenant sets no limit of its own.
+    pub fn quota(&self) -> Option<RatePolicy> {
+        let policy = &self.policy;
+        if policy.requests_per_minute.is_none()
+            && policy.tokens_per_minute.is_none()
+            && policy.tokens_per_day.is_none()
+        {
+            return None;
+        }
+        Some(RatePolicy {
+            requests_per_minute: policy.requests_per_minute.unwrap_or(u32::MAX),
+            tokens_per_minute: policy.tokens_per_minute.unwrap_or(u64::MAX),
+            tokens_per_day: policy.tokens_per_day.unwrap_or(u64::MAX),
+        })
+    }
+
+    /// Rate-limiter bucket for the shared quota; prefixed so it cannot collide with a key.
+    pub fn quota_key(&self) -> String {
+        format!("tenant:{}", self.id)
+    }
+}
+
+pub fn read_tenants() -> HashMap<String, TenantPolicy> {
+    let Ok(raw) = env::var("GATEWAY_TENANTS") else {
+        return HashMap::new();
+    };
+    if raw.trim().is_empty() {
+        return HashMap::new();
+    }
+
+    match serde_json::from_str::<HashMap<String, TenantPolicy>>(&raw) {
+        Ok(tenants) => tenants,
+        Err(error) => {
+            warn!(error = %error, "invalid GATEWAY_TENANTS, ignoring tenant policies");
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Tenant, TenantPolicy};
+    use crate::cache::CacheScope;
+
+    #[test]
+    fn parses_policies_and_applies_model_allowlists_and_quotas() {
+        let tenants: HashMap<String, TenantPolicy> = serde_json::from_str(
+            r#"{"acme":{"models":["gpt-4o*"],"requests_per_minute":600,"cache_scope":"key"}}"#,
+        )
+        .expect("valid tenants");
+        let acme = Tenant::new("acme", tenants["acme"].clone());
+        assert!(acme.allows_model("gpt-4o-mini"));
+        assert!(!acme.allows_model("o1"));
+        assert_eq!(acme.policy.cache_scope, Some(CacheScope::ApiKey));
+        let quota = acme.quota().expect("shared quota");
+        assert_eq!(quota.requests_per_minute, 600);
+        assert_eq!(quota.tokens_per_day, u64::MAX);
+        assert_eq!(acme.quota_key(), "tenant:acme");
+
+        let solo = Tenant::new("key_dev", TenantPolicy::default());
+        assert!(solo.allows_model("anything"));
+        assert!(solo.quota().is_none());
+    }
+}
@@ -0,0 +1,307 @@
+//! Helpers for integration tests of services that embed the gateway, enabled by the `testing`
+//! feature.
+//!
+//! [`TestGateway`] serves [`build_app`] on a loopback port with everything kept in memory: no
+//! environment is read, caching and prepaid credits are off unless asked for, and the default
+//! backend is a [`MockBackend`] that streams without delay and answers from an optional script.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+use crate::{
+    auth::{ApiKeyRegistry, KeyPolicy, RatePolicy},
+    backend::{
+        mock::{MockBackend, MockFaultRule, MockScriptEntry},
+        InferenceBackend,
+    },
+    build_app,
+    cache::CacheConfig,
+    clock::Clock,
+    credits::CreditLedger,
+    pricing::PricingTable,
+    state::AppState,
+    tenants::TenantPolicy,
+    GatewayBuilder,
+};
+
+/// Configures a [`TestGateway`]; see [`TestGateway::builder`].
+pub struct TestGatewayBuilder {
+    keys: Vec<(String, KeyPolicy)>,
+    tenants: Vec<(String, TenantPolicy)>,
+    rate: RatePolicy,
+    backend: Option<Arc<dyn InferenceBackend>>,
+    script: Vec<MockScriptEntry>,
+    faults: Vec<MockFaultRule>,
+    pricing: PricingTable,
+    cache: bool,
+    credits: Option<Vec<(String, f64)>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl TestGatewayBuilder {
+    /// Accepts `api_key` with the given policy; repeat for more keys.
+    pub fn key(mut self, api_key: impl Into<String>, policy: KeyPolicy) -> Self {
+        self.keys.push((api_key.into(), policy));
+        self
+    }
+
+    pub fn tenant(mut self, tenant_id: impl Into<String>, policy: TenantPolicy) -> Self {
+        self.tenants.push((tenant_id.into(), policy));
+        self
+    }
+
+    /// Rate limits shared by every key; defaults to [`RatePolicy::default`].
+    pub fn rate_policy(mut self, rate: RatePolicy) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Serves requests from `backend` instead of the mock; scripts and faults are then ignored.
+    pub fn backend(mut self, backend: Arc<dyn InferenceBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Canned responses for the mock backend, as in `GATEWAY_MOCK_SCRIPT`.
+    pub fn script(mut self, script: Vec<MockScriptEntry>) -> Self {
+        self.script = script;
+        self
+    }
+
+    /// Failures for the mock backend to inject, as in `GATEWAY_MOCK_FAULTS`.
+    pub fn faults(mut self, faults: Vec<MockFaultRule>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub fn pricing(mut self, pricing: PricingTable) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// Turns on the response cache, which is off so repeated prompts reach the backend.
+    pub fn enable_cache(mut self) -> Self {
+        self.cache = true;
+        self
+    }
+
+    /// Turns on prepaid credits and opens `account` (a tenant id) with `balance_usd`; repeat
+    /// for more accounts.
+    pub fn credits(mut self, account: impl Into<String>, balance_usd: f64) -> Self {
+        self.credits
+            .get_or_insert_with(Vec::new)
+            .push((account.into(), balance_usd));
+        self
+    }
+
+    /// Time source for rate limits, cache expiry, and circuit breakers, typically a
+    /// [`crate::clock::ManualClock`] the test advances.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// The assembled state, for tests that drive [`build_app`] themselves.
+    pub async fn build_state(self) -> AppState {
+        let mut registry = ApiKeyRegistry::new(self.keys.iter().map(|(key, _)| key), self.rate);
+        for (key, policy) in self.keys.iter().cloned() {
+            registry = registry.with_key_policy(key, policy);
+        }
+        for (tenant_id, policy) in self.tenants {
+            registry = registry.with_tenant(tenant_id, policy);
+        }
+        let backend = self.backend.unwrap_or_else(|| {
+            Arc::new(
+                MockBackend::default()
+                    .with_token_delay(Duration::ZERO)
+                    .with_script(self.script)
+                    .with_faults(self.faults),
+            )
+        });
+        let cache = if self.cache {
+            CacheConfig::default()
+        } else {
+            CacheConfig::disabled()
+        };
+        let mut builder = GatewayBuilder::new();
+        if let Some(clock) = self.clock {
+            builder = builder.clock(clock);
+        }
+        let mut state = builder
+            .key_store(Arc::new(registry))
+            .pricing(self.pricing)
+            .cache_config(cache)
+            .assemble(backend);
+        if let Some(accounts) = self.credits {
+            let ledger = CreditLedger::in_memory();
+            for (account, balance_usd) in accounts {
+                let _ = ledger.set(&account, balance_usd).await;
+            }
+            state.credits = Arc::new(ledger);
+        }
+        state
+    }
+
+    /// Serves the gateway on an ephemeral loopback port.
+    pub async fn start(self) -> TestGateway {
+        let state = self.build_state().await;
+        let app = build_app(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind test gateway");
+        let addr = listener.local_addr().expect("test gateway address");
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await;
+        });
+        TestGateway {
+            addr,
+            state,
+            client: reqwest::Client::new(),
+            server,
+        }
+    }
+}
+
+/// A gateway served for the length of a test; the server stops when this is dropped.
+pub struct TestGateway {
+    addr: SocketAddr,
+    state: AppState,
+    client: reqwest::Client,
+    server: JoinHandle<()>,
+}
+
+impl TestGateway {
+    /// A builder with no keys, the default rate policy, and the mock backend.
+    pub fn builder() -> TestGatewayBuilder {
+        TestGatewayBuilder {
+            keys: Vec::new(),
+            tenants: Vec::new(),
+            rate: RatePolicy::default(),
+            backend: None,
+            script: Vec::new(),
+            faults: Vec::new(),
+            pricing: PricingTable::default(),
+            cache: false,
+            credits: None,
+            clock: None,
+        }
+    }
+
+    /// Base URL such as `http://127.0.0.1:41234`, for clients under test.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The running gateway's state, e.g. to read metrics or change quotas mid-test.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// A router over the same state, for tests that call it in-process.
+    pub fn router(&self) -> Router {
+        build_app(self.state.clone())
+    }
+
+    /// `POST /v1/chat/completions` with `body` as the caller holding `api_key`.
+    pub async fn chat(&self, api_key: &str, body: Value) -> TestResponse {
+        let request = self
+            .client
+            .post(format!("{}/v1/chat/completions", self.url()))
+            .header("x-api-key", api_key)
+            .json(&body);
+        TestResponse::read(request.send().await.expect("test gateway request")).await
+    }
+
+    /// `GET` of any path, such as `/healthz` or an admin endpoint.
+    pub async fn get(&self, path: &str) -> TestResponse {
+        let request = self.client.get(format!("{}{path}", self.url()));
+        TestResponse::read(request.send().await.expect("test gateway request")).await
+    }
+}
+
+impl Drop for TestGateway {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A fully read response; streamed responses hold the raw event-stream text.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    async fn read(response: reqwest::Response) -> Self {
+        let status = StatusCode::from_u16(response.status().as_u16()).expect("status code");
+        let mut headers = HeaderMap::new();
+        for (name, value) in response.headers() {
+            if let (Ok(name), Ok(value)) = (
+                axum::http::HeaderName::from_bytes(name.as_str().as_bytes()),
+                axum::http::HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        let body = response.text().await.expect("test gateway response body");
+        Self {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The body parsed as JSON; panics when it is not.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).expect("json response body")
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::TestGateway;
+    use crate::{auth::KeyPolicy, backend::mock::MockScriptEntry};
+
+    #[tokio::test]
+    async fn serves_scripted_responses_to_configured_keys() {
+        let gateway = TestGateway::builder()
+            .key("test-key", KeyPolicy::default())
+            .script(vec![MockScriptEntry {
+                content: "scripted answer".to_owned(),
+                ..MockScriptEntry::default()
+            }])
+            .start()
+            .await;
+        let body = json!({"model": "mock-1", "messages": [{"role": "user", "content": "hi"}]});
+
+        let response = gateway.chat("test-key", body.clone()).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.json()["choices"][0]["message"]["content"],
+            "scripted answer"
+        );
+        assert_eq!(
+            gateway.chat("other-key", body).await.status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
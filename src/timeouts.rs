@@ -0,0 +1,29 @@
+use std::{env, time::Duration};
+
+/// Server-side time limits for chat requests, applied on top of any client deadline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// Longest the gateway works on one request, streams included. It tightens the client's
+    /// `timeout`/`x-gateway-timeout-ms` deadline and, like it, ends in a `504`.
+    pub request_timeout: Option<Duration>,
+    /// Streams whose backend sends no chunk for this long are aborted.
+    pub stream_idle_timeout: Option<Duration>,
+}
+
+impl TimeoutConfig {
+    pub fn from_env() -> Self {
+        Self {
+            request_timeout: read_nonzero("GATEWAY_REQUEST_TIMEOUT_MS").map(Duration::from_millis),
+            stream_idle_timeout: read_nonzero("GATEWAY_STREAM_IDLE_TIMEOUT_SECS")
+                .map(Duration::from_secs),
+        }
+    }
+}
+
+/// `0`, unset, or unparsable all mean no limit.
+fn read_nonzero(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|value| *value > 0)
+}
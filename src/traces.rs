@@ -0,0 +1,814 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    models::{GenerationParams, NormalizedChatRequest, Usage},
+    usage_sink::UsageRecord,
+};
+
+const QUEUE_CAPACITY: usize = 1_024;
+const BATCH_SIZE: usize = 64;
+const DEFAULT_LANGFUSE_URL: &str = "https://cloud.langfuse.com";
+const DEFAULT_OTLP_URL: &str = "http://localhost:4318";
+const SERVICE_NAME: &str = "rust-llm-inference-gateway";
+
+/// Collector that prompt traces are shipped to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceTarget {
+    /// Langfuse's batch ingestion API, authenticated with a project key pair.
+    Langfuse {
+        base_url: String,
+        public_key: String,
+        secret_key: String,
+    },
+    /// An OTLP/HTTP collector taking JSON spans with GenAI semantic-convention attributes.
+    Otlp {
+        base_url: String,
+        /// Extra request headers, typically the collector's auth.
+        headers: Vec<(String, String)>,
+    },
+}
+
+impl TraceTarget {
+    fn url(&self) -> String {
+        match self {
+            Self::Langfuse { base_url, .. } => {
+                format!("{}/api/public/ingestion", base_url.trim_end_matches('/'))
+            }
+            Self::Otlp { base_url, .. } => format!("{}/v1/traces", base_url.trim_end_matches('/')),
+        }
+    }
+
+    /// The id the collector files the trace of request `request_id` under.
+    pub fn trace_id(&self, request_id: &str) -> String {
+        match self {
+            Self::Langfuse { .. } => request_id.to_owned(),
+            Self::Otlp { .. } => blake3::hash(request_id.as_bytes()).to_hex()[..32].to_owned(),
+        }
+    }
+
+    /// Parses `name=value` headers separated by commas.
+    pub fn parse_headers(raw: &str) -> Result<Vec<(String, String)>, String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("trace header `{entry}` must be `name=value`"))?;
+                Ok((name.trim().to_owned(), value.trim().to_owned()))
+            })
+            .collect()
+    }
+}
+
+/// Which collector format `GATEWAY_TRACE_EXPORT` selects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Off,
+    Langfuse,
+    Otlp,
+}
+
+impl TraceFormat {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "langfuse" => Ok(Self::Langfuse),
+            "otlp" => Ok(Self::Otlp),
+            other => Err(format!(
+                "unknown trace export `{other}`, expected `off`, `langfuse`, or `otlp`"
+            )),
+        }
+    }
+}
+
+/// An evaluation score attached to a trace. The gateway records none itself; the field is
+/// carried so exported traces have the shape scores are later attached to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceScore {
+    pub name: String,
+    pub value: f64,
+}
+
+/// One served prompt and its response.
+#[derive(Debug, Clone)]
+pub struct PromptTrace {
+    pub request_id: String,
+    pub key_id: String,
+    pub tenant_id: String,
+    pub model: String,
+    pub backend: Option<String>,
+    pub stream: bool,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// When the first streamed text arrived; unset for one-shot requests.
+    pub first_token_ms: Option<u64>,
+    /// Messages as sent upstream, or `None` when content capture is off.
+    pub input: Option<Value>,
+    pub output: Option<String>,
+    pub finish_reason: Option<String>,
+    pub parameters: Option<GenerationParams>,
+    pub usage: Usage,
+    pub cost_usd: Option<f64>,
+    pub cache: Option<&'static str>,
+    pub experiment: Option<String>,
+    pub variant: Option<String>,
+    pub scores: Vec<TraceScore>,
+}
+
+impl PromptTrace {
+    fn metadata(&self) -> Map<String, Value> {
+        let mut metadata = Map::new();
+        metadata.insert("request_id".to_owned(), json!(self.request_id));
+        metadata.insert("tenant_id".to_owned(), json!(self.tenant_id));
+        metadata.insert("stream".to_owned(), json!(self.stream));
+        for (name, value) in [
+            ("backend", self.backend.as_deref()),
+            ("cache", self.cache),
+            ("finish_reason", self.finish_reason.as_deref()),
+            ("experiment", self.experiment.as_deref()),
+            ("variant", self.variant.as_deref()),
+        ] {
+            if let Some(value) = value {
+                metadata.insert(name.to_owned(), json!(value));
+            }
+        }
+        metadata
+    }
+
+    /// The Langfuse ingestion events for this trace: the trace, its generation, and its scores.
+    fn to_langfuse_events(&self) -> Vec<Value> {
+        let now = iso8601(unix_millis());
+        let output = self
+            .output
+            .as_ref()
+            .map(|content| json!({ "role": "assistant", "content": content }));
+        let mut generation = json!({
+            "id": format!("{}-generation", self.request_id),
+            "traceId": self.request_id,
+            "name": "chat.completions",
+            "startTime": iso8601(self.start_ms),
+            "endTime": iso8601(self.end_ms),
+            "model": self.model,
+            "modelParameters": self.parameters.as_ref().map(model_parameters).unwrap_or_default(),
+            "input": self.input,
+            "output": output,
+            "usage": {
+                "input": self.usage.prompt_tokens,
+                "output": self.usage.completion_tokens,
+                "total": self.usage.total_tokens,
+                "unit": "TOKENS",
+            },
+            "metadata": self.metadata(),
+        });
+        if let Some(first_token_ms) = self.first_token_ms {
+            generation["completionStartTime"] = json!(iso8601(first_token_ms));
+        }
+        if let Some(cost) = self.cost_usd {
+            generation["costDetails"] = json!({ "total": cost });
+        }
+        let mut tags = vec![self.model.clone()];
+        if let (Some(experiment), Some(variant)) = (&self.experiment, &self.variant) {
+            tags.push(format!("{experiment}:{variant}"));
+        }
+        let mut events = vec![
+            json!({
+                "id": Uuid::new_v4().to_string(),
+                "type": "trace-create",
+                "timestamp": now,
+                "body": {
+                    "id": self.request_id,
+                    "timestamp": iso8601(self.start_ms),
+                    "name": "chat.completions",
+                    "userId": self.key_id,
+                    "input": self.input,
+                    "output": output,
+                    "metadata": self.metadata(),
+                    "tags": tags,
+                },
+            }),
+            json!({
+                "id": Uuid::new_v4().to_string(),
+                "type": "generation-create",
+                "timestamp": now,
+                "body": generation,
+            }),
+        ];
+        events.extend(self.scores.iter().map(|score| {
+            json!({
+                "id": Uuid::new_v4().to_string(),
+                "type": "score-create",
+                "timestamp": now,
+                "body": {
+                    "traceId": self.request_id,
+                    "name": score.name,
+                    "value": score.value,
+                },
+            })
+        }));
+        events
+    }
+
+    /// An OTLP span following the GenAI semantic conventions, with the prompt, completion, and
+    /// scores as span events. Trace and span ids derive from the request id, so the span can be
+    /// found again from `x-request-id`.
+    fn to_otlp_span(&self) -> Value {
+        let ids = blake3::hash(self.request_id.as_bytes()).to_hex();
+        let mut attributes = vec![
+            attribute("gen_ai.operation.name", json!({ "stringValue": "chat" })),
+            attribute(
+                "gen_ai.system",
+                json!({ "stringValue": self.backend.as_deref().unwrap_or("unknown") }),
+            ),
+            attribute("gen_ai.request.model", json!({ "stringValue": self.model })),
+            attribute(
+                "gen_ai.response.model",
+                json!({ "stringValue": self.model }),
+            ),
+            attribute(
+                "gen_ai.usage.input_tokens",
+                json!({ "intValue": self.usage.prompt_tokens.to_string() }),
+            ),
+            attribute(
+                "gen_ai.usage.output_tokens",
+                json!({ "intValue": self.usage.completion_tokens.to_string() }),
+            ),
+            attribute(
+                "gateway.request_id",
+                json!({ "stringValue": self.request_id }),
+            ),
+            attribute("gateway.key_id", json!({ "stringValue": self.key_id })),
+            attribute(
+                "gateway.tenant_id",
+                json!({ "stringValue": self.tenant_id }),
+            ),
+            attribute("gateway.stream", json!({ "boolValue": self.stream })),
+        ];
+        if let Some(parameters) = &self.parameters {
+            if let Some(max_tokens) = parameters.max_tokens {
+                attributes.push(attribute(
+                    "gen_ai.request.max_tokens",
+                    json!({ "intValue": max_tokens.to_string() }),
+                ));
+            }
+            for (name, value) in [
+                ("gen_ai.request.temperature", parameters.temperature),
+                ("gen_ai.request.top_p", parameters.top_p),
+            ] {
+                if let Some(value) = value {
+                    attributes.push(attribute(name, json!({ "doubleValue": value })));
+                }
+            }
+        }
+        if let Some(finish_reason) = &self.finish_reason {
+            attributes.push(attribute(
+                "gen_ai.response.finish_reasons",
+                json!({ "arrayValue": { "values": [{ "stringValue": finish_reason }] } }),
+            ));
+        }
+        if let Some(cost) = self.cost_usd {
+            attributes.push(attribute(
+                "gateway.cost_usd",
+                json!({ "doubleValue": cost }),
+            ));
+        }
+        for (name, value) in [
+            ("gateway.cache", self.cache),
+            ("gateway.experiment", self.experiment.as_deref()),
+            ("gateway.variant", self.variant.as_deref()),
+        ] {
+            if let Some(value) = value {
+                attributes.push(attribute(name, json!({ "stringValue": value })));
+            }
+        }
+
+        let mut events = Vec::new();
+        for message in self
+            .input
+            .as_ref()
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            events.push(json!({
+                "name": format!("gen_ai.{}.message", message["role"].as_str().unwrap_or("user")),
+                "timeUnixNano": nanos(self.start_ms),
+                "attributes": [attribute("content", json!({ "stringValue": message["content"] }))],
+            }));
+        }
+        if let Some(output) = &self.output {
+            events.push(json!({
+                "name": "gen_ai.choice",
+                "timeUnixNano": nanos(self.end_ms),
+                "attributes": [
+                    attribute("index", json!({ "intValue": "0" })),
+                    attribute(
+                        "finish_reason",
+                        json!({ "stringValue": self.finish_reason.as_deref().unwrap_or("stop") }),
+                    ),
+                    attribute("content", json!({ "stringValue": output })),
+                ],
+            }));
+        }
+        for score in &self.scores {
+            events.push(json!({
+                "name": "gen_ai.evaluation.result",
+                "timeUnixNano": nanos(self.end_ms),
+                "attributes": [
+                    attribute("gen_ai.evaluation.name", json!({ "stringValue": score.name })),
+                    attribute("gen_ai.evaluation.score.value", json!({ "doubleValue": score.value })),
+                ],
+            }));
+        }
+
+        json!({
+            "traceId": &ids[..32],
+            "spanId": &ids[32..48],
+            "name": format!("chat {}", self.model),
+            // SPAN_KIND_CLIENT: the gateway calls the model on the caller's behalf.
+            "kind": 3,
+            "startTimeUnixNano": nanos(self.start_ms),
+            "endTimeUnixNano": nanos(self.end_ms),
+            "attributes": attributes,
+            "events": events,
+        })
+    }
+}
+
+fn model_parameters(parameters: &GenerationParams) -> Map<String, Value> {
+    let mut map = Map::new();
+    if let Some(max_tokens) = parameters.max_tokens {
+        map.insert("max_tokens".to_owned(), json!(max_tokens));
+    }
+    if let Some(temperature) = parameters.temperature {
+        map.insert("temperature".to_owned(), json!(temperature));
+    }
+    if let Some(top_p) = parameters.top_p {
+        map.insert("top_p".to_owned(), json!(top_p));
+    }
+    map
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn nanos(unix_ms: u64) -> String {
+    (u128::from(unix_ms) * 1_000_000).to_string()
+}
+
+fn langfuse_batch(traces: &[PromptTrace]) -> Value {
+    json!({ "batch": traces.iter().flat_map(PromptTrace::to_langfuse_events).collect::<Vec<_>>() })
+}
+
+fn otlp_export(traces: &[PromptTrace]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!({ "stringValue": SERVICE_NAME })),
+                    attribute(
+                        "service.version",
+                        json!({ "stringValue": env!("CARGO_PKG_VERSION") }),
+                    ),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                "spans": traces.iter().map(PromptTrace::to_otlp_span).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+/// Ships a trace of every completed request to a prompt-analytics collector off the request
+/// path. Traces are queued and sent in batches by a background task; when the queue is full
+/// they are dropped.
+pub struct TraceExporter {
+    tx: Option<mpsc::Sender<PromptTrace>>,
+    target: Option<TraceTarget>,
+    capture_content: bool,
+}
+
+impl TraceExporter {
+    /// Enabled by `GATEWAY_TRACE_EXPORT` (`langfuse` or `otlp`), sending to
+    /// `GATEWAY_TRACE_ENDPOINT`. Langfuse needs `GATEWAY_LANGFUSE_PUBLIC_KEY` and
+    /// `GATEWAY_LANGFUSE_SECRET_KEY`; OTLP collectors get `GATEWAY_TRACE_HEADERS`.
+    /// `GATEWAY_TRACE_CAPTURE_CONTENT=false` leaves prompts and completions out.
+    pub fn from_env() -> Self {
+        let format = env::var("GATEWAY_TRACE_EXPORT")
+            .ok()
+            .map(|raw| {
+                TraceFormat::parse(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_TRACE_EXPORT, trace export disabled");
+                    TraceFormat::Off
+                })
+            })
+            .unwrap_or_default();
+        let endpoint = env::var("GATEWAY_TRACE_ENDPOINT")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let target = match format {
+            TraceFormat::Off => return Self::disabled(),
+            TraceFormat::Langfuse => {
+                let key = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+                let (Some(public_key), Some(secret_key)) = (
+                    key("GATEWAY_LANGFUSE_PUBLIC_KEY"),
+                    key("GATEWAY_LANGFUSE_SECRET_KEY"),
+                ) else {
+                    warn!("Langfuse trace export needs GATEWAY_LANGFUSE_PUBLIC_KEY and GATEWAY_LANGFUSE_SECRET_KEY, trace export disabled");
+                    return Self::disabled();
+                };
+                TraceTarget::Langfuse {
+                    base_url: endpoint.unwrap_or_else(|| DEFAULT_LANGFUSE_URL.to_owned()),
+                    public_key,
+                    secret_key,
+                }
+            }
+            TraceFormat::Otlp => TraceTarget::Otlp {
+                base_url: endpoint.unwrap_or_else(|| DEFAULT_OTLP_URL.to_owned()),
+                headers: env::var("GATEWAY_TRACE_HEADERS")
+                    .ok()
+                    .map(|raw| {
+                        TraceTarget::parse_headers(&raw).unwrap_or_else(|error| {
+                            warn!(error = %error, "invalid GATEWAY_TRACE_HEADERS, sending traces without extra headers");
+                            Vec::new()
+                        })
+                    })
+                    .unwrap_or_default(),
+            },
+        };
+        let capture_content = env::var("GATEWAY_TRACE_CAPTURE_CONTENT")
+            .map(|value| value != "0" && !value.eq_ignore_ascii_case("false"))
+            .unwrap_or(true);
+        Self::new(target, capture_content)
+    }
+
+    pub fn new(target: TraceTarget, capture_content: bool) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run_sender(target.clone(), rx));
+        Self {
+            tx: Some(tx),
+            target: Some(target),
+            capture_content,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            tx: None,
+            target: None,
+            capture_content: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// The id request `request_id`'s trace is exported under, when traces are exported.
+    pub fn trace_id(&self, request_id: &str) -> Option<String> {
+        self.target
+            .as_ref()
+            .map(|target| target.trace_id(request_id))
+    }
+
+    /// A capture for one request; it records nothing when export is off.
+    pub fn start(&self) -> TraceCapture {
+        TraceCapture {
+            inner: self.tx.clone().map(|tx| {
+                Arc::new(CaptureInner {
+                    tx,
+                    capture_content: self.capture_content,
+                    fields: Mutex::default(),
+                })
+            }),
+        }
+    }
+}
+
+/// Trace content gathered while one request is served. Clones share the same capture, so the
+/// handler and the streamed body can both fill it in; `finish` queues the trace.
+#[derive(Clone, Default)]
+pub struct TraceCapture {
+    inner: Option<Arc<CaptureInner>>,
+}
+
+struct CaptureInner {
+    tx: mpsc::Sender<PromptTrace>,
+    capture_content: bool,
+    fields: Mutex<CaptureFields>,
+}
+
+#[derive(Default)]
+struct CaptureFields {
+    input: Option<Value>,
+    parameters: Option<GenerationParams>,
+    output: String,
+    finish_reason: Option<String>,
+    first_token_ms: Option<u64>,
+}
+
+impl TraceCapture {
+    /// Records the request as it is sent upstream, after system prompts and truncation.
+    pub fn set_input(&self, request: &NormalizedChatRequest) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut fields = inner.lock();
+        fields.parameters = Some(request.generation.clone());
+        if inner.capture_content {
+            fields.input = Some(json!(request.messages));
+        }
+    }
+
+    /// Appends response text as the client receives it.
+    pub fn push_output(&self, text: &str) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut fields = inner.lock();
+        fields.first_token_ms.get_or_insert_with(unix_millis);
+        if inner.capture_content {
+            fields.output.push_str(text);
+        }
+    }
+
+    pub fn set_finish_reason(&self, finish_reason: &str) {
+        if let Some(inner) = &self.inner {
+            inner.lock().finish_reason = Some(finish_reason.to_owned());
+        }
+    }
+
+    /// Queues the trace of a request that reported usage, timed and attributed by `record`.
+    pub fn finish(&self, record: &UsageRecord) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let fields = inner.lock();
+        let trace = PromptTrace {
+            request_id: record.request_id.clone(),
+            key_id: record.key_id.clone(),
+            tenant_id: record.tenant_id.clone(),
+            model: record.model.clone(),
+            backend: record.backend.clone(),
+            stream: record.stream,
+            start_ms: record.timestamp_ms.saturating_sub(record.latency_ms),
+            end_ms: record.timestamp_ms,
+            first_token_ms: fields.first_token_ms.filter(|_| record.stream),
+            input: fields.input.clone(),
+            output: inner.capture_content.then(|| fields.output.clone()),
+            finish_reason: fields.finish_reason.clone(),
+            parameters: fields.parameters.clone(),
+            usage: Usage::new(record.prompt_tokens, record.completion_tokens),
+            cost_usd: record.cost_usd,
+            cache: record.cache,
+            experiment: record.experiment.clone(),
+            variant: record.variant.clone(),
+            scores: Vec::new(),
+        };
+        drop(fields);
+        let _ = inner.tx.try_send(trace);
+    }
+}
+
+impl CaptureInner {
+    fn lock(&self) -> std::sync::MutexGuard<'_, CaptureFields> {
+        self.fields
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+async fn run_sender(target: TraceTarget, mut rx: mpsc::Receiver<PromptTrace>) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(error) => {
+            warn!(error = %error, "failed to build trace export client; trace export disabled");
+            return;
+        }
+    };
+    let url = target.url();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some(trace) = rx.recv().await {
+        batch.push(trace);
+        while batch.len() < BATCH_SIZE {
+            match rx.try_recv() {
+                Ok(trace) => batch.push(trace),
+                Err(_) => break,
+            }
+        }
+        let request = match &target {
+            TraceTarget::Langfuse {
+                public_key,
+                secret_key,
+                ..
+            } => client
+                .post(&url)
+                .basic_auth(public_key, Some(secret_key))
+                .json(&langfuse_batch(&batch)),
+            TraceTarget::Otlp { headers, .. } => headers.iter().fold(
+                client.post(&url).json(&otlp_export(&batch)),
+                |request, (name, value)| request.header(name, value),
+            ),
+        };
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                warn!(status = %response.status(), traces = batch.len(), "trace collector rejected batch")
+            }
+            Err(error) => warn!(error = %error, traces = batch.len(), "failed to export traces"),
+        }
+        batch.clear();
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Formats a Unix time in milliseconds as an RFC 3339 UTC timestamp.
+fn iso8601(unix_ms: u64) -> String {
+    let days = (unix_ms / 86_400_000) as i64;
+    let ms_of_day = unix_ms % 86_400_000;
+    // Civil-from-days over 400-year eras, as in Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1_000 % 60,
+        ms_of_day % 1_000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{iso8601, langfuse_batch, otlp_export, PromptTrace, TraceScore, TraceTarget};
+    use crate::models::{GenerationParams, Usage};
+
+    fn trace() -> PromptTrace {
+        PromptTrace {
+            request_id: "req_1".to_owned(),
+            key_id: "key_dev".to_owned(),
+            tenant_id: "acme".to_owned(),
+            model: "gpt-4o".to_owned(),
+            backend: Some("openai".to_owned()),
+            stream: true,
+            start_ms: 1_700_000_000_000,
+            end_ms: 1_700_000_001_500,
+            first_token_ms: Some(1_700_000_000_250),
+            input: Some(json!([{ "role": "user", "content": "hello" }])),
+            output: Some("hi there".to_owned()),
+            finish_reason: Some("stop".to_owned()),
+            parameters: Some(GenerationParams {
+                max_tokens: Some(64),
+                temperature: Some(0.5),
+                top_p: None,
+            }),
+            usage: Usage::new(12, 3),
+            cost_usd: Some(0.002),
+            cache: Some("miss"),
+            experiment: Some("mini".to_owned()),
+            variant: Some("control".to_owned()),
+            scores: vec![TraceScore {
+                name: "helpfulness".to_owned(),
+                value: 0.9,
+            }],
+        }
+    }
+
+    #[test]
+    fn langfuse_batches_carry_trace_generation_and_scores() {
+        let batch = langfuse_batch(&[trace()]);
+        let events = batch["batch"].as_array().expect("events");
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(events[0]["type"], "trace-create");
+        assert_eq!(events[0]["body"]["id"], "req_1");
+        assert_eq!(events[0]["body"]["userId"], "key_dev");
+        assert_eq!(events[0]["body"]["tags"], json!(["gpt-4o", "mini:control"]));
+
+        let generation = &events[1]["body"];
+        assert_eq!(events[1]["type"], "generation-create");
+        assert_eq!(generation["traceId"], "req_1");
+        assert_eq!(generation["startTime"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(
+            generation["completionStartTime"],
+            "2023-11-14T22:13:20.250Z"
+        );
+        assert_eq!(generation["output"]["content"], "hi there");
+        assert_eq!(generation["usage"]["input"], 12);
+        assert_eq!(generation["usage"]["total"], 15);
+        assert_eq!(generation["modelParameters"]["max_tokens"], 64);
+        assert!(generation["modelParameters"].get("top_p").is_none());
+        assert_eq!(generation["metadata"]["tenant_id"], "acme");
+
+        assert_eq!(events[2]["type"], "score-create");
+        assert_eq!(events[2]["body"]["name"], "helpfulness");
+    }
+
+    #[test]
+    fn otlp_spans_follow_genai_semantic_conventions() {
+        let export = otlp_export(&[trace()]);
+        let span = &export["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "chat gpt-4o");
+        assert_eq!(span["traceId"].as_str().map(str::len), Some(32));
+        // Metric exemplars name the same trace.
+        let target = TraceTarget::Otlp {
+            base_url: "http://collector:4318".to_owned(),
+            headers: Vec::new(),
+        };
+        assert_eq!(span["traceId"], target.trace_id("req_1"));
+        assert_eq!(span["spanId"].as_str().map(str::len), Some(16));
+        assert_eq!(span["startTimeUnixNano"], "1700000000000000000");
+
+        let attribute = |key: &str| {
+            span["attributes"]
+                .as_array()
+                .and_then(|attributes| attributes.iter().find(|item| item["key"] == key))
+                .map(|item| item["value"].clone())
+        };
+        assert_eq!(
+            attribute("gen_ai.usage.input_tokens"),
+            Some(json!({ "intValue": "12" }))
+        );
+        assert_eq!(
+            attribute("gen_ai.response.finish_reasons"),
+            Some(json!({ "arrayValue": { "values": [{ "stringValue": "stop" }] } }))
+        );
+        assert_eq!(attribute("gen_ai.request.top_p"), None);
+
+        let events = span["events"].as_array().expect("events");
+        let names = events
+            .iter()
+            .map(|event| event["name"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "gen_ai.user.message",
+                "gen_ai.choice",
+                "gen_ai.evaluation.result"
+            ]
+        );
+
+        let untraced = PromptTrace {
+            input: None,
+            output: None,
+            ..trace()
+        };
+        assert_eq!(
+            otlp_export(&[untraced])["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["events"]
+                .as_array()
+                .map(Vec::len),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn formats_timestamps_and_parses_headers() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(951_782_400_123), "2000-02-29T00:00:00.123Z");
+        assert_eq!(
+            TraceTarget::parse_headers("authorization=Bearer abc, x-team=analytics")
+                .expect("valid headers"),
+            [
+                ("authorization".to_owned(), "Bearer abc".to_owned()),
+                ("x-team".to_owned(), "analytics".to_owned())
+            ]
+        );
+        assert!(TraceTarget::parse_headers("no-value").is_err());
+    }
+}
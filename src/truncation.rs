@@ -0,0 +1,314 @@
+use std::{env, sync::Arc};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    backend::{with_deadline, InferenceBackend},
+    limits::estimate_prompt_tokens,
+    models::{ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage},
+};
+
+const DEFAULT_SUMMARY_MAX_TOKENS: u32 = 256;
+
+const SUMMARY_INSTRUCTIONS: &str = "Summarize the conversation below in a few sentences. Keep \
+names, numbers, decisions, and open questions; leave out pleasantries.";
+
+/// How a conversation too long for its model's context window is shortened. Reported back in
+/// `x-gateway-truncation` when it changed the request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TruncationStrategy {
+    /// Leaves the conversation alone, so an oversized one is rejected.
+    #[default]
+    Off,
+    /// Drops the oldest non-system messages until the rest fit.
+    Oldest,
+    /// Drops the same messages but first has the summary model condense them into a system
+    /// message. Falls back to `oldest` when the summary call fails.
+    Summarize,
+}
+
+impl TruncationStrategy {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "oldest" => Ok(Self::Oldest),
+            "summarize" => Ok(Self::Summarize),
+            other => Err(format!(
+                "unknown truncation strategy `{other}`, expected `off`, `oldest`, or `summarize`"
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Oldest => "oldest",
+            Self::Summarize => "summarize",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruncationConfig {
+    /// Applies to keys whose policy does not pick a strategy.
+    pub strategy: TruncationStrategy,
+    /// Cheap model that writes summaries; `summarize` acts as `oldest` without one.
+    pub summary_model: Option<String>,
+    pub summary_max_tokens: u32,
+}
+
+impl Default for TruncationConfig {
+    fn default() -> Self {
+        Self {
+            strategy: TruncationStrategy::Off,
+            summary_model: None,
+            summary_max_tokens: DEFAULT_SUMMARY_MAX_TOKENS,
+        }
+    }
+}
+
+impl TruncationConfig {
+    pub fn from_env() -> Self {
+        let strategy = env::var("GATEWAY_TRUNCATION")
+            .ok()
+            .map(|raw| {
+                TruncationStrategy::parse(&raw).unwrap_or_else(|error| {
+                    warn!(error = %error, "invalid GATEWAY_TRUNCATION, leaving conversations untruncated");
+                    TruncationStrategy::Off
+                })
+            })
+            .unwrap_or_default();
+        Self {
+            strategy,
+            summary_model: env::var("GATEWAY_TRUNCATION_SUMMARY_MODEL")
+                .ok()
+                .filter(|model| !model.trim().is_empty()),
+            summary_max_tokens: env::var("GATEWAY_TRUNCATION_SUMMARY_MAX_TOKENS")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|tokens| *tokens > 0)
+                .unwrap_or(DEFAULT_SUMMARY_MAX_TOKENS),
+        }
+    }
+
+    /// Shortens `request` so its prompt plus `max_tokens` fits `window`, returning the strategy
+    /// that changed it. The newest message is always kept, so a request that still does not
+    /// fit is left for the context-window check to reject.
+    pub async fn fit(
+        &self,
+        strategy: TruncationStrategy,
+        backend: &Arc<dyn InferenceBackend>,
+        request: &mut NormalizedChatRequest,
+        window: u64,
+    ) -> Option<TruncationStrategy> {
+        let budget = window.saturating_sub(request.generation.max_tokens.map_or(0, u64::from));
+        if strategy == TruncationStrategy::Off || estimate_prompt_tokens(request) <= budget {
+            return None;
+        }
+        let dropped = drop_oldest(request, budget);
+        if dropped.is_empty() {
+            return None;
+        }
+        if strategy == TruncationStrategy::Summarize {
+            if let Some(summary) = self.summarize(backend, request, &dropped).await {
+                let at = request
+                    .messages
+                    .iter()
+                    .position(|message| message.role != MessageRole::System)
+                    .unwrap_or(request.messages.len());
+                request.messages.insert(
+                    at,
+                    NormalizedMessage {
+                        role: MessageRole::System,
+                        content: format!("Summary of the earlier conversation: {summary}"),
+                        cache_control: None,
+                    },
+                );
+                drop_oldest(request, budget);
+                return Some(TruncationStrategy::Summarize);
+            }
+        }
+        Some(TruncationStrategy::Oldest)
+    }
+
+    async fn summarize(
+        &self,
+        backend: &Arc<dyn InferenceBackend>,
+        request: &NormalizedChatRequest,
+        dropped: &[NormalizedMessage],
+    ) -> Option<String> {
+        let model = self.summary_model.clone()?;
+        let transcript = dropped
+            .iter()
+            .map(|message| format!("{:?}: {}", message.role, message.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let summary_request = Arc::new(NormalizedChatRequest {
+            request_id: format!("{}-summary", request.request_id),
+            user_id: request.user_id.clone(),
+            model,
+            messages: vec![
+                NormalizedMessage {
+                    role: MessageRole::System,
+                    content: SUMMARY_INSTRUCTIONS.to_owned(),
+                    cache_control: None,
+                },
+                NormalizedMessage {
+                    role: MessageRole::User,
+                    content: transcript,
+                    cache_control: None,
+                },
+            ],
+            generation: GenerationParams {
+                max_tokens: Some(self.summary_max_tokens),
+                temperature: Some(0.0),
+                top_p: None,
+            },
+            stream: false,
+            priority: request.priority,
+            deadline: request.deadline,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        });
+        match with_deadline(
+            summary_request.deadline,
+            "truncation_summary",
+            backend.execute_chat(summary_request.clone()),
+        )
+        .await
+        {
+            Ok(response) if !response.content.trim().is_empty() => Some(response.content),
+            Ok(_) => None,
+            Err(error) => {
+                warn!(error = %error, "conversation summary failed, dropping oldest messages instead");
+                None
+            }
+        }
+    }
+}
+
+/// Removes the oldest non-system messages, never the newest message, until the prompt fits
+/// `budget`; returns them in their original order.
+fn drop_oldest(request: &mut NormalizedChatRequest, budget: u64) -> Vec<NormalizedMessage> {
+    let mut dropped = Vec::new();
+    while estimate_prompt_tokens(request) > budget {
+        let newest = request.messages.len().saturating_sub(1);
+        let Some(index) = request.messages[..newest]
+            .iter()
+            .position(|message| message.role != MessageRole::System)
+        else {
+            break;
+        };
+        dropped.push(request.messages.remove(index));
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{TruncationConfig, TruncationStrategy};
+    use crate::{
+        backend::{mock::MockBackend, InferenceBackend},
+        models::{
+            ClientTags, GenerationParams, MessageRole, NormalizedChatRequest, NormalizedMessage,
+            Priority,
+        },
+    };
+
+    fn message(role: MessageRole, words: usize) -> NormalizedMessage {
+        NormalizedMessage {
+            role,
+            content: vec!["word"; words].join(" "),
+            cache_control: None,
+        }
+    }
+
+    fn request() -> NormalizedChatRequest {
+        NormalizedChatRequest {
+            request_id: "req_1".to_owned(),
+            user_id: "user_a".to_owned(),
+            model: "gpt-test".to_owned(),
+            messages: vec![
+                message(MessageRole::System, 10),
+                message(MessageRole::User, 40),
+                message(MessageRole::Assistant, 40),
+                message(MessageRole::User, 20),
+            ],
+            generation: GenerationParams {
+                max_tokens: Some(20),
+                temperature: None,
+                top_p: None,
+            },
+            stream: false,
+            priority: Priority::Normal,
+            deadline: None,
+            passthrough: false,
+            pinned_backend: None,
+            client: ClientTags::default(),
+        }
+    }
+
+    fn roles(request: &NormalizedChatRequest) -> Vec<MessageRole> {
+        request
+            .messages
+            .iter()
+            .map(|message| message.role.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_messages_or_replaces_them_with_a_summary() {
+        let backend: Arc<dyn InferenceBackend> = Arc::new(MockBackend::default());
+        let config = TruncationConfig {
+            summary_model: Some("mock-mini".to_owned()),
+            ..TruncationConfig::default()
+        };
+
+        let mut untouched = request();
+        assert_eq!(
+            config
+                .fit(TruncationStrategy::Oldest, &backend, &mut untouched, 1000)
+                .await,
+            None
+        );
+        assert_eq!(untouched.messages.len(), 4);
+
+        let mut trimmed = request();
+        let applied = config
+            .fit(TruncationStrategy::Oldest, &backend, &mut trimmed, 100)
+            .await;
+        assert_eq!(applied, Some(TruncationStrategy::Oldest));
+        assert_eq!(
+            roles(&trimmed),
+            [
+                MessageRole::System,
+                MessageRole::Assistant,
+                MessageRole::User
+            ]
+        );
+
+        // The summary takes room of its own, so the assistant turn goes as well.
+        let mut summarized = request();
+        let applied = config
+            .fit(
+                TruncationStrategy::Summarize,
+                &backend,
+                &mut summarized,
+                120,
+            )
+            .await;
+        assert_eq!(applied, Some(TruncationStrategy::Summarize));
+        assert_eq!(
+            roles(&summarized),
+            [MessageRole::System, MessageRole::System, MessageRole::User]
+        );
+        assert!(summarized.messages[1]
+            .content
+            .starts_with("Summary of the earlier conversation: Mock response"));
+    }
+}
@@ -0,0 +1,395 @@
+use std::{collections::BTreeMap, env, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::warn;
+
+use crate::metrics::AppMetrics;
+
+/// One served chat request as persisted for billing and usage reporting.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageRecord {
+    pub timestamp_ms: u64,
+    pub request_id: String,
+    pub key_id: String,
+    /// Tenant the key belongs to, for per-tenant usage rollups.
+    pub tenant_id: String,
+    pub model: String,
+    pub backend: Option<String>,
+    pub stream: bool,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    /// Upstream spend; only set for requests that actually triggered a priced backend call.
+    pub cost_usd: Option<f64>,
+    pub latency_ms: u64,
+    pub cache: Option<&'static str>,
+    pub coalesced: Option<&'static str>,
+    /// A/B experiment and variant the request was assigned to. Omitted when unset, so tables
+    /// created before experiments existed keep accepting records from untagged traffic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// The request's own `user` and `metadata` tags, omitted when it sent none, for the same
+    /// reason.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+/// Destination database for usage records; each call receives one flushed batch.
+#[async_trait]
+pub trait UsageWriter: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn write_batch(&self, records: &[UsageRecord]) -> Result<(), String>;
+}
+
+/// Usage totals for one key over a report window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyUsageTotals {
+    pub tenant_id: String,
+    pub key_id: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+}
+
+/// Read side of the usage store, aggregating persisted records for reports.
+#[async_trait]
+pub trait UsageQuery: Send + Sync {
+    /// Totals per key for records with `from_ms <= timestamp_ms < to_ms`.
+    async fn key_totals(&self, from_ms: u64, to_ms: u64) -> Result<Vec<KeyUsageTotals>, String>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UsageSinkConfig {
+    /// Records written per insert.
+    pub batch_size: usize,
+    /// Longest a record waits for its batch to fill before it is written anyway.
+    pub flush_interval: Duration,
+    /// Records buffered ahead of the writer; past this new records are dropped.
+    pub queue_capacity: usize,
+}
+
+impl UsageSinkConfig {
+    pub fn from_env() -> Self {
+        Self {
+            batch_size: read_usize("GATEWAY_USAGE_BATCH_SIZE", 500).max(1),
+            flush_interval: Duration::from_millis(read_usize(
+                "GATEWAY_USAGE_FLUSH_INTERVAL_MS",
+                1_000,
+            ) as u64),
+            queue_capacity: read_usize("GATEWAY_USAGE_QUEUE_CAPACITY", 10_000).max(1),
+        }
+    }
+}
+
+/// Buffers usage records off the request path and writes them to a [`UsageWriter`] in
+/// batches, so per-key accounting does not depend on Prometheus label cardinality.
+pub struct UsageSink {
+    tx: Option<mpsc::Sender<UsageRecord>>,
+    metrics: Option<Arc<AppMetrics>>,
+    query: Option<Arc<dyn UsageQuery>>,
+}
+
+impl UsageSink {
+    /// Builds the sink selected by `GATEWAY_USAGE_SINK` (`off` or `clickhouse`).
+    pub fn from_env(metrics: Arc<AppMetrics>) -> Self {
+        let sink = env::var("GATEWAY_USAGE_SINK").unwrap_or_default();
+        match sink.trim().to_ascii_lowercase().as_str() {
+            "" | "off" => Self::disabled(),
+            "clickhouse" => match ClickHouseWriter::from_env() {
+                Ok(writer) => {
+                    let writer = Arc::new(writer);
+                    Self::new(writer.clone(), UsageSinkConfig::from_env(), metrics)
+                        .with_query(writer)
+                }
+                Err(error) => {
+                    warn!(error = %error, "usage sink disabled");
+                    Self::disabled()
+                }
+            },
+            other => {
+                warn!(sink = %other, "unknown GATEWAY_USAGE_SINK, usage sink disabled");
+                Self::disabled()
+            }
+        }
+    }
+
+    pub fn new(
+        writer: Arc<dyn UsageWriter>,
+        config: UsageSinkConfig,
+        metrics: Arc<AppMetrics>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_capacity);
+        tokio::spawn(run_writer(writer, config, rx, metrics.clone()));
+        Self {
+            tx: Some(tx),
+            metrics: Some(metrics),
+            query: None,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            tx: None,
+            metrics: None,
+            query: None,
+        }
+    }
+
+    /// Lets admin reports read back what the sink stored.
+    pub fn with_query(mut self, query: Arc<dyn UsageQuery>) -> Self {
+        self.query = Some(query);
+        self
+    }
+
+    pub fn query(&self) -> Option<&Arc<dyn UsageQuery>> {
+        self.query.as_ref()
+    }
+
+    /// Queues `record` for the next batch; drops it if the writer has fallen too far behind.
+    pub fn record(&self, record: UsageRecord) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if tx.try_send(record).is_err() {
+            if let Some(metrics) = &self.metrics {
+                metrics.observe_usage_records("dropped", 1);
+            }
+        }
+    }
+}
+
+async fn run_writer(
+    writer: Arc<dyn UsageWriter>,
+    config: UsageSinkConfig,
+    mut rx: mpsc::Receiver<UsageRecord>,
+    metrics: Arc<AppMetrics>,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    while let Some(first) = rx.recv().await {
+        batch.push(first);
+        let flush_at = Instant::now() + config.flush_interval;
+        while batch.len() < config.batch_size {
+            tokio::select! {
+                next = rx.recv() => match next {
+                    Some(record) => batch.push(record),
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(flush_at) => break,
+            }
+        }
+
+        match writer.write_batch(&batch).await {
+            Ok(()) => metrics.observe_usage_records("written", batch.len()),
+            Err(error) => {
+                warn!(
+                    writer = writer.name(),
+                    records = batch.len(),
+                    error = %error,
+                    "failed to write usage batch"
+                );
+                metrics.observe_usage_records("failed", batch.len());
+            }
+        }
+        batch.clear();
+    }
+}
+
+/// Inserts batches as `JSONEachRow` over ClickHouse's HTTP interface.
+pub struct ClickHouseWriter {
+    client: reqwest::Client,
+    url: String,
+    table: String,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl ClickHouseWriter {
+    pub fn from_env() -> Result<Self, String> {
+        let url = env::var("GATEWAY_USAGE_CLICKHOUSE_URL")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .ok_or_else(|| "GATEWAY_USAGE_CLICKHOUSE_URL is required".to_owned())?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|error| format!("failed to build ClickHouse HTTP client: {error}"))?;
+        Ok(Self {
+            client,
+            url: url.trim_end_matches('/').to_owned(),
+            table: env::var("GATEWAY_USAGE_CLICKHOUSE_TABLE")
+                .unwrap_or_else(|_| "gateway_usage".to_owned()),
+            user: env::var("GATEWAY_USAGE_CLICKHOUSE_USER").ok(),
+            password: env::var("GATEWAY_USAGE_CLICKHOUSE_PASSWORD").ok(),
+        })
+    }
+}
+
+#[async_trait]
+impl UsageWriter for ClickHouseWriter {
+    fn name(&self) -> &str {
+        "clickhouse"
+    }
+
+    async fn write_batch(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let mut body = String::new();
+        for record in records {
+            body.push_str(&serde_json::to_string(record).map_err(|error| error.to_string())?);
+            body.push('\n');
+        }
+
+        let mut request = self
+            .client
+            .post(format!("{}/", self.url))
+            .query(&[(
+                "query",
+                format!("INSERT INTO {} FORMAT JSONEachRow", self.table),
+            )])
+            .body(body);
+        if let Some(user) = &self.user {
+            request = request.basic_auth(user, self.password.as_deref());
+        }
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let detail = response.text().await.unwrap_or_default();
+            return Err(format!("ClickHouse returned {status}: {detail}"));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UsageQuery for ClickHouseWriter {
+    async fn key_totals(&self, from_ms: u64, to_ms: u64) -> Result<Vec<KeyUsageTotals>, String> {
+        let query = format!(
+            "SELECT tenant_id, key_id, count() AS requests, \
+             sum(prompt_tokens) AS prompt_tokens, sum(completion_tokens) AS completion_tokens, \
+             sum(total_tokens) AS total_tokens, sum(ifNull(cost_usd, 0)) AS cost_usd \
+             FROM {} WHERE timestamp_ms >= {from_ms} AND timestamp_ms < {to_ms} \
+             GROUP BY tenant_id, key_id FORMAT JSONEachRow",
+            self.table
+        );
+        let mut request = self.client.post(format!("{}/", self.url)).query(&[
+            ("query", query.as_str()),
+            // Sums are UInt64, which ClickHouse otherwise quotes in JSON output.
+            ("output_format_json_quote_64bit_integers", "0"),
+        ]);
+        if let Some(user) = &self.user {
+            request = request.basic_auth(user, self.password.as_deref());
+        }
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        let status = response.status();
+        let body = response.text().await.map_err(|error| error.to_string())?;
+        if !status.is_success() {
+            return Err(format!("ClickHouse returned {status}: {body}"));
+        }
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|error| format!("unexpected ClickHouse row: {error}"))
+            })
+            .collect()
+    }
+}
+
+fn read_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{UsageRecord, UsageSink, UsageSinkConfig, UsageWriter};
+    use crate::metrics::AppMetrics;
+
+    #[derive(Default)]
+    struct MemoryWriter {
+        batches: Mutex<Vec<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl UsageWriter for MemoryWriter {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        async fn write_batch(&self, records: &[UsageRecord]) -> Result<(), String> {
+            self.batches.lock().expect("batches").push(
+                records
+                    .iter()
+                    .map(|record| record.request_id.clone())
+                    .collect(),
+            );
+            Ok(())
+        }
+    }
+
+    fn record(request_id: &str) -> UsageRecord {
+        UsageRecord {
+            timestamp_ms: 0,
+            request_id: request_id.to_owned(),
+            key_id: "key_dev".to_owned(),
+            tenant_id: "dev".to_owned(),
+            model: "mock-1".to_owned(),
+            backend: Some("mock-a".to_owned()),
+            stream: false,
+            prompt_tokens: 3,
+            completion_tokens: 4,
+            total_tokens: 7,
+            cost_usd: None,
+            latency_ms: 12,
+            cache: Some("miss"),
+            coalesced: Some("leader"),
+            experiment: None,
+            variant: None,
+            client_user: None,
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_full_batches_then_flushes_the_rest_on_interval() {
+        let metrics = Arc::new(AppMetrics::new());
+        let writer = Arc::new(MemoryWriter::default());
+        let sink = UsageSink::new(
+            writer.clone(),
+            UsageSinkConfig {
+                batch_size: 2,
+                flush_interval: Duration::from_millis(20),
+                queue_capacity: 16,
+            },
+            metrics.clone(),
+        );
+
+        for request_id in ["req_1", "req_2", "req_3"] {
+            sink.record(record(request_id));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            *writer.batches.lock().expect("batches"),
+            vec![vec!["req_1", "req_2"], vec!["req_3"]]
+        );
+        let rendered = metrics.render().expect("render metrics");
+        assert!(rendered.contains("gateway_usage_records_total{outcome=\"written\"} 3"));
+    }
+}
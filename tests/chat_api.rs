@@ -1,12 +1,46 @@
-use std::env;
+use std::{env, net::SocketAddr, time::Duration};
 
+use async_trait::async_trait;
 use axum::{
-    body::{to_bytes, Body},
+    body::{to_bytes, Body, HttpBody},
+    extract::ConnectInfo,
     http::{Request, StatusCode},
 };
-use rust_llm_inference_gateway::{backend::mock::MockBackend, build_app, state::AppState};
+use futures_util::StreamExt;
+use rust_llm_inference_gateway::{
+    admin::{AdminConfig, Cidr},
+    admission::{AdmissionConfig, AdmissionController},
+    auth::{ApiKeyRegistry, KeyGrant, KeyPolicy, KeyStore, RatePolicy},
+    backend::{mock::MockBackend, BackendError, BackendStream, InferenceBackend},
+    batcher::{BatchConfig, Batcher},
+    body_limits::BodyLimits,
+    build_app,
+    catalog::ModelCatalog,
+    credits::CreditLedger,
+    experiments::Experiments,
+    forwarded_headers::ForwardedHeaders,
+    models::{BackendChatResponse, BackendChunk, NormalizedChatRequest, Usage},
+    postprocess::ProcessorSpec,
+    pricing::{ModelPrice, PricingTable},
+    prompts::{SystemPromptMode, SystemPromptPolicy},
+    router::BackendRouter,
+    state::AppState,
+    streaming::StreamingConfig,
+    tenants::TenantPolicy,
+    timeouts::TimeoutConfig,
+    usage_sink::{
+        KeyUsageTotals, UsageQuery, UsageRecord, UsageSink, UsageSinkConfig, UsageWriter,
+    },
+    GatewayBuilder,
+};
 use tower::util::ServiceExt;
 
+/// Peer address of an operator on the gateway's own host; `/admin/*` refuses everyone else
+/// while no admin token or allowlist is configured.
+fn loopback() -> ConnectInfo<SocketAddr> {
+    ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40_000)))
+}
+
 fn api_key_for_tests() -> String {
     env::var("GATEWAY_API_KEYS")
         .ok()
@@ -93,9 +127,2447 @@ async fn returns_cache_hit_on_repeated_identical_non_stream_request() {
         Some("hit")
     );
 
+    // The body's length stays known, so the response goes out with a `Content-Length`.
+    let length = second.body().size_hint().exact();
     let bytes = to_bytes(second.into_body(), 1024 * 1024)
         .await
         .expect("body should be readable");
+    assert_eq!(length, Some(bytes.len() as u64));
     let body = String::from_utf8(bytes.to_vec()).expect("response body should be UTF-8");
     assert!(body.contains("\"chat.completion\""));
 }
+
+#[tokio::test]
+async fn replays_cached_stream_on_repeated_identical_stream_request() {
+    let state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    let app = build_app(state);
+    let api_key = api_key_for_tests();
+    let body =
+        r#"{"model":"mock-1","messages":[{"role":"user","content":"stream me"}],"stream":true}"#;
+
+    let mut transcripts = Vec::new();
+    for expected_cache in ["miss", "hit"] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/chat/completions")
+                    .header("content-type", "application/json")
+                    .header("x-api-key", &api_key)
+                    .body(Body::from(body))
+                    .expect("request build"),
+            )
+            .await
+            .expect("stream request execution");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get("x-cache")
+                .and_then(|value| value.to_str().ok()),
+            Some(expected_cache)
+        );
+
+        let bytes = to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .expect("stream body should be readable");
+        let body = String::from_utf8(bytes.to_vec()).expect("stream body should be UTF-8");
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+        transcripts.push(body.matches("\"content\"").count());
+    }
+
+    assert_eq!(transcripts[0], transcripts[1]);
+}
+
+/// Mock responses, counting direct calls apart from the sizes of batched ones.
+#[derive(Default)]
+struct BatchRecordingBackend {
+    inner: MockBackend,
+    direct_calls: std::sync::atomic::AtomicUsize,
+    batch_sizes: std::sync::Mutex<Vec<usize>>,
+}
+
+#[async_trait]
+impl InferenceBackend for BatchRecordingBackend {
+    fn name(&self) -> &str {
+        "batch-recording"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        self.direct_calls
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        self.inner.stream_chat(request).await
+    }
+
+    async fn execute_chat_batch(
+        &self,
+        requests: Vec<std::sync::Arc<NormalizedChatRequest>>,
+    ) -> Vec<Result<BackendChatResponse, BackendError>> {
+        self.batch_sizes.lock().unwrap().push(requests.len());
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            results.push(self.inner.execute_chat(request).await);
+        }
+        results
+    }
+}
+
+#[tokio::test]
+async fn batch_opt_out_header_bypasses_micro_batcher() {
+    let backend = std::sync::Arc::new(BatchRecordingBackend::default());
+    let mut state = AppState::new_for_tests(backend.clone());
+    let max_wait = Duration::from_millis(300);
+    state.batcher = std::sync::Arc::new(Batcher::new(
+        backend.clone(),
+        BatchConfig {
+            max_wait,
+            ..BatchConfig::default()
+        },
+        state.metrics.clone(),
+    ));
+    let app = build_app(state.clone());
+    let api_key = api_key_for_tests();
+    let chat = |prompt: &str, batch: Option<&str>| {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("x-api-key", &api_key);
+        if let Some(batch) = batch {
+            builder = builder.header("x-gateway-batch", batch);
+        }
+        builder
+            .body(Body::from(format!(
+                r#"{{"model":"mock-1","messages":[{{"role":"user","content":"{prompt}"}}]}}"#
+            )))
+            .expect("request build")
+    };
+
+    let started = std::time::Instant::now();
+    let response = app
+        .clone()
+        .oneshot(chat("interactive", Some("off")))
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(started.elapsed() < max_wait);
+    assert_eq!(
+        backend
+            .direct_calls
+            .load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+    assert!(backend.batch_sizes.lock().unwrap().is_empty());
+
+    // Without the header, requests sit out the wait window and reach the backend together.
+    let started = std::time::Instant::now();
+    let (first, second) = tokio::join!(
+        app.clone().oneshot(chat("batched-1", None)),
+        app.clone().oneshot(chat("batched-2", None)),
+    );
+    assert_eq!(first.expect("first").status(), StatusCode::OK);
+    assert_eq!(second.expect("second").status(), StatusCode::OK);
+    assert!(started.elapsed() >= max_wait);
+    assert_eq!(*backend.batch_sizes.lock().unwrap(), vec![2]);
+    assert_eq!(
+        backend
+            .direct_calls
+            .load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+
+    let rendered = state.metrics.render().expect("render metrics");
+    assert!(rendered.contains("gateway_batch_flushes_total{reason=\"deadline\"} 1"));
+}
+
+#[tokio::test]
+async fn rejects_invalid_timeout_header() {
+    let state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    let app = build_app(state);
+    let api_key = api_key_for_tests();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", &api_key)
+                .header("x-gateway-timeout-ms", "soon")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hello"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn priced_requests_count_cost_per_model_and_backend() {
+    let backend: std::sync::Arc<dyn InferenceBackend> =
+        std::sync::Arc::new(MockBackend::named("mock-a"));
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(BackendRouter::new(vec![backend])));
+    state.pricing = std::sync::Arc::new(PricingTable::new(
+        ModelPrice::parse_list("mock-*=1000000:1000000").expect("valid prices"),
+    ));
+    let app = build_app(state.clone());
+    let api_key = api_key_for_tests();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", &api_key)
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"what does this cost"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("response body");
+    let payload: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    let total_tokens = payload["usage"]["total_tokens"]
+        .as_u64()
+        .expect("usage reported");
+
+    // One dollar per token, so spend equals the token count.
+    let rendered = state.metrics.render().expect("render metrics");
+    assert!(rendered.contains(&format!(
+        "gateway_cost_usd_total{{backend=\"mock-a\",model=\"mock-1\"}} {total_tokens}"
+    )));
+}
+
+#[tokio::test]
+async fn dry_runs_return_the_plan_without_calling_a_backend() {
+    let backends: Vec<std::sync::Arc<dyn InferenceBackend>> = vec![
+        std::sync::Arc::new(MockBackend::named("mock-a")),
+        std::sync::Arc::new(MockBackend::named("mock-b")),
+    ];
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(BackendRouter::new(backends)));
+    state.pricing = std::sync::Arc::new(PricingTable::new(
+        ModelPrice::parse_list("mock-*=1000000:1000000").expect("valid prices"),
+    ));
+    let app = build_app(state.clone());
+    let api_key = api_key_for_tests();
+    let body =
+        r#"{"model":"mock-1","max_tokens":10,"messages":[{"role":"user","content":"plan this"}]}"#;
+
+    for (uri, header) in [
+        ("/v1/chat/completions", Some("true")),
+        ("/v1/chat/completions/validate", None),
+    ] {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .header("x-api-key", &api_key);
+        if let Some(value) = header {
+            request = request.header("x-gateway-dry-run", value);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from(body)).expect("request build"))
+            .await
+            .expect("request execution");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-gateway-dry-run"], "true");
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("response body");
+        let plan: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        assert_eq!(plan["object"], "chat.completion.plan");
+        assert_eq!(plan["model"], "mock-1");
+        assert_eq!(plan["backends"], serde_json::json!(["mock-a", "mock-b"]));
+        let tokens = &plan["estimated_tokens"];
+        assert_eq!(tokens["completion"], 10);
+        assert_eq!(
+            tokens["total"].as_u64(),
+            Some(tokens["prompt"].as_u64().expect("prompt estimate") + 10)
+        );
+        // One dollar per token.
+        assert_eq!(
+            plan["estimated_cost_usd"].as_f64(),
+            tokens["total"].as_f64()
+        );
+    }
+
+    // Nothing was spent, because nothing was called.
+    let rendered = state.metrics.render().expect("render metrics");
+    assert!(!rendered.contains("gateway_cost_usd_total{"));
+}
+
+#[tokio::test]
+async fn trusted_keys_pin_requests_to_a_backend() {
+    let backends: Vec<std::sync::Arc<dyn InferenceBackend>> = vec![
+        std::sync::Arc::new(MockBackend::named("mock-a")),
+        std::sync::Arc::new(MockBackend::named("mock-b")),
+    ];
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(BackendRouter::new(backends)));
+    state.pricing = std::sync::Arc::new(PricingTable::new(
+        ModelPrice::parse_list("mock-*=1000000:1000000").expect("valid prices"),
+    ));
+    state.auth = std::sync::Arc::new(
+        ApiKeyRegistry::new(["ops", "plain"], RatePolicy::default()).with_key_policy(
+            "ops",
+            KeyPolicy {
+                backends: vec!["mock-*".to_owned()],
+                ..KeyPolicy::default()
+            },
+        ),
+    );
+    let app = build_app(state.clone());
+    let send = |api_key: &'static str, backend: &'static str, dry_run: bool| {
+        let app = app.clone();
+        async move {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .header("x-gateway-backend", backend);
+            if dry_run {
+                request = request.header("x-gateway-dry-run", "true");
+            }
+            let body = r#"{"model":"mock-1","messages":[{"role":"user","content":"pin me"}]}"#;
+            let response = app
+                .oneshot(request.body(Body::from(body)).expect("request build"))
+                .await
+                .expect("request execution");
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("response body");
+            let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+            (status, json)
+        }
+    };
+
+    let (status, plan) = send("ops", "mock-b", true).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(plan["backends"], serde_json::json!(["mock-b"]));
+    assert_eq!(plan["batching"], false);
+    assert_eq!(plan["cache"]["read"], false);
+
+    // The same prompt every time, yet each call reaches the pinned backend.
+    for _ in 0..3 {
+        assert_eq!(send("ops", "mock-b", false).await.0, StatusCode::OK);
+    }
+    let rendered = state.metrics.render().expect("render metrics");
+    assert!(rendered.contains("gateway_cost_usd_total{backend=\"mock-b\",model=\"mock-1\"}"));
+    assert!(!rendered.contains("gateway_cost_usd_total{backend=\"mock-a\""));
+
+    let (status, error) = send("plain", "mock-b", false).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["error"]["code"], "permission_denied");
+    let (status, error) = send("ops", "mock-c", false).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "unknown_backend");
+    assert_eq!(error["error"]["param"], "x-gateway-backend");
+}
+
+#[tokio::test]
+async fn the_model_catalog_lists_resolves_routes_and_prices_models() {
+    let catalog = ModelCatalog::parse(
+        r#"{
+            "mock-1": {
+                "display_name": "Mock One",
+                "aliases": ["mock-latest"],
+                "pricing": {"prompt_per_million": 1000000, "completion_per_million": 1000000},
+                "backend": "mock-b"
+            },
+            "mock-tools": {"capabilities": ["tools"]},
+            "mock-hidden": {}
+        }"#,
+    )
+    .expect("valid catalog");
+    let keys = ApiKeyRegistry::new(["acme-a"], RatePolicy::default())
+        .with_key_policy(
+            "acme-a",
+            KeyPolicy {
+                tenant: Some("acme".to_owned()),
+                ..KeyPolicy::default()
+            },
+        )
+        .with_tenant(
+            "acme",
+            TenantPolicy {
+                models: vec!["mock-1".to_owned(), "mock-tools".to_owned()],
+                ..TenantPolicy::default()
+            },
+        );
+    let state = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::named("mock-a")))
+        .backend(std::sync::Arc::new(MockBackend::named("mock-b")))
+        .key_store(std::sync::Arc::new(keys))
+        .catalog(catalog)
+        .disable_cache()
+        .build_state()
+        .expect("gateway builds");
+    let app = build_app(state.clone());
+    let read = |response: axum::response::Response| async move {
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("response body");
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+        (status, json)
+    };
+    let send = |model: &'static str, stream: bool| {
+        let body = serde_json::json!({
+            "model": model,
+            "stream": stream,
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", "acme-a")
+                .body(Body::from(body.to_string()))
+                .expect("request build"),
+        )
+    };
+
+    let list = |api_key: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri("/v1/models")
+                .header("x-api-key", api_key)
+                .body(Body::empty())
+                .expect("request build"),
+        )
+    };
+    let (status, models) = read(list("acme-a").await.expect("request execution")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(models["object"], "list");
+    let ids = models["data"]
+        .as_array()
+        .expect("model list")
+        .iter()
+        .map(|model| model["id"].as_str().expect("model id"))
+        .collect::<Vec<_>>();
+    // Sorted by id, and only what the tenant may call.
+    assert_eq!(ids, ["mock-1", "mock-tools"]);
+    assert_eq!(models["data"][0]["display_name"], "Mock One");
+    assert_eq!(
+        models["data"][0]["aliases"],
+        serde_json::json!(["mock-latest"])
+    );
+    let (status, _) = read(list("unknown").await.expect("request execution")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // An alias is answered as the model it names, from the model's backend, at its price.
+    for _ in 0..3 {
+        let (status, json) =
+            read(send("mock-latest", false).await.expect("request execution")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["model"], "mock-1");
+    }
+    let rendered = state.metrics.render().expect("render metrics");
+    assert!(rendered.contains("gateway_cost_usd_total{backend=\"mock-b\",model=\"mock-1\"}"));
+    assert!(!rendered.contains("gateway_cost_usd_total{backend=\"mock-a\""));
+
+    let (status, error) = read(send("gpt-4o", false).await.expect("request execution")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "model_not_found");
+    assert_eq!(error["error"]["param"], "model");
+    let (status, error) = read(send("mock-tools", true).await.expect("request execution")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "stream_not_supported");
+}
+
+#[tokio::test]
+async fn echoes_client_request_id_in_header_and_error_body() {
+    let state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    let app = build_app(state);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-request-id", "client-trace-7")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hello"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response
+            .headers()
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok()),
+        Some("client-trace-7")
+    );
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("response body");
+    let payload: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(payload["error"]["request_id"], "client-trace-7");
+}
+
+#[tokio::test]
+async fn metrics_endpoint_requires_admin_token_when_configured() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.admin = std::sync::Arc::new(AdminConfig {
+        token: Some("ops-secret".to_owned()),
+        ..AdminConfig::default()
+    });
+    let app = build_app(state);
+
+    let denied = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+
+    let allowed = app
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .header("authorization", "Bearer ops-secret")
+                .body(Body::empty())
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(allowed.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rejects_oversized_and_deeply_nested_bodies_with_openai_errors() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.body_limits = std::sync::Arc::new(BodyLimits {
+        max_body_bytes: 256,
+        max_json_depth: 4,
+    });
+    let metrics = state.metrics.clone();
+    let app = build_app(state);
+    let api_key = api_key_for_tests();
+    let send = |body: String| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", &api_key)
+                .body(Body::from(body))
+                .expect("request build"),
+        )
+    };
+    let error_type = |bytes: &[u8]| {
+        let json: serde_json::Value = serde_json::from_slice(bytes).expect("json error body");
+        json["error"]["type"].as_str().map(ToOwned::to_owned)
+    };
+
+    let oversized = format!(
+        r#"{{"model":"mock-1","messages":[{{"role":"user","content":"{}"}}]}}"#,
+        "a".repeat(512)
+    );
+    let response = send(oversized).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    assert_eq!(error_type(&body).as_deref(), Some("invalid_request_error"));
+
+    let nested = format!(
+        r#"{{"model":"mock-1","messages":[{{"role":"user","content":"hi"}}],"user":{}1{}}}"#,
+        "[".repeat(8),
+        "]".repeat(8)
+    );
+    let response = send(nested).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send("{not json".to_owned())
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    assert_eq!(error_type(&body).as_deref(), Some("invalid_request_error"));
+
+    let rendered = metrics.render().expect("render metrics");
+    for reason in ["body_too_large", "json_too_deep", "invalid_json"] {
+        assert!(
+            rendered.contains(&format!(
+                "gateway_request_rejections_total{{reason=\"{reason}\"}} 1"
+            )),
+            "{reason}"
+        );
+    }
+}
+
+/// Accepts a single token, as a service backed by its own identity store might.
+struct SingleTenantKeys;
+
+#[async_trait]
+impl KeyStore for SingleTenantKeys {
+    async fn lookup(&self, api_key: &str) -> Option<KeyGrant> {
+        (api_key == "tenant-token").then(|| KeyGrant {
+            rate: RatePolicy::default(),
+            policy: KeyPolicy {
+                tenant: Some("acme".to_owned()),
+                ..KeyPolicy::default()
+            },
+            tenant: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn embeds_gateway_with_custom_key_store_and_cache_disabled() {
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::named("embedded")))
+        .key_store(std::sync::Arc::new(SingleTenantKeys))
+        .disable_cache()
+        .disable_batching()
+        .build()
+        .expect("gateway builds");
+
+    let send = |api_key: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hello"}]}"#,
+                ))
+                .expect("request build"),
+        )
+    };
+
+    let response = send("dev-key").await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    for _ in 0..2 {
+        let response = send("tenant-token").await.expect("request execution");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(
+            response
+                .headers()
+                .get("x-cache")
+                .and_then(|value| value.to_str().ok()),
+            Some("hit")
+        );
+    }
+}
+
+#[test]
+fn builder_requires_a_backend() {
+    assert!(GatewayBuilder::new().build_state().is_err());
+}
+
+/// Mock responses, but only after `delay`.
+struct SlowBackend {
+    inner: MockBackend,
+    delay: Duration,
+}
+
+#[async_trait]
+impl InferenceBackend for SlowBackend {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.stream_chat(request).await
+    }
+}
+
+#[tokio::test]
+async fn followers_fail_when_the_stream_leader_goes_away_while_starting() {
+    let state = AppState::new_for_tests(std::sync::Arc::new(SlowBackend {
+        inner: MockBackend::default(),
+        delay: Duration::from_secs(60),
+    }));
+    let app = build_app(state);
+    let request = || {
+        Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("x-api-key", api_key_for_tests())
+            .header("x-gateway-coalesce", "on")
+            .header("cache-control", "no-store")
+            .body(Body::from(
+                r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+            ))
+            .expect("request build")
+    };
+
+    let leader = tokio::spawn(app.clone().oneshot(request()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let follower = app.oneshot(request()).await.expect("follower execution");
+    assert_eq!(follower.status(), StatusCode::OK);
+    // The leader's client disconnects while its stream is still starting.
+    leader.abort();
+
+    let bytes = tokio::time::timeout(
+        Duration::from_secs(5),
+        to_bytes(follower.into_body(), usize::MAX),
+    )
+    .await
+    .expect("the follower is not left waiting")
+    .expect("stream body");
+    let body = String::from_utf8(bytes.to_vec()).expect("UTF-8 stream body");
+    assert!(body.contains("stream leader stopped"), "{body}");
+}
+
+#[tokio::test]
+async fn server_request_timeout_returns_gateway_timeout() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(SlowBackend {
+        inner: MockBackend::default(),
+        delay: Duration::from_secs(5),
+    }));
+    state.timeouts = std::sync::Arc::new(TimeoutConfig {
+        request_timeout: Some(Duration::from_millis(50)),
+        stream_idle_timeout: None,
+    });
+    let app = build_app(state);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key_for_tests())
+                .header("x-gateway-cache", "bypass")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"too slow"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json error body");
+    assert_eq!(json["error"]["type"], "timeout_error");
+}
+
+#[tokio::test]
+async fn error_payloads_carry_openai_param_and_code() {
+    let app = build_app(AppState::new_for_tests(std::sync::Arc::new(
+        MockBackend::default(),
+    )));
+    let send = |api_key: String, body: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .body(Body::from(body))
+                .expect("request build"),
+        )
+    };
+    let error_of = |bytes: &[u8]| {
+        let json: serde_json::Value = serde_json::from_slice(bytes).expect("json error body");
+        json["error"].clone()
+    };
+
+    let response = send(api_key_for_tests(), r#"{"model":"mock-1","messages":[]}"#)
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let error = error_of(&body);
+    assert_eq!(error["type"], "invalid_request_error");
+    assert_eq!(error["param"], "messages");
+    assert_eq!(error["code"], "empty_array");
+
+    let response = send(
+        "not-a-key".to_owned(),
+        r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}]}"#,
+    )
+    .await
+    .expect("request execution");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let error = error_of(&body);
+    assert_eq!(error["code"], "invalid_api_key");
+    // Present as `null` rather than omitted, matching OpenAI's payload shape.
+    assert!(error.get("param").is_some_and(serde_json::Value::is_null));
+}
+
+/// Streams one delta and then fails, as a provider that drops mid-generation does.
+struct BrokenStreamBackend;
+
+#[async_trait]
+impl InferenceBackend for BrokenStreamBackend {
+    fn name(&self) -> &str {
+        "broken-stream"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        MockBackend::default().execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let items = vec![
+            Ok(BackendChunk {
+                delta: Some("partial".to_owned()),
+                finish_reason: None,
+                provider_finish_reason: None,
+                usage: None,
+                done: false,
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            }),
+            Err(BackendError::Unavailable("connection reset".to_owned())),
+        ];
+        Ok(Box::pin(futures_util::stream::iter(items)))
+    }
+}
+
+#[tokio::test]
+async fn mid_stream_failures_end_with_an_openai_error_event_then_done() {
+    let app = build_app(AppState::new_for_tests(std::sync::Arc::new(
+        BrokenStreamBackend,
+    )));
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key_for_tests())
+                .header("x-request-id", "trace-stream-error")
+                .header("cache-control", "no-store")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("stream body");
+    let body = String::from_utf8(bytes.to_vec()).expect("UTF-8 stream body");
+    let events = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect::<Vec<_>>();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+    let error: serde_json::Value =
+        serde_json::from_str(events[events.len() - 2]).expect("error event JSON");
+    assert_eq!(error["error"]["type"], "backend_error");
+    assert_eq!(error["error"]["code"], "upstream_error");
+    assert!(error["error"]["param"].is_null());
+    assert_eq!(error["error"]["request_id"], "trace-stream-error");
+    assert!(error["error"]["message"]
+        .as_str()
+        .is_some_and(|message| message.contains("connection reset")));
+}
+
+/// Streams one delta and then goes silent, as a slow generation does between tokens.
+struct StalledStreamBackend;
+
+#[async_trait]
+impl InferenceBackend for StalledStreamBackend {
+    fn name(&self) -> &str {
+        "stalled-stream"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        MockBackend::default().execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let first = Ok(BackendChunk {
+            delta: Some("thinking".to_owned()),
+            finish_reason: None,
+            provider_finish_reason: None,
+            usage: None,
+            done: false,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        });
+        Ok(Box::pin(
+            futures_util::stream::iter([first]).chain(futures_util::stream::pending()),
+        ))
+    }
+}
+
+#[tokio::test]
+async fn silent_streams_get_keep_alive_comments_until_the_maximum_duration() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(StalledStreamBackend));
+    state.streaming = std::sync::Arc::new(StreamingConfig {
+        keep_alive_interval: Some(Duration::from_millis(20)),
+        keep_alive_text: "ping".to_owned(),
+        max_duration: Some(Duration::from_millis(200)),
+        ..StreamingConfig::default()
+    });
+    let response = build_app(state)
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key_for_tests())
+                .header("cache-control", "no-store")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}],"stream":true}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("stream body");
+    let body = String::from_utf8(bytes.to_vec()).expect("UTF-8 stream body");
+    assert!(
+        body.lines().filter(|line| *line == ": ping").count() >= 2,
+        "{body}"
+    );
+    let events = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect::<Vec<_>>();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+    let error: serde_json::Value =
+        serde_json::from_str(events[events.len() - 2]).expect("error event JSON");
+    assert!(error["error"]["message"]
+        .as_str()
+        .is_some_and(|message| message.contains("maximum duration")));
+}
+
+#[tokio::test]
+async fn queued_streams_get_their_place_in_line_until_admitted() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.admission = std::sync::Arc::new(AdmissionController::new(
+        AdmissionConfig {
+            max_concurrency: 1,
+            queue_capacity: 4,
+            queue_timeout: Duration::from_secs(5),
+            progress_interval: Some(Duration::from_millis(20)),
+            ..AdmissionConfig::default()
+        },
+        state.metrics.clone(),
+    ));
+    let app = build_app(state);
+    let send = |content: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key_for_tests())
+                .header("cache-control", "no-store")
+                .body(Body::from(format!(
+                    r#"{{"model":"mock-1","messages":[{{"role":"user","content":"{content}"}}],"stream":true}}"#
+                )))
+                .expect("request build"),
+        )
+    };
+
+    // The first stream keeps the only slot until its body is read or dropped.
+    let holder = send("first").await.expect("request execution");
+    let queued = send("second").await.expect("request execution");
+    assert_eq!(queued.status(), StatusCode::OK);
+    assert_eq!(queued.headers()["x-gateway-queue-position"], "1");
+    assert_eq!(queued.headers()["content-type"], "text/event-stream");
+
+    let mut body = queued.into_body().into_data_stream();
+    let first = body.next().await.expect("frame").expect("bytes");
+    assert_eq!(&first[..], b": queued position=1\n\n");
+    drop(holder);
+
+    let mut rest = String::new();
+    while let Some(frame) = body.next().await {
+        rest.push_str(std::str::from_utf8(&frame.expect("bytes")).expect("UTF-8 frame"));
+    }
+    assert!(rest.contains("chat.completion.chunk"), "{rest}");
+    assert!(rest.trim_end().ends_with("data: [DONE]"), "{rest}");
+}
+
+#[tokio::test]
+async fn include_usage_streams_end_with_a_usage_only_chunk() {
+    let app = build_app(AppState::new_for_tests(std::sync::Arc::new(
+        MockBackend::default(),
+    )));
+    let stream_events = |body: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/chat/completions")
+                        .header("content-type", "application/json")
+                        .header("x-api-key", api_key_for_tests())
+                        .header("cache-control", "no-store")
+                        .body(Body::from(body))
+                        .expect("request build"),
+                )
+                .await
+                .expect("request execution");
+            let bytes = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("stream body");
+            String::from_utf8(bytes.to_vec())
+                .expect("UTF-8 stream body")
+                .lines()
+                .filter_map(|line| line.strip_prefix("data: "))
+                .filter(|data| *data != "[DONE]")
+                .map(|data| serde_json::from_str::<serde_json::Value>(data).expect("chunk JSON"))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let chunks = stream_events(
+        r#"{"model":"mock-1","messages":[{"role":"user","content":"count me"}],"stream":true,"stream_options":{"include_usage":true}}"#,
+    )
+    .await;
+    let (usage_chunk, content_chunks) = chunks.split_last().expect("chunks");
+    assert_eq!(usage_chunk["choices"], serde_json::json!([]));
+    assert!(usage_chunk["usage"]["total_tokens"].as_u64() > Some(0));
+    assert!(content_chunks
+        .iter()
+        .all(|chunk| chunk.get("usage") == Some(&serde_json::Value::Null)));
+    assert_eq!(
+        content_chunks.last().expect("finish chunk")["choices"][0]["finish_reason"],
+        "stop"
+    );
+
+    let chunks = stream_events(
+        r#"{"model":"mock-1","messages":[{"role":"user","content":"count me"}],"stream":true}"#,
+    )
+    .await;
+    assert!(chunks.iter().all(|chunk| chunk.get("usage").is_none()));
+}
+
+/// Streams three provider events, content, finish, and usage, the way the OpenAI adapter
+/// decodes them, attaching them verbatim when the request asks for passthrough.
+struct UpstreamEventBackend;
+
+const UPSTREAM_EVENTS: [&str; 3] = [
+    r#"{"id":"chatcmpl-upstream","object":"chat.completion.chunk","created":1,"model":"gpt-4o-2024-08-06","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"},"finish_reason":null}]}"#,
+    r#"{"id":"chatcmpl-upstream","object":"chat.completion.chunk","created":1,"model":"gpt-4o-2024-08-06","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+    r#"{"id":"chatcmpl-upstream","object":"chat.completion.chunk","created":1,"model":"gpt-4o-2024-08-06","choices":[],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#,
+];
+
+#[async_trait]
+impl InferenceBackend for UpstreamEventBackend {
+    fn name(&self) -> &str {
+        "upstream-events"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        MockBackend::default().execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let raw = |index: usize| {
+            request
+                .passthrough
+                .then(|| UPSTREAM_EVENTS[index].to_owned())
+        };
+        let items = vec![
+            Ok(BackendChunk {
+                delta: Some("hi".to_owned()),
+                finish_reason: None,
+                provider_finish_reason: None,
+                usage: None,
+                done: false,
+                backend: None,
+                raw: raw(0),
+                upstream_headers: None,
+            }),
+            Ok(BackendChunk {
+                delta: None,
+                finish_reason: None,
+                provider_finish_reason: None,
+                usage: None,
+                done: false,
+                backend: None,
+                raw: raw(1),
+                upstream_headers: None,
+            }),
+            Ok(BackendChunk {
+                delta: None,
+                finish_reason: Some("stop".to_owned()),
+                provider_finish_reason: None,
+                usage: Some(Usage::new(1, 1)),
+                done: true,
+                backend: None,
+                raw: raw(2),
+                upstream_headers: None,
+            }),
+        ];
+        Ok(Box::pin(futures_util::stream::iter(items)))
+    }
+}
+
+#[tokio::test]
+async fn passthrough_forwards_upstream_events_only_on_uncoalesced_streams() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(UpstreamEventBackend));
+    state.streaming = std::sync::Arc::new(StreamingConfig {
+        passthrough: true,
+        ..StreamingConfig::default()
+    });
+    let app = build_app(state);
+    let stream_events = |coalesce: &'static str, include_usage: bool| {
+        let app = app.clone();
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+            "stream_options": {"include_usage": include_usage},
+        });
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/chat/completions")
+                        .header("content-type", "application/json")
+                        .header("x-api-key", api_key_for_tests())
+                        .header("x-gateway-coalesce", coalesce)
+                        .header("cache-control", "no-store")
+                        .body(Body::from(body.to_string()))
+                        .expect("request build"),
+                )
+                .await
+                .expect("request execution");
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("stream body");
+            String::from_utf8(bytes.to_vec())
+                .expect("UTF-8 stream body")
+                .lines()
+                .filter_map(|line| line.strip_prefix("data: ").map(ToOwned::to_owned))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // The gateway asks upstream for usage; a client that did not ask sees no usage event.
+    let forwarded = stream_events("off", false).await;
+    assert_eq!(
+        forwarded,
+        [UPSTREAM_EVENTS[0], UPSTREAM_EVENTS[1], "[DONE]"]
+    );
+
+    // A client that asked gets the upstream's usage event, and no second one from the gateway.
+    let with_usage = stream_events("off", true).await;
+    assert_eq!(
+        with_usage,
+        [
+            UPSTREAM_EVENTS[0],
+            UPSTREAM_EVENTS[1],
+            UPSTREAM_EVENTS[2],
+            "[DONE]"
+        ]
+    );
+
+    // A coalesced stream may gain followers, so it is rebuilt under the gateway's own id.
+    let rebuilt = stream_events("on", false).await;
+    assert_eq!(rebuilt.len(), 4);
+    assert!(rebuilt
+        .iter()
+        .all(|event| !event.contains("chatcmpl-upstream")));
+}
+
+#[tokio::test]
+async fn tenant_policies_gate_models_and_share_one_quota_across_keys() {
+    let tenant_key = |tenant: &str| KeyPolicy {
+        tenant: Some(tenant.to_owned()),
+        ..KeyPolicy::default()
+    };
+    let keys = ApiKeyRegistry::new(["acme-a", "acme-b"], RatePolicy::default())
+        .with_key_policy("acme-a", tenant_key("acme"))
+        .with_key_policy("acme-b", tenant_key("acme"))
+        .with_tenant(
+            "acme",
+            TenantPolicy {
+                models: vec!["mock-*".to_owned()],
+                requests_per_minute: Some(1),
+                ..TenantPolicy::default()
+            },
+        );
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::default()))
+        .key_store(std::sync::Arc::new(keys))
+        .build()
+        .expect("gateway builds");
+    let send = |api_key: &'static str, model: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .body(Body::from(format!(
+                    r#"{{"model":"{model}","messages":[{{"role":"user","content":"hi"}}]}}"#
+                )))
+                .expect("request build"),
+        )
+    };
+
+    let response = send("acme-a", "gpt-4o").await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = send("acme-a", "mock-1").await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The second key has its own per-key budget left, but the tenant's is spent.
+    let response = send("acme-b", "mock-1").await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json error body");
+    assert_eq!(
+        json["error"]["message"],
+        "tenant requests per minute quota exceeded"
+    );
+}
+
+#[tokio::test]
+async fn keys_without_streaming_are_refused_streams() {
+    let keys = ApiKeyRegistry::new(["batch-key"], RatePolicy::default()).with_key_policy(
+        "batch-key",
+        KeyPolicy {
+            streaming: Some(false),
+            ..KeyPolicy::default()
+        },
+    );
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::default()))
+        .key_store(std::sync::Arc::new(keys))
+        .build()
+        .expect("gateway builds");
+    let send = |stream: bool| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", "batch-key")
+                .body(Body::from(format!(
+                    r#"{{"model":"mock-1","messages":[{{"role":"user","content":"hi"}}],"stream":{stream}}}"#
+                )))
+                .expect("request build"),
+        )
+    };
+
+    let response = send(true).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json error body");
+    assert_eq!(json["error"]["param"], "stream");
+    assert_eq!(json["error"]["code"], "stream_not_allowed");
+
+    let response = send(false).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Answers with the messages it was sent, one `role: content` line each.
+struct EchoMessagesBackend;
+
+#[async_trait]
+impl InferenceBackend for EchoMessagesBackend {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        let content = request
+            .messages
+            .iter()
+            .map(|message| format!("{:?}: {}", message.role, message.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(BackendChatResponse {
+            content,
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: None,
+        })
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        Err(BackendError::Unavailable(
+            "streams not supported".to_owned(),
+        ))
+    }
+}
+
+#[tokio::test]
+async fn system_prompt_policies_are_injected_and_keep_cache_entries_apart() {
+    let policy = |content: &str, mode: SystemPromptMode| {
+        Some(SystemPromptPolicy {
+            content: content.to_owned(),
+            mode,
+        })
+    };
+    let keys = ApiKeyRegistry::new(["acme-a", "acme-b", "solo"], RatePolicy::default())
+        .with_key_policy(
+            "acme-a",
+            KeyPolicy {
+                tenant: Some("acme".to_owned()),
+                ..KeyPolicy::default()
+            },
+        )
+        .with_key_policy(
+            "acme-b",
+            KeyPolicy {
+                tenant: Some("acme".to_owned()),
+                system_prompt: policy("Answer as Acme.", SystemPromptMode::Replace),
+                ..KeyPolicy::default()
+            },
+        )
+        .with_tenant(
+            "acme",
+            TenantPolicy {
+                system_prompt: policy("Follow Acme policy.", SystemPromptMode::Prepend),
+                ..TenantPolicy::default()
+            },
+        );
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(EchoMessagesBackend))
+        .key_store(std::sync::Arc::new(keys))
+        .build()
+        .expect("gateway builds");
+    let send = |api_key: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/chat/completions")
+                        .header("content-type", "application/json")
+                        .header("x-api-key", api_key)
+                        .body(Body::from(
+                            r#"{"model":"mock-1","messages":[{"role":"system","content":"Be terse."},{"role":"user","content":"hi"}]}"#,
+                        ))
+                        .expect("request build"),
+                )
+                .await
+                .expect("request execution");
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+            json["choices"][0]["message"]["content"]
+                .as_str()
+                .expect("content")
+                .to_owned()
+        }
+    };
+
+    assert_eq!(
+        send("acme-a").await,
+        "System: Follow Acme policy.\nSystem: Be terse.\nUser: hi"
+    );
+    assert_eq!(send("acme-b").await, "System: Answer as Acme.\nUser: hi");
+    // The same body without a policy must not be served the tenant's cached answer.
+    assert_eq!(send("solo").await, "System: Be terse.\nUser: hi");
+}
+
+/// Answers in Markdown split across deltas, so processors must carry state between them.
+struct MarkdownBackend;
+
+const MARKDOWN_DELTAS: [&str; 3] = ["Hello **wor", "ld**. ST", "OP secret"];
+
+#[async_trait]
+impl InferenceBackend for MarkdownBackend {
+    fn name(&self) -> &str {
+        "markdown"
+    }
+
+    async fn execute_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        Ok(BackendChatResponse {
+            content: MARKDOWN_DELTAS.concat(),
+            finish_reason: "length".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 3),
+            backend: None,
+            upstream_headers: None,
+        })
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let mut items = MARKDOWN_DELTAS
+            .iter()
+            .map(|delta| {
+                Ok(BackendChunk {
+                    delta: Some((*delta).to_owned()),
+                    finish_reason: None,
+                    provider_finish_reason: None,
+                    usage: None,
+                    done: false,
+                    backend: None,
+                    raw: None,
+                    upstream_headers: None,
+                })
+            })
+            .collect::<Vec<_>>();
+        items.push(Ok(BackendChunk {
+            delta: None,
+            finish_reason: Some("length".to_owned()),
+            provider_finish_reason: None,
+            usage: Some(Usage::new(1, 3)),
+            done: true,
+            backend: None,
+            raw: None,
+            upstream_headers: None,
+        }));
+        Ok(Box::pin(futures_util::stream::iter(items)))
+    }
+}
+
+#[tokio::test]
+async fn post_processors_rewrite_one_shot_and_streamed_text_per_key() {
+    let keys = ApiKeyRegistry::new(["plain", "raw"], RatePolicy::default()).with_key_policy(
+        "plain",
+        KeyPolicy {
+            post_process: vec!["markdown".to_owned(), "stop".to_owned()],
+            ..KeyPolicy::default()
+        },
+    );
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MarkdownBackend))
+        .key_store(std::sync::Arc::new(keys))
+        .post_processor("markdown", ProcessorSpec::StripMarkdown.build())
+        .post_processor(
+            "stop",
+            ProcessorSpec::StopSequences {
+                sequences: vec!["STOP".to_owned()],
+            }
+            .build(),
+        )
+        .build()
+        .expect("gateway builds");
+    let send = |api_key: &'static str, stream: bool| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/chat/completions")
+                        .header("content-type", "application/json")
+                        .header("x-api-key", api_key)
+                        .body(Body::from(format!(
+                            r#"{{"model":"mock-1","messages":[{{"role":"user","content":"hi"}}],"stream":{stream}}}"#
+                        )))
+                        .expect("request build"),
+                )
+                .await
+                .expect("request execution");
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            String::from_utf8(body.to_vec()).expect("UTF-8 body")
+        }
+    };
+    let one_shot = |body: String| {
+        let json: serde_json::Value = serde_json::from_str(&body).expect("json body");
+        (
+            json["choices"][0]["message"]["content"]
+                .as_str()
+                .expect("content")
+                .to_owned(),
+            json["choices"][0]["finish_reason"].clone(),
+        )
+    };
+    let streamed = |body: String| {
+        let mut content = String::new();
+        let mut finish_reason = serde_json::Value::Null;
+        for data in body.lines().filter_map(|line| line.strip_prefix("data: ")) {
+            let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+                continue;
+            };
+            if let Some(delta) = chunk["choices"][0]["delta"]["content"].as_str() {
+                content.push_str(delta);
+            }
+            if !chunk["choices"][0]["finish_reason"].is_null() {
+                finish_reason = chunk["choices"][0]["finish_reason"].clone();
+            }
+        }
+        (content, finish_reason)
+    };
+
+    assert_eq!(
+        one_shot(send("plain", false).await),
+        ("Hello world. ".to_owned(), serde_json::json!("stop"))
+    );
+    assert_eq!(
+        streamed(send("plain", true).await),
+        ("Hello world. ".to_owned(), serde_json::json!("stop"))
+    );
+    // The cache keeps the backend's text, so a key without processors still sees all of it.
+    assert_eq!(
+        one_shot(send("raw", false).await),
+        (MARKDOWN_DELTAS.concat(), serde_json::json!("length"))
+    );
+}
+
+#[tokio::test]
+async fn experiments_route_variants_and_tag_responses() {
+    let experiments = Experiments::parse(
+        r#"[{"name":"smaller-model","model":"mock-1","treatment_percent":100,
+            "control":{"name":"control"},"treatment":{"name":"small","model":"mock-small"}}]"#,
+    )
+    .expect("valid experiments");
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::default()))
+        .key_store(std::sync::Arc::new(ApiKeyRegistry::new(
+            ["key-a"],
+            RatePolicy::default(),
+        )))
+        .experiments(Experiments::new(experiments))
+        .build()
+        .expect("gateway builds");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", "key-a")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-gateway-experiment"], "smaller-model");
+    assert_eq!(response.headers()["x-gateway-variant"], "small");
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(json["model"], "mock-small");
+    assert_eq!(
+        json["choices"][0]["message"]["content"],
+        "Mock response for model mock-small: hi"
+    );
+}
+
+#[tokio::test]
+async fn experiment_variants_stay_inside_the_tenant_allowlist() {
+    let experiments = Experiments::parse(
+        r#"[{"name":"bigger-model","model":"mock-1","treatment_percent":100,
+            "control":{"name":"control"},"treatment":{"name":"big","model":"gpt-4o"}}]"#,
+    )
+    .expect("valid experiments");
+    let keys = ApiKeyRegistry::new(["acme-a"], RatePolicy::default())
+        .with_key_policy(
+            "acme-a",
+            KeyPolicy {
+                tenant: Some("acme".to_owned()),
+                ..KeyPolicy::default()
+            },
+        )
+        .with_tenant(
+            "acme",
+            TenantPolicy {
+                models: vec!["mock-*".to_owned()],
+                ..TenantPolicy::default()
+            },
+        );
+    let app = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::default()))
+        .key_store(std::sync::Arc::new(keys))
+        .experiments(Experiments::new(experiments))
+        .build()
+        .expect("gateway builds");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", "acme-a")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-gateway-experiment").is_none());
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(json["model"], "mock-1");
+}
+
+/// A usage store holding one key's totals, whatever the window.
+struct FixedUsage;
+
+#[async_trait]
+impl UsageQuery for FixedUsage {
+    async fn key_totals(&self, from_ms: u64, _to_ms: u64) -> Result<Vec<KeyUsageTotals>, String> {
+        assert_eq!(from_ms, 1_767_225_600_000);
+        Ok(vec![KeyUsageTotals {
+            tenant_id: "acme".to_owned(),
+            key_id: "key_acme-a".to_owned(),
+            requests: 3,
+            prompt_tokens: 30,
+            completion_tokens: 12,
+            total_tokens: 42,
+            cost_usd: 0.5,
+        }])
+    }
+}
+
+#[tokio::test]
+async fn cost_reports_roll_up_usage_as_json_or_csv() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.usage_sink =
+        std::sync::Arc::new(UsageSink::disabled().with_query(std::sync::Arc::new(FixedUsage)));
+    let app = build_app(state);
+    let get = |uri: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .uri(uri)
+                .extension(loopback())
+                .body(Body::empty())
+                .expect("request build"),
+        )
+    };
+
+    let response = get("/admin/reports/costs?from=2026-01-01&to=2026-02-01")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let report: serde_json::Value = serde_json::from_slice(&body).expect("json report");
+    assert_eq!(report["total_cost_usd"], 0.5);
+    assert_eq!(report["tenants"][0]["tenant_id"], "acme");
+    assert_eq!(report["tenants"][0]["keys"][0]["total_tokens"], 42);
+
+    let response = get("/admin/reports/costs?from=2026-01-01&format=csv")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let csv = String::from_utf8(body.to_vec()).expect("UTF-8 csv");
+    assert_eq!(
+        csv.lines().nth(1),
+        Some("acme,key_acme-a,3,30,12,42,0.500000")
+    );
+
+    let response = get("/admin/reports/costs?to=2026-01-01")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn quota_overrides_apply_live_and_can_be_lifted() {
+    let app = build_app(AppState::new_for_tests(std::sync::Arc::new(
+        MockBackend::default(),
+    )));
+    let api_key = api_key_for_tests();
+    let key_id = format!("key_{}", api_key.chars().take(8).collect::<String>());
+    let quota_uri = format!("/admin/quotas/keys/{key_id}");
+    let admin = |method: &str, uri: &str, body: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .extension(loopback())
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .expect("request build"),
+        )
+    };
+    let chat = || {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", &api_key)
+                .header("cache-control", "no-store")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .expect("request build"),
+        )
+    };
+
+    let response = admin("PUT", &quota_uri, r#"{"requests_per_minute":0}"#)
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let response = admin("GET", "/admin/quotas", "")
+        .await
+        .expect("request execution");
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let listed: serde_json::Value = serde_json::from_slice(&body).expect("json list");
+    assert_eq!(listed[0]["id"], key_id.as_str());
+    assert_eq!(listed[0]["requests_per_minute"], 0);
+
+    let response = admin("DELETE", &quota_uri, "")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = admin("GET", &quota_uri, "")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = admin(
+        "PUT",
+        "/admin/quotas/users/x",
+        r#"{"requests_per_minute":1}"#,
+    )
+    .await
+    .expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn quota_changes_need_the_admin_token_or_loopback() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.admin = std::sync::Arc::new(AdminConfig {
+        allowed_networks: Cidr::parse_list("10.0.0.0/8").expect("valid cidrs"),
+        ..AdminConfig::default()
+    });
+    let app = build_app(state);
+    let admin = |method: &str, uri: &str, body: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 5], 40_000))))
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .expect("request build"),
+        )
+    };
+
+    // An allowlisted network may look, but not lift a frozen key or raise its own limits.
+    let response = admin("GET", "/admin/quotas", "")
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    for method in ["PUT", "DELETE"] {
+        let response = admin(
+            method,
+            "/admin/quotas/keys/key_a",
+            r#"{"requests_per_minute":1}"#,
+        )
+        .await
+        .expect("request execution");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{method}");
+    }
+}
+
+#[tokio::test]
+async fn prepaid_credits_are_drawn_down_and_refuse_requests_once_spent() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.pricing = std::sync::Arc::new(PricingTable::new(
+        ModelPrice::parse_list("mock-*=1000000:1000000").expect("valid prices"),
+    ));
+    state.credits = std::sync::Arc::new(CreditLedger::in_memory());
+    let app = build_app(state);
+    let api_key = api_key_for_tests();
+    let tenant_id = format!("key_{}", api_key.chars().take(8).collect::<String>());
+    let credits_uri = format!("/admin/credits/{tenant_id}");
+    let admin = |method: &str, body: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(&credits_uri)
+                .extension(loopback())
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .expect("request build"),
+        )
+    };
+    let chat = || {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", &api_key)
+                .header("cache-control", "no-store")
+                .body(Body::from(
+                    r#"{"model":"mock-1","messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .expect("request build"),
+        )
+    };
+    let remaining = |response: &axum::response::Response| {
+        response.headers()["x-gateway-credits-remaining"]
+            .to_str()
+            .expect("ascii header")
+            .parse::<f64>()
+            .expect("numeric balance")
+    };
+
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let payload: serde_json::Value = serde_json::from_slice(&body).expect("json error");
+    assert_eq!(payload["error"]["code"], "insufficient_credits");
+
+    // One dollar per token: a single request overdraws a one-dollar balance.
+    let response = admin("PUT", r#"{"balance_usd":1}"#)
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(remaining(&response) < 0.0);
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+    assert!(remaining(&response) < 0.0);
+
+    let response = admin("POST", r#"{"amount_usd":1000}"#)
+        .await
+        .expect("request execution");
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let balance: serde_json::Value = serde_json::from_slice(&body).expect("json balance");
+    let topped_up = balance["balance_usd"].as_f64().expect("balance");
+    let response = chat().await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(remaining(&response) < topped_up);
+}
+
+#[tokio::test]
+async fn prepaid_credits_charge_every_request_and_refuse_unpriced_models() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(SlowBackend {
+        inner: MockBackend::default(),
+        delay: Duration::from_millis(200),
+    }));
+    state.pricing = std::sync::Arc::new(PricingTable::new(
+        ModelPrice::parse_list("mock-*=1000000:1000000").expect("valid prices"),
+    ));
+    state.credits = std::sync::Arc::new(CreditLedger::in_memory());
+    let app = build_app(state);
+    let api_key = api_key_for_tests();
+    let tenant_id = format!("key_{}", api_key.chars().take(8).collect::<String>());
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .uri(format!("/admin/credits/{tenant_id}"))
+                .extension(loopback())
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"balance_usd":1000}"#))
+                .expect("request build"),
+        )
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+    let chat = |model: &str, prompt: &str, no_store: bool| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("x-api-key", &api_key);
+        if no_store {
+            request = request.header("cache-control", "no-store");
+        }
+        app.clone().oneshot(
+            request
+                .body(Body::from(format!(
+                    r#"{{"model":"{model}","messages":[{{"role":"user","content":"{prompt}"}}]}}"#
+                )))
+                .expect("request build"),
+        )
+    };
+    let remaining = |response: &axum::response::Response| {
+        response.headers()["x-gateway-credits-remaining"]
+            .to_str()
+            .expect("ascii header")
+            .parse::<f64>()
+            .expect("numeric balance")
+    };
+
+    // A coalesced follower pays for its own usage, just as its leader does.
+    let (first, second) = tokio::join!(
+        chat("mock-1", "shared", true),
+        chat("mock-1", "shared", true)
+    );
+    let (first, second) = (first.expect("first"), second.expect("second"));
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(second.status(), StatusCode::OK);
+    let (high, low) = {
+        let (a, b) = (remaining(&first), remaining(&second));
+        (a.max(b), a.min(b))
+    };
+    let cost = 1000.0 - high;
+    assert!(cost > 0.0);
+    assert!((1000.0 - low - 2.0 * cost).abs() < 1e-6);
+
+    // So does a request served from the cache.
+    let miss = chat("mock-1", "cached", false).await.expect("miss");
+    assert_eq!(miss.headers()["x-cache"], "miss");
+    let hit = chat("mock-1", "cached", false).await.expect("hit");
+    assert_eq!(hit.headers()["x-cache"], "hit");
+    assert!((remaining(&miss) - remaining(&hit) - cost).abs() < 1e-6);
+
+    let response = chat("gpt-4o", "unpriced", true)
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn admin_endpoints_refuse_remote_clients_without_an_admin_token() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    state.credits = std::sync::Arc::new(CreditLedger::in_memory());
+    let app = build_app(state);
+    let top_up = |client: Option<SocketAddr>| {
+        let mut request = Request::builder()
+            .method("PUT")
+            .uri("/admin/credits/acme")
+            .header("content-type", "application/json");
+        if let Some(client) = client {
+            request = request.extension(ConnectInfo(client));
+        }
+        app.clone().oneshot(
+            request
+                .body(Body::from(r#"{"balance_usd":1000000}"#))
+                .expect("request build"),
+        )
+    };
+
+    for client in [None, Some(SocketAddr::from(([203, 0, 113, 7], 40_000)))] {
+        let response = top_up(client).await.expect("request execution");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let response = top_up(Some(loopback().0)).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn credential_rotation_needs_the_admin_token_from_remote_clients() {
+    let replace = |state: AppState, bearer: Option<&'static str>| {
+        let mut request = Request::builder()
+            .method("PUT")
+            .uri("/admin/backends/mock/credentials")
+            .header("content-type", "application/json")
+            .extension(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 40_000))));
+        if let Some(bearer) = bearer {
+            request = request.header("authorization", format!("Bearer {bearer}"));
+        }
+        build_app(state).oneshot(
+            request
+                .body(Body::from(r#"{"api_keys":["sk-attacker"]}"#))
+                .expect("request build"),
+        )
+    };
+    let open = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    let response = replace(open, None).await.expect("request execution");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let mut guarded = AppState::new_for_tests(std::sync::Arc::new(MockBackend::default()));
+    guarded.admin = std::sync::Arc::new(AdminConfig {
+        token: Some("ops-secret".to_owned()),
+        ..AdminConfig::default()
+    });
+    let response = replace(guarded.clone(), Some("ops-secreT"))
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    // Past the guard; the mock backend has no keys to replace.
+    let response = replace(guarded, Some("ops-secret"))
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let payload: serde_json::Value = serde_json::from_slice(&body).expect("json error");
+    assert_eq!(payload["error"]["code"], "not_found");
+    assert_eq!(
+        payload["error"]["message"],
+        "backend `mock` has no credentials to replace"
+    );
+}
+
+/// Streams "one two three" shortly after it is called and never reports usage, as some
+/// backends leave it out of their streams; the delay leaves followers time to join.
+struct UsagelessStreamBackend;
+
+#[async_trait]
+impl InferenceBackend for UsagelessStreamBackend {
+    fn name(&self) -> &str {
+        "usageless-stream"
+    }
+
+    async fn execute_chat(
+        &self,
+        request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        MockBackend::default().execute_chat(request).await
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let chunk = |delta: Option<&str>| {
+            Ok(BackendChunk {
+                delta: delta.map(ToOwned::to_owned),
+                finish_reason: delta.is_none().then(|| "stop".to_owned()),
+                provider_finish_reason: None,
+                usage: None,
+                done: delta.is_none(),
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            })
+        };
+        Ok(Box::pin(futures_util::stream::iter([
+            chunk(Some("one ")),
+            chunk(Some("two ")),
+            chunk(Some("three")),
+            chunk(None),
+        ])))
+    }
+}
+
+/// A usage store that keeps every record written to it.
+#[derive(Default)]
+struct RecordedUsage(std::sync::Mutex<Vec<UsageRecord>>);
+
+#[async_trait]
+impl UsageWriter for RecordedUsage {
+    fn name(&self) -> &str {
+        "recorded"
+    }
+
+    async fn write_batch(&self, records: &[UsageRecord]) -> Result<(), String> {
+        self.0.lock().expect("records").extend_from_slice(records);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn every_stream_subscriber_settles_usage_for_its_own_key() {
+    let keys = ApiKeyRegistry::new(["lead", "follow", "replay", "leave"], RatePolicy::default());
+    let mut state = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(UsagelessStreamBackend))
+        .key_store(std::sync::Arc::new(keys))
+        .build_state()
+        .expect("gateway builds");
+    let records = std::sync::Arc::new(RecordedUsage::default());
+    state.usage_sink = std::sync::Arc::new(UsageSink::new(
+        records.clone(),
+        UsageSinkConfig {
+            batch_size: 1,
+            flush_interval: Duration::from_millis(10),
+            queue_capacity: 64,
+        },
+        state.metrics.clone(),
+    ));
+    let app = build_app(state);
+    let send = |api_key: &'static str, content: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .body(Body::from(
+                    serde_json::json!({
+                        "model": "mock-1",
+                        "max_tokens": 50,
+                        "messages": [{"role": "user", "content": content}],
+                        "stream": true,
+                    })
+                    .to_string(),
+                ))
+                .expect("request build"),
+        )
+    };
+    let stream_to_end = |api_key: &'static str, content: &'static str| {
+        let response = send(api_key, content);
+        async move {
+            let response = response.await.expect("request execution");
+            assert_eq!(response.status(), StatusCode::OK);
+            to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("stream body");
+        }
+    };
+
+    // A leader and a follower share one backend call, then a third key replays the cache.
+    tokio::join!(stream_to_end("lead", "count to three"), async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        stream_to_end("follow", "count to three").await;
+    });
+    let response = send("replay", "count to three")
+        .await
+        .expect("request execution");
+    assert_eq!(response.headers()["x-cache"], "hit");
+    to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("stream body");
+
+    // A follower that hangs up after the first word is settled for what it was sent.
+    let leaver = async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let response = send("leave", "count once more")
+            .await
+            .expect("request execution");
+        let mut body = response.into_body().into_data_stream();
+        while let Some(frame) = body.next().await {
+            if String::from_utf8_lossy(&frame.expect("frame")).contains("one") {
+                break;
+            }
+        }
+    };
+    tokio::join!(stream_to_end("lead", "count once more"), leaver);
+
+    let mut written = Vec::new();
+    for _ in 0..100 {
+        written = records.0.lock().expect("records").clone();
+        if written.len() == 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let total_tokens = |key_id: &str| {
+        written
+            .iter()
+            .filter(|record| record.key_id == key_id)
+            .map(|record| record.total_tokens)
+            .collect::<Vec<_>>()
+    };
+    // Three prompt words, plus the three words streamed, or the one sent before hanging up.
+    assert_eq!(total_tokens("key_lead"), [6, 6]);
+    assert_eq!(total_tokens("key_follow"), [6]);
+    assert_eq!(total_tokens("key_replay"), [6]);
+    assert_eq!(total_tokens("key_leave"), [4]);
+
+    // Each key's limiter holds its settled usage, not the 53-token estimate, plus the 51
+    // tokens this request is estimated at.
+    for (api_key, settled) in [("follow", 6), ("replay", 6), ("leave", 4)] {
+        let response = send(api_key, "hi").await.expect("request execution");
+        assert_eq!(
+            response.headers()["x-ratelimit-remaining-tokens-minute"],
+            (120_000 - settled - 51).to_string().as_str()
+        );
+    }
+}
+
+#[tokio::test]
+async fn client_user_and_metadata_are_kept_on_usage_records() {
+    let keys = ApiKeyRegistry::new(["tagger"], RatePolicy::default());
+    let mut state = GatewayBuilder::new()
+        .backend(std::sync::Arc::new(MockBackend::default()))
+        .key_store(std::sync::Arc::new(keys))
+        .build_state()
+        .expect("gateway builds");
+    let records = std::sync::Arc::new(RecordedUsage::default());
+    state.usage_sink = std::sync::Arc::new(UsageSink::new(
+        records.clone(),
+        UsageSinkConfig {
+            batch_size: 1,
+            flush_interval: Duration::from_millis(10),
+            queue_capacity: 8,
+        },
+        state.metrics.clone(),
+    ));
+    let app = build_app(state);
+    let send = |metadata: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .header("x-api-key", "tagger")
+                .body(Body::from(
+                    serde_json::json!({
+                        "model": "mock-1",
+                        "messages": [{"role": "user", "content": "tag me"}],
+                        "user": "end-user-42",
+                        "metadata": metadata,
+                    })
+                    .to_string(),
+                ))
+                .expect("request build"),
+        )
+    };
+
+    let response = send(serde_json::json!({"feature": "search", "experiment": "ranker-v2"}))
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let too_many = (0..17)
+        .map(|i| (format!("tag{i}"), serde_json::json!("x")))
+        .collect::<serde_json::Map<_, _>>();
+    let response = send(serde_json::Value::Object(too_many))
+        .await
+        .expect("request execution");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .expect("error body");
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("error json");
+    assert_eq!(error["error"]["param"], "metadata");
+
+    let mut written = Vec::new();
+    for _ in 0..100 {
+        written = records.0.lock().expect("records").clone();
+        if !written.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0].key_id, "key_tagger");
+    assert_eq!(written[0].client_user.as_deref(), Some("end-user-42"));
+    let metadata = written[0].metadata.as_ref().expect("metadata tags");
+    assert_eq!(metadata["feature"], "search");
+    assert_eq!(metadata["experiment"], "ranker-v2");
+}
+
+/// Answers as a provider whose HTTP responses carry their own correlation headers.
+struct ProviderHeadersBackend;
+
+fn provider_headers() -> Option<axum::http::HeaderMap> {
+    let mut headers = axum::http::HeaderMap::new();
+    for (name, value) in [
+        ("openai-processing-ms", "42"),
+        ("openai-organization", "org-acme"),
+        ("x-request-id", "req_provider"),
+    ] {
+        headers.insert(name, axum::http::HeaderValue::from_static(value));
+    }
+    Some(headers)
+}
+
+#[async_trait]
+impl InferenceBackend for ProviderHeadersBackend {
+    fn name(&self) -> &str {
+        "provider-headers"
+    }
+
+    async fn execute_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendChatResponse, BackendError> {
+        Ok(BackendChatResponse {
+            content: "hello".to_owned(),
+            finish_reason: "stop".to_owned(),
+            provider_finish_reason: None,
+            usage: Usage::new(1, 1),
+            backend: None,
+            upstream_headers: provider_headers(),
+        })
+    }
+
+    async fn stream_chat(
+        &self,
+        _request: std::sync::Arc<NormalizedChatRequest>,
+    ) -> Result<BackendStream, BackendError> {
+        let items = [
+            Ok(BackendChunk {
+                delta: Some("hello".to_owned()),
+                finish_reason: None,
+                provider_finish_reason: None,
+                usage: None,
+                done: false,
+                backend: None,
+                raw: None,
+                upstream_headers: provider_headers(),
+            }),
+            Ok(BackendChunk {
+                delta: None,
+                finish_reason: Some("stop".to_owned()),
+                provider_finish_reason: None,
+                usage: Some(Usage::new(1, 1)),
+                done: true,
+                backend: None,
+                raw: None,
+                upstream_headers: None,
+            }),
+        ];
+        Ok(Box::pin(futures_util::stream::iter(items)))
+    }
+}
+
+#[tokio::test]
+async fn allowlisted_upstream_headers_reach_the_client() {
+    let mut state = AppState::new_for_tests(std::sync::Arc::new(ProviderHeadersBackend));
+    let app = build_app(state.clone());
+    state.forwarded_headers = std::sync::Arc::new(
+        ForwardedHeaders::new(["openai-processing-ms", "x-request-id"]).expect("valid names"),
+    );
+    let forwarding = build_app(state);
+    let api_key = api_key_for_tests();
+    let send = |app: axum::Router, content: &'static str, stream: bool| {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("x-api-key", &api_key)
+            .header("x-request-id", "req_client")
+            .body(Body::from(
+                serde_json::json!({
+                    "model": "mock-1",
+                    "messages": [{"role": "user", "content": content}],
+                    "stream": stream,
+                })
+                .to_string(),
+            ))
+            .expect("request build");
+        async move {
+            let response = app.oneshot(request).await.expect("request execution");
+            assert_eq!(response.status(), StatusCode::OK);
+            response.headers().clone()
+        }
+    };
+
+    // Nothing is forwarded unless it is listed.
+    let headers = send(app, "stripped", false).await;
+    assert!(!headers.contains_key("openai-processing-ms"));
+    assert!(!headers.contains_key("x-upstream-request-id"));
+
+    for stream in [false, true] {
+        let headers = send(forwarding.clone(), "forwarded", stream).await;
+        assert_eq!(headers["openai-processing-ms"], "42");
+        assert!(!headers.contains_key("openai-organization"));
+        // The gateway's own request id stays; the provider's is renamed.
+        assert_eq!(headers["x-request-id"], "req_client");
+        assert_eq!(headers["x-upstream-request-id"], "req_provider");
+    }
+
+    // A cache hit made no upstream call, so it has no upstream headers to pass on.
+    let headers = send(forwarding, "forwarded", false).await;
+    assert_eq!(headers["x-cache"], "hit");
+    assert!(!headers.contains_key("openai-processing-ms"));
+}